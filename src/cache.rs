@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+
+use crate::cfg::Cfg;
+use crate::imports::{self, Library};
+use crate::interpreter::{fnv1a, Code, Constant, Effects, Fraction, Function, Garbage, Module, Region, Symbol, FNV_OFFSET};
+use crate::isa::Stream;
+use crate::options::EngineOptions;
+use crate::parser;
+use crate::syntaxchecker::check_library;
+use crate::syntaxtree::{FunctionPrototype, ParamLink};
+use crate::tokeniser::Tokeniser;
+
+
+// Compiled projects are kept in target/reaver/<name>.json, and each module they import from source
+// in target/reaver/modules/<module>.rvc as a library artifact with its debug tables. Each is under
+// a header holding the format version and a key hashed from the compiler version, the build
+// flags and optimisation level, its source, and the keys of the modules it imports. Editing a
+// file so only recompiles it and the modules importing it, directly or not. A build or module
+// whose key matches is loaded instead of recompiled //
+const CACHE_DIR: &str = "target/reaver";
+const MODULE_DIR: &str = "target/reaver/modules";
+const CACHE_VERSION: u32 = 3;
+
+// Library artifacts (.rvc) hold a module compiled without global statements, under a header
+// holding their format version, and the prototypes of the functions it exports so that importers
+// can check calls into it, link groups included, without its source //
+const LIBRARY_VERSION: u32 = 1;
+
+
+// Load the cached build of the project whose main file is at `path` if it and its imports are
+// unchanged, otherwise compile it and cache the result. It's compiled under the configuration
+// given to `compile`, which caches the modules it imports //
+pub fn load_or_compile<F>(name: &str, path: &str, cfg: &Cfg, options: &EngineOptions, compile: F) -> Option<Module>
+where F: FnOnce(&Cfg) -> Option<Module> {
+    let mut cfg = cfg.clone();
+    cfg.set_opt_level(options.opt_level);
+    cfg.set_cache_modules();
+    // Imports are also looked for next to the main file, as when it's compiled //
+    cfg.add_include_path(Path::new(path).parent().unwrap_or(Path::new(".")));
+    let cache_path = Path::new(CACHE_DIR).join(format!("{}.json", name));
+    let key = fs::read_to_string(path).ok().map(|src| format!("{:016x}", module_key(&src, &cfg, &mut HashMap::new())));
+    if let Some(module) = key.as_ref().and_then(|key| load(&cache_path, key)) {
+        log::info!("Loaded the cached build {}", cache_path.display());
+        return Some(module);
+    }
+    log::info!("No cached build of {} matches, compiling", name);
+    let module = compile(&cfg)?;
+    if let Some(key) = key {
+        if let Err(err) = store(&cache_path, &format!("monoxide-build {} {}", CACHE_VERSION, key), &module_to_json(&module)) {
+            eprintln!("Can't write the build cache {}: {}", cache_path.display(), err);
+        }
+    }
+    Some(module)
+}
+
+// The library an imported module compiles to, loaded from the cache if its key matches, otherwise
+// compiled and cached. None if it has to be compiled from source along with its importer, as it
+// defines macros or generic functions, which have no code of their own, or doesn't check as a
+// library, for the importer to report why //
+pub fn cached_module(name: &str, src: &str, cfg: &Cfg) -> Option<Library> {
+    let key = format!("{:016x}", module_key(src, cfg, &mut HashMap::new()));
+    let header = format!("monoxide-module {} {}", CACHE_VERSION, key);
+    let cache_path = Path::new(MODULE_DIR).join(format!("{}.rvc", name));
+    let cached = fs::read_to_string(&cache_path).ok()
+        .and_then(|contents| Some(contents.split_once('\n').filter(|(found, _)| *found == header)?.1.to_string()))
+        .and_then(|payload| library_from_json(&serde_json::from_str(&payload).ok()?));
+    if let Some((module, prototypes)) = cached {
+        log::info!("Loaded module {} from the build cache", name);
+        return Some(Library{name: name.to_string(), module: Arc::new(module), prototypes});
+    }
+
+    let parsed = parser::parse(Tokeniser::new(src)).ok()?;
+    if !parsed.macros.is_empty() || parsed.functions.iter().any(|f| f.is_pub && !f.const_params.is_empty()) {
+        return None;
+    }
+    log::info!("Compiling module {} into the build cache", name);
+    let (module, prototypes) = check_library(parsed, cfg).ok()?;
+    let module = module.compile(true, &EngineOptions::default().with_opt_level(cfg.opt_level()));
+    if let Err(err) = store(&cache_path, &header, &library_json(&module, &prototypes)) {
+        eprintln!("Can't write the build cache {}: {}", cache_path.display(), err);
+    }
+    Some(Library{name: name.to_string(), module: Arc::new(module), prototypes})
+}
+
+// A hash of a module's source and the keys of the modules it imports, under the compiler version
+// and the configuration that changes what it compiles to. Keys already found are kept in `keys`,
+// where a module whose key is being found is None, so an import cycle adds nothing more //
+fn module_key(src: &str, cfg: &Cfg, keys: &mut HashMap<String, Option<u64>>) -> u64 {
+    let mut hash = fnv1a(env!("CARGO_PKG_VERSION").as_bytes(), FNV_OFFSET);
+    hash = fnv1a(cfg.flags().join("\n").as_bytes(), hash);
+    hash = fnv1a(&[cfg.is_strict() as u8, cfg.is_sketch() as u8, cfg.checks_aliasing() as u8, cfg.opt_level()], hash);
+    hash = fnv1a(src.as_bytes(), hash);
+    let imports = parser::parse(Tokeniser::new(src)).map(|module| module.imports).unwrap_or_default();
+    for import in imports {
+        hash = fnv1a(import.name.as_bytes(), hash);
+        hash = fnv1a(&import_key(&import.name, cfg, keys).to_le_bytes(), hash);
+    }
+    hash
+}
+
+fn import_key(name: &str, cfg: &Cfg, keys: &mut HashMap<String, Option<u64>>) -> u64 {
+    if name == "std" {
+        return fnv1a(imports::STD.as_bytes(), FNV_OFFSET);
+    }
+    if let Some(key) = keys.get(name) {
+        return key.unwrap_or(FNV_OFFSET);
+    }
+    keys.insert(name.to_string(), None);
+    let path = imports::find_module(name, cfg);
+    let contents = path.as_ref().and_then(|path| fs::read_to_string(path).ok()).unwrap_or_default();
+    let key = match path {
+        Some(path) if path.extension().is_some_and(|ext| ext == "mx") => module_key(&contents, cfg, keys),
+        _ => fnv1a(contents.as_bytes(), FNV_OFFSET)
+    };
+    keys.insert(name.to_string(), Some(key));
+    key
+}
+
+// A cached build that is missing, stale or unreadable is a miss //
+fn load(path: &Path, key: &str) -> Option<Module> {
+    let contents = fs::read_to_string(path).ok()?;
+    let (header, payload) = contents.split_once('\n')?;
+    if header != format!("monoxide-build {} {}", CACHE_VERSION, key) {
+        return None;
+    }
+    let module = module_from_json(&serde_json::from_str(payload).ok()?)?;
+    module.validate().ok()?;
+    Some(module)
+}
+
+fn store(path: &Path, header: &str, payload: &Value) -> std::io::Result<()> {
+    fs::create_dir_all(path.parent().unwrap_or(Path::new(CACHE_DIR)))?;
+    fs::write(path, format!("{}\n{}", header, payload))
+}
+
+pub fn library_to_string(module: &Module, prototypes: &[(String, FunctionPrototype)]) -> String {
+    format!("monoxide-library {}\n{}", LIBRARY_VERSION, library_json(module, prototypes))
+}
+
+// An artifact of another version, or that doesn't hold a valid module, can't be linked //
+pub fn library_from_str(contents: &str) -> Option<(Module, Vec<(String, FunctionPrototype)>)> {
+    let (header, payload) = contents.split_once('\n')?;
+    if header != format!("monoxide-library {}", LIBRARY_VERSION) {
+        return None;
+    }
+    library_from_json(&serde_json::from_str(payload).ok()?)
+}
+
+fn library_json(module: &Module, prototypes: &[(String, FunctionPrototype)]) -> Value {
+    let prototypes: Vec<_> = prototypes.iter().map(|(name, prototype)| prototype_to_json(name, prototype)).collect();
+    json!({"module": module_to_json(module), "prototypes": prototypes})
+}
+
+fn library_from_json(value: &Value) -> Option<(Module, Vec<(String, FunctionPrototype)>)> {
+    let module = module_from_json(&value["module"])?;
+    module.validate().ok()?;
+    let prototypes = value["prototypes"].as_array()?.iter().map(prototype_from_json).collect::<Option<Vec<_>>>()?;
+    if prototypes.iter().any(|(_, prototype)| prototype.id >= module.functions.len()) {
+        return None;
+    }
+    Some((module, prototypes))
+}
+
+
+// ------------------------------ Serialisation ------------------------------ //
+
+fn module_to_json(module: &Module) -> Value {
+    json!({
+        "main_idx": module.main_idx,
+        "global_func_idx": module.global_func_idx,
+        "consts": module.consts.iter().map(constant_to_json).collect::<Vec<_>>(),
+        "strings": module.strings,
+        "functions": module.functions.iter().map(function_to_json).collect::<Vec<_>>()
+    })
+}
+
+fn module_from_json(value: &Value) -> Option<Module> {
+    let consts: Arc<Vec<Constant>> = Arc::new(
+        value["consts"].as_array()?.iter().map(constant_from_json).collect::<Option<_>>()?);
+    let functions = value["functions"].as_array()?.iter()
+        .map(|func| function_from_json(func, &consts))
+        .collect::<Option<_>>()?;
+    let strings = value["strings"].as_array()?.iter()
+        .map(|string| string.as_str().map(String::from))
+        .collect::<Option<_>>()?;
+    Some(Module{
+        main_idx: value["main_idx"].as_u64().map(|idx| idx as usize),
+        global_func_idx: value["global_func_idx"].as_u64()? as usize,
+        functions,
+        consts,
+        strings
+    })
+}
+
+fn function_to_json(func: &Function) -> Value {
+    json!({
+        "name": func.name,
+        "fwd": stream_to_json(&func.code.fwd),
+        "bkwd": stream_to_json(&func.code.bkwd),
+        "regions": func.code.regions.iter().map(|region| json!([
+            region.fwd.start, region.fwd.end, region.bkwd.start, region.bkwd.end,
+            region.is_mono, region.depth, region.line, region.col
+        ])).collect::<Vec<_>>(),
+        "symbols": func.symbols.iter().map(|symbol| json!([
+            symbol.name, symbol.register, symbol.var_id,
+            symbol.fwd.start, symbol.fwd.end, symbol.bkwd.start, symbol.bkwd.end
+        ])).collect::<Vec<_>>(),
+        "num_registers": func.num_registers,
+        "num_borrows": func.num_borrows,
+        "num_steals": func.num_steals,
+        "num_returns": func.num_returns,
+        "is_pure": func.is_pure,
+        "effects": [
+            func.effects.prints, func.effects.reads_input, func.effects.touches_globals,
+            func.effects.steals, func.effects.is_mono
+        ],
+        "garbage": func.garbage.iter().map(|garbage| json!([
+            garbage.line, garbage.col, garbage.callee, garbage.names, garbage.last_use
+        ])).collect::<Vec<_>>(),
+        "specialised": func.specialised
+    })
+}
+
+fn function_from_json(value: &Value, consts: &Arc<Vec<Constant>>) -> Option<Function> {
+    let usize_at = |value: &Value, idx: usize| value.get(idx)?.as_u64().map(|x| x as usize);
+    let regions = value["regions"].as_array()?.iter().map(|region| Some(Region{
+        fwd: usize_at(region, 0)?..usize_at(region, 1)?,
+        bkwd: usize_at(region, 2)?..usize_at(region, 3)?,
+        is_mono: region.get(4)?.as_bool()?,
+        depth: usize_at(region, 5)?,
+        line: usize_at(region, 6)?,
+        col: usize_at(region, 7)?
+    })).collect::<Option<_>>()?;
+    let symbols = value["symbols"].as_array()?.iter().map(|symbol| Some(Symbol{
+        name: symbol.get(0)?.as_str()?.to_string(),
+        register: usize_at(symbol, 1)?,
+        var_id: symbol.get(2)?.as_i64()? as isize,
+        fwd: usize_at(symbol, 3)?..usize_at(symbol, 4)?,
+        bkwd: usize_at(symbol, 5)?..usize_at(symbol, 6)?
+    })).collect::<Option<_>>()?;
+    let garbage = value["garbage"].as_array()?.iter().map(|garbage| Some(Garbage{
+        line: usize_at(garbage, 0)?,
+        col: usize_at(garbage, 1)?,
+        callee: garbage.get(2)?.as_str()?.to_string(),
+        names: garbage.get(3)?.as_array()?.iter()
+            .map(|name| name.as_str().map(String::from))
+            .collect::<Option<_>>()?,
+        last_use: match garbage.get(4)? {
+            Value::Null => None,
+            last_use => Some((usize_at(last_use, 0)?, usize_at(last_use, 1)?))
+        }
+    })).collect::<Option<_>>()?;
+    let specialised = value["specialised"].as_array()?.iter().map(|param| Some((
+        usize_at(param, 0)?,
+        param.get(1)?.as_str()?.to_string(),
+        param.get(2)?.as_str()?.to_string()
+    ))).collect::<Option<_>>()?;
+    let effect = |idx: usize| value["effects"].get(idx)?.as_bool();
+    let effects = Effects{
+        prints: effect(0)?,
+        reads_input: effect(1)?,
+        touches_globals: effect(2)?,
+        steals: effect(3)?,
+        is_mono: effect(4)?
+    };
+    Some(Function{
+        name: value["name"].as_str()?.to_string(),
+        code: Code::new(stream_from_json(&value["fwd"])?, stream_from_json(&value["bkwd"])?, regions),
+        consts: Arc::clone(consts),
+        num_registers: value["num_registers"].as_u64()? as usize,
+        symbols,
+        num_borrows: value["num_borrows"].as_u64()? as usize,
+        num_steals: value["num_steals"].as_u64()? as usize,
+        num_returns: value["num_returns"].as_u64()? as usize,
+        is_pure: value["is_pure"].as_bool()?,
+        effects,
+        garbage,
+        specialised
+    })
+}
+
+fn stream_to_json(stream: &Stream) -> Value {
+    let (words, wide) = stream.parts();
+    json!({"words": words, "wide": wide})
+}
+
+fn stream_from_json(value: &Value) -> Option<Stream> {
+    let words = value["words"].as_array()?.iter()
+        .map(|word| word.as_u64().map(|word| word as u32))
+        .collect::<Option<_>>()?;
+    let wide = value["wide"].as_array()?.iter()
+        .map(|operand| operand.as_i64().map(|operand| operand as isize))
+        .collect::<Option<_>>()?;
+    Some(Stream::from_parts(words, wide))
+}
+
+fn constant_to_json(value: &Constant) -> Value {
+    match value {
+        Constant::Frac(value) => json!({"frac": value.to_string()}),
+        Constant::Float(value) => json!({"float": value}),
+        Constant::Str(value) => json!({"str": value}),
+        Constant::Handle(_) => unreachable!("Handles only exist in running programs"),
+        Constant::Array(items) => json!({"array": items.iter().map(constant_to_json).collect::<Vec<_>>()})
+    }
+}
+
+fn constant_from_json(value: &Value) -> Option<Constant> {
+    if let Some(frac) = value["frac"].as_str() {
+        return frac.parse::<Fraction>().ok().map(Constant::Frac);
+    }
+    if let Some(float) = value["float"].as_f64() {
+        return Some(Constant::Float(float));
+    }
+    if let Some(string) = value["str"].as_str() {
+        return Some(Constant::Str(string.to_string()));
+    }
+    let items = value["array"].as_array()?.iter().map(constant_from_json).collect::<Option<_>>()?;
+    Some(Constant::Array(items))
+}
+
+// The module a prototype belongs to and whether it's inlined aren't kept, as the importer decides
+// both. Defaults are stored as constants //
+fn prototype_to_json(name: &str, prototype: &FunctionPrototype) -> Value {
+    let params = |params: &[Option<ParamLink>]| params.iter().map(|param| param.as_ref().map(|param| json!([
+        param.is_interior, param.link, param.linked_borrow, param.linked_io
+    ]))).collect::<Vec<_>>();
+    json!({
+        "name": name,
+        "id": prototype.id,
+        "owned_link_groups": prototype.owned_link_groups,
+        "borrow_params": params(&prototype.borrow_params),
+        "borrow_defaults": prototype.borrow_defaults.iter()
+            .map(|value| constant_to_json(&Constant::from_variable(value)))
+            .collect::<Vec<_>>(),
+        "steal_params": params(&prototype.steal_params),
+        "return_params": params(&prototype.return_params),
+        "is_mono": prototype.is_mono,
+        "is_pure": prototype.is_pure,
+        "is_generator": prototype.is_generator,
+        "is_pub": prototype.is_pub
+    })
+}
+
+fn prototype_from_json(value: &Value) -> Option<(String, FunctionPrototype)> {
+    let usize_at = |value: &Value| value.as_u64().map(|x| x as usize);
+    let indices = |value: &Value| value.as_array()?.iter().map(usize_at).collect::<Option<Vec<_>>>();
+    let params = |value: &Value| value.as_array()?.iter().map(|param| match param {
+        Value::Null => Some(None),
+        param => Some(Some(ParamLink{
+            is_interior: param.get(0)?.as_bool()?,
+            link: match param.get(1)? {
+                Value::Null => None,
+                link => Some(link.as_str()?.to_string())
+            },
+            linked_borrow: match param.get(2)? {
+                Value::Null => None,
+                idx => Some(usize_at(idx)?)
+            },
+            linked_io: match param.get(3)? {
+                Value::Null => None,
+                idx => Some(usize_at(idx)?)
+            }
+        }))
+    }).collect::<Option<Vec<_>>>();
+    let owned_link_groups = value["owned_link_groups"].as_array()?.iter().map(|groups| Some([
+        indices(groups.get(0)?)?, indices(groups.get(1)?)?, indices(groups.get(2)?)?
+    ])).collect::<Option<_>>()?;
+    let borrow_defaults = value["borrow_defaults"].as_array()?.iter()
+        .map(|value| constant_from_json(value).map(|value| value.to_variable()))
+        .collect::<Option<_>>()?;
+    Some((value["name"].as_str()?.to_string(), FunctionPrototype{
+        id: usize_at(&value["id"])?,
+        owned_link_groups,
+        borrow_params: params(&value["borrow_params"])?,
+        borrow_defaults,
+        steal_params: params(&value["steal_params"])?,
+        return_params: params(&value["return_params"])?,
+        is_inline: false,
+        is_mono: value["is_mono"].as_bool()?,
+        is_pure: value["is_pure"].as_bool()?,
+        is_generator: value["is_generator"].as_bool()?,
+        is_pub: value["is_pub"].as_bool()?,
+        module: None
+    }))
+}
@@ -0,0 +1,167 @@
+use std::collections::HashSet;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use crate::parsetree as PT;
+use crate::syntaxchecker::SyntaxError;
+
+
+// The build configuration: the flags #[cfg(...)] attributes are tested against, the directories
+// imported modules are searched for in, and whether the program is checked in strict or sketch
+// mode. A plain flag such as "debug" enables #[cfg(debug)], and a keyed one such as "feature=x"
+// enables #[cfg(feature = 'x')] //
+#[derive(Debug, Clone, Default)]
+pub struct Cfg {
+    flags: HashSet<String>,
+    include_paths: Vec<PathBuf>,
+    strict: bool,
+    sketch: bool,
+    check_aliasing: bool,
+    cache_modules: bool,
+    opt_level: u8
+}
+
+impl Cfg {
+    pub fn set(&mut self, flag: &str) {
+        let flag = match flag.split_once('=') {
+            Some((key, value)) => format!("{}={}", key.trim(), value.trim()),
+            None => flag.trim().to_string()
+        };
+        self.flags.insert(flag);
+    }
+
+    // Earlier paths take priority //
+    pub fn add_include_path(&mut self, path: &Path) {
+        if !self.include_paths.iter().any(|p| p == path) {
+            self.include_paths.push(path.to_path_buf());
+        }
+    }
+
+    pub fn flags(&self) -> Vec<&str> {
+        let mut flags: Vec<&str> = self.flags.iter().map(|flag| flag.as_str()).collect();
+        flags.sort();
+        flags
+    }
+
+    pub fn include_paths(&self) -> &[PathBuf] {
+        &self.include_paths
+    }
+
+    // Strict mode turns the language's permissive defaults into errors: if statements must give
+    // their backward condition, values can only become mono by using mono variables, and functions
+    // must uninitialise every variable they don't return //
+    pub fn set_strict(&mut self) {
+        self.strict = true;
+    }
+
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    // Sketch mode is the opposite, for prototyping: variables a function leaves bound that are
+    // provably still what their let made them are uninitialised for it, with a warning for each //
+    pub fn set_sketch(&mut self) {
+        self.sketch = true;
+    }
+
+    pub fn is_sketch(&self) -> bool {
+        self.sketch
+    }
+
+    // Compile checks that unreferencing removes a reference from what it points to, and that
+    // nothing references an element as it's pulled, catching what computed indices hide from the
+    // syntax checker //
+    pub fn set_check_aliasing(&mut self) {
+        self.check_aliasing = true;
+    }
+
+    pub fn checks_aliasing(&self) -> bool {
+        self.check_aliasing
+    }
+
+    // Imported modules are compiled to artifacts in the build cache, and loaded from there while
+    // their source and imports are unchanged //
+    pub fn set_cache_modules(&mut self) {
+        self.cache_modules = true;
+    }
+
+    pub fn caches_modules(&self) -> bool {
+        self.cache_modules
+    }
+
+    // The optimisations made while checking, such as hoisting loop invariants, follow the opt level
+    // the program is compiled at //
+    pub fn set_opt_level(&mut self, opt_level: u8) {
+        self.opt_level = opt_level;
+    }
+
+    pub fn opt_level(&self) -> u8 {
+        self.opt_level
+    }
+
+    // Add the flags and modes of another configuration, e.g. those given on the command line //
+    pub fn extend(&mut self, other: &Cfg) {
+        self.flags.extend(other.flags.iter().cloned());
+        for path in other.include_paths.iter() {
+            self.add_include_path(path);
+        }
+        self.strict |= other.strict;
+        self.sketch |= other.sketch;
+        self.check_aliasing |= other.check_aliasing;
+        self.cache_modules |= other.cache_modules;
+        self.opt_level = self.opt_level.max(other.opt_level);
+    }
+
+    fn is_enabled(&self, attribute: &PT::Attribute) -> Result<bool, SyntaxError> {
+        match attribute.args.as_slice() {
+            [(name, None)] => Ok(self.flags.contains(name)),
+            [(key, Some(value))] => Ok(self.flags.contains(&format!("{}={}", key, value))),
+            _ => Err(SyntaxError{line: attribute.line, col: attribute.col, desc: String::from(
+                "#[cfg] takes exactly one flag, e.g. #[cfg(debug)] or #[cfg(feature = 'x')]"), note: None})
+        }
+    }
+
+    // Whether an item with these attributes is compiled. The #[cfg] attributes are removed, leaving
+    // only those for later passes //
+    fn keep(&self, attributes: &mut Vec<PT::Attribute>) -> Result<bool, SyntaxError> {
+        let mut keep = true;
+        for attribute in attributes.iter().filter(|a| a.name == "cfg") {
+            keep &= self.is_enabled(attribute)?;
+        }
+        attributes.retain(|a| a.name != "cfg");
+        Ok(keep)
+    }
+}
+
+
+// Remove the statements and functions disabled by #[cfg] attributes, before syntax checking //
+pub fn configure(module: &mut PT::Module, cfg: &Cfg) -> Result<(), SyntaxError> {
+    configure_stmts(&mut module.global_func.stmts, cfg)?;
+    configure_functions(&mut module.functions, cfg)
+}
+
+fn configure_functions(functions: &mut Vec<PT::FunctionNode>, cfg: &Cfg) -> Result<(), SyntaxError> {
+    for mut function in mem::take(functions) {
+        if cfg.keep(&mut function.attributes)? {
+            configure_stmts(&mut function.stmts, cfg)?;
+            configure_functions(&mut function.functions, cfg)?;
+            functions.push(function);
+        }
+    }
+    Ok(())
+}
+
+pub fn configure_stmts(stmts: &mut Vec<PT::StatementNode>, cfg: &Cfg) -> Result<(), SyntaxError> {
+    for mut stmt in mem::take(stmts) {
+        let mut attributes = stmt.take_attributes();
+        if cfg.keep(&mut attributes)? {
+            if let Some(PT::Attribute{line, col, name, ..}) = attributes.into_iter().next() {
+                return Err(SyntaxError{line, col, desc: format!(
+                    "Statements can only take #[cfg] attributes, not #[{}]", name), note: None});
+            }
+            stmt.configure(cfg)?;
+            stmts.push(stmt);
+        }
+    }
+    Ok(())
+}
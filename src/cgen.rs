@@ -0,0 +1,196 @@
+use std::fmt::Write;
+
+use crate::interpreter::{Constant, Function, Instruction, Module};
+
+
+// Programs built with "--backend c" become a single C file: the runtime, followed by a forward and a
+// backward C function per Monoxide function. Each is a switch on the instruction pointer with one
+// labelled case per instruction, so execution falls through the stream and jumps are gotos. Reverse
+// returns the ip to resume at, and a small driver swaps direction until the stream ends //
+const RUNTIME: &str = include_str!("native_runtime.c");
+
+pub fn module_source(module: &Module, name: &str) -> Result<String, String> {
+    let main_idx = module.main_idx.ok_or_else(|| String::from("The c backend needs a main function"))?;
+    let mut out = String::from(RUNTIME);
+    writeln!(out, "\n\n/* ------------------------- Generated from {} ------------------------- */", name).unwrap();
+
+    writeln!(out, "\nstatic const char *STRINGS[] = {{").unwrap();
+    for string in module.strings.iter() {
+        writeln!(out, "    {},", string_literal(string)).unwrap();
+    }
+    writeln!(out, "    NULL\n}};").unwrap();
+
+    writeln!(out, "\nstatic void init_consts(void) {{").unwrap();
+    writeln!(out, "    consts = checked_alloc({} * sizeof(value_t));", module.consts.len()).unwrap();
+    for (idx, value) in module.consts.iter().enumerate() {
+        writeln!(out, "    consts[{}] = {};", idx, value_source(value)?).unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+
+    for idx in 0..module.functions.len() {
+        writeln!(out, "static void f{}(int forwards);", idx).unwrap();
+    }
+    for (idx, func) in module.functions.iter().enumerate() {
+        function_source(idx, func, &mut out)?;
+    }
+
+    let global_func = &module.functions[module.global_func_idx];
+    writeln!(out, concat!(
+        "\nint main(void) {{\n",
+        "    init_runtime({});\n",
+        "    init_consts();\n",
+        "    f{}(1);\n",
+        "    f{}(1);\n",
+        "    fflush(stdout);\n",
+        "    return 0;\n",
+        "}}"), global_func.num_registers.max(1), module.global_func_idx, main_idx).unwrap();
+    Ok(out)
+}
+
+fn value_source(value: &Constant) -> Result<String, String> {
+    Ok(match value {
+        Constant::Frac(val) => {
+            let (numer, denom) = match (val.numer().to_string().parse::<i64>(), val.denom().to_string().parse::<i64>()) {
+                (Ok(numer), Ok(denom)) => (numer, denom),
+                _ => return Err(format!("The c backend's fractions are 64-bit, and can't hold the constant {}", val))
+            };
+            if numer == i64::MIN {
+                format!("frac_value(frac_make((__int128)INT64_MIN, {}))", denom)
+            } else {
+                format!("frac_value(frac_make({}LL, {}LL))", numer, denom)
+            }
+        },
+        Constant::Float(val) => format!("float_value(from_bits({:#x}ULL))", val.to_bits()),
+        Constant::Str(val) => format!("str_value({})", string_literal(val)),
+        Constant::Handle(_) => unreachable!("Handles only exist in running programs"),
+        Constant::Array(items) => {
+            let items = items.iter().map(value_source).collect::<Result<Vec<_>, _>>()?;
+            format!("array_of({}{})", items.len(), items.iter().map(|item| format!(", {}", item)).collect::<String>())
+        }
+    })
+}
+
+// Anything outside printable ascii is written as an octal escape, which can't run into the next
+// character the way a hex escape can //
+fn string_literal(string: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in string.bytes() {
+        match byte {
+            b'"' => literal.push_str("\\\""),
+            b'\\' => literal.push_str("\\\\"),
+            b'?' => literal.push_str("\\?"),
+            b' '..=b'~' => literal.push(byte as char),
+            _ => write!(literal, "\\{:03o}", byte).unwrap()
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+fn function_source(idx: usize, func: &Function, out: &mut String) -> Result<(), String> {
+    writeln!(out, "\n/* {} */", func.name.replace("*/", "* /")).unwrap();
+    for (suffix, forwards) in [("fwd", true), ("bkwd", false)] {
+        let code = func.code.decoded(forwards);
+        writeln!(out, "static long f{}_{}(var_t **r, long ip) {{", idx, suffix).unwrap();
+        // Outlined blocks return by dispatching again on the ip they saved //
+        if code.iter().any(|instruction| matches!(instruction, Instruction::Gosub{..})) {
+            writeln!(out, "    long ret = 0;\n    dispatch: switch (ip) {{").unwrap();
+        } else {
+            writeln!(out, "    switch (ip) {{").unwrap();
+        }
+        for (ip, instruction) in code.iter().enumerate() {
+            let statement = instruction_source(instruction, ip, forwards).ok_or_else(|| format!(
+                "The c backend can't compile {} in \"{}\"", instruction.name(), func.name))?;
+            writeln!(out, "    case {0}: L{0}: {1};", ip, statement).unwrap();
+        }
+        writeln!(out, "    case {0}: L{0}: ;\n    }}\n    return -1;\n}}", code.len()).unwrap();
+    }
+    let num_registers = func.num_registers.max(1);
+    writeln!(out, "static void f{}(int forwards) {{", idx).unwrap();
+    writeln!(out, "    var_t *r[{}] = {{0}};\n    long ip = 0;", num_registers).unwrap();
+    writeln!(out, "    while ((ip = forwards ? f{0}_fwd(r, ip) : f{0}_bkwd(r, ip)) >= 0) forwards = !forwards;", idx).unwrap();
+    writeln!(out, "    release_registers(r, {});\n}}", num_registers).unwrap();
+    Ok(())
+}
+
+// The statement for an instruction's case. Reverse hands the ip back to the driver, and everything
+// else falls through into the next case //
+fn instruction_source(instruction: &Instruction, ip: usize, forwards: bool) -> Option<String> {
+    use Instruction::*;
+    let forwards = forwards as u8;
+    Some(match *instruction {
+        LoadConst{idx} => format!("load_const({})", idx),
+        LoadString{idx} => format!("push(str_value(STRINGS[{}]))", idx),
+        LoadRegister{register} => format!("load(r[{}])", register),
+        LoadGlobalRegister{register} => format!("load(globals[{}])", register),
+        StoreRegister{register} => format!("set_reg(&r[{}], pop_var())", register),
+        StoreGlobalRegister{register} => format!("set_reg(&globals[{}], pop_var())", register),
+        FreeRegister{register} => format!("set_reg(&r[{}], NULL)", register),
+        MoveRegister{from, to} => format!("move_reg(&r[{}], &r[{}])", from, to),
+        Subscript{size} => format!("subscript({})", size),
+        Slice => String::from("slice()"),
+        CheckRef{register} => format!("check_ref(r[{}])", register),
+        CheckUnshared{register} => format!("check_unshared(r[{}])", register),
+        Store => String::from("store()"),
+        Pull{register} => format!("pull(&r[{}], 0)", register),
+        PullFront{register} => format!("pull(&r[{}], 1)", register),
+        Push{register} => format!("push_item(&r[{}], 0)", register),
+        PushFront{register} => format!("push_item(&r[{}], 1)", register),
+        CreateInt{val} => format!("push(int_value({}))", val),
+        BinopAdd => String::from("arith(ADD, \"+\")"),
+        BinopSub => String::from("arith(SUB, \"-\")"),
+        BinopMul => String::from("arith(MUL, \"*\")"),
+        BinopDiv => String::from("arith(DIV, \"/\")"),
+        BinopMod => String::from("arith(MOD, \"%\")"),
+        BinopIDiv => String::from("idiv()"),
+        BinopPow => String::from("power()"),
+        Shift{bits} => format!("shift({})", bits),
+        BinopLess => String::from("compare(LESS, \"<\")"),
+        BinopLeq => String::from("compare(LEQ, \"<=\")"),
+        BinopGreat => String::from("compare(GREAT, \">\")"),
+        BinopGeq => String::from("compare(GEQ, \">=\")"),
+        BinopEq => String::from("equal(1)"),
+        BinopNeq => String::from("equal(0)"),
+        BinopXor => String::from("xor()"),
+        UniopNeg => String::from("neg()"),
+        UniopNot => String::from("not()"),
+        UniopLen => String::from("len()"),
+        UniopToFloat => String::from("to_float()"),
+        UniopToFrac => String::from("to_frac()"),
+        UniopSin => String::from("float_fn(\"sin\", sin)"),
+        UniopCos => String::from("float_fn(\"cos\", cos)"),
+        UniopExp => String::from("float_fn(\"exp\", exp)"),
+        UniopLog => String::from("float_fn(\"log\", log)"),
+        UniopSqrt => String::from("float_fn(\"sqrt\", sqrt)"),
+        ArrayLiteral{size} => format!("array_literal({})", size),
+        ArrayRepeat => String::from("array_repeat()"),
+        DuplicateRef => String::from("duplicate_ref()"),
+        UniqueVar => String::from("unique_var()"),
+        Call{idx} => format!("f{}(1)", idx),
+        Uncall{idx} => format!("f{}(0)", idx),
+        // As in the rust backend, remembered calls are simply made again //
+        Remember{idx} => format!("f{}(1)", idx),
+        CreateIter{register, reversed, index_register, depth} => format!(
+            "create_iter({}, {}, {}, {}, {})", register, reversed as u8,
+            index_register.map_or(-1, |reg| reg as isize), depth, forwards),
+        CreateRangeIter{register} => format!("create_range_iter({}, {})", register, forwards),
+        StepIter{ip} | ParallelStepIter{ip} => format!("if (!step_iter(r, {})) goto L{}", forwards, ip),
+        Print{count} => format!("print({})", count),
+        Expect => format!("expect({})", forwards),
+        ExpectEqual => format!("expect_equal({})", forwards),
+        Jump{ip} => format!("goto L{}", ip),
+        JumpIfTrue{ip} => format!("if (truth()) goto L{}", ip),
+        JumpIfFalse{ip} => format!("if (!truth()) goto L{}", ip),
+        Reverse{idx} => format!("return {}", idx),
+        Gosub{ip: target} => format!("ret = {}; goto L{}", ip + 1, target),
+        Return => String::from("ip = ret; goto dispatch"),
+        Quit => String::from("quit()"),
+        Hole => String::from("fail(\"Evaluated the unfinished expression \\\"\\?\\?\\?\\\"\")"),
+        Todo => String::from("fail(\"Ran the unfinished statement \\\"todo\\\"\")"),
+        ClockMs => String::from("clock_ms()"),
+        Marker{..} => String::new(),
+        Steps | Backtrace | DebugPrint | BinopAnd | BinopOr |
+        RelativeJump{..} | RelativeJumpIfTrue{..} | RelativeJumpIfFalse{..} |
+        CreateGenerator{..} | Resume{..} | Suspend | HostCall => return None
+    })
+}
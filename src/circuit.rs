@@ -0,0 +1,1014 @@
+use num_traits::{One, ToPrimitive};
+
+use crate::interpreter::{Instruction, Variable};
+use crate::syntaxchecker::SyntaxError;
+use crate::syntaxtree as ST;
+use crate::typechecker::operator_symbol;
+use ST::Type;
+
+
+// Exports a function as a reversible circuit of X gates with any number of controls, written as
+// OpenQASM 3. Numbers become words of a fixed number of qubits, least significant first, so
+// arithmetic wraps as with "--arithmetic wrapping" at that width. Calls are inlined, for loops
+// unrolled, and if statements become gates controlled by a helper qubit holding the condition.
+// Only what has a fixed-size circuit translates, and anything else is an error at the statement
+// using it. Every qubit but the entry function's inputs starts at |0>, and the helper qubits are
+// back at |0> by the end, provided the program's unlet and fi assertions hold //
+pub struct CircuitContext<'a> {
+    functions: &'a [ST::FunctionNode],
+    function: &'a ST::FunctionNode,
+    bits: usize,
+    backwards: bool,
+    registers: Vec<Option<Wires>>,
+    controls: Vec<usize>,  // The qubits every gate is currently conditioned on
+    inlined: Vec<usize>,   // The functions being inlined, to catch recursion
+    free: Vec<usize>,      // Released qubits, which are back at |0>
+    holding: usize,        // Inside do blocks, whose undoing reruns gates on released qubits
+    num_qubits: usize,
+    gates: Vec<Gate>,
+    position: (usize, usize)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Wires {
+    Word(Vec<usize>),
+    Array(Vec<Wires>),
+    Const(i64)  // An unrolled loop's variable, known as the circuit is built
+}
+
+// Flips the target when every control is |1>. Each gate is its own inverse, so a sequence is
+// undone by running it in reverse //
+#[derive(Clone, Debug)]
+struct Gate {
+    controls: Vec<usize>,
+    target: usize
+}
+
+// A test that holds when every control is |1> once the setup gates have run, or that holds when
+// they aren't all |1> if negated //
+pub struct Condition {
+    setup: Vec<Gate>,
+    controls: Vec<usize>,
+    negated: bool
+}
+
+pub trait CircuitExpression {
+    // The value, when it's known as the circuit is built //
+    fn circuit_constant(&self, _ctx: &CircuitContext) -> Option<i64> {None}
+
+    // The values of an array repetition's dimensions //
+    fn circuit_constants(&self, ctx: &CircuitContext) -> Option<Vec<i64>> {
+        self.circuit_constant(ctx).map(|value| vec![value])
+    }
+
+    // The wires of the variable or array item looked up //
+    fn circuit_lookup(&self, _ctx: &CircuitContext) -> Option<Result<Wires, SyntaxError>> {None}
+
+    // Add the value to a word, or subtract it if negated //
+    fn circuit_add(&self, ctx: &mut CircuitContext, target: &[usize], negated: bool) -> Result<(), SyntaxError>;
+
+    // New wires shaped to hold the value //
+    fn circuit_allocate(&self, ctx: &mut CircuitContext) -> Result<Wires, SyntaxError> {
+        match self.circuit_lookup(ctx) {
+            Some(wires) => Ok(ctx.allocate_like(&wires?)),
+            None => Ok(Wires::Word(ctx.allocate_word()))
+        }
+    }
+
+    // Bind the value into wires at |0>, or unbind it from wires holding it if negated. Copies are
+    // made with CNOTs, which clear the copy when run again //
+    fn circuit_fill(&self, ctx: &mut CircuitContext, target: &Wires, negated: bool) -> Result<(), SyntaxError> {
+        if let Some(value) = self.circuit_constant(ctx) {
+            return match target {
+                Wires::Word(target) => {
+                    ctx.xor_constant(target, value);
+                    Ok(())
+                },
+                _ => Err(ctx.error(String::from("Binding an array to a number")))
+            };
+        }
+        match self.circuit_lookup(ctx).transpose()? {
+            Some(source) => ctx.copy(&source, target),
+            None => match target {
+                Wires::Word(target) => self.circuit_add(ctx, target, negated),
+                _ => Err(ctx.unsupported("building arrays from this expression"))
+            }
+        }
+    }
+
+    fn circuit_condition(&self, ctx: &CircuitContext) -> Result<Condition, SyntaxError> {
+        match self.circuit_constant(ctx) {
+            Some(value) => Ok(Condition::constant(value != 0)),
+            None => Err(ctx.unsupported("conditions other than testing words for equality"))
+        }
+    }
+}
+
+pub trait CircuitStatement {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError>;
+}
+
+
+impl Condition {
+    fn constant(value: bool) -> Condition {
+        Condition{setup: Vec::new(), controls: Vec::new(), negated: !value}
+    }
+}
+
+impl<'a> CircuitContext<'a> {
+    fn error(&self, desc: String) -> SyntaxError {
+        SyntaxError{line: self.position.0, col: self.position.1, desc, note: None}
+    }
+
+    fn unsupported(&self, what: &str) -> SyntaxError {
+        self.error(format!("The circuit export doesn't support {}", what))
+    }
+
+    fn emit_stmts(&mut self, stmts: &[ST::StatementNode]) -> Result<(), SyntaxError> {
+        if self.backwards {
+            stmts.iter().rev().try_for_each(|stmt| stmt.emit_circuit(self))
+        } else {
+            stmts.iter().try_for_each(|stmt| stmt.emit_circuit(self))
+        }
+    }
+
+    fn gate(&mut self, controls: &[usize], target: usize) {
+        let controls = self.controls.iter().chain(controls).copied().collect();
+        self.gates.push(Gate{controls, target});
+    }
+
+    // Run the gates an emitter adds in reverse, computing its inverse //
+    fn inverted<F>(&mut self, emit: F) -> Result<(), SyntaxError>
+        where F: FnOnce(&mut Self) -> Result<(), SyntaxError>
+    {
+        let start = self.gates.len();
+        emit(self)?;
+        self.gates[start..].reverse();
+        Ok(())
+    }
+
+    fn allocate_qubit(&mut self) -> usize {
+        self.free.pop().unwrap_or_else(|| {
+            self.num_qubits += 1;
+            self.num_qubits - 1
+        })
+    }
+
+    fn allocate_word(&mut self) -> Vec<usize> {
+        (0..self.bits).map(|_| self.allocate_qubit()).collect()
+    }
+
+    fn allocate_like(&mut self, wires: &Wires) -> Wires {
+        match wires {
+            Wires::Array(items) => Wires::Array(items.iter().map(|item| self.allocate_like(item)).collect()),
+            _ => Wires::Word(self.allocate_word())
+        }
+    }
+
+    fn release(&mut self, wires: Wires) {
+        match wires {
+            Wires::Word(qubits) if self.holding == 0 => self.free.extend(qubits.into_iter().rev()),
+            Wires::Array(items) => items.into_iter().for_each(|item| self.release(item)),
+            _ => ()
+        }
+    }
+
+    fn constant(&self, const_idx: usize) -> Result<i64, SyntaxError> {
+        match &self.function.consts[const_idx] {
+            Variable::Frac(val) if val.denom().is_one() => val.numer().to_i64()
+                .ok_or_else(|| self.error(format!("{} doesn't fit in 64 bits", val))),
+            _ => Err(self.unsupported("values other than integers"))
+        }
+    }
+
+    // Two's complement, so negative numbers wrap around like any other //
+    fn constant_bits(&self, value: i64) -> impl Iterator<Item=usize> {
+        let value = value as u64;
+        (0..self.bits.min(64)).filter(move |&bit| value >> bit & 1 == 1)
+    }
+
+    fn xor_constant(&mut self, target: &[usize], value: i64) {
+        for bit in self.constant_bits(value).collect::<Vec<_>>() {
+            self.gate(&[], target[bit]);
+        }
+    }
+
+    // Add one to a word, or to some of its top bits, flipping each bit whose lower bits are all
+    // |1>, from the top down //
+    fn increment(&mut self, target: &[usize], controls: &[usize]) {
+        for idx in (0..target.len()).rev() {
+            let controls: Vec<usize> = controls.iter().chain(&target[..idx]).copied().collect();
+            self.gate(&controls, target[idx]);
+        }
+    }
+
+    fn add_constant(&mut self, target: &[usize], value: i64, negated: bool) {
+        let value = if negated {value.wrapping_neg()} else {value};
+        for bit in self.constant_bits(value).collect::<Vec<_>>() {
+            self.increment(&target[bit..], &[]);
+        }
+    }
+
+    // Add a word shifted left, one increment per bit conditioned on that bit //
+    fn add_word(&mut self, target: &[usize], source: &[usize], shift: usize, negated: bool) -> Result<(), SyntaxError> {
+        if source.iter().any(|qubit| target.contains(qubit)) {
+            return Err(self.error(String::from("Adding a word to itself, which can't be undone")));
+        }
+        let emit = |ctx: &mut Self| {
+            for bit in 0..target.len().saturating_sub(shift) {
+                ctx.increment(&target[bit + shift..], &[source[bit]]);
+            }
+            Ok(())
+        };
+        if negated {self.inverted(emit)} else {emit(self)}
+    }
+
+    fn copy(&mut self, source: &Wires, target: &Wires) -> Result<(), SyntaxError> {
+        match (source, target) {
+            (Wires::Const(value), Wires::Word(target)) => self.xor_constant(target, *value),
+            (Wires::Word(source), Wires::Word(target)) => {
+                for (&source, &target) in source.iter().zip(target) {
+                    self.gate(&[source], target);
+                }
+            },
+            (Wires::Array(sources), Wires::Array(targets)) if sources.len() == targets.len() => {
+                for (source, target) in sources.iter().zip(targets) {
+                    self.copy(source, target)?;
+                }
+            },
+            _ => return Err(self.error(String::from("Binding a value to wires of a different shape")))
+        }
+        Ok(())
+    }
+
+    fn lookup_wires(&self, lookup: &ST::LookupNode) -> Result<Wires, SyntaxError> {
+        if lookup.is_global {
+            return Err(self.unsupported("global variables"));
+        }
+        let mut wires = self.registers[lookup.register].clone()
+            .ok_or_else(|| self.error(String::from("Using a variable before it's bound")))?;
+        for index in &lookup.indices {
+            let index = index.circuit_constant(self)
+                .ok_or_else(|| self.unsupported("indices that aren't known as the circuit is built"))?;
+            wires = match wires {
+                Wires::Array(mut items) if index >= 0 && (index as usize) < items.len() => items.swap_remove(index as usize),
+                Wires::Array(items) => return Err(self.error(format!(
+                    "Index {} is out of bounds for an array of length {}", index, items.len()))),
+                _ => return Err(self.error(String::from("Indexing a number")))
+            };
+        }
+        Ok(wires)
+    }
+
+    fn lookup_word(&self, lookup: &ST::LookupNode) -> Result<Vec<usize>, SyntaxError> {
+        match self.lookup_wires(lookup)? {
+            Wires::Word(qubits) => Ok(qubits),
+            Wires::Const(_) => Err(self.error(String::from("Modifying a loop variable"))),
+            Wires::Array(_) => Err(self.unsupported("adding to whole arrays"))
+        }
+    }
+
+    // Flip a helper qubit if the condition holds and the gate's other controls are all |1> //
+    fn compute(&mut self, condition: &Condition, target: usize) {
+        self.gates.extend(condition.setup.iter().cloned());
+        self.gate(&condition.controls, target);
+        if condition.negated {
+            self.gate(&[], target);
+        }
+        self.gates.extend(condition.setup.iter().rev().cloned());
+    }
+
+    fn bind(&mut self, register: usize, wires: Wires) {
+        self.registers[register] = Some(wires);
+    }
+
+    fn take(&mut self, register: usize) -> Result<Wires, SyntaxError> {
+        self.registers[register].take().ok_or_else(|| self.error(String::from("Using a variable before it's bound")))
+    }
+}
+
+
+// ---------------------------- Expression Nodes ---------------------------- //
+
+impl CircuitExpression for ST::FractionNode {
+    fn circuit_constant(&self, ctx: &CircuitContext) -> Option<i64> {
+        ctx.constant(self.const_idx).ok()
+    }
+
+    fn circuit_add(&self, ctx: &mut CircuitContext, target: &[usize], negated: bool) -> Result<(), SyntaxError> {
+        let value = ctx.constant(self.const_idx)?;
+        ctx.add_constant(target, value, negated);
+        Ok(())
+    }
+}
+
+impl CircuitExpression for ST::StringNode {
+    fn circuit_add(&self, ctx: &mut CircuitContext, _target: &[usize], _negated: bool) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("strings"))
+    }
+}
+
+impl CircuitExpression for ST::HoistedNode {
+    fn circuit_constant(&self, ctx: &CircuitContext) -> Option<i64> {
+        self.expr.circuit_constant(ctx)
+    }
+
+    fn circuit_lookup(&self, ctx: &CircuitContext) -> Option<Result<Wires, SyntaxError>> {
+        self.expr.circuit_lookup(ctx)
+    }
+
+    fn circuit_add(&self, ctx: &mut CircuitContext, target: &[usize], negated: bool) -> Result<(), SyntaxError> {
+        self.expr.circuit_add(ctx, target, negated)
+    }
+
+    fn circuit_condition(&self, ctx: &CircuitContext) -> Result<Condition, SyntaxError> {
+        self.expr.circuit_condition(ctx)
+    }
+}
+
+impl CircuitExpression for ST::HoleNode {
+    fn circuit_add(&self, ctx: &mut CircuitContext, _target: &[usize], _negated: bool) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("holes"))
+    }
+}
+
+impl CircuitExpression for ST::CounterNode {
+    fn circuit_add(&self, ctx: &mut CircuitContext, _target: &[usize], _negated: bool) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported(&format!("{}()", operator_symbol(&self.op))))
+    }
+}
+
+impl CircuitExpression for ST::ArrayLiteralNode {
+    fn circuit_constants(&self, ctx: &CircuitContext) -> Option<Vec<i64>> {
+        self.items.iter().map(|item| item.circuit_constant(ctx)).collect()
+    }
+
+    fn circuit_add(&self, ctx: &mut CircuitContext, _target: &[usize], _negated: bool) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("arrays in arithmetic"))
+    }
+
+    fn circuit_allocate(&self, ctx: &mut CircuitContext) -> Result<Wires, SyntaxError> {
+        Ok(Wires::Array(self.items.iter().map(|item| item.circuit_allocate(ctx)).collect::<Result<_, _>>()?))
+    }
+
+    fn circuit_fill(&self, ctx: &mut CircuitContext, target: &Wires, negated: bool) -> Result<(), SyntaxError> {
+        match target {
+            Wires::Array(targets) if targets.len() == self.items.len() => {
+                self.items.iter().zip(targets).try_for_each(|(item, target)| item.circuit_fill(ctx, target, negated))
+            },
+            _ => Err(ctx.error(String::from("Binding an array literal to wires of a different shape")))
+        }
+    }
+}
+
+impl CircuitExpression for ST::ArrayRepeatNode {
+    fn circuit_add(&self, ctx: &mut CircuitContext, _target: &[usize], _negated: bool) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("arrays in arithmetic"))
+    }
+
+    fn circuit_allocate(&self, ctx: &mut CircuitContext) -> Result<Wires, SyntaxError> {
+        let dimensions = match self.dimensions.circuit_constants(ctx) {
+            Some(dimensions) if !dimensions.is_empty() && dimensions.iter().all(|&size| size >= 0) => dimensions,
+            _ => return Err(ctx.unsupported("arrays whose size isn't known as the circuit is built"))
+        };
+        let mut wires = Vec::new();
+        let count: i64 = dimensions.iter().product();
+        for _ in 0..count {
+            wires.push(self.item.circuit_allocate(ctx)?);
+        }
+        for &size in dimensions[1..].iter().rev() {
+            wires = wires.chunks(size as usize).map(|chunk| Wires::Array(chunk.to_vec())).collect();
+        }
+        Ok(Wires::Array(wires))
+    }
+
+    fn circuit_fill(&self, ctx: &mut CircuitContext, target: &Wires, negated: bool) -> Result<(), SyntaxError> {
+        let depth = self.dimensions.circuit_constants(ctx).map_or(1, |dimensions| dimensions.len());
+        fill_repeated(&*self.item, ctx, target, depth, negated)
+    }
+}
+
+fn fill_repeated(item: &dyn ST::Expression, ctx: &mut CircuitContext, target: &Wires, depth: usize, negated: bool) -> Result<(), SyntaxError> {
+    match target {
+        Wires::Array(targets) if depth > 0 => {
+            targets.iter().try_for_each(|target| fill_repeated(item, ctx, target, depth - 1, negated))
+        },
+        _ => item.circuit_fill(ctx, target, negated)
+    }
+}
+
+impl CircuitExpression for ST::ComprehensionNode {
+    fn circuit_add(&self, ctx: &mut CircuitContext, _target: &[usize], _negated: bool) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("comprehensions"))
+    }
+}
+
+impl CircuitExpression for ST::CallExprNode {
+    fn circuit_add(&self, ctx: &mut CircuitContext, _target: &[usize], _negated: bool) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("calls in expressions"))
+    }
+}
+
+impl CircuitExpression for ST::LookupNode {
+    fn circuit_constant(&self, ctx: &CircuitContext) -> Option<i64> {
+        match ctx.lookup_wires(self) {
+            Ok(Wires::Const(value)) => Some(value),
+            _ => None
+        }
+    }
+
+    fn circuit_lookup(&self, ctx: &CircuitContext) -> Option<Result<Wires, SyntaxError>> {
+        Some(ctx.lookup_wires(self))
+    }
+
+    fn circuit_add(&self, ctx: &mut CircuitContext, target: &[usize], negated: bool) -> Result<(), SyntaxError> {
+        match ctx.lookup_wires(self)? {
+            Wires::Word(source) => ctx.add_word(target, &source, 0, negated),
+            Wires::Const(value) => {
+                ctx.add_constant(target, value, negated);
+                Ok(())
+            },
+            Wires::Array(_) => Err(ctx.unsupported("arrays in arithmetic"))
+        }
+    }
+
+    // Whether any bit is set, as not all of the flipped bits are //
+    fn circuit_condition(&self, ctx: &CircuitContext) -> Result<Condition, SyntaxError> {
+        match ctx.lookup_wires(self)? {
+            Wires::Word(qubits) => Ok(Condition{
+                setup: qubits.iter().map(|&target| Gate{controls: Vec::new(), target}).collect(),
+                controls: qubits,
+                negated: true
+            }),
+            Wires::Const(value) => Ok(Condition::constant(value != 0)),
+            Wires::Array(_) => Err(ctx.unsupported("testing whole arrays"))
+        }
+    }
+}
+
+impl CircuitExpression for ST::BinopNode {
+    fn circuit_constant(&self, ctx: &CircuitContext) -> Option<i64> {
+        let (lhs, rhs) = (self.lhs.circuit_constant(ctx)?, self.rhs.circuit_constant(ctx)?);
+        Some(match self.op {
+            Instruction::BinopAdd => lhs.wrapping_add(rhs),
+            Instruction::BinopSub => lhs.wrapping_sub(rhs),
+            Instruction::BinopMul => lhs.wrapping_mul(rhs),
+            Instruction::BinopDiv if rhs != 0 && lhs % rhs == 0 => lhs / rhs,
+            Instruction::BinopIDiv if rhs != 0 => lhs / rhs,
+            Instruction::BinopMod if rhs != 0 => lhs % rhs,
+            Instruction::BinopLess => (lhs < rhs) as i64,
+            Instruction::BinopLeq => (lhs <= rhs) as i64,
+            Instruction::BinopGreat => (lhs > rhs) as i64,
+            Instruction::BinopGeq => (lhs >= rhs) as i64,
+            Instruction::BinopEq => (lhs == rhs) as i64,
+            Instruction::BinopNeq => (lhs != rhs) as i64,
+            Instruction::BinopAnd => (lhs != 0 && rhs != 0) as i64,
+            Instruction::BinopOr => (lhs != 0 || rhs != 0) as i64,
+            Instruction::BinopXor => ((lhs != 0) != (rhs != 0)) as i64,
+            _ => return None
+        })
+    }
+
+    fn circuit_add(&self, ctx: &mut CircuitContext, target: &[usize], negated: bool) -> Result<(), SyntaxError> {
+        if let Some(value) = self.circuit_constant(ctx) {
+            ctx.add_constant(target, value, negated);
+            return Ok(());
+        }
+        match self.op {
+            Instruction::BinopAdd | Instruction::BinopSub => {
+                self.lhs.circuit_add(ctx, target, negated)?;
+                self.rhs.circuit_add(ctx, target, negated != (self.op == Instruction::BinopSub))
+            },
+            // Multiplying by a constant adds a shifted copy of the word for each bit it has set //
+            Instruction::BinopMul => {
+                let (value, word) = match (self.lhs.circuit_constant(ctx), self.rhs.circuit_constant(ctx)) {
+                    (Some(value), None) => (value, &self.rhs),
+                    (None, Some(value)) => (value, &self.lhs),
+                    _ => return Err(ctx.unsupported("multiplying words together"))
+                };
+                let source = match word.circuit_lookup(ctx).transpose()? {
+                    Some(Wires::Word(source)) => source,
+                    _ => return Err(ctx.unsupported("multiplying anything but a variable by a constant"))
+                };
+                for shift in ctx.constant_bits(value).collect::<Vec<_>>() {
+                    ctx.add_word(target, &source, shift, negated)?;
+                }
+                Ok(())
+            },
+            _ => Err(ctx.unsupported(&format!("the \"{}\" operator outside of conditions", operator_symbol(&self.op))))
+        }
+    }
+
+    fn circuit_condition(&self, ctx: &CircuitContext) -> Result<Condition, SyntaxError> {
+        if let Some(value) = self.circuit_constant(ctx) {
+            return Ok(Condition::constant(value != 0));
+        }
+        match self.op {
+            Instruction::BinopEq | Instruction::BinopNeq => {
+                let mut condition = equality(ctx, &*self.lhs, &*self.rhs)?;
+                condition.negated = self.op == Instruction::BinopNeq;
+                Ok(condition)
+            },
+            // Conjunctions of tests that hold when their controls are all |1> share one gate, and
+            // so do disjunctions of negated tests //
+            Instruction::BinopAnd | Instruction::BinopOr => {
+                let (mut lhs, rhs) = (self.lhs.circuit_condition(ctx)?, self.rhs.circuit_condition(ctx)?);
+                let negated = self.op == Instruction::BinopOr;
+                if lhs.negated != negated || rhs.negated != negated {
+                    return Err(ctx.unsupported(&format!(
+                        "\"{}\" of these conditions; test each in its own if statement",
+                        operator_symbol(&self.op))));
+                }
+                lhs.setup.extend(rhs.setup);
+                lhs.controls.extend(rhs.controls);
+                Ok(lhs)
+            },
+            _ => Err(ctx.unsupported(&format!("the \"{}\" operator in conditions", operator_symbol(&self.op))))
+        }
+    }
+}
+
+// Whether two words are equal, as every bit of their complemented xor is |1> //
+fn equality(ctx: &CircuitContext, lhs: &dyn ST::Expression, rhs: &dyn ST::Expression) -> Result<Condition, SyntaxError> {
+    let word = |expr: &dyn ST::Expression| match expr.circuit_lookup(ctx).transpose()? {
+        Some(Wires::Word(qubits)) => Ok(Some(qubits)),
+        Some(Wires::Array(_)) => Err(ctx.unsupported("comparing whole arrays")),
+        _ => match expr.circuit_constant(ctx) {
+            Some(_) => Ok(None),
+            None => Err(ctx.unsupported("comparing anything but variables and constants"))
+        }
+    };
+    let flip = |target: usize| Gate{controls: Vec::new(), target};
+    let (qubits, setup) = match (word(lhs)?, word(rhs)?) {
+        (Some(lhs), Some(rhs)) => {
+            if lhs.iter().any(|qubit| rhs.contains(qubit)) {
+                return Err(ctx.error(String::from("Comparing a word with itself")));
+            }
+            let mut setup: Vec<Gate> = lhs.iter().zip(&rhs).map(|(&control, &target)| Gate{controls: vec![control], target}).collect();
+            setup.extend(rhs.iter().map(|&target| flip(target)));
+            (rhs, setup)
+        },
+        (Some(qubits), None) | (None, Some(qubits)) => {
+            let value = lhs.circuit_constant(ctx).or_else(|| rhs.circuit_constant(ctx)).unwrap_or_default() as u64;
+            let setup = qubits.iter().enumerate()
+                .filter(|&(bit, _)| value >> bit & 1 == 0)
+                .map(|(_, &target)| flip(target)).collect();
+            (qubits, setup)
+        },
+        (None, None) => unreachable!()
+    };
+    Ok(Condition{setup, controls: qubits, negated: false})
+}
+
+impl CircuitExpression for ST::UniopNode {
+    fn circuit_constant(&self, ctx: &CircuitContext) -> Option<i64> {
+        let value = self.expr.circuit_constant(ctx)?;
+        match self.op {
+            Instruction::UniopNeg => Some(value.wrapping_neg()),
+            Instruction::UniopNot => Some((value == 0) as i64),
+            _ => None
+        }
+    }
+
+    fn circuit_add(&self, ctx: &mut CircuitContext, target: &[usize], negated: bool) -> Result<(), SyntaxError> {
+        match self.op {
+            Instruction::UniopNeg => self.expr.circuit_add(ctx, target, !negated),
+            _ => Err(ctx.unsupported(&format!("\"{}\"", operator_symbol(&self.op))))
+        }
+    }
+
+    fn circuit_condition(&self, ctx: &CircuitContext) -> Result<Condition, SyntaxError> {
+        match self.op {
+            Instruction::UniopNot => {
+                let mut condition = self.expr.circuit_condition(ctx)?;
+                condition.negated = !condition.negated;
+                Ok(condition)
+            },
+            _ => Err(ctx.unsupported(&format!("\"{}\" in conditions", operator_symbol(&self.op))))
+        }
+    }
+}
+
+
+// ---------------------------- Statement Nodes ----------------------------- //
+
+impl CircuitStatement for ST::PositionedNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        ctx.position = (self.line, self.col);
+        self.stmt.emit_circuit(ctx)
+    }
+}
+
+impl CircuitStatement for ST::PrintNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("printing"))
+    }
+}
+
+impl CircuitStatement for ST::LetUnletNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        if self.is_mono {
+            return Err(ctx.unsupported("mono variables"));
+        }
+        if self.is_unlet == ctx.backwards {
+            let wires = self.rhs.circuit_allocate(ctx)?;
+            self.rhs.circuit_fill(ctx, &wires, false)?;
+            ctx.bind(self.register, wires);
+        } else {
+            let wires = ctx.take(self.register)?;
+            self.rhs.circuit_fill(ctx, &wires, true)?;
+            ctx.release(wires);
+        }
+        Ok(())
+    }
+}
+
+impl CircuitStatement for ST::RefUnrefNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        if self.range.is_some() {
+            return Err(ctx.unsupported("references to a range of elements"));
+        }
+        if self.is_unref == ctx.backwards {
+            let wires = ctx.lookup_wires(&self.rhs)?;
+            ctx.bind(self.register, wires);
+        } else {
+            ctx.registers[self.register] = None;
+        }
+        Ok(())
+    }
+}
+
+impl CircuitStatement for ST::RelinkNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        let wires = ctx.lookup_wires(if ctx.backwards {&self.old} else {&self.new})?;
+        ctx.bind(self.register, wires);
+        Ok(())
+    }
+}
+
+impl CircuitStatement for ST::MoveNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        if self.is_mono {
+            return Err(ctx.unsupported("mono variables"));
+        }
+        let (from, to) = if ctx.backwards {(self.to, self.from)} else {(self.from, self.to)};
+        let wires = ctx.take(from)?;
+        ctx.bind(to, wires);
+        Ok(())
+    }
+}
+
+impl CircuitStatement for ST::ModopNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        if self.is_mono {
+            return Err(ctx.unsupported("mono variables"));
+        }
+        let negated = match self.op {
+            Instruction::BinopAdd => ctx.backwards,
+            Instruction::BinopSub => !ctx.backwards,
+            _ => return Err(ctx.unsupported(&format!("\"{}=\", only \"+=\" and \"-=\"", operator_symbol(&self.op))))
+        };
+        let target = ctx.lookup_word(&self.lookup)?;
+        self.rhs.circuit_add(ctx, &target, negated)
+    }
+}
+
+impl CircuitStatement for ST::DestructureLetNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("destructuring arrays"))
+    }
+}
+
+impl CircuitStatement for ST::PushPullNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("pushing and pulling, as arrays in circuits have a fixed size"))
+    }
+}
+
+// The branches are conditioned on a helper qubit holding the entry condition, which the exit
+// condition returns to |0> afterwards //
+impl CircuitStatement for ST::IfNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        if self.is_mono {
+            return Err(ctx.unsupported("mono if statements"));
+        }
+        let (enter, exit) = match ctx.backwards {
+            false => (&self.fwd_expr, &self.bkwd_expr),
+            true => (&self.bkwd_expr, &self.fwd_expr)
+        };
+        let enter = enter.circuit_condition(ctx)?;
+        let flag = ctx.allocate_qubit();
+        ctx.compute(&enter, flag);
+        let outer = std::mem::replace(&mut ctx.controls, vec![flag]);
+        ctx.emit_stmts(&self.if_stmts)?;
+        if !self.else_stmts.is_empty() {
+            ctx.gates.push(Gate{controls: Vec::new(), target: flag});
+            ctx.controls = outer.iter().copied().chain([flag]).collect();
+            ctx.emit_stmts(&self.else_stmts)?;
+            ctx.gates.push(Gate{controls: Vec::new(), target: flag});
+        }
+        ctx.controls = outer;
+        let exit = exit.circuit_condition(ctx)?;
+        ctx.compute(&exit, flag);
+        ctx.release(Wires::Word(vec![flag]));
+        Ok(())
+    }
+}
+
+impl CircuitStatement for ST::WhileNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("while loops, which have no fixed number of iterations"))
+    }
+}
+
+impl CircuitStatement for ST::ForNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        if self.is_mono {
+            return Err(ctx.unsupported("mono for loops"));
+        }
+        let items = match ctx.lookup_wires(&self.iterator)? {
+            Wires::Array(items) => items,
+            _ => return Err(ctx.error(String::from("For loop iterator is not an array")))
+        };
+        let mut order: Vec<usize> = (0..items.len()).collect();
+        if self.reversed != ctx.backwards {
+            order.reverse();
+        }
+        for idx in order {
+            ctx.bind(self.register, items[idx].clone());
+            if let Some(index_register) = self.index_register {
+                ctx.bind(index_register, Wires::Const(idx as i64));
+            }
+            ctx.emit_stmts(&self.stmts)?;
+        }
+        ctx.registers[self.register] = None;
+        if let Some(index_register) = self.index_register {
+            ctx.registers[index_register] = None;
+        }
+        Ok(())
+    }
+}
+
+impl CircuitStatement for ST::ForRangeNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        if self.is_mono {
+            return Err(ctx.unsupported("mono for loops"));
+        }
+        let bounds = (self.start.circuit_constant(ctx), self.end.circuit_constant(ctx), self.step.circuit_constant(ctx));
+        let (start, end, step) = match bounds {
+            (Some(start), Some(end), Some(step)) if step != 0 => (start, end, step),
+            _ => return Err(ctx.unsupported("ranges whose bounds aren't known as the circuit is built"))
+        };
+        let count = ((end - start) as f64 / step as f64).ceil().max(0.0) as i64;
+        let mut order: Vec<i64> = (0..count).collect();
+        if ctx.backwards {
+            order.reverse();
+        }
+        for idx in order {
+            ctx.bind(self.register, Wires::Const(start + step * idx));
+            ctx.emit_stmts(&self.stmts)?;
+        }
+        ctx.registers[self.register] = None;
+        Ok(())
+    }
+}
+
+impl CircuitStatement for ST::ForGenNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("generators"))
+    }
+}
+
+impl CircuitStatement for ST::YieldNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("generators"))
+    }
+}
+
+// The do block always runs forwards, and is undone by its gates in reverse, which frees what it
+// bound. Its released qubits aren't reused until then, as undoing it runs gates on them again //
+impl CircuitStatement for ST::DoYieldNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        let before = ctx.registers.clone();
+        let backwards = std::mem::replace(&mut ctx.backwards, false);
+        let start = ctx.gates.len();
+        ctx.holding += 1;
+        ctx.emit_stmts(&self.do_stmts)?;
+        let undo: Vec<Gate> = ctx.gates[start..].iter().rev().cloned().collect();
+        let after_do = ctx.registers.clone();
+        ctx.backwards = backwards;
+        ctx.emit_stmts(&self.yield_stmts)?;
+        ctx.gates.extend(undo);
+        ctx.holding -= 1;
+        for (register, (before, after_do)) in before.into_iter().zip(after_do).enumerate() {
+            if before != after_do {
+                if let Some(wires) = after_do.filter(|_| before.is_none()) {
+                    ctx.release(wires);
+                }
+                ctx.registers[register] = before;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CircuitStatement for ST::StaticAssertNode {
+    fn emit_circuit(&self, _ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        Ok(())
+    }
+}
+
+impl CircuitStatement for ST::BacktraceNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("backtrace"))
+    }
+}
+
+impl CircuitStatement for ST::TodoNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("todo"))
+    }
+}
+
+// Expectations only check the state, so are left out //
+impl CircuitStatement for ST::ExpectNode {
+    fn emit_circuit(&self, _ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        Ok(())
+    }
+}
+
+impl CircuitStatement for ST::CatchNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("catch"))
+    }
+}
+
+impl CircuitStatement for ST::TryNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("try/catch"))
+    }
+}
+
+// The memo table only saves recomputing calls, so the block is simply inlined //
+impl CircuitStatement for ST::RememberNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        ctx.emit_stmts(&self.body)
+    }
+}
+
+// Calls are inlined, with the callee's statements emitted in reverse for an uncall, or for a call
+// being run backwards //
+impl CircuitStatement for ST::CallNode {
+    fn emit_circuit(&self, ctx: &mut CircuitContext) -> Result<(), SyntaxError> {
+        let functions = ctx.functions;
+        let func = &functions[self.func_idx];
+        if self.is_mono {
+            return Err(ctx.unsupported("mono calls"));
+        }
+        if ctx.inlined.contains(&self.func_idx) {
+            return Err(ctx.unsupported(&format!("recursion, as \"{}\" has no fixed-size circuit", func.name)));
+        }
+        let mut borrowed = Vec::with_capacity(func.borrow_registers.len());
+        for arg in &self.borrow_args {
+            borrowed.push(match arg.circuit_constant(ctx) {
+                Some(value) => Wires::Const(value),
+                None => ctx.lookup_wires(arg)?
+            });
+        }
+        for &const_idx in &self.default_args {
+            borrowed.push(Wires::Const(ctx.constant(const_idx)?));
+        }
+        let (consumed, produced) = match ctx.backwards {
+            false => (&self.stolen_args, &self.return_args),
+            true => (&self.return_args, &self.stolen_args)
+        };
+        let consumed = consumed.iter().map(|&register| ctx.take(register)).collect::<Result<Vec<_>, _>>()?;
+        let backwards = self.is_uncall != ctx.backwards;
+        let (params, results) = match backwards {
+            false => (&func.steal_registers, &func.return_registers),
+            true => (&func.return_registers, &func.steal_registers)
+        };
+
+        let caller = (ctx.function, ctx.backwards, ctx.position);
+        let caller_registers = std::mem::replace(&mut ctx.registers, vec![None; func.num_registers]);
+        for (&register, wires) in func.borrow_registers.iter().zip(borrowed).chain(params.iter().zip(consumed)) {
+            ctx.bind(register, wires);
+        }
+        ctx.function = func;
+        ctx.backwards = backwards;
+        ctx.inlined.push(self.func_idx);
+        ctx.emit_stmts(&func.stmts)?;
+        let results = results.iter().map(|&register| ctx.take(register)).collect::<Result<Vec<_>, _>>()?;
+        ctx.inlined.pop();
+        ctx.registers = caller_registers;
+        (ctx.function, ctx.backwards, ctx.position) = caller;
+        for (&register, wires) in produced.iter().zip(results) {
+            ctx.bind(register, wires);
+        }
+        Ok(())
+    }
+}
+
+
+fn qubits_source(qubits: &[usize]) -> String {
+    let contiguous = qubits.windows(2).all(|pair| pair[1] == pair[0] + 1);
+    match (qubits, contiguous) {
+        ([qubit], _) => format!("q[{}]", qubit),
+        ([first, .., last], true) => format!("q[{}:{}]", first, last),
+        _ => qubits.iter().map(|qubit| format!("q[{}]", qubit)).collect::<Vec<_>>().join(", ")
+    }
+}
+
+fn describe_wires(label: &str, name: &str, wires: &Wires, lines: &mut Vec<String>) {
+    match wires {
+        Wires::Word(qubits) => lines.push(format!("// {} {}: {}", label, name, qubits_source(qubits))),
+        Wires::Array(items) => for (idx, item) in items.iter().enumerate() {
+            describe_wires(label, &format!("{}[{}]", name, idx), item, lines);
+        },
+        Wires::Const(_) => ()
+    }
+}
+
+fn gate_source(gate: &Gate) -> String {
+    let operands = gate.controls.iter().chain([&gate.target]).map(|qubit| format!("q[{}]", qubit)).collect::<Vec<_>>().join(", ");
+    match gate.controls.len() {
+        0 => format!("x {};", operands),
+        1 => format!("cx {};", operands),
+        2 => format!("ccx {};", operands),
+        count => format!("ctrl({}) @ x {};", count, operands)
+    }
+}
+
+// The entry function as an OpenQASM 3 circuit on words of the given number of bits //
+pub fn module_source(module: &ST::Module, entry: Option<&str>, bits: usize) -> Result<String, SyntaxError> {
+    let error = |desc: String| SyntaxError{line: 0, col: 0, desc, note: None};
+    if !module.global_func.stmts.is_empty() {
+        return Err(error(String::from("The circuit export doesn't support global variables")));
+    }
+    let found = match entry {
+        None => module.main_idx,
+        Some(name) => module.functions.iter().position(|func| func.name == name)
+    };
+    let function = match found {
+        Some(idx) => &module.functions[idx],
+        None => return Err(error(format!("No function \"{}\" to export", entry.unwrap_or("main"))))
+    };
+    let mut ctx = CircuitContext{
+        functions: &module.functions, function, bits,
+        backwards: false,
+        registers: vec![None; function.num_registers],
+        controls: Vec::new(),
+        inlined: found.into_iter().collect(),
+        free: Vec::new(),
+        holding: 0,
+        num_qubits: 0,
+        gates: Vec::new(),
+        position: (0, 0)
+    };
+    let name_of = |register: usize, at_end: bool| function.symbols.iter().rev()
+        .find(|symbol| symbol.register == register && (!at_end || symbol.end.is_none()))
+        .map(|symbol| symbol.name.clone())
+        .unwrap_or_else(|| format!("r{}", register));
+
+    let mut lines = Vec::new();
+    let params = function.borrow_registers.iter().zip(&function.borrow_types)
+        .chain(function.steal_registers.iter().zip(&function.steal_types));
+    for (&register, t) in params {
+        if !t.is_compatible(&Type::Num) {
+            return Err(error(format!(
+                "The parameters of a circuit are words, but \"{}\" is {}, which has no fixed size", name_of(register, false), t)));
+        }
+        let word = Wires::Word(ctx.allocate_word());
+        describe_wires("input", &name_of(register, false), &word, &mut lines);
+        ctx.bind(register, word);
+    }
+    ctx.emit_stmts(&function.stmts)?;
+
+    for &register in &function.return_registers {
+        if let Some(wires) = ctx.registers[register].take() {
+            describe_wires("output", &name_of(register, true), &wires, &mut lines);
+        }
+    }
+    for (register, wires) in ctx.registers.iter().enumerate() {
+        if function.borrow_registers.contains(&register) {
+            continue;
+        }
+        if let Some(wires) = wires {
+            describe_wires("variable", &name_of(register, true), wires, &mut lines);
+        }
+    }
+
+    let mut source = format!(
+        "// \"{}\" as a reversible circuit on {}-bit words, least significant bit first\n// {} qubits, {} gates\n",
+        function.name, bits, ctx.num_qubits, ctx.gates.len());
+    source += "OPENQASM 3.0;\ninclude \"stdgates.inc\";\n\n";
+    source += &format!("qubit[{}] q;\n", ctx.num_qubits.max(1));
+    for line in lines {
+        source += &format!("{}\n", line);
+    }
+    source += "\n";
+    for gate in &ctx.gates {
+        source += &format!("{}\n", gate_source(gate));
+    }
+    Ok(source)
+}
@@ -1,9 +1,14 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::iter;
+use std::ops::Range;
+use std::sync::Arc;
 
 use crate::syntaxtree as ST;
 use crate::syntaxtree::Expression as STExpression;
 use crate::interpreter;
-use interpreter::Instruction;
+use crate::optimiser;
+use crate::options::EngineOptions;
+use interpreter::{Constant, Instruction};
 
 
 #[derive(Clone, Default, Debug)]
@@ -11,7 +16,8 @@ pub struct Code {
     fwd: Vec<Instruction>,
     bkwd: Vec<Instruction>,
     f2b_links: Vec<(usize, usize)>,
-    b2f_links: Vec<(usize, usize)>
+    b2f_links: Vec<(usize, usize)>,
+    regions: Vec<interpreter::Region>
 }
 
 impl Code {
@@ -24,7 +30,8 @@ impl Code {
             fwd: Vec::with_capacity(l1),
             bkwd: Vec::with_capacity(l2),
             f2b_links: Vec::new(),
-            b2f_links: Vec::new()
+            b2f_links: Vec::new(),
+            regions: Vec::new()
         }
     }
 
@@ -35,9 +42,9 @@ impl Code {
     }
     
     pub fn link_bkwd2fwd(&mut self) {
-        self.b2f_links.push((self.bkwd.len(), self.fwd.len()));
-        // Insert dummy instruction //
+        // Insert dummy instruction, which finalise locates counting back from the end //
         self.bkwd.push(Instruction::Reverse{idx: 0});
+        self.b2f_links.push((self.bkwd.len(), self.fwd.len()));
     }
 
     pub fn push_fwd(&mut self, x: Instruction) {
@@ -66,16 +73,58 @@ impl Code {
 
     pub fn clear_bkwd(&mut self) {
         if self.bkwd.len() == 0 {return};
+        // Reversals may still land in the backward code, so keep it but never fall into it //
+        if !self.f2b_links.is_empty() || !self.b2f_links.is_empty() {
+            self.push_bkwd(Instruction::RelativeJump{delta: self.bkwd.len() as isize + 1});
+            for region in self.regions.iter_mut() {
+                region.is_mono = true;
+            }
+            return;
+        }
         for instruction in self.bkwd.drain(..) {
             if let Instruction::Reverse{idx: _} = instruction {
                 panic!("Internal inconsistency: clear_bkwd called on a Reverse instruction");
             }
         }
+        for region in self.regions.iter_mut() {
+            region.bkwd = 0..0;
+            region.is_mono = true;
+        }
+    }
+
+    // Compile a statement, bracketing it with markers carrying its region's index //
+    pub fn extend_statement(&mut self, stmt: &ST::StatementNode) {
+        let code = stmt.compile();
+        let id = self.regions.len() + code.regions.len();
+        let (fwd_start, bkwd_start) = (self.fwd.len(), self.bkwd.len());
+        let first_nested = self.regions.len();
+        self.push_fwd(Instruction::Marker{stmt: id});
+        self.extend(code);
+        for region in self.regions[first_nested..].iter_mut() {
+            region.depth += 1;
+        }
+        if !stmt.is_mono() {
+            self.push_bkwd(Instruction::Marker{stmt: id});
+        }
+        self.regions.push(interpreter::Region{
+            fwd: fwd_start..self.fwd.len(),
+            bkwd: bkwd_start..self.bkwd.len(),
+            is_mono: stmt.is_mono(),
+            depth: 0,
+            line: stmt.position().map_or(0, |(line, _)| line),
+            col: stmt.position().map_or(0, |(_, col)| col)
+        });
     }
 
     pub fn extend(&mut self, other: Code) {
-        let Code{fwd, bkwd, f2b_links, b2f_links} = other;
+        let Code{mut fwd, mut bkwd, f2b_links, b2f_links, regions} = other;
         let (flen, blen) = (self.fwd.len(), self.bkwd.len());
+        let num_regions = self.regions.len();
+        for instruction in fwd.iter_mut().chain(bkwd.iter_mut()) {
+            if let Instruction::Marker{stmt} = instruction {
+                *stmt += num_regions;
+            }
+        }
         self.fwd.extend(fwd);
         self.bkwd.extend(bkwd);
         for (f, b) in f2b_links.into_iter() {
@@ -84,6 +133,16 @@ impl Code {
         for (b, f) in b2f_links.into_iter() {
             self.b2f_links.push((b + blen, f + flen));
         }
+        for region in regions.into_iter() {
+            self.regions.push(interpreter::Region{
+                fwd: region.fwd.start + flen .. region.fwd.end + flen,
+                bkwd: region.bkwd.start + blen .. region.bkwd.end + blen,
+                is_mono: region.is_mono,
+                depth: region.depth,
+                line: region.line,
+                col: region.col
+            });
+        }
     }
 
     pub fn reversed(mut self) -> Code {
@@ -95,19 +154,33 @@ impl Code {
             *f = self.fwd.len() - *f;
             *b = self.bkwd.len() - *b;
         }
+        let regions = self.regions.iter().map(|region| interpreter::Region{
+            fwd: mirror(&region.bkwd, self.bkwd.len()),
+            bkwd: mirror(&region.fwd, self.fwd.len()),
+            is_mono: region.is_mono,
+            depth: region.depth,
+            line: region.line,
+            col: region.col
+        }).collect();
         self.bkwd.reverse();
         self.fwd.reverse();
         Code{
             fwd: self.bkwd,
             bkwd: self.fwd,
             f2b_links: self.b2f_links,
-            b2f_links: self.f2b_links
+            b2f_links: self.f2b_links,
+            regions
         }
     }
 
-    pub fn finalise(code: Code) -> interpreter::Code {
-        let Code{mut fwd, mut bkwd, f2b_links, b2f_links} = code;
+    // Optimisation level 1 and above runs the peephole optimiser, given the function's consts, and
+    // strips the statement markers //
+    pub fn finalise(code: Code, opt_level: u8, consts: Option<&mut Vec<interpreter::Variable>>) -> interpreter::Code {
+        let Code{mut fwd, mut bkwd, f2b_links, b2f_links, mut regions} = code;
         bkwd.reverse();
+        for region in regions.iter_mut() {
+            region.bkwd = mirror(&region.bkwd, bkwd.len());
+        }
 
         // Compute instruction pointers for reversals //
         for (f, b) in f2b_links.into_iter() {
@@ -140,6 +213,12 @@ impl Code {
                 Instruction::StepIter{ip} => {
                     fwd[i] = Instruction::StepIter{ip: i + ip}
                 },
+                Instruction::ParallelStepIter{ip} => {
+                    fwd[i] = Instruction::ParallelStepIter{ip: i + ip}
+                },
+                Instruction::Resume{register, ip} => {
+                    fwd[i] = Instruction::Resume{register, ip: i + ip}
+                },
                 _ => {}
             }
         }
@@ -157,13 +236,63 @@ impl Code {
                 Instruction::StepIter{ip} => {
                     bkwd[i] = Instruction::StepIter{ip: i + ip}
                 },
+                Instruction::ParallelStepIter{ip} => {
+                    bkwd[i] = Instruction::ParallelStepIter{ip: i + ip}
+                },
+                Instruction::Resume{register, ip} => {
+                    bkwd[i] = Instruction::Resume{register, ip: i + ip}
+                },
+                _ => {}
+            }
+        }
+
+        if opt_level > 0 {
+            if let Some(consts) = consts {
+                optimiser::peephole(&mut fwd, &mut bkwd, &mut regions, consts);
+            }
+            strip_markers(&mut fwd, &mut bkwd, &mut regions);
+        }
+        interpreter::Code::new(fwd.into(), bkwd.into(), regions)
+    }
+}
+
+// Remove statement markers from finalised code, moving jump targets and regions to match //
+fn strip_markers(fwd: &mut Vec<Instruction>, bkwd: &mut Vec<Instruction>, regions: &mut [interpreter::Region]) {
+    let new_ips = |instructions: &[Instruction]| {
+        let mut ret = Vec::with_capacity(instructions.len() + 1);
+        let mut ip = 0;
+        for instruction in instructions.iter() {
+            ret.push(ip);
+            if !matches!(instruction, Instruction::Marker{..}) {ip += 1}
+        }
+        ret.push(ip);
+        ret
+    };
+    let (fwd_ips, bkwd_ips) = (new_ips(fwd), new_ips(bkwd));
+
+    for (instructions, ips, other_ips) in [(fwd, &fwd_ips, &bkwd_ips), (bkwd, &bkwd_ips, &fwd_ips)] {
+        for instruction in instructions.iter_mut() {
+            match instruction {
+                Instruction::Jump{ip} | Instruction::JumpIfTrue{ip} |
+                Instruction::JumpIfFalse{ip} | Instruction::StepIter{ip} |
+                Instruction::ParallelStepIter{ip} | Instruction::Resume{ip, ..} | Instruction::Gosub{ip} => *ip = ips[*ip],
+                Instruction::Reverse{idx} => *idx = other_ips[*idx],
                 _ => {}
             }
         }
-        interpreter::Code{fwd, bkwd}
+        instructions.retain(|instruction| !matches!(instruction, Instruction::Marker{..}));
+    }
+    for region in regions.iter_mut() {
+        region.fwd = fwd_ips[region.fwd.start]..fwd_ips[region.fwd.end];
+        region.bkwd = bkwd_ips[region.bkwd.start]..bkwd_ips[region.bkwd.end];
     }
 }
 
+// Where a range lands when the instructions of length `len` are reversed //
+fn mirror(range: &Range<usize>, len: usize) -> Range<usize> {
+    len - range.end .. len - range.start
+}
+
 
 impl ST::Expression for ST::FractionNode {
     fn is_mono(&self) -> bool {false}
@@ -172,6 +301,8 @@ impl ST::Expression for ST::FractionNode {
     fn compile(&self) -> Vec<Instruction> {
         vec![Instruction::LoadConst{idx: self.const_idx}]
     }
+
+    fn can_hoist(&self) -> bool {true}
 }
 
 impl ST::Expression for ST::StringNode {
@@ -181,6 +312,35 @@ impl ST::Expression for ST::StringNode {
     fn compile(&self) -> Vec<Instruction> {
         vec![Instruction::LoadConst{idx: self.const_idx}]
     }
+
+    fn can_hoist(&self) -> bool {true}
+}
+
+impl ST::Expression for ST::HoistedNode {
+    fn is_mono(&self) -> bool {false}
+    fn used_vars(&self) -> &HashSet<isize> {self.expr.used_vars()}
+
+    fn compile(&self) -> Vec<Instruction> {
+        vec![Instruction::LoadRegister{register: self.register}]
+    }
+}
+
+impl ST::Expression for ST::HoleNode {
+    fn is_mono(&self) -> bool {false}
+    fn used_vars(&self) -> &HashSet<isize> {&self.used_vars}
+
+    fn compile(&self) -> Vec<Instruction> {
+        vec![Instruction::Hole]
+    }
+}
+
+impl ST::Expression for ST::CounterNode {
+    fn is_mono(&self) -> bool {true}
+    fn used_vars(&self) -> &HashSet<isize> {&self.used_vars}
+
+    fn compile(&self) -> Vec<Instruction> {
+        vec![self.op]
+    }
 }
 
 impl ST::Expression for ST::LookupNode {
@@ -188,6 +348,25 @@ impl ST::Expression for ST::LookupNode {
     fn used_vars(&self) -> &HashSet<isize> {&self.used_vars}
 
     fn compile(&self) -> Vec<Instruction> {
+        let mut instructions = self.compile_path();
+        if !self.indices.is_empty() {
+            instructions.push(Instruction::Subscript{size: self.indices.len()});
+        }
+        instructions
+    }
+
+    fn can_hoist(&self) -> bool {
+        self.indices.is_empty() && !self.is_global
+    }
+
+    fn subexpressions(&mut self) -> Vec<&mut ST::ExpressionNode> {
+        self.indices.iter_mut().collect()
+    }
+}
+
+impl ST::LookupNode {
+    // Push the indices then the variable, ready to be subscripted //
+    fn compile_path(&self) -> Vec<Instruction> {
         let mut instructions = Vec::with_capacity(self.indices.len()+1);        
         for index in self.indices.iter().rev() {
             instructions.extend(index.compile());
@@ -198,11 +377,28 @@ impl ST::Expression for ST::LookupNode {
         } else {
             instructions.push(Instruction::LoadRegister{register:self.register});
         }
+        instructions
+    }
+}
 
-        if !self.indices.is_empty() {
-            instructions.push(Instruction::Subscript{size: self.indices.len()});
+impl ST::Expression for ST::CallExprNode {
+    fn is_mono(&self) -> bool {self.is_mono}
+    fn used_vars(&self) -> &HashSet<isize> {&self.used_vars}
+
+    // As a call statement, but leaving the single return value on the stack //
+    fn compile(&self) -> Vec<Instruction> {
+        let mut ret = Vec::new();
+        for &idx in self.default_args.iter().rev() {
+            ret.push(Instruction::LoadConst{idx});
         }
-        instructions
+        for arg in self.borrow_args.iter().rev() {
+            ret.extend(arg.compile());
+        }
+        ret.push(match self.remember {
+            true => Instruction::Remember{idx: self.func_idx},
+            false => Instruction::Call{idx: self.func_idx}
+        });
+        ret
     }
 }
 
@@ -229,10 +425,20 @@ impl ST::Expression for ST::BinopNode {
         } else {
             ret.extend(lhs);
             ret.extend(rhs);
-            ret.push(self.op.clone());
+            ret.push(self.op);
         }
         ret
     }
+
+    // Arithmetic can overflow, ordering fails on mismatched types and logic fails on handles, so
+    // only equality is safe to run ahead of a loop that may not run it //
+    fn can_hoist(&self) -> bool {
+        matches!(self.op, Instruction::BinopEq | Instruction::BinopNeq) && self.lhs.can_hoist() && self.rhs.can_hoist()
+    }
+
+    fn subexpressions(&mut self) -> Vec<&mut ST::ExpressionNode> {
+        vec![&mut self.lhs, &mut self.rhs]
+    }
 }
 
 impl ST::Expression for ST::UniopNode {
@@ -242,9 +448,13 @@ impl ST::Expression for ST::UniopNode {
     fn compile(&self) -> Vec<Instruction> {
         let mut ret = Vec::new();
         ret.extend(self.expr.compile());
-        ret.push(self.op.clone());
+        ret.push(self.op);
         ret
     }
+
+    fn subexpressions(&mut self) -> Vec<&mut ST::ExpressionNode> {
+        vec![&mut self.expr]
+    }
 }
 
 impl ST::Expression for ST::ArrayLiteralNode {
@@ -273,11 +483,69 @@ impl ST::Expression for ST::ArrayRepeatNode {
     }
 }
 
+impl ST::Expression for ST::ComprehensionNode {
+    fn is_mono(&self) -> bool {self.is_mono}
+    fn used_vars(&self) -> &HashSet<isize> {&self.used_vars}
+
+    // A counting loop over the source that pushes each kept item onto a fresh array //
+    fn compile(&self) -> Vec<Instruction> {
+        let (idx, acc, tmp) = (self.index_register, self.acc_register, self.tmp_register);
+        let source = self.source.compile();
+
+        let mut test = vec![Instruction::LoadRegister{register: idx}];
+        test.extend(source.clone());
+        test.extend([Instruction::UniopLen, Instruction::BinopLess]);
+
+        let mut push = self.item.compile();
+        push.extend([
+            Instruction::UniqueVar,
+            Instruction::StoreRegister{register: tmp},
+            Instruction::LoadRegister{register: acc},
+            Instruction::Push{register: tmp}
+        ]);
+
+        let mut body = vec![Instruction::LoadRegister{register: idx}];
+        body.extend(source);
+        body.extend([Instruction::Subscript{size: 1}, Instruction::StoreRegister{register: self.register}]);
+        if let Some(filter) = &self.filter {
+            body.extend(filter.compile());
+            body.push(Instruction::RelativeJumpIfFalse{delta: push.len() as isize + 1});
+        }
+        body.extend(push);
+        body.extend([
+            Instruction::FreeRegister{register: self.register},
+            Instruction::LoadRegister{register: idx},
+            Instruction::CreateInt{val: 1},
+            Instruction::BinopAdd,
+            Instruction::StoreRegister{register: idx}
+        ]);
+
+        let mut ret = vec![
+            Instruction::ArrayLiteral{size: 0},
+            Instruction::StoreRegister{register: acc},
+            Instruction::CreateInt{val: 0},
+            Instruction::StoreRegister{register: idx}
+        ];
+        let loop_len = (test.len() + body.len() + 1) as isize;
+        ret.extend(test);
+        ret.push(Instruction::RelativeJumpIfFalse{delta: body.len() as isize + 2});
+        ret.extend(body);
+        ret.push(Instruction::RelativeJump{delta: -loop_len});
+        ret.extend([
+            Instruction::FreeRegister{register: idx},
+            Instruction::LoadRegister{register: acc},
+            Instruction::FreeRegister{register: acc}
+        ]);
+        ret
+    }
+}
+
 
 // ------------------------------ Statement Nodes ------------------------------ //
 
 impl ST::Statement for ST::PrintNode {
     fn is_mono(&self) -> bool {true}
+    fn expressions(&mut self) -> Vec<&mut ST::ExpressionNode> {self.items.iter_mut().collect()}
 
     fn compile(&self) -> Code {
         let mut count = self.items.len() as isize;
@@ -303,6 +571,8 @@ impl ST::Statement for ST::PrintNode {
 
 impl ST::Statement for ST::LetUnletNode {
     fn is_mono(&self) -> bool {self.is_mono}
+    fn is_assignment(&self) -> bool {true}
+    fn expressions(&mut self) -> Vec<&mut ST::ExpressionNode> {vec![&mut self.rhs]}
 
     fn compile(&self) -> Code {
         let mut code = Code::new();
@@ -330,9 +600,27 @@ impl ST::Statement for ST::RefUnrefNode {
     fn is_mono(&self) -> bool {self.is_mono}
 
     fn compile(&self) -> Code {
-        let mut create_ref = self.rhs.compile();
+        // An exterior reference to a local shares its register, so freeing it would free the local //
+        if !self.rhs.is_global && self.rhs.register == self.register {
+            return Code::new();
+        }
+        let mut create_ref = Vec::new();
+        if let Some((start, end)) = &self.range {
+            create_ref.extend(end.compile());
+            create_ref.extend(start.compile());
+        }
+        create_ref.extend(self.rhs.compile());
+        if self.range.is_some() {
+            create_ref.push(Instruction::Slice);
+        }
+        // The reference is made again in the other direction, so must be to the same place //
+        let mut remove_ref = Vec::new();
+        if self.check_aliasing {
+            remove_ref.extend(create_ref.iter().copied());
+            remove_ref.push(Instruction::CheckRef{register: self.register});
+        }
         create_ref.push(Instruction::StoreRegister{register: self.register});
-        let remove_ref = vec![Instruction::FreeRegister{register: self.register}];
+        remove_ref.push(Instruction::FreeRegister{register: self.register});
 
         let mut code = Code::new();
         if self.is_unref{
@@ -349,8 +637,46 @@ impl ST::Statement for ST::RefUnrefNode {
 }
 
 
+impl ST::Statement for ST::RelinkNode {
+    fn is_mono(&self) -> bool {self.is_mono}
+
+    fn compile(&self) -> Code {
+        let relink = |from: &ST::LookupNode, to: &ST::LookupNode| {
+            let mut instructions = Vec::new();
+            if self.check_aliasing {
+                instructions.extend(from.compile());
+                instructions.push(Instruction::CheckRef{register: self.register});
+            }
+            instructions.push(Instruction::FreeRegister{register: self.register});
+            instructions.extend(to.compile());
+            instructions.push(Instruction::StoreRegister{register: self.register});
+            instructions
+        };
+        let mut code = Code::new();
+        code.append_fwd(relink(&self.old, &self.new));
+        if !self.is_mono {code.append_bkwd(relink(&self.new, &self.old));}
+        code
+    }
+}
+
+impl ST::Statement for ST::MoveNode {
+    fn is_mono(&self) -> bool {self.is_mono}
+
+    fn compile(&self) -> Code {
+        let mut code = Code::new();
+        code.push_fwd(Instruction::MoveRegister{from: self.from, to: self.to});
+        if !self.is_mono {code.push_bkwd(Instruction::MoveRegister{from: self.to, to: self.from});}
+        code
+    }
+}
+
 impl ST::Statement for ST::ModopNode {
     fn is_mono(&self) -> bool {self.is_mono}
+    fn is_assignment(&self) -> bool {true}
+
+    fn expressions(&mut self) -> Vec<&mut ST::ExpressionNode> {
+        self.lookup.indices.iter_mut().chain(iter::once(&mut self.rhs)).collect()
+    }
 
     fn compile(&self) -> Code {
         let lookup = self.lookup.compile();
@@ -369,7 +695,7 @@ impl ST::Statement for ST::ModopNode {
         code.append_fwd(lookup.clone());
         code.push_fwd(Instruction::DuplicateRef);
         code.append_fwd(rhs.clone());
-        code.push_fwd(self.op.clone());
+        code.push_fwd(self.op);
         code.push_fwd(Instruction::Store);
 
         code.push_bkwd(Instruction::Store);
@@ -383,6 +709,56 @@ impl ST::Statement for ST::ModopNode {
     }
 }
 
+impl ST::DestructureLetNode {
+    // The rest variable takes over the array, then the named items are pulled off either end //
+    fn split(&self) -> Vec<Instruction> {
+        let rest = self.rest_register;
+        let mut ret = vec![
+            Instruction::LoadRegister{register: self.register},
+            Instruction::StoreRegister{register: rest},
+            Instruction::FreeRegister{register: self.register}
+        ];
+        for &register in self.suffix.iter().rev() {
+            ret.extend([Instruction::LoadRegister{register: rest}, Instruction::Pull{register}]);
+        }
+        for &register in self.prefix.iter() {
+            ret.extend([Instruction::LoadRegister{register: rest}, Instruction::PullFront{register}]);
+        }
+        ret
+    }
+
+    fn join(&self) -> Vec<Instruction> {
+        let rest = self.rest_register;
+        let mut ret = Vec::new();
+        for &register in self.prefix.iter().rev() {
+            ret.extend([Instruction::LoadRegister{register: rest}, Instruction::PushFront{register}]);
+        }
+        for &register in self.suffix.iter() {
+            ret.extend([Instruction::LoadRegister{register: rest}, Instruction::Push{register}]);
+        }
+        ret.extend([
+            Instruction::LoadRegister{register: rest},
+            Instruction::StoreRegister{register: self.register},
+            Instruction::FreeRegister{register: rest}
+        ]);
+        ret
+    }
+}
+
+impl ST::Statement for ST::DestructureLetNode {
+    fn is_mono(&self) -> bool {self.is_mono}
+
+    fn compile(&self) -> Code {
+        let mut code = Code::new();
+        let (fwd, bkwd) = if self.is_unlet {(self.join(), self.split())} else {(self.split(), self.join())};
+        code.append_fwd(fwd);
+        code.append_bkwd(bkwd);
+
+        if self.is_mono {code.clear_bkwd();}
+        code
+    }
+}
+
 impl ST::Statement for ST::PushPullNode {
     fn is_mono(&self) -> bool {self.is_mono}
     
@@ -395,12 +771,14 @@ impl ST::Statement for ST::PushPullNode {
             code.append_fwd(lookup.clone());
             code.push_fwd(Instruction::Push{register});
     
+            if self.check_aliasing {code.push_bkwd(Instruction::CheckUnshared{register});}
             code.push_bkwd(Instruction::Pull{register});
             code.append_bkwd(lookup);
 
         } else {
             code.append_fwd(lookup.clone());
             code.push_fwd(Instruction::Pull{register});
+            if self.check_aliasing {code.push_fwd(Instruction::CheckUnshared{register});}
     
             code.push_bkwd(Instruction::Push{register});
             code.append_bkwd(lookup);
@@ -414,17 +792,18 @@ impl ST::Statement for ST::PushPullNode {
 
 impl ST::Statement for ST::IfNode {
     fn is_mono(&self) -> bool {self.is_mono}
-    
+    fn expressions(&mut self) -> Vec<&mut ST::ExpressionNode> {vec![&mut self.fwd_expr, &mut self.bkwd_expr]}
+
     fn compile(&self) -> Code {
         let fwd_expr = self.fwd_expr.compile();
         let bkwd_expr = self.bkwd_expr.compile();
         let mut if_block = Code::new();
         for stmt in self.if_stmts.iter() {
-            if_block.extend(stmt.compile());
+            if_block.extend_statement(stmt);
         }
         let mut else_block = Code::new();
         for stmt in self.else_stmts.iter() {
-            else_block.extend(stmt.compile());
+            else_block.extend_statement(stmt);
         }
         let if_bkwd_len = if_block.bkwd_len() as isize;
         let else_bkwd_len = else_block.bkwd_len() as isize;
@@ -453,16 +832,41 @@ impl ST::Statement for ST::IfNode {
 }
 
 
+// Compute a loop's invariant expressions into their registers before it runs, in either direction,
+// and free them after //
+fn with_hoisted(code: Code, hoisted: &[(usize, Vec<Instruction>)]) -> Code {
+    if hoisted.is_empty() {
+        return code;
+    }
+    let mut ret = Code::new();
+    for (register, instructions) in hoisted {
+        ret.append_fwd(instructions.clone());
+        ret.push_fwd(Instruction::StoreRegister{register: *register});
+        ret.push_bkwd(Instruction::FreeRegister{register: *register});
+    }
+    ret.extend(code);
+    for (register, instructions) in hoisted.iter().rev() {
+        ret.push_fwd(Instruction::FreeRegister{register: *register});
+        ret.push_bkwd(Instruction::StoreRegister{register: *register});
+        ret.append_bkwd(instructions.clone());
+    }
+    ret
+}
+
 impl ST::Statement for ST::WhileNode {
     fn is_mono(&self) -> bool {self.is_mono}
-    
+
+    fn expressions(&mut self) -> Vec<&mut ST::ExpressionNode> {
+        iter::once(&mut self.fwd_expr).chain(self.bkwd_expr.as_mut()).collect()
+    }
+
     fn compile(&self) -> Code {
         let fwd_expr = self.fwd_expr.compile();
         // The backward condition can be None if the loop is mono
         let bkwd_expr = self.bkwd_expr.as_ref().map(|e| e.compile());
         let mut stmts = Code::new();
         for stmt in self.stmts.iter() {
-            stmts.extend(stmt.compile());
+            stmts.extend_statement(stmt);
         }
 
         let stmts_fwd_len = stmts.fwd_len() as isize;
@@ -498,7 +902,7 @@ impl ST::Statement for ST::WhileNode {
 
 
         if self.is_mono {code.clear_bkwd();}
-        code
+        with_hoisted(code, &self.hoisted)
     }
 }
 
@@ -507,11 +911,11 @@ impl ST::Statement for ST::ForNode {
     fn is_mono(&self) -> bool {self.is_mono}
     
     fn compile(&self) -> Code {
-        let iter_lookup = self.iterator.compile();
+        let iter_lookup = self.iterator.compile_path();
 
         let mut stmts = Code::new();
         for stmt in self.stmts.iter() {
-            stmts.extend(stmt.compile());
+            stmts.extend_statement(stmt);
         }
         let stmts_fwd_len = stmts.fwd_len();
         let stmts_bkwd_len = stmts.bkwd_len();
@@ -519,7 +923,49 @@ impl ST::Statement for ST::ForNode {
         let mut code = Code::new();
         
         code.append_fwd(iter_lookup.clone());
-        code.push_fwd(Instruction::CreateIter{register: self.register});
+        let create_iter = Instruction::CreateIter{
+            register: self.register, reversed: self.reversed, index_register: self.index_register,
+            depth: self.iterator.indices.len()
+        };
+        code.push_fwd(create_iter);
+        let step_iter = |ip| match self.parallel {
+            true => Instruction::ParallelStepIter{ip},
+            false => Instruction::StepIter{ip}
+        };
+        code.push_fwd(step_iter(stmts_fwd_len + 2));
+        code.push_bkwd(Instruction::RelativeJump{delta: -(1 + stmts_bkwd_len as isize)});
+
+        code.extend(stmts);
+
+        code.push_fwd(Instruction::RelativeJump{delta: -(1 + stmts_fwd_len as isize)});
+        code.push_bkwd(step_iter(stmts_bkwd_len + 2));
+        code.push_bkwd(create_iter);
+        code.append_bkwd(iter_lookup);
+        
+        if self.is_mono {code.clear_bkwd();}
+        with_hoisted(code, &self.hoisted)
+    }
+}
+
+impl ST::Statement for ST::ForRangeNode {
+    fn is_mono(&self) -> bool {self.is_mono}
+    
+    fn compile(&self) -> Code {
+        let mut range = self.start.compile();
+        range.extend(self.end.compile());
+        range.extend(self.step.compile());
+
+        let mut stmts = Code::new();
+        for stmt in self.stmts.iter() {
+            stmts.extend_statement(stmt);
+        }
+        let stmts_fwd_len = stmts.fwd_len();
+        let stmts_bkwd_len = stmts.bkwd_len();
+
+        let mut code = Code::new();
+        
+        code.append_fwd(range.clone());
+        code.push_fwd(Instruction::CreateRangeIter{register: self.register});
         code.push_fwd(Instruction::StepIter{ip: stmts_fwd_len + 2});
         code.push_bkwd(Instruction::RelativeJump{delta: -(1 + stmts_bkwd_len as isize)});
 
@@ -527,10 +973,57 @@ impl ST::Statement for ST::ForNode {
 
         code.push_fwd(Instruction::RelativeJump{delta: -(1 + stmts_fwd_len as isize)});
         code.push_bkwd(Instruction::StepIter{ip: stmts_bkwd_len + 2});
-        code.push_bkwd(Instruction::CreateIter{register: self.register});
-        code.append_bkwd(iter_lookup);
+        code.push_bkwd(Instruction::CreateRangeIter{register: self.register});
+        code.append_bkwd(range);
         
         if self.is_mono {code.clear_bkwd();}
+        with_hoisted(code, &self.hoisted)
+    }
+}
+
+impl ST::Statement for ST::ForGenNode {
+    fn is_mono(&self) -> bool {false}
+
+    fn compile(&self) -> Code {
+        // Args are loaded as for a call in the loop's direction, which starts the generator //
+        let args_fwd: Vec<Instruction> = self.borrow_args.iter().rev().flat_map(|arg| arg.compile()).collect();
+        let args_bkwd: Vec<Instruction> = self.borrow_args.iter().flat_map(|arg| arg.compile()).collect();
+
+        let mut stmts = Code::new();
+        for stmt in self.stmts.iter() {
+            stmts.extend_statement(stmt);
+        }
+        let stmts_fwd_len = stmts.fwd_len();
+        let stmts_bkwd_len = stmts.bkwd_len();
+        let create = Instruction::CreateGenerator{idx: self.func_idx};
+
+        let mut code = Code::new();
+
+        code.append_fwd(args_fwd);
+        code.push_fwd(create);
+        code.push_fwd(Instruction::Resume{register: self.register, ip: stmts_fwd_len + 2});
+        code.push_bkwd(Instruction::RelativeJump{delta: -(1 + stmts_bkwd_len as isize)});
+
+        code.extend(stmts);
+
+        code.push_fwd(Instruction::RelativeJump{delta: -(1 + stmts_fwd_len as isize)});
+        code.push_bkwd(Instruction::Resume{register: self.register, ip: stmts_bkwd_len + 2});
+        code.push_bkwd(create);
+        code.append_bkwd(args_bkwd);
+
+        code
+    }
+}
+
+impl ST::Statement for ST::YieldNode {
+    fn is_mono(&self) -> bool {false}
+
+    fn compile(&self) -> Code {
+        let mut code = Code::new();
+        code.append_fwd(self.expr.compile());
+        code.push_fwd(Instruction::Suspend);
+        code.append_bkwd(vec![Instruction::Suspend]);
+        code.append_bkwd(self.expr.compile());
         code
     }
 }
@@ -542,11 +1035,11 @@ impl ST::Statement for ST::DoYieldNode {
 
         let mut code = Code::new();
         for do_stmt in self.do_stmts.iter() {
-            code.extend(do_stmt.compile());
+            code.extend_statement(do_stmt);
         }
         let undo_block = code.clone().reversed();
         for yield_stmt in self.yield_stmts.iter() {
-            code.extend(yield_stmt.compile());
+            code.extend_statement(yield_stmt);
         }
         code.extend(undo_block);
         
@@ -555,9 +1048,45 @@ impl ST::Statement for ST::DoYieldNode {
 }
 
 
+impl ST::Statement for ST::PositionedNode {
+    fn is_mono(&self) -> bool {self.stmt.is_mono()}
+    fn is_undoable(&self) -> bool {self.stmt.is_undoable()}
+    fn is_assignment(&self) -> bool {self.stmt.is_assignment()}
+    fn position(&self) -> Option<(usize, usize)> {Some((self.line, self.col))}
+    fn expressions(&mut self) -> Vec<&mut ST::ExpressionNode> {self.stmt.expressions()}
+
+    fn compile(&self) -> Code {
+        self.stmt.compile()
+    }
+}
+
+impl ST::Statement for ST::ExpectNode {
+    fn is_mono(&self) -> bool {self.is_mono}
+    fn expressions(&mut self) -> Vec<&mut ST::ExpressionNode> {
+        iter::once(&mut self.actual).chain(self.expected.as_mut()).collect()
+    }
+
+    // The same check runs in both directions //
+    fn compile(&self) -> Code {
+        let mut check = self.actual.compile();
+        if let Some(expected) = &self.expected {
+            check.extend(expected.compile());
+        }
+        check.push(Instruction::LoadConst{idx: self.desc_idx});
+        check.push(if self.expected.is_some() {Instruction::ExpectEqual} else {Instruction::Expect});
+
+        let mut code = Code::new();
+        code.append_fwd(check.clone());
+        code.append_bkwd(check);
+        if self.is_mono {code.clear_bkwd();}
+        code
+    }
+}
+
 impl ST::Statement for ST::CatchNode {
     fn is_mono(&self) -> bool {true}
-    
+    fn is_undoable(&self) -> bool {true}
+
     fn compile(&self) -> Code {
         let mut code = Code::new();
         code.append_fwd(self.expr.compile());
@@ -567,30 +1096,151 @@ impl ST::Statement for ST::CatchNode {
     }
 }
 
+impl ST::Statement for ST::TryNode {
+    fn is_mono(&self) -> bool {false}
+    fn expressions(&mut self) -> Vec<&mut ST::ExpressionNode> {vec![&mut self.cond, &mut self.bkwd_cond]}
+
+    // Running backwards, the backward condition tells whether the handler ran, to undo it or else
+    // the body. The flag register tells the end of the undone body whether a catch is undoing it, to
+    // turn forwards into the handler, or the whole statement is being run backwards //
+    fn compile(&self) -> Code {
+        let flag = self.flag_register;
+        let mut body = Code::new();
+        for stmt in self.body.iter() {
+            body.extend_statement(stmt);
+        }
+        let mut handler = Code::new();
+        for stmt in self.handler.iter() {
+            handler.extend_statement(stmt);
+        }
+        let (body_bkwd_len, handler_bkwd_len) = (body.bkwd_len() as isize, handler.bkwd_len() as isize);
+
+        let mut code = Code::new();
+        code.push_fwd(Instruction::CreateInt{val: 0});
+        code.push_fwd(Instruction::StoreRegister{register: flag});
+        code.push_bkwd(Instruction::FreeRegister{register: flag});
+        // Undoing the body turns forwards here, with the flag set to divert into the handler //
+        code.link_bkwd2fwd();
+        code.push_bkwd(Instruction::RelativeJumpIfFalse{delta: 2});
+        code.push_bkwd(Instruction::LoadRegister{register: flag});
+        code.push_fwd(Instruction::LoadRegister{register: flag});
+        let divert = code.fwd_len();
+        code.push_fwd(Instruction::RelativeJumpIfTrue{delta: 0});
+        code.push_fwd(Instruction::CreateInt{val: 1});
+        code.push_fwd(Instruction::StoreRegister{register: flag});
+        code.extend(body);
+        code.append_fwd(self.cond.compile());
+        code.push_fwd(Instruction::RelativeJumpIfFalse{delta: 2});
+        code.link_fwd2bkwd();
+        code.push_fwd(Instruction::RelativeJump{delta: handler.fwd_len() as isize + 1});
+        code.fwd[divert] = Instruction::RelativeJumpIfTrue{delta: (code.fwd_len() - divert) as isize};
+        // Past the undone handler, carry on backwards past the body //
+        code.push_bkwd(Instruction::RelativeJump{delta: body_bkwd_len + 4});
+        code.extend(handler);
+        code.push_bkwd(Instruction::RelativeJumpIfFalse{delta: handler_bkwd_len + 2});
+        code.append_bkwd(self.bkwd_cond.compile());
+        code.push_fwd(Instruction::FreeRegister{register: flag});
+        code.push_bkwd(Instruction::StoreRegister{register: flag});
+        code.push_bkwd(Instruction::CreateInt{val: 0});
+        code
+    }
+}
+
+impl ST::Statement for ST::RememberNode {
+    fn is_mono(&self) -> bool {false}
+
+    fn compile(&self) -> Code {
+        let mut code = Code::new();
+        for stmt in self.body.iter() {
+            code.extend_statement(stmt);
+        }
+        code
+    }
+}
+
+impl ST::Statement for ST::StaticAssertNode {
+    fn is_mono(&self) -> bool {false}
+
+    fn compile(&self) -> Code {
+        Code::new()
+    }
+}
+
+impl ST::Statement for ST::TodoNode {
+    fn is_mono(&self) -> bool {false}
+
+    fn compile(&self) -> Code {
+        let mut code = Code::new();
+        code.push_fwd(Instruction::Todo);
+        code.push_bkwd(Instruction::Todo);
+        code
+    }
+}
+
+impl ST::Statement for ST::BacktraceNode {
+    fn is_mono(&self) -> bool {true}
+
+    fn compile(&self) -> Code {
+        let mut code = Code::new();
+        code.push_fwd(Instruction::Backtrace);
+        code
+    }
+}
+
+impl ST::CallNode {
+    // Feed `inputs` to the callee's steal params and receive `outputs` from its return params //
+    fn call_sequence(&self, inputs: &[usize], outputs: &[usize]) -> Vec<Instruction> {
+        let mut ret = Vec::new();
+        for &register in inputs.iter().rev() {
+            ret.push(Instruction::LoadRegister{register});
+            ret.push(Instruction::FreeRegister{register});
+        }
+        for &idx in self.default_args.iter().rev() {
+            ret.push(Instruction::LoadConst{idx});
+        }
+        for arg in self.borrow_args.iter().rev() {
+            ret.extend(arg.compile());
+        }
+        ret.push(Instruction::Call{idx: self.func_idx});
+        for &register in outputs.iter().rev() {
+            ret.push(Instruction::StoreRegister{register});
+        }
+        ret
+    }
+
+    // Feed `inputs` to the callee's return params and receive `outputs` from its steal params //
+    fn uncall_sequence(&self, inputs: &[usize], outputs: &[usize]) -> Vec<Instruction> {
+        let mut ret = Vec::new();
+        for &register in inputs.iter() {
+            ret.push(Instruction::LoadRegister{register});
+            ret.push(Instruction::FreeRegister{register});
+        }
+        for arg in self.borrow_args.iter() {
+            ret.extend(arg.compile());
+        }
+        for &idx in self.default_args.iter() {
+            ret.push(Instruction::LoadConst{idx});
+        }
+        ret.push(Instruction::Uncall{idx: self.func_idx});
+        for &register in outputs.iter() {
+            ret.push(Instruction::StoreRegister{register});
+        }
+        ret
+    }
+}
+
 impl ST::Statement for ST::CallNode {
     fn is_mono(&self) -> bool {self.is_mono}
     
     fn compile(&self) -> Code {
         let mut code = Code::new();
 
-        for &register in self.stolen_args.iter().rev() {
-            code.push_fwd(Instruction::LoadRegister{register});
-            code.push_fwd(Instruction::FreeRegister{register});
-        }
-
         if self.is_uncall {
-            code.push_bkwd(Instruction::Call{idx: self.func_idx});
-            code.push_fwd(Instruction::Uncall{idx: self.func_idx});
+            code.append_fwd(self.uncall_sequence(&self.stolen_args, &self.return_args));
+            code.append_bkwd(self.call_sequence(&self.return_args, &self.stolen_args));
         } else {
-            for arg in self.borrow_args.iter().rev() {
-                code.append_fwd(arg.compile());
-            }
-            code.push_fwd(Instruction::Call{idx: self.func_idx});
-            code.push_bkwd(Instruction::Uncall{idx: self.func_idx});
-        }
-
-        for &register in self.return_args.iter().rev() {
-            code.push_fwd(Instruction::StoreRegister{register});
+            code.append_fwd(self.call_sequence(&self.stolen_args, &self.return_args));
+            code.append_bkwd(self.uncall_sequence(&self.return_args, &self.stolen_args));
         }
 
         if self.is_mono {code.clear_bkwd();}
@@ -598,10 +1248,45 @@ impl ST::Statement for ST::CallNode {
     }
 }
 
+impl ST::Symbol {
+    // Find the instruction ranges of the statements that bound and unbound the name. Backwards,
+    // the name is live from undoing its unbinding up to undoing its binding //
+    fn resolve(&self, code: &interpreter::Code) -> interpreter::Symbol {
+        let region = |position: (usize, usize)| code.regions.iter()
+            .find(|r| (r.line, r.col) == position)
+            .expect("Symbol bound outside any statement");
+        let (fwd_start, bkwd_end) = match self.start {
+            Some(position) => (region(position).fwd.start, region(position).bkwd.end),
+            None => (0, code.bkwd.len())
+        };
+        let (fwd_end, bkwd_start) = match self.end {
+            Some(position) => (region(position).fwd.end, region(position).bkwd.start),
+            None => (code.fwd.len(), 0)
+        };
+        interpreter::Symbol{
+            name: self.name.clone(),
+            register: self.register,
+            var_id: self.var_id,
+            fwd: fwd_start..fwd_end,
+            bkwd: bkwd_start..bkwd_end
+        }
+    }
+}
+
 impl ST::FunctionNode {
-    pub fn compile(&self) -> interpreter::Function {
+    pub fn compile(&self, debug_symbols: bool, options: &EngineOptions) -> interpreter::Function {
+        if let Some(linked) = &self.linked {
+            return self.link(linked);
+        }
         let mut code = Code::new();
 
+        // Host functions leave their args on the stack for the host //
+        if self.is_host {
+            code.push_fwd(Instruction::HostCall);
+            code.push_bkwd(Instruction::HostCall);
+            return self.finish(code, debug_symbols, options);
+        }
+
         for &register in &self.borrow_registers {
             code.push_fwd(Instruction::StoreRegister{register});
         }
@@ -611,7 +1296,7 @@ impl ST::FunctionNode {
         }
 
         for stmt in &self.stmts {
-            code.extend(stmt.compile());
+            code.extend_statement(stmt);
         }
 
         for &register in &self.return_registers {
@@ -621,39 +1306,243 @@ impl ST::FunctionNode {
         for &register in &self.borrow_registers {
             code.push_bkwd(Instruction::StoreRegister{register});
         }
+        self.finish(code, debug_symbols, options)
+    }
+
+    fn finish(&self, code: Code, debug_symbols: bool, options: &EngineOptions) -> interpreter::Function {
+        let mut consts = self.consts.clone();
+        let code = Code::finalise(code, options.opt_level, Some(&mut consts));
+        log::debug!("Compiled {}: {} forward and {} backward instructions", self.name, code.fwd.len(), code.bkwd.len());
+        for (direction, stream) in [("fwd", &code.fwd), ("bkwd", &code.bkwd)] {
+            for (ip, instruction) in stream.iter().enumerate() {
+                log::trace!("{} {} {}: {:?}", self.name, direction, ip, instruction);
+            }
+        }
+        let symbols = if debug_symbols {
+            self.symbols.iter().map(|symbol| symbol.resolve(&code)).collect()
+        } else {
+            Vec::new()
+        };
 
         interpreter::Function{
-            consts: self.consts.clone(),
-            code: Code::finalise(code),
-            num_registers: self.num_registers
+            name: self.name.clone(),
+            consts: Arc::new(consts.iter().map(Constant::from_variable).collect()),
+            code,
+            num_registers: self.num_registers,
+            symbols,
+            num_borrows: self.borrow_registers.len(),
+            num_steals: self.steal_registers.len(),
+            num_returns: self.return_registers.len(),
+            is_pure: self.is_pure,
+            effects: self.effects,
+            garbage: self.garbage.clone(),
+            specialised: self.specialised.clone()
         }
     }
 
-    // Compile as the special 'global function' which is run for the global scope before main
-    pub fn compile_to_global(&self) -> interpreter::Function {
-        let mut func = self.compile();
-        for instruction in func.code.fwd.iter_mut().chain(func.code.bkwd.iter_mut()) {
-            match instruction {
-                interpreter::Instruction::LoadRegister{register} => {
-                    *instruction = interpreter::Instruction::LoadGlobalRegister{register: *register};
+    // Take a library function's compiled code, calling the library's other functions where they now
+    // sit. The consts and strings it loads become its own, for share_consts to pool with the rest //
+    fn link(&self, linked: &ST::Linked) -> interpreter::Function {
+        let library = &linked.library;
+        let func = &library.functions[linked.idx];
+        let mut consts = Vec::new();
+        let mut owned: HashMap<(bool, usize), usize> = HashMap::new();
+        let mut relink = |instruction: &mut Instruction| {
+            let key = match *instruction {
+                Instruction::LoadConst{idx} => (false, idx),
+                Instruction::LoadString{idx} => (true, idx),
+                Instruction::Call{ref mut idx} | Instruction::Uncall{ref mut idx} |
+                Instruction::Remember{ref mut idx} | Instruction::CreateGenerator{ref mut idx} => {
+                    *idx += linked.base;
+                    return;
                 },
-                interpreter::Instruction::StoreRegister{register} => {
-                    *instruction = interpreter::Instruction::StoreGlobalRegister{register: *register};
-                }
-                _ => {}
+                _ => return
+            };
+            let idx = *owned.entry(key).or_insert_with(|| {
+                consts.push(match key {
+                    (false, idx) => library.consts[idx].clone(),
+                    (true, idx) => Constant::Str(library.strings[idx].clone())
+                });
+                consts.len() - 1
+            });
+            *instruction = Instruction::LoadConst{idx};
+        };
+        let (mut fwd, mut bkwd) = (func.code.fwd.clone(), func.code.bkwd.clone());
+        fwd.rewrite(&mut relink);
+        bkwd.rewrite(&mut relink);
+
+        interpreter::Function{
+            name: self.name.clone(),
+            code: interpreter::Code::new(fwd, bkwd, func.code.regions.clone()),
+            consts: Arc::new(consts),
+            num_registers: func.num_registers,
+            symbols: func.symbols.clone(),
+            num_borrows: func.num_borrows,
+            num_steals: func.num_steals,
+            num_returns: func.num_returns,
+            is_pure: func.is_pure,
+            effects: func.effects,
+            garbage: func.garbage.clone(),
+            specialised: func.specialised.clone()
+        }
+    }
+
+    // Compile as the special 'global function' which is run for the global scope before main
+    pub fn compile_to_global(&self, debug_symbols: bool, options: &EngineOptions) -> interpreter::Function {
+        let mut func = self.compile(debug_symbols, options);
+        let globalise = |instruction: &mut Instruction| match *instruction {
+            Instruction::LoadRegister{register} => {
+                *instruction = Instruction::LoadGlobalRegister{register};
+            },
+            Instruction::StoreRegister{register} => {
+                *instruction = Instruction::StoreGlobalRegister{register};
             }
+            _ => {}
         };
+        func.code.fwd.rewrite(globalise);
+        func.code.bkwd.rewrite(globalise);
         func
     }
 }
 
+// Compile a lone expression into forward code that leaves its value on the stack //
+pub fn compile_expression(expr: &ST::ExpressionNode) -> interpreter::Code {
+    let mut code = Code::new();
+    code.append_fwd(expr.compile());
+    Code::finalise(code, EngineOptions::default().opt_level, None)
+}
+
 impl ST::Module {
-    pub fn compile(&self) -> interpreter::Module {
+    // Debug symbols let debuggers and runtime errors refer to variables by name //
+    pub fn compile(&self, debug_symbols: bool, options: &EngineOptions) -> interpreter::Module {
         let main_idx = self.main_idx;
-        let mut functions: Vec<_> = self.functions.iter().map(|f| f.compile()).collect();
+        let mut functions: Vec<_> = self.functions.iter().map(|f| f.compile(debug_symbols, options)).collect();
         let global_func_idx = functions.len();
-        functions.push(self.global_func.compile_to_global());
+        functions.push(self.global_func.compile_to_global(debug_symbols, options));
 
-        interpreter::Module{main_idx, functions, global_func_idx}
+        let (consts, strings) = share_consts(&mut functions);
+
+        let module = interpreter::Module{main_idx, functions, global_func_idx, consts, strings};
+        if cfg!(debug_assertions) {
+            if let Err(err) = module.validate() {
+                panic!("Internal compiler error: {}", err);
+            }
+        }
+        module
     }
-}
\ No newline at end of file
+
+    // Recompile one function and swap it into a module compiled from an earlier version of this
+    // source, keeping the rest of the module, and so the registers of any session running it. The
+    // module's callers were compiled against the function's params, purity and effects, so those must
+    // be unchanged. The new code is decoded afresh when first run //
+    pub fn reload_function(&self, name: &str, module: &mut interpreter::Module, debug_symbols: bool,
+                           options: &EngineOptions) -> Result<(), interpreter::ReloadError> {
+        let err = |desc: String| Err(interpreter::ReloadError{desc});
+        let Some(idx) = self.functions.iter().position(|func| func.name == name) else {
+            return err(format!("No function \"{}\" to reload", name));
+        };
+        let Some(old) = module.functions.get(idx).filter(|func| func.name == name) else {
+            return err(format!("\"{}\" has moved since the module was compiled, so it must be recompiled in full", name));
+        };
+        let mut func = self.functions[idx].compile(debug_symbols, options);
+        let prototype = |func: &interpreter::Function| (func.num_borrows, func.num_steals, func.num_returns, func.is_pure, func.effects);
+        if prototype(&func) != prototype(old) {
+            return err(format!("The params, purity or effects of \"{}\" have changed, so its callers must be recompiled", name));
+        }
+        // Its calls may now pass other values to params the functions they call were specialised to //
+        if self.functions.iter().zip(module.functions.iter()).any(|(new, old)| new.specialised != old.specialised) {
+            return err(format!("The constants \"{}\" passes have changed, so the module must be recompiled", name));
+        }
+
+        let mut pool = ConstPool::of(module);
+        pool.add(&mut func);
+        let consts = Arc::new(pool.pool);
+        module.functions[idx] = func;
+        for func in module.functions.iter_mut() {
+            func.consts = Arc::clone(&consts);
+        }
+        module.consts = consts;
+        module.strings = pool.strings;
+        log::debug!("Reloaded {}", name);
+        if cfg!(debug_assertions) {
+            if let Err(err) = module.validate() {
+                panic!("Internal compiler error: {}", err);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Merge every function's consts into one pool, so literals repeated across functions are stored
+// once, and renumber the functions' LoadConst instructions to match. Strings go to the module's
+// string table instead, loaded by LoadString //
+fn share_consts(functions: &mut [interpreter::Function]) -> (Arc<Vec<Constant>>, Vec<String>) {
+    let mut pool = ConstPool::default();
+    for func in functions.iter_mut() {
+        pool.add(func);
+    }
+    let ConstPool{pool, strings, ..} = pool;
+    let pool = Arc::new(pool);
+    for func in functions.iter_mut() {
+        func.consts = Arc::clone(&pool);
+    }
+    (pool, strings)
+}
+
+#[derive(Default)]
+struct ConstPool {
+    pool: Vec<Constant>,
+    strings: Vec<String>,
+    indices: HashMap<String, usize>,
+    string_indices: HashMap<String, usize>
+}
+
+impl ConstPool {
+    // The pool of a compiled module, to add a recompiled function's consts to //
+    fn of(module: &interpreter::Module) -> ConstPool {
+        let mut pool = ConstPool{pool: module.consts.to_vec(), strings: module.strings.clone(), ..Default::default()};
+        for (idx, value) in pool.pool.iter().enumerate() {
+            if let Constant::Frac(frac) = value {
+                pool.indices.entry(frac.to_string()).or_insert(idx);
+            }
+        }
+        for (idx, string) in pool.strings.iter().enumerate() {
+            pool.string_indices.entry(string.clone()).or_insert(idx);
+        }
+        pool
+    }
+
+    fn add(&mut self, func: &mut interpreter::Function) {
+        let mut remap = Vec::with_capacity(func.consts.len());
+        for value in func.consts.iter() {
+            let instruction = match value {
+                Constant::Str(string) => {
+                    let strings = &mut self.strings;
+                    let idx = *self.string_indices.entry(string.clone()).or_insert_with(|| {
+                        strings.push(string.clone());
+                        strings.len() - 1
+                    });
+                    Instruction::LoadString{idx}
+                },
+                Constant::Frac(frac) => {
+                    let pool = &mut self.pool;
+                    let idx = *self.indices.entry(frac.to_string()).or_insert_with(|| {
+                        pool.push(value.clone());
+                        pool.len() - 1
+                    });
+                    Instruction::LoadConst{idx}
+                },
+                Constant::Float(_) | Constant::Array(_) | Constant::Handle(_) => {
+                    self.pool.push(value.clone());
+                    Instruction::LoadConst{idx: self.pool.len() - 1}
+                }
+            };
+            remap.push(instruction);
+        }
+        let renumber = |instruction: &mut Instruction| if let Instruction::LoadConst{idx} = *instruction {
+            *instruction = remap[idx];
+        };
+        func.code.fwd.rewrite(renumber);
+        func.code.bkwd.rewrite(renumber);
+    }
+}
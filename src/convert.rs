@@ -0,0 +1,187 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+use num_traits::cast::ToPrimitive;
+
+use crate::interpreter::{Fraction, Variable};
+
+
+// Conversions between rust values and program values, for passing args into a program and reading
+// back what it returns, e.g. in a host function or around Interpreter::run_entry //
+pub trait IntoReaver {
+    fn into_reaver(self) -> Variable;
+}
+
+pub trait FromReaver: Sized {
+    fn from_reaver(var: &Variable) -> Result<Self, ConversionError>;
+}
+
+#[derive(Debug, Clone)]
+pub struct ConversionError {
+    pub desc: String
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ConversionError: {}", self.desc)
+    }
+}
+
+impl ConversionError {
+    pub fn expected(what: &str, var: &Variable) -> ConversionError {
+        let found = match var {
+            Variable::Frac(_) => "a number",
+            Variable::Float(_) => "a float",
+            Variable::Str(_) => "a string",
+            Variable::Array(_) => "an array",
+            Variable::Handle(_) => "a handle"
+        };
+        ConversionError{desc: format!("Expected {}, but found {} ({})", what, found, var)}
+    }
+}
+
+// The items of an array of exactly len values, which e.g. a struct converted by reaver_struct!
+// is read from //
+pub fn array_items<'v>(var: &'v Variable, len: usize, what: &str) -> Result<&'v [Rc<RefCell<Variable>>], ConversionError> {
+    match var {
+        Variable::Array(items) if items.len() == len => Ok(items),
+        Variable::Array(items) => Err(ConversionError{desc: format!(
+            "Expected {} as an array of {} items, but found {}", what, len, items.len())}),
+        _ => Err(ConversionError::expected(&format!("{} as an array", what), var))
+    }
+}
+
+pub fn array_of(items: Vec<Variable>) -> Variable {
+    Variable::Array(items.into_iter().map(|item| Rc::new(RefCell::new(item))).collect())
+}
+
+
+impl IntoReaver for Variable {
+    fn into_reaver(self) -> Variable {self}
+}
+
+impl FromReaver for Variable {
+    fn from_reaver(var: &Variable) -> Result<Variable, ConversionError> {
+        Ok(var.deep_copy())
+    }
+}
+
+impl IntoReaver for i64 {
+    fn into_reaver(self) -> Variable {
+        Variable::Frac(Fraction::from_integer(BigInt::from(self)))
+    }
+}
+
+impl FromReaver for i64 {
+    fn from_reaver(var: &Variable) -> Result<i64, ConversionError> {
+        match var {
+            Variable::Frac(val) if val.is_integer() => val.numer().to_i64().ok_or_else(|| ConversionError{
+                desc: format!("{} is too large for an i64", val)}),
+            _ => Err(ConversionError::expected("an integer", var))
+        }
+    }
+}
+
+// Floats are lossy both ways: fractions are rounded to the nearest f64, and f64s stay floats, so
+// only reach mono variables //
+impl IntoReaver for f64 {
+    fn into_reaver(self) -> Variable {
+        Variable::Float(self)
+    }
+}
+
+impl FromReaver for f64 {
+    fn from_reaver(var: &Variable) -> Result<f64, ConversionError> {
+        match var {
+            Variable::Float(val) => Ok(*val),
+            Variable::Frac(val) => match (val.numer().to_f64(), val.denom().to_f64()) {
+                (Some(numer), Some(denom)) => Ok(numer / denom),
+                _ => Err(ConversionError{desc: format!("{} exceeds f64 precision", val)})
+            },
+            _ => Err(ConversionError::expected("a number", var))
+        }
+    }
+}
+
+impl IntoReaver for Fraction {
+    fn into_reaver(self) -> Variable {
+        Variable::Frac(self)
+    }
+}
+
+impl FromReaver for Fraction {
+    fn from_reaver(var: &Variable) -> Result<Fraction, ConversionError> {
+        match var {
+            Variable::Frac(val) => Ok(val.clone()),
+            _ => Err(ConversionError::expected("a number", var))
+        }
+    }
+}
+
+impl IntoReaver for String {
+    fn into_reaver(self) -> Variable {
+        Variable::Str(self)
+    }
+}
+
+impl IntoReaver for &str {
+    fn into_reaver(self) -> Variable {
+        Variable::Str(self.to_string())
+    }
+}
+
+impl FromReaver for String {
+    fn from_reaver(var: &Variable) -> Result<String, ConversionError> {
+        match var {
+            Variable::Str(string) => Ok(string.clone()),
+            _ => Err(ConversionError::expected("a string", var))
+        }
+    }
+}
+
+impl<T: IntoReaver> IntoReaver for Vec<T> {
+    fn into_reaver(self) -> Variable {
+        array_of(self.into_iter().map(IntoReaver::into_reaver).collect())
+    }
+}
+
+impl<T: FromReaver> FromReaver for Vec<T> {
+    fn from_reaver(var: &Variable) -> Result<Vec<T>, ConversionError> {
+        match var {
+            Variable::Array(items) => items.iter().map(|item| T::from_reaver(&item.borrow())).collect(),
+            _ => Err(ConversionError::expected("an array", var))
+        }
+    }
+}
+
+
+// Convert a struct to and from an array of its fields' values, in the order given, e.g.
+//     struct Point {x: i64, y: i64}
+//     reaver_struct!(Point {x, y});
+// makes Point{x: 1, y: 2} the array [1, 2] //
+#[macro_export]
+macro_rules! reaver_struct {
+    ($name:ident {$($field:ident),* $(,)?}) => {
+        impl $crate::convert::IntoReaver for $name {
+            fn into_reaver(self) -> $crate::interpreter::Variable {
+                $crate::convert::array_of(vec![$($crate::convert::IntoReaver::into_reaver(self.$field)),*])
+            }
+        }
+
+        impl $crate::convert::FromReaver for $name {
+            fn from_reaver(var: &$crate::interpreter::Variable) -> Result<$name, $crate::convert::ConversionError> {
+                let len = [$(stringify!($field)),*].len();
+                let mut items = $crate::convert::array_items(var, len, stringify!($name))?.iter();
+                Ok($name{$(
+                    $field: $crate::convert::FromReaver::from_reaver(
+                        &items.next().expect("Array shorter than its checked length").borrow()
+                    ).map_err(|err| $crate::convert::ConversionError{
+                        desc: format!("In field \"{}\" of {}: {}", stringify!($field), stringify!($name), err.desc)
+                    })?
+                ),*})
+            }
+        }
+    };
+}
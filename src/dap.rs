@@ -0,0 +1,356 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use serde_json::{json, Value};
+
+use crate::cfg::Cfg;
+use crate::debugger::{Debugger, Step, Stop};
+use crate::interpreter::{Module, Variable};
+use crate::options::EngineOptions;
+use crate::repl;
+
+
+// Debug Adapter Protocol messages are JSON bodies, each preceded by a Content-Length header //
+struct Connection<R, W> {
+    input: R,
+    output: W,
+    seq: u64
+}
+
+impl<R: BufRead, W: Write> Connection<R, W> {
+    fn read(&mut self) -> Option<Value> {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            if self.input.read_line(&mut header).ok()? == 0 {return None}
+            let header = header.trim();
+            if header.is_empty() {break}
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+        let mut body = vec![0; length?];
+        self.input.read_exact(&mut body).ok()?;
+        serde_json::from_slice(&body).ok()
+    }
+
+    fn send(&mut self, mut message: Value) {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        let body = message.to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+            .and_then(|_| self.output.flush())
+            .expect("Failed to write to the debug client");
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) {
+        self.send(json!({
+            "type": "response", "request_seq": request["seq"], "command": request["command"],
+            "success": true, "body": body
+        }));
+    }
+
+    fn fail(&mut self, request: &Value, message: &str) {
+        self.send(json!({
+            "type": "response", "request_seq": request["seq"], "command": request["command"],
+            "success": false, "message": message
+        }));
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        self.send(json!({"type": "event", "event": event, "body": body}));
+    }
+}
+
+
+// What a variablesReference handed to the client refers to. Handles only last until the
+// program next runs //
+enum Handle {
+    Frame(usize),
+    Globals,
+    Value(Rc<RefCell<Variable>>)
+}
+
+
+struct Adapter<'a> {
+    debugger: Debugger<'a>,
+    program: String,
+    handles: Vec<Handle>,
+    finished: bool
+}
+
+impl<'a> Adapter<'a> {
+    fn new(module: &'a Module, program: &str) -> Adapter<'a> {
+        let mut debugger = Debugger::new(module);
+        debugger.interpreter_mut().capture_output();
+        Adapter{debugger, program: program.to_string(), handles: Vec::new(), finished: false}
+    }
+
+    fn serve<R: BufRead, W: Write>(&mut self, conn: &mut Connection<R, W>, stop_on_entry: bool) {
+        while let Some(request) = conn.read() {
+            let args = &request["arguments"];
+            match request["command"].as_str().unwrap_or("") {
+                "setBreakpoints" => {
+                    let body = self.set_breakpoints(args);
+                    conn.respond(&request, body);
+                },
+                "setExceptionBreakpoints" => conn.respond(&request, json!({"breakpoints": []})),
+                "configurationDone" => {
+                    conn.respond(&request, json!({}));
+                    if stop_on_entry {
+                        self.run(conn, |debugger| debugger.step(Step::In), "entry");
+                    } else {
+                        self.run(conn, |debugger| debugger.resume(), "step");
+                    }
+                },
+                "threads" => conn.respond(&request, json!({"threads": [{"id": 1, "name": "main"}]})),
+                "stackTrace" => {
+                    let body = self.stack_trace();
+                    conn.respond(&request, body);
+                },
+                "scopes" => {
+                    let body = self.scopes(args["frameId"].as_u64().unwrap_or(0) as usize);
+                    conn.respond(&request, body);
+                },
+                "variables" => match self.variables(args["variablesReference"].as_u64().unwrap_or(0) as usize) {
+                    Some(body) => conn.respond(&request, body),
+                    None => conn.fail(&request, "Unknown variables reference")
+                },
+                "evaluate" => match self.debugger.evaluate(args["expression"].as_str().unwrap_or("")) {
+                    Ok(value) => conn.respond(&request, json!({"result": value.to_string(), "variablesReference": 0})),
+                    Err(err) => conn.fail(&request, &err)
+                },
+                "continue" => {
+                    conn.respond(&request, json!({"allThreadsContinued": true}));
+                    self.run(conn, |debugger| debugger.resume(), "step");
+                },
+                "next" | "stepIn" | "stepOut" => {
+                    let step = match request["command"].as_str() {
+                        Some("next") => Step::Over,
+                        Some("stepIn") => Step::In,
+                        _ => Step::Out
+                    };
+                    conn.respond(&request, json!({}));
+                    self.run(conn, |debugger| debugger.step(step), "step");
+                },
+                // Stepping back runs the backward code of the previous statement //
+                "stepBack" => match self.debugger.step_back() {
+                    Ok(_) => {
+                        conn.respond(&request, json!({}));
+                        self.stopped(conn, Stop::Step, "step");
+                    },
+                    Err(err) => conn.fail(&request, &err)
+                },
+                "reverseContinue" => {
+                    conn.respond(&request, json!({}));
+                    self.run(conn, |debugger| match debugger.reverse_continue() {
+                        Ok(Some(idx)) => Stop::Breakpoint(idx),
+                        Ok(None) => Stop::Step,
+                        Err(err) => Stop::Error(err)
+                    }, "step");
+                },
+                "pause" => conn.fail(&request, "Pausing a running program is not supported"),
+                "disconnect" | "terminate" => {
+                    conn.respond(&request, json!({}));
+                    return;
+                },
+                _ => conn.fail(&request, "Unsupported request")
+            }
+        }
+    }
+
+    // A client's breakpoints replace all existing ones, as there is only one source file //
+    fn set_breakpoints(&mut self, args: &Value) -> Value {
+        self.debugger.clear_breakpoints();
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        let breakpoints: Vec<Value> = requested.iter().map(|breakpoint| {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+            let condition = breakpoint["condition"].as_str().filter(|c| !c.trim().is_empty()).map(String::from);
+            match self.debugger.add_breakpoint(line, condition) {
+                Ok(idx) => json!({"id": idx, "verified": true, "line": line}),
+                Err(err) => json!({"verified": false, "line": line, "message": err})
+            }
+        }).collect();
+        json!({"breakpoints": breakpoints})
+    }
+
+    fn run<R: BufRead, W: Write, F>(&mut self, conn: &mut Connection<R, W>, action: F, reason: &str)
+    where F: FnOnce(&mut Debugger<'a>) -> Stop {
+        if self.finished {
+            conn.event("terminated", json!({}));
+            return;
+        }
+        let stop = action(&mut self.debugger);
+        self.stopped(conn, stop, reason);
+    }
+
+    fn stopped<R: BufRead, W: Write>(&mut self, conn: &mut Connection<R, W>, stop: Stop, reason: &str) {
+        self.flush_output(conn);
+        self.handles.clear();
+        let body = match stop {
+            Stop::Finished => return self.finish(conn, 0),
+            Stop::Failed(err) => {
+                conn.event("output", json!({"category": "stderr", "output": format!("{}\n", err)}));
+                return self.finish(conn, 1);
+            },
+            Stop::Step => json!({"reason": reason}),
+            Stop::Breakpoint(idx) => json!({"reason": "breakpoint", "hitBreakpointIds": [idx]}),
+            Stop::Watchpoint(changes, _) => {
+                let description: Vec<String> = changes.iter().map(|change| change.path.clone()).collect();
+                json!({"reason": "data breakpoint", "description": description.join(", ")})
+            },
+            Stop::Error(err) => json!({"reason": "exception", "text": err})
+        };
+        let mut body = body;
+        body["threadId"] = json!(1);
+        body["allThreadsStopped"] = json!(true);
+        conn.event("stopped", body);
+    }
+
+    fn finish<R: BufRead, W: Write>(&mut self, conn: &mut Connection<R, W>, exit_code: i32) {
+        self.finished = true;
+        conn.event("exited", json!({"exitCode": exit_code}));
+        conn.event("terminated", json!({}));
+    }
+
+    fn flush_output<R: BufRead, W: Write>(&mut self, conn: &mut Connection<R, W>) {
+        let output = self.debugger.interpreter_mut().take_output();
+        if !output.is_empty() {
+            conn.event("output", json!({"category": "stdout", "output": output}));
+        }
+    }
+
+    // The global scope's frame sits below main for the whole run, so is only shown while it
+    // is the innermost. Frame ids count outwards from the innermost frame //
+    fn stack_trace(&self) -> Value {
+        let module = self.debugger.module();
+        let source = json!({
+            "name": Path::new(&self.program).file_name().map_or(self.program.clone(), |n| n.to_string_lossy().into_owned()),
+            "path": self.program
+        });
+        let frames: Vec<Value> = self.debugger.interpreter().frames().into_iter().enumerate()
+            .filter(|(id, (func_idx, ..))| *id == 0 || *func_idx != module.global_func_idx)
+            .map(|(id, (func_idx, forwards, ip, _))| {
+                let name = &module.functions[func_idx].name;
+                let name = if func_idx == module.global_func_idx {String::from("<global scope>")}
+                           else if forwards {name.clone()}
+                           else {format!("~{}", name)};
+                json!({
+                    "id": id, "name": name, "source": source,
+                    "line": self.debugger.line_at(func_idx, forwards, ip), "column": 1
+                })
+            })
+            .collect();
+        json!({"stackFrames": frames, "totalFrames": frames.len()})
+    }
+
+    fn handle(&mut self, handle: Handle) -> usize {
+        self.handles.push(handle);
+        self.handles.len()
+    }
+
+    fn scopes(&mut self, frame_id: usize) -> Value {
+        let globals = json!({"name": "Globals", "variablesReference": self.handle(Handle::Globals), "expensive": false});
+        let module = self.debugger.module();
+        let is_global = self.debugger.interpreter().frames()
+            .get(frame_id)
+            .is_none_or(|(func_idx, ..)| *func_idx == module.global_func_idx);
+        if is_global {
+            return json!({"scopes": [globals]});
+        }
+        let locals = json!({"name": "Locals", "variablesReference": self.handle(Handle::Frame(frame_id)), "expensive": false});
+        json!({"scopes": [locals, globals]})
+    }
+
+    // Variables are listed by their source names, and arrays can be expanded //
+    fn variables(&mut self, reference: usize) -> Option<Value> {
+        let module = self.debugger.module();
+        let named: Vec<(String, Rc<RefCell<Variable>>)> = match self.handles.get(reference.checked_sub(1)?)? {
+            Handle::Frame(frame_id) => {
+                let (func_idx, forwards, ip, registers) = *self.debugger.interpreter().frames().get(*frame_id)?;
+                self.debugger.bound_names(func_idx, Some((forwards, ip)), registers).into_iter()
+                    .filter_map(|(name, register)| Some((name, Rc::clone(registers[register].as_ref()?))))
+                    .collect()
+            },
+            Handle::Globals => {
+                let registers = self.debugger.interpreter().global_registers();
+                self.debugger.bound_names(module.global_func_idx, None, registers).into_iter()
+                    .filter_map(|(name, register)| Some((name, Rc::clone(registers[register].as_ref()?))))
+                    .collect()
+            },
+            Handle::Value(var) => match &*var.borrow() {
+                Variable::Array(items) => items.iter().enumerate()
+                    .map(|(idx, item)| (format!("[{}]", idx), Rc::clone(item)))
+                    .collect(),
+                _ => Vec::new()
+            }
+        };
+        let variables: Vec<Value> = named.into_iter().map(|(name, var)| {
+            let value = var.borrow().to_string();
+            let is_array = matches!(&*var.borrow(), Variable::Array(_));
+            let reference = if is_array {self.handle(Handle::Value(var))} else {0};
+            json!({"name": name, "value": value, "variablesReference": reference})
+        }).collect();
+        Some(json!({"variables": variables}))
+    }
+}
+
+
+fn capabilities() -> Value {
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsConditionalBreakpoints": true,
+        "supportsStepBack": true,
+        "supportsEvaluateForHovers": true,
+        "supportsTerminateRequest": true
+    })
+}
+
+
+// Serve a single debug session over stdin and stdout. The program is compiled on launch, and
+// runs once the client has finished sending its configuration //
+pub fn run() {
+    let stdin = io::stdin();
+    let mut conn = Connection{input: stdin.lock(), output: io::stdout(), seq: 1};
+    while let Some(request) = conn.read() {
+        match request["command"].as_str().unwrap_or("") {
+            "initialize" => conn.respond(&request, capabilities()),
+            "launch" => {
+                let args = &request["arguments"];
+                let program = match args["program"].as_str() {
+                    Some(program) => program.to_string(),
+                    None => {
+                        conn.fail(&request, "No program to launch");
+                        continue;
+                    }
+                };
+                // Flags for #[cfg(...)] attributes, e.g. ["debug", "feature=x"] //
+                let mut cfg = Cfg::default();
+                for flag in args["cfg"].as_array().into_iter().flatten().filter_map(|f| f.as_str()) {
+                    cfg.set(flag);
+                }
+                cfg.add_include_path(Path::new(&program).parent().unwrap_or(Path::new(".")));
+                let module = match fs::read_to_string(&program).map_err(|err| err.to_string()).and_then(|src| repl::compile(&src, &cfg, &EngineOptions::default())) {
+                    Ok(module) => module,
+                    Err(err) => {
+                        conn.fail(&request, &err);
+                        continue;
+                    }
+                };
+                conn.respond(&request, json!({}));
+                conn.event("initialized", json!({}));
+                let stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                Adapter::new(&module, &program).serve(&mut conn, stop_on_entry);
+                return;
+            },
+            "disconnect" | "terminate" => {
+                conn.respond(&request, json!({}));
+                return;
+            },
+            _ => conn.fail(&request, "The program has not been launched")
+        }
+    }
+}
@@ -0,0 +1,570 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use std::rc::Rc;
+
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+use crate::compiler::compile_expression;
+use crate::diff::{self, Change};
+use crate::interpreter::{Interpreter, Module, RuntimeError, StateError, Status, Variable};
+use crate::parser::{parse_expression, ParseError};
+use crate::syntaxchecker::{check_expression, SyntaxError};
+use crate::tokeniser::Tokeniser;
+
+
+// A variable, or an element of one, identified by the register holding it in some function //
+#[derive(Debug, Clone)]
+pub struct Watchpoint {
+    pub expr: String,
+    pub func_idx: usize,
+    pub registers: Vec<usize>,
+    pub indices: Vec<usize>,
+    value: Option<Variable>
+}
+
+impl Watchpoint {
+    // Read the watched value from the innermost frame of its function, if there is one //
+    fn read(&self, interpreter: &Interpreter, global_func_idx: usize) -> Option<Variable> {
+        let registers = if self.func_idx == global_func_idx {
+            interpreter.global_registers()
+        } else {
+            interpreter.frame_registers(self.func_idx)?
+        };
+        let mut var: Rc<RefCell<Variable>> = self.registers.iter()
+            .find_map(|&r| registers.get(r).and_then(|v| v.as_ref()))
+            .map(Rc::clone)?;
+        for &idx in self.indices.iter() {
+            let item = match &*var.borrow() {
+                Variable::Array(items) => Rc::clone(items.get(idx)?),
+                _ => return None
+            };
+            var = item;
+        }
+        let value = var.borrow().deep_copy();
+        Some(value)
+    }
+}
+
+
+// Parse `name` or `name[i][j]...` //
+fn parse_watch_expr(expr: &str) -> Option<(String, Vec<usize>)> {
+    let (name, mut rest) = match expr.find('[') {
+        Some(pos) => (&expr[..pos], &expr[pos..]),
+        None => (expr, "")
+    };
+    let mut indices = Vec::new();
+    while !rest.is_empty() {
+        let close = rest.find(']')?;
+        if !rest.starts_with('[') {return None}
+        indices.push(rest[1..close].trim().parse().ok()?);
+        rest = &rest[close + 1..];
+    }
+    if name.is_empty() {return None}
+    Some((name.to_string(), indices))
+}
+
+
+// A source line, stopped at in either direction when its condition holds //
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    pub line: usize,
+    pub condition: Option<String>,
+    positions: Vec<(usize, bool, usize)>
+}
+
+
+// Parse `[file:]LINE [if CONDITION]` //
+fn parse_break_args(args: &str) -> Option<(usize, Option<String>)> {
+    let (location, condition) = match args.find(" if ") {
+        Some(pos) => (args[..pos].trim(), Some(args[pos + 4..].trim().to_string())),
+        None => (args.trim(), None)
+    };
+    let line = location.rsplit(':').next()?.parse().ok()?;
+    Some((line, condition))
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    In,    // Stop at the next statement anywhere
+    Over,  // Stop at the next statement in this frame or a caller
+    Out    // Stop at the next statement in a caller
+}
+
+
+// Why the program stopped //
+pub enum Stop {
+    Watchpoint(Vec<Change>, (usize, bool, usize)),  // With the position of the instruction that made the changes
+    Breakpoint(usize),
+    Step,
+    Error(String),
+    Failed(RuntimeError),  // The program hit a runtime error, which ends it
+    Finished
+}
+
+
+pub struct Debugger<'a> {
+    module: &'a Module,
+    interpreter: Interpreter<'a>,
+    watchpoints: Vec<Watchpoint>,
+    breakpoints: Vec<Breakpoint>,
+    in_main: bool,
+    finished: bool
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(module: &'a Module) -> Debugger<'a> {
+        Debugger{
+            module,
+            interpreter: Interpreter::new(module),
+            watchpoints: Vec::new(),
+            breakpoints: Vec::new(),
+            in_main: false,
+            finished: false
+        }
+    }
+
+    // Names are looked up in the paused function, then main, then the global scope. A name can be
+    // bound to several registers over a function's life, so a watch follows whatever is in the
+    // first of its registers holding a variable //
+    pub fn watch(&mut self, expr: &str) -> Result<usize, String> {
+        let (name, indices) = parse_watch_expr(expr).ok_or(format!("Can't parse watch expression \"{}\"", expr))?;
+        let (current, _, _) = self.interpreter.position();
+        let candidates = [Some(current), self.module.main_idx, Some(self.module.global_func_idx)];
+        for func_idx in candidates.iter().flatten() {
+            let mut registers: Vec<usize> = self.module.functions[*func_idx].symbols.iter()
+                .filter(|symbol| symbol.name == name)
+                .map(|symbol| symbol.register)
+                .collect();
+            registers.sort();
+            registers.dedup();
+            if !registers.is_empty() {
+                let mut watchpoint = Watchpoint{
+                    expr: expr.to_string(), func_idx: *func_idx, registers, indices, value: None
+                };
+                watchpoint.value = watchpoint.read(&self.interpreter, self.module.global_func_idx);
+                self.watchpoints.push(watchpoint);
+                self.interpreter.set_watching(true);
+                return Ok(self.watchpoints.len() - 1);
+            }
+        }
+        Err(format!("No variable named \"{}\"", name))
+    }
+
+    pub fn unwatch(&mut self, idx: usize) -> Result<(), String> {
+        if idx >= self.watchpoints.len() {
+            return Err(format!("No watchpoint {}", idx));
+        }
+        self.watchpoints.remove(idx);
+        self.interpreter.set_watching(!self.watchpoints.is_empty());
+        Ok(())
+    }
+
+    // A breakpoint stops at the start of every statement on the line, in each direction //
+    pub fn add_breakpoint(&mut self, line: usize, condition: Option<String>) -> Result<usize, String> {
+        let mut positions = Vec::new();
+        for (func_idx, func) in self.module.functions.iter().enumerate() {
+            for region in func.code.regions.iter().filter(|r| r.line == line) {
+                if !region.fwd.is_empty() {positions.push((func_idx, true, region.fwd.start))}
+                if !region.bkwd.is_empty() {positions.push((func_idx, false, region.bkwd.start))}
+            }
+        }
+        if positions.is_empty() {
+            return Err(format!("No statement starts on line {}", line));
+        }
+        self.breakpoints.push(Breakpoint{line, condition, positions});
+        self.update_breakpoints();
+        Ok(self.breakpoints.len() - 1)
+    }
+
+    pub fn delete_breakpoint(&mut self, idx: usize) -> Result<(), String> {
+        if idx >= self.breakpoints.len() {
+            return Err(format!("No breakpoint {}", idx));
+        }
+        self.breakpoints.remove(idx);
+        self.update_breakpoints();
+        Ok(())
+    }
+
+    fn update_breakpoints(&mut self) {
+        let positions: HashSet<_> = self.breakpoints.iter()
+            .flat_map(|b| b.positions.iter().copied())
+            .collect();
+        self.interpreter.set_breakpoints(positions);
+    }
+
+    // The names in a function's frame with variables in their registers, either live at a
+    // position or anywhere in the function. Where an inner block shadows a name, the inner
+    // binding is the one kept //
+    pub fn bound_names(
+        &self, func_idx: usize, position: Option<(bool, usize)>, registers: &[Option<Rc<RefCell<Variable>>>]
+    ) -> Vec<(String, usize)> {
+        let mut bound: Vec<(String, usize)> = Vec::new();
+        for symbol in self.module.functions[func_idx].symbols.iter().rev() {
+            if position.is_some_and(|(forwards, ip)| !symbol.is_live(forwards, ip)) {continue}
+            if registers.get(symbol.register).is_none_or(|v| v.is_none()) {continue}
+            if !bound.iter().any(|(name, _)| *name == symbol.name) {
+                bound.push((symbol.name.clone(), symbol.register));
+            }
+        }
+        bound.reverse();
+        bound
+    }
+
+    // Evaluate an expression in the paused function's scope //
+    pub fn evaluate(&self, src: &str) -> Result<Variable, String> {
+        let expr = parse_expression(Tokeniser::new(src))
+            .map_err(|ParseError{col, ..}| format!("ParseError in expression at column {}", col))?;
+        let (current, forwards, ip) = self.interpreter.position();
+        let globals = self.bound_names(self.module.global_func_idx, None, self.interpreter.global_registers());
+        let locals = match self.interpreter.frame_registers(current) {
+            Some(registers) if current != self.module.global_func_idx => {
+                self.bound_names(current, Some((forwards, ip)), registers)
+            },
+            _ => Vec::new()
+        };
+        let (expr, consts) = check_expression(expr, &locals, &globals)
+            .map_err(|SyntaxError{desc, ..}| format!("SyntaxError in expression: {}", desc))?;
+        let code = compile_expression(&expr);
+        self.interpreter.evaluate(&code, &consts)
+            .map_err(|err| format!("Evaluating the expression failed: {}", err.desc))
+    }
+
+    // The first breakpoint at the current position whose condition holds //
+    fn triggered_breakpoint(&self) -> Result<Option<usize>, String> {
+        let position = self.interpreter.position();
+        for (idx, breakpoint) in self.breakpoints.iter().enumerate() {
+            if !breakpoint.positions.contains(&position) {continue}
+            match &breakpoint.condition {
+                None => return Ok(Some(idx)),
+                Some(condition) => if self.evaluate(condition)?.to_bool()? {return Ok(Some(idx))}
+            }
+        }
+        Ok(None)
+    }
+
+    // Changes to watched values since they were last read //
+    fn check_watchpoints(&mut self) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for watchpoint in self.watchpoints.iter_mut() {
+            let value = watchpoint.read(&self.interpreter, self.module.global_func_idx);
+            if value != watchpoint.value {
+                changes.push(Change{
+                    path: watchpoint.expr.clone(),
+                    old: watchpoint.value.as_ref().map(|v| v.to_string()),
+                    new: value.as_ref().map(|v| v.to_string())
+                });
+                watchpoint.value = value;
+            }
+        }
+        changes
+    }
+
+    // Run until a watched value changes, a breakpoint's condition holds or the program ends //
+    pub fn resume(&mut self) -> Stop {
+        self.run_until(None)
+    }
+
+    // Run to the start of the next statement, in either direction, that `step` allows stopping
+    // at. Breakpoints and watchpoints met on the way stop it early //
+    pub fn step(&mut self, step: Step) -> Stop {
+        self.run_until(Some(step))
+    }
+
+    fn run_until(&mut self, step: Option<Step>) -> Stop {
+        let mut statement_starts = HashSet::new();
+        if step.is_some() {
+            for (func_idx, func) in self.module.functions.iter().enumerate() {
+                for region in func.code.regions.iter() {
+                    for forwards in [true, false] {
+                        let range = region.range(forwards);
+                        if !range.is_empty() {statement_starts.insert((func_idx, forwards, range.start));}
+                    }
+                }
+            }
+            let mut positions = statement_starts.clone();
+            positions.extend(self.breakpoints.iter().flat_map(|b| b.positions.iter().copied()));
+            self.interpreter.set_breakpoints(positions);
+        }
+
+        let mut depth = self.interpreter.depth();
+        let stop = loop {
+            if self.finished {break Stop::Finished}
+            let status = match self.interpreter.execute() {
+                Ok(status) => status,
+                Err(err) => {
+                    self.finished = true;
+                    break Stop::Failed(err);
+                }
+            };
+            match status {
+                Status::Paused => {
+                    let changes = self.check_watchpoints();
+                    if !changes.is_empty() {
+                        // Count the position as already stopped at, so the next step moves on. The
+                        // instruction that paused can't have jumped, so it sits just behind ip //
+                        let (func_idx, forwards, ip) = self.interpreter.position();
+                        self.interpreter.jump_to(forwards, ip);
+                        break Stop::Watchpoint(changes, (func_idx, forwards, ip - 1));
+                    }
+                },
+                Status::Breakpoint => {
+                    match self.triggered_breakpoint() {
+                        Ok(Some(idx)) => break Stop::Breakpoint(idx),
+                        Ok(None) => {},
+                        Err(err) => break Stop::Error(err)
+                    }
+                    let now = self.interpreter.depth();
+                    let stop_here = match step {
+                        Some(Step::In) => true,
+                        Some(Step::Over) => now <= depth,
+                        Some(Step::Out) => now < depth,
+                        None => false
+                    };
+                    if stop_here && statement_starts.contains(&self.interpreter.position()) {
+                        break Stop::Step;
+                    }
+                },
+                Status::Finished if !self.in_main => {
+                    self.in_main = true;
+                    let main_idx = self.module.main_idx.expect("No main function");
+                    if let Err(err) = self.interpreter.call(main_idx, true) {
+                        self.finished = true;
+                        break Stop::Failed(err);
+                    }
+                    // Leaving the global scope for main counts as stepping out of it //
+                    depth = self.interpreter.depth() + usize::from(matches!(step, Some(Step::Out)));
+                },
+                Status::Finished => self.finished = true,
+                Status::Stopped => {}
+            }
+        };
+        if step.is_some() {self.update_breakpoints()}
+        stop
+    }
+
+    // Undo the statement before the one the paused frame is about to run, leaving the frame at
+    // its start. Only a statement in the same block can be undone, since what ran before a block
+    // depends on how it was entered //
+    pub fn step_back(&mut self) -> Result<Vec<Change>, String> {
+        if self.finished {return Err(String::from("The program has finished"))}
+        let (func_idx, forwards, ip) = self.interpreter.position();
+        let regions = &self.module.functions[func_idx].code.regions;
+        let current = regions.iter()
+            .find(|r| !r.range(forwards).is_empty() && r.range(forwards).start == ip)
+            .ok_or("Not at the start of a statement")?;
+        let previous = regions.iter()
+            .find(|r| r.depth == current.depth && !r.range(forwards).is_empty() && r.range(forwards).end == ip)
+            .ok_or("No earlier statement in this block")?;
+        if previous.is_mono {
+            return Err(String::from("Can't step back over a mono statement"));
+        }
+
+        let undo = previous.range(!forwards).clone();
+        self.interpreter.set_breakpoints(HashSet::new());
+        self.interpreter.jump_to(!forwards, undo.start);
+        self.interpreter.set_stop(Some((self.interpreter.depth(), !forwards, undo.end)));
+        loop {
+            match self.interpreter.execute() {
+                Ok(Status::Stopped) => break,
+                Ok(_) => {},
+                Err(err) => {
+                    self.finished = true;
+                    return Err(err.to_string());
+                }
+            }
+        }
+        self.interpreter.jump_to(forwards, previous.range(forwards).start);
+        self.update_breakpoints();
+        Ok(self.check_watchpoints())
+    }
+
+    // Step back until reaching a breakpoint whose condition holds, or the start of the block //
+    pub fn reverse_continue(&mut self) -> Result<Option<usize>, String> {
+        loop {
+            if self.step_back().is_err() {return Ok(None)}
+            if let Some(idx) = self.triggered_breakpoint()? {return Ok(Some(idx))}
+        }
+    }
+
+    // Replace the program's state with one dumped earlier, keeping breakpoints and watchpoints //
+    pub fn load_state(&mut self, path: &Path) -> Result<(), StateError> {
+        self.interpreter = Interpreter::load_state(self.module, path)?;
+        let (func_idx, _, _) = self.interpreter.position();
+        self.in_main = self.interpreter.depth() > 0 || func_idx != self.module.global_func_idx;
+        self.finished = false;
+        self.update_breakpoints();
+        self.interpreter.set_watching(!self.watchpoints.is_empty());
+        self.check_watchpoints();
+        Ok(())
+    }
+
+    pub fn module(&self) -> &'a Module {
+        self.module
+    }
+
+    pub fn interpreter(&self) -> &Interpreter<'a> {
+        &self.interpreter
+    }
+
+    pub fn interpreter_mut(&mut self) -> &mut Interpreter<'a> {
+        &mut self.interpreter
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+        self.update_breakpoints();
+    }
+
+    // The line of the innermost statement containing a position //
+    pub fn line_at(&self, func_idx: usize, forwards: bool, ip: usize) -> usize {
+        self.module.functions[func_idx].code.regions.iter()
+            .filter(|r| r.line != 0 && r.range(forwards).contains(&ip))
+            .max_by_key(|r| r.depth)
+            .map_or(0, |r| r.line)
+    }
+
+    fn describe_position(&self) -> String {
+        self.describe(self.interpreter.position())
+    }
+
+    fn describe(&self, (func_idx, forwards, ip): (usize, bool, usize)) -> String {
+        format!(
+            "{}, line {} ({} ip {})",
+            self.module.functions[func_idx].name, self.line_at(func_idx, forwards, ip),
+            if forwards {"fwd"} else {"bkwd"}, ip
+        )
+    }
+
+    fn report(&self, stop: Stop) {
+        match stop {
+            Stop::Finished => println!("Program finished"),
+            Stop::Watchpoint(changes, position) => {
+                println!("Watchpoint hit at {}", self.describe(position));
+                print!("{}", diff::format_changes(&changes, io::stdout().is_terminal()));
+            },
+            Stop::Breakpoint(idx) => println!("Breakpoint {} hit at {}", idx, self.describe_position()),
+            Stop::Step => println!("At {}", self.describe_position()),
+            Stop::Error(err) => {
+                println!("Stopped at {}", self.describe_position());
+                eprintln!("{}", err);
+            },
+            Stop::Failed(err) => eprintln!("{}", err)
+        }
+    }
+
+    pub fn command(&mut self, line: &str) -> bool {
+        let line = line.trim();
+        let (cmd, arg) = match line.find(' ') {
+            Some(pos) => (&line[..pos], line[pos..].trim()),
+            None => (line, "")
+        };
+        match cmd {
+            "continue" | "c" => {
+                if self.finished {
+                    println!("The program has finished");
+                    return true;
+                }
+                let stop = self.resume();
+                self.report(stop);
+            },
+            "step" | "s" | "next" | "n" | "finish" => {
+                let step = match cmd {
+                    "step" | "s" => Step::In,
+                    "next" | "n" => Step::Over,
+                    _ => Step::Out
+                };
+                let stop = self.step(step);
+                self.report(stop);
+            },
+            "back" => match self.step_back() {
+                Ok(changes) => {
+                    println!("At {}", self.describe_position());
+                    print!("{}", diff::format_changes(&changes, io::stdout().is_terminal()));
+                },
+                Err(err) => eprintln!("{}", err)
+            },
+            "break" | "b" => match parse_break_args(arg) {
+                Some((line, condition)) => match self.add_breakpoint(line, condition) {
+                    Ok(idx) => println!("Breakpoint {}: {}", idx, arg),
+                    Err(err) => eprintln!("{}", err)
+                },
+                None => eprintln!("Usage: break [FILE:]LINE [if CONDITION]")
+            },
+            "delete" => match arg.parse() {
+                Ok(idx) => if let Err(err) = self.delete_breakpoint(idx) {eprintln!("{}", err)},
+                Err(_) => eprintln!("Usage: delete N")
+            },
+            "breaks" => for (idx, breakpoint) in self.breakpoints.iter().enumerate() {
+                match &breakpoint.condition {
+                    Some(condition) => println!("  {}: line {} if {}", idx, breakpoint.line, condition),
+                    None => println!("  {}: line {}", idx, breakpoint.line)
+                }
+            },
+            "print" | "p" => match self.evaluate(arg) {
+                Ok(value) => println!("{}", value),
+                Err(err) => eprintln!("{}", err)
+            },
+            "watch" => match self.watch(arg) {
+                Ok(idx) => println!("Watchpoint {}: {}", idx, arg),
+                Err(err) => eprintln!("{}", err)
+            },
+            "unwatch" => match arg.parse() {
+                Ok(idx) => if let Err(err) = self.unwatch(idx) {eprintln!("{}", err)},
+                Err(_) => eprintln!("Usage: unwatch N")
+            },
+            "watches" => for (idx, watchpoint) in self.watchpoints.iter().enumerate() {
+                let value = watchpoint.value.as_ref().map_or(String::from("<empty>"), |v| v.to_string());
+                println!("  {}: {} = {}", idx, watchpoint.expr, value);
+            },
+            "where" => println!("{}", self.describe_position()),
+            "reversals" => for (idx, reversal) in self.interpreter.reversals().iter().enumerate() {
+                println!(
+                    "  {}: catch in {}, line {}, unwound {} instructions",
+                    idx, self.module.functions[reversal.func_idx].name, reversal.line, reversal.unwound
+                );
+            },
+            "dump" => match self.interpreter.dump_state(Path::new(arg)) {
+                Ok(()) => println!("State written to {}", arg),
+                Err(StateError{desc}) => eprintln!("{}", desc)
+            },
+            "load" => match self.load_state(Path::new(arg)) {
+                Ok(()) => println!("At {}", self.describe_position()),
+                Err(StateError{desc}) => eprintln!("{}", desc)
+            },
+            "quit" | "q" => return false,
+            _ => eprintln!(concat!(
+                "Commands: continue, step, next, finish, back, break [FILE:]LINE [if COND], delete N, breaks, ",
+                "watch EXPR, unwatch N, watches, print EXPR, where, reversals, dump PATH, load PATH, quit"
+            ))
+        }
+        true
+    }
+}
+
+
+pub fn run(module: &Module) {
+    let mut editor = DefaultEditor::new().expect("Failed to initialise line editor");
+    let mut debugger = Debugger::new(module);
+    loop {
+        match editor.readline("(debug) ") {
+            Ok(line) => {
+                let _ = editor.add_history_entry(line.as_str());
+                if !debugger.command(&line) {break}
+            },
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("{}", err);
+                break;
+            }
+        }
+    }
+}
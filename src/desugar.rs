@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use std::mem;
+
+use crate::interpreter::{Fraction, Instruction};
+use crate::macros::expand_macros;
+use crate::parsetree as PT;
+use crate::syntaxchecker::SyntaxError;
+
+
+// A rewrite of the parse tree into simpler parse tree, run after imports and #[cfg] attributes are
+// resolved and before syntax checking //
+pub struct Pass {
+    pub name: &'static str,
+    pub run: fn(&mut PT::Module) -> Result<(), SyntaxError>
+}
+
+// Every pass, in the order they run //
+pub const PASSES: [Pass; 2] = [
+    Pass{name: "macros", run: expand_macros},
+    Pass{name: "statements", run: expand_statements}
+];
+
+pub fn desugar(module: &mut PT::Module) -> Result<(), SyntaxError> {
+    run_passes(module, &PASSES)
+}
+
+pub fn run_passes(module: &mut PT::Module, passes: &[Pass]) -> Result<(), SyntaxError> {
+    for pass in passes {
+        log::debug!("Running desugaring pass {}", pass.name);
+        (pass.run)(module)?;
+    }
+    Ok(())
+}
+
+// Apply a rewrite to the statements of the global scope and of every function //
+fn for_each_stmts<F>(module: &mut PT::Module, rewrite: &F) -> Result<(), SyntaxError>
+    where F: Fn(&mut Vec<PT::StatementNode>) -> Result<(), SyntaxError>
+{
+    rewrite(&mut module.global_func.stmts)?;
+    for_each_function_stmts(&mut module.functions, rewrite)
+}
+
+fn for_each_function_stmts<F>(functions: &mut [PT::FunctionNode], rewrite: &F) -> Result<(), SyntaxError>
+    where F: Fn(&mut Vec<PT::StatementNode>) -> Result<(), SyntaxError>
+{
+    for function in functions {
+        rewrite(&mut function.stmts)?;
+        for_each_function_stmts(&mut function.functions, rewrite)?;
+    }
+    Ok(())
+}
+
+
+// Replace each statement that is sugar, such as a machine block, with the statements it stands for //
+fn expand_statements(module: &mut PT::Module) -> Result<(), SyntaxError> {
+    for_each_stmts(module, &desugar_stmts)
+}
+
+fn desugar_stmts(stmts: &mut Vec<PT::StatementNode>) -> Result<(), SyntaxError> {
+    for mut stmt in mem::take(stmts) {
+        match stmt.desugar() {
+            Some(expansion) => {
+                let mut expansion = expansion?;
+                desugar_stmts(&mut expansion)?;
+                stmts.extend(expansion);
+            },
+            None => {
+                for substmts in stmt.substatements() {
+                    desugar_stmts(substmts)?;
+                }
+                stmts.push(stmt);
+            }
+        }
+    }
+    Ok(())
+}
+
+
+// A machine named m with states numbered 0 to n-1 becomes
+//
+//     m = 0;
+//     m.trail = [];
+//     while (m != n) {
+//         m.prev = m;
+//         m.prev => m.trail;
+//         if (m == 0) {
+//             <state 0 body>
+//             if (cond) {m += target - 0;} else {... m += n - 0;} ~if (m == target);
+//         } else {
+//             <the same for states 1 to n-1>
+//         } ~if (m.trail[#m.trail - 1] == 0);
+//     } ~while (#m.trail);
+//
+// The trail of states visited lets each step be undone, so m and m.trail are left for the caller
+// to undo, e.g. by running the machine in a do-yield block //
+pub fn expand_machine(machine: &PT::MachineNode) -> Result<Vec<PT::StatementNode>, SyntaxError> {
+    let PT::MachineNode{line, col, name, states} = machine;
+    let (line, col) = (*line, *col);
+    let trail = format!("{}.trail", name);
+    let prev = format!("{}.prev", name);
+
+    let mut numbers = HashMap::new();
+    for (idx, state) in states.iter().enumerate() {
+        if numbers.insert(state.name.as_str(), idx).is_some() {
+            return Err(SyntaxError{line: state.line, col: state.col, desc: format!(
+                "Machine \"{}\" has more than one state named \"{}\"", name, state.name), note: None});
+        }
+    }
+
+    let builder = Builder{line, col};
+    let halted = states.len();
+    let mut dispatch = Vec::new();
+    for (idx, state) in states.iter().enumerate().rev() {
+        let mut stmts = state.stmts.clone();
+        stmts.extend(builder.transitions(name, idx, halted, state, &numbers)?);
+        let last_state = builder.index(&trail, builder.binop(
+            builder.len(&trail), Instruction::BinopSub, builder.num(1)));
+        dispatch = vec![builder.stmt(PT::IfNode{
+            fwd_expr: builder.binop(builder.lookup(name), Instruction::BinopEq, builder.num(idx)),
+            if_stmts: stmts,
+            else_stmts: dispatch,
+            bkwd_expr: builder.binop(last_state, Instruction::BinopEq, builder.num(idx)),
+            bkwd_elided: false
+        })];
+    }
+
+    let mut body = vec![
+        builder.stmt(builder.let_(&prev, builder.lookup(name))),
+        builder.stmt(PT::PushPullNode{line, col, is_push: true, name: prev, lookup: builder.lookup_node(&trail)})
+    ];
+    body.extend(dispatch);
+    Ok(vec![
+        builder.stmt(builder.let_(name, builder.num(0))),
+        builder.stmt(builder.let_(&trail, Box::new(PT::ArrayLiteralNode{line, col, items: Vec::new()}))),
+        builder.stmt(PT::WhileNode{
+            fwd_expr: builder.binop(builder.lookup(name), Instruction::BinopNeq, builder.num(halted)),
+            stmts: body,
+            bkwd_expr: Some(builder.len(&trail))
+        })
+    ])
+}
+
+// Makes nodes attributed to the machine's position //
+struct Builder {
+    line: usize,
+    col: usize
+}
+
+impl Builder {
+    // Each transition moves to a different state, so which was taken can be told afterwards from
+    // the state alone. Falling through every transition halts the machine //
+    fn transitions(
+        &self,
+        name: &str,
+        idx: usize,
+        halted: usize,
+        state: &PT::MachineState,
+        numbers: &HashMap<&str, usize>
+    ) -> Result<Vec<PT::StatementNode>, SyntaxError> {
+        let mut targets = Vec::new();
+        for PT::Transition{line, col, target, ..} in &state.transitions {
+            let err = |desc| Err(SyntaxError{line: *line, col: *col, desc, note: None});
+            match numbers.get(target.as_str()) {
+                None => return err(format!("Machine \"{}\" has no state named \"{}\"", name, target)),
+                Some(number) if targets.contains(number) => return err(format!(
+                    "State \"{}\" has more than one transition to \"{}\"", state.name, target)),
+                Some(&number) => targets.push(number)
+            }
+        }
+        let mut stmts = self.move_state(name, idx, halted);
+        for (transition, &target) in state.transitions.iter().zip(targets.iter()).rev() {
+            stmts = vec![self.stmt(PT::IfNode{
+                fwd_expr: transition.cond.clone(),
+                if_stmts: self.move_state(name, idx, target),
+                else_stmts: stmts,
+                bkwd_expr: self.binop(self.lookup(name), Instruction::BinopEq, self.num(target)),
+                bkwd_elided: false
+            })];
+        }
+        Ok(stmts)
+    }
+
+    fn move_state(&self, name: &str, from: usize, to: usize) -> Vec<PT::StatementNode> {
+        let (op, delta) = match to.cmp(&from) {
+            std::cmp::Ordering::Equal => return Vec::new(),
+            std::cmp::Ordering::Greater => (Instruction::BinopAdd, to - from),
+            std::cmp::Ordering::Less => (Instruction::BinopSub, from - to)
+        };
+        vec![self.stmt(PT::ModopNode{lookup: self.lookup_node(name), op, rhs: self.num(delta)})]
+    }
+
+    fn stmt<S: PT::Statement + 'static>(&self, stmt: S) -> PT::StatementNode {
+        Box::new(PT::PositionedNode{line: self.line, col: self.col, stmt: Box::new(stmt), attributes: Vec::new()})
+    }
+
+    fn let_(&self, name: &str, rhs: PT::ExpressionNode) -> PT::LetUnletNode {
+        PT::LetUnletNode{line: self.line, col: self.col, is_unlet: false, name: name.to_string(), rhs}
+    }
+
+    fn lookup_node(&self, name: &str) -> PT::LookupNode {
+        PT::LookupNode{line: self.line, col: self.col, name: name.to_string(), indices: Vec::new()}
+    }
+
+    fn lookup(&self, name: &str) -> PT::ExpressionNode {
+        Box::new(self.lookup_node(name))
+    }
+
+    fn index(&self, name: &str, index: PT::ExpressionNode) -> PT::ExpressionNode {
+        Box::new(PT::LookupNode{line: self.line, col: self.col, name: name.to_string(), indices: vec![index]})
+    }
+
+    fn len(&self, name: &str) -> PT::ExpressionNode {
+        Box::new(PT::UniopNode{line: self.line, col: self.col, expr: self.lookup(name), op: Instruction::UniopLen})
+    }
+
+    fn num(&self, value: usize) -> PT::ExpressionNode {
+        Box::new(PT::FractionNode{line: self.line, col: self.col, value: Fraction::from_integer(value.into())})
+    }
+
+    fn binop(&self, lhs: PT::ExpressionNode, op: Instruction, rhs: PT::ExpressionNode) -> PT::ExpressionNode {
+        Box::new(PT::BinopNode{lhs, rhs, op})
+    }
+}
@@ -0,0 +1,110 @@
+use std::cell::RefCell;
+use std::cmp;
+use std::fmt::Write;
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::interpreter::{Module, Session, Variable};
+
+
+// A deep copy of the variables held in registers at one point in execution //
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub registers: Vec<Option<Variable>>,
+    pub global_registers: Vec<Option<Variable>>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub path: String,
+    pub old: Option<String>,
+    pub new: Option<String>
+}
+
+
+fn copy_registers(registers: &[Option<Rc<RefCell<Variable>>>]) -> Vec<Option<Variable>> {
+    registers.iter().map(|r| r.as_ref().map(|var| var.borrow().deep_copy())).collect()
+}
+
+impl Snapshot {
+    pub fn new(
+        registers: &[Option<Rc<RefCell<Variable>>>],
+        global_registers: &[Option<Rc<RefCell<Variable>>>]
+    ) -> Snapshot {
+        Snapshot{
+            registers: copy_registers(registers),
+            global_registers: copy_registers(global_registers)
+        }
+    }
+
+    pub fn of(session: &Session) -> Snapshot {
+        Snapshot::new(&session.registers, &session.global_registers)
+    }
+}
+
+
+// Every register, global register or array element whose value differs between two snapshots.
+// Arrays are compared element by element, so a changed element is reported by its index //
+pub fn diff(before: &Snapshot, after: &Snapshot) -> Vec<Change> {
+    let mut changes = Vec::new();
+    for (prefix, old, new) in [
+        ("g", &before.global_registers, &after.global_registers),
+        ("r", &before.registers, &after.registers)
+    ] {
+        for idx in 0..cmp::max(old.len(), new.len()) {
+            let old = old.get(idx).and_then(|v| v.as_ref());
+            let new = new.get(idx).and_then(|v| v.as_ref());
+            diff_variable(format!("{}{}", prefix, idx), old, new, &mut changes);
+        }
+    }
+    changes
+}
+
+fn diff_variable(path: String, old: Option<&Variable>, new: Option<&Variable>, changes: &mut Vec<Change>) {
+    match (old, new) {
+        (Some(Variable::Array(old_items)), Some(Variable::Array(new_items))) => {
+            for idx in 0..cmp::max(old_items.len(), new_items.len()) {
+                let old = old_items.get(idx).map(|item| item.borrow());
+                let new = new_items.get(idx).map(|item| item.borrow());
+                diff_variable(format!("{}[{}]", path, idx), old.as_deref(), new.as_deref(), changes);
+            }
+        },
+        _ if old != new => changes.push(Change{
+            path,
+            old: old.map(|v| v.to_string()),
+            new: new.map(|v| v.to_string())
+        }),
+        _ => {}
+    }
+}
+
+// A change's path with its register replaced by the source variable held there, from the debug
+// symbols of main or the global function. Registers are reused, so a main register is named by
+// the last variable declared in it whose code overlaps `span`, the code that made the change //
+pub fn name_path(path: &str, module: &Module, span: Range<usize>) -> String {
+    let split = path.find('[').unwrap_or(path.len());
+    let (register, rest) = path.split_at(split);
+    let func_idx = match register.starts_with('g') {
+        true => module.global_func_idx,
+        false => module.main_idx.expect("No main function")
+    };
+    let register = register[1..].parse::<usize>().ok();
+    module.functions[func_idx].symbols.iter()
+        .filter(|symbol| register == Some(symbol.register))
+        .filter(|symbol| func_idx == module.global_func_idx || (symbol.fwd.start < span.end && span.start < symbol.fwd.end))
+        .max_by_key(|symbol| symbol.fwd.start)
+        .map_or(path.to_string(), |symbol| format!("{}{}", symbol.name, rest))
+}
+
+
+// One line per change, with the old value in red and the new in green when colour is on //
+pub fn format_changes(changes: &[Change], colour: bool) -> String {
+    let (red, green, reset) = if colour {("\x1b[31m", "\x1b[32m", "\x1b[0m")} else {("", "", "")};
+    let mut out = String::new();
+    for Change{path, old, new} in changes.iter() {
+        writeln!(out, "  {}: {}{}{} -> {}{}{}", path,
+                 red, old.as_deref().unwrap_or("<empty>"), reset,
+                 green, new.as_deref().unwrap_or("<empty>"), reset).unwrap();
+    }
+    out
+}
@@ -0,0 +1,637 @@
+use num_traits::sign::Signed;
+
+use crate::interpreter::{Instruction, Variable};
+use crate::parser;
+use crate::parsetree as PT;
+use crate::tokeniser::Tokeniser;
+use crate::syntaxtree::Type;
+use crate::typechecker::operator_symbol;
+
+
+// Whether printing the module and parsing the result gives back the same tree, as the minimiser,
+// the fuzzer and "--emit desugared" rely on. If not, describes the first difference //
+pub fn check_round_trip(module: &PT::Module) -> Result<(), String> {
+    let src = module_source(module);
+    let reparsed = parser::parse(Tokeniser::new(&src))
+        .map_err(|err| format!("The formatted source doesn't parse, {}:\n{}", err, src))?;
+    if reparsed == *module {
+        return Ok(());
+    }
+    let (before, after) = (module.structure(), reparsed.structure());
+    let idx = before.iter().zip(&after).position(|(a, b)| a != b).unwrap_or(before.len().min(after.len()));
+    let field = |fields: &[String]| fields.get(idx).map_or(String::from("<end>"), |field| field.trim().to_string());
+    Err(format!("The formatted source parses differently, {} where the original has {}:\n{}",
+                field(&after), field(&before), src))
+}
+
+// Writes parse trees back out as source, so the result of desugaring can be read and re-parsed //
+pub fn module_source(module: &PT::Module) -> String {
+    let mut w = SourceWriter{out: String::new(), indent: 0};
+    // The parser marks the global scope strict along with the functions of a "#pragma strict" file //
+    if module.global_func.is_strict {
+        w.text("#pragma strict\n");
+    }
+    for PT::Import{name, ..} in &module.imports {
+        w.start_line();
+        w.text(&format!("import {};\n", name));
+    }
+    w.stmts(&module.global_func.stmts);
+    for PT::MacroNode{name, params, stmts, ..} in &module.macros {
+        if !w.out.is_empty() {
+            w.text("\n");
+        }
+        w.text(&format!("macro {}({}) ", name, params.join(", ")));
+        w.block(stmts);
+        w.text("\n");
+    }
+    for function in &module.functions {
+        if !w.out.is_empty() {
+            w.text("\n");
+        }
+        w.function(function);
+    }
+    w.out
+}
+
+// One statement or expression, without indentation or a trailing newline //
+pub fn source<T: Source + ?Sized>(item: &T) -> String {
+    let mut w = SourceWriter{out: String::new(), indent: 0};
+    item.write_source(&mut w);
+    w.out.trim_end().to_string()
+}
+
+pub struct SourceWriter {
+    out: String,
+    indent: usize
+}
+
+// Statements write whole lines, starting with the indentation. Expressions write inline //
+pub trait Source {
+    fn write_source(&self, w: &mut SourceWriter);
+
+    // Whether the expression needs brackets when it's the operand of an operator //
+    fn is_compound(&self) -> bool {
+        false
+    }
+}
+
+impl SourceWriter {
+    fn text(&mut self, text: &str) {
+        self.out.push_str(text);
+    }
+
+    fn start_line(&mut self) {
+        self.out.push_str(&"    ".repeat(self.indent));
+    }
+
+    fn operand(&mut self, expr: &PT::ExpressionNode) {
+        if expr.is_compound() {
+            self.text("(");
+            expr.write_source(self);
+            self.text(")");
+        } else {
+            expr.write_source(self);
+        }
+    }
+
+    fn list<T: Source>(&mut self, items: &[T]) {
+        for (idx, item) in items.iter().enumerate() {
+            if idx > 0 {
+                self.text(", ");
+            }
+            item.write_source(self);
+        }
+    }
+
+    fn stmts(&mut self, stmts: &[PT::StatementNode]) {
+        for stmt in stmts {
+            stmt.write_source(self);
+        }
+    }
+
+    // The braces and the statements inside, leaving the line open after the closing brace //
+    fn block(&mut self, stmts: &[PT::StatementNode]) {
+        self.text("{\n");
+        self.indent += 1;
+        self.stmts(stmts);
+        self.indent -= 1;
+        self.start_line();
+        self.text("}");
+    }
+
+    fn attributes(&mut self, attributes: &[PT::Attribute]) {
+        for PT::Attribute{name, args, ..} in attributes {
+            self.start_line();
+            self.text(&format!("#[{}", name));
+            if !args.is_empty() {
+                let args: Vec<String> = args.iter().map(|arg| match arg {
+                    (name, Some(value)) => format!("{} = '{}'", name, value),
+                    (name, None) => name.clone()
+                }).collect();
+                self.text(&format!("({})", args.join(", ")));
+            }
+            self.text("]\n");
+        }
+    }
+
+    fn function(&mut self, function: &PT::FunctionNode) {
+        self.attributes(&function.attributes);
+        self.start_line();
+        self.text(&format!("{}fn {}", if function.is_pub {"pub "} else {""}, function.name));
+        let links: Vec<String> = function.owned_links.iter().cloned()
+            .chain(function.const_params.iter().map(|name| format!("const {}", name)))
+            .collect();
+        if !links.is_empty() {
+            self.text(&format!("<{}>", links.join(", ")));
+        }
+        self.text(&format!("({})({}) {{\n", params(&function.borrow_params), params(&function.steal_params)));
+        self.indent += 1;
+        self.stmts(&function.stmts);
+        for nested in &function.functions {
+            self.function(nested);
+        }
+        self.indent -= 1;
+        self.start_line();
+        self.text(&format!("}} ~{}({})\n", function.name, params(&function.return_params)));
+    }
+}
+
+fn params(params: &[PT::FunctionParam]) -> String {
+    let params: Vec<String> = params.iter().map(|PT::FunctionParam{name, is_ref, link, annotation, default}| {
+        let mut param = match (is_ref, link) {
+            (true, Some(link)) => format!("&{} {}", link, name),
+            (true, None) => format!("&{}", name),
+            (false, _) => name.clone()
+        };
+        if *annotation != Type::Unknown {
+            param.push_str(&format!(": {}", annotation));
+        }
+        if let Some(default) = default {
+            param.push_str(&format!(" = {}", literal(default)));
+        }
+        param
+    }).collect();
+    params.join(", ")
+}
+
+fn literal(value: &Variable) -> String {
+    match value {
+        Variable::Str(string) => format!("'{}'", string),
+        value => value.to_string()
+    }
+}
+
+
+impl Source for PT::ExpressionNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        (**self).write_source(w)
+    }
+
+    fn is_compound(&self) -> bool {
+        (**self).is_compound()
+    }
+}
+
+impl Source for PT::StatementNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        (**self).write_source(w)
+    }
+}
+
+impl Source for PT::FractionNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.text(&self.value.to_string())
+    }
+
+    fn is_compound(&self) -> bool {
+        !self.value.is_integer() || self.value.is_negative()
+    }
+}
+
+impl Source for PT::StringNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.text(&format!("'{}'", self.value))
+    }
+}
+
+impl Source for PT::HoleNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.text("???")
+    }
+}
+
+impl Source for PT::CounterNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.text(&format!("{}()", operator_symbol(&self.op)))
+    }
+}
+
+impl Source for PT::ArrayLiteralNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.text("[");
+        w.list(&self.items);
+        w.text("]");
+    }
+}
+
+impl Source for PT::ArrayRepeatNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.text("[");
+        self.item.write_source(w);
+        w.text(" repeat ");
+        self.dimensions.write_source(w);
+        w.text("]");
+    }
+}
+
+impl Source for PT::ComprehensionNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.text("[");
+        self.item.write_source(w);
+        w.text(&format!(" for {} in ", self.iter_var));
+        self.source.write_source(w);
+        if let Some(filter) = &self.filter {
+            w.text(" if ");
+            filter.write_source(w);
+        }
+        w.text("]");
+    }
+}
+
+impl Source for PT::CallExprNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.text(&format!("{}(", self.name));
+        w.list(&self.borrow_args);
+        w.text(")");
+    }
+}
+
+impl Source for PT::LookupNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.text(&self.name);
+        for index in &self.indices {
+            w.text("[");
+            index.write_source(w);
+            w.text("]");
+        }
+    }
+}
+
+impl Source for PT::BinopNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.operand(&self.lhs);
+        w.text(&format!(" {} ", operator_symbol(&self.op)));
+        w.operand(&self.rhs);
+    }
+
+    fn is_compound(&self) -> bool {
+        true
+    }
+}
+
+impl Source for PT::UniopNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        match self.op {
+            Instruction::UniopNeg | Instruction::UniopNot | Instruction::UniopLen => {
+                w.text(operator_symbol(&self.op));
+                w.operand(&self.expr);
+            },
+            _ => {
+                w.text(&format!("{}(", operator_symbol(&self.op)));
+                self.expr.write_source(w);
+                w.text(")");
+            }
+        }
+    }
+}
+
+
+impl Source for PT::PositionedNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.attributes(&self.attributes);
+        self.stmt.write_source(w);
+    }
+}
+
+impl Source for PT::PrintNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text(if self.newline {"println("} else {"print("});
+        w.list(&self.items);
+        w.text(");\n");
+    }
+}
+
+impl Source for PT::LetUnletNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text(&format!("{} {} ", self.name, if self.is_unlet {"~="} else {"="}));
+        self.rhs.write_source(w);
+        w.text(";\n");
+    }
+}
+
+impl Source for PT::RefUnrefNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text(&format!("{} {} &", self.name, if self.is_unref {"~="} else {"="}));
+        self.rhs.write_source(w);
+        if let Some((start, end)) = &self.range {
+            w.text("[");
+            start.write_source(w);
+            w.text(":");
+            end.write_source(w);
+            w.text("]");
+        }
+        w.text(";\n");
+    }
+}
+
+impl Source for PT::RelinkNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text(&format!("{} ~= &", self.name));
+        self.old.write_source(w);
+        w.text(" = &");
+        self.new.write_source(w);
+        w.text(";\n");
+    }
+}
+
+impl Source for PT::MoveNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text(&format!("{} <- {};\n", self.name, self.src));
+    }
+}
+
+impl Source for PT::ModopNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        self.lookup.write_source(w);
+        w.text(&format!(" {}= ", operator_symbol(&self.op)));
+        self.rhs.write_source(w);
+        w.text(";\n");
+    }
+}
+
+impl Source for PT::DestructureLetNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        let pattern: Vec<String> = self.pattern.iter()
+            .map(|(name, is_rest)| format!("{}{}", name, if *is_rest {"..."} else {""}))
+            .collect();
+        w.start_line();
+        w.text(&format!("[{}] {} {};\n", pattern.join(", "), if self.is_unlet {"~="} else {"="}, self.name));
+    }
+}
+
+impl Source for PT::PushPullNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text(&format!("{} {} ", self.name, if self.is_push {"=>"} else {"<="}));
+        self.lookup.write_source(w);
+        w.text(";\n");
+    }
+}
+
+impl Source for PT::IfNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text("if (");
+        self.fwd_expr.write_source(w);
+        w.text(") ");
+        w.block(&self.if_stmts);
+        if !self.else_stmts.is_empty() {
+            w.text(" else ");
+            w.block(&self.else_stmts);
+        }
+        w.text(" ~if (");
+        if !self.bkwd_elided {
+            self.bkwd_expr.write_source(w);
+        }
+        w.text(");\n");
+    }
+}
+
+impl Source for PT::WhileNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text("while (");
+        self.fwd_expr.write_source(w);
+        w.text(") ");
+        w.block(&self.stmts);
+        w.text(" ~while (");
+        if let Some(bkwd_expr) = &self.bkwd_expr {
+            bkwd_expr.write_source(w);
+        }
+        w.text(");\n");
+    }
+}
+
+impl Source for PT::ForNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        let keyword = if self.parallel {"parfor"} else {"for"};
+        match &self.index_var {
+            Some(index_var) => w.text(&format!("{} (({}, {}) in enumerate(", keyword, index_var, self.iter_var)),
+            None if self.reversed => w.text(&format!("{} ({} in reversed(", keyword, self.iter_var)),
+            None => w.text(&format!("{} ({} in ", keyword, self.iter_var))
+        }
+        self.iterator.write_source(w);
+        w.text(if self.index_var.is_some() || self.reversed {")) "} else {") "});
+        w.block(&self.stmts);
+        w.text(";\n");
+    }
+}
+
+impl Source for PT::ForRangeNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text(&format!("for ({} in ", self.iter_var));
+        self.start.write_source(w);
+        w.text("..");
+        self.end.write_source(w);
+        if let Some(step) = &self.step {
+            w.text(" step ");
+            step.write_source(w);
+        }
+        w.text(") ");
+        w.block(&self.stmts);
+        w.text(";\n");
+    }
+}
+
+impl Source for PT::ForGenNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text(&format!("for ({} in {}(", self.iter_var, self.name));
+        w.list(&self.borrow_args);
+        w.text(")) ");
+        w.block(&self.stmts);
+        w.text(";\n");
+    }
+}
+
+impl Source for PT::YieldNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text("yield ");
+        self.expr.write_source(w);
+        w.text(";\n");
+    }
+}
+
+impl Source for PT::DoYieldNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text("do ");
+        w.block(&self.do_stmts);
+        if !self.yield_stmts.is_empty() {
+            w.text(" yield ");
+            w.block(&self.yield_stmts);
+        }
+        w.text(" ~do;\n");
+    }
+}
+
+impl Source for PT::CatchNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text("catch (");
+        self.expr.write_source(w);
+        w.text(");\n");
+    }
+}
+
+impl Source for PT::TryNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text("try ");
+        w.block(&self.body);
+        w.text(" catch (");
+        self.cond.write_source(w);
+        w.text(") ");
+        w.block(&self.handler);
+        if !self.bkwd_elided {
+            w.text(" ~catch (");
+            self.bkwd_cond.write_source(w);
+            w.text(");");
+        }
+        w.text("\n");
+    }
+}
+
+impl Source for PT::RememberNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text("remember ");
+        w.block(&self.body);
+        w.text("\n");
+    }
+}
+
+impl Source for PT::MachineNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text(&format!("machine {} {{\n", self.name));
+        w.indent += 1;
+        for state in &self.states {
+            w.start_line();
+            w.text(&format!("state {} {{\n", state.name));
+            w.indent += 1;
+            w.stmts(&state.stmts);
+            for PT::Transition{cond, target, ..} in &state.transitions {
+                w.start_line();
+                w.text("on ");
+                cond.write_source(w);
+                w.text(&format!(" -> {};\n", target));
+            }
+            w.indent -= 1;
+            w.start_line();
+            w.text("}\n");
+        }
+        w.indent -= 1;
+        w.start_line();
+        w.text("}\n");
+    }
+}
+
+impl Source for PT::MacroCallNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text(&format!("{}!(", self.name));
+        w.list(&self.args);
+        w.text(");\n");
+    }
+}
+
+impl Source for PT::TodoNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text("todo;\n");
+    }
+}
+
+impl Source for PT::BacktraceNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text("backtrace();\n");
+    }
+}
+
+impl Source for PT::StaticAssertNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text("static_assert(");
+        self.expr.write_source(w);
+        w.text(&format!(", '{}');\n", self.message));
+    }
+}
+
+impl Source for PT::ExpectNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        w.text("expect ");
+        self.actual.write_source(w);
+        if let Some(expected) = &self.expected {
+            w.text(" == ");
+            expected.write_source(w);
+        }
+        w.text(";\n");
+    }
+}
+
+impl Source for PT::ConstEvalNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        let args: Vec<String> = self.args.iter().map(literal).collect();
+        w.text(&format!("const {} = eval {}({});\n", self.name, self.func_name, args.join(", ")));
+    }
+}
+
+impl Source for PT::CallNode {
+    fn write_source(&self, w: &mut SourceWriter) {
+        w.start_line();
+        if !self.bindings.is_empty() {
+            w.text(&format!("({}) = call ", params(&self.bindings)));
+        }
+        if !self.stolen_args.is_empty() {
+            w.text(&format!("{} => ", self.stolen_args.join(", ")));
+        }
+        w.text(&format!("{}{}", if self.is_uncall {"~"} else {""}, self.name));
+        if !self.const_args.is_empty() {
+            let const_args: Vec<String> = self.const_args.iter().map(|arg| match arg {
+                PT::ConstArg::Value(value) => literal(value),
+                PT::ConstArg::Name(name) => name.clone()
+            }).collect();
+            w.text(&format!("<{}>", const_args.join(", ")));
+        }
+        w.text("(");
+        w.list(&self.borrow_args);
+        w.text(")");
+        if self.bindings.is_empty() && !self.return_args.is_empty() {
+            w.text(&format!(" => {}", self.return_args.join(", ")));
+        }
+        w.text(";\n");
+    }
+}
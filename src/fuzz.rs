@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::formatter;
+use crate::minimise::panic_message;
+use crate::parser;
+use crate::tokeniser::Tokeniser;
+
+
+// Past this depth of rules, or this many tokens, the generator takes the shortest way out of every
+// choice so that each input stays small //
+const MAX_DEPTH: usize = 30;
+const MAX_TOKENS: usize = 300;
+
+const NAMES: [&str; 8] = ["x", "y", "n", "arr", "f", "g", "i", "main"];
+const NUMBERS: [&str; 6] = ["0", "1", "2", "7", "10", "3/4"];
+const STRINGS: [&str; 2] = ["'s'", "''"];
+
+
+// One production of the grammar, as printed by "grammar --ebnf" //
+#[derive(Debug, Clone)]
+enum Ebnf {
+    Literal(String),
+    Token(String),  // NAME, NUMBER, STRING or END
+    Rule(String),
+    Seq(Vec<Ebnf>),
+    Alt(Vec<Ebnf>),
+    Optional(Box<Ebnf>),
+    Repeat(Box<Ebnf>),
+    Except(Box<Ebnf>, Box<Ebnf>)
+}
+
+// Reads the EBNF of one production. "|" binds loosest, then ",", then "-" //
+struct EbnfReader {
+    items: Vec<String>,
+    pos: usize
+}
+
+impl EbnfReader {
+    fn new(text: &str) -> EbnfReader {
+        let mut items = Vec::new();
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => {
+                    let mut literal = String::from("\"");
+                    for c in chars.by_ref() {
+                        literal.push(c);
+                        if c == '"' {break}
+                    }
+                    items.push(literal);
+                },
+                c if c.is_alphanumeric() || c == '_' => {
+                    let mut name = String::from(c);
+                    while let Some(&c) = chars.peek().filter(|c| c.is_alphanumeric() || **c == '_') {
+                        name.push(c);
+                        chars.next();
+                    }
+                    items.push(name);
+                },
+                c if c.is_whitespace() => {},
+                c => items.push(c.to_string())
+            }
+        }
+        EbnfReader{items, pos: 0}
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.items.get(self.pos).map(|item| item.as_str())
+    }
+
+    fn expect(&mut self, item: &str) {
+        assert_eq!(self.peek(), Some(item), "Malformed grammar production");
+        self.pos += 1;
+    }
+
+    fn alternatives(&mut self) -> Ebnf {
+        let mut options = vec![self.sequence()];
+        while self.peek() == Some("|") {
+            self.pos += 1;
+            options.push(self.sequence());
+        }
+        if options.len() == 1 {options.pop().unwrap()} else {Ebnf::Alt(options)}
+    }
+
+    fn sequence(&mut self) -> Ebnf {
+        let mut items = vec![self.exception()];
+        while self.peek() == Some(",") {
+            self.pos += 1;
+            items.push(self.exception());
+        }
+        if items.len() == 1 {items.pop().unwrap()} else {Ebnf::Seq(items)}
+    }
+
+    fn exception(&mut self) -> Ebnf {
+        let item = self.primary();
+        if self.peek() == Some("-") {
+            self.pos += 1;
+            return Ebnf::Except(Box::new(item), Box::new(self.primary()));
+        }
+        item
+    }
+
+    fn primary(&mut self) -> Ebnf {
+        let item = self.peek().expect("Malformed grammar production").to_string();
+        self.pos += 1;
+        let group = |reader: &mut EbnfReader, close: &str| {
+            let inner = reader.alternatives();
+            reader.expect(close);
+            inner
+        };
+        match item.as_str() {
+            "[" => Ebnf::Optional(Box::new(group(self, "]"))),
+            "{" => Ebnf::Repeat(Box::new(group(self, "}"))),
+            "(" => group(self, ")"),
+            _ if item.starts_with('"') => Ebnf::Literal(item[1..item.len() - 1].to_string()),
+            "NAME" | "NUMBER" | "STRING" | "END" => Ebnf::Token(item),
+            _ => Ebnf::Rule(item)
+        }
+    }
+}
+
+
+// Generates programs from the grammar, some as they are and some with a token or two deleted,
+// repeated, swapped or inserted, and checks the parser never panics on them and that the ones it
+// accepts parse to the same tree once printed by the formatter. Runs are repeatable from the seed //
+pub struct Fuzzer {
+    rules: HashMap<String, Ebnf>,
+    shortest: HashMap<String, usize>,  // The fewest tokens each rule can match
+    literals: Vec<String>,
+    state: u64,
+    pub accepted: usize
+}
+
+impl Fuzzer {
+    pub fn new(seed: u64) -> Fuzzer {
+        let mut rules = HashMap::new();
+        for line in parser::grammar_ebnf().lines().filter(|line| !line.starts_with("(*")) {
+            let (rule, production) = line.split_once(" = ").expect("Malformed grammar line");
+            let production = production.strip_suffix(" ;").expect("Malformed grammar line");
+            rules.insert(rule.to_string(), EbnfReader::new(production).alternatives());
+        }
+
+        let mut literals = Vec::new();
+        for production in rules.values() {
+            collect_literals(production, &mut literals);
+        }
+        literals.sort();
+        literals.dedup();
+
+        // The shortest matches, grown until no rule finds a shorter one //
+        let mut shortest = HashMap::new();
+        loop {
+            let found: HashMap<String, usize> = rules.iter()
+                .map(|(rule, production)| (rule.clone(), shortest_len(production, &shortest)))
+                .collect();
+            if found == shortest {break}
+            shortest = found;
+        }
+
+        // Zero would leave the generator stuck on the same numbers //
+        Fuzzer{rules, shortest, literals, state: seed ^ 0x9e37_79b9_7f4a_7c15, accepted: 0}
+    }
+
+    // xorshift64*, as the crate has no dependency for randomness //
+    fn random(&mut self, below: usize) -> usize {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32) as usize % below.max(1)
+    }
+
+    fn pick<'s>(&mut self, choices: &[&'s str]) -> &'s str {
+        choices[self.random(choices.len())]
+    }
+
+    fn generate(&mut self, production: &Ebnf, depth: usize, out: &mut Vec<String>) {
+        let deep = depth > MAX_DEPTH || out.len() > MAX_TOKENS;
+        match production {
+            Ebnf::Literal(literal) => out.push(literal.clone()),
+            Ebnf::Token(token) => match token.as_str() {
+                "NAME" => out.push(self.pick(&NAMES).to_string()),
+                "NUMBER" => out.push(self.pick(&NUMBERS).to_string()),
+                "STRING" => out.push(self.pick(&STRINGS).to_string()),
+                _ => {}
+            },
+            Ebnf::Rule(rule) => {
+                let production = self.rules[rule].clone();
+                self.generate(&production, depth + 1, out);
+            },
+            Ebnf::Seq(items) => for item in items {
+                self.generate(item, depth, out);
+            },
+            Ebnf::Alt(options) => {
+                let option = match deep {
+                    true => options.iter().min_by_key(|option| shortest_len(option, &self.shortest)).unwrap(),
+                    false => &options[self.random(options.len())]
+                };
+                self.generate(option, depth, out);
+            },
+            Ebnf::Optional(inner) => if !deep && self.random(2) == 0 {
+                self.generate(inner, depth, out);
+            },
+            Ebnf::Repeat(inner) => if !deep {
+                for _ in 0..self.random(3) {
+                    self.generate(inner, depth, out);
+                }
+            },
+            // A name that comes out as one of the excluded words is swapped for one that isn't //
+            Ebnf::Except(inner, excluded) => {
+                let start = out.len();
+                self.generate(inner, depth, out);
+                let mut words = Vec::new();
+                collect_literals(excluded, &mut words);
+                if out.len() == start + 1 && words.contains(&out[start]) {
+                    out[start] = NAMES[0].to_string();
+                }
+            }
+        }
+    }
+
+    // A program from the module rule, nudged off the grammar half of the time //
+    pub fn next_input(&mut self) -> String {
+        let mut tokens = Vec::new();
+        let module = self.rules["module"].clone();
+        self.generate(&module, 0, &mut tokens);
+
+        if self.random(2) == 0 {
+            for _ in 0..=self.random(2) {
+                let pos = self.random(tokens.len());
+                match self.random(4) {
+                    0 if !tokens.is_empty() => {tokens.remove(pos);},
+                    1 if !tokens.is_empty() => tokens.insert(pos, tokens[pos].clone()),
+                    2 if pos + 1 < tokens.len() => tokens.swap(pos, pos + 1),
+                    _ => {
+                        let literal = self.random(self.literals.len());
+                        tokens.insert(pos, self.literals[literal].clone());
+                    }
+                }
+            }
+        }
+        tokens.join(" ")
+    }
+
+    // Why the parser or formatter mishandles the source, if it does //
+    pub fn check(&mut self, src: &str) -> Result<(), String> {
+        let parse = |src: &str| panic::catch_unwind(AssertUnwindSafe(|| parser::parse(Tokeniser::new(src))));
+        let module = match parse(src) {
+            Ok(Ok(module)) => module,
+            // A left-recursive rule that never stops growing fails the parse rather than looping //
+            Ok(Err(err)) if err.hint.as_deref().is_some_and(|hint| hint.starts_with("Internal parser error")) =>
+                return Err(err.to_string()),
+            Ok(Err(_)) => return Ok(()),
+            Err(payload) => return Err(format!("The parser panicked: {}", panic_message(payload)))
+        };
+        self.accepted += 1;
+
+        panic::catch_unwind(AssertUnwindSafe(|| formatter::check_round_trip(&module)))
+            .unwrap_or_else(|payload| Err(format!("Panicked printing and reparsing: {}", panic_message(payload))))
+    }
+}
+
+fn collect_literals(production: &Ebnf, literals: &mut Vec<String>) {
+    match production {
+        Ebnf::Literal(literal) => literals.push(literal.clone()),
+        Ebnf::Seq(items) | Ebnf::Alt(items) => for item in items {
+            collect_literals(item, literals);
+        },
+        Ebnf::Optional(inner) | Ebnf::Repeat(inner) | Ebnf::Except(inner, _) => collect_literals(inner, literals),
+        Ebnf::Token(_) | Ebnf::Rule(_) => {}
+    }
+}
+
+// The fewest tokens a production can match, given the fewest found so far for each rule //
+fn shortest_len(production: &Ebnf, shortest: &HashMap<String, usize>) -> usize {
+    match production {
+        Ebnf::Literal(_) => 1,
+        Ebnf::Token(token) => (token != "END") as usize,
+        Ebnf::Rule(rule) => shortest.get(rule).copied().unwrap_or(usize::MAX),
+        Ebnf::Seq(items) => items.iter().fold(0, |total, item| total.saturating_add(shortest_len(item, shortest))),
+        Ebnf::Alt(options) => options.iter().map(|option| shortest_len(option, shortest)).min().unwrap(),
+        Ebnf::Optional(_) | Ebnf::Repeat(_) => 0,
+        Ebnf::Except(inner, _) => shortest_len(inner, shortest)
+    }
+}
@@ -0,0 +1,34 @@
+use std::fmt;
+
+use crate::interpreter::{Instruction, Interpreter};
+
+
+// Callbacks for watching a program run, registered with Interpreter::set_hook. Each is given the
+// interpreter to inspect, e.g. with position() and depth(), and a watchdog can stop a run by
+// panicking just as a runtime error would. Instructions are seen in the direction they run, and a
+// parfor runs its iterations in sequence while a hook is registered //
+pub trait ExecutionHook {
+    fn before_instruction(&mut self, _interpreter: &Interpreter, _instruction: Instruction) {}
+
+    // The instruction pointer has already moved on, to the next instruction or the jump's target //
+    fn after_instruction(&mut self, _interpreter: &Interpreter, _instruction: Instruction) {}
+
+    // Once the callee's frame is entered, so not for remembered calls answered from the memo table //
+    fn on_call(&mut self, _interpreter: &Interpreter, _func_idx: usize, _forwards: bool) {}
+
+    // Caught is false when an unwinding finishes, turning execution back the way it was going //
+    fn on_reversal(&mut self, _interpreter: &Interpreter, _caught: bool) {}
+}
+
+// Interpreters print themselves when debugging, naming their hook but not its contents //
+impl fmt::Debug for dyn ExecutionHook + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ExecutionHook")
+    }
+}
+
+// What execute runs with when no hook is registered. Its callbacks compile away entirely, so
+// unhooked runs pay nothing for the hook points //
+pub struct NoHook;
+
+impl ExecutionHook for NoHook {}
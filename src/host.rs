@@ -0,0 +1,114 @@
+use std::any::Any;
+use std::fmt;
+
+use crate::interpreter::{Interpreter, Variable};
+
+
+// The body of a #[host] function, registered with Interpreter::register_host under the function's
+// name. Borrowed args are given as copies, and the values stolen or returned are moved across.
+// Uncalling runs the inverse, which must give back what call stole from what it returned. An Err
+// stops the program with a RuntimeError at the call //
+pub trait HostFunction {
+    fn call(&mut self, handles: &mut Handles, borrowed: &[Variable], stolen: Vec<Variable>) -> Result<Vec<Variable>, String>;
+    fn uncall(&mut self, handles: &mut Handles, borrowed: &[Variable], returned: Vec<Variable>) -> Result<Vec<Variable>, String>;
+}
+
+impl fmt::Debug for dyn HostFunction + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("HostFunction")
+    }
+}
+
+// The host objects programs hold handles to. A handle is only an id into this table, so programs
+// can pass it around but only host functions can reach what it refers to //
+#[derive(Debug, Default)]
+pub struct Handles {
+    objects: Vec<Option<Box<dyn Any>>>,
+    free: Vec<usize>
+}
+
+impl Handles {
+    pub fn insert(&mut self, object: Box<dyn Any>) -> Variable {
+        match self.free.pop() {
+            Some(id) => {
+                self.objects[id] = Some(object);
+                Variable::Handle(id)
+            },
+            None => {
+                self.objects.push(Some(object));
+                Variable::Handle(self.objects.len() - 1)
+            }
+        }
+    }
+
+    pub fn get<T: 'static>(&self, handle: &Variable) -> Option<&T> {
+        match handle {
+            Variable::Handle(id) => self.objects.get(*id)?.as_ref()?.downcast_ref(),
+            _ => None
+        }
+    }
+
+    pub fn get_mut<T: 'static>(&mut self, handle: &Variable) -> Option<&mut T> {
+        match handle {
+            Variable::Handle(id) => self.objects.get_mut(*id)?.as_mut()?.downcast_mut(),
+            _ => None
+        }
+    }
+
+    // Release the object, so its id can be reused by a later insert //
+    pub fn remove(&mut self, handle: Variable) -> Option<Box<dyn Any>> {
+        let id = match handle {
+            Variable::Handle(id) => id,
+            _ => return None
+        };
+        let object = self.objects.get_mut(id)?.take()?;
+        self.free.push(id);
+        Some(object)
+    }
+
+    pub fn live(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+}
+
+
+// A stack of values kept by the host, e.g. "#[host] fn stash_open()() {} ~stash_open(stash)" to
+// make one and "#[host] fn stash_put(stash)(value) {} ~stash_put()" to move a value into it.
+// Uncalling stash_put takes the last value back out, and uncalling stash_open releases the stash,
+// which must be empty by then. The monoxide binary registers these for every program it runs //
+struct StashOpen;
+struct StashPut;
+
+impl HostFunction for StashOpen {
+    fn call(&mut self, handles: &mut Handles, _borrowed: &[Variable], _stolen: Vec<Variable>) -> Result<Vec<Variable>, String> {
+        Ok(vec![handles.insert(Box::new(Vec::<Variable>::new()))])
+    }
+
+    fn uncall(&mut self, handles: &mut Handles, _borrowed: &[Variable], mut returned: Vec<Variable>) -> Result<Vec<Variable>, String> {
+        let stash = returned.pop().ok_or("Missing stash")?;
+        match handles.get::<Vec<Variable>>(&stash) {
+            Some(values) if values.is_empty() => {handles.remove(stash);},
+            Some(values) => return Err(format!("Can't release a stash still holding {} value(s)", values.len())),
+            None => return Err(String::from("Released something that isn't a stash"))
+        }
+        Ok(Vec::new())
+    }
+}
+
+impl HostFunction for StashPut {
+    fn call(&mut self, handles: &mut Handles, borrowed: &[Variable], stolen: Vec<Variable>) -> Result<Vec<Variable>, String> {
+        let values = handles.get_mut::<Vec<Variable>>(&borrowed[0]).ok_or("Put into something that isn't a stash")?;
+        values.extend(stolen);
+        Ok(Vec::new())
+    }
+
+    fn uncall(&mut self, handles: &mut Handles, borrowed: &[Variable], _returned: Vec<Variable>) -> Result<Vec<Variable>, String> {
+        let values = handles.get_mut::<Vec<Variable>>(&borrowed[0]).ok_or("Took from something that isn't a stash")?;
+        Ok(vec![values.pop().ok_or("Took from an empty stash")?])
+    }
+}
+
+pub fn register_builtins(interpreter: &mut Interpreter) {
+    interpreter.register_host("stash_open", Box::new(StashOpen));
+    interpreter.register_host("stash_put", Box::new(StashPut));
+}
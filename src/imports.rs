@@ -0,0 +1,108 @@
+use std::collections::HashSet;
+use std::fs;
+use std::mem;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::cache;
+use crate::cfg::Cfg;
+use crate::interpreter::Module;
+use crate::parser::{self, ParseError};
+use crate::parsetree as PT;
+use crate::syntaxchecker::SyntaxError;
+use crate::syntaxtree::FunctionPrototype;
+use crate::tokeniser::Tokeniser;
+
+
+// The standard library, written in Monoxide and compiled alongside the modules that import it //
+pub const STD: &str = include_str!("std.mx");
+
+
+// A module imported as a compiled library artifact rather than from source: its compiled functions,
+// and the prototypes of those it exports, named as in the library and numbered by their index in it //
+pub struct Library {
+    pub name: String,
+    pub module: Arc<Module>,
+    pub prototypes: Vec<(String, FunctionPrototype)>
+}
+
+enum Loaded {
+    Source(Box<PT::Module>),
+    Library(Library)
+}
+
+
+// Add the functions and macros of each imported module, renamed "module::function" so they can't clash with
+// the importer's own. Modules other than std are files found in the include paths, where
+// "import a.b;" loads "a/b.mx", and may import further modules themselves. Imported functions
+// are checked from source alongside the importer's, so calls into them are held to the same
+// prototypes, link groups included, as local calls. Only those declared "pub fn" can be called from
+// outside their module. A module with no source but a library artifact "a/b.rvc" is returned to be
+// linked instead, and calls into it are checked against the prototypes stored in it, as are those
+// into modules found in the build cache when the configuration caches modules //
+pub fn resolve_imports(module: &mut PT::Module, cfg: &Cfg) -> Result<Vec<Library>, SyntaxError> {
+    let mut libraries = Vec::new();
+    let mut imported = HashSet::new();
+    let mut pending = mem::take(&mut module.imports);
+    let mut idx = 0;
+    while let Some(PT::Import{line, col, name}) = pending.get(idx).cloned() {
+        idx += 1;
+        if !imported.insert(name.clone()) {continue}
+        log::debug!("Importing module {}", name);
+        let library = match name.as_str() {
+            "std" => parser::parse(Tokeniser::new(STD)).expect("Failed to parse the standard library"),
+            _ => match load(&name, cfg).map_err(|desc| SyntaxError{line, col, desc, note: None})? {
+                Loaded::Source(library) => *library,
+                Loaded::Library(library) => {
+                    libraries.push(library);
+                    continue;
+                }
+            }
+        };
+        if !library.global_func.stmts.is_empty() {
+            return Err(SyntaxError{line, col, desc: format!(
+                "Imported module \"{}\" has global statements, but may only define functions", name), note: None});
+        }
+        pending.extend(library.imports);
+        for mut function in library.functions {
+            function.name = format!("{}::{}", name, function.name);
+            function.module = Some(name.clone());
+            module.functions.push(function);
+        }
+        for mut macro_def in library.macros {
+            macro_def.name = format!("{}::{}", name, macro_def.name);
+            module.macros.push(macro_def);
+        }
+    }
+    Ok(libraries)
+}
+
+// The file of a module in the first include path that has one, preferring its source to a library
+// artifact //
+pub fn find_module(name: &str, cfg: &Cfg) -> Option<PathBuf> {
+    let file = name.replace('.', "/");
+    let find = |ext: &str| cfg.include_paths().iter()
+        .map(|dir| dir.join(format!("{}.{}", file, ext)))
+        .find(|path| path.is_file());
+    find("mx").or_else(|| find("rvc"))
+}
+
+fn load(name: &str, cfg: &Cfg) -> Result<Loaded, String> {
+    let file = name.replace('.', "/");
+    let path = find_module(name, cfg)
+        .ok_or_else(|| format!("Unknown module \"{}\", no {}.mx or {}.rvc in the include paths", name, file, file))?;
+    if path.extension().is_some_and(|ext| ext == "rvc") {
+        let contents = fs::read_to_string(&path).map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+        let (module, prototypes) = cache::library_from_str(&contents)
+            .ok_or_else(|| format!("{} isn't a library artifact this version of monoxide can link", path.display()))?;
+        return Ok(Loaded::Library(Library{name: name.to_string(), module: Arc::new(module), prototypes}));
+    }
+    let src = fs::read_to_string(&path).map_err(|err| format!("Can't read {}: {}", path.display(), err))?;
+    if cfg.caches_modules() {
+        if let Some(library) = cache::cached_module(name, &src, cfg) {
+            return Ok(Loaded::Library(library));
+        }
+    }
+    parser::parse(Tokeniser::new(&src)).map(|module| Loaded::Source(Box::new(module))).map_err(|ParseError{line, col, hint}| format!(
+        "ParseError in {} at line {}, column {}{}", path.display(), line, col, hint.map_or(String::new(), |hint| format!(": {}", hint))))
+}
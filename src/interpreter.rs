@@ -2,37 +2,64 @@
 extern crate num_rational;
 extern crate num_bigint;
 
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::cell::{RefCell, Ref};
+use std::fs;
+use std::path::Path;
+use std::cell::RefCell;
 use std::mem::replace;
 use num_traits::cast::ToPrimitive;
 use num_traits::identities::{Zero, One};
-use std::ops::Index;
+use std::ops::Range;
 use std::rc::Rc;
+use std::sync::{Arc, OnceLock};
+#[cfg(feature = "parallel")]
+use std::panic;
+#[cfg(feature = "parallel")]
+use std::thread;
+use std::time::Instant;
+
+use serde_json::{json, Value};
+
+use crate::convert::{array_of, ConversionError, FromReaver, IntoReaver};
+use crate::hook::{ExecutionHook, NoHook};
+use crate::host::{Handles, HostFunction};
+use crate::isa::Stream;
+use crate::options::EngineOptions;
+use crate::timeline::Timeline;
 
 
 pub type Fraction = num_rational::BigRational;
 
-fn fraction_to_f64(x: &Fraction) -> f64 {
+// A frame's variables by register, None where no variable is bound //
+pub type Registers = [Option<Rc<RefCell<Variable>>>];
+type Window = Vec<Option<Rc<RefCell<Variable>>>>;
+
+fn fraction_to_f64(x: &Fraction) -> Result<f64, String> {
     match (x.numer().to_f64(), x.denom().to_f64()) {
-        (Some(n), Some(d)) => n/d,
-        _ => panic!("Rationals exceed f64 precision")
+        (Some(n), Some(d)) => Ok(n/d),
+        _ => Err(String::from("Rationals exceed f64 precision"))
     }
 }
 
+// Floats are not exactly invertible, so the syntax checker only lets them into mono variables //
 #[derive(PartialEq, Clone)]
 pub enum Variable {
     Frac(Fraction),
+    Float(f64),
     Array(Vec<Rc<RefCell<Variable>>>),
-    Str(String)
+    Str(String),
+    Handle(usize)  // An id into the interpreter's table of host objects
 }
 
 impl fmt::Debug for Variable {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Variable::Frac(val) => write!(f, "{}", val),
+            Variable::Float(val) => write!(f, "{:?}", val),
             Variable::Array(vec) => write!(f, "Array({:#?})", vec),
-            Variable::Str(string) => write!(f, "{}", string)
+            Variable::Str(string) => write!(f, "{}", string),
+            Variable::Handle(id) => write!(f, "Handle({})", id)
         }
     }
 }
@@ -41,7 +68,9 @@ impl fmt::Display for Variable {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Variable::Frac(val) => write!(f, "{}", val),
+            Variable::Float(val) => write!(f, "{:?}", val),
             Variable::Str(string) => write!(f, "{}", string),
+            Variable::Handle(id) => write!(f, "<handle {}>", id),
             Variable::Array(vec) => {
                 let mut out = String::new();
                 if vec.len() > 0 {
@@ -58,34 +87,55 @@ impl fmt::Display for Variable {
 }
 
 impl Variable {
-    fn to_bool(&self) -> bool {
+    // As written in a program, so with strings quoted //
+    pub fn source(&self) -> String {
+        match self {
+            Variable::Str(string) => format!("'{}'", string),
+            _ => self.to_string()
+        }
+    }
+
+    pub fn to_bool(&self) -> Result<bool, String> {
         match self {
-            Variable::Frac(value) => !value.is_zero(),
-            Variable::Array(items) => items.len() > 0,
-            Variable::Str(string) => string.len() > 0
+            Variable::Frac(value) => Ok(!value.is_zero()),
+            Variable::Float(value) => Ok(*value != 0.0),
+            Variable::Array(items) => Ok(!items.is_empty()),
+            Variable::Str(string) => Ok(!string.is_empty()),
+            Variable::Handle(_) => Err(String::from("Handles can't be used as conditions"))
         }
     }
 
-    fn to_usize(&self) -> usize {
+    fn to_usize(&self) -> Result<usize, String> {
         match self {
             Variable::Frac(value) => {
-                value.to_integer().to_usize().unwrap()
+                value.to_integer().to_usize().ok_or_else(|| String::from("Index is not a non-negative integer"))
             },
-            _ => panic!("Index is not a number")
+            Variable::Float(_) => Err(String::from("Floats can't be used as indices")),
+            _ => Err(String::from("Index is not a number"))
         }
     }
 
-    fn get_array_length(&self) -> usize {
+    fn get_array_length(&self) -> Result<usize, String> {
         match self {
-            Variable::Array(items) => items.len(),
-            _ => panic!("Length operator (#) used on non-array")
+            Variable::Array(items) => Ok(items.len()),
+            _ => Err(String::from("Length operator (#) used on non-array"))
         }
     }
 
-    fn deep_copy(&self) -> Self {
+    fn item(&self, idx: usize) -> Result<&Rc<RefCell<Variable>>, String> {
+        match self {
+            Variable::Array(items) => items.get(idx).ok_or_else(
+                || format!("Index {} is out of bounds for an array of length {}", idx, items.len())),
+            _ => Err(String::from("Indexing is only supported by arrays"))
+        }
+    }
+
+    pub fn deep_copy(&self) -> Self {
         match self {
             Variable::Frac(value) => Variable::Frac(value.clone()),
+            Variable::Float(value) => Variable::Float(*value),
             Variable::Str(value) => Variable::Str(value.clone()),
+            Variable::Handle(id) => Variable::Handle(*id),
             Variable::Array(items) => {
                 Variable::Array(
                     items.iter().map(
@@ -95,16 +145,79 @@ impl Variable {
             }
         }
     }
+
+    fn holds_handle(&self) -> bool {
+        match self {
+            Variable::Handle(_) => true,
+            Variable::Array(items) => items.iter().any(|item| item.borrow().holds_handle()),
+            _ => false
+        }
+    }
 }
 
-impl Index<usize> for Variable {
-    type Output = Rc<RefCell<Variable>>;
+// A value in a compiled module's constant pool. Arrays hold their items directly rather than
+// through shared cells, so a module can be shared between threads each running its own
+// interpreter, and every load makes a fresh variable //
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Frac(Fraction),
+    Float(f64),
+    Array(Vec<Constant>),
+    Str(String),
+    Handle(usize)  // Only taken from running programs, e.g. for a parfor's shared registers
+}
+
+impl Constant {
+    pub fn from_variable(var: &Variable) -> Constant {
+        match var {
+            Variable::Frac(value) => Constant::Frac(value.clone()),
+            Variable::Float(value) => Constant::Float(*value),
+            Variable::Str(value) => Constant::Str(value.clone()),
+            Variable::Handle(id) => Constant::Handle(*id),
+            Variable::Array(items) => Constant::Array(items.iter().map(|item| Constant::from_variable(&item.borrow())).collect())
+        }
+    }
 
-    fn index(&self, idx: usize) -> &Self::Output {
+    pub fn to_variable(&self) -> Variable {
         match self {
-            Variable::Array(items) => &items[idx],
-            _ => panic!("Indexing is only supported by arrays")
+            Constant::Frac(value) => Variable::Frac(value.clone()),
+            Constant::Float(value) => Variable::Float(*value),
+            Constant::Str(value) => Variable::Str(value.clone()),
+            Constant::Handle(id) => Variable::Handle(*id),
+            Constant::Array(items) => Variable::Array(items.iter().map(|item| Rc::new(RefCell::new(item.to_variable()))).collect())
+        }
+    }
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_variable())
+    }
+}
+
+// Ranges are iterated without materialising an array of their items. Arrays are found afresh at
+// each step by following the index path from the variable that was looked up, so the loop sees
+// the array as it is now, even if the body has moved or resized it //
+#[derive(Debug, Clone)]
+enum IterSource {
+    Array{root: Rc<RefCell<Variable>>, path: Vec<usize>},
+    Range{start: Fraction, step: Fraction, len: usize}
+}
+
+impl IterSource {
+    fn array(root: &Rc<RefCell<Variable>>, path: &[usize]) -> Result<Rc<RefCell<Variable>>, String> {
+        let mut var = Rc::clone(root);
+        for &index in path {
+            let item = match &*var.borrow() {
+                Variable::Array(items) => match items.get(index) {
+                    Some(item) => Rc::clone(item),
+                    None => return Err(String::from("For loop iterator no longer exists"))
+                },
+                _ => return Err(String::from("For loop iterator no longer exists"))
+            };
+            var = item;
         }
+        Ok(var)
     }
 }
 
@@ -112,35 +225,67 @@ impl Index<usize> for Variable {
 struct IterState {
     pub idx: isize,
     pub register: usize,
-    pub var: Rc<RefCell<Variable>>
+    pub source: IterSource,
+    pub reversed: bool,
+    pub index_register: Option<usize>  // Receives each item's index, when enumerating
+}
+
+// A generator's frame between the values it yields, kept on the stack of the loop running it //
+#[derive(Debug)]
+struct Suspended {
+    func_idx: usize,
+    ip: usize,
+    forwards: bool,
+    registers: Vec<Option<Rc<RefCell<Variable>>>>,
+    stack: Vec<StackObject>  // What the generator had on the stack, e.g. the iterators of its own loops
+}
+
+// Where a running generator was resumed from: its frame depth, the height of the stack beneath
+// what it has on it, and the loop's variable and exit //
+#[derive(Debug, Clone, Copy)]
+struct Resumed {
+    depth: usize,
+    base: usize,
+    register: usize,
+    exit: usize
 }
 
 #[derive(Debug)]
 enum StackObject {
     Var(Rc<RefCell<Variable>>),
-    Iter(IterState)
+    Iter(IterState),
+    Generator(Suspended)
 }
 
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Instruction {
     LoadConst{idx: usize},
+    LoadString{idx: usize},
     LoadGlobalRegister{register: usize},
     LoadRegister{register: usize},
     StoreRegister{register: usize},
     StoreGlobalRegister{register: usize},
     FreeRegister{register: usize},
+    MoveRegister{from: usize, to: usize},
     Subscript{size: usize},
+    Slice,
+    CheckRef{register: usize},
+    CheckUnshared{register: usize},
     Store,
     Pull{register: usize},
     Push{register: usize},
+    PullFront{register: usize},
+    PushFront{register: usize},
     CreateInt{val: isize},
     BinopAdd, BinopSub, BinopMul, BinopDiv,
     BinopOr, BinopAnd, BinopXor, 
     BinopLeq, BinopGeq, BinopLess, BinopGreat,
     BinopEq, BinopNeq,
     BinopIDiv, BinopMod, BinopPow,
-    UniopNeg, UniopNot, UniopLen,
+    Shift{bits: isize},  // Multiplies by 2 ** bits, in place of a power of two constant
+    UniopNeg, UniopNot, UniopLen, UniopToFloat, UniopToFrac,
+    UniopSin, UniopCos, UniopExp, UniopLog, UniopSqrt,
     Reverse{idx: usize},
     Jump{ip: usize},
     JumpIfTrue{ip: usize},
@@ -152,20 +297,95 @@ pub enum Instruction {
     ArrayRepeat,
     Call{idx: usize},
     Uncall{idx: usize},
+    Remember{idx: usize},
     DuplicateRef,
     UniqueVar,
-    CreateIter{register: usize},
+    CreateIter{register: usize, reversed: bool, index_register: Option<usize>, depth: usize},
+    CreateRangeIter{register: usize},
     StepIter{ip: usize},
+    ParallelStepIter{ip: usize},  // Runs every iteration at once when first stepped, with the parallel feature
+    CreateGenerator{idx: usize},
+    Resume{register: usize, ip: usize},  // Jumps to ip once the generator finishes
+    Suspend,
+    Gosub{ip: usize},  // Runs an outlined backward block, which makes no calls and so never nests
+    Return,  // Ends an outlined block, carrying on after the Gosub that ran it
+    HostCall,  // The whole body of a #[host] function
     Print{count: isize},
     Quit,
     DebugPrint,
+    Marker{stmt: usize},
+    Hole,
+    Todo,
+    Backtrace,
+    Expect,  // Fails unless the condition under the description holds
+    ExpectEqual,  // Fails unless the two values under the description are equal, reporting both
+    Steps,
+    ClockMs,
 }
 
 
 #[derive(Debug)]
 pub struct Code {
-    pub fwd: Vec<Instruction>,
-    pub bkwd: Vec<Instruction>
+    pub fwd: Stream,
+    pub bkwd: Stream,
+    pub regions: Vec<Region>,
+    decoded: OnceLock<[Vec<Instruction>; 2]>  // Both streams unpacked, once first executed
+}
+
+impl Code {
+    pub fn new(fwd: Stream, bkwd: Stream, regions: Vec<Region>) -> Code {
+        Code{fwd, bkwd, regions, decoded: OnceLock::new()}
+    }
+
+    // The stream for one direction, unpacked on first use so that functions called from hot loops
+    // don't decode every instruction again each time. The packed streams mustn't change after //
+    pub fn decoded(&self, forwards: bool) -> &[Instruction] {
+        let [fwd, bkwd] = self.decoded.get_or_init(|| [self.fwd.iter().collect(), self.bkwd.iter().collect()]);
+        if forwards {fwd} else {bkwd}
+    }
+
+    // The line and column of the deepest statement containing an instruction //
+    pub fn statement_position(&self, forwards: bool, ip: usize) -> Option<(usize, usize)> {
+        self.regions.iter()
+            .filter(|region| region.range(forwards).contains(&ip))
+            .max_by_key(|region| region.depth)
+            .map(|region| (region.line, region.col))
+    }
+}
+
+// The instructions belonging to one source statement in each stream //
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub fwd: Range<usize>,
+    pub bkwd: Range<usize>,
+    pub is_mono: bool,
+    pub depth: usize,  // 0 for statements directly in the function body
+    pub line: usize,   // Where the statement starts, 0 if unknown
+    pub col: usize
+}
+
+impl Region {
+    pub fn range(&self, forwards: bool) -> &Range<usize> {
+        if forwards {&self.fwd} else {&self.bkwd}
+    }
+}
+
+
+// Debug information naming the variable held in a register over a range of each instruction
+// stream //
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub register: usize,
+    pub var_id: isize,
+    pub fwd: Range<usize>,
+    pub bkwd: Range<usize>
+}
+
+impl Symbol {
+    pub fn is_live(&self, forwards: bool, ip: usize) -> bool {
+        if forwards {self.fwd.contains(&ip)} else {self.bkwd.contains(&ip)}
+    }
 }
 
 
@@ -174,13 +394,275 @@ pub struct Interpreter<'a> {
     functions: &'a Vec<Function>,
     stack: Vec<StackObject>,
     scope_stack: Vec<Scope<'a>>,
+    resumed: Vec<Resumed>,  // The generators running, innermost last
 
     code: &'a Code,
     ip: usize,
     forwards: bool,
     registers: Vec<Option<Rc<RefCell<Variable>>>>,
     global_registers: Vec<Option<Rc<RefCell<Variable>>>>,
-    consts: &'a Vec<Variable>
+    consts: &'a [Constant],
+    strings: &'a [String],
+    func_idx: usize,
+    stop_at: Option<(usize, bool, usize)>,  // Frame depth, direction and instruction pointer
+    watching: bool,
+    breakpoints: HashSet<(usize, bool, usize)>,
+    at_breakpoint: bool,
+    output: Option<String>,
+    sandboxed: bool,
+    reversals: ReversalLog,
+    frame_pool: FramePool,
+    memo: MemoTable,
+    arithmetic: Arithmetic,
+    steps: u64,  // Instructions executed so far, read by steps()
+    started: Instant,  // Read by clock_ms()
+    call_counts: Option<HashMap<(usize, usize, usize), isize>>,  // Calls not yet undone, by caller and position
+    costs: Option<Vec<CostTotals>>,  // Indexed by function, when reporting costs
+    timeline: Option<Timeline>,
+    hook: Option<Rc<RefCell<dyn ExecutionHook + 'a>>>,
+    host_functions: HashMap<String, Box<dyn HostFunction + 'a>>,
+    handles: Handles,
+    options: EngineOptions,  // The step budget, memory cap and seed are read while running
+    frame_values: usize,  // Registers held by the frames on the call stack, for the memory cap
+    subroutine_return: Option<usize>  // Where to carry on after the subroutine running, if one is
+}
+
+
+// How the arithmetic instructions treat numbers. Exact keeps unlimited-precision fractions, while
+// the fixed-width modes work on 64-bit integers, either failing or wrapping around on overflow //
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arithmetic {
+    Exact,
+    Checked,
+    Wrapping
+}
+
+impl Arithmetic {
+    pub fn from_name(name: &str) -> Option<Arithmetic> {
+        match name {
+            "exact" => Some(Arithmetic::Exact),
+            "checked" => Some(Arithmetic::Checked),
+            "wrapping" => Some(Arithmetic::Wrapping),
+            _ => None
+        }
+    }
+}
+
+fn to_fixed_width(x: &Fraction) -> Result<i64, String> {
+    if !x.is_integer() {
+        return Err(format!("Fixed-width arithmetic needs integer operands, not {}", x));
+    }
+    x.to_integer().to_i64().ok_or_else(|| format!("{} does not fit in 64 bits", x))
+}
+
+fn from_fixed_width(x: i64) -> Fraction {
+    Fraction::from_integer(num_bigint::BigInt::from(x))
+}
+
+// Fixed-width "/" fails unless the quotient is whole, as truncating it would lose what's needed to
+// multiply back to the dividend when the division is undone //
+fn check_exact_div(left: i64, right: i64) -> Result<(), String> {
+    match left.wrapping_rem(right) {
+        0 => Ok(()),
+        _ => Err(format!("Inexact division {} / {} in fixed-width arithmetic, which couldn't be undone", left, right))
+    }
+}
+
+pub fn trailing_zeros(x: &num_bigint::BigInt) -> usize {
+    let (_, bytes) = x.to_bytes_le();
+    bytes.iter().position(|&byte| byte != 0).map_or(0, |idx| idx * 8 + bytes[idx].trailing_zeros() as usize)
+}
+
+// x * 2 ** bits, cancelling factors of two from the other side first so the result is still in
+// lowest terms without taking a gcd //
+fn shift_fraction(x: &Fraction, bits: isize) -> Fraction {
+    if x.is_zero() {return x.clone()}
+    let (mut numer, mut denom) = (x.numer().clone(), x.denom().clone());
+    let (grow, shrink) = if bits < 0 {(&mut denom, &mut numer)} else {(&mut numer, &mut denom)};
+    let cancelled = trailing_zeros(shrink).min(bits.unsigned_abs());
+    *shrink = &*shrink >> cancelled;
+    *grow = &*grow << (bits.unsigned_abs() - cancelled);
+    Fraction::new_raw(numer, denom)
+}
+
+// Kept out of line, so checking the budget adds little to each step //
+#[cold]
+#[inline(never)]
+fn exceeded_step_budget(budget: u64) -> String {
+    format!("Exceeded the step budget of {} instructions", budget)
+}
+
+// A function specialised to the constants its calls pass computes with those, whatever it's given //
+fn check_specialised(function: &Function, args: &[Variable]) -> Result<(), String> {
+    for (idx, name, value) in function.specialised.iter() {
+        if let Some(arg) = args.get(*idx).filter(|arg| arg.source() != *value) {
+            return Err(format!(
+                "\"{}\" was specialised to {} = {}, so can't be given {}. Mark it #[no_specialise] to enter it with other values",
+                function.name, name, value, arg.source()));
+        }
+    }
+    Ok(())
+}
+
+// Whether two arrays are ranges of the same elements //
+fn shares_elements(lhs: &Variable, rhs: &Variable) -> bool {
+    match (lhs, rhs) {
+        (Variable::Array(left), Variable::Array(right)) =>
+            left.len() == right.len() && left.iter().zip(right.iter()).all(|(l, r)| Rc::ptr_eq(l, r)),
+        _ => false
+    }
+}
+
+// Whether anything besides its one owner references a variable or any element inside it //
+fn is_shared(var: &Rc<RefCell<Variable>>) -> bool {
+    Rc::strong_count(var) > 1 || match &*var.borrow() {
+        Variable::Array(items) => items.iter().any(is_shared),
+        _ => false
+    }
+}
+
+// Floats equal fractions of the same value //
+fn numeric_eq(lhs: &Variable, rhs: &Variable) -> Result<bool, String> {
+    match float_operands(lhs, rhs)? {
+        Some((left, right)) => Ok(left == right),
+        None => Ok(lhs == rhs)
+    }
+}
+
+// Mixing a float with a fraction gives a float, which fails if the fraction is beyond f64 //
+fn float_operands(lhs: &Variable, rhs: &Variable) -> Result<Option<(f64, f64)>, String> {
+    match (lhs, rhs) {
+        (Variable::Float(left), Variable::Float(right)) => Ok(Some((*left, *right))),
+        (Variable::Float(left), Variable::Frac(right)) => Ok(Some((*left, fraction_to_f64(right)?))),
+        (Variable::Frac(left), Variable::Float(right)) => Ok(Some((fraction_to_f64(left)?, *right))),
+        _ => Ok(None)
+    }
+}
+
+
+// A failure during execution, with the instruction that raised it. Line and column come from the
+// deepest statement region containing the instruction, and are 0 when none does //
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RuntimeError {
+    pub func_idx: usize,
+    pub func_name: String,
+    pub forwards: bool,
+    pub ip: usize,
+    pub line: usize,
+    pub col: usize,
+    pub desc: String
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let direction = if self.forwards {"fwd"} else {"bkwd"};
+        match self.line {
+            0 => write!(f, "RuntimeError in {} ({} ip {}): {}", self.func_name, direction, self.ip, self.desc),
+            _ => write!(f, "RuntimeError at line {}, column {} in {} ({} ip {}): {}",
+                        self.line, self.col, self.func_name, direction, self.ip, self.desc)
+        }
+    }
+}
+
+
+// A reversal triggered by a catch, and how many instructions it unwound before execution turned
+// again or left the function //
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Reversal {
+    pub func_idx: usize,
+    pub forwards: bool,  // The direction of the stream holding the catch
+    pub ip: usize,
+    pub line: usize,  // Of the catching statement, 0 if unknown
+    pub col: usize,
+    pub unwound: usize
+}
+
+#[derive(Debug, Default)]
+struct ReversalLog {
+    reversals: Vec<Reversal>,
+    unwinding: Vec<(usize, usize)>,  // Index of each reversal in progress, and its frame depth
+    notify: bool  // Report each finished reversal on stderr
+}
+
+
+// The instruction costs charged to one function, split by the direction it was running in. Code run
+// backwards while a catch unwinds is counted as reversal overhead rather than backward cost //
+#[derive(Debug, Clone, Copy, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CostTotals {
+    pub forward: u64,
+    pub backward: u64,
+    pub reversal: u64
+}
+
+impl CostTotals {
+    fn add(&mut self, other: &CostTotals) {
+        self.forward += other.forward;
+        self.backward += other.backward;
+        self.reversal += other.reversal;
+    }
+}
+
+
+// Results of the pure calls made in remember blocks, keyed by function and arguments, with how
+// many uses of each haven't been undone yet //
+#[derive(Debug, Default)]
+struct MemoTable {
+    entries: HashMap<(usize, String), (Variable, usize)>,
+    pending: Vec<((usize, String), usize)>  // Misses being computed, with the frame depth of the caller
+}
+
+// Register buffers of returned frames, reused by later calls instead of allocating afresh. Each
+// function keeps the windows its own frames used, emptied but still sized for it, so alternating
+// calls and uncalls of a function take the same window back as it is //
+#[derive(Debug, Default)]
+struct FramePool {
+    windows: Vec<Vec<Window>>,  // By function index
+    stats: FrameStats
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrameStats {
+    pub allocated: usize,
+    pub reused: usize
+}
+
+impl FramePool {
+    fn take(&mut self, func_idx: usize, num_registers: usize) -> Window {
+        match self.windows.get_mut(func_idx).and_then(Vec::pop) {
+            Some(mut registers) => {
+                self.stats.reused += 1;
+                registers.resize(num_registers, None);
+                registers
+            },
+            None => {
+                self.stats.allocated += 1;
+                vec![None; num_registers]
+            }
+        }
+    }
+
+    // Emptied straight away, since a variable still held here would look aliased //
+    fn give(&mut self, func_idx: usize, mut registers: Window) {
+        registers.fill(None);
+        if self.windows.len() <= func_idx {
+            self.windows.resize_with(func_idx + 1, Vec::new);
+        }
+        self.windows[func_idx].push(registers);
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Status {
+    Finished,
+    Paused,  // After a state change while watching
+    Breakpoint,  // Before the instruction at a breakpoint
+    Stopped  // On reaching the stop position
 }
 
 
@@ -190,15 +672,77 @@ pub struct Scope<'a> {
     ip: usize,
     forwards: bool,
     registers: Vec<Option<Rc<RefCell<Variable>>>>,
-    consts: &'a Vec<Variable>
+    consts: &'a [Constant],
+    func_idx: usize
 }
 
 
 #[derive(Debug)]
 pub struct Function {
+    pub name: String,
     pub code: Code,
-    pub consts: Vec<Variable>,
-    pub num_registers: usize
+    pub consts: Arc<Vec<Constant>>,  // The module's shared pool, once compiled
+    pub num_registers: usize,
+    pub symbols: Vec<Symbol>,  // Empty unless compiled with debug symbols
+    pub num_borrows: usize,
+    pub num_steals: usize,
+    pub num_returns: usize,
+    pub is_pure: bool,  // Without side effects, so may be called from expressions
+    pub effects: Effects,
+    pub garbage: Vec<Garbage>,  // Calls leaving values the caller never uses, for --garbage-report
+    pub specialised: Vec<(usize, String, String)>  // Borrow params every call passes the same constant, with their indices
+}
+
+
+// A call whose caller never uses some of the values it returns, so holds them until the call is
+// undone. Found by the syntax checker, and counted at runtime for --garbage-report //
+#[derive(Debug, Clone, PartialEq)]
+pub struct Garbage {
+    pub line: usize,
+    pub col: usize,
+    pub callee: String,
+    pub names: Vec<String>,
+    pub last_use: Option<(usize, usize)>  // The last statement using the call's other results
+}
+
+
+// What a function does besides compute what it returns, inferred by the syntax checker. Printing,
+// reading input and touching globals are also done by whatever calls a function doing them //
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Effects {
+    pub prints: bool,
+    pub reads_input: bool,  // The step counter or clock, or a #[host] function
+    pub touches_globals: bool,
+    pub steals: bool,
+    pub is_mono: bool  // Only runs forwards
+}
+
+impl Effects {
+    // Whether calls can run in any order, as in the iterations of a parfor //
+    pub fn is_unordered(&self) -> bool {
+        !self.prints && !self.reads_input && !self.touches_globals
+    }
+
+    pub fn is_reversible_pure(&self) -> bool {
+        self.is_unordered() && !self.steals && !self.is_mono
+    }
+
+    // Take on the effects a callee passes to its callers, returning whether any were new //
+    pub fn inherit(&mut self, callee: &Effects) -> bool {
+        let before = *self;
+        self.prints |= callee.prints;
+        self.reads_input |= callee.reads_input;
+        self.touches_globals |= callee.touches_globals;
+        *self != before
+    }
+}
+
+
+// Registers kept alive between incremental runs of main, as used by the REPL //
+#[derive(Debug, Default)]
+pub struct Session {
+    pub registers: Vec<Option<Rc<RefCell<Variable>>>>,
+    pub global_registers: Vec<Option<Rc<RefCell<Variable>>>>
 }
 
 
@@ -206,259 +750,1030 @@ pub struct Function {
 pub struct Module {
     pub main_idx: Option<usize>,
     pub global_func_idx: usize,
-    pub functions: Vec<Function>
+    pub functions: Vec<Function>,
+    pub consts: Arc<Vec<Constant>>,  // Shared by every function
+    pub strings: Vec<String>  // Every string literal, each stored once
 }
 
+// A compiled module is immutable, so embedders can put it in an Arc and run many interpreters
+// over it on different threads, each with its own registers and stacks. Nothing reachable from a
+// module may hold an Rc or a cell that isn't thread safe //
+const _: () = {
+    const fn shared<T: Send + Sync>() {}
+    shared::<Module>();
+};
+
 
 macro_rules! binop_method {
-    ($name:ident, $op:tt) => {
-        fn $name (&mut self) {
-            let rhs = self.pop_var();
-            let lhs = self.pop_var();
+    ($name:ident, $op:tt, $checked:ident, $wrapping:ident) => {
+        fn $name (&mut self) -> Result<(), RuntimeError> {
+            let rhs = self.pop_var()?;
+            let lhs = self.pop_var()?;
+            if let Some((left, right)) = float_operands(&lhs.borrow(), &rhs.borrow()).map_err(|desc| self.error(desc))? {
+                self.stack.push(StackObject::Var(Rc::new(RefCell::new(Variable::Float(left $op right)))));
+                return Ok(());
+            }
+            let fixed_width = |left: &Fraction, right: &Fraction| -> Result<(i64, i64), String> {
+                let (left, right) = (to_fixed_width(left)?, to_fixed_width(right)?);
+                if stringify!($op) == "/" {
+                    check_exact_div(left, right)?;
+                }
+                Ok((left, right))
+            };
             let result = match (&*lhs.borrow(), &*rhs.borrow()) {
-                (Variable::Frac(left), Variable::Frac(right)) => {
-                    Variable::Frac(left $op right)
-                },
-                (Variable::Array(_), Variable::Array(_)) => {
-                    unimplemented!();
+                (Variable::Frac(_), Variable::Frac(right))
+                    if right.is_zero() && matches!(stringify!($op), "/" | "%") => Err(String::from("Division by zero")),
+                (Variable::Frac(left), Variable::Frac(right)) => match self.arithmetic {
+                    Arithmetic::Exact => Ok(left $op right),
+                    Arithmetic::Checked => fixed_width(left, right).and_then(|(left, right)| match left.$checked(right) {
+                        Some(value) => Ok(from_fixed_width(value)),
+                        None => Err(format!("Integer overflow in binop \"{}\"", stringify!($op)))
+                    }),
+                    Arithmetic::Wrapping => {
+                        fixed_width(left, right).map(|(left, right)| from_fixed_width(left.$wrapping(right)))
+                    }
                 },
-                _ => panic!("Applying binop \"{}\" to incompatible types", stringify!($op))
+                _ => Err(format!("Applying binop \"{}\" to incompatible types", stringify!($op)))
             };
-            self.stack.push(StackObject::Var(Rc::new(RefCell::new(result))));
+            let result = result.map_err(|desc| self.error(desc))?;
+            self.stack.push(StackObject::Var(Rc::new(RefCell::new(Variable::Frac(result)))));
+            Ok(())
         }
     };
 }
 
 macro_rules! bincomp_method {
     ($name:ident, $op:tt) => {
-        fn $name (&mut self) {
-            let rhs = self.pop_var();
-            let lhs = self.pop_var();
+        fn $name (&mut self) -> Result<(), RuntimeError> {
+            let rhs = self.pop_var()?;
+            let lhs = self.pop_var()?;
             let result = match (&*lhs.borrow(), &*rhs.borrow()) {
-                (Variable::Frac(left), Variable::Frac(right)) => {
-                    if left $op right {Variable::Frac(Fraction::one())}
-                    else              {Variable::Frac(Fraction::zero())}
-                },
-                _ => panic!("Applying binop \"{}\" to incompatible types", stringify!($op))
+                (Variable::Frac(left), Variable::Frac(right)) => left $op right,
+                (left, right) => match float_operands(left, right).map_err(|desc| self.error(desc))? {
+                    Some((left, right)) => left $op right,
+                    None => return Err(self.error(format!("Applying binop \"{}\" to incompatible types", stringify!($op))))
+                }
             };
-            self.stack.push(StackObject::Var(Rc::new(RefCell::new(result))));
+            self.create_int(if result {1} else {0});
+            Ok(())
         }
     };
 }
 
 impl<'a> Interpreter<'a> {
 
-    pub fn run(module: &Module) {
-        let main_idx = module.main_idx.expect("No main function");
-        let global_func = module.functions.get(module.global_func_idx).unwrap();
-        let mut interpreter = Interpreter {
+    // An interpreter positioned at the start of the global scope //
+    pub fn new(module: &'a Module) -> Interpreter<'a> {
+        let global_func = module.functions.get(module.global_func_idx).expect("Module has no global function");
+        Interpreter {
             functions: &module.functions,
             stack: Vec::new(),
             scope_stack: Vec::new(),
+            resumed: Vec::new(),
             code: &global_func.code,
             ip: 0,
             forwards: true,
             registers: Vec::new(),
             global_registers: vec![None; global_func.num_registers],
-            consts: &global_func.consts
-        };
-        interpreter.execute();  // Execute the global scope
-        interpreter.call(main_idx, true);  // Initialise call to main
-        interpreter.execute();
+            consts: &global_func.consts,
+            strings: &module.strings,
+            func_idx: module.global_func_idx,
+            stop_at: None,
+            watching: false,
+            breakpoints: HashSet::new(),
+            at_breakpoint: false,
+            output: None,
+            sandboxed: false,
+            reversals: ReversalLog::default(),
+            frame_pool: FramePool::default(),
+            memo: MemoTable::default(),
+            arithmetic: Arithmetic::Exact,
+            steps: 0,
+            started: Instant::now(),
+            call_counts: None,
+            costs: None,
+            timeline: None,
+            hook: None,
+            host_functions: HashMap::new(),
+            handles: Handles::default(),
+            options: EngineOptions::default(),
+            frame_values: 0,
+            subroutine_return: None
+        }
     }
 
-    pub fn execute(&mut self) -> () {
-
-        'refresh_instructions: loop{
-
-            let instructions = if self.forwards {&self.code.fwd} 
-                               else             {&self.code.bkwd};
+    pub fn run(module: &Module) {
+        Interpreter::new(module).run_to_completion(module);
+    }
 
-            loop {
+    // Run with side effects disabled. Programs that passed the sandboxed syntax check never
+    // attempt one, so this guards against code compiled without it //
+    pub fn run_sandboxed(module: &Module, options: &EngineOptions) -> Result<(), RuntimeError> {
+        let mut interpreter = Interpreter::new(module);
+        interpreter.set_options(options);
+        interpreter.set_sandboxed(true);
+        interpreter.try_run_to_completion(module)
+    }
 
-                let instruction = match instructions.get(self.ip) {
-                    Some(inst) => inst,
-                    None => {
-                        if self.scope_stack.is_empty() { 
-                            break 'refresh_instructions;
-                        } else {
-                            self.end_call(); 
-                            continue 'refresh_instructions;
-                        };
-                    }
-                };
+    pub fn set_arithmetic(&mut self, arithmetic: Arithmetic) {
+        self.arithmetic = arithmetic;
+    }
 
-                // println!("{} IP: {}, {:?}", if self.forwards {"FWD"} else {"BKWD"}, self.ip, instruction);
+    pub fn set_options(&mut self, options: &EngineOptions) {
+        self.options = *options;
+    }
 
-                match instruction {
-                    Instruction::LoadConst{idx} => self.load_const(*idx),
-                    Instruction::LoadRegister{register} => self.load_register(*register),
-                    Instruction::LoadGlobalRegister{register} => self.load_global_register(*register),
-                    Instruction::StoreRegister{register} => self.store_register(*register),
-                    Instruction::StoreGlobalRegister{register} => self.store_global_register(*register),
-                    Instruction::FreeRegister{register} => self.free_register(*register),
-                    Instruction::Store => self.store(),
-                    Instruction::Subscript{size} => self.subscript(*size),
-                    Instruction::DuplicateRef => self.duplicate_ref(),
-                    Instruction::UniqueVar => self.copy_var(),
-                    Instruction::CreateInt{val} => self.create_int(*val),
-                    Instruction::BinopAdd => self.binop_add(),
-                    Instruction::BinopSub => self.binop_sub(),
-                    Instruction::BinopMul => self.binop_mul(),
-                    Instruction::BinopDiv => self.binop_div(),
-                    Instruction::BinopMod => self.binop_mod(),
-                    Instruction::BinopIDiv => self.binop_idiv(),
-                    Instruction::BinopPow => self.binop_pow(),
-                    Instruction::BinopLess => self.binop_less(),
-                    Instruction::BinopLeq => self.binop_leq(),
-                    Instruction::BinopGreat => self.binop_great(),
-                    Instruction::BinopGeq => self.binop_geq(),
-                    Instruction::BinopEq => self.binop_eq(),
-                    Instruction::BinopNeq => self.binop_neq(),
-                    Instruction::BinopXor => self.binop_xor(),
-                    Instruction::UniopNeg => self.uniop_neg(),
-                    Instruction::UniopNot => self.uniop_not(),
-                    Instruction::UniopLen => self.uniop_len(),
-                    Instruction::ArrayLiteral{size} => self.array_literal(*size),
-                    Instruction::ArrayRepeat => self.array_repeat(),
-                    Instruction::Pull{register} => self.pull(*register),
-                    Instruction::Push{register} => self.push(*register),
-                    Instruction::Print{count} => self.print(*count),
-                    Instruction::CreateIter{register} => self.create_iter(*register),
-                    Instruction::StepIter{ip} => {self.step_iter(*ip); continue 'refresh_instructions},
-                    
-                    Instruction::Jump{ip} => {self.jump(*ip); continue 'refresh_instructions},
-                    Instruction::JumpIfTrue{ip} => {self.jump_if_true(*ip); continue 'refresh_instructions},
-                    Instruction::JumpIfFalse{ip} => {self.jump_if_false(*ip); continue 'refresh_instructions},
-                    Instruction::Call{idx} => {self.call(*idx, true); continue 'refresh_instructions},
-                    Instruction::Uncall{idx} => {self.call(*idx, false); continue 'refresh_instructions},
-                    Instruction::Reverse{idx} => {self.reverse(*idx); continue 'refresh_instructions;}
-                    Instruction::Quit => break 'refresh_instructions,
-                    Instruction::DebugPrint => self.debug_print(),
+    pub fn seed(&self) -> u64 {
+        self.options.seed
+    }
 
-                    
-                    Instruction::BinopAnd => unimplemented!("BinopAnd"),
-                    Instruction::BinopOr => unimplemented!("BinopOr"),
-                    Instruction::RelativeJump{delta: _} => unimplemented!("RelativeJump"),
-                    Instruction::RelativeJumpIfTrue{delta: _} => unimplemented!("RelativeJumpIfTrue"),
-                    Instruction::RelativeJumpIfFalse{delta: _} => unimplemented!("RelativeJumpIfFalse")
-                }
-                
-                self.ip += 1;
-            }
+    // The memory cap bounds the values in any one array, and in the registers of the frames on the
+    // call stack, which between them are what a runaway program grows //
+    fn check_memory(&self, values: usize, holder: &str) -> Result<(), RuntimeError> {
+        match self.options.memory_cap {
+            Some(cap) if values > cap => Err(self.error(format!(
+                "{} would hold {} values, over the memory cap of {}", holder, values, cap))),
+            _ => Ok(())
         }
     }
 
-    pub fn call(&mut self, func_idx: usize, forwards: bool) {
-        let func: &'a Function = self.functions.get(func_idx).expect("Call to undefined function");
-        self.scope_stack.push(
-            Scope{
-                code      : replace(&mut self.code     , &func.code),
-                consts    : replace(&mut self.consts   , &func.consts),
-                registers : replace(&mut self.registers, vec![None; func.num_registers]),
-                ip        : replace(&mut self.ip       , 0),
-                forwards  : replace(&mut self.forwards , forwards)
-            }
-        );
+    pub fn set_sandboxed(&mut self, sandboxed: bool) {
+        self.sandboxed = sandboxed;
     }
 
-    pub fn end_call(&mut self) {
-        let scope = self.scope_stack.pop().unwrap();
-        self.code = scope.code;
-        self.consts = scope.consts;
-        self.registers = scope.registers;
-        self.ip = scope.ip + 1;
-        self.forwards = scope.forwards;
+    fn check_sandbox(&self, action: &str) -> Result<(), RuntimeError> {
+        match self.sandboxed {
+            true => Err(self.error(format!("{} is not allowed in sandboxed code", action))),
+            false => Ok(())
+        }
     }
 
-    #[inline]
-    fn jump(&mut self, ip: usize) {
-        self.ip = ip;
+    // Run main's code in one direction over `range`, stopping when main reaches the end of the
+    // range, using the registers left by a previous run. The session is only updated if
+    // execution completes //
+    pub fn resume(module: &Module, forwards: bool, range: Range<usize>,
+                  session: &mut Session, options: &EngineOptions) -> Result<(), RuntimeError> {
+        let mut interpreter = Interpreter::resuming(module, forwards, range, session)?;
+        interpreter.set_options(options);
+        interpreter.execute()?;
+        session.registers = interpreter.registers;
+        session.global_registers = interpreter.global_registers;
+        Ok(())
     }
 
-    #[inline]
-    fn jump_if_true(&mut self, ip: usize) {
-        if self.pop_var().borrow().to_bool() {
-            self.jump(ip);
-        } else {
-            self.ip += 1;
+    // As resume, but collecting what is printed, including up to a runtime error //
+    pub fn resume_captured(module: &Module, forwards: bool, range: Range<usize>,
+                           session: &mut Session, options: &EngineOptions) -> (String, Result<(), RuntimeError>) {
+        let mut interpreter = match Interpreter::resuming(module, forwards, range, session) {
+            Ok(interpreter) => interpreter,
+            Err(err) => return (String::new(), Err(err))
+        };
+        interpreter.set_options(options);
+        interpreter.capture_output();
+        let result = interpreter.execute();
+        let output = interpreter.take_output();
+        match result {
+            Ok(_) => {
+                session.registers = interpreter.registers;
+                session.global_registers = interpreter.global_registers;
+                (output, Ok(()))
+            },
+            Err(err) => (output, Err(err))
         }
     }
 
-    #[inline]
-    fn jump_if_false(&mut self, ip: usize) {
-        if !self.pop_var().borrow().to_bool() {
-            self.jump(ip);
-        } else {
-            self.ip += 1;
-        }
+    fn resuming<'b>(module: &'b Module, forwards: bool, range: Range<usize>, session: &Session) -> Result<Interpreter<'b>, RuntimeError> {
+        let main_idx = match module.main_idx {
+            Some(main_idx) => main_idx,
+            None => return Err(Interpreter::new(module).error(String::from("No main function")))
+        };
+        let main_func = &module.functions[main_idx];
+        let global_func = &module.functions[module.global_func_idx];
+        let mut registers = session.registers.clone();
+        let mut global_registers = session.global_registers.clone();
+        registers.resize(main_func.num_registers, None);
+        global_registers.resize(global_func.num_registers, None);
+
+        Ok(Interpreter {
+            functions: &module.functions,
+            stack: Vec::new(),
+            scope_stack: Vec::new(),
+            resumed: Vec::new(),
+            code: &main_func.code,
+            ip: range.start,
+            forwards,
+            registers,
+            global_registers,
+            consts: &main_func.consts,
+            strings: &module.strings,
+            func_idx: main_idx,
+            stop_at: Some((0, forwards, range.end)),
+            watching: false,
+            breakpoints: HashSet::new(),
+            at_breakpoint: false,
+            output: None,
+            sandboxed: false,
+            reversals: ReversalLog::default(),
+            frame_pool: FramePool::default(),
+            memo: MemoTable::default(),
+            arithmetic: Arithmetic::Exact,
+            steps: 0,
+            started: Instant::now(),
+            call_counts: None,
+            costs: None,
+            timeline: None,
+            hook: None,
+            host_functions: HashMap::new(),
+            handles: Handles::default(),
+            options: EngineOptions::default(),
+            frame_values: 0,
+            subroutine_return: None
+        })
     }
 
-    #[inline]
-    fn reverse(&mut self, ip: usize) {
-        self.forwards = !self.forwards;
-        self.ip = ip;
+    // Pause after every instruction that changes a variable, so a debugger can check watchpoints.
+    // Costs a single flag test per instruction when off //
+    pub fn set_watching(&mut self, watching: bool) {
+        self.watching = watching;
     }
 
-    #[inline]
-    fn load_const(&mut self, idx: usize) {
-        self.stack.push(StackObject::Var(Rc::new(RefCell::new(
-            self.consts[idx].clone()
-        ))));
-    }  
-
-    #[inline]
-    fn load_register(&mut self, idx: usize) {
-        let new_var_ref = Rc::clone(self.registers[idx].as_ref().unwrap());
-        self.stack.push(StackObject::Var(new_var_ref));
+    // Pause before executing any of these (function, direction, instruction pointer) positions //
+    pub fn set_breakpoints(&mut self, breakpoints: HashSet<(usize, bool, usize)>) {
+        self.breakpoints = breakpoints;
     }
 
-    #[inline]
-    fn load_global_register(&mut self, idx: usize) {
-        let new_var_ref = Rc::clone(self.global_registers[idx].as_ref().unwrap());
-        self.stack.push(StackObject::Var(new_var_ref));
+    // Stop when the frame at this depth reaches the given direction and instruction pointer //
+    pub fn set_stop(&mut self, stop_at: Option<(usize, bool, usize)>) {
+        self.stop_at = stop_at;
     }
 
-    #[inline]
-    fn store_register(&mut self, idx: usize) {
-        self.registers[idx] = Some(self.pop_var());
+    // Move the innermost frame, as if it had been paused there //
+    pub fn jump_to(&mut self, forwards: bool, ip: usize) {
+        self.forwards = forwards;
+        self.ip = ip;
+        self.at_breakpoint = true;
     }
 
-    #[inline]
-    fn store_global_register(&mut self, idx: usize) {
-        self.global_registers[idx] = Some(self.pop_var());
+    // Collect printed output instead of writing it to stdout //
+    pub fn capture_output(&mut self) {
+        self.output = Some(String::new());
     }
 
-    #[inline]
-    fn free_register(&mut self, idx: usize) {
-        self.registers[idx] = None;
+    pub fn take_output(&mut self) -> String {
+        self.output.as_mut().map(std::mem::take).unwrap_or_default()
     }
 
-    pub fn array_literal(&mut self, size: usize) {
-        let mut items = Vec::with_capacity(size);
-        for _ in 0..size {
-            let mut item = self.pop_var();
-            if Rc::strong_count(&item) > 1 {
-                let val = item.borrow().deep_copy();
-                item = Rc::new(RefCell::new(val));
-            };
-            items.push(item);
-        }
-        self.stack.push(StackObject::Var(Rc::new(RefCell::new(
-            Variable::Array(items)
-        ))));
+    // Print a notice on stderr as each reversal finishes unwinding //
+    pub fn set_trace_reversals(&mut self, notify: bool) {
+        self.reversals.notify = notify;
     }
 
-    pub fn array_repeat(&mut self) {
-
-        let dimensions = self.pop_var();
-        let content = self.pop_var();
+    // Count the calls made at each site and not yet undone, for garbage_report //
+    pub fn set_garbage_report(&mut self, report: bool) {
+        self.call_counts = if report {Some(HashMap::new())} else {None};
+    }
 
-        let dimensions: Vec<_> = match &*dimensions.borrow() {
-            Variable::Array(array) => array.iter().map(|d| d.borrow().to_usize()).collect(),
-            Variable::Frac(value) => vec![value.to_integer().to_usize().unwrap()],
-            Variable::Str(_) => panic!("Array repetition dimensions must be specified in an array")
+    // How many values each call found to leave garbage is still holding, when the run finished //
+    pub fn garbage_report(&self) -> String {
+        let counts = match &self.call_counts {
+            Some(counts) => counts,
+            None => return String::new()
         };
+        let mut report = String::new();
+        let mut total = 0;
+        for (func_idx, func) in self.functions.iter().enumerate() {
+            for garbage in &func.garbage {
+                let calls = counts.get(&(func_idx, garbage.line, garbage.col)).copied().unwrap_or(0);
+                if calls <= 0 {continue}
+                let values = calls as usize * garbage.names.len();
+                total += values;
+                report += &format!("  {} value(s) from {} call(s) to {} in {} at line {}, column {}: {}\n",
+                                   values, calls, garbage.callee, func.name, garbage.line, garbage.col,
+                                   garbage.names.join(", "));
+            }
+        }
+        match total {
+            0 => String::from("Garbage report: no garbage was left\n"),
+            _ => format!("Garbage report: {} value(s) left as garbage\n{}", total, report)
+        }
+    }
+
+    // Charge each instruction run to its function, weighted as in Instruction::cost //
+    pub fn set_cost_report(&mut self, report: bool) {
+        self.costs = if report {Some(vec![CostTotals::default(); self.functions.len()])} else {None};
+    }
+
+    pub fn cost_report(&self) -> String {
+        let costs = match &self.costs {
+            Some(costs) => costs,
+            None => return String::new()
+        };
+        let mut report = format!("{:<24} {:>10} {:>10} {:>10} {:>10}\n",
+                                 "Function", "Forward", "Backward", "Reversal", "Total");
+        let mut total = CostTotals::default();
+        for (func, totals) in self.functions.iter().zip(costs.iter()) {
+            let sum = totals.forward + totals.backward + totals.reversal;
+            if sum == 0 {continue}
+            total.add(totals);
+            report += &format!("{:<24} {:>10} {:>10} {:>10} {:>10}\n",
+                               func.name, totals.forward, totals.backward, totals.reversal, sum);
+        }
+        report += &format!("{:<24} {:>10} {:>10} {:>10} {:>10}\n", "(all)",
+                           total.forward, total.backward, total.reversal,
+                           total.forward + total.backward + total.reversal);
+        report
+    }
+
+    // Record the statements run, and the variables live at each, for timeline_html //
+    pub fn set_timeline(&mut self, record: bool) {
+        self.timeline = if record {Some(Timeline::new())} else {None};
+    }
+
+    // Shared, so the embedder can keep a handle to read back what the hook collected //
+    pub fn set_hook(&mut self, hook: Option<Rc<RefCell<dyn ExecutionHook + 'a>>>) {
+        self.hook = hook;
+    }
+
+    // Calls to the #[host] function of this name run the given body //
+    pub fn register_host(&mut self, name: &str, function: Box<dyn HostFunction + 'a>) {
+        self.host_functions.insert(name.to_string(), function);
+    }
+
+    pub fn handles(&mut self) -> &mut Handles {
+        &mut self.handles
+    }
+
+    pub fn timeline_html(&self, title: &str, src: &str) -> String {
+        self.timeline.as_ref().map_or(String::new(), |timeline| timeline.html(self.functions, title, src))
+    }
+
+    // Every reversal so far, in the order their catches fired //
+    pub fn reversals(&self) -> &[Reversal] {
+        &self.reversals.reversals
+    }
+
+    // How many calls allocated a new register buffer, and how many reused a window of the same
+    // function's that had returned //
+    pub fn frame_stats(&self) -> FrameStats {
+        self.frame_pool.stats
+    }
+
+    // Number of frames below the innermost one //
+    pub fn depth(&self) -> usize {
+        self.scope_stack.len()
+    }
+
+    // The function, direction, instruction pointer and registers of each frame, innermost first //
+    pub fn frames(&self) -> Vec<(usize, bool, usize, &Registers)> {
+        let mut frames = vec![(self.func_idx, self.forwards, self.ip, &self.registers[..])];
+        for scope in self.scope_stack.iter().rev() {
+            frames.push((scope.func_idx, scope.forwards, scope.ip, &scope.registers[..]));
+        }
+        frames
+    }
+
+    // Evaluate compiled expression code against the registers of the innermost frame, without
+    // disturbing the paused program //
+    pub fn evaluate(&self, code: &Code, consts: &[Variable]) -> Result<Variable, RuntimeError> {
+        let consts: Vec<Constant> = consts.iter().map(Constant::from_variable).collect();
+        let mut interpreter = Interpreter {
+            functions: self.functions,
+            stack: Vec::new(),
+            scope_stack: Vec::new(),
+            resumed: Vec::new(),
+            code,
+            ip: 0,
+            forwards: true,
+            registers: self.registers.clone(),
+            global_registers: self.global_registers.clone(),
+            consts: &consts,
+            strings: self.strings,
+            func_idx: self.func_idx,
+            stop_at: None,
+            watching: false,
+            breakpoints: HashSet::new(),
+            at_breakpoint: false,
+            output: None,
+            sandboxed: false,
+            reversals: ReversalLog::default(),
+            frame_pool: FramePool::default(),
+            memo: MemoTable::default(),
+            arithmetic: self.arithmetic,
+            steps: self.steps,
+            started: self.started,
+            call_counts: None,
+            costs: None,
+            timeline: None,
+            hook: None,
+            host_functions: HashMap::new(),
+            handles: Handles::default(),
+            options: EngineOptions::default(),
+            frame_values: 0,
+            subroutine_return: None
+        };
+        interpreter.execute()?;
+        let result = interpreter.pop_var()?;
+        let value = result.borrow().deep_copy();
+        Ok(value)
+    }
+
+    // Evaluate compiled expression code which reads no variables, e.g. the condition of a static
+    // assertion //
+    pub fn evaluate_const(code: &Code, consts: &[Variable]) -> Result<Variable, RuntimeError> {
+        let consts: Vec<Constant> = consts.iter().map(Constant::from_variable).collect();
+        let functions = Vec::new();
+        let mut interpreter = Interpreter {
+            functions: &functions,
+            stack: Vec::new(),
+            scope_stack: Vec::new(),
+            resumed: Vec::new(),
+            code,
+            ip: 0,
+            forwards: true,
+            registers: Vec::new(),
+            global_registers: Vec::new(),
+            consts: &consts,
+            strings: &[],
+            func_idx: 0,
+            stop_at: None,
+            watching: false,
+            breakpoints: HashSet::new(),
+            at_breakpoint: false,
+            output: None,
+            sandboxed: true,
+            reversals: ReversalLog::default(),
+            frame_pool: FramePool::default(),
+            memo: MemoTable::default(),
+            arithmetic: Arithmetic::Exact,
+            steps: 0,
+            started: Instant::now(),
+            call_counts: None,
+            costs: None,
+            timeline: None,
+            hook: None,
+            host_functions: HashMap::new(),
+            handles: Handles::default(),
+            options: EngineOptions::default(),
+            frame_values: 0,
+            subroutine_return: None
+        };
+        interpreter.execute()?;
+        let result = interpreter.pop_var()?;
+        let value = result.borrow().deep_copy();
+        Ok(value)
+    }
+
+    // The function, direction and instruction pointer of the innermost frame //
+    pub fn position(&self) -> (usize, bool, usize) {
+        (self.func_idx, self.forwards, self.ip)
+    }
+
+    // A failure executing at the current position. The ip is only advanced after an instruction
+    // completes, so it still points at the one that failed //
+    pub fn error(&self, desc: String) -> RuntimeError {
+        let (line, col) = self.source_position().unwrap_or((0, 0));
+        RuntimeError{
+            func_idx: self.func_idx,
+            func_name: self.functions.get(self.func_idx).map_or("?", |func| func.name.as_str()).to_string(),
+            forwards: self.forwards,
+            ip: self.ip,
+            line,
+            col,
+            desc
+        }
+    }
+
+    // As error, but placed at the call into the current function, which is where a #[host]
+    // function's own errors come from //
+    fn caller_error(&self, desc: String) -> RuntimeError {
+        let scope = match self.scope_stack.last() {
+            Some(scope) => scope,
+            None => return self.error(desc)
+        };
+        let (line, col) = scope.code.statement_position(scope.forwards, scope.ip).unwrap_or((0, 0));
+        RuntimeError{
+            func_idx: scope.func_idx,
+            func_name: self.functions.get(scope.func_idx).map_or("?", |func| func.name.as_str()).to_string(),
+            forwards: scope.forwards,
+            ip: scope.ip,
+            line,
+            col,
+            desc
+        }
+    }
+
+    // The line and column of the deepest statement containing the current instruction, or inside
+    // a subroutine the Gosub that ran it //
+    fn source_position(&self) -> Option<(usize, usize)> {
+        let ip = self.subroutine_return.map_or(self.ip, |ip| ip - 1);
+        self.code.statement_position(self.forwards, ip)
+    }
+
+    // The registers of the innermost active frame of a function //
+    pub fn frame_registers(&self, func_idx: usize) -> Option<&Registers> {
+        if self.func_idx == func_idx {
+            return Some(&self.registers);
+        }
+        self.scope_stack.iter().rev().find(|scope| scope.func_idx == func_idx).map(|scope| &scope.registers[..])
+    }
+
+    pub fn global_registers(&self) -> &Registers {
+        &self.global_registers
+    }
+
+    pub fn execute(&mut self) -> Result<Status, RuntimeError> {
+        match self.hook.clone() {
+            Some(hook) => self.execute_hooked(&mut *hook.borrow_mut()),
+            None => self.execute_hooked(&mut NoHook)
+        }
+    }
+
+    // Monomorphised for NoHook, so the unhooked loop has no hook points left in it //
+    fn execute_hooked<H: ExecutionHook + ?Sized>(&mut self, hook: &mut H) -> Result<Status, RuntimeError> {
+
+        'refresh_instructions: loop{
+
+            let code = self.code;
+            let instructions = code.decoded(self.forwards);
+
+            loop {
+
+                if self.stop_at == Some((self.scope_stack.len(), self.forwards, self.ip)) {
+                    self.stop_at = None;
+                    self.at_breakpoint = true;
+                    return Ok(Status::Stopped);
+                }
+
+                // Resuming from a breakpoint executes its instruction rather than stopping again //
+                if self.at_breakpoint {
+                    self.at_breakpoint = false;
+                } else if !self.breakpoints.is_empty()
+                    && self.breakpoints.contains(&(self.func_idx, self.forwards, self.ip)) {
+                    self.at_breakpoint = true;
+                    return Ok(Status::Breakpoint);
+                }
+
+                let instruction = match instructions.get(self.ip) {
+                    Some(&inst) => inst,
+                    None => {
+                        if self.scope_stack.is_empty() { 
+                            return Ok(Status::Finished);
+                        } else {
+                            self.end_call()?; 
+                            continue 'refresh_instructions;
+                        };
+                    }
+                };
+
+                // println!("{} IP: {}, {:?}", if self.forwards {"FWD"} else {"BKWD"}, self.ip, instruction);
+
+                self.steps += 1;
+                if let Some(budget) = self.options.step_budget {
+                    if self.steps > budget {
+                        return Err(self.error(exceeded_step_budget(budget)));
+                    }
+                }
+                if let Some(costs) = &mut self.costs {
+                    let totals = &mut costs[self.func_idx];
+                    if !self.reversals.unwinding.is_empty() {
+                        totals.reversal += instruction.cost();
+                    } else if self.forwards {
+                        totals.forward += instruction.cost();
+                    } else {
+                        totals.backward += instruction.cost();
+                    }
+                }
+                for &(idx, _) in self.reversals.unwinding.iter() {
+                    self.reversals.reversals[idx].unwound += 1;
+                }
+                if let Some(timeline) = &mut self.timeline {
+                    timeline.step(self.functions, self.func_idx, self.forwards, self.ip,
+                                  self.scope_stack.len(), !self.reversals.unwinding.is_empty());
+                }
+                hook.before_instruction(self, instruction);
+
+                // Instructions that jump or change frame leave ip where execution continues //
+                let mut jumped = false;
+                match instruction {
+                    Instruction::LoadConst{idx} => self.load_const(idx)?,
+                    Instruction::LoadString{idx} => self.load_string(idx)?,
+                    Instruction::LoadRegister{register} => self.load_register(register)?,
+                    Instruction::LoadGlobalRegister{register} => self.load_global_register(register)?,
+                    Instruction::StoreRegister{register} => self.store_register(register)?,
+                    Instruction::StoreGlobalRegister{register} => self.store_global_register(register)?,
+                    Instruction::FreeRegister{register} => self.free_register(register)?,
+                    Instruction::MoveRegister{from, to} => self.move_register(from, to)?,
+                    Instruction::Store => self.store()?,
+                    Instruction::Subscript{size} => self.subscript(size)?,
+                    Instruction::Slice => self.slice()?,
+                    Instruction::CheckRef{register} => self.check_ref(register)?,
+                    Instruction::CheckUnshared{register} => self.check_unshared(register)?,
+                    Instruction::DuplicateRef => self.duplicate_ref()?,
+                    Instruction::UniqueVar => self.copy_var()?,
+                    Instruction::CreateInt{val} => self.create_int(val),
+                    Instruction::BinopAdd => self.binop_add()?,
+                    Instruction::BinopSub => self.binop_sub()?,
+                    Instruction::BinopMul => self.binop_mul()?,
+                    Instruction::BinopDiv => self.binop_div()?,
+                    Instruction::BinopMod => self.binop_mod()?,
+                    Instruction::BinopIDiv => self.binop_idiv()?,
+                    Instruction::BinopPow => self.binop_pow()?,
+                    Instruction::Shift{bits} => self.shift(bits)?,
+                    Instruction::BinopLess => self.binop_less()?,
+                    Instruction::BinopLeq => self.binop_leq()?,
+                    Instruction::BinopGreat => self.binop_great()?,
+                    Instruction::BinopGeq => self.binop_geq()?,
+                    Instruction::BinopEq => self.binop_eq()?,
+                    Instruction::BinopNeq => self.binop_neq()?,
+                    Instruction::BinopXor => self.binop_xor()?,
+                    Instruction::UniopNeg => self.uniop_neg()?,
+                    Instruction::UniopNot => self.uniop_not()?,
+                    Instruction::UniopLen => self.uniop_len()?,
+                    Instruction::UniopToFloat => self.uniop_to_float()?,
+                    Instruction::UniopToFrac => self.uniop_to_frac()?,
+                    Instruction::UniopSin => self.uniop_float("sin", f64::sin)?,
+                    Instruction::UniopCos => self.uniop_float("cos", f64::cos)?,
+                    Instruction::UniopExp => self.uniop_float("exp", f64::exp)?,
+                    Instruction::UniopLog => self.uniop_float("log", f64::ln)?,
+                    Instruction::UniopSqrt => self.uniop_float("sqrt", f64::sqrt)?,
+                    Instruction::ArrayLiteral{size} => self.array_literal(size)?,
+                    Instruction::ArrayRepeat => self.array_repeat()?,
+                    Instruction::Pull{register} => self.pull(register)?,
+                    Instruction::Push{register} => self.push(register)?,
+                    Instruction::PullFront{register} => self.pull_front(register)?,
+                    Instruction::PushFront{register} => self.push_front(register)?,
+                    Instruction::Print{count} => self.print(count)?,
+                    Instruction::CreateIter{register, reversed, index_register, depth} => {
+                        self.create_iter(register, reversed, index_register, depth)?
+                    },
+                    Instruction::CreateRangeIter{register} => self.create_range_iter(register)?,
+                    Instruction::StepIter{ip} => {self.step_iter(ip)?; jumped = true},
+                    Instruction::ParallelStepIter{ip} => {self.parallel_step_iter(ip)?; jumped = true},
+                    Instruction::CreateGenerator{idx} => self.create_generator(idx)?,
+                    Instruction::Resume{register, ip} => {self.resume_generator(register, ip)?; jumped = true},
+                    Instruction::Suspend => {self.suspend()?; jumped = true},
+                    Instruction::HostCall => self.host_call()?,
+                    
+                    Instruction::Jump{ip} => {self.jump(ip); jumped = true},
+                    Instruction::Gosub{ip} => {self.gosub(ip); jumped = true},
+                    Instruction::Return => {self.return_from_subroutine()?; jumped = true},
+                    Instruction::JumpIfTrue{ip} => {self.jump_if_true(ip)?; jumped = true},
+                    Instruction::JumpIfFalse{ip} => {self.jump_if_false(ip)?; jumped = true},
+                    Instruction::Call{idx} => {
+                        self.call(idx, true)?;
+                        hook.on_call(self, idx, true);
+                        jumped = true
+                    },
+                    Instruction::Uncall{idx} => {
+                        self.call(idx, false)?;
+                        hook.on_call(self, idx, false);
+                        jumped = true
+                    },
+                    Instruction::Remember{idx} => {
+                        let depth = self.scope_stack.len();
+                        self.remember(idx)?;
+                        if self.scope_stack.len() > depth {
+                            hook.on_call(self, idx, true);
+                        }
+                        jumped = true
+                    },
+                    Instruction::Reverse{idx} => {
+                        let caught = self.reverse(idx);
+                        hook.on_reversal(self, caught);
+                        jumped = true
+                    }
+                    Instruction::Quit => return Ok(Status::Finished),
+                    Instruction::Hole => return Err(self.error(String::from("Evaluated the unfinished expression \"???\""))),
+                    Instruction::Todo => return Err(self.error(String::from("Ran the unfinished statement \"todo\""))),
+                    Instruction::DebugPrint => self.debug_print()?,
+                    Instruction::Backtrace => self.backtrace()?,
+                    Instruction::Expect => self.expect()?,
+                    Instruction::ExpectEqual => self.expect_equal()?,
+                    Instruction::Steps => self.push_count(self.steps),
+                    Instruction::ClockMs => {
+                        self.check_sandbox("Reading the clock")?;
+                        self.push_count(self.started.elapsed().as_millis() as u64)
+                    },
+                    Instruction::Marker{..} => {},
+
+                    
+                    Instruction::BinopAnd | Instruction::BinopOr | Instruction::RelativeJump{..}
+                    | Instruction::RelativeJumpIfTrue{..} | Instruction::RelativeJumpIfFalse{..} => {
+                        return Err(self.error(format!("The interpreter doesn't implement {:?}", instruction)));
+                    }
+                }
+
+                if !jumped {
+                    self.ip += 1;
+                }
+                hook.after_instruction(self, instruction);
+                if jumped {
+                    continue 'refresh_instructions;
+                }
+
+                if self.watching && instruction.modifies_variables() {
+                    return Ok(Status::Paused);
+                }
+            }
+        }
+    }
+
+    pub fn call(&mut self, func_idx: usize, forwards: bool) -> Result<(), RuntimeError> {
+        let func: &'a Function = self.functions.get(func_idx)
+            .ok_or_else(|| self.error(String::from("Call to undefined function")))?;
+        log::trace!("{} {} at depth {}", if forwards {"Calling"} else {"Uncalling"}, func.name, self.scope_stack.len());
+        if let Some(counts) = &mut self.call_counts {
+            if let Some((line, col)) = self.code.statement_position(self.forwards, self.ip) {
+                *counts.entry((self.func_idx, line, col)).or_insert(0) += if forwards {1} else {-1};
+            }
+        }
+        self.frame_values += func.num_registers;
+        self.check_memory(self.frame_values, "The call stack")?;
+        self.scope_stack.push(
+            Scope{
+                code      : replace(&mut self.code     , &func.code),
+                consts    : replace(&mut self.consts   , &func.consts),
+                registers : replace(&mut self.registers, self.frame_pool.take(func_idx, func.num_registers)),
+                ip        : replace(&mut self.ip       , 0),
+                forwards  : replace(&mut self.forwards , forwards),
+                func_idx  : replace(&mut self.func_idx , func_idx)
+            }
+        );
+        if let Some(timeline) = &mut self.timeline {
+            timeline.enter_frame();
+        }
+        Ok(())
+    }
+
+    pub fn end_call(&mut self) -> Result<(), RuntimeError> {
+        while self.reversals.unwinding.last().is_some_and(|&(_, depth)| depth == self.scope_stack.len()) {
+            self.finish_reversal();
+        }
+        let scope = self.scope_stack.pop().ok_or_else(|| self.error(String::from("Returned from the global scope")))?;
+        if let Some(timeline) = &mut self.timeline {
+            timeline.leave_frame();
+        }
+        self.code = scope.code;
+        self.consts = scope.consts;
+        let registers = replace(&mut self.registers, scope.registers);
+        self.frame_values -= registers.len();
+        self.frame_pool.give(self.func_idx, registers);
+        self.ip = scope.ip + 1;
+        self.forwards = scope.forwards;
+        self.func_idx = scope.func_idx;
+        // A generator that runs to the end leaves the loop that resumed it //
+        if self.resumed.last().is_some_and(|resumed| resumed.depth == self.scope_stack.len() + 1) {
+            self.ip = self.resumed.pop().unwrap().exit;
+        }
+        if self.memo.pending.last().is_some_and(|&(_, depth)| depth == self.scope_stack.len()) {
+            let (key, _) = self.memo.pending.pop().unwrap();
+            let value = match self.stack.last() {
+                Some(StackObject::Var(var)) => var.borrow().deep_copy(),
+                _ => return Err(self.error(String::from("Remembered call returned no value")))
+            };
+            self.memo.entries.insert(key, (value, 1));
+        }
+        Ok(())
+    }
+
+    // Call a pure function through the memo table. Running backwards undoes a forwards use, so
+    // the entry is always there to be released //
+    fn remember(&mut self, func_idx: usize) -> Result<(), RuntimeError> {
+        let num_args = self.functions.get(func_idx)
+            .ok_or_else(|| self.error(String::from("Call to undefined function")))?.num_borrows;
+        let base = self.stack_base(num_args)?;
+        let mut key = String::new();
+        for object in &self.stack[base..] {
+            match object {
+                StackObject::Var(var) => memo_key(&var.borrow(), &mut key),
+                StackObject::Iter(_) | StackObject::Generator(_) => {
+                    return Err(self.error(String::from("Iterator passed to a remembered call")));
+                }
+            }
+        }
+        let key = (func_idx, key);
+        let value = match self.memo.entries.get_mut(&key) {
+            Some((value, uses)) => {
+                let value = value.deep_copy();
+                if self.forwards {
+                    *uses += 1;
+                } else {
+                    *uses -= 1;
+                    if *uses == 0 {
+                        self.memo.entries.remove(&key);
+                    }
+                }
+                value
+            },
+            None => {
+                if self.forwards {
+                    self.memo.pending.push((key, self.scope_stack.len()));
+                }
+                return self.call(func_idx, true);
+            }
+        };
+        self.stack.truncate(base);
+        self.stack.push(StackObject::Var(Rc::new(RefCell::new(value))));
+        self.ip += 1;
+        Ok(())
+    }
+
+    #[inline]
+    fn jump(&mut self, ip: usize) {
+        self.ip = ip;
+    }
+
+    fn gosub(&mut self, ip: usize) {
+        self.subroutine_return = Some(self.ip + 1);
+        self.ip = ip;
+    }
+
+    fn return_from_subroutine(&mut self) -> Result<(), RuntimeError> {
+        self.ip = self.subroutine_return.take()
+            .ok_or_else(|| self.error(String::from("Returning from outside a subroutine")))?;
+        Ok(())
+    }
+
+    #[inline]
+    fn jump_if_true(&mut self, ip: usize) -> Result<(), RuntimeError> {
+        if self.pop_bool()? {
+            self.jump(ip);
+        } else {
+            self.ip += 1;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn jump_if_false(&mut self, ip: usize) -> Result<(), RuntimeError> {
+        if !self.pop_bool()? {
+            self.jump(ip);
+        } else {
+            self.ip += 1;
+        }
+        Ok(())
+    }
+
+    // Whether a catch fired, rather than an unwinding finishing //
+    #[inline]
+    fn reverse(&mut self, ip: usize) -> bool {
+        // Turning again in the same frame ends the unwinding, otherwise a catch has fired //
+        let finishing = self.reversals.unwinding.last().is_some_and(|&(_, depth)| depth == self.scope_stack.len());
+        if finishing {
+            self.finish_reversal();
+        } else {
+            self.start_reversal();
+        }
+        if let Some(timeline) = &mut self.timeline {
+            timeline.turn(!finishing);
+        }
+        self.forwards = !self.forwards;
+        self.ip = ip;
+        !finishing
+    }
+
+    fn start_reversal(&mut self) {
+        let (line, col) = self.source_position().unwrap_or((0, 0));
+        self.reversals.unwinding.push((self.reversals.reversals.len(), self.scope_stack.len()));
+        self.reversals.reversals.push(Reversal{
+            func_idx: self.func_idx,
+            forwards: self.forwards,
+            ip: self.ip,
+            line,
+            col,
+            unwound: 0
+        });
+    }
+
+    fn finish_reversal(&mut self) {
+        let (idx, _) = self.reversals.unwinding.pop().unwrap();
+        if self.reversals.notify {
+            let Reversal{func_idx, ip, line, col, unwound, ..} = &self.reversals.reversals[idx];
+            let name = self.functions.get(*func_idx).map_or("?", |func| func.name.as_str());
+            match line {
+                0 => eprintln!("Reversal: catch at ip {} in {} unwound {} instructions", ip, name, unwound),
+                _ => eprintln!(
+                    "Reversal: catch at line {}, column {} in {} unwound {} instructions", line, col, name, unwound)
+            }
+        }
+    }
+
+    #[inline]
+    fn load_const(&mut self, idx: usize) -> Result<(), RuntimeError> {
+        let value = self.consts.get(idx)
+            .ok_or_else(|| self.error(String::from("Constant index out of range")))?.to_variable();
+        self.stack.push(StackObject::Var(Rc::new(RefCell::new(value))));
+        Ok(())
+    }  
+
+    #[inline]
+    fn load_string(&mut self, idx: usize) -> Result<(), RuntimeError> {
+        let string = self.strings.get(idx)
+            .ok_or_else(|| self.error(String::from("String index out of range")))?.clone();
+        self.stack.push(StackObject::Var(Rc::new(RefCell::new(Variable::Str(string)))));
+        Ok(())
+    }
+
+    #[inline]
+    fn load_register(&mut self, idx: usize) -> Result<(), RuntimeError> {
+        let new_var_ref = match self.register(idx)? {
+            Some(var) => Rc::clone(var),
+            None => return Err(self.error(format!("Using {} before it has a value", self.register_name(idx))))
+        };
+        self.stack.push(StackObject::Var(new_var_ref));
+        Ok(())
+    }
+
+    #[inline]
+    fn load_global_register(&mut self, idx: usize) -> Result<(), RuntimeError> {
+        let new_var_ref = match self.global_registers.get(idx) {
+            Some(Some(var)) => Rc::clone(var),
+            Some(None) => return Err(self.error(format!("Using global register {} before it has a value", idx))),
+            None => return Err(self.error(String::from("Global register out of range")))
+        };
+        self.stack.push(StackObject::Var(new_var_ref));
+        Ok(())
+    }
+
+    #[inline]
+    fn store_register(&mut self, idx: usize) -> Result<(), RuntimeError> {
+        let var = self.pop_var()?;
+        *self.register_mut(idx)? = Some(var);
+        Ok(())
+    }
+
+    #[inline]
+    fn store_global_register(&mut self, idx: usize) -> Result<(), RuntimeError> {
+        let var = self.pop_var()?;
+        if idx >= self.global_registers.len() {
+            return Err(self.error(String::from("Global register out of range")));
+        }
+        self.global_registers[idx] = Some(var);
+        Ok(())
+    }
+
+    #[inline]
+    fn free_register(&mut self, idx: usize) -> Result<(), RuntimeError> {
+        *self.register_mut(idx)? = None;
+        Ok(())
+    }
+
+    #[inline]
+    fn move_register(&mut self, from: usize, to: usize) -> Result<(), RuntimeError> {
+        let var = self.register_mut(from)?.take();
+        if var.is_none() {
+            return Err(self.error(format!("Using {} before it has a value", self.register_name(from))));
+        }
+        *self.register_mut(to)? = var;
+        Ok(())
+    }
+
+    #[inline]
+    fn register(&self, idx: usize) -> Result<&Option<Rc<RefCell<Variable>>>, RuntimeError> {
+        self.registers.get(idx).ok_or_else(|| self.error(String::from("Register out of range")))
+    }
+
+    #[inline]
+    fn register_mut(&mut self, idx: usize) -> Result<&mut Option<Rc<RefCell<Variable>>>, RuntimeError> {
+        if idx >= self.registers.len() {
+            return Err(self.error(String::from("Register out of range")));
+        }
+        Ok(&mut self.registers[idx])
+    }
+
+    pub fn array_literal(&mut self, size: usize) -> Result<(), RuntimeError> {
+        self.check_memory(size, "An array")?;
+        let mut items = Vec::with_capacity(size);
+        for _ in 0..size {
+            let mut item = self.pop_var()?;
+            if Rc::strong_count(&item) > 1 {
+                let val = item.borrow().deep_copy();
+                item = Rc::new(RefCell::new(val));
+            };
+            items.push(item);
+        }
+        self.stack.push(StackObject::Var(Rc::new(RefCell::new(
+            Variable::Array(items)
+        ))));
+        Ok(())
+    }
+
+    pub fn array_repeat(&mut self) -> Result<(), RuntimeError> {
+
+        let dimensions = self.pop_var()?;
+        let content = self.pop_var()?;
+
+        let dimensions: Result<Vec<_>, String> = match &*dimensions.borrow() {
+            Variable::Array(array) => array.iter().map(|d| d.borrow().to_usize()).collect(),
+            Variable::Frac(_) => dimensions.borrow().to_usize().map(|d| vec![d]),
+            Variable::Float(_) => Err(String::from("Array repetition dimensions can't be floats")),
+            Variable::Str(_) | Variable::Handle(_) => Err(String::from("Array repetition dimensions must be specified in an array"))
+        };
+        let dimensions = dimensions.map_err(|desc| self.error(desc))?;
+        if dimensions.is_empty() {
+            return Err(self.error(String::from("Array repetition needs at least one dimension")));
+        }
+        self.check_memory(dimensions.iter().fold(1, |n: usize, &d| n.saturating_mul(d)), "An array")?;
         
         fn recursive_array_maker(content: &Variable, dims: &[usize]) -> Vec<Rc<RefCell<Variable>>> {
             let mut ret = Vec::with_capacity(dims[0]);
@@ -479,34 +1794,81 @@ impl<'a> Interpreter<'a> {
         let array = recursive_array_maker(&*content.borrow(), dimensions.as_slice());
         let var = Rc::new(RefCell::new(Variable::Array(array)));
         self.stack.push(StackObject::Var(var));
+        Ok(())
     }
 
-    fn subscript(&mut self, size: usize) {
-        let mut var_ref = self.pop_var();
+    fn subscript(&mut self, size: usize) -> Result<(), RuntimeError> {
+        let mut var_ref = self.pop_var()?;
         for _ in 0..size {
-            let index = self.pop_var().borrow().to_usize();
-            let new_ref = Rc::clone(&Ref::map(var_ref.borrow(), |var| &var[index]));
+            let index = self.pop_usize()?;
+            let new_ref = var_ref.borrow().item(index).map(Rc::clone).map_err(|desc| self.error(desc))?;
             var_ref = new_ref;
         }
         self.stack.push(StackObject::Var(var_ref));
+        Ok(())
+    }
+
+    // A new array sharing a range of another's elements, for a reference to that range //
+    fn slice(&mut self) -> Result<(), RuntimeError> {
+        let var_ref = self.pop_var()?;
+        let start = self.pop_usize()?;
+        let end = self.pop_usize()?;
+        let items = match &*var_ref.borrow() {
+            Variable::Array(items) if start <= end && end <= items.len() => items[start..end].to_vec(),
+            Variable::Array(items) => return Err(self.error(format!(
+                "Range {}:{} is out of bounds for an array of length {}", start, end, items.len()))),
+            _ => return Err(self.error(String::from("Slicing is only supported by arrays")))
+        };
+        self.stack.push(StackObject::Var(Rc::new(RefCell::new(Variable::Array(items)))));
+        Ok(())
+    }
+
+    // Under --check-aliasing, before a reference is removed: it must be to the variable, or the
+    // elements, it's being removed from, as that's where it's made again in the other direction //
+    fn check_ref(&mut self, register: usize) -> Result<(), RuntimeError> {
+        let var_ref = self.pop_var()?;
+        let same = match self.register(register)? {
+            Some(held) => Rc::ptr_eq(held, &var_ref) || shares_elements(&held.borrow(), &var_ref.borrow()),
+            None => false
+        };
+        if !same {
+            return Err(self.error(format!(
+                "Removing {} from something it doesn't reference, so reversing would make it somewhere else",
+                self.register_name(register))));
+        }
+        Ok(())
+    }
+
+    // Under --check-aliasing, after a pull: nothing else may still reference what was pulled //
+    fn check_unshared(&mut self, register: usize) -> Result<(), RuntimeError> {
+        if self.register(register)?.as_ref().is_some_and(is_shared) {
+            return Err(self.error(format!("Pulled {} while a reference still points into it", self.register_name(register))));
+        }
+        Ok(())
     }
 
-    fn store(&mut self) {
-        let value = self.pop_var().borrow().clone();
-        *self.pop_var().borrow_mut() = value;
+    fn store(&mut self) -> Result<(), RuntimeError> {
+        let value = self.pop_var()?.borrow().clone();
+        *self.pop_var()?.borrow_mut() = value;
+        Ok(())
     }
 
-    fn duplicate_ref(&mut self) {
-        let new = match self.stack.last().unwrap() {
-            StackObject::Var(cell) => StackObject::Var(Rc::clone(cell)),
-            _ => panic!("Trying to duplicate non-variable")
+    fn duplicate_ref(&mut self) -> Result<(), RuntimeError> {
+        let new = match self.stack.last() {
+            Some(StackObject::Var(cell)) => StackObject::Var(Rc::clone(cell)),
+            Some(_) => return Err(self.error(String::from("Trying to duplicate non-variable"))),
+            None => return Err(self.error(String::from("Duplicated off empty stack")))
         };
         self.stack.push(new);
+        Ok(())
     }
 
-    fn copy_var(&mut self) {
-        let var = self.pop_var();
+    fn copy_var(&mut self) -> Result<(), RuntimeError> {
+        let var = self.pop_var()?;
         if Rc::strong_count(&var) > 1 {
+            if var.borrow().holds_handle() {
+                return Err(self.error(String::from("Copying a handle, which can only be moved")));
+            }
             self.stack.push(
                 StackObject::Var(Rc::new(RefCell::new(
                     var.borrow().deep_copy()
@@ -515,6 +1877,7 @@ impl<'a> Interpreter<'a> {
         } else {
             self.stack.push(StackObject::Var(var));
         }
+        Ok(())
     }
 
     fn create_int(&mut self, val: isize) {
@@ -525,80 +1888,175 @@ impl<'a> Interpreter<'a> {
         ))));
     }
 
-    binop_method!(binop_add, +);
-    binop_method!(binop_sub, -);
-    binop_method!(binop_mul, *);
-    binop_method!(binop_div, /);
-    binop_method!(binop_mod, %);
+    binop_method!(binop_add, +, checked_add, wrapping_add);
+    binop_method!(binop_sub, -, checked_sub, wrapping_sub);
+    binop_method!(binop_mul, *, checked_mul, wrapping_mul);
+    binop_method!(binop_div, /, checked_div, wrapping_div);
+    binop_method!(binop_mod, %, checked_rem, wrapping_rem);
 
     bincomp_method!(binop_less,  < );
     bincomp_method!(binop_leq,   <=);
     bincomp_method!(binop_great, > );
     bincomp_method!(binop_geq,   >=);
     
-    fn binop_xor (&mut self) {
-        let rhs = self.pop_var();
-        let lhs = self.pop_var();
-        let result = lhs.borrow().to_bool() ^ rhs.borrow().to_bool();
-        self.create_int(if result {1} else {0});
+    fn binop_xor (&mut self) -> Result<(), RuntimeError> {
+        let rhs = self.pop_bool()?;
+        let lhs = self.pop_bool()?;
+        self.create_int(if lhs ^ rhs {1} else {0});
+        Ok(())
     }
 
-    fn binop_idiv(&mut self) {
-        let rhs = self.pop_var();
-        let lhs = self.pop_var();
+    fn binop_idiv(&mut self) -> Result<(), RuntimeError> {
+        let rhs = self.pop_var()?;
+        let lhs = self.pop_var()?;
+        if let Some((left, right)) = float_operands(&lhs.borrow(), &rhs.borrow()).map_err(|desc| self.error(desc))? {
+            self.stack.push(StackObject::Var(Rc::new(RefCell::new(Variable::Float((left / right).trunc())))));
+            return Ok(());
+        }
         let result = match (&*lhs.borrow(), &*rhs.borrow()) {
-            (Variable::Frac(left), Variable::Frac(right)) => {
-                Variable::Frac((left/right).trunc())
-            },
-            _ => panic!("Applying binop \"//\" to incompatible types")
+            (Variable::Frac(_), Variable::Frac(right)) if right.is_zero() => Err(String::from("Division by zero")),
+            (Variable::Frac(left), Variable::Frac(right)) => match self.arithmetic {
+                Arithmetic::Exact => Ok((left/right).trunc()),
+                Arithmetic::Checked => to_fixed_width(left).and_then(|left| {
+                    left.checked_div(to_fixed_width(right)?).map(from_fixed_width)
+                        .ok_or_else(|| String::from("Integer overflow in binop \"//\""))
+                }),
+                Arithmetic::Wrapping => to_fixed_width(left).and_then(|left| {
+                    Ok(from_fixed_width(left.wrapping_div(to_fixed_width(right)?)))
+                })
+            }.map(Variable::Frac),
+            _ => Err(String::from("Applying binop \"//\" to incompatible types"))
         };
+        let result = result.map_err(|desc| self.error(desc))?;
         self.stack.push(StackObject::Var(Rc::new(RefCell::new(result))));
+        Ok(())
+    }
+
+    // Exact fractions are shifted directly, and anything else is multiplied or divided as it
+    // would have been by the constant //
+    fn shift(&mut self, bits: isize) -> Result<(), RuntimeError> {
+        let operand = self.pop_var()?;
+        let shifted = match &*operand.borrow() {
+            Variable::Frac(x) if self.arithmetic == Arithmetic::Exact => Some(shift_fraction(x, bits)),
+            _ => None
+        };
+        if let Some(result) = shifted {
+            self.stack.push(StackObject::Var(Rc::new(RefCell::new(Variable::Frac(result)))));
+            return Ok(());
+        }
+        let factor = Fraction::from_integer(num_bigint::BigInt::one() << bits.unsigned_abs());
+        self.stack.push(StackObject::Var(operand));
+        self.stack.push(StackObject::Var(Rc::new(RefCell::new(Variable::Frac(factor)))));
+        if bits < 0 {self.binop_div()} else {self.binop_mul()}
     }
 
-    fn binop_pow(&mut self) {
-        let rhs = self.pop_var();
-        let lhs = self.pop_var();
+    fn binop_pow(&mut self) -> Result<(), RuntimeError> {
+        let rhs = self.pop_var()?;
+        let lhs = self.pop_var()?;
+        if let Some((left, right)) = float_operands(&lhs.borrow(), &rhs.borrow()).map_err(|desc| self.error(desc))? {
+            self.stack.push(StackObject::Var(Rc::new(RefCell::new(Variable::Float(left.powf(right))))));
+            return Ok(());
+        }
         let result = match (&*lhs.borrow(), &*rhs.borrow()) {
+            (Variable::Frac(left), Variable::Frac(right)) if self.arithmetic != Arithmetic::Exact => {
+                to_fixed_width(left).and_then(|base| {
+                    let exponent = to_fixed_width(right)?.to_u32()
+                        .ok_or_else(|| String::from("Fixed-width powers need a non-negative exponent"))?;
+                    match self.arithmetic {
+                        Arithmetic::Wrapping => Ok(base.wrapping_pow(exponent)),
+                        _ => base.checked_pow(exponent).ok_or_else(|| String::from("Integer overflow in binop \"**\""))
+                    }
+                }).map(|value| Variable::Frac(from_fixed_width(value)))
+            },
             (Variable::Frac(left), Variable::Frac(right)) => {
-                let value = fraction_to_f64(left).powf(fraction_to_f64(right));
-                let value = Fraction::from_float(value).expect("Computing power created an infinite float");
-                Variable::Frac(value)
+                let value = fraction_to_f64(left).and_then(|left| Ok(left.powf(fraction_to_f64(right)?)));
+                value.and_then(|value| Fraction::from_float(value).map(Variable::Frac)
+                    .ok_or_else(|| String::from("Computing power created an infinite float")))
             },
-            _ => panic!("Applying binop \"**\" to incompatible types")
+            _ => Err(String::from("Applying binop \"**\" to incompatible types"))
         };
+        let result = result.map_err(|desc| self.error(desc))?;
         self.stack.push(StackObject::Var(Rc::new(RefCell::new(result))));
+        Ok(())
     }
 
-    fn binop_eq(&mut self) {
-        let rhs = self.pop_var();
-        let lhs = self.pop_var();
-        let value = if *lhs.borrow() == *rhs.borrow() {Fraction::one()}
-                    else                              {Fraction::zero()};
+    fn binop_eq(&mut self) -> Result<(), RuntimeError> {
+        let rhs = self.pop_var()?;
+        let lhs = self.pop_var()?;
+        let value = if numeric_eq(&lhs.borrow(), &rhs.borrow()).map_err(|desc| self.error(desc))? {Fraction::one()}
+                    else                                            {Fraction::zero()};
         let var = Rc::new(RefCell::new(Variable::Frac(value)));
         self.stack.push(StackObject::Var(var));
+        Ok(())
     }
 
-    fn binop_neq(&mut self) {
-        let rhs = self.pop_var();
-        let lhs = self.pop_var();
-        let value = if *lhs.borrow() != *rhs.borrow() {Fraction::one()}
-                    else                              {Fraction::zero()};
+    fn binop_neq(&mut self) -> Result<(), RuntimeError> {
+        let rhs = self.pop_var()?;
+        let lhs = self.pop_var()?;
+        let value = if !numeric_eq(&lhs.borrow(), &rhs.borrow()).map_err(|desc| self.error(desc))? {Fraction::one()}
+                    else                                             {Fraction::zero()};
         let var = Rc::new(RefCell::new(Variable::Frac(value)));
         self.stack.push(StackObject::Var(var));
+        Ok(())
     }
 
-    fn uniop_neg(&mut self) {
-        let expr = self.pop_var();
+    fn uniop_neg(&mut self) -> Result<(), RuntimeError> {
+        let expr = self.pop_var()?;
         let result = match &*expr.borrow() {
-            Variable::Frac(x) => Variable::Frac(-x),
-            _ => panic!("The negation operation is only supported by numbers"),
+            Variable::Float(x) => Ok(Variable::Float(-x)),
+            Variable::Frac(x) => match self.arithmetic {
+                Arithmetic::Exact => Ok(-x),
+                Arithmetic::Checked => to_fixed_width(x).and_then(|x| {
+                    x.checked_neg().map(from_fixed_width).ok_or_else(|| String::from("Integer overflow in negation"))
+                }),
+                Arithmetic::Wrapping => to_fixed_width(x).map(|x| from_fixed_width(x.wrapping_neg()))
+            }.map(Variable::Frac),
+            _ => Err(String::from("The negation operation is only supported by numbers")),
         };
+        let result = result.map_err(|desc| self.error(desc))?;
         self.stack.push(StackObject::Var(Rc::new(RefCell::new(result))));
+        Ok(())
     }
 
-    fn uniop_not(&mut self) {
-        let expr = self.pop_var();
-        let result = if expr.borrow().to_bool() {
+    fn uniop_to_float(&mut self) -> Result<(), RuntimeError> {
+        let expr = self.pop_var()?;
+        let result = match &*expr.borrow() {
+            Variable::Frac(x) => Variable::Float(fraction_to_f64(x).map_err(|desc| self.error(desc))?),
+            Variable::Float(x) => Variable::Float(*x),
+            _ => return Err(self.error(String::from("to_float is only supported by numbers")))
+        };
+        self.stack.push(StackObject::Var(Rc::new(RefCell::new(result))));
+        Ok(())
+    }
+
+    fn uniop_to_frac(&mut self) -> Result<(), RuntimeError> {
+        let expr = self.pop_var()?;
+        let result = match &*expr.borrow() {
+            Variable::Float(x) => match Fraction::from_float(*x) {
+                Some(value) => Variable::Frac(value),
+                None => return Err(self.error(String::from("to_frac of an infinite or NaN float")))
+            },
+            Variable::Frac(x) => Variable::Frac(x.clone()),
+            _ => return Err(self.error(String::from("to_frac is only supported by numbers")))
+        };
+        self.stack.push(StackObject::Var(Rc::new(RefCell::new(result))));
+        Ok(())
+    }
+
+    // The transcendental builtins, computed on floats //
+    fn uniop_float(&mut self, name: &str, func: fn(f64) -> f64) -> Result<(), RuntimeError> {
+        let expr = self.pop_var()?;
+        let result = match &*expr.borrow() {
+            Variable::Frac(x) => Variable::Float(func(fraction_to_f64(x).map_err(|desc| self.error(desc))?)),
+            Variable::Float(x) => Variable::Float(func(*x)),
+            _ => return Err(self.error(format!("{} is only supported by numbers", name)))
+        };
+        self.stack.push(StackObject::Var(Rc::new(RefCell::new(result))));
+        Ok(())
+    }
+
+    fn uniop_not(&mut self) -> Result<(), RuntimeError> {
+        let result = if self.pop_bool()? {
             Variable::Frac(Fraction::zero())
         } else {
             Variable::Frac(Fraction::one())
@@ -606,11 +2064,12 @@ impl<'a> Interpreter<'a> {
         self.stack.push(
             StackObject::Var(Rc::new(RefCell::new(result)))
         );
+        Ok(())
     }
 
-    fn uniop_len(&mut self) {
-        let expr = self.pop_var();
-        let len = expr.borrow().get_array_length();
+    fn uniop_len(&mut self) -> Result<(), RuntimeError> {
+        let expr = self.pop_var()?;
+        let len = expr.borrow().get_array_length().map_err(|desc| self.error(desc))?;
         self.stack.push(
             StackObject::Var(Rc::new(RefCell::new(
                 Variable::Frac(Fraction::from_integer(
@@ -618,98 +2077,964 @@ impl<'a> Interpreter<'a> {
                 ))
             )))
         );
+        Ok(())
     }
 
-    fn pull(&mut self, register: usize) {
-        let new_var = match &mut *self.pop_var().borrow_mut() {
+    fn pull(&mut self, register: usize) -> Result<(), RuntimeError> {
+        let new_var = match &mut *self.pop_var()?.borrow_mut() {
             Variable::Array(items) => match items.pop() {
                 Some(item) => item,
-                None => panic!("Pulling from empty array")
+                None => return Err(self.error(String::from("Pulling from empty array")))
             },
-            _ => panic!("Pulling is only supported by arrays")
+            _ => return Err(self.error(String::from("Pulling is only supported by arrays")))
         };
-        replace(
-            self.registers.get_mut(register).unwrap(),
-            Some(new_var)
-        );
+        *self.register_mut(register)? = Some(new_var);
+        Ok(())
+    }
+
+    fn push(&mut self, register: usize) -> Result<(), RuntimeError> {
+        let src_ref = match self.register_mut(register)?.take() {
+            Some(var) => var,
+            None => return Err(self.error(format!("Pushing {} before it has a value", self.register_name(register))))
+        };
+        match &mut *self.pop_var()?.borrow_mut() {
+            Variable::Array(items) => {
+                self.check_memory(items.len() + 1, "An array")?;
+                items.push(src_ref);
+            },
+            _ => return Err(self.error(String::from("Pushing is only supported by arrays")))
+        }
+        Ok(())
+    }
+
+    fn pull_front(&mut self, register: usize) -> Result<(), RuntimeError> {
+        let new_var = match &mut *self.pop_var()?.borrow_mut() {
+            Variable::Array(items) if items.is_empty() => return Err(self.error(String::from("Pulling from empty array"))),
+            Variable::Array(items) => items.remove(0),
+            _ => return Err(self.error(String::from("Pulling is only supported by arrays")))
+        };
+        *self.register_mut(register)? = Some(new_var);
+        Ok(())
+    }
+
+    fn push_front(&mut self, register: usize) -> Result<(), RuntimeError> {
+        let src_ref = match self.register_mut(register)?.take() {
+            Some(var) => var,
+            None => return Err(self.error(format!("Pushing {} before it has a value", self.register_name(register))))
+        };
+        match &mut *self.pop_var()?.borrow_mut() {
+            Variable::Array(items) => {
+                self.check_memory(items.len() + 1, "An array")?;
+                items.insert(0, src_ref);
+            },
+            _ => return Err(self.error(String::from("Pushing is only supported by arrays")))
+        }
+        Ok(())
+    }
+
+    // Describe a register of the current frame by the variable it holds here, if known //
+    fn register_name(&self, register: usize) -> String {
+        let symbols = self.functions.get(self.func_idx).map_or(&[][..], |func| &func.symbols);
+        symbols.iter()
+            .find(|s| s.register == register && s.is_live(self.forwards, self.ip))
+            .map_or(format!("register {}", register), |s| format!("\"{}\"", s.name))
     }
 
-    fn push(&mut self, register: usize) {
-        let src_ref = replace(
-            self.registers.get_mut(register).unwrap(),
-            None
-        ).unwrap();
-        match &mut *self.pop_var().borrow_mut() {
-            Variable::Array(items) => items.push(src_ref),
-            _ => panic!("Pushing is only supported by arrays")
+    // An expectation holds at the same point in both directions, so failing names the direction //
+    fn expect(&mut self) -> Result<(), RuntimeError> {
+        let desc = self.pop_var()?.borrow().to_string();
+        if !self.pop_bool()? {
+            return Err(self.error(format!(
+                "Expectation \"{}\" failed running {}", desc, if self.forwards {"forwards"} else {"backwards"})));
         }
+        Ok(())
     }
 
-    fn print(&mut self, count: isize) {
+    fn expect_equal(&mut self) -> Result<(), RuntimeError> {
+        let desc = self.pop_var()?.borrow().to_string();
+        let expected = self.pop_var()?;
+        let actual = self.pop_var()?;
+        if !numeric_eq(&actual.borrow(), &expected.borrow()).map_err(|desc| self.error(desc))? {
+            return Err(self.error(format!("Expectation \"{}\" failed running {}: expected {}, got {}",
+                desc, if self.forwards {"forwards"} else {"backwards"}, expected.borrow(), actual.borrow())));
+        }
+        Ok(())
+    }
+
+    fn print(&mut self, count: isize) -> Result<(), RuntimeError> {
+        self.check_sandbox("Printing")?;
         for _ in 0..count.abs() {
-            print!("{}", self.pop_var().borrow());
+            let text = self.pop_var()?.borrow().to_string();
+            self.write_output(&text);
         }
         if count < 0 {
-            print!("\n");
+            self.write_output("\n");
         }
-    } 
+        Ok(())
+    }
+
+    fn push_count(&mut self, count: u64) {
+        self.stack.push(StackObject::Var(Rc::new(RefCell::new(
+            Variable::Frac(Fraction::from_integer(num_bigint::BigInt::from(count)))
+        ))));
+    }
+
+    fn write_output(&mut self, text: &str) {
+        match &mut self.output {
+            Some(output) => output.push_str(text),
+            None => print!("{}", text)
+        }
+    }
 
-    fn create_iter(&mut self, register: usize) {
-        let var = self.pop_var();
-        let array_len = match &*var.borrow() {
+    fn create_iter(&mut self, register: usize, reversed: bool, index_register: Option<usize>, depth: usize) -> Result<(), RuntimeError> {
+        let root = self.pop_var()?;
+        let path = (0..depth).map(|_| self.pop_usize()).collect::<Result<Vec<usize>, _>>()?;
+        let array = IterSource::array(&root, &path).map_err(|desc| self.error(desc))?;
+        let array_len = match &*array.borrow() {
             Variable::Array(array) => array.len(),
-            _ => panic!("For loop iterator is not an array")
+            _ => return Err(self.error(String::from("For loop iterator is not an array")))
         };
-        let idx = if self.forwards {-1}
-                  else {array_len as isize};
-        let iter_state = IterState{register, var, idx};
+        let iter_state = IterState{register, source: IterSource::Array{root, path}, idx: 0, reversed, index_register};
+        self.push_iter(iter_state, array_len);
+        Ok(())
+    }
+
+    fn create_range_iter(&mut self, register: usize) -> Result<(), RuntimeError> {
+        let step = self.pop_var()?;
+        let end = self.pop_var()?;
+        let start = self.pop_var()?;
+        let (start, end, step) = match (&*start.borrow(), &*end.borrow(), &*step.borrow()) {
+            (Variable::Frac(start), Variable::Frac(end), Variable::Frac(step)) => {
+                (start.clone(), end.clone(), step.clone())
+            },
+            _ => return Err(self.error(String::from("Range bounds and step must be numbers")))
+        };
+        if step.is_zero() {
+            return Err(self.error(String::from("Range step is zero")));
+        }
+        let steps = ((end - &start) / &step).ceil();
+        let len = match steps > Fraction::zero() {
+            true => steps.to_integer().to_usize().ok_or_else(|| self.error(String::from("Range is too long")))?,
+            false => 0
+        };
+        let source = IterSource::Range{start, step, len};
+        self.push_iter(IterState{register, source, idx: 0, reversed: false, index_register: None}, len);
+        Ok(())
+    }
+
+    // Iterating backwards, or a reversed iterator forwards, starts from the end //
+    fn push_iter(&mut self, mut iter_state: IterState, len: usize) {
+        iter_state.idx = if self.forwards != iter_state.reversed {-1}
+                         else {len as isize};
         self.stack.push(StackObject::Iter(iter_state));
     }
 
-    fn step_iter(&mut self, ip: usize) {
-        // Get iterator state off the stack
-        let (idx, var, register) = match self.stack.last_mut() {
-            Some(StackObject::Iter(IterState{idx, var, register})) => (idx, var.borrow(), *register),
-            _ => panic!("No IterState on the stack")
+    fn step_iter(&mut self, ip: usize) -> Result<(), RuntimeError> {
+        // Find the next item from the iterator state on the stack, or None if it's exhausted. The
+        // length is checked each time, in case the body resized the array //
+        let (register, index_register, next) = match self.stack.last() {
+            Some(StackObject::Iter(IterState{idx, source, register, reversed, index_register})) => {
+                let ascending = self.forwards != *reversed;
+                let (len, array) = match source {
+                    IterSource::Array{root, path} => {
+                        let array = IterSource::array(root, path).map_err(|desc| self.error(desc))?;
+                        let len = match &*array.borrow() {
+                            Variable::Array(items) => items.len(),
+                            _ => return Err(self.error(String::from("For loop iterator is not an array")))
+                        };
+                        (len, Some(array))
+                    },
+                    IterSource::Range{len, ..} => (*len, None)
+                };
+                let next = if (ascending && *idx + 1 >= len as isize) || (!ascending && *idx <= 0) {
+                    None
+                } else {
+                    let idx = *idx + if ascending {1} else {-1};
+                    let item = match (array, source) {
+                        (Some(array), _) => Rc::clone(array.borrow().item(idx as usize).map_err(|desc| self.error(desc))?),
+                        (None, IterSource::Range{start, step, ..}) => Rc::new(RefCell::new(Variable::Frac(
+                            start + step * Fraction::from_integer(num_bigint::BigInt::from(idx))
+                        ))),
+                        (None, IterSource::Array{..}) => unreachable!()
+                    };
+                    Some((idx, item))
+                };
+                (*register, *index_register, next)
+            },
+            _ => return Err(self.error(String::from("No IterState on the stack")))
+        };
+
+        // Step iteration, or jump to after loop if iterator exhausted //
+        match next {
+            None => {
+                self.pop()?;
+                *self.register_mut(register)? = None;
+                if let Some(index_register) = index_register {
+                    *self.register_mut(index_register)? = None;
+                }
+                self.jump(ip);
+            },
+            Some((idx, item)) => {
+                if let Some(StackObject::Iter(iter_state)) = self.stack.last_mut() {
+                    iter_state.idx = idx;
+                }
+                if let Some(index_register) = index_register {
+                    let index = Rc::new(RefCell::new(Variable::Frac(
+                        Fraction::from_integer(num_bigint::BigInt::from(idx))
+                    )));
+                    *self.register_mut(index_register)? = Some(index);
+                }
+                *self.register_mut(register)? = Some(item);
+                self.ip += 1;
+            }
+        }
+        Ok(())
+    }
+
+    // Without the parallel feature a parfor steps just like a for loop //
+    fn parallel_step_iter(&mut self, ip: usize) -> Result<(), RuntimeError> {
+        #[cfg(feature = "parallel")]
+        self.run_iterations(ip)?;
+        self.step_iter(ip)
+    }
+
+    // Suspend a new frame of the generator at func_idx, holding the args it borrows, before its
+    // first instruction in the current direction //
+    fn create_generator(&mut self, func_idx: usize) -> Result<(), RuntimeError> {
+        let func: &'a Function = self.functions.get(func_idx)
+            .ok_or_else(|| self.error(String::from("Call to undefined function")))?;
+        let base = self.stack_base(func.num_borrows)?;
+        let stack = self.stack.split_off(base);
+        let registers = self.frame_pool.take(func_idx, func.num_registers);
+        self.stack.push(StackObject::Generator(Suspended{func_idx, ip: 0, forwards: self.forwards, registers, stack}));
+        Ok(())
+    }
+
+    // Enter the frame of the generator on the stack as though calling it, putting back what it had
+    // on the stack. It returns to the loop by yielding the next value into register, or by
+    // finishing, which jumps to exit //
+    fn resume_generator(&mut self, register: usize, exit: usize) -> Result<(), RuntimeError> {
+        *self.register_mut(register)? = None;
+        let generator = match self.pop()? {
+            StackObject::Generator(generator) => generator,
+            _ => return Err(self.error(String::from("No generator on the stack")))
+        };
+        if generator.forwards != self.forwards {
+            return Err(self.error(String::from(
+                "Resumed a generator in the opposite direction to the one it was suspended in")));
+        }
+        let func: &'a Function = &self.functions[generator.func_idx];
+        log::trace!("Resuming {} at depth {}", func.name, self.scope_stack.len());
+        self.frame_values += generator.registers.len();
+        self.check_memory(self.frame_values, "The call stack")?;
+        self.scope_stack.push(
+            Scope{
+                code      : replace(&mut self.code     , &func.code),
+                consts    : replace(&mut self.consts   , &func.consts),
+                registers : replace(&mut self.registers, generator.registers),
+                ip        : replace(&mut self.ip       , generator.ip),
+                forwards  : self.forwards,
+                func_idx  : replace(&mut self.func_idx , generator.func_idx)
+            }
+        );
+        self.resumed.push(Resumed{depth: self.scope_stack.len(), base: self.stack.len(), register, exit});
+        self.stack.extend(generator.stack);
+        if let Some(timeline) = &mut self.timeline {
+            timeline.enter_frame();
+        }
+        Ok(())
+    }
+
+    // Hand the yielded value to the loop that resumed this generator, putting the generator's frame
+    // and what it has on the stack in its place. The value is copied if the generator still holds
+    // it, so the loop can't see it change //
+    fn suspend(&mut self) -> Result<(), RuntimeError> {
+        let value = self.pop_var()?;
+        let value = match Rc::strong_count(&value) {
+            1 => value,
+            _ => Rc::new(RefCell::new(value.borrow().deep_copy()))
         };
-        let array = match &*var {
-            Variable::Array(array) => array,
-            _ => panic!("For loop iterator is not an array")
+        let resumed = match self.resumed.pop() {
+            Some(resumed) if resumed.depth == self.scope_stack.len() => resumed,
+            _ => return Err(self.error(String::from("Yielded outside a loop over the generator")))
         };
+        let stack = self.stack.split_off(resumed.base);
+        let scope = self.scope_stack.pop().ok_or_else(|| self.error(String::from("Returned from the global scope")))?;
+        if let Some(timeline) = &mut self.timeline {
+            timeline.leave_frame();
+        }
+        self.code = scope.code;
+        self.consts = scope.consts;
+        let generator = Suspended{
+            func_idx  : replace(&mut self.func_idx , scope.func_idx),
+            ip        : self.ip + 1,
+            forwards  : self.forwards,
+            registers : replace(&mut self.registers, scope.registers),
+            stack
+        };
+        self.frame_values -= generator.registers.len();
+        self.stack.push(StackObject::Generator(generator));
+        *self.register_mut(resumed.register)? = Some(value);
+        self.ip = scope.ip + 1;
+        Ok(())
+    }
 
-        // Step iteration, or jump to after loop if iterator exhausted
-        if (self.forwards && *idx == array.len() as isize - 1) || 
-           (!self.forwards && *idx == 0) {
-            drop(var);
-            self.pop();
-            self.registers[register] = None;
-            self.jump(ip);
+    // Run the body registered for this #[host] function on its args. A call leaves its borrows on
+    // top with the first uppermost, above what it steals, while an uncall pushes what it passes in
+    // order, so backwards the host is given what was returned and gives back what was stolen //
+    fn host_call(&mut self) -> Result<(), RuntimeError> {
+        self.check_sandbox("Calling a host function")?;
+        let func: &'a Function = &self.functions[self.func_idx];
+        if !self.host_functions.contains_key(&func.name) {
+            return Err(self.error(format!("No host function is registered as \"{}\"", func.name)));
+        }
+        let (num_taken, num_given) = match self.forwards {
+            true => (func.num_steals, func.num_returns),
+            false => (func.num_returns, func.num_steals)
+        };
+        let base = self.stack_base(func.num_borrows + num_taken)?;
+        let args: Option<Vec<Variable>> = self.stack.split_off(base).into_iter().map(|object| match object {
+            StackObject::Var(var) => Some(Rc::try_unwrap(var).map_or_else(|var| var.borrow().clone(), RefCell::into_inner)),
+            _ => None
+        }).collect();
+        let mut args = args.ok_or_else(|| self.error(String::from("Non-variable found on the stack")))?;
+        let host = self.host_functions.get_mut(&func.name).unwrap();
+        let given = if self.forwards {
+            args.reverse();
+            let taken = args.split_off(func.num_borrows);
+            host.call(&mut self.handles, &args, taken)
         } else {
-            *idx += if self.forwards {1} else {-1};
-            self.registers[register] = Some(Rc::clone(&array[*idx as usize]));
-            self.ip += 1;
+            let borrowed = args.split_off(num_taken);
+            host.uncall(&mut self.handles, &borrowed, args).map(|mut given| {given.reverse(); given})
         };
+        let given = given.map_err(|desc| self.caller_error(format!("In host function \"{}\": {}", func.name, desc)))?;
+        if given.len() != num_given {
+            return Err(self.error(format!(
+                "Host function \"{}\" gave back {} value(s), but should give {}", func.name, given.len(), num_given)));
+        }
+        self.stack.extend(given.into_iter().map(|value| StackObject::Var(Rc::new(RefCell::new(value)))));
+        Ok(())
     }
 
     #[inline]
-    fn pop(&mut self) -> StackObject {
-        self.stack.pop().expect("Popped off empty stack")
+    fn pop(&mut self) -> Result<StackObject, RuntimeError> {
+        self.stack.pop().ok_or_else(|| self.error(String::from("Popped off empty stack")))
+    }
+
+    #[inline]
+    fn pop_var(&mut self) -> Result<Rc<RefCell<Variable>>, RuntimeError> {
+        match self.pop()? {
+            StackObject::Var(x) => Ok(x),
+            _ => Err(self.error(String::from("Non-variable found on the stack")))
+        }
     }
 
     #[inline]
-    fn pop_var(&mut self) -> Rc<RefCell<Variable>> {
-        match self.pop() {
-            StackObject::Var(x) => x,
-            _ => panic!("Non-variable found on the stack")
+    fn pop_bool(&mut self) -> Result<bool, RuntimeError> {
+        let var = self.pop_var()?;
+        let value = var.borrow().to_bool();
+        value.map_err(|desc| self.error(desc))
+    }
+
+    #[inline]
+    fn pop_usize(&mut self) -> Result<usize, RuntimeError> {
+        let var = self.pop_var()?;
+        let value = var.borrow().to_usize();
+        value.map_err(|desc| self.error(desc))
+    }
+
+    // Where the top num_objects objects on the stack start //
+    #[inline]
+    fn stack_base(&self, num_objects: usize) -> Result<usize, RuntimeError> {
+        self.stack.len().checked_sub(num_objects).ok_or_else(|| self.error(String::from("Popped off empty stack")))
+    }
+
+    // Print each frame's function and the statement it is executing, innermost first. Callers
+    // are positioned at the statement making the call //
+    fn backtrace(&mut self) -> Result<(), RuntimeError> {
+        self.check_sandbox("Printing a backtrace")?;
+        let mut frames = vec![(self.func_idx, self.forwards, self.ip, self.code)];
+        for scope in self.scope_stack.iter().rev() {
+            frames.push((scope.func_idx, scope.forwards, scope.ip, scope.code));
+        }
+        let mut text = String::from("Backtrace, innermost call first:\n");
+        for (depth, (func_idx, forwards, ip, code)) in frames.into_iter().enumerate() {
+            let name = self.functions.get(func_idx).map_or("?", |func| func.name.as_str());
+            let direction = if forwards {"fwd"} else {"bkwd"};
+            text.push_str(&match code.statement_position(forwards, ip) {
+                Some((line, col)) if line > 0 => format!(
+                    "  {}: {} ({}) at line {}, column {}\n", depth, name, direction, line, col),
+                _ => format!("  {}: {} ({} ip {})\n", depth, name, direction, ip)
+            });
         }
+        self.write_output(&text);
+        Ok(())
     }
 
-    pub fn debug_print(&self) {
+    pub fn debug_print(&self) -> Result<(), RuntimeError> {
+        self.check_sandbox("Printing")?;
         println!(
             "registers: {:#?}\nglobals: {:#?}\nStack: {:#?}\n----------", 
             self.registers,
             self.global_registers,
             self.stack);
+        Ok(())
+    }
+}
+
+
+
+// ------------------------------ State dumps ------------------------------ //
+
+// A dump is a header line holding the format version and a checksum of the rest of the file,
+// followed by the state as JSON. Variables are stored once each in a table, so aliases made by
+// references are restored as aliases //
+const STATE_VERSION: u32 = 1;
+pub const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+#[derive(Debug)]
+pub struct StateError {
+    pub desc: String
+}
+
+impl StateError {
+    fn new(desc: &str) -> StateError {
+        StateError{desc: desc.to_string()}
     }
 }
 
+// Why a function couldn't be swapped into a loaded module by ST::Module::reload_function //
+#[derive(Debug)]
+pub struct ReloadError {
+    pub desc: String
+}
+
+// Extend a memo table key with a value, tagged by type so that e.g. 1 and '1' differ //
+fn memo_key(var: &Variable, key: &mut String) {
+    match var {
+        Variable::Frac(val) => key.push_str(&format!("n{};", val)),
+        Variable::Float(val) => key.push_str(&format!("f{:?};", val)),
+        Variable::Str(string) => key.push_str(&format!("s{}:{}", string.len(), string)),
+        Variable::Handle(id) => key.push_str(&format!("h{};", id)),
+        Variable::Array(items) => {
+            key.push('[');
+            for item in items {
+                memo_key(&item.borrow(), key);
+            }
+            key.push(']');
+        }
+    }
+}
+
+pub fn fnv1a(bytes: &[u8], hash: u64) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+// Identifies the compiled program a dump was taken from //
+fn module_fingerprint(functions: &[Function]) -> u64 {
+    functions.iter().fold(FNV_OFFSET, |hash, func| {
+        fnv1a(format!("{:?}{:?}", func.code.fwd, func.code.bkwd).as_bytes(), hash)
+    })
+}
+
+#[derive(Default)]
+struct VariableTable {
+    ids: HashMap<*const RefCell<Variable>, usize>,
+    entries: Vec<Value>
+}
+
+impl VariableTable {
+    // Array items always get higher ids than the array holding them //
+    fn id(&mut self, var: &Rc<RefCell<Variable>>) -> usize {
+        if let Some(&id) = self.ids.get(&Rc::as_ptr(var)) {return id}
+        let id = self.entries.len();
+        self.ids.insert(Rc::as_ptr(var), id);
+        self.entries.push(Value::Null);
+        self.entries[id] = match &*var.borrow() {
+            Variable::Frac(value) => json!({"frac": value.to_string()}),
+            Variable::Float(value) => json!({"float": value}),
+            Variable::Str(value) => json!({"str": value}),
+            Variable::Handle(id) => json!({"handle": id}),
+            Variable::Array(items) => json!({"array": items.iter().map(|item| self.id(item)).collect::<Vec<_>>()})
+        };
+        id
+    }
+
+    fn registers(&mut self, registers: &[Option<Rc<RefCell<Variable>>>]) -> Value {
+        registers.iter().map(|var| var.as_ref().map(|var| self.id(var))).collect()
+    }
+
+    // Suspended generators hold their own frame and stack, so are stored with them //
+    fn stack(&mut self, stack: &[StackObject]) -> Value {
+        stack.iter().map(|object| match object {
+            StackObject::Var(var) => json!({"var": self.id(var)}),
+            StackObject::Iter(IterState{idx, register, source, reversed, index_register}) => {
+                let mut iter = json!({
+                    "idx": idx, "register": register, "reversed": reversed, "index_register": index_register
+                });
+                match source {
+                    IterSource::Array{root, path} => {
+                        iter["var"] = json!(self.id(root));
+                        iter["path"] = json!(path);
+                    },
+                    IterSource::Range{start, step, len} => iter["range"] = json!({
+                        "start": start.to_string(), "step": step.to_string(), "len": len
+                    })
+                }
+                json!({"iter": iter})
+            },
+            StackObject::Generator(Suspended{func_idx, ip, forwards, registers, stack}) => json!({"generator": {
+                "func": func_idx, "ip": ip, "forwards": forwards,
+                "registers": self.registers(registers), "stack": self.stack(stack)
+            }})
+        }).collect()
+    }
+}
+
+fn restore_variables(entries: &[Value]) -> Result<Vec<Rc<RefCell<Variable>>>, StateError> {
+    let mut vars: Vec<Option<Rc<RefCell<Variable>>>> = vec![None; entries.len()];
+    for (id, entry) in entries.iter().enumerate().rev() {
+        let var = if let Some(value) = entry["frac"].as_str() {
+            Variable::Frac(value.parse().map_err(|_| StateError::new("Malformed number"))?)
+        } else if let Some(value) = entry["float"].as_f64() {
+            Variable::Float(value)
+        } else if let Some(value) = entry["str"].as_str() {
+            Variable::Str(value.to_string())
+        } else if let Some(items) = entry["array"].as_array() {
+            let items = items.iter()
+                .map(|item| item.as_u64()
+                    .filter(|&item| item as usize > id)
+                    .and_then(|item| vars.get(item as usize).cloned().flatten())
+                    .ok_or(StateError::new("Malformed array")))
+                .collect::<Result<_, _>>()?;
+            Variable::Array(items)
+        } else {
+            return Err(StateError::new("Malformed variable"));
+        };
+        vars[id] = Some(Rc::new(RefCell::new(var)));
+    }
+    Ok(vars.into_iter().flatten().collect())
+}
+
+fn restore_stack(value: &Value, vars: &[Rc<RefCell<Variable>>], module: &Module) -> Result<Vec<StackObject>, StateError> {
+    let var = |id: &Value| id.as_u64().and_then(|id| vars.get(id as usize)).map(Rc::clone)
+                             .ok_or(StateError::new("Malformed stack"));
+    let frac = |value: &Value| value.as_str().and_then(|value| value.parse().ok())
+                                    .ok_or(StateError::new("Malformed stack"));
+    let mut stack = Vec::new();
+    for object in value.as_array().ok_or(StateError::new("Missing stack"))? {
+        stack.push(if let Some(iter) = object.get("iter") {
+            let source = match iter.get("range") {
+                Some(range) => IterSource::Range{
+                    start: frac(&range["start"])?,
+                    step: frac(&range["step"])?,
+                    len: range["len"].as_u64().ok_or(StateError::new("Malformed stack"))? as usize
+                },
+                None => IterSource::Array{
+                    root: var(&iter["var"])?,
+                    path: iter["path"].as_array().map_or(Ok(Vec::new()), |path| path.iter()
+                        .map(|index| index.as_u64().map(|index| index as usize))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or(StateError::new("Malformed stack")))?
+                }
+            };
+            StackObject::Iter(IterState{
+                idx: iter["idx"].as_i64().ok_or(StateError::new("Malformed stack"))? as isize,
+                register: iter["register"].as_u64().ok_or(StateError::new("Malformed stack"))? as usize,
+                source,
+                reversed: iter["reversed"].as_bool().unwrap_or(false),
+                index_register: iter["index_register"].as_u64().map(|r| r as usize)
+            })
+        } else if let Some(generator) = object.get("generator") {
+            let func_idx = generator["func"].as_u64().map(|f| f as usize)
+                .filter(|&f| f < module.functions.len())
+                .ok_or(StateError::new("Malformed generator"))?;
+            let func = &module.functions[func_idx];
+            let forwards = generator["forwards"].as_bool().ok_or(StateError::new("Malformed generator"))?;
+            let ip = generator["ip"].as_u64().map(|ip| ip as usize)
+                .filter(|&ip| ip <= if forwards {func.code.fwd.len()} else {func.code.bkwd.len()})
+                .ok_or(StateError::new("Malformed generator"))?;
+            StackObject::Generator(Suspended{
+                func_idx, ip, forwards,
+                registers: restore_registers(&generator["registers"], vars, func.num_registers)?,
+                stack: restore_stack(&generator["stack"], vars, module)?
+            })
+        } else {
+            StackObject::Var(var(&object["var"])?)
+        });
+    }
+    Ok(stack)
+}
+
+fn restore_registers(
+    value: &Value, vars: &[Rc<RefCell<Variable>>], num_registers: usize
+) -> Result<Vec<Option<Rc<RefCell<Variable>>>>, StateError> {
+    let registers = value.as_array().ok_or(StateError::new("Malformed registers"))?;
+    if registers.len() != num_registers {
+        return Err(StateError::new("Wrong number of registers"));
+    }
+    registers.iter().map(|register| match register {
+        Value::Null => Ok(None),
+        _ => register.as_u64()
+            .and_then(|id| vars.get(id as usize))
+            .map(|var| Some(Rc::clone(var)))
+            .ok_or(StateError::new("Malformed register"))
+    }).collect()
+}
+
+impl<'a> Interpreter<'a> {
+    // Run whatever remains of the program: the rest of the global scope if it is still
+    // running, then main. Failing panics with the error, for callers that can't carry on //
+    pub fn run_to_completion(&mut self, module: &Module) {
+        self.try_run_to_completion(module).unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    // Run the global scope, then call the function at func_idx in place of main with the given
+    // values for its borrowed params, returning whatever it returns //
+    pub fn run_entry(&mut self, module: &Module, func_idx: usize, args: Vec<Variable>) -> Vec<Variable> {
+        self.try_run_entry(module, func_idx, args).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    // As run_entry, with the args given as rust values and the returns read into one, as an array
+    // of them all, e.g. into a Vec or a struct converted by reaver_struct! //
+    pub fn run_entry_as<R: FromReaver>(
+        &mut self, module: &Module, func_idx: usize, args: Vec<impl IntoReaver>
+    ) -> Result<R, ConversionError> {
+        let args = args.into_iter().map(IntoReaver::into_reaver).collect();
+        R::from_reaver(&array_of(self.run_entry(module, func_idx, args)))
+    }
+
+    pub fn try_run_entry(&mut self, module: &Module, func_idx: usize, args: Vec<Variable>) -> Result<Vec<Variable>, RuntimeError> {
+        check_specialised(&module.functions[func_idx], &args).map_err(|desc| self.error(desc))?;
+        if self.scope_stack.is_empty() && self.func_idx == module.global_func_idx {
+            self.execute()?;
+        }
+        for arg in args.into_iter().rev() {
+            self.stack.push(StackObject::Var(Rc::new(RefCell::new(arg))));
+        }
+        self.call(func_idx, true)?;
+        self.execute()?;
+        let mut returns = (0..module.functions[func_idx].num_returns)
+            .map(|_| self.pop_var().map(|var| var.borrow().deep_copy()))
+            .collect::<Result<Vec<Variable>, _>>()?;
+        returns.reverse();
+        Ok(returns)
+    }
+
+    // Run the global scope, then uncall the function at func_idx on the values it would have
+    // returned, with the given values for its borrowed params, returning what it would have stolen //
+    pub fn uncall_entry(&mut self, module: &Module, func_idx: usize, args: Vec<Variable>, outputs: Vec<Variable>) -> Vec<Variable> {
+        self.try_uncall_entry(module, func_idx, args, outputs).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    pub fn try_uncall_entry(
+        &mut self, module: &Module, func_idx: usize, args: Vec<Variable>, outputs: Vec<Variable>
+    ) -> Result<Vec<Variable>, RuntimeError> {
+        check_specialised(&module.functions[func_idx], &args).map_err(|desc| self.error(desc))?;
+        if self.scope_stack.is_empty() && self.func_idx == module.global_func_idx {
+            self.execute()?;
+        }
+        for value in outputs.into_iter().chain(args) {
+            self.stack.push(StackObject::Var(Rc::new(RefCell::new(value))));
+        }
+        self.call(func_idx, false)?;
+        self.execute()?;
+        (0..module.functions[func_idx].num_steals)
+            .map(|_| self.pop_var().map(|var| var.borrow().deep_copy()))
+            .collect()
+    }
+
+    pub fn try_run_to_completion(&mut self, module: &Module) -> Result<(), RuntimeError> {
+        if self.scope_stack.is_empty() && self.func_idx == module.global_func_idx {
+            self.execute()?;
+            let main_idx = module.main_idx.ok_or_else(|| self.error(String::from("No main function")))?;
+            self.call(main_idx, true)?;
+        }
+        self.execute()?;
+        Ok(())
+    }
+
+    pub fn dump_state(&self, path: &Path) -> Result<(), StateError> {
+        // Host objects live outside the interpreter, so couldn't be brought back //
+        if self.handles.live() > 0 {
+            return Err(StateError::new("The program holds handles to host objects, which can't be saved"));
+        }
+        let mut table = VariableTable::default();
+        let frame = |table: &mut VariableTable, func_idx: usize, ip: usize, forwards: bool, registers: &[_]| json!({
+            "func": func_idx, "ip": ip, "forwards": forwards, "registers": table.registers(registers)
+        });
+        let scopes: Vec<Value> = self.scope_stack.iter()
+            .map(|scope| frame(&mut table, scope.func_idx, scope.ip, scope.forwards, &scope.registers))
+            .collect();
+        let current = frame(&mut table, self.func_idx, self.ip, self.forwards, &self.registers);
+        let globals = table.registers(&self.global_registers);
+        let stack = table.stack(&self.stack);
+        let resumed: Vec<Value> = self.resumed.iter().map(|Resumed{depth, base, register, exit}| json!({
+            "depth": depth, "base": base, "register": register, "exit": exit
+        })).collect();
+
+        let payload = json!({
+            "module": format!("{:016x}", module_fingerprint(self.functions)),
+            "variables": table.entries,
+            "scopes": scopes,
+            "current": current,
+            "globals": globals,
+            "stack": stack,
+            "resumed": resumed
+        }).to_string();
+        let checksum = fnv1a(payload.as_bytes(), FNV_OFFSET);
+        fs::write(path, format!("monoxide-state {} {:016x}\n{}", STATE_VERSION, checksum, payload))
+            .map_err(|err| StateError{desc: err.to_string()})
+    }
+
+    // Restore an interpreter from a dump of the same compiled program //
+    pub fn load_state(module: &'a Module, path: &Path) -> Result<Interpreter<'a>, StateError> {
+        let contents = fs::read_to_string(path).map_err(|err| StateError{desc: err.to_string()})?;
+        let (header, payload) = contents.split_once('\n').ok_or(StateError::new("Missing header"))?;
+        let header: Vec<&str> = header.split(' ').collect();
+        match header.as_slice() {
+            ["monoxide-state", version, checksum] => {
+                if version.parse() != Ok(STATE_VERSION) {
+                    return Err(StateError{desc: format!("Unsupported state version {}", version)});
+                }
+                if u64::from_str_radix(checksum, 16).ok() != Some(fnv1a(payload.as_bytes(), FNV_OFFSET)) {
+                    return Err(StateError::new("Checksum mismatch, the state is corrupt"));
+                }
+            },
+            _ => return Err(StateError::new("Not a monoxide state dump"))
+        }
+        let state: Value = serde_json::from_str(payload).map_err(|err| StateError{desc: err.to_string()})?;
+        if state["module"].as_str() != Some(&format!("{:016x}", module_fingerprint(&module.functions))) {
+            return Err(StateError::new("The state was dumped from a different program"));
+        }
+
+        let vars = restore_variables(state["variables"].as_array().ok_or(StateError::new("Missing variables"))?)?;
+        let mut interpreter = Interpreter::new(module);
+        let mut frames = Vec::new();
+        for frame in state["scopes"].as_array().ok_or(StateError::new("Missing scopes"))?.iter()
+                                    .chain(std::iter::once(&state["current"])) {
+            let func_idx = frame["func"].as_u64().map(|f| f as usize)
+                .filter(|&f| f < module.functions.len())
+                .ok_or(StateError::new("Malformed frame"))?;
+            let func = &module.functions[func_idx];
+            let forwards = frame["forwards"].as_bool().ok_or(StateError::new("Malformed frame"))?;
+            let ip = frame["ip"].as_u64().map(|ip| ip as usize)
+                .filter(|&ip| ip <= if forwards {func.code.fwd.len()} else {func.code.bkwd.len()})
+                .ok_or(StateError::new("Malformed frame"))?;
+            // The global scope keeps its variables in the global registers //
+            let num_registers = if func_idx == module.global_func_idx {0} else {func.num_registers};
+            let registers = restore_registers(&frame["registers"], &vars, num_registers)?;
+            frames.push(Scope{code: &func.code, consts: &func.consts, ip, forwards, registers, func_idx});
+        }
+        let current = frames.pop().expect("No current frame");
+        interpreter.code = current.code;
+        interpreter.consts = current.consts;
+        interpreter.ip = current.ip;
+        interpreter.forwards = current.forwards;
+        interpreter.registers = current.registers;
+        interpreter.func_idx = current.func_idx;
+        interpreter.scope_stack = frames;
+        // The bottom frame is the global scope's, which no call pushed //
+        interpreter.frame_values = interpreter.scope_stack.iter().skip(1).map(|scope| scope.registers.len()).sum::<usize>()
+            + if interpreter.scope_stack.is_empty() {0} else {interpreter.registers.len()};
+        interpreter.global_registers = restore_registers(
+            &state["globals"], &vars, module.functions[module.global_func_idx].num_registers)?;
+
+        interpreter.stack = restore_stack(&state["stack"], &vars, module)?;
+        // Dumps from before generators have none running //
+        if let Some(resumed) = state.get("resumed") {
+            let field = |entry: &Value, name: &str| entry[name].as_u64().map(|value| value as usize)
+                                                              .ok_or(StateError::new("Malformed generator"));
+            for entry in resumed.as_array().ok_or(StateError::new("Malformed generator"))? {
+                interpreter.resumed.push(Resumed{
+                    depth: field(entry, "depth")?,
+                    base: field(entry, "base")?,
+                    register: field(entry, "register")?,
+                    exit: field(entry, "exit")?
+                });
+            }
+        }
+        Ok(interpreter)
+    }
+}
+
+
+// The name of the threads running parfor iterations, whose own parfors run in sequence //
+#[cfg(feature = "parallel")]
+const WORKER: &str = "parfor";
+
+#[cfg(feature = "parallel")]
+impl<'a> Interpreter<'a> {
+
+    // Run every iteration of a fresh parfor iterator at once, each on a copy of the frame, then
+    // write back the items and leave the iterator exhausted. The checker only lets iterations
+    // modify their own item, so this can't be told apart from running them in sequence, in either
+    // direction. Execution that is being watched step by step runs in sequence instead //
+    fn run_iterations(&mut self, exit: usize) -> Result<(), RuntimeError> {
+        let body = self.ip + 1..exit - 1;
+        let (root, array, register, index_register, ascending, idx) = match self.stack.last() {
+            Some(StackObject::Iter(IterState{idx, source: IterSource::Array{root, path}, register, reversed, index_register})) => {
+                let array = IterSource::array(root, path).map_err(|desc| self.error(desc))?;
+                (Rc::clone(root), array, *register, *index_register, self.forwards != *reversed, *idx)
+            },
+            _ => return Err(self.error(String::from("No IterState on the stack")))
+        };
+        let items: Vec<(usize, Constant)> = match &*array.borrow() {
+            Variable::Array(items) => items.iter().enumerate()
+                .map(|(idx, item)| (idx, Constant::from_variable(&item.borrow())))
+                .collect(),
+            _ => return Err(self.error(String::from("For loop iterator is not an array")))
+        };
+        let fresh = if ascending {idx == -1} else {idx == items.len() as isize};
+        let stops_inside = match self.stop_at {
+            Some((depth, _, ip)) => depth > self.scope_stack.len() || (depth == self.scope_stack.len() && body.contains(&ip)),
+            None => false
+        };
+        let observed = self.watching || !self.breakpoints.is_empty() || stops_inside || self.costs.is_some()
+            || self.call_counts.is_some() || self.timeline.is_some() || self.hook.is_some();
+        if !fresh || items.len() < 2 || observed || thread::current().name() == Some(WORKER) {
+            return Ok(());
+        }
+
+        // Iterations share read-only copies of everything but the array //
+        let snapshot = |registers: &[Option<Rc<RefCell<Variable>>>]| -> Vec<Option<Constant>> {
+            registers.iter().map(|var| match var {
+                Some(var) if !Rc::ptr_eq(var, &root) && !Rc::ptr_eq(var, &array) => Some(Constant::from_variable(&var.borrow())),
+                _ => None
+            }).collect()
+        };
+        let (registers, global_registers) = (snapshot(&self.registers), snapshot(&self.global_registers));
+        let threads = thread::available_parallelism().map_or(1, |n| n.get()).min(items.len());
+        let (functions, code, consts, strings) = (self.functions, self.code, self.consts, self.strings);
+        let (func_idx, forwards, arithmetic, sandboxed) = (self.func_idx, self.forwards, self.arithmetic, self.sandboxed);
+        // Workers share what's left of the budget, and the total is checked once they're merged //
+        let mut options = self.options;
+        options.step_budget = options.step_budget.map(|budget| budget.saturating_sub(self.steps));
+
+        let results: Vec<IterationResults> = thread::scope(|scope| {
+            let workers: Vec<_> = items.chunks(items.len().div_ceil(threads)).map(|chunk| {
+                let (registers, global_registers, body) = (&registers, &global_registers, body.clone());
+                thread::Builder::new().name(String::from(WORKER)).spawn_scoped(scope, move || {
+                    let mut worker = Interpreter::new_worker(functions, code, consts, strings, func_idx, forwards);
+                    worker.registers = registers.iter().map(|value| value.as_ref().map(|value| Rc::new(RefCell::new(value.to_variable())))).collect();
+                    worker.global_registers = global_registers.iter().map(|value| value.as_ref().map(|value| Rc::new(RefCell::new(value.to_variable())))).collect();
+                    worker.arithmetic = arithmetic;
+                    worker.sandboxed = sandboxed;
+                    worker.options = options;
+                    let mut done = Vec::with_capacity(chunk.len());
+                    for (idx, item) in chunk {
+                        worker.forwards = forwards;
+                        worker.ip = body.start;
+                        worker.stop_at = Some((0, forwards, body.end));
+                        let position = |worker: &Interpreter| worker.scope_stack.is_empty().then_some((worker.forwards, worker.ip));
+                        let value = (|| {
+                            *worker.register_mut(register)? = Some(Rc::new(RefCell::new(item.to_variable())));
+                            if let Some(index_register) = index_register {
+                                *worker.register_mut(index_register)? = Some(Rc::new(RefCell::new(Variable::Frac(
+                                    Fraction::from_integer(num_bigint::BigInt::from(*idx))))));
+                            }
+                            worker.execute()?;
+                            let value = worker.register_mut(register)?.take();
+                            value.ok_or_else(|| worker.error(String::from("Parfor item was released")))
+                        })().map_err(|err| (err, position(&worker)))?;
+                        done.push((*idx, Constant::from_variable(&value.borrow())));
+                        if let Some(index_register) = index_register {
+                            worker.registers[index_register] = None;
+                        }
+                    }
+                    Ok((done, worker.steps))
+                }).expect("Failed to start a parfor thread")
+            }).collect();
+            workers.into_iter().map(|worker| worker.join().unwrap_or_else(|payload| panic::resume_unwind(payload))).collect()
+        });
+
+        // A failure is reported from the iteration that would have failed first in sequence //
+        let mut written = Vec::with_capacity(items.len());
+        let mut failure = None;
+        for result in results {
+            match result {
+                Ok((done, steps)) => {
+                    written.extend(done);
+                    self.steps += steps;
+                },
+                Err(err) => if failure.is_none() || !ascending {failure = Some(err)}
+            }
+        }
+        if let Some((err, position)) = failure {
+            if let Some((forwards, ip)) = position {
+                self.forwards = forwards;
+                self.ip = ip;
+            }
+            return Err(err);
+        }
+        if let Variable::Array(array_items) = &*array.borrow() {
+            for (idx, value) in written {
+                assign_in_place(&array_items[idx], &value);
+            }
+        }
+        if let Some(StackObject::Iter(iter_state)) = self.stack.last_mut() {
+            iter_state.idx = if ascending {items.len() as isize - 1} else {0};
+        }
+        Ok(())
+    }
+
+    fn new_worker(functions: &'a Vec<Function>, code: &'a Code, consts: &'a [Constant], strings: &'a [String],
+                  func_idx: usize, forwards: bool) -> Interpreter<'a> {
+        Interpreter {
+            functions,
+            stack: Vec::new(),
+            scope_stack: Vec::new(),
+            resumed: Vec::new(),
+            code,
+            ip: 0,
+            forwards,
+            registers: Vec::new(),
+            global_registers: Vec::new(),
+            consts,
+            strings,
+            func_idx,
+            stop_at: None,
+            watching: false,
+            breakpoints: HashSet::new(),
+            at_breakpoint: false,
+            output: None,
+            sandboxed: false,
+            reversals: ReversalLog::default(),
+            frame_pool: FramePool::default(),
+            memo: MemoTable::default(),
+            arithmetic: Arithmetic::Exact,
+            steps: 0,
+            started: Instant::now(),
+            call_counts: None,
+            costs: None,
+            timeline: None,
+            hook: None,
+            host_functions: HashMap::new(),
+            handles: Handles::default(),
+            options: EngineOptions::default(),
+            frame_values: 0,
+            subroutine_return: None
+        }
+    }
+}
+
+// Each worker's finished items and steps taken, or the failure that stopped it with the direction
+// and instruction pointer it happened at, if that was in the parfor's own frame //
+#[cfg(feature = "parallel")]
+type IterationResults = Result<(Vec<(usize, Constant)>, u64), (RuntimeError, Option<(bool, usize)>)>;
+
+// Overwrite a variable with a value computed elsewhere, keeping the cells of items that are still
+// there, so references into them see the new values //
+#[cfg(feature = "parallel")]
+fn assign_in_place(var: &Rc<RefCell<Variable>>, value: &Constant) {
+    if let (Variable::Array(items), Constant::Array(values)) = (&*var.borrow(), value) {
+        if items.len() == values.len() {
+            for (item, value) in items.iter().zip(values.iter()) {
+                assign_in_place(item, value);
+            }
+            return;
+        }
+    }
+    *var.borrow_mut() = value.to_variable();
+}
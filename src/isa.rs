@@ -0,0 +1,559 @@
+use std::fmt::{self, Write};
+
+use crate::interpreter::{Instruction, Code, Function, Module};
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StackEffect {
+    pub pops: usize,
+    pub pushes: usize
+}
+
+#[derive(Debug)]
+pub struct InstructionInfo {
+    pub name: &'static str,
+    pub operands: &'static str,
+    pub stack: &'static str,
+    pub inverse: &'static str,
+    pub desc: &'static str
+}
+
+
+// Defines all_instructions from one instance of each variant. The same list is matched on,
+// exhaustively, so a variant added to Instruction fails to compile until it's listed //
+macro_rules! instruction_table {
+    ($($name:ident $({$($field:ident: $value:expr),*})?),* $(,)?) => {
+        pub fn all_instructions() -> Vec<Instruction> {
+            use Instruction::*;
+            let _ = |instruction: Instruction| match instruction {
+                $($name $({$($field: _),*})? => {}),*
+            };
+            vec![$($name $({$($field: $value),*})?),*]
+        }
+    };
+}
+
+// One instance of every variant, used to generate the reference table //
+instruction_table!(
+    LoadConst{idx: 0}, LoadString{idx: 0}, LoadGlobalRegister{register: 0}, LoadRegister{register: 0},
+    StoreRegister{register: 0}, StoreGlobalRegister{register: 0}, FreeRegister{register: 0}, MoveRegister{from: 0, to: 0},
+    Subscript{size: 0}, Slice, CheckRef{register: 0}, CheckUnshared{register: 0}, Store, Pull{register: 0}, Push{register: 0},
+    PullFront{register: 0}, PushFront{register: 0}, CreateInt{val: 0},
+    BinopAdd, BinopSub, BinopMul, BinopDiv,
+    BinopOr, BinopAnd, BinopXor,
+    BinopLeq, BinopGeq, BinopLess, BinopGreat,
+    BinopEq, BinopNeq,
+    BinopIDiv, BinopMod, BinopPow, Shift{bits: 0},
+    UniopNeg, UniopNot, UniopLen, UniopToFloat, UniopToFrac,
+    UniopSin, UniopCos, UniopExp, UniopLog, UniopSqrt,
+    Reverse{idx: 0}, Jump{ip: 0}, JumpIfTrue{ip: 0}, JumpIfFalse{ip: 0},
+    RelativeJump{delta: 0}, RelativeJumpIfTrue{delta: 0}, RelativeJumpIfFalse{delta: 0},
+    ArrayLiteral{size: 0}, ArrayRepeat, Call{idx: 0}, Uncall{idx: 0},
+    DuplicateRef, UniqueVar, CreateIter{register: 0, reversed: false, index_register: None, depth: 0}, CreateRangeIter{register: 0}, StepIter{ip: 0}, ParallelStepIter{ip: 0},
+    CreateGenerator{idx: 0}, Resume{register: 0, ip: 0}, Suspend, Gosub{ip: 0}, Return, HostCall,
+    Print{count: 0}, Quit, DebugPrint, Marker{stmt: 0}, Hole, Todo, Backtrace, Expect, ExpectEqual, Steps, ClockMs, Remember{idx: 0},
+);
+
+
+impl Instruction {
+
+    pub fn info(&self) -> InstructionInfo {
+        use Instruction::*;
+        let (name, operands, stack, inverse, desc) = match self {
+            LoadConst{..} => ("LoadConst", "idx", "0 -> 1", "-",
+                "Push a copy of constant idx"),
+            LoadString{..} => ("LoadString", "idx", "0 -> 1", "-",
+                "Push a copy of string idx from the module's string table"),
+            LoadGlobalRegister{..} => ("LoadGlobalRegister", "register", "0 -> 1", "-",
+                "Push a reference to the variable in a global register"),
+            LoadRegister{..} => ("LoadRegister", "register", "0 -> 1", "-",
+                "Push a reference to the variable in a register"),
+            StoreRegister{..} => ("StoreRegister", "register", "1 -> 0", "FreeRegister",
+                "Pop a reference into a register"),
+            StoreGlobalRegister{..} => ("StoreGlobalRegister", "register", "1 -> 0", "-",
+                "Pop a reference into a global register"),
+            FreeRegister{..} => ("FreeRegister", "register", "0 -> 0", "StoreRegister",
+                "Clear a register"),
+            MoveRegister{..} => ("MoveRegister", "from, to", "0 -> 0", "MoveRegister",
+                "Move the variable in register from into register to, leaving from empty"),
+            Subscript{..} => ("Subscript", "size", "size+1 -> 1", "-",
+                "Pop a reference then size indices, push a reference to the indexed element"),
+            Slice => ("Slice", "", "3 -> 1", "-",
+                "Pop an array reference, a start then an end, push a new array sharing the elements between them"),
+            CheckRef{..} => ("CheckRef", "register", "1 -> 0", "-",
+                "Pop a reference, and fail unless a register holds the same variable or the same range of elements"),
+            CheckUnshared{..} => ("CheckUnshared", "register", "0 -> 0", "-",
+                "Fail if anything besides a register references its variable or the elements in it"),
+            Store => ("Store", "", "2 -> 0", "Store",
+                "Pop a value then a reference, and overwrite the referenced variable"),
+            Pull{..} => ("Pull", "register", "1 -> 0", "Push",
+                "Pop an array reference and move its last element into a register"),
+            Push{..} => ("Push", "register", "1 -> 0", "Pull",
+                "Pop an array reference and move a register's variable onto its end"),
+            PullFront{..} => ("PullFront", "register", "1 -> 0", "PushFront",
+                "Pop an array reference and move its first element into a register"),
+            PushFront{..} => ("PushFront", "register", "1 -> 0", "PullFront",
+                "Pop an array reference and move a register's variable onto its start"),
+            CreateInt{..} => ("CreateInt", "val", "0 -> 1", "-",
+                "Push a new integer"),
+            BinopAdd => ("BinopAdd", "", "2 -> 1", "BinopSub", "Addition"),
+            BinopSub => ("BinopSub", "", "2 -> 1", "BinopAdd", "Subtraction"),
+            BinopMul => ("BinopMul", "", "2 -> 1", "BinopDiv", "Multiplication"),
+            BinopDiv => ("BinopDiv", "", "2 -> 1", "BinopMul", "Division"),
+            BinopOr => ("BinopOr", "", "2 -> 1", "-", "Logical or, compiled to jumps"),
+            BinopAnd => ("BinopAnd", "", "2 -> 1", "-", "Logical and, compiled to jumps"),
+            BinopXor => ("BinopXor", "", "2 -> 1", "-", "Logical xor"),
+            BinopLeq => ("BinopLeq", "", "2 -> 1", "-", "Less than or equal"),
+            BinopGeq => ("BinopGeq", "", "2 -> 1", "-", "Greater than or equal"),
+            BinopLess => ("BinopLess", "", "2 -> 1", "-", "Less than"),
+            BinopGreat => ("BinopGreat", "", "2 -> 1", "-", "Greater than"),
+            BinopEq => ("BinopEq", "", "2 -> 1", "-", "Equality"),
+            BinopNeq => ("BinopNeq", "", "2 -> 1", "-", "Inequality"),
+            BinopIDiv => ("BinopIDiv", "", "2 -> 1", "-", "Truncating division"),
+            BinopMod => ("BinopMod", "", "2 -> 1", "-", "Modulo"),
+            BinopPow => ("BinopPow", "", "2 -> 1", "-", "Power (via f64)"),
+            Shift{..} => ("Shift", "bits", "1 -> 1", "Shift",
+                "Multiply by 2 ** bits, in place of multiplying or dividing by a constant power of two, undone by negating bits"),
+            UniopNeg => ("UniopNeg", "", "1 -> 1", "-", "Negation"),
+            UniopNot => ("UniopNot", "", "1 -> 1", "-", "Logical not"),
+            UniopLen => ("UniopLen", "", "1 -> 1", "-", "Array length"),
+            UniopToFloat => ("UniopToFloat", "", "1 -> 1", "-", "Convert a number to a float"),
+            UniopToFrac => ("UniopToFrac", "", "1 -> 1", "-", "Convert a number to a fraction"),
+            UniopSin => ("UniopSin", "", "1 -> 1", "-", "Sine, as a float"),
+            UniopCos => ("UniopCos", "", "1 -> 1", "-", "Cosine, as a float"),
+            UniopExp => ("UniopExp", "", "1 -> 1", "-", "Exponential, as a float"),
+            UniopLog => ("UniopLog", "", "1 -> 1", "-", "Natural logarithm, as a float"),
+            UniopSqrt => ("UniopSqrt", "", "1 -> 1", "-", "Square root, as a float"),
+            Reverse{..} => ("Reverse", "idx", "0 -> 0", "Reverse",
+                "Switch direction, continuing at idx in the other stream"),
+            Jump{..} => ("Jump", "ip", "0 -> 0", "-",
+                "Jump to ip"),
+            JumpIfTrue{..} => ("JumpIfTrue", "ip", "1 -> 0", "-",
+                "Pop a condition, jump to ip if it is true"),
+            JumpIfFalse{..} => ("JumpIfFalse", "ip", "1 -> 0", "-",
+                "Pop a condition, jump to ip if it is false"),
+            RelativeJump{..} => ("RelativeJump", "delta", "0 -> 0", "-",
+                "Compiler-internal, replaced by Jump during finalisation"),
+            RelativeJumpIfTrue{..} => ("RelativeJumpIfTrue", "delta", "1 -> 0", "-",
+                "Compiler-internal, replaced by JumpIfTrue during finalisation"),
+            RelativeJumpIfFalse{..} => ("RelativeJumpIfFalse", "delta", "1 -> 0", "-",
+                "Compiler-internal, replaced by JumpIfFalse during finalisation"),
+            ArrayLiteral{..} => ("ArrayLiteral", "size", "size -> 1", "-",
+                "Pop size items into a new array"),
+            ArrayRepeat => ("ArrayRepeat", "", "2 -> 1", "-",
+                "Pop dimensions then an item, push a new array of copies"),
+            Call{..} => ("Call", "idx", "callee", "Uncall",
+                "Call function idx forwards"),
+            Uncall{..} => ("Uncall", "idx", "callee", "Call",
+                "Call function idx backwards"),
+            DuplicateRef => ("DuplicateRef", "", "1 -> 2", "-",
+                "Push another reference to the variable on top of the stack"),
+            UniqueVar => ("UniqueVar", "", "1 -> 1", "-",
+                "Replace the top reference with a deep copy if it is aliased"),
+            CreateIter{..} => ("CreateIter", "register", "depth+1 -> 1", "-",
+                "Pop a variable reference then depth indices, push an iterator bound to a register over the array they lead to, optionally reversed or enumerating"),
+            CreateRangeIter{..} => ("CreateRangeIter", "register", "3 -> 1", "-",
+                "Pop a step, an end then a start, push an iterator over the range bound to a register"),
+            StepIter{..} => ("StepIter", "ip", "1 -> 1 | 1 -> 0", "-",
+                "Advance the iterator, or pop it and jump to ip when exhausted"),
+            ParallelStepIter{..} => ("ParallelStepIter", "ip", "1 -> 1 | 1 -> 0", "-",
+                "As StepIter, for a parfor. With the parallel feature a fresh iterator runs every iteration at once, then jumps to ip"),
+            CreateGenerator{..} => ("CreateGenerator", "idx", "callee -> 1", "-",
+                "Pop the args generator idx borrows, push a suspended frame of it for running in the current direction"),
+            Resume{..} => ("Resume", "register, ip", "1 -> 1 | 1 -> 0", "-",
+                "Pop a generator and run it until it yields into a register, pushing it back, or finishes and jumps to ip"),
+            Gosub{..} => ("Gosub", "ip", "subroutine", "-",
+                "Run the backward block outlined at ip, then carry on with the next instruction"),
+            Return => ("Return", "", "0 -> 0", "-",
+                "End an outlined backward block, carrying on after the Gosub that ran it"),
+            Suspend => ("Suspend", "", "1 -> 0", "-",
+                "Pop a yielded value and return it to the loop that resumed this generator, suspending its frame"),
+            HostCall => ("HostCall", "", "callee", "HostCall",
+                "Pop the current function's args and run the host function registered under its name, pushing what it gives back"),
+            Print{..} => ("Print", "count", "|count| -> 0", "Print",
+                "Pop and print |count| items, with a newline if count is negative"),
+            Quit => ("Quit", "", "0 -> 0", "-",
+                "Halt execution"),
+            DebugPrint => ("DebugPrint", "", "0 -> 0", "-",
+                "Dump the registers and stack"),
+            Marker{..} => ("Marker", "stmt", "0 -> 0", "-",
+                "No-op tagging the start of statement region stmt, stripped in release builds"),
+            Hole => ("Hole", "", "0 -> 1", "-",
+                "Raise an error, in place of an unwritten expression"),
+            Todo => ("Todo", "", "0 -> 0", "Todo",
+                "Raise an error, in place of unwritten statements"),
+            Backtrace => ("Backtrace", "", "0 -> 0", "-",
+                "Print the function and source position of every frame on the call stack"),
+            Expect => ("Expect", "", "2 -> 0", "Expect",
+                "Pop a description and a condition, raising an error naming the direction if the condition is false"),
+            ExpectEqual => ("ExpectEqual", "", "3 -> 0", "ExpectEqual",
+                "Pop a description, an expected value and an actual one, raising an error showing both if they differ"),
+            Remember{..} => ("Remember", "idx", "callee", "Remember",
+                "Call pure function idx through the memo table, taking a use of its cached result forwards and releasing one backwards"),
+            Steps => ("Steps", "", "0 -> 1", "-",
+                "Push the number of instructions executed so far"),
+            ClockMs => ("ClockMs", "", "0 -> 1", "-",
+                "Push the milliseconds elapsed since the interpreter started"),
+        };
+        InstructionInfo{name, operands, stack, inverse, desc}
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.info().name
+    }
+
+    // None when the effect depends on the callee or subroutine //
+    pub fn stack_effect(&self) -> Option<StackEffect> {
+        use Instruction::*;
+        let (pops, pushes) = match self {
+            LoadConst{..} | LoadString{..} | LoadGlobalRegister{..} | LoadRegister{..} | CreateInt{..} | Hole | Steps | ClockMs => (0, 1),
+            StoreRegister{..} | StoreGlobalRegister{..} | Pull{..} | Push{..} |
+            PullFront{..} | PushFront{..} | CheckRef{..} => (1, 0),
+            FreeRegister{..} | MoveRegister{..} | Reverse{..} | Jump{..} | RelativeJump{..} | Return |
+            Quit | DebugPrint | Marker{..} | Todo | Backtrace | CheckUnshared{..} => (0, 0),
+            JumpIfTrue{..} | JumpIfFalse{..} | RelativeJumpIfTrue{..} | RelativeJumpIfFalse{..} | Suspend => (1, 0),
+            Subscript{size} => (size + 1, 1),
+            Store | Expect => (2, 0),
+            ExpectEqual => (3, 0),
+            BinopAdd | BinopSub | BinopMul | BinopDiv |
+            BinopOr | BinopAnd | BinopXor |
+            BinopLeq | BinopGeq | BinopLess | BinopGreat |
+            BinopEq | BinopNeq |
+            BinopIDiv | BinopMod | BinopPow | ArrayRepeat => (2, 1),
+            CreateRangeIter{..} | Slice => (3, 1),
+            UniopNeg | UniopNot | UniopLen | UniopToFloat | UniopToFrac |
+            UniopSin | UniopCos | UniopExp | UniopLog | UniopSqrt | UniqueVar | StepIter{..} | ParallelStepIter{..} |
+            Shift{..} | Resume{..} => (1, 1),
+            CreateIter{depth, ..} => (*depth + 1, 1),
+            ArrayLiteral{size} => (*size, 1),
+            DuplicateRef => (1, 2),
+            Print{count} => (count.unsigned_abs(), 0),
+            Call{..} | Uncall{..} | Remember{..} | CreateGenerator{..} | HostCall | Gosub{..} => return None
+        };
+        Some(StackEffect{pops, pushes})
+    }
+
+    // The weight --cost-report charges each time this runs, in arbitrary units. Moving references
+    // costs 1, arithmetic more by how much work it does, and calls the frame they set up. Freeing a
+    // register discards information, so is charged as the one irreversible step //
+    pub fn cost(&self) -> u64 {
+        use Instruction::*;
+        match self {
+            Marker{..} | Hole | Jump{..} | RelativeJump{..} => 0,
+            LoadConst{..} | LoadString{..} | LoadGlobalRegister{..} | LoadRegister{..} |
+            StoreRegister{..} | StoreGlobalRegister{..} | CreateInt{..} | DuplicateRef |
+            JumpIfTrue{..} | JumpIfFalse{..} | RelativeJumpIfTrue{..} | RelativeJumpIfFalse{..} |
+            Steps | ClockMs | CheckRef{..} | MoveRegister{..} | Gosub{..} | Return => 1,
+            Subscript{..} | Store | Pull{..} | Push{..} | PullFront{..} | PushFront{..} |
+            BinopAdd | BinopSub | BinopOr | BinopAnd | BinopXor | Shift{..} |
+            BinopLeq | BinopGeq | BinopLess | BinopGreat | BinopEq | BinopNeq |
+            UniopNeg | UniopNot | UniopLen | UniopToFloat | UniopToFrac |
+            StepIter{..} | ParallelStepIter{..} | Reverse{..} | Suspend | Expect | ExpectEqual => 2,
+            BinopMul | BinopDiv | BinopIDiv | BinopMod | UniqueVar |
+            CreateIter{..} | CreateRangeIter{..} | ArrayLiteral{..} | ArrayRepeat | Slice | CheckUnshared{..} => 4,
+            BinopPow | UniopSin | UniopCos | UniopExp | UniopLog | UniopSqrt |
+            Call{..} | Uncall{..} | Remember{..} | CreateGenerator{..} | Resume{..} | HostCall => 8,
+            FreeRegister{..} => 16,
+            Print{..} | Quit | DebugPrint | Todo | Backtrace => 0
+        }
+    }
+
+    // The instruction that undoes this one's effect on program state, if it has one //
+    pub fn inverse(&self) -> Option<Instruction> {
+        use Instruction::*;
+        match self {
+            StoreRegister{register} => Some(FreeRegister{register: *register}),
+            FreeRegister{register} => Some(StoreRegister{register: *register}),
+            MoveRegister{from, to} => Some(MoveRegister{from: *to, to: *from}),
+            Pull{register} => Some(Push{register: *register}),
+            Push{register} => Some(Pull{register: *register}),
+            PullFront{register} => Some(PushFront{register: *register}),
+            PushFront{register} => Some(PullFront{register: *register}),
+            Call{idx} => Some(Uncall{idx: *idx}),
+            Uncall{idx} => Some(Call{idx: *idx}),
+            BinopAdd => Some(BinopSub),
+            BinopSub => Some(BinopAdd),
+            BinopMul => Some(BinopDiv),
+            BinopDiv => Some(BinopMul),
+            Shift{bits} => Some(Shift{bits: -bits}),
+            Store | Reverse{..} | Print{..} | Todo | Remember{..} | HostCall | Expect | ExpectEqual => Some(*self),
+            _ => None
+        }
+    }
+
+    // Whether executing this can change the value of a variable //
+    pub fn modifies_variables(&self) -> bool {
+        use Instruction::*;
+        matches!(self, StoreRegister{..} | StoreGlobalRegister{..} | FreeRegister{..} | MoveRegister{..} | Store |
+                       Pull{..} | Push{..} | PullFront{..} | PushFront{..})
+    }
+
+    // Whether this produces a float, whose value can't be relied on to undo anything //
+    pub fn makes_float(&self) -> bool {
+        use Instruction::*;
+        matches!(self, UniopToFloat | UniopSin | UniopCos | UniopExp | UniopLog | UniopSqrt)
+    }
+
+    // The absolute instruction pointer this instruction may transfer control to //
+    pub fn jump_target(&self) -> Option<usize> {
+        match self {
+            Instruction::Jump{ip} | Instruction::JumpIfTrue{ip} |
+            Instruction::JumpIfFalse{ip} | Instruction::StepIter{ip} | Instruction::ParallelStepIter{ip} |
+            Instruction::Resume{ip, ..} | Instruction::Gosub{ip} => Some(*ip),
+            _ => None
+        }
+    }
+}
+
+
+// A finalised instruction stream, packed one u32 word per instruction. The low 8 bits hold the
+// opcode and the rest an operand. Operands that don't fit in 23 bits, and the operands of CreateIter,
+// Resume and MoveRegister, are kept in a side array which the word indexes instead, flagged by the top bit //
+#[derive(Clone, Default, PartialEq)]
+pub struct Stream {
+    words: Vec<u32>,
+    wide: Vec<isize>
+}
+
+const WIDE: u32 = 1 << 31;
+const MAX_INLINE: isize = (1 << 23) - 1;
+
+macro_rules! packing {
+    ($($opcode:literal => $variant:ident $({$field:ident})?),* $(,)?) => {
+        impl Stream {
+            pub fn push(&mut self, instruction: &Instruction) {
+                match *instruction {
+                    $(Instruction::$variant $({$field})? => self.push_word($opcode, 0 $(+ $field as isize)?),)*
+                    Instruction::CreateIter{register, reversed, index_register, depth} => {
+                        self.words.push(CREATE_ITER | WIDE | (self.wide.len() as u32) << 8);
+                        self.wide.extend([
+                            register as isize, reversed as isize, index_register.map_or(-1, |r| r as isize), depth as isize
+                        ]);
+                    },
+                    Instruction::Resume{register, ip} => {
+                        self.words.push(RESUME | WIDE | (self.wide.len() as u32) << 8);
+                        self.wide.extend([register as isize, ip as isize]);
+                    },
+                    Instruction::MoveRegister{from, to} => {
+                        self.words.push(MOVE_REGISTER | WIDE | (self.wide.len() as u32) << 8);
+                        self.wide.extend([from as isize, to as isize]);
+                    }
+                }
+            }
+
+            #[inline]
+            pub fn get(&self, ip: usize) -> Option<Instruction> {
+                let word = *self.words.get(ip)?;
+                let operand = match word & WIDE {
+                    0 => (word >> 8) as isize,
+                    _ => self.wide[((word & !WIDE) >> 8) as usize]
+                };
+                Some(match word & 0xFF {
+                    $($opcode => Instruction::$variant $({$field: operand as _})?,)*
+                    CREATE_ITER => {
+                        let idx = ((word & !WIDE) >> 8) as usize;
+                        Instruction::CreateIter{
+                            register: self.wide[idx] as usize,
+                            reversed: self.wide[idx + 1] != 0,
+                            index_register: match self.wide[idx + 2] {
+                                -1 => None,
+                                register => Some(register as usize)
+                            },
+                            depth: self.wide[idx + 3] as usize
+                        }
+                    },
+                    RESUME => {
+                        let idx = ((word & !WIDE) >> 8) as usize;
+                        Instruction::Resume{register: self.wide[idx] as usize, ip: self.wide[idx + 1] as usize}
+                    },
+                    MOVE_REGISTER => {
+                        let idx = ((word & !WIDE) >> 8) as usize;
+                        Instruction::MoveRegister{from: self.wide[idx] as usize, to: self.wide[idx + 1] as usize}
+                    },
+                    opcode => panic!("Invalid opcode {}", opcode)
+                })
+            }
+        }
+    };
+}
+
+const CREATE_ITER: u32 = 255;
+const RESUME: u32 = 254;
+const MOVE_REGISTER: u32 = 253;
+
+packing!(
+    0 => LoadConst{idx}, 1 => LoadString{idx}, 2 => LoadGlobalRegister{register},
+    3 => LoadRegister{register}, 4 => StoreRegister{register}, 5 => StoreGlobalRegister{register},
+    6 => FreeRegister{register}, 7 => Subscript{size}, 8 => Store, 9 => Pull{register},
+    10 => Push{register}, 11 => PullFront{register}, 12 => PushFront{register}, 13 => CreateInt{val},
+    14 => BinopAdd, 15 => BinopSub, 16 => BinopMul, 17 => BinopDiv,
+    18 => BinopOr, 19 => BinopAnd, 20 => BinopXor,
+    21 => BinopLeq, 22 => BinopGeq, 23 => BinopLess, 24 => BinopGreat,
+    25 => BinopEq, 26 => BinopNeq,
+    27 => BinopIDiv, 28 => BinopMod, 29 => BinopPow,
+    30 => UniopNeg, 31 => UniopNot, 32 => UniopLen,
+    33 => Reverse{idx}, 34 => Jump{ip}, 35 => JumpIfTrue{ip}, 36 => JumpIfFalse{ip},
+    37 => RelativeJump{delta}, 38 => RelativeJumpIfTrue{delta}, 39 => RelativeJumpIfFalse{delta},
+    40 => ArrayLiteral{size}, 41 => ArrayRepeat, 42 => Call{idx}, 43 => Uncall{idx},
+    44 => DuplicateRef, 45 => UniqueVar, 46 => CreateRangeIter{register}, 47 => StepIter{ip},
+    48 => Print{count}, 49 => Quit, 50 => DebugPrint, 51 => Marker{stmt},
+    52 => UniopToFloat, 53 => UniopToFrac,
+    54 => UniopSin, 55 => UniopCos, 56 => UniopExp, 57 => UniopLog, 58 => UniopSqrt,
+    59 => Hole, 60 => Todo, 61 => Backtrace,
+    62 => Steps, 63 => ClockMs, 64 => Remember{idx}, 65 => ParallelStepIter{ip},
+    66 => CreateGenerator{idx}, 67 => Suspend, 68 => HostCall, 69 => Slice,
+    70 => CheckRef{register}, 71 => CheckUnshared{register}, 72 => Shift{bits},
+    73 => Gosub{ip}, 74 => Return, 75 => Expect, 76 => ExpectEqual,
+);
+
+impl Stream {
+    fn push_word(&mut self, opcode: u32, operand: isize) {
+        if (0..=MAX_INLINE).contains(&operand) {
+            self.words.push(opcode | (operand as u32) << 8);
+        } else {
+            self.words.push(opcode | WIDE | (self.wide.len() as u32) << 8);
+            self.wide.push(operand);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Instruction> + '_ {
+        (0..self.len()).map(move |ip| self.get(ip).unwrap())
+    }
+
+    // Apply a change to every instruction, repacking the stream //
+    pub fn rewrite(&mut self, mut f: impl FnMut(&mut Instruction)) {
+        let mut instructions: Vec<_> = self.iter().collect();
+        instructions.iter_mut().for_each(&mut f);
+        *self = Stream::from(instructions);
+    }
+
+    // Memory used by the packed instructions and their wide operands //
+    pub fn size_in_bytes(&self) -> usize {
+        self.words.len() * std::mem::size_of::<u32>() + self.wide.len() * std::mem::size_of::<isize>()
+    }
+
+    // The subroutine outlined at ip, up to but not including its Return //
+    pub fn subroutine(&self, ip: usize) -> Vec<Instruction> {
+        (ip..self.len()).map(|ip| self.get(ip).unwrap()).take_while(|i| *i != Instruction::Return).collect()
+    }
+
+    // The packed words and wide operands, as stored in build artifacts //
+    pub fn parts(&self) -> (&[u32], &[isize]) {
+        (&self.words, &self.wide)
+    }
+
+    pub fn from_parts(words: Vec<u32>, wide: Vec<isize>) -> Stream {
+        Stream{words, wide}
+    }
+}
+
+impl From<Vec<Instruction>> for Stream {
+    fn from(instructions: Vec<Instruction>) -> Stream {
+        let mut stream = Stream{words: Vec::with_capacity(instructions.len()), wide: Vec::new()};
+        for instruction in instructions.iter() {
+            stream.push(instruction);
+        }
+        stream
+    }
+}
+
+impl fmt::Debug for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+
+pub fn reference_table() -> String {
+    let mut out = String::new();
+    writeln!(out, "{:<20} {:<10} {:<18} {:<14} {:<5} Description",
+             "Opcode", "Operands", "Stack", "Inverse", "Cost").unwrap();
+    writeln!(out, "{}", "-".repeat(106)).unwrap();
+    for instruction in all_instructions() {
+        let InstructionInfo{name, operands, stack, inverse, desc} = instruction.info();
+        writeln!(out, "{:<20} {:<10} {:<18} {:<14} {:<5} {}",
+                 name, operands, stack, inverse, instruction.cost(), desc).unwrap();
+    }
+    out
+}
+
+
+fn disassemble_stream(out: &mut String, label: &str, instructions: &Stream) {
+    writeln!(out, "  {}:", label).unwrap();
+    for (ip, instruction) in instructions.iter().enumerate() {
+        writeln!(out, "    {:>4}  {:?}", ip, instruction).unwrap();
+    }
+}
+
+pub fn disassemble_code(out: &mut String, code: &Code) {
+    disassemble_stream(out, "fwd", &code.fwd);
+    disassemble_stream(out, "bkwd", &code.bkwd);
+}
+
+pub fn disassemble(module: &Module) -> String {
+    let mut out = String::new();
+    writeln!(out, "consts").unwrap();
+    for (i, c) in module.consts.iter().enumerate() {
+        writeln!(out, "  {:>3}  {}", i, c).unwrap();
+    }
+    writeln!(out).unwrap();
+    writeln!(out, "strings").unwrap();
+    for (i, string) in module.strings.iter().enumerate() {
+        writeln!(out, "  {:>3}  {:?}", i, string).unwrap();
+    }
+    writeln!(out).unwrap();
+    for (idx, function) in module.functions.iter().enumerate() {
+        let mut title = format!("function {}", idx);
+        if Some(idx) == module.main_idx {title.push_str(" (main)")}
+        if idx == module.global_func_idx {title.push_str(" (global)")}
+        if function.is_pure {title.push_str(" (pure)")}
+        writeln!(out, "{}, {} registers", title, function.num_registers).unwrap();
+        disassemble_code(&mut out, &function.code);
+        writeln!(out).unwrap();
+    }
+    out
+}
+
+// The effects the checker found a function to have, for "monoxide info" //
+pub fn function_info(function: &Function) -> String {
+    let mut out = String::new();
+    writeln!(out, "function {}", function.name).unwrap();
+    let effects = &function.effects;
+    for (effect, has) in [
+        ("prints", effects.prints),
+        ("reads input", effects.reads_input),
+        ("touches globals", effects.touches_globals),
+        ("steals", effects.steals),
+        ("mono", effects.is_mono),
+        ("reversible-pure", effects.is_reversible_pure())
+    ] {
+        writeln!(out, "  {:<16} {}", effect, if has {"yes"} else {"no"}).unwrap();
+    }
+    out
+}
+
+// The borrow params the checker specialised to constants and the backward blocks the optimiser
+// outlined, for --stats //
+pub fn stats(module: &Module) -> String {
+    let mut out = String::new();
+    let count = |instruction: fn(&Instruction) -> bool| -> usize {
+        module.functions.iter().map(|f| f.code.bkwd.iter().filter(instruction).count()).sum()
+    };
+    let (blocks, subroutines) = (count(|i| matches!(i, Instruction::Gosub{..})), count(|i| *i == Instruction::Return));
+    writeln!(out, "Outlined {} backward block(s) into {} subroutine(s)", blocks, subroutines).unwrap();
+    let specialised: Vec<&Function> = module.functions.iter().filter(|f| !f.specialised.is_empty()).collect();
+    writeln!(out, "Specialised {} function(s)", specialised.len()).unwrap();
+    for function in specialised {
+        let params: Vec<String> = function.specialised.iter().map(|(_, name, value)| format!("{} = {}", name, value)).collect();
+        writeln!(out, "  {}: {}", function.name, params.join(", ")).unwrap();
+    }
+    out
+}
@@ -0,0 +1,581 @@
+use num_traits::identities::One;
+
+use crate::interpreter::{Instruction, Variable};
+use crate::syntaxchecker::SyntaxError;
+use crate::syntaxtree as ST;
+use crate::typechecker::operator_symbol;
+use ST::Type;
+
+
+// Translates the checked syntax tree into Janus, in the dialect of the Jana interpreter, so that
+// programs can be cross-checked against existing Janus implementations. Janus only has integers,
+// integer arrays, and procedures that update their parameters in place, so anything outside that
+// overlap is an error at the statement using it //
+pub struct JanusContext<'a> {
+    functions: &'a [ST::FunctionNode],
+    function: &'a ST::FunctionNode,
+    names: Vec<String>,  // The name last bound to each register
+    locals: Vec<Local>,  // Open local blocks, innermost last
+    declarations: Vec<String>,
+    is_main: bool,
+    position: (usize, usize),
+    indent: usize,
+    lines: Vec<String>
+}
+
+// A variable bound by a local statement, which becomes one of main's declarations if it's still
+// bound when main ends //
+struct Local {
+    name: String,
+    line: usize,
+    declaration: String,
+    initialisers: Vec<String>,
+    position: (usize, usize)
+}
+
+pub trait JanusExpression {
+    fn janus_source(&self, ctx: &JanusContext) -> Result<String, SyntaxError>;
+
+    // The values initialising a variable, one per item for an array literal //
+    fn janus_items(&self, ctx: &JanusContext) -> Result<Vec<String>, SyntaxError> {
+        Ok(vec![self.janus_source(ctx)?])
+    }
+
+    // The statement printing this, as Janus can only print strings and whole variables //
+    fn janus_print(&self, ctx: &JanusContext) -> Result<String, SyntaxError> {
+        Err(ctx.unsupported("printing expressions"))
+    }
+}
+
+pub trait JanusStatement {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError>;
+}
+
+
+impl<'a> JanusContext<'a> {
+    fn error(&self, desc: String) -> SyntaxError {
+        SyntaxError{line: self.position.0, col: self.position.1, desc, note: None}
+    }
+
+    fn unsupported(&self, what: &str) -> SyntaxError {
+        self.error(format!("Janus has no equivalent of {}", what))
+    }
+
+    fn line(&mut self, text: &str) {
+        self.lines.push(format!("{}{}", "    ".repeat(self.indent), text));
+    }
+
+    // Janus starts main's declared variables at zero, so the local is replaced by additions //
+    fn declare(&mut self, local: Local) -> Result<(), SyntaxError> {
+        if !self.is_main {
+            return Err(SyntaxError{line: local.position.0, col: local.position.1, desc: format!(
+                "Janus procedures can't return new variables, only update their parameters, so \"{}\" must be unbound",
+                local.name), note: None});
+        }
+        let indent = self.lines[local.line].len() - self.lines[local.line].trim_start().len();
+        self.lines[local.line] = local.initialisers.iter()
+            .map(|line| format!("{}{}", " ".repeat(indent), line))
+            .collect::<Vec<_>>().join("\n");
+        self.declarations.push(local.declaration);
+        Ok(())
+    }
+
+    fn emit_stmts(&mut self, stmts: &[ST::StatementNode]) -> Result<(), SyntaxError> {
+        self.indent += 1;
+        if stmts.is_empty() {
+            self.line("skip");
+        }
+        for stmt in stmts {
+            stmt.emit_janus(self)?;
+        }
+        self.indent -= 1;
+        Ok(())
+    }
+
+    // The name of the variable a statement at the current position binds to the register //
+    fn bind(&mut self, register: usize) -> String {
+        let symbols = &self.function.symbols;
+        let name = symbols.iter()
+            .find(|symbol| symbol.register == register && symbol.start == Some(self.position))
+            .or_else(|| symbols.iter().find(|symbol| symbol.register == register))
+            .map(|symbol| symbol.name.clone())
+            .unwrap_or_else(|| format!("r{}", register));
+        self.names[register] = name.clone();
+        name
+    }
+
+    fn name_of_id(&self, var_id: isize) -> String {
+        self.function.symbols.iter()
+            .find(|symbol| symbol.var_id == var_id)
+            .map(|symbol| symbol.name.clone())
+            .unwrap_or_else(|| format!("v{}", var_id))
+    }
+
+    fn constant(&self, const_idx: usize) -> Result<String, SyntaxError> {
+        match &self.function.consts[const_idx] {
+            Variable::Frac(val) if val.denom().is_one() => Ok(val.numer().to_string()),
+            Variable::Frac(_) | Variable::Float(_) => Err(self.unsupported("non-integer numbers")),
+            Variable::Str(_) => Err(self.unsupported("strings outside of print")),
+            Variable::Array(_) => Err(self.unsupported("constant arrays")),
+            Variable::Handle(_) => Err(self.unsupported("handles"))
+        }
+    }
+
+    fn param(&self, name: &str, t: &Type) -> Result<String, SyntaxError> {
+        match t {
+            Type::Num | Type::Unknown => Ok(format!("int {}", name)),
+            Type::Array(item) if item.is_compatible(&Type::Num) => Ok(format!("int {}[]", name)),
+            _ => Err(self.error(format!("Janus parameters are integers or integer arrays, but \"{}\" is {}", name, t)))
+        }
+    }
+}
+
+fn function_name(name: &str) -> String {
+    name.replace("::", "_")
+}
+
+
+// ---------------------------- Expression Nodes ---------------------------- //
+
+impl JanusExpression for ST::FractionNode {
+    fn janus_source(&self, ctx: &JanusContext) -> Result<String, SyntaxError> {
+        ctx.constant(self.const_idx)
+    }
+}
+
+impl JanusExpression for ST::StringNode {
+    fn janus_source(&self, ctx: &JanusContext) -> Result<String, SyntaxError> {
+        Err(ctx.unsupported("strings outside of print"))
+    }
+
+    fn janus_print(&self, ctx: &JanusContext) -> Result<String, SyntaxError> {
+        match &ctx.function.consts[self.const_idx] {
+            Variable::Str(s) => Ok(format!("print({:?})", s)),
+            _ => Err(ctx.unsupported("printing constants"))
+        }
+    }
+}
+
+// Janus has no registers to hoist into, so the expression stays in the loop //
+impl JanusExpression for ST::HoistedNode {
+    fn janus_source(&self, ctx: &JanusContext) -> Result<String, SyntaxError> {
+        self.expr.janus_source(ctx)
+    }
+
+    fn janus_print(&self, ctx: &JanusContext) -> Result<String, SyntaxError> {
+        self.expr.janus_print(ctx)
+    }
+}
+
+impl JanusExpression for ST::HoleNode {
+    fn janus_source(&self, ctx: &JanusContext) -> Result<String, SyntaxError> {
+        Err(ctx.unsupported("holes"))
+    }
+}
+
+impl JanusExpression for ST::CounterNode {
+    fn janus_source(&self, ctx: &JanusContext) -> Result<String, SyntaxError> {
+        Err(ctx.unsupported(&format!("{}()", operator_symbol(&self.op))))
+    }
+}
+
+impl JanusExpression for ST::ArrayLiteralNode {
+    fn janus_source(&self, ctx: &JanusContext) -> Result<String, SyntaxError> {
+        Err(ctx.unsupported("array literals, except when binding a variable"))
+    }
+
+    fn janus_items(&self, ctx: &JanusContext) -> Result<Vec<String>, SyntaxError> {
+        self.items.iter().map(|item| item.janus_source(ctx)).collect()
+    }
+}
+
+impl JanusExpression for ST::ArrayRepeatNode {
+    fn janus_source(&self, ctx: &JanusContext) -> Result<String, SyntaxError> {
+        Err(ctx.unsupported("repeated arrays"))
+    }
+}
+
+impl JanusExpression for ST::ComprehensionNode {
+    fn janus_source(&self, ctx: &JanusContext) -> Result<String, SyntaxError> {
+        Err(ctx.unsupported("comprehensions"))
+    }
+}
+
+impl JanusExpression for ST::CallExprNode {
+    fn janus_source(&self, ctx: &JanusContext) -> Result<String, SyntaxError> {
+        Err(ctx.unsupported("calls in expressions"))
+    }
+}
+
+impl JanusExpression for ST::LookupNode {
+    fn janus_source(&self, ctx: &JanusContext) -> Result<String, SyntaxError> {
+        if self.is_global {
+            return Err(ctx.unsupported("global variables"));
+        }
+        let mut source = ctx.name_of_id(self.var_id);
+        for index in &self.indices {
+            source += &format!("[{}]", index.janus_source(ctx)?);
+        }
+        Ok(source)
+    }
+
+    fn janus_print(&self, ctx: &JanusContext) -> Result<String, SyntaxError> {
+        match self.indices.is_empty() {
+            true => Ok(format!("show({})", self.janus_source(ctx)?)),
+            false => Err(ctx.unsupported("printing array items"))
+        }
+    }
+}
+
+impl JanusExpression for ST::BinopNode {
+    fn janus_source(&self, ctx: &JanusContext) -> Result<String, SyntaxError> {
+        let (lhs, rhs) = (self.lhs.janus_source(ctx)?, self.rhs.janus_source(ctx)?);
+        // Janus divides integers, rounding towards zero, so only integer division carries over //
+        let op = match self.op {
+            Instruction::BinopAdd | Instruction::BinopSub | Instruction::BinopMul | Instruction::BinopMod |
+            Instruction::BinopLess | Instruction::BinopLeq | Instruction::BinopGreat |
+            Instruction::BinopGeq | Instruction::BinopNeq => operator_symbol(&self.op),
+            Instruction::BinopIDiv => "/",
+            Instruction::BinopEq => "=",
+            Instruction::BinopAnd => "&&",
+            Instruction::BinopOr => "||",
+            Instruction::BinopXor => return Ok(format!("(({} != 0) != ({} != 0))", lhs, rhs)),
+            _ => return Err(ctx.unsupported(&format!("the \"{}\" operator", operator_symbol(&self.op))))
+        };
+        Ok(format!("({} {} {})", lhs, op, rhs))
+    }
+}
+
+impl JanusExpression for ST::UniopNode {
+    fn janus_source(&self, ctx: &JanusContext) -> Result<String, SyntaxError> {
+        let expr = self.expr.janus_source(ctx)?;
+        match self.op {
+            Instruction::UniopNeg => Ok(format!("(0 - {})", expr)),
+            Instruction::UniopNot => Ok(format!("!{}", expr)),
+            _ => Err(ctx.unsupported(&format!("\"{}\"", operator_symbol(&self.op))))
+        }
+    }
+}
+
+
+// ---------------------------- Statement Nodes ----------------------------- //
+
+impl JanusStatement for ST::PositionedNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        ctx.position = (self.line, self.col);
+        self.stmt.emit_janus(ctx)
+    }
+}
+
+impl JanusStatement for ST::PrintNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        for item in &self.items {
+            let line = item.janus_print(ctx)?;
+            ctx.line(&line);
+        }
+        Ok(())
+    }
+}
+
+// Each binding opens a local block, which Janus needs closing in reverse order. The variables of
+// main still bound when it ends are declared instead //
+impl JanusStatement for ST::LetUnletNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        if self.is_mono {
+            return Err(ctx.unsupported("mono variables"));
+        }
+        let items = self.rhs.janus_items(ctx)?;
+        let (decl_suffix, value) = match self.rhs.literal_length() {
+            Some(len) => (format!("[{}]", len), format!("{{{}}}", items.join(", "))),
+            None => (String::new(), items.join(", "))
+        };
+        if self.is_unlet {
+            let name = ctx.names[self.register].clone();
+            let idx = match ctx.locals.iter().rposition(|local| local.name == name) {
+                Some(idx) => idx,
+                None => return Err(ctx.error(format!("Janus has no local binding of \"{}\" to unbind", name)))
+            };
+            // Those bound since can only outlive this one as declarations of main //
+            for local in ctx.locals.split_off(idx + 1) {
+                ctx.declare(local)?;
+            }
+            ctx.locals.pop();
+            ctx.line(&format!("delocal int {}{} = {}", name, decl_suffix, value));
+            return Ok(());
+        }
+        let name = ctx.bind(self.register);
+        let initialisers = match self.rhs.literal_length() {
+            Some(_) => items.iter().enumerate().map(|(i, item)| format!("{}[{}] += {}", name, i, item)).collect(),
+            None => vec![format!("{} += {}", name, value)]
+        };
+        ctx.line(&format!("local int {}{} = {}", name, decl_suffix, value));
+        ctx.locals.push(Local{
+            name: name.clone(),
+            line: ctx.lines.len() - 1,
+            declaration: format!("int {}{}", name, decl_suffix),
+            initialisers,
+            position: ctx.position
+        });
+        Ok(())
+    }
+}
+
+impl JanusStatement for ST::RefUnrefNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("references"))
+    }
+}
+
+impl JanusStatement for ST::RelinkNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("references"))
+    }
+}
+
+impl JanusStatement for ST::MoveNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("moving variables"))
+    }
+}
+
+impl JanusStatement for ST::ModopNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        if self.is_mono {
+            return Err(ctx.unsupported("mono variables"));
+        }
+        let op = match self.op {
+            Instruction::BinopAdd => "+=",
+            Instruction::BinopSub => "-=",
+            _ => return Err(ctx.unsupported(&format!("\"{}=\"", operator_symbol(&self.op))))
+        };
+        let line = format!("{} {} {}", self.lookup.janus_source(ctx)?, op, self.rhs.janus_source(ctx)?);
+        ctx.line(&line);
+        Ok(())
+    }
+}
+
+impl JanusStatement for ST::DestructureLetNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("destructuring arrays"))
+    }
+}
+
+impl JanusStatement for ST::PushPullNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("pushing and pulling array items"))
+    }
+}
+
+impl JanusStatement for ST::IfNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        if self.is_mono {
+            return Err(ctx.unsupported("mono if statements"));
+        }
+        let line = format!("if {} then", self.fwd_expr.janus_source(ctx)?);
+        ctx.line(&line);
+        ctx.emit_stmts(&self.if_stmts)?;
+        if !self.else_stmts.is_empty() {
+            ctx.line("else");
+            ctx.emit_stmts(&self.else_stmts)?;
+        }
+        let line = format!("fi {}", self.bkwd_expr.janus_source(ctx)?);
+        ctx.line(&line);
+        Ok(())
+    }
+}
+
+// The backward condition holds once the loop has run, so negated it is Janus's entry assertion //
+impl JanusStatement for ST::WhileNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        let bkwd_expr = match (&self.bkwd_expr, self.is_mono) {
+            (Some(bkwd_expr), false) => bkwd_expr.janus_source(ctx)?,
+            _ => return Err(ctx.unsupported("mono while loops"))
+        };
+        let line = format!("from !{} do", bkwd_expr);
+        ctx.line(&line);
+        ctx.emit_stmts(&[])?;
+        ctx.line("loop");
+        ctx.emit_stmts(&self.stmts)?;
+        let line = format!("until !{}", self.fwd_expr.janus_source(ctx)?);
+        ctx.line(&line);
+        Ok(())
+    }
+}
+
+impl JanusStatement for ST::ForNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("for loops over arrays"))
+    }
+}
+
+impl JanusStatement for ST::ForRangeNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        if self.is_mono {
+            return Err(ctx.unsupported("mono for loops"));
+        }
+        if self.step.janus_source(ctx)? != "1" {
+            return Err(ctx.unsupported("ranges with a step other than 1"));
+        }
+        let (start, end) = (self.start.janus_source(ctx)?, self.end.janus_source(ctx)?);
+        let name = ctx.bind(self.register);
+        ctx.line(&format!("iterate int {} = {} to {} - 1", name, start, end));
+        ctx.emit_stmts(&self.stmts)?;
+        ctx.line("end");
+        Ok(())
+    }
+}
+
+impl JanusStatement for ST::ForGenNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("generators"))
+    }
+}
+
+impl JanusStatement for ST::YieldNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("generators"))
+    }
+}
+
+impl JanusStatement for ST::DoYieldNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("do/yield blocks; move the do block into a procedure, and call then uncall it"))
+    }
+}
+
+impl JanusStatement for ST::StaticAssertNode {
+    fn emit_janus(&self, _ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        Ok(())
+    }
+}
+
+impl JanusStatement for ST::BacktraceNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("backtrace"))
+    }
+}
+
+impl JanusStatement for ST::TodoNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("todo"))
+    }
+}
+
+// Expectations only check the state, so are left out //
+impl JanusStatement for ST::ExpectNode {
+    fn emit_janus(&self, _ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        Ok(())
+    }
+}
+
+impl JanusStatement for ST::CatchNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("catch"))
+    }
+}
+
+impl JanusStatement for ST::TryNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("try/catch"))
+    }
+}
+
+impl JanusStatement for ST::RememberNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        Err(ctx.unsupported("remember blocks"))
+    }
+}
+
+// Janus parameters are passed by reference, so a call translates when each result takes the name
+// of the stolen argument it replaces //
+impl JanusStatement for ST::CallNode {
+    fn emit_janus(&self, ctx: &mut JanusContext) -> Result<(), SyntaxError> {
+        let func = &ctx.functions[self.func_idx];
+        if self.is_mono {
+            return Err(ctx.unsupported("mono calls"));
+        }
+        if !self.default_args.is_empty() {
+            return Err(ctx.unsupported("default arguments"));
+        }
+        let mut args = Vec::with_capacity(self.borrow_args.len() + self.stolen_args.len());
+        for arg in &self.borrow_args {
+            if !arg.indices.is_empty() {
+                return Err(ctx.unsupported("passing array items"));
+            }
+            args.push(arg.janus_source(ctx)?);
+        }
+        let stolen: Vec<String> = self.stolen_args.iter().map(|&register| ctx.names[register].clone()).collect();
+        let returned: Vec<String> = self.return_args.iter().map(|&register| ctx.bind(register)).collect();
+        if stolen != returned {
+            return Err(ctx.error(format!(
+                "Janus calls update their arguments in place, so \"{}\" must return {} in the order they were stolen",
+                func.name, stolen.join(", "))));
+        }
+        args.extend(stolen);
+        let keyword = if self.is_uncall {"uncall"} else {"call"};
+        ctx.line(&format!("{} {}({})", keyword, function_name(&func.name), args.join(", ")));
+        Ok(())
+    }
+}
+
+
+fn function_source(function: &ST::FunctionNode, functions: &[ST::FunctionNode], is_main: bool) -> Result<String, SyntaxError> {
+    let mut ctx = JanusContext{
+        functions, function,
+        names: vec![String::new(); function.num_registers],
+        locals: Vec::new(),
+        declarations: Vec::new(),
+        is_main,
+        position: (0, 0),
+        indent: 0,
+        lines: Vec::new()
+    };
+    let params = function.borrow_registers.iter().zip(&function.borrow_types)
+        .chain(function.steal_registers.iter().zip(&function.steal_types));
+    let mut param_decls = Vec::new();
+    for (&register, t) in params {
+        let name = ctx.bind(register);
+        param_decls.push(ctx.param(&name, t)?);
+    }
+    let steals: Vec<&String> = function.steal_registers.iter().map(|&register| &ctx.names[register]).collect();
+    let returns: Vec<&String> = function.return_registers.iter().map(|&register| {
+        let symbol = function.symbols.iter().rev().find(|symbol| symbol.register == register && symbol.end.is_none());
+        symbol.map(|symbol| &symbol.name).unwrap_or(&ctx.names[register])
+    }).collect();
+    if steals != returns {
+        return Err(ctx.error(format!(
+            "Janus procedures update their parameters in place, so \"{}\" must return what it steals, in the same order",
+            function.name)));
+    }
+
+    ctx.emit_stmts(&function.stmts)?;
+    for local in std::mem::take(&mut ctx.locals) {
+        ctx.declare(local)?;
+    }
+
+    let name = if is_main {String::from("main")} else {function_name(&function.name)};
+    let mut source = format!("procedure {}({})\n", name, param_decls.join(", "));
+    for declaration in &ctx.declarations {
+        source += &format!("    {}\n", declaration);
+    }
+    for line in &ctx.lines {
+        source += &format!("{}\n", line);
+    }
+    Ok(source)
+}
+
+// The module as Janus source, with main first as Janus expects //
+pub fn module_source(module: &ST::Module) -> Result<String, SyntaxError> {
+    if !module.global_func.stmts.is_empty() {
+        return Err(SyntaxError{line: 0, col: 0, desc: String::from(
+            "Janus has no equivalent of global variables"), note: None});
+    }
+    let main_idx = module.main_idx.ok_or(SyntaxError{line: 0, col: 0, desc: String::from(
+        "Janus programs need a main function"), note: None})?;
+    let order = std::iter::once(main_idx).chain((0..module.functions.len()).filter(|&idx| idx != main_idx));
+    let mut procedures = Vec::with_capacity(module.functions.len());
+    for idx in order {
+        procedures.push(function_source(&module.functions[idx], &module.functions, idx == main_idx)?);
+    }
+    Ok(procedures.join("\n"))
+}
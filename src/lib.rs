@@ -0,0 +1,44 @@
+// The language as a library, for embedding: compile a module, then run it with an Interpreter,
+// registering host functions and execution hooks on it as needed. The monoxide binary is built on it //
+
+extern crate num_rational;
+
+pub mod tokeniser;
+pub mod interpreter;
+pub mod parsetree;
+pub mod syntaxtree;
+pub mod syntaxchecker;
+pub mod compiler;
+pub mod optimiser;
+pub mod parser;
+pub mod isa;
+pub mod verifier;
+pub mod repl;
+pub mod notebook;
+pub mod rustgen;
+pub mod cgen;
+pub mod diff;
+pub mod debugger;
+pub mod dap;
+pub mod typechecker;
+pub mod cfg;
+pub mod desugar;
+pub mod formatter;
+pub mod janus;
+pub mod circuit;
+pub mod timeline;
+pub mod hook;
+pub mod host;
+pub mod convert;
+#[cfg(feature = "serde")]
+pub mod serialise;
+pub mod macros;
+pub mod imports;
+pub mod cache;
+pub mod manifest;
+pub mod lint;
+pub mod minimise;
+pub mod fuzz;
+pub mod mutate;
+pub mod logging;
+pub mod options;
@@ -0,0 +1,103 @@
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::cfg::Cfg;
+use crate::parser::{self, ParseError};
+use crate::syntaxchecker::{check_syntax_quietly, Note, SyntaxError};
+use crate::tokeniser::Tokeniser;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub col: usize,
+    pub severity: Severity,
+    pub desc: String,
+    pub note: Option<Note>
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning"
+        };
+        write!(f, "{}:{}: {}: {}", self.line, self.col, severity, self.desc)
+    }
+}
+
+
+// Tokenise, parse and check a source file without compiling it, collecting everything wrong with
+// it rather than printing anything. Checking stops at the first error, so there is at most one,
+// and warnings are only found in programs that check cleanly //
+pub fn check(src: &str, cfg: &Cfg) -> Vec<Diagnostic> {
+    let error = |line, col, desc, note| vec![Diagnostic{line, col, severity: Severity::Error, desc, note}];
+    let checked = panic::catch_unwind(AssertUnwindSafe(|| {
+        let parsed = parser::parse(Tokeniser::new(src))?;
+        Ok(check_syntax_quietly(parsed, cfg, false))
+    }));
+    match checked {
+        Ok(Ok(Ok((_, warnings)))) => warnings.into_iter()
+            .map(|SyntaxError{line, col, desc, note}| Diagnostic{line, col, severity: Severity::Warning, desc, note})
+            .collect(),
+        Ok(Ok(Err(SyntaxError{line, col, desc, note}))) => error(line, col, desc, note),
+        Ok(Err(ParseError{line, col, hint})) => error(line, col, match hint {
+            Some(hint) => format!("Failed to parse, {}", hint),
+            None => String::from("Failed to parse")
+        }, None),
+        Err(payload) => {
+            let desc = match payload.downcast::<String>() {
+                Ok(desc) => *desc,
+                Err(payload) => payload.downcast::<&str>().map_or("Unknown error", |desc| *desc).to_string()
+            };
+            error(0, 0, desc, None)
+        }
+    }
+}
+
+
+// The lines of a source file, kept so diagnostics can quote them //
+pub struct SourceText<'a> {
+    lines: Vec<&'a str>
+}
+
+impl<'a> SourceText<'a> {
+    pub fn new(src: &'a str) -> SourceText<'a> {
+        SourceText{lines: src.lines().collect()}
+    }
+
+    // The quoted line of a position with carets under the token there, followed by the line of
+    // any note with its label. Empty for positions outside the file //
+    pub fn snippet(&self, line: usize, col: usize, note: Option<&Note>) -> String {
+        let width = note.map_or(line, |note| note.line.max(line)).to_string().len();
+        let mut out = self.quote(line, col, width, '^', "");
+        if let Some(Note{line, col, desc}) = note {
+            out += &self.quote(*line, *col, width, '-', desc);
+        }
+        out
+    }
+
+    fn quote(&self, line: usize, col: usize, width: usize, marker: char, label: &str) -> String {
+        let text = match line.checked_sub(1).and_then(|idx| self.lines.get(idx)) {
+            Some(text) if col <= text.len() && text.is_char_boundary(col) => text,
+            _ => return String::new()
+        };
+        // Keep tabs in the indent so the markers line up with the quoted text //
+        let indent: String = text[..col].chars().map(|c| if c == '\t' {'\t'} else {' '}).collect();
+        let token = &text[col..];
+        let len = match token.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.')) {
+            Some(0) => token.chars().next().map_or(1, char::len_utf8),
+            Some(len) => len,
+            None => token.len().max(1)
+        };
+        let markers = marker.to_string().repeat(token[..len].chars().count().max(1));
+        let label = if label.is_empty() {String::new()} else {format!(" {}", label)};
+        format!("{:w$} |\n{:>w$} | {}\n{:w$} | {}{}{}\n", "", line, text, "", indent, markers, label, w = width)
+    }
+}
@@ -0,0 +1,42 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+
+// Internal logging, written to stderr so it never mixes with a program's output. Off unless a
+// level is chosen with --log, e.g. "--log trace" to see memo hits, register allocation, emitted
+// instructions and calls //
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let module = record.target().rsplit("::").next().unwrap_or("");
+            eprintln!("[{} {}] {}", level_name(record.level()), module, record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Error => "error",
+        Level::Warn => "warn",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace"
+    }
+}
+
+pub fn level_from_name(name: &str) -> Option<LevelFilter> {
+    name.parse().ok()
+}
+
+pub fn init(level: LevelFilter) {
+    static LOGGER: StderrLogger = StderrLogger;
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+}
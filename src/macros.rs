@@ -0,0 +1,545 @@
+use std::collections::HashMap;
+use std::mem;
+
+use crate::parsetree as PT;
+use crate::syntaxchecker::{Note, SyntaxError};
+
+
+// Replace each macro call with the body of the macro. Parameters are replaced by the caller's
+// arguments, so the body works on the caller's variables directly, and any other variable the
+// body binds is given a fresh name for each use, so it can't clash with the caller's or with
+// another use of the macro. The ownership checker then treats them as ordinary locals //
+pub fn expand_macros(module: &mut PT::Module) -> Result<(), SyntaxError> {
+    let mut macros = HashMap::new();
+    for macro_def in mem::take(&mut module.macros) {
+        check_params(&macro_def)?;
+        if let Some(existing) = macros.get(&macro_def.name) {
+            let PT::MacroNode{line, col, ..} = existing;
+            return Err(SyntaxError{line: macro_def.line, col: macro_def.col, desc: format!(
+                "Macro \"{}\" is defined more than once", macro_def.name), note: Some(Note{
+                line: *line, col: *col, desc: String::from("first defined here")})});
+        }
+        macros.insert(macro_def.name.clone(), macro_def);
+    }
+
+    let mut expander = Expander{macros, expanded: 0, stack: Vec::new()};
+    check_no_params(&mut module.global_func.stmts)?;
+    expander.expand_stmts(&mut module.global_func.stmts, "")?;
+    expander.expand_functions(&mut module.functions, "")
+}
+
+fn check_params(macro_def: &PT::MacroNode) -> Result<(), SyntaxError> {
+    let PT::MacroNode{line, col, name, params, ..} = macro_def;
+    for (idx, param) in params.iter().enumerate() {
+        let desc = if !param.starts_with('@') {
+            format!("Macro \"{}\" has parameter \"{}\", but parameters are written with a leading @, e.g. \"@{}\"",
+                name, param, param.trim_start_matches('.'))
+        } else if params[..idx].contains(param) {
+            format!("Macro \"{}\" has more than one parameter named \"{}\"", name, param)
+        } else {
+            continue;
+        };
+        return Err(SyntaxError{line: *line, col: *col, desc, note: None});
+    }
+    Ok(())
+}
+
+// Macro parameters mean nothing outside a macro body //
+fn check_no_params(stmts: &mut [PT::StatementNode]) -> Result<(), SyntaxError> {
+    let mut substitution = Substitution::new(None, HashMap::new(), String::new());
+    substitution.collecting = false;
+    for stmt in stmts {
+        stmt.substitute(&mut substitution)?;
+    }
+    Ok(())
+}
+
+struct Expander {
+    macros: HashMap<String, PT::MacroNode>,
+    expanded: usize,  // Numbers each use, to make the names of its variables unique //
+    stack: Vec<String>  // The macros being expanded, to catch a macro that uses itself //
+}
+
+impl Expander {
+    fn expand_functions(&mut self, functions: &mut [PT::FunctionNode], scope: &str) -> Result<(), SyntaxError> {
+        for function in functions {
+            let name = match scope {
+                "" => function.name.clone(),
+                _ => format!("{}::{}", scope, function.name)
+            };
+            check_no_params(&mut function.stmts)?;
+            self.expand_stmts(&mut function.stmts, &name)?;
+            self.expand_functions(&mut function.functions, &name)?;
+        }
+        Ok(())
+    }
+
+    fn expand_stmts(&mut self, stmts: &mut Vec<PT::StatementNode>, scope: &str) -> Result<(), SyntaxError> {
+        for mut stmt in mem::take(stmts) {
+            match stmt.macro_call() {
+                Some(call) => {
+                    let mut expansion = self.expand(call, scope)?;
+                    self.expand_stmts(&mut expansion, scope)?;
+                    self.stack.pop();
+                    stmts.extend(expansion);
+                },
+                None => {
+                    for substmts in stmt.substatements() {
+                        self.expand_stmts(substmts, scope)?;
+                    }
+                    stmts.push(stmt);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Macros defined in an imported module are found from that module's functions unqualified, as
+    // functions are //
+    fn lookup(&self, name: &str, scope: &str) -> Option<&PT::MacroNode> {
+        let mut scope = Some(scope).filter(|scope| !scope.is_empty());
+        while let Some(prefix) = scope {
+            if let Some(macro_def) = self.macros.get(&format!("{}::{}", prefix, name)) {
+                return Some(macro_def);
+            }
+            scope = prefix.rfind("::").map(|idx| &prefix[..idx]);
+        }
+        self.macros.get(name)
+    }
+
+    fn expand(&mut self, call: &PT::MacroCallNode, scope: &str) -> Result<Vec<PT::StatementNode>, SyntaxError> {
+        let PT::MacroCallNode{line, col, name, args} = call;
+        let err = |desc| Err(SyntaxError{line: *line, col: *col, desc, note: None});
+        let macro_def = match self.lookup(name, scope) {
+            Some(macro_def) => macro_def.clone(),
+            None => return err(format!("Undefined macro \"{}\"", name))
+        };
+        if self.stack.contains(&macro_def.name) {
+            return err(format!("Macro \"{}\" is used inside its own expansion", macro_def.name));
+        }
+        if args.len() != macro_def.params.len() {
+            let plural = |count| if count == 1 {""} else {"s"};
+            return err(format!("Macro \"{}\" takes {} argument{}, but was given {}",
+                name, macro_def.params.len(), plural(macro_def.params.len()), args.len()));
+        }
+
+        self.expanded += 1;
+        let base_name = macro_def.name.rsplit("::").next().unwrap_or(&macro_def.name);
+        let params = macro_def.params.iter().cloned().zip(args.iter().cloned()).collect();
+        let mut substitution = Substitution::new(
+            Some(&macro_def.name), params, format!("{}.{}.", base_name, self.expanded));
+        let mut stmts = macro_def.stmts.clone();
+        for stmt in stmts.iter_mut() {
+            stmt.substitute(&mut substitution)?;
+        }
+        substitution.collecting = false;
+        for stmt in stmts.iter_mut() {
+            stmt.substitute(&mut substitution)?;
+        }
+        self.stack.push(macro_def.name.clone());
+        Ok(stmts)
+    }
+}
+
+
+// Renames the variables of a macro body. A first pass over the body collects the names it binds,
+// and a second renames them, and replaces the parameters with the arguments //
+pub struct Substitution<'a> {
+    macro_name: Option<&'a str>,
+    params: HashMap<String, PT::LookupNode>,
+    renamed: HashMap<String, String>,
+    prefix: String,
+    collecting: bool
+}
+
+pub trait Substitute {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError>;
+}
+
+impl<'a> Substitution<'a> {
+    fn new(macro_name: Option<&'a str>, params: HashMap<String, PT::LookupNode>, prefix: String) -> Substitution<'a> {
+        Substitution{macro_name, params, renamed: HashMap::new(), prefix, collecting: true}
+    }
+
+    fn unknown_param(&self, name: &str, line: usize, col: usize) -> SyntaxError {
+        let desc = match self.macro_name {
+            Some(macro_name) => format!("Macro \"{}\" has no parameter \"{}\"", macro_name, name),
+            None => format!("\"{}\" is a macro parameter, so can only be used inside a macro", name)
+        };
+        SyntaxError{line, col, desc, note: None}
+    }
+
+    // A variable named outright, rather than looked up, e.g. the "x" in "x = 1;" //
+    fn name(&mut self, name: &mut String, line: usize, col: usize, is_binding: bool) -> Result<(), SyntaxError> {
+        if self.collecting {
+            if is_binding && !name.starts_with('@') && !self.renamed.contains_key(name) {
+                let fresh = match name.strip_prefix('.') {
+                    Some(mono_name) => format!(".{}{}", self.prefix, mono_name),
+                    None => format!("{}{}", self.prefix, name)
+                };
+                self.renamed.insert(name.clone(), fresh);
+            }
+        } else if name.starts_with('@') {
+            match self.params.get(name.as_str()) {
+                Some(arg) if arg.indices.is_empty() => *name = arg.name.clone(),
+                Some(_) => return Err(SyntaxError{line, col, desc: format!(
+                    "Macro \"{}\" binds or unbinds {} by name, so its argument must be a plain variable, not an array element",
+                    self.macro_name.unwrap_or_default(), name), note: None}),
+                None => return Err(self.unknown_param(name, line, col))
+            }
+        } else if let Some(fresh) = self.renamed.get(name.as_str()) {
+            *name = fresh.clone();
+        }
+        Ok(())
+    }
+
+    fn bound(&mut self, name: &mut String, line: usize, col: usize) -> Result<(), SyntaxError> {
+        self.name(name, line, col, true)
+    }
+
+    fn used(&mut self, name: &mut String, line: usize, col: usize) -> Result<(), SyntaxError> {
+        self.name(name, line, col, false)
+    }
+
+    fn lookup(&mut self, lookup: &mut PT::LookupNode) -> Result<(), SyntaxError> {
+        for index in lookup.indices.iter_mut() {
+            index.substitute(self)?;
+        }
+        if self.collecting {
+            return Ok(());
+        }
+        if lookup.name.starts_with('@') {
+            let arg = self.params.get(lookup.name.as_str())
+                .ok_or_else(|| self.unknown_param(&lookup.name, lookup.line, lookup.col))?;
+            lookup.name = arg.name.clone();
+            lookup.indices.splice(0..0, arg.indices.iter().cloned());
+        } else if let Some(fresh) = self.renamed.get(lookup.name.as_str()) {
+            lookup.name = fresh.clone();
+        }
+        Ok(())
+    }
+
+    fn exprs(&mut self, exprs: &mut [PT::ExpressionNode]) -> Result<(), SyntaxError> {
+        for expr in exprs {
+            expr.substitute(self)?;
+        }
+        Ok(())
+    }
+
+    fn stmts(&mut self, stmts: &mut [PT::StatementNode]) -> Result<(), SyntaxError> {
+        for stmt in stmts {
+            stmt.substitute(self)?;
+        }
+        Ok(())
+    }
+}
+
+
+impl Substitute for PT::FractionNode {
+    fn substitute(&mut self, _s: &mut Substitution) -> Result<(), SyntaxError> {
+        Ok(())
+    }
+}
+
+impl Substitute for PT::StringNode {
+    fn substitute(&mut self, _s: &mut Substitution) -> Result<(), SyntaxError> {
+        Ok(())
+    }
+}
+
+impl Substitute for PT::HoleNode {
+    fn substitute(&mut self, _s: &mut Substitution) -> Result<(), SyntaxError> {
+        Ok(())
+    }
+}
+
+impl Substitute for PT::CounterNode {
+    fn substitute(&mut self, _s: &mut Substitution) -> Result<(), SyntaxError> {
+        Ok(())
+    }
+}
+
+impl Substitute for PT::ArrayLiteralNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.exprs(&mut self.items)
+    }
+}
+
+impl Substitute for PT::ArrayRepeatNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        self.item.substitute(s)?;
+        self.dimensions.substitute(s)
+    }
+}
+
+impl Substitute for PT::ComprehensionNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.bound(&mut self.iter_var, self.line, self.col)?;
+        self.item.substitute(s)?;
+        s.lookup(&mut self.source)?;
+        match &mut self.filter {
+            Some(filter) => filter.substitute(s),
+            None => Ok(())
+        }
+    }
+}
+
+impl Substitute for PT::CallExprNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        for arg in self.borrow_args.iter_mut() {
+            s.lookup(arg)?;
+        }
+        Ok(())
+    }
+}
+
+impl Substitute for PT::LookupNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.lookup(self)
+    }
+}
+
+impl Substitute for PT::BinopNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        self.lhs.substitute(s)?;
+        self.rhs.substitute(s)
+    }
+}
+
+impl Substitute for PT::UniopNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        self.expr.substitute(s)
+    }
+}
+
+
+impl Substitute for PT::PositionedNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        self.stmt.substitute(s)
+    }
+}
+
+impl Substitute for PT::PrintNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.exprs(&mut self.items)
+    }
+}
+
+impl Substitute for PT::LetUnletNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.name(&mut self.name, self.line, self.col, !self.is_unlet)?;
+        self.rhs.substitute(s)
+    }
+}
+
+impl Substitute for PT::RefUnrefNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.name(&mut self.name, self.line, self.col, !self.is_unref)?;
+        if let Some((start, end)) = &mut self.range {
+            start.substitute(s)?;
+            end.substitute(s)?;
+        }
+        s.lookup(&mut self.rhs)
+    }
+}
+
+impl Substitute for PT::RelinkNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.name(&mut self.name, self.line, self.col, false)?;
+        s.lookup(&mut self.old)?;
+        s.lookup(&mut self.new)
+    }
+}
+
+impl Substitute for PT::MoveNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.name(&mut self.name, self.line, self.col, true)?;
+        s.name(&mut self.src, self.line, self.col, false)
+    }
+}
+
+impl Substitute for PT::ModopNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.lookup(&mut self.lookup)?;
+        self.rhs.substitute(s)
+    }
+}
+
+impl Substitute for PT::DestructureLetNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        for (name, _) in self.pattern.iter_mut() {
+            s.name(name, self.line, self.col, !self.is_unlet)?;
+        }
+        s.name(&mut self.name, self.line, self.col, self.is_unlet)
+    }
+}
+
+impl Substitute for PT::PushPullNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.name(&mut self.name, self.line, self.col, !self.is_push)?;
+        s.lookup(&mut self.lookup)
+    }
+}
+
+impl Substitute for PT::IfNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        self.fwd_expr.substitute(s)?;
+        s.stmts(&mut self.if_stmts)?;
+        s.stmts(&mut self.else_stmts)?;
+        self.bkwd_expr.substitute(s)
+    }
+}
+
+impl Substitute for PT::WhileNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        self.fwd_expr.substitute(s)?;
+        s.stmts(&mut self.stmts)?;
+        match &mut self.bkwd_expr {
+            Some(bkwd_expr) => bkwd_expr.substitute(s),
+            None => Ok(())
+        }
+    }
+}
+
+impl Substitute for PT::ForNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        let (line, col) = (self.iterator.line, self.iterator.col);
+        s.bound(&mut self.iter_var, line, col)?;
+        if let Some(index_var) = &mut self.index_var {
+            s.bound(index_var, line, col)?;
+        }
+        s.lookup(&mut self.iterator)?;
+        s.stmts(&mut self.stmts)
+    }
+}
+
+impl Substitute for PT::ForRangeNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.bound(&mut self.iter_var, self.line, self.col)?;
+        self.start.substitute(s)?;
+        self.end.substitute(s)?;
+        if let Some(step) = &mut self.step {
+            step.substitute(s)?;
+        }
+        s.stmts(&mut self.stmts)
+    }
+}
+
+impl Substitute for PT::ForGenNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.bound(&mut self.iter_var, self.line, self.col)?;
+        for arg in self.borrow_args.iter_mut() {
+            s.lookup(arg)?;
+        }
+        s.stmts(&mut self.stmts)
+    }
+}
+
+impl Substitute for PT::YieldNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        self.expr.substitute(s)
+    }
+}
+
+impl Substitute for PT::DoYieldNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.stmts(&mut self.do_stmts)?;
+        s.stmts(&mut self.yield_stmts)
+    }
+}
+
+impl Substitute for PT::CatchNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        self.expr.substitute(s)
+    }
+}
+
+impl Substitute for PT::TryNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.stmts(&mut self.body)?;
+        self.cond.substitute(s)?;
+        s.stmts(&mut self.handler)?;
+        self.bkwd_cond.substitute(s)
+    }
+}
+
+impl Substitute for PT::RememberNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.stmts(&mut self.body)
+    }
+}
+
+impl Substitute for PT::MachineNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.bound(&mut self.name, self.line, self.col)?;
+        for state in self.states.iter_mut() {
+            s.stmts(&mut state.stmts)?;
+            for transition in state.transitions.iter_mut() {
+                transition.cond.substitute(s)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Substitute for PT::MacroCallNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        for arg in self.args.iter_mut() {
+            s.lookup(arg)?;
+        }
+        Ok(())
+    }
+}
+
+impl Substitute for PT::TodoNode {
+    fn substitute(&mut self, _s: &mut Substitution) -> Result<(), SyntaxError> {
+        Ok(())
+    }
+}
+
+impl Substitute for PT::BacktraceNode {
+    fn substitute(&mut self, _s: &mut Substitution) -> Result<(), SyntaxError> {
+        Ok(())
+    }
+}
+
+impl Substitute for PT::StaticAssertNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        self.expr.substitute(s)
+    }
+}
+
+impl Substitute for PT::ExpectNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        self.actual.substitute(s)?;
+        match &mut self.expected {
+            Some(expected) => expected.substitute(s),
+            None => Ok(())
+        }
+    }
+}
+
+impl Substitute for PT::ConstEvalNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        s.name(&mut self.name, self.line, self.col, true)
+    }
+}
+
+impl Substitute for PT::CallNode {
+    fn substitute(&mut self, s: &mut Substitution) -> Result<(), SyntaxError> {
+        let (line, col) = (self.line, self.col);
+        for arg in self.borrow_args.iter_mut() {
+            s.lookup(arg)?;
+        }
+        for name in self.stolen_args.iter_mut() {
+            s.used(name, line, col)?;
+        }
+        for name in self.return_args.iter_mut() {
+            s.bound(name, line, col)?;
+        }
+        for binding in self.bindings.iter_mut() {
+            s.bound(&mut binding.name, line, col)?;
+        }
+        Ok(())
+    }
+}
@@ -1,41 +1,767 @@
-#![allow(dead_code)]
+use std::env;
+use std::fs;
+use std::io;
+use std::mem;
+use std::panic;
+use std::path::Path;
+use std::process;
+use std::time::Instant;
 
-extern crate num_rational;
+use monoxide::{
+    cache, cfg, cgen, circuit, compiler, dap, debugger, desugar, formatter, fuzz, host, interpreter, isa,
+    janus, lint, logging, manifest, minimise, mutate, notebook, options, parser, parsetree, repl, rustgen, syntaxchecker, tokeniser
+};
+
+use cfg::Cfg;
+use options::EngineOptions;
+use syntaxchecker::{check_library, check_syntax_quietly, SyntaxError};
 
-use std::fs;
 
-mod tokeniser;
-mod interpreter;
-mod parsetree;
-mod syntaxtree;
-mod syntaxchecker;
-mod compiler;
-mod parser;
+fn report_syntax_error(src: &str, SyntaxError{line, col, desc, note}: SyntaxError) {
+    let source = lint::SourceText::new(src);
+    eprintln!("SyntaxError at line {}, column {}:\n ->  {}\n{}", line, col, desc, source.snippet(line, col, note.as_ref()));
+}
 
-use syntaxchecker::{check_syntax, SyntaxError};
+// The file's parse tree, or else the parse error is reported and the process exits //
+fn parse_or_exit(src: &str) -> parsetree::Module {
+    parser::parse(tokeniser::Tokeniser::new(src)).unwrap_or_else(|err| {
+        eprintln!("{}\n{}", err, lint::SourceText::new(src).snippet(err.line, err.col, None));
+        process::exit(1)
+    })
+}
 
+fn compile_file(path: &str, cfg: &Cfg, options: &EngineOptions, debug_symbols: bool, sandboxed: bool) -> Option<interpreter::Module> {
+    let src = fs::read_to_string(path).expect("File io error");
+    let parsed = parse_or_exit(&src);
 
-type Fraction = num_rational::BigRational;
+    // Imports not found in the configured paths are looked for next to the file //
+    let mut cfg = cfg.clone();
+    cfg.add_include_path(Path::new(path).parent().unwrap_or(Path::new(".")));
+    let cfg = &cfg;
+
+    let source = lint::SourceText::new(&src);
+    let module = match check_syntax_quietly(parsed, cfg, sandboxed) {
+        Ok((module, warnings)) => {
+            for SyntaxError{line, col, desc, note} in warnings {
+                eprintln!("Warning at line {}, column {}:\n ->  {}\n{}", line, col, desc, source.snippet(line, col, note.as_ref()));
+            }
+            module
+        },
+        Err(err) => {
+            report_syntax_error(&src, err);
+            return None;
+        }
+    };
+
+    // println!("Module: {:#?}", module);
+    Some(module.compile(debug_symbols, options))
+}
+
+// Print the module as source once #[cfg] attributes are applied and the desugaring passes have run.
+// Imports are left as they are, so the output can be parsed again //
+fn emit_desugared(path: &str, cfg: &Cfg) {
+    let src = fs::read_to_string(path).expect("File io error");
+    let mut module = parse_or_exit(&src);
+    match cfg::configure(&mut module, cfg).and_then(|_| desugar::desugar(&mut module)) {
+        Ok(()) => print!("{}", formatter::module_source(&module)),
+        Err(err) => report_syntax_error(&src, err)
+    }
+}
+
+// Check that each file's parse tree, and the tree once #[cfg] attributes are applied and desugared,
+// come back the same from printing and parsing again, as the minimiser and --emit desugared assume //
+fn self_check(paths: &[&str], cfg: &Cfg) -> bool {
+    let mut passed = true;
+    for path in paths {
+        let src = fs::read_to_string(path).expect("File io error");
+        let result = parser::parse(tokeniser::Tokeniser::new(&src)).map_err(|err| err.to_string()).and_then(|mut module| {
+            formatter::check_round_trip(&module)?;
+            cfg::configure(&mut module, cfg).and_then(|_| desugar::desugar(&mut module))
+                .map_err(|SyntaxError{line, col, desc, ..}| format!("SyntaxError at line {}, column {}: {}", line, col, desc))?;
+            formatter::check_round_trip(&module).map_err(|desc| format!("Once desugared, {}", desc))
+        });
+        match result {
+            Ok(()) => println!("{}: ok", path),
+            Err(desc) => {
+                println!("{}: {}", path, desc);
+                passed = false;
+            }
+        }
+    }
+    passed
+}
+
+// Print the checked module as Janus source, for running on other reversible language interpreters //
+fn emit_janus(path: &str, cfg: &Cfg) {
+    let src = fs::read_to_string(path).expect("File io error");
+    let parsed = parse_or_exit(&src);
+    let mut cfg = cfg.clone();
+    cfg.add_include_path(Path::new(path).parent().unwrap_or(Path::new(".")));
+    match check_syntax_quietly(parsed, &cfg, false).and_then(|(module, _)| janus::module_source(&module)) {
+        Ok(source) => print!("{}", source),
+        Err(err) => report_syntax_error(&src, err)
+    }
+}
+
+fn emit_qasm(path: &str, cfg: &Cfg, entry: Option<&str>, bits: usize) {
+    let src = fs::read_to_string(path).expect("File io error");
+    let parsed = parse_or_exit(&src);
+    let mut cfg = cfg.clone();
+    cfg.add_include_path(Path::new(path).parent().unwrap_or(Path::new(".")));
+    match check_syntax_quietly(parsed, &cfg, false).and_then(|(module, _)| circuit::module_source(&module, entry, bits)) {
+        Ok(source) => print!("{}", source),
+        Err(err) => report_syntax_error(&src, err)
+    }
+}
+
+// Print the library artifact (.rvc) of a module, which modules with it in their include paths can
+// import without its source //
+fn emit_library(path: &str, cfg: &Cfg, options: &EngineOptions) {
+    let src = fs::read_to_string(path).expect("File io error");
+    let parsed = parse_or_exit(&src);
+    let mut cfg = cfg.clone();
+    cfg.add_include_path(Path::new(path).parent().unwrap_or(Path::new(".")));
+    match check_library(parsed, &cfg) {
+        Ok((module, prototypes)) => print!("{}", cache::library_to_string(&module.compile(false, options), &prototypes)),
+        Err(err) => report_syntax_error(&src, err)
+    }
+}
+
+// Write the project as a cargo project in target/rust/<name>, to be compiled into a native binary //
+fn build_native(program: &interpreter::Module, name: &str) {
+    let source = match rustgen::module_source(program, name) {
+        Ok(source) => source,
+        Err(desc) => {
+            eprintln!("{}", desc);
+            return;
+        }
+    };
+    let dir = Path::new("target/rust").join(name);
+    let written = fs::create_dir_all(dir.join("src"))
+        .and_then(|_| fs::write(dir.join("Cargo.toml"), rustgen::cargo_manifest(name)))
+        .and_then(|_| fs::write(dir.join("src/main.rs"), source));
+    match written {
+        Ok(()) => println!("Wrote {}, build it with: cargo build --release --manifest-path {}",
+                           dir.display(), dir.join("Cargo.toml").display()),
+        Err(err) => eprintln!("Can't write {}: {}", dir.display(), err)
+    }
+}
+
+// Write the project as a single C file in target/c/<name>, to be compiled by any C compiler //
+fn build_c(program: &interpreter::Module, name: &str) {
+    let source = match cgen::module_source(program, name) {
+        Ok(source) => source,
+        Err(desc) => {
+            eprintln!("{}", desc);
+            return;
+        }
+    };
+    let dir = Path::new("target/c").join(name);
+    let file = dir.join(format!("{}.c", name));
+    match fs::create_dir_all(&dir).and_then(|_| fs::write(&file, source)) {
+        Ok(()) => println!("Wrote {}, build it with: cc -O2 -o {} {} -lm",
+                           file.display(), dir.join(name).display(), file.display()),
+        Err(err) => eprintln!("Can't write {}: {}", dir.display(), err)
+    }
+}
+
+// Time repeated runs of a program, and compare the size of its packed instructions with the
+// unpacked enum //
+fn bench(program: &interpreter::Module, runs: usize, arithmetic: interpreter::Arithmetic, options: &EngineOptions) {
+    let streams = program.functions.iter().flat_map(|func| [&func.code.fwd, &func.code.bkwd]);
+    let count: usize = streams.clone().map(|stream| stream.len()).sum();
+    let packed: usize = streams.map(|stream| stream.size_in_bytes()).sum();
+    let unpacked = count * mem::size_of::<interpreter::Instruction>();
+    println!("{} instructions: {} bytes packed, {} bytes unpacked", count, packed, unpacked);
+
+    let start = Instant::now();
+    let mut frames = interpreter::FrameStats::default();
+    for _ in 0..runs {
+        let mut interpreter = interpreter::Interpreter::new(program);
+        host::register_builtins(&mut interpreter);
+        interpreter.capture_output();
+        interpreter.set_arithmetic(arithmetic);
+        interpreter.set_options(options);
+        interpreter.run_to_completion(program);
+        frames = interpreter.frame_stats();
+    }
+    let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+    println!("{} runs: {:.3} ms per run", runs, elapsed / runs.max(1) as f64);
+    println!("Calls per run: {} allocated frames, {} reused", frames.allocated, frames.reused);
+}
+
+// The function a run starts from, main unless chosen with --entry, and the source of the values
+// for its borrowed params //
+struct Entry<'a> {
+    name: Option<&'a str>,
+    args: Vec<&'a str>
+}
+
+impl Entry<'_> {
+    fn resolve(&self, program: &interpreter::Module) -> Result<(usize, Vec<interpreter::Variable>), String> {
+        let (func_idx, func) = self.function(program)?;
+        if func.num_steals > 0 {
+            return Err(format!("\"{}\" steals parameters, so can't be the entry point", func.name));
+        }
+        Ok((func_idx, self.borrow_args(func)?))
+    }
+
+    // As resolve, for uncalling the entry point on the values given for what it returns, which
+    // must be an array of one value for each //
+    fn resolve_inverse(&self, program: &interpreter::Module, outputs: &str)
+        -> Result<(usize, Vec<interpreter::Variable>, Vec<interpreter::Variable>), String>
+    {
+        let (func_idx, func) = self.function(program)?;
+        let outputs = match entry_arg(outputs)? {
+            interpreter::Variable::Array(items) => items.iter().map(|item| item.borrow().deep_copy()).collect::<Vec<_>>(),
+            _ => return Err(format!("--outputs takes an array of the {} value(s) \"{}\" returns, e.g. [1, 2]",
+                                    func.num_returns, func.name))
+        };
+        if outputs.len() != func.num_returns {
+            return Err(format!("\"{}\" returns {} value(s), but --outputs gives {}", func.name, func.num_returns, outputs.len()));
+        }
+        Ok((func_idx, self.borrow_args(func)?, outputs))
+    }
+
+    fn function<'p>(&self, program: &'p interpreter::Module) -> Result<(usize, &'p interpreter::Function), String> {
+        let found = match self.name {
+            None => program.main_idx,
+            Some(name) => program.functions.iter().enumerate()
+                .find(|&(idx, func)| func.name == name && idx != program.global_func_idx)
+                .map(|(idx, _)| idx)
+        };
+        let name = self.name.unwrap_or("main");
+        let func = match found {
+            Some(idx) => &program.functions[idx],
+            None if self.name.is_none() => return Err(String::from(
+                "No main function. Declare one with \"fn main()() {...} ~main()\", or choose another with --entry")),
+            None => return Err(format!("No function \"{}\" to use as the entry point", name))
+        };
+        Ok((found.unwrap_or_default(), func))
+    }
+
+    fn borrow_args(&self, func: &interpreter::Function) -> Result<Vec<interpreter::Variable>, String> {
+        if func.num_borrows != self.args.len() {
+            return Err(format!("\"{}\" borrows {} parameters, but {} arguments were given after --",
+                func.name, func.num_borrows, self.args.len()));
+        }
+        self.args.iter().map(|arg| entry_arg(arg)).collect()
+    }
+}
+
+// Entry point arguments are constant expressions, e.g. 3, 1/2, [1, 2] or 'text' //
+fn entry_arg(src: &str) -> Result<interpreter::Variable, String> {
+    let expr = parser::parse_expression(tokeniser::Tokeniser::new(src))
+        .map_err(|_| format!("Can't parse the argument \"{}\"", src))?;
+    let (expr, consts) = syntaxchecker::check_expression(expr, &[], &[])
+        .map_err(|SyntaxError{desc, ..}| format!("Can't use \"{}\" as an argument: {}", src, desc))?;
+    interpreter::Interpreter::evaluate_const(&compiler::compile_expression(&expr), &consts)
+        .map_err(|err| format!("Can't use \"{}\" as an argument: {}", src, err.desc))
+}
+
+// Run to the end, reporting any runtime error. An entry point other than main has what it
+// returns printed, one value per line //
+fn run(interpreter: &mut interpreter::Interpreter, program: &interpreter::Module, entry: &Entry) {
+    let (func_idx, args) = match entry.resolve(program) {
+        Ok(resolved) => resolved,
+        Err(desc) => {
+            eprintln!("{}", desc);
+            return;
+        }
+    };
+    let result = match entry.name {
+        None => interpreter.try_run_to_completion(program).map(|_| Vec::new()),
+        Some(_) => interpreter.try_run_entry(program, func_idx, args)
+    };
+    match result {
+        Ok(returns) => for value in returns {
+            println!("{}", value);
+        },
+        Err(err) => eprintln!("{}", err)
+    }
+}
+
+// Uncall the entry point on the values given for what it returns, printing what it would have had
+// to steal to return them, one value per line //
+fn invert(program: &interpreter::Module, entry: &Entry, outputs: &str, options: &EngineOptions) {
+    let (func_idx, args, outputs) = match entry.resolve_inverse(program, outputs) {
+        Ok(resolved) => resolved,
+        Err(desc) => {
+            eprintln!("{}", desc);
+            return;
+        }
+    };
+    let mut interpreter = interpreter::Interpreter::new(program);
+    host::register_builtins(&mut interpreter);
+    interpreter.set_options(options);
+    let result = interpreter.try_uncall_entry(program, func_idx, args, outputs);
+    match result {
+        Ok(inputs) => for value in inputs {
+            println!("{}", value);
+        },
+        Err(err) => eprintln!("{}", err)
+    }
+}
+
+// Run the bytecode verifier over each file's compiled module. With mutate, also check that it
+// rejects every deliberate mistake in the backward code, listing any it lets through //
+fn verify(paths: &[&str], mutate: bool, cfg: &Cfg, options: &EngineOptions) -> bool {
+    let mut passed = true;
+    for path in paths {
+        let Some(mut program) = compile_file(path, cfg, options, false, false) else {
+            passed = false;
+            continue;
+        };
+        if let Err(err) = program.validate() {
+            println!("{}: {}", path, err);
+            passed = false;
+            continue;
+        }
+        if !mutate {
+            println!("{}: ok", path);
+            continue;
+        }
+        let mutate::MutationReport{tried, survivors} = mutate::mutation_test(&mut program);
+        println!("{}: {} of {} mutations caught", path, tried - survivors.len(), tried);
+        for survivor in &survivors {
+            println!("{}: survived: {}", path, survivor);
+        }
+        passed &= survivors.is_empty();
+    }
+    passed
+}
+
+// Generate programs from the grammar and check the parser and formatter handle each, printing any
+// they don't. The seed given with --seed picks the programs //
+fn fuzz_parser(runs: usize, seed: u64) -> bool {
+    let mut fuzzer = fuzz::Fuzzer::new(seed);
+    let mut failures = 0;
+    panic::set_hook(Box::new(|_| {}));
+    for _ in 0..runs {
+        let src = fuzzer.next_input();
+        if let Err(desc) = fuzzer.check(&src) {
+            failures += 1;
+            println!("{}\n ->  on input: {}\n", desc, src);
+        }
+    }
+    let _ = panic::take_hook();
+    println!("{} inputs, {} accepted by the parser, {} failures", runs, fuzzer.accepted, failures);
+    failures == 0
+}
+
+fn write_timeline(interpreter: &interpreter::Interpreter, path: &str, out: &str) {
+    let src = fs::read_to_string(path).unwrap_or_default();
+    match fs::write(out, interpreter.timeline_html(path, &src)) {
+        Ok(()) => eprintln!("Wrote the timeline to {}", out),
+        Err(err) => eprintln!("Can't write the timeline to {}: {}", out, err)
+    }
+}
+
+// The options for a run: the base, e.g. those in the project's manifest, then any REAVER_*
+// environment variables, then any flags. Logging starts at the level chosen //
+fn engine_options(base: EngineOptions, flags: &[(&str, &str)]) -> Option<EngineOptions> {
+    let mut options = base;
+    match options.apply_env().and_then(|_| options.apply_flags(flags)) {
+        Ok(()) => {
+            logging::init(options.log_level);
+            Some(options)
+        },
+        Err(desc) => {
+            eprintln!("{}", desc);
+            None
+        }
+    }
+}
+
+// The module file, name, configuration and engine options of the project whose manifest is in
+// the current directory, with any flags given on the command line added //
+fn load_project(extra: &Cfg, option_flags: &[(&str, &str)]) -> Option<(String, String, Cfg, EngineOptions)> {
+    let loaded = manifest::Manifest::load(Path::new("."));
+    let found = loaded.and_then(|manifest| Ok((manifest.main_file()?, manifest)));
+    match found {
+        Ok((path, manifest)) => {
+            let mut cfg = manifest.cfg();
+            cfg.extend(extra);
+            let options = engine_options(manifest.options, option_flags)?;
+            cfg.set_opt_level(options.opt_level);
+            Some((path.to_string_lossy().into_owned(), manifest.name, cfg, options))
+        },
+        Err(manifest::ManifestError{desc}) => {
+            eprintln!("{}", desc);
+            None
+        }
+    }
+}
 
 fn main() {
-    
-    let src = fs::read_to_string("examples/tmp.mx").expect("File io error");
-    let tokens = tokeniser::tokenise(&src);
-    // println!("Tokens: {:#?}", tokens);
-    let parsed = parser::parse(tokens).expect("Failed to parse");
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+
+    // Arguments for the entry point follow "--", e.g. "--entry gcd run a.mx -- 12 18" //
+    let entry_args = match args.iter().position(|&arg| arg == "--") {
+        Some(idx) => args.split_off(idx).split_off(1),
+        None => Vec::new()
+    };
+    let mut entry = Entry{name: None, args: entry_args};
+    if let Some(idx) = args.iter().position(|&arg| arg == "--entry") {
+        match args.get(idx + 1) {
+            Some(&name) => entry.name = Some(name),
+            None => {
+                eprintln!("Missing function name after --entry");
+                return;
+            }
+        }
+        args.drain(idx..idx + 2);
+    }
 
+    // Flags for #[cfg(...)] attributes, e.g. "--cfg debug --cfg feature=x" //
+    let mut cfg = Cfg::default();
+    while let Some(idx) = args.iter().position(|&arg| arg == "--cfg") {
+        match args.get(idx + 1) {
+            Some(&flag) => cfg.set(flag),
+            None => {
+                eprintln!("Missing flag after --cfg");
+                return;
+            }
+        }
+        args.drain(idx..idx + 2);
+    }
+
+    // Check in strict mode, as though every file began "#pragma strict" //
+    if let Some(idx) = args.iter().position(|&arg| arg == "--strict") {
+        args.remove(idx);
+        cfg.set_strict();
+    }
 
-    let module = match check_syntax(parsed) {
-        Ok(module) => module,
-        Err(SyntaxError{line, col, desc}) => {
-            eprintln!("SyntaxError at line {}, column {}:\n ->  {}\n", line, col, desc);
+    // Or in sketch mode, uninitialising the variables functions leave bound where that's safe //
+    if let Some(idx) = args.iter().position(|&arg| arg == "--sketch") {
+        args.remove(idx);
+        if cfg.is_strict() {
+            eprintln!("--sketch and --strict can't be used together");
             return;
         }
+        cfg.set_sketch();
+    }
+
+    // Check at runtime what references point to, where computed indices hide it from the checker //
+    if let Some(idx) = args.iter().position(|&arg| arg == "--check-aliasing") {
+        args.remove(idx);
+        cfg.set_check_aliasing();
+    }
+
+    // Report on stderr whenever a catch reverses execution //
+    let trace_reversals = match args.iter().position(|&arg| arg == "--trace-reversals") {
+        Some(idx) => {args.remove(idx); true},
+        None => false
     };
 
-    // println!("Module: {:#?}", module);
-    let program = module.compile();
-    // println!("Compiled: {:#?}", program);
-    interpreter::Interpreter::run(&program);
-    
-}
\ No newline at end of file
+    // After the run, report on stderr how much garbage the calls the checker warned about left //
+    let garbage_report = match args.iter().position(|&arg| arg == "--garbage-report") {
+        Some(idx) => {args.remove(idx); true},
+        None => false
+    };
+
+    // After the run, report on stderr the cost of the instructions each function ran //
+    let cost_report = match args.iter().position(|&arg| arg == "--cost-report") {
+        Some(idx) => {args.remove(idx); true},
+        None => false
+    };
+
+    // After compiling, report on stderr the borrow params specialised to constants //
+    let stats = match args.iter().position(|&arg| arg == "--stats") {
+        Some(idx) => {args.remove(idx); true},
+        None => false
+    };
+
+    // After the run, write an html timeline of the statements run and the variables live, e.g.
+    // "--timeline trace.html" //
+    let mut timeline = None;
+    if let Some(idx) = args.iter().position(|&arg| arg == "--timeline") {
+        match args.get(idx + 1) {
+            Some(&path) => timeline = Some(path),
+            None => {
+                eprintln!("Missing file after --timeline");
+                return;
+            }
+        }
+        args.drain(idx..idx + 2);
+    }
+
+    // Engine options, e.g. "--step-budget 1000000 --log debug", overriding any set in REAVER_*
+    // environment variables or the project's manifest //
+    let option_flags = match options::take_flags(&mut args) {
+        Ok(flags) => flags,
+        Err(desc) => {
+            eprintln!("{}", desc);
+            return;
+        }
+    };
+    let Some(options) = engine_options(EngineOptions::default(), &option_flags) else {return};
+    cfg.set_opt_level(options.opt_level);
+
+    // How arithmetic treats numbers: exact fractions, or 64-bit integers that are checked for
+    // overflow or wrap around. Either way "/" fails unless it divides exactly //
+    let mut arithmetic = interpreter::Arithmetic::Exact;
+    if let Some(idx) = args.iter().position(|&arg| arg == "--arithmetic") {
+        match args.get(idx + 1).and_then(|mode| interpreter::Arithmetic::from_name(mode)) {
+            Some(mode) => arithmetic = mode,
+            None => {
+                eprintln!("--arithmetic takes one of exact, checked or wrapping");
+                return;
+            }
+        }
+        args.drain(idx..idx + 2);
+    }
+
+    // The width of the words in circuits exported with "--emit qasm" //
+    let mut bits = 8;
+    if let Some(idx) = args.iter().position(|&arg| arg == "--bits") {
+        match args.get(idx + 1).and_then(|bits| bits.parse().ok()) {
+            Some(width @ 1..=64) => bits = width,
+            _ => {
+                eprintln!("--bits takes a word width from 1 to 64");
+                return;
+            }
+        }
+        args.drain(idx..idx + 2);
+    }
+
+    // Print the program at a stage of compilation instead of running it //
+    if let Some(idx) = args.iter().position(|&arg| arg == "--emit") {
+        match (args.get(idx + 1), &args[..idx], args.get(idx + 2..).unwrap_or_default()) {
+            (Some(&"desugared"), [], [path]) => emit_desugared(path, &cfg),
+            (Some(&"janus"), [], [path]) => emit_janus(path, &cfg),
+            (Some(&"qasm"), [], [path]) => emit_qasm(path, &cfg, entry.name, bits),
+            (Some(&"rvc"), [], [path]) => emit_library(path, &cfg, &options),
+            (Some(&"desugared"), _, _) | (Some(&"janus"), _, _) | (Some(&"qasm"), _, _) | (Some(&"rvc"), _, _) => eprintln!(
+                "Usage: monoxide [--cfg <flag>]... [--strict | --sketch] [--entry <function>] [--bits <n>] --emit desugared|janus|qasm|rvc <file>"),
+            _ => eprintln!("--emit takes desugared, janus, qasm or rvc")
+        }
+        return;
+    }
+
+    if let Some(idx) = args.iter().position(|&arg| arg == "--self-check") {
+        match (&args[..idx], &args[idx + 1..]) {
+            ([], paths) if !paths.is_empty() => if !self_check(paths, &cfg) {
+                process::exit(1);
+            },
+            _ => eprintln!("Usage: monoxide [--cfg <flag>]... --self-check <file>...")
+        }
+        return;
+    }
+
+    match args.as_slice() {
+        ["isa"] => print!("{}", isa::reference_table()),
+        ["grammar", "--ebnf"] => print!("{}", parser::grammar_ebnf()),
+        ["repl"] => repl::run(cfg, options),
+        ["notebook", path] => notebook::run(path, &cfg, &options),
+        ["build", "--backend", "rust"] => {
+            if let Some((path, name, cfg, options)) = load_project(&cfg, &option_flags) {
+                if let Some(program) = compile_file(&path, &cfg, &options, false, false) {
+                    build_native(&program, &name);
+                }
+            }
+        },
+        ["build", "--backend", "c"] => {
+            if let Some((path, name, cfg, options)) = load_project(&cfg, &option_flags) {
+                if let Some(program) = compile_file(&path, &cfg, &options, false, false) {
+                    build_c(&program, &name);
+                }
+            }
+        },
+        ["build", "--backend", backend] if *backend != "bytecode" => eprintln!("--backend takes bytecode, rust or c"),
+        ["build"] | ["build", "--backend", _] => {
+            if let Some((path, name, cfg, options)) = load_project(&cfg, &option_flags) {
+                if let Some(program) = cache::load_or_compile(&name, &path, &cfg, &options, |cfg| compile_file(&path, cfg, &options, true, false)) {
+                    println!("Built {}: {} functions", path, program.functions.len());
+                    if stats {
+                        eprint!("{}", isa::stats(&program));
+                    }
+                }
+            }
+        },
+        ["run"] => {
+            if let Some((path, name, cfg, options)) = load_project(&cfg, &option_flags) {
+                if let Some(program) = cache::load_or_compile(&name, &path, &cfg, &options, |cfg| compile_file(&path, cfg, &options, true, false)) {
+                    if stats {
+                        eprint!("{}", isa::stats(&program));
+                    }
+                    let mut interpreter = interpreter::Interpreter::new(&program);
+                    host::register_builtins(&mut interpreter);
+                    interpreter.set_trace_reversals(trace_reversals);
+                    interpreter.set_arithmetic(arithmetic);
+                    interpreter.set_options(&options);
+                    interpreter.set_garbage_report(garbage_report);
+                    interpreter.set_cost_report(cost_report);
+                    interpreter.set_timeline(timeline.is_some());
+                    run(&mut interpreter, &program, &entry);
+                    if garbage_report {
+                        eprint!("{}", interpreter.garbage_report());
+                    }
+                    if cost_report {
+                        eprint!("{}", interpreter.cost_report());
+                    }
+                    if let Some(out) = timeline {
+                        write_timeline(&interpreter, &path, out);
+                    }
+                }
+            }
+        },
+        ["dap"] => dap::run(),
+        ["check", path] => {
+            // One line per diagnostic on stdout, failing if there are any errors, for CI and hooks //
+            let src = fs::read_to_string(path).expect("File io error");
+            let mut cfg = cfg.clone();
+            cfg.add_include_path(Path::new(path).parent().unwrap_or(Path::new(".")));
+            let diagnostics = lint::check(&src, &cfg);
+            for diagnostic in &diagnostics {
+                println!("{}:{}", path, diagnostic);
+                if let Some(note) = &diagnostic.note {
+                    println!("{}:{}:{}: note: {}", path, note.line, note.col, note.desc);
+                }
+            }
+            if diagnostics.iter().any(|diagnostic| diagnostic.severity == lint::Severity::Error) {
+                process::exit(1);
+            }
+        },
+        ["minimise", path, "--check", check] => {
+            // The smallest program found failing with the checked text goes to stdout //
+            let src = fs::read_to_string(path).expect("File io error");
+            let mut cfg = cfg.clone();
+            cfg.add_include_path(Path::new(path).parent().unwrap_or(Path::new(".")));
+            let mut minimiser = minimise::Minimiser::new(&cfg, &options, check);
+            panic::set_hook(Box::new(|_| {}));
+            let minimised = minimiser.minimise(&src);
+            let _ = panic::take_hook();
+            match minimised {
+                Ok(minimised) => {
+                    print!("{}", minimised);
+                    eprintln!("Minimised {} from {} to {} lines in {} runs", path, src.lines().count(),
+                              minimised.lines().count(), minimiser.runs);
+                },
+                Err(desc) => eprintln!("{}", desc)
+            }
+        },
+        ["debug", path] => {
+            if let Some(program) = compile_file(path, &cfg, &options, true, false) {
+                debugger::run(&program);
+            }
+        },
+        ["sandbox", path] => {
+            if let Some(program) = compile_file(path, &cfg, &options, true, true) {
+                if let Err(err) = interpreter::Interpreter::run_sandboxed(&program, &options) {
+                    eprintln!("{}", err);
+                }
+            }
+        },
+        ["resume", path, state] => {
+            if let Some(program) = compile_file(path, &cfg, &options, true, false) {
+                match interpreter::Interpreter::load_state(&program, Path::new(state)) {
+                    Ok(mut interpreter) => {
+                        interpreter.set_arithmetic(arithmetic);
+                        interpreter.set_options(&options);
+                        run(&mut interpreter, &program, &Entry{name: None, args: Vec::new()})
+                    },
+                    Err(interpreter::StateError{desc}) => eprintln!("Can't resume from {}: {}", state, desc)
+                }
+            }
+        },
+        ["bench", path] | ["bench", path, _] => {
+            let runs = match args.get(2).map(|runs| runs.parse()) {
+                Some(Ok(runs)) => runs,
+                Some(Err(_)) => {
+                    eprintln!("The number of runs must be a positive integer");
+                    return;
+                },
+                None => 10
+            };
+            if let Some(program) = compile_file(path, &cfg, &options, false, false) {
+                bench(&program, runs, arithmetic, &options);
+            }
+        },
+        ["fuzz-parser"] | ["fuzz-parser", _] => {
+            let runs = match args.get(1).map(|runs| runs.parse()) {
+                Some(Ok(runs)) => runs,
+                Some(Err(_)) => {
+                    eprintln!("The number of runs must be a positive integer");
+                    return;
+                },
+                None => 1000
+            };
+            if !fuzz_parser(runs, options.seed) {
+                process::exit(1);
+            }
+        },
+        ["profile-parse", path] => {
+            // Streamed from the file, so the parse is timed as it reads //
+            let file = fs::File::open(path).expect("File io error");
+            let start = Instant::now();
+            let (parsed, profile) = parser::parse_profiled(tokeniser::Tokeniser::from_reader(io::BufReader::new(file)));
+            let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+            if let Err(err) = parsed {
+                eprintln!("{}", err);
+            }
+            print!("{}", profile);
+            println!("Parsed in {:.3} ms", elapsed);
+        },
+        ["disasm", path] => {
+            if let Some(program) = compile_file(path, &cfg, &options, false, false) {
+                print!("{}", isa::disassemble(&program));
+            }
+        },
+        ["invert", path, "--outputs", outputs] => {
+            if let Some(program) = compile_file(path, &cfg, &options, true, false) {
+                invert(&program, &entry, outputs, &options);
+            }
+        },
+        ["verify", paths @ ..] if paths.iter().any(|&path| path != "--mutate") => {
+            let mutate = paths[0] == "--mutate";
+            if !verify(&paths[mutate as usize..], mutate, &cfg, &options) {
+                process::exit(1);
+            }
+        },
+        ["info", path, name] => {
+            if let Some(program) = compile_file(path, &cfg, &options, false, false) {
+                match program.functions.iter().find(|function| function.name == *name) {
+                    Some(function) => print!("{}", isa::function_info(function)),
+                    None => eprintln!("No function named \"{}\" in {}", name, path)
+                }
+            }
+        },
+        ["run", path] | [path] => {
+            if let Some(program) = compile_file(path, &cfg, &options, true, false) {
+                if stats {
+                    eprint!("{}", isa::stats(&program));
+                }
+                let mut interpreter = interpreter::Interpreter::new(&program);
+                host::register_builtins(&mut interpreter);
+                interpreter.set_trace_reversals(trace_reversals);
+                interpreter.set_arithmetic(arithmetic);
+                interpreter.set_options(&options);
+                interpreter.set_garbage_report(garbage_report);
+                interpreter.set_cost_report(cost_report);
+                interpreter.set_timeline(timeline.is_some());
+                run(&mut interpreter, &program, &entry);
+                if garbage_report {
+                    eprint!("{}", interpreter.garbage_report());
+                }
+                if cost_report {
+                    eprint!("{}", interpreter.cost_report());
+                }
+                if let Some(out) = timeline {
+                    write_timeline(&interpreter, path, out);
+                }
+            }
+        },
+        [] => {
+            if let Some(program) = compile_file("examples/tmp.mx", &cfg, &options, true, false) {
+                // println!("Compiled: {:#?}", program);
+                let mut interpreter = interpreter::Interpreter::new(&program);
+                host::register_builtins(&mut interpreter);
+                interpreter.set_options(&options);
+                interpreter.run_to_completion(&program);
+            }
+        },
+        _ => eprintln!("Usage: monoxide [--cfg <flag>]... [--strict | --sketch] [--check-aliasing] [--trace-reversals] [--garbage-report] [--cost-report] [--stats] [--timeline <out.html>] [--opt-level 0|1] [--step-budget <n>] [--memory-cap <n>] [--log <level>] [--seed <n>] [--arithmetic exact|checked|wrapping] [--entry <function>] [run] <file> [-- <args>...] | [--entry <function>] [--bits <n>] --emit desugared|janus|qasm|rvc <file> | --self-check <file>... | build [--backend bytecode|rust|c] | run | check <file> | minimise <file> --check <text> | [--entry <function>] invert <file> --outputs <values> [-- <args>...] | verify [--mutate] <file>... | sandbox <file> | disasm <file> | info <file> <function> | fuzz-parser [runs] | profile-parse <file> | bench <file> [runs] | debug <file> | resume <file> <state> | dap | isa | grammar --ebnf | repl | notebook <file>")
+    }
+}
@@ -0,0 +1,123 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::Cfg;
+use crate::options::{self, EngineOptions};
+
+
+pub const MANIFEST_FILE: &str = "reaver.toml";
+
+// A project's reaver.toml, describing where its files live, e.g.
+//
+//     name = "demo"
+//     source_roots = ["src"]
+//     include_paths = ["lib"]
+//     features = ["debug", "feature=x"]
+//     step_budget = 1000000
+//     log = "info"
+//
+// The module "demo" is then src/demo.mx. Imports are searched for in the source roots and then
+// the include paths, all relative to the manifest's directory. Any engine options given are the
+// defaults for the project's runs //
+#[derive(Debug)]
+pub struct Manifest {
+    pub name: String,
+    pub source_roots: Vec<PathBuf>,
+    pub include_paths: Vec<PathBuf>,
+    pub features: Vec<String>,
+    pub options: EngineOptions
+}
+
+#[derive(Debug)]
+pub struct ManifestError {
+    pub desc: String
+}
+
+impl Manifest {
+    pub fn load(dir: &Path) -> Result<Manifest, ManifestError> {
+        let path = dir.join(MANIFEST_FILE);
+        let src = fs::read_to_string(&path).map_err(|err| ManifestError{
+            desc: format!("Can't read {}: {}", path.display(), err)})?;
+        Manifest::parse(&src, dir)
+    }
+
+    // Only the subset of TOML needed here: top-level keys, each a string, an integer or a
+    // one-line array of strings //
+    pub fn parse(src: &str, dir: &Path) -> Result<Manifest, ManifestError> {
+        let mut name = None;
+        let mut source_roots = vec![dir.to_path_buf()];
+        let mut include_paths = Vec::new();
+        let mut features = Vec::new();
+        let mut options = EngineOptions::default();
+        for (idx, line) in src.lines().enumerate() {
+            let err = |desc: &str| ManifestError{desc: format!("{} line {}: {}", MANIFEST_FILE, idx + 1, desc)};
+            let line = strip_comment(line).trim();
+            if line.is_empty() {continue}
+            let (key, value) = line.split_once('=').ok_or_else(|| err("Expected key = value"))?;
+            let value = value.trim();
+            let paths = |value: &str| -> Result<Vec<PathBuf>, ManifestError> {
+                Ok(parse_strings(value).ok_or_else(|| err("Expected an array of strings"))?
+                    .into_iter().map(|path| dir.join(path)).collect())
+            };
+            match key.trim() {
+                "name" => name = Some(parse_string(value).ok_or_else(|| err("Expected a string"))?),
+                "source_roots" => source_roots = paths(value)?,
+                "include_paths" => include_paths = paths(value)?,
+                "features" => features = parse_strings(value).ok_or_else(|| err("Expected an array of strings"))?,
+                key if options::KEYS.contains(&key) => {
+                    let value = parse_string(value).unwrap_or_else(|| value.to_string());
+                    options.set(key, &value).map_err(|desc| err(&format!("{} {}", key, desc)))?;
+                },
+                key => return Err(err(&format!("Unknown key \"{}\"", key)))
+            }
+        }
+        let name = name.ok_or(ManifestError{desc: format!("{} doesn't give a name", MANIFEST_FILE)})?;
+        Ok(Manifest{name, source_roots, include_paths, features, options})
+    }
+
+    // The file defining the module, from the first source root holding one //
+    pub fn main_file(&self) -> Result<PathBuf, ManifestError> {
+        let file = format!("{}.mx", self.name);
+        self.source_roots.iter()
+            .map(|root| root.join(&file))
+            .find(|path| path.is_file())
+            .ok_or(ManifestError{desc: format!("No {} in the source roots", file)})
+    }
+
+    // The features as #[cfg] flags, and the directories imports are searched for in //
+    pub fn cfg(&self) -> Cfg {
+        let mut cfg = Cfg::default();
+        for feature in self.features.iter() {
+            cfg.set(feature);
+        }
+        for path in self.source_roots.iter().chain(self.include_paths.iter()) {
+            cfg.add_include_path(path);
+        }
+        cfg
+    }
+}
+
+// The line up to any comment. A "#" inside a string is part of the string //
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (idx, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..idx],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_string(value: &str) -> Option<String> {
+    let inner = value.strip_prefix('"')?.strip_suffix('"')?;
+    if inner.contains('"') {return None}
+    Some(inner.to_string())
+}
+
+fn parse_strings(value: &str) -> Option<Vec<String>> {
+    let inner = value.strip_prefix('[')?.strip_suffix(']')?.trim();
+    if inner.is_empty() {return Some(Vec::new())}
+    inner.trim_end_matches(',').split(',').map(|item| parse_string(item.trim())).collect()
+}
@@ -0,0 +1,168 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::cfg::Cfg;
+use crate::formatter;
+use crate::interpreter::Interpreter;
+use crate::options::EngineOptions;
+use crate::parser;
+use crate::parsetree as PT;
+use crate::syntaxchecker::{check_syntax_quietly, SyntaxError};
+use crate::tokeniser::Tokeniser;
+
+
+// Deleting statements easily leaves a loop that never ends or an array that never stops growing,
+// so candidates run with these limits unless others are given //
+const DEFAULT_STEP_BUDGET: u64 = 10_000_000;
+const DEFAULT_MEMORY_CAP: usize = 10_000_000;
+
+// Shrinks a failing program, e.g. one hitting an internal compiler error, to a smaller one failing
+// the same way, for a bug report. Functions and then the statements of each block are deleted by
+// delta debugging. Each candidate is printed by the formatter, then parsed, checked, compiled and
+// run again, and kept if its failure still contains the text being checked for //
+pub struct Minimiser<'a> {
+    cfg: &'a Cfg,
+    options: EngineOptions,
+    check: &'a str,
+    pub runs: usize
+}
+
+impl<'a> Minimiser<'a> {
+    pub fn new(cfg: &'a Cfg, options: &EngineOptions, check: &'a str) -> Minimiser<'a> {
+        let mut options = *options;
+        options.step_budget = options.step_budget.or(Some(DEFAULT_STEP_BUDGET));
+        options.memory_cap = options.memory_cap.or(Some(DEFAULT_MEMORY_CAP));
+        Minimiser{cfg, options, check, runs: 0}
+    }
+
+    // How the program fails, or None if it runs to the end //
+    pub fn failure(&mut self, src: &str) -> Option<String> {
+        self.runs += 1;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| -> Result<(), String> {
+            let parsed = parser::parse(Tokeniser::new(src)).map_err(|err| err.to_string())?;
+            let (module, _) = check_syntax_quietly(parsed, self.cfg, false).map_err(|SyntaxError{line, col, desc, ..}|
+                format!("SyntaxError at line {}, column {}: {}", line, col, desc))?;
+            let program = module.compile(true, &self.options);
+            let mut interpreter = Interpreter::new(&program);
+            interpreter.capture_output();
+            interpreter.set_options(&self.options);
+            interpreter.try_run_to_completion(&program).map_err(|err| err.to_string())
+        }));
+        match result {
+            Ok(Ok(())) => None,
+            Ok(Err(desc)) => Some(desc),
+            Err(payload) => Some(panic_message(payload))
+        }
+    }
+
+    fn fails(&mut self, module: &PT::Module) -> bool {
+        self.failure(&formatter::module_source(module)).is_some_and(|desc| desc.contains(self.check))
+    }
+
+    pub fn minimise(&mut self, src: &str) -> Result<String, String> {
+        let mut module = parser::parse(Tokeniser::new(src)).map_err(|err|
+            format!("Only programs that parse can be minimised, but there's a {}", err))?;
+        match self.failure(&formatter::module_source(&module)) {
+            Some(desc) if desc.contains(self.check) => {},
+            Some(desc) => return Err(format!("The program fails with \"{}\", which doesn't contain \"{}\"", desc, self.check)),
+            None => return Err(String::from("The program runs without failing, so there's nothing to minimise"))
+        }
+
+        // Deleting a statement can let others go that couldn't before, so repeat until nothing can //
+        loop {
+            let before = formatter::module_source(&module).len();
+
+            let functions = module.functions.clone();
+            let kept = ddmin(functions.len(), |keep| {
+                let mut candidate = module.clone();
+                candidate.functions = keep.iter().map(|&idx| functions[idx].clone()).collect();
+                self.fails(&candidate)
+            });
+            module.functions = kept.iter().map(|&idx| functions[idx].clone()).collect();
+
+            // Lists are numbered outside in, so shrinking one only renumbers those not yet visited //
+            let mut n = 0;
+            while let Some(stmts) = nth_stmts(&mut module, n).map(|stmts| stmts.clone()) {
+                let kept = ddmin(stmts.len(), |keep| {
+                    let mut candidate = module.clone();
+                    *nth_stmts(&mut candidate, n).expect("Statement list missing from a copy") =
+                        keep.iter().map(|&idx| stmts[idx].clone()).collect();
+                    self.fails(&candidate)
+                });
+                *nth_stmts(&mut module, n).expect("Statement list missing") =
+                    kept.iter().map(|&idx| stmts[idx].clone()).collect();
+                n += 1;
+            }
+
+            if formatter::module_source(&module).len() >= before {break}
+        }
+        Ok(formatter::module_source(&module))
+    }
+}
+
+pub fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(desc) => *desc,
+        Err(payload) => payload.downcast::<&str>().map_or(String::from("Unknown error"), |desc| desc.to_string())
+    }
+}
+
+// Delta debugging: a subset of the indices 0..count, as small as found, for which the test still
+// fails. Ever smaller chunks are deleted while any can go //
+fn ddmin<F: FnMut(&[usize]) -> bool>(count: usize, mut fails: F) -> Vec<usize> {
+    let mut kept: Vec<usize> = (0..count).collect();
+    let mut chunks = 2;
+    while !kept.is_empty() {
+        let size = kept.len().div_ceil(chunks);
+        let smaller = (0..kept.len()).step_by(size).find_map(|start| {
+            let rest = &kept[(start + size).min(kept.len())..];
+            let candidate: Vec<usize> = kept[..start].iter().chain(rest).copied().collect();
+            fails(&candidate).then_some(candidate)
+        });
+        match smaller {
+            Some(candidate) => {
+                kept = candidate;
+                chunks = (chunks - 1).max(2);
+            },
+            None if size == 1 => break,
+            None => chunks = (chunks * 2).min(kept.len())
+        }
+    }
+    kept
+}
+
+// The nth statement list of the module, numbering the global scope's and then each function's
+// from 0, each followed by the lists nested inside it //
+fn nth_stmts(module: &mut PT::Module, mut n: usize) -> Option<&mut Vec<PT::StatementNode>> {
+    if let Some(stmts) = find_stmts(&mut module.global_func.stmts, &mut n) {
+        return Some(stmts);
+    }
+    find_function_stmts(&mut module.functions, &mut n)
+}
+
+fn find_function_stmts<'m>(functions: &'m mut [PT::FunctionNode], n: &mut usize) -> Option<&'m mut Vec<PT::StatementNode>> {
+    for function in functions {
+        if let Some(stmts) = find_stmts(&mut function.stmts, n) {
+            return Some(stmts);
+        }
+        if let Some(stmts) = find_function_stmts(&mut function.functions, n) {
+            return Some(stmts);
+        }
+    }
+    None
+}
+
+fn find_stmts<'m>(stmts: &'m mut Vec<PT::StatementNode>, n: &mut usize) -> Option<&'m mut Vec<PT::StatementNode>> {
+    if *n == 0 {
+        return Some(stmts);
+    }
+    *n -= 1;
+    for stmt in stmts.iter_mut() {
+        for substmts in stmt.substatements() {
+            if let Some(found) = find_stmts(substmts, n) {
+                return Some(found);
+            }
+        }
+    }
+    None
+}
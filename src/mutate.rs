@@ -0,0 +1,104 @@
+use std::fmt;
+use std::mem;
+
+use crate::interpreter::{Code, Instruction, Module};
+use crate::isa::Stream;
+
+
+// One deliberate mistake in the backward code of a reversible statement //
+#[derive(Debug, Clone, Copy)]
+pub enum Mutation {
+    Swap{from: Instruction, to: Instruction},  // An instruction replaced with its own inverse
+    Drop{instruction: Instruction}  // An instruction replaced with a marker, so the mirror is missing
+}
+
+impl fmt::Display for Mutation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mutation::Swap{from, to} => write!(f, "swapped {:?} for {:?}", from, to),
+            Mutation::Drop{instruction} => write!(f, "dropped {:?}", instruction)
+        }
+    }
+}
+
+// A mutation the verifier accepted //
+pub struct Survivor {
+    pub function: String,
+    pub ip: usize,
+    pub mutation: Mutation
+}
+
+impl fmt::Display for Survivor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}, bkwd ip {}: {}", self.function, self.ip, self.mutation)
+    }
+}
+
+pub struct MutationReport {
+    pub tried: usize,
+    pub survivors: Vec<Survivor>
+}
+
+
+// Tests the verifier against the compiler's backward streams. Each instruction of a reversible
+// statement's backward code that undoes some state change is in turn swapped for its inverse, as
+// if the compiler had forgotten to invert it, and dropped, as if the compiler had forgotten to
+// emit it. Every such mutant must be rejected by Module::validate. The module must verify before
+// it's mutated //
+pub fn mutation_test(module: &mut Module) -> MutationReport {
+    let mut report = MutationReport{tried: 0, survivors: Vec::new()};
+    for func_idx in 0..module.functions.len() {
+        let bkwd: Vec<Instruction> = module.functions[func_idx].code.bkwd.iter().collect();
+        for (ip, &instruction) in bkwd.iter().enumerate() {
+            let Some(stmt) = innermost_reversible(&module.functions[func_idx].code, ip) else {continue};
+            if !undoes_state(module, &bkwd, ip) {continue}
+
+            let mut mutations = vec![Mutation::Drop{instruction}];
+            if let Some(inverse) = instruction.inverse().filter(|inverse| *inverse != instruction) {
+                mutations.insert(0, Mutation::Swap{from: instruction, to: inverse});
+            }
+            for mutation in mutations {
+                let mut mutant = bkwd.clone();
+                mutant[ip] = match mutation {
+                    Mutation::Swap{to, ..} => to,
+                    Mutation::Drop{..} => Instruction::Marker{stmt}
+                };
+                report.tried += 1;
+                if !rejects(module, func_idx, mutant) {
+                    let function = module.functions[func_idx].name.clone();
+                    report.survivors.push(Survivor{function, ip, mutation});
+                }
+            }
+        }
+    }
+    report
+}
+
+// The deepest reversible statement whose backward code includes ip //
+fn innermost_reversible(code: &Code, ip: usize) -> Option<usize> {
+    code.regions.iter().enumerate()
+        .filter(|(_, region)| !region.is_mono && !region.fwd.is_empty() && region.bkwd.contains(&ip))
+        .max_by_key(|(_, region)| region.depth)
+        .map(|(stmt, _)| stmt)
+}
+
+// Whether the instruction at ip changes a variable, the stack of another function or the operator
+// applied by the following Store, rather than just computing a value //
+fn undoes_state(module: &Module, bkwd: &[Instruction], ip: usize) -> bool {
+    match bkwd[ip] {
+        Instruction::Call{idx} | Instruction::Uncall{idx} => !module.functions[idx].is_pure,
+        instruction if instruction.modifies_variables() => true,
+        instruction => instruction.inverse().is_some() && bkwd.get(ip + 1) == Some(&Instruction::Store)
+    }
+}
+
+// Whether the verifier fails the module with one function's backward code replaced, putting the
+// original code back afterwards //
+fn rejects(module: &mut Module, func_idx: usize, bkwd: Vec<Instruction>) -> bool {
+    let code = &module.functions[func_idx].code;
+    let mutant = Code::new(code.fwd.clone(), Stream::from(bkwd), code.regions.clone());
+    let original = mem::replace(&mut module.functions[func_idx].code, mutant);
+    let rejected = module.validate().is_err();
+    module.functions[func_idx].code = original;
+    rejected
+}
@@ -0,0 +1,817 @@
+/* The runtime of programs built with "monoxide build --backend c". This file isn't part of the
+   compiler: cgen pastes it ahead of the generated functions. Values behave as they do in the
+   interpreter, except that fractions are 64-bit, and fail on overflow rather than growing */
+
+#include <math.h>
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+#pragma GCC diagnostic ignored "-Wunused-label"
+#pragma GCC diagnostic ignored "-Wunused-function"
+#pragma GCC diagnostic ignored "-Wunused-parameter"
+#pragma GCC diagnostic ignored "-Wimplicit-fallthrough"
+
+typedef struct { int64_t num, den; } frac_t;
+typedef enum { FRAC, FLOAT, ARRAY, STR } kind_t;
+typedef struct var var_t;
+
+typedef struct {
+    kind_t kind;
+    frac_t frac;
+    double flt;
+    var_t **items;
+    size_t len, cap;
+    char *str;
+} value_t;
+
+/* Shared by reference count, like the interpreter's Rc<RefCell<Variable>> */
+struct var {
+    size_t refs;
+    value_t val;
+};
+
+static void fail(const char *format, ...) {
+    va_list args;
+    fflush(stdout);
+    va_start(args, format);
+    vfprintf(stderr, format, args);
+    va_end(args);
+    fputc('\n', stderr);
+    exit(1);
+}
+
+static void *checked_alloc(size_t size) {
+    void *ptr = malloc(size ? size : 1);
+    if (!ptr) fail("Out of memory");
+    return ptr;
+}
+
+
+/* ------------------------------ Fractions ------------------------------ */
+
+static __int128 gcd128(__int128 a, __int128 b) {
+    if (a < 0) a = -a;
+    if (b < 0) b = -b;
+    while (b) { __int128 t = a % b; a = b; b = t; }
+    return a;
+}
+
+static frac_t frac_make(__int128 num, __int128 den) {
+    if (den == 0) fail("Division by zero");
+    if (den < 0) { num = -num; den = -den; }
+    __int128 g = gcd128(num, den);
+    if (g > 1) { num /= g; den /= g; }
+    if (num > INT64_MAX || num < INT64_MIN || den > INT64_MAX)
+        fail("Fraction overflow: the C backend's fractions are 64-bit");
+    frac_t f = { (int64_t)num, (int64_t)den };
+    return f;
+}
+
+static frac_t frac_int(int64_t x) { frac_t f = { x, 1 }; return f; }
+static frac_t frac_add(frac_t a, frac_t b) { return frac_make((__int128)a.num * b.den + (__int128)b.num * a.den, (__int128)a.den * b.den); }
+static frac_t frac_sub(frac_t a, frac_t b) { return frac_make((__int128)a.num * b.den - (__int128)b.num * a.den, (__int128)a.den * b.den); }
+static frac_t frac_mul(frac_t a, frac_t b) { return frac_make((__int128)a.num * b.num, (__int128)a.den * b.den); }
+static frac_t frac_div(frac_t a, frac_t b) { return frac_make((__int128)a.num * b.den, (__int128)a.den * b.num); }
+static frac_t frac_trunc(frac_t a) { return frac_int(a.num / a.den); }
+static frac_t frac_rem(frac_t a, frac_t b) { return frac_sub(a, frac_mul(b, frac_trunc(frac_div(a, b)))); }
+static int frac_cmp(frac_t a, frac_t b) {
+    __int128 l = (__int128)a.num * b.den, r = (__int128)b.num * a.den;
+    return (l > r) - (l < r);
+}
+static double frac_to_double(frac_t a) { return (double)a.num / (double)a.den; }
+
+/* Exactly, as every finite double is a fraction with a power of two denominator */
+static frac_t frac_from_double(double x) {
+    if (!isfinite(x)) fail("Can't make a fraction from an infinite or NaN float");
+    int exp;
+    double mantissa = frexp(x, &exp);
+    __int128 num = (__int128)ldexp(mantissa, 53);
+    exp -= 53;
+    while (exp < 0 && num % 2 == 0 && num != 0) { num /= 2; exp++; }
+    if (num == 0) return frac_int(0);
+    if (exp >= 0) {
+        if (exp > 62) fail("Fraction overflow: the C backend's fractions are 64-bit");
+        return frac_make(num << exp, 1);
+    }
+    if (exp < -62) fail("Fraction overflow: the C backend's fractions are 64-bit");
+    return frac_make(num, (__int128)1 << -exp);
+}
+
+
+/* ------------------------------- Values -------------------------------- */
+
+static var_t *var_new(value_t val) {
+    var_t *var = checked_alloc(sizeof(var_t));
+    var->refs = 1;
+    var->val = val;
+    return var;
+}
+
+static var_t *ref(var_t *var) { var->refs++; return var; }
+
+static void value_free(value_t *val);
+
+static void unref(var_t *var) {
+    if (var && --var->refs == 0) {
+        value_free(&var->val);
+        free(var);
+    }
+}
+
+static void value_free(value_t *val) {
+    if (val->kind == ARRAY) {
+        for (size_t i = 0; i < val->len; i++) unref(val->items[i]);
+        free(val->items);
+    } else if (val->kind == STR) {
+        free(val->str);
+    }
+}
+
+static value_t frac_value(frac_t f) { value_t v = {0}; v.kind = FRAC; v.frac = f; return v; }
+static value_t int_value(int64_t x) { return frac_value(frac_int(x)); }
+static value_t truth_value(int x) { return int_value(x ? 1 : 0); }
+static value_t float_value(double x) { value_t v = {0}; v.kind = FLOAT; v.flt = x; return v; }
+
+static value_t str_value(const char *s) {
+    value_t v = {0};
+    v.kind = STR;
+    v.str = checked_alloc(strlen(s) + 1);
+    strcpy(v.str, s);
+    return v;
+}
+
+static value_t array_value(size_t cap) {
+    value_t v = {0};
+    v.kind = ARRAY;
+    v.cap = cap;
+    v.items = checked_alloc(cap * sizeof(var_t *));
+    return v;
+}
+
+static void array_append(value_t *array, var_t *item) {
+    if (array->len == array->cap) {
+        array->cap = array->cap ? array->cap * 2 : 4;
+        array->items = realloc(array->items, array->cap * sizeof(var_t *));
+        if (!array->items) fail("Out of memory");
+    }
+    array->items[array->len++] = item;
+}
+
+/* Arrays share their items, as cloning a Vec of Rcs does */
+static value_t value_clone(const value_t *val) {
+    if (val->kind == ARRAY) {
+        value_t copy = array_value(val->len);
+        for (size_t i = 0; i < val->len; i++) array_append(&copy, ref(val->items[i]));
+        return copy;
+    }
+    if (val->kind == STR) return str_value(val->str);
+    return *val;
+}
+
+static value_t value_deep_copy(const value_t *val) {
+    if (val->kind == ARRAY) {
+        value_t copy = array_value(val->len);
+        for (size_t i = 0; i < val->len; i++) array_append(&copy, var_new(value_deep_copy(&val->items[i]->val)));
+        return copy;
+    }
+    return value_clone(val);
+}
+
+static int value_eq(const value_t *a, const value_t *b) {
+    if (a->kind != b->kind) return 0;
+    switch (a->kind) {
+    case FRAC: return a->frac.num == b->frac.num && a->frac.den == b->frac.den;
+    case FLOAT: return a->flt == b->flt;
+    case STR: return strcmp(a->str, b->str) == 0;
+    case ARRAY:
+        if (a->len != b->len) return 0;
+        for (size_t i = 0; i < a->len; i++) if (!value_eq(&a->items[i]->val, &b->items[i]->val)) return 0;
+        return 1;
+    }
+    return 0;
+}
+
+static int value_truth(const value_t *val) {
+    switch (val->kind) {
+    case FRAC: return val->frac.num != 0;
+    case FLOAT: return val->flt != 0.0;
+    case ARRAY: return val->len > 0;
+    case STR: return val->str[0] != '\0';
+    }
+    return 0;
+}
+
+static size_t value_index(const value_t *val) {
+    if (val->kind == FLOAT) fail("Floats can't be used as indices");
+    if (val->kind != FRAC) fail("Index is not a number");
+    int64_t idx = val->frac.num / val->frac.den;
+    if (idx < 0) fail("Index is not a non-negative integer");
+    return (size_t)idx;
+}
+
+static var_t *value_item(const value_t *val, size_t idx) {
+    if (val->kind != ARRAY) fail("Indexing is only supported by arrays");
+    if (idx >= val->len) fail("Index %zu is out of bounds for an array of length %zu", idx, val->len);
+    return ref(val->items[idx]);
+}
+
+/* As Rust formats floats with {:?}: the shortest digits that read back the same */
+static void print_float(FILE *out, double x) {
+    if (isnan(x)) { fprintf(out, "NaN"); return; }
+    if (isinf(x)) { fprintf(out, x > 0 ? "inf" : "-inf"); return; }
+    if (x == 0) { fprintf(out, signbit(x) ? "-0.0" : "0.0"); return; }
+    char digits[32];
+    int precision;
+    for (precision = 1; precision < 17; precision++) {
+        snprintf(digits, sizeof digits, "%.*e", precision - 1, x);
+        if (strtod(digits, NULL) == x) break;
+    }
+    snprintf(digits, sizeof digits, "%.*e", precision - 1, x);
+    char *e = strchr(digits, 'e');
+    int exp = atoi(e + 1);
+    *e = '\0';
+    char mantissa[32];
+    size_t n = 0;
+    for (char *c = digits; *c; c++) if (*c != '.' && *c != '-') mantissa[n++] = *c;
+    mantissa[n] = '\0';
+    if (x < 0) fputc('-', out);
+    if (exp < -5 || exp >= 16) {
+        fprintf(out, "%c", mantissa[0]);
+        if (n > 1) fprintf(out, ".%s", mantissa + 1);
+        fprintf(out, "e%d", exp);
+    } else if (exp < 0) {
+        fprintf(out, "0.");
+        for (int i = -1; i > exp; i--) fputc('0', out);
+        fprintf(out, "%s", mantissa);
+    } else if ((size_t)exp + 1 >= n) {
+        fprintf(out, "%s", mantissa);
+        for (size_t i = n; i < (size_t)exp + 1; i++) fputc('0', out);
+        fprintf(out, ".0");
+    } else {
+        fprintf(out, "%.*s.%s", exp + 1, mantissa, mantissa + exp + 1);
+    }
+}
+
+static void print_value(FILE *out, const value_t *val) {
+    switch (val->kind) {
+    case FRAC:
+        if (val->frac.den == 1) fprintf(out, "%lld", (long long)val->frac.num);
+        else fprintf(out, "%lld/%lld", (long long)val->frac.num, (long long)val->frac.den);
+        break;
+    case FLOAT: print_float(out, val->flt); break;
+    case STR: fprintf(out, "%s", val->str); break;
+    case ARRAY:
+        fputc('[', out);
+        for (size_t i = 0; i < val->len; i++) {
+            if (i) fprintf(out, ", ");
+            print_value(out, &val->items[i]->val);
+        }
+        fputc(']', out);
+        break;
+    }
+}
+
+
+/* ------------------------------- The stack ------------------------------ */
+
+typedef struct {
+    long idx;
+    size_t reg;
+    int reversed;
+    long index_reg;  /* -1 when not enumerating */
+    int is_range;
+    var_t *root;
+    size_t *path, depth;
+    frac_t start, step;
+    size_t len;
+} iter_t;
+
+typedef struct {
+    var_t *var;
+    iter_t *iter;
+} object_t;
+
+static object_t *stack;
+static size_t stack_len, stack_cap;
+static var_t **globals;
+static value_t *consts;
+static struct timespec started;
+
+static void push_object(object_t obj) {
+    if (stack_len == stack_cap) {
+        stack_cap = stack_cap ? stack_cap * 2 : 64;
+        stack = realloc(stack, stack_cap * sizeof(object_t));
+        if (!stack) fail("Out of memory");
+    }
+    stack[stack_len++] = obj;
+}
+
+static void push_var(var_t *var) { object_t obj = { var, NULL }; push_object(obj); }
+static void push(value_t val) { push_var(var_new(val)); }
+
+static var_t *pop_var(void) {
+    if (stack_len == 0) fail("Popped off empty stack");
+    object_t obj = stack[--stack_len];
+    if (!obj.var) fail("Non-variable found on the stack");
+    return obj.var;
+}
+
+static int truth(void) {
+    var_t *var = pop_var();
+    int result = value_truth(&var->val);
+    unref(var);
+    return result;
+}
+
+static void set_reg(var_t **reg, var_t *var) {
+    unref(*reg);
+    *reg = var;
+}
+
+static void move_reg(var_t **from, var_t **to) {
+    var_t *var = *from;
+    *from = NULL;
+    set_reg(to, var);
+}
+
+static void release_registers(var_t **regs, size_t count) {
+    for (size_t i = 0; i < count; i++) unref(regs[i]);
+}
+
+static void load(var_t *reg) {
+    if (!reg) fail("Using a register before it has a value");
+    push_var(ref(reg));
+}
+
+static void load_const(size_t idx) { push(value_clone(&consts[idx])); }
+
+static void subscript(size_t size) {
+    var_t *var = pop_var();
+    for (size_t i = 0; i < size; i++) {
+        var_t *index = pop_var();
+        var_t *item = value_item(&var->val, value_index(&index->val));
+        unref(index);
+        unref(var);
+        var = item;
+    }
+    push_var(var);
+}
+
+static void slice(void) {
+    var_t *var = pop_var(), *start = pop_var(), *end = pop_var();
+    size_t from = value_index(&start->val), to = value_index(&end->val);
+    if (var->val.kind != ARRAY) fail("Slicing is only supported by arrays");
+    if (from > to || to > var->val.len) fail("Range %zu:%zu is out of bounds for an array of length %zu", from, to, var->val.len);
+    value_t items = array_value(to - from);
+    for (size_t i = from; i < to; i++) array_append(&items, ref(var->val.items[i]));
+    unref(start);
+    unref(end);
+    unref(var);
+    push(items);
+}
+
+static void check_ref(var_t *reg) {
+    var_t *var = pop_var();
+    int same = reg == var;
+    if (!same && reg && reg->val.kind == ARRAY && var->val.kind == ARRAY && reg->val.len == var->val.len) {
+        same = 1;
+        for (size_t i = 0; i < reg->val.len; i++) same &= reg->val.items[i] == var->val.items[i];
+    }
+    unref(var);
+    if (!same) fail("Removing a reference from something it doesn't reference, so reversing would make it somewhere else");
+}
+
+static int is_shared(const var_t *var) {
+    if (var->refs > 1) return 1;
+    if (var->val.kind == ARRAY) {
+        for (size_t i = 0; i < var->val.len; i++) if (is_shared(var->val.items[i])) return 1;
+    }
+    return 0;
+}
+
+static void check_unshared(const var_t *reg) {
+    if (reg && is_shared(reg)) fail("Pulled a variable while a reference still points into it");
+}
+
+static void store(void) {
+    var_t *src = pop_var(), *dst = pop_var();
+    value_t val = value_clone(&src->val);
+    value_free(&dst->val);
+    dst->val = val;
+    unref(src);
+    unref(dst);
+}
+
+static void duplicate_ref(void) {
+    if (stack_len == 0 || !stack[stack_len - 1].var) fail("Trying to duplicate non-variable");
+    push_var(ref(stack[stack_len - 1].var));
+}
+
+static void unique_var(void) {
+    var_t *var = pop_var();
+    if (var->refs > 1) {
+        push(value_deep_copy(&var->val));
+        unref(var);
+    } else {
+        push_var(var);
+    }
+}
+
+
+/* ------------------------------ Operators ------------------------------ */
+
+enum { ADD, SUB, MUL, DIV, MOD };
+
+static int float_operands(const value_t *a, const value_t *b, double *x, double *y) {
+    if (a->kind == FLOAT && b->kind == FLOAT) { *x = a->flt; *y = b->flt; return 1; }
+    if (a->kind == FLOAT && b->kind == FRAC) { *x = a->flt; *y = frac_to_double(b->frac); return 1; }
+    if (a->kind == FRAC && b->kind == FLOAT) { *x = frac_to_double(a->frac); *y = b->flt; return 1; }
+    return 0;
+}
+
+static void arith(int op, const char *symbol) {
+    var_t *rhs = pop_var(), *lhs = pop_var();
+    double x, y;
+    value_t result;
+    if (float_operands(&lhs->val, &rhs->val, &x, &y)) {
+        switch (op) {
+        case ADD: result = float_value(x + y); break;
+        case SUB: result = float_value(x - y); break;
+        case MUL: result = float_value(x * y); break;
+        case DIV: result = float_value(x / y); break;
+        default: result = float_value(fmod(x, y)); break;
+        }
+    } else if (lhs->val.kind == FRAC && rhs->val.kind == FRAC) {
+        frac_t a = lhs->val.frac, b = rhs->val.frac;
+        if ((op == DIV || op == MOD) && b.num == 0) fail("Division by zero");
+        switch (op) {
+        case ADD: result = frac_value(frac_add(a, b)); break;
+        case SUB: result = frac_value(frac_sub(a, b)); break;
+        case MUL: result = frac_value(frac_mul(a, b)); break;
+        case DIV: result = frac_value(frac_div(a, b)); break;
+        default: result = frac_value(frac_rem(a, b)); break;
+        }
+    } else {
+        fail("Applying binop \"%s\" to incompatible types", symbol);
+    }
+    unref(lhs);
+    unref(rhs);
+    push(result);
+}
+
+static void idiv(void) {
+    var_t *rhs = pop_var(), *lhs = pop_var();
+    double x, y;
+    value_t result;
+    if (float_operands(&lhs->val, &rhs->val, &x, &y)) {
+        result = float_value(trunc(x / y));
+    } else if (lhs->val.kind == FRAC && rhs->val.kind == FRAC) {
+        if (rhs->val.frac.num == 0) fail("Division by zero");
+        result = frac_value(frac_trunc(frac_div(lhs->val.frac, rhs->val.frac)));
+    } else {
+        fail("Applying binop \"//\" to incompatible types");
+    }
+    unref(lhs);
+    unref(rhs);
+    push(result);
+}
+
+static void power(void) {
+    var_t *rhs = pop_var(), *lhs = pop_var();
+    double x, y;
+    value_t result;
+    if (float_operands(&lhs->val, &rhs->val, &x, &y)) {
+        result = float_value(pow(x, y));
+    } else if (lhs->val.kind == FRAC && rhs->val.kind == FRAC) {
+        result = frac_value(frac_from_double(pow(frac_to_double(lhs->val.frac), frac_to_double(rhs->val.frac))));
+    } else {
+        fail("Applying binop \"**\" to incompatible types");
+    }
+    unref(lhs);
+    unref(rhs);
+    push(result);
+}
+
+/* Multiplying or dividing by 2 ** bits, which the compiler put in place of a constant */
+static void shift(int bits) {
+    push(int_value((int64_t)1 << (bits < 0 ? -bits : bits)));
+    if (bits < 0) arith(DIV, "/");
+    else arith(MUL, "*");
+}
+
+enum { LESS, LEQ, GREAT, GEQ };
+
+static void compare(int op, const char *symbol) {
+    var_t *rhs = pop_var(), *lhs = pop_var();
+    double x, y;
+    int order;
+    if (lhs->val.kind == FRAC && rhs->val.kind == FRAC) {
+        order = frac_cmp(lhs->val.frac, rhs->val.frac);
+    } else if (float_operands(&lhs->val, &rhs->val, &x, &y)) {
+        order = (x > y) - (x < y);
+    } else {
+        fail("Applying binop \"%s\" to incompatible types", symbol);
+    }
+    unref(lhs);
+    unref(rhs);
+    switch (op) {
+    case LESS: push(truth_value(order < 0)); break;
+    case LEQ: push(truth_value(order <= 0)); break;
+    case GREAT: push(truth_value(order > 0)); break;
+    default: push(truth_value(order >= 0)); break;
+    }
+}
+
+/* Floats equal fractions of the same value */
+static void equal(int want) {
+    var_t *rhs = pop_var(), *lhs = pop_var();
+    double x, y;
+    int eq = float_operands(&lhs->val, &rhs->val, &x, &y) ? x == y : value_eq(&lhs->val, &rhs->val);
+    unref(lhs);
+    unref(rhs);
+    push(truth_value(eq == want));
+}
+
+static void xor(void) {
+    int rhs = truth(), lhs = truth();
+    push(truth_value(lhs != rhs));
+}
+
+static void neg(void) {
+    var_t *var = pop_var();
+    if (var->val.kind == FLOAT) push(float_value(-var->val.flt));
+    else if (var->val.kind == FRAC) push(frac_value(frac_sub(frac_int(0), var->val.frac)));
+    else fail("The negation operation is only supported by numbers");
+    unref(var);
+}
+
+static void not(void) { push(truth_value(!truth())); }
+
+static void len(void) {
+    var_t *var = pop_var();
+    if (var->val.kind != ARRAY) fail("Length operator (#) used on non-array");
+    push(int_value((int64_t)var->val.len));
+    unref(var);
+}
+
+static void to_float(void) {
+    var_t *var = pop_var();
+    if (var->val.kind == FRAC) push(float_value(frac_to_double(var->val.frac)));
+    else if (var->val.kind == FLOAT) push(float_value(var->val.flt));
+    else fail("to_float is only supported by numbers");
+    unref(var);
+}
+
+static void to_frac(void) {
+    var_t *var = pop_var();
+    if (var->val.kind == FLOAT) push(frac_value(frac_from_double(var->val.flt)));
+    else if (var->val.kind == FRAC) push(frac_value(var->val.frac));
+    else fail("to_frac is only supported by numbers");
+    unref(var);
+}
+
+static void float_fn(const char *name, double (*func)(double)) {
+    var_t *var = pop_var();
+    if (var->val.kind == FRAC) push(float_value(func(frac_to_double(var->val.frac))));
+    else if (var->val.kind == FLOAT) push(float_value(func(var->val.flt)));
+    else fail("%s is only supported by numbers", name);
+    unref(var);
+}
+
+
+/* ------------------------------- Arrays -------------------------------- */
+
+static void array_literal(size_t size) {
+    value_t array = array_value(size);
+    for (size_t i = 0; i < size; i++) {
+        var_t *item = pop_var();
+        if (item->refs > 1) {
+            var_t *copy = var_new(value_deep_copy(&item->val));
+            unref(item);
+            item = copy;
+        }
+        array_append(&array, item);
+    }
+    push(array);
+}
+
+static value_t repeat(const value_t *content, const size_t *dims, size_t count) {
+    value_t array = array_value(dims[0]);
+    for (size_t i = 0; i < dims[0]; i++)
+        array_append(&array, var_new(count == 1 ? value_deep_copy(content) : repeat(content, dims + 1, count - 1)));
+    return array;
+}
+
+static void array_repeat(void) {
+    var_t *dimensions = pop_var(), *content = pop_var();
+    size_t count, *dims;
+    if (dimensions->val.kind == ARRAY) {
+        count = dimensions->val.len;
+        dims = checked_alloc(count * sizeof(size_t));
+        for (size_t i = 0; i < count; i++) dims[i] = value_index(&dimensions->val.items[i]->val);
+    } else if (dimensions->val.kind == FRAC) {
+        count = 1;
+        dims = checked_alloc(sizeof(size_t));
+        dims[0] = value_index(&dimensions->val);
+    } else {
+        fail("Array repetition dimensions must be numbers, or specified in an array");
+    }
+    if (count == 0) fail("Array repetition needs at least one dimension");
+    push(repeat(&content->val, dims, count));
+    free(dims);
+    unref(dimensions);
+    unref(content);
+}
+
+static void pull(var_t **reg, int front) {
+    var_t *array = pop_var();
+    if (array->val.kind != ARRAY) fail("Pulling is only supported by arrays");
+    if (array->val.len == 0) fail("Pulling from empty array");
+    var_t *item;
+    if (front) {
+        item = array->val.items[0];
+        memmove(array->val.items, array->val.items + 1, (array->val.len - 1) * sizeof(var_t *));
+        array->val.len--;
+    } else {
+        item = array->val.items[--array->val.len];
+    }
+    set_reg(reg, item);
+    unref(array);
+}
+
+static void push_item(var_t **reg, int front) {
+    if (!*reg) fail("Pushing a register before it has a value");
+    var_t *item = *reg, *array = pop_var();
+    *reg = NULL;
+    if (array->val.kind != ARRAY) fail("Pushing is only supported by arrays");
+    array_append(&array->val, item);
+    if (front) {
+        memmove(array->val.items + 1, array->val.items, (array->val.len - 1) * sizeof(var_t *));
+        array->val.items[0] = item;
+    }
+    unref(array);
+}
+
+static void print(long count) {
+    for (long i = 0; i < labs(count); i++) {
+        var_t *var = pop_var();
+        print_value(stdout, &var->val);
+        unref(var);
+    }
+    if (count < 0) putchar('\n');
+}
+
+/* An expectation holds at the same point in both directions, so failing names the direction */
+static void expect(int forwards) {
+    var_t *desc = pop_var();
+    if (!truth()) fail("Expectation \"%s\" failed running %s", desc->val.str, forwards ? "forwards" : "backwards");
+    unref(desc);
+}
+
+static void expect_equal(int forwards) {
+    var_t *desc = pop_var(), *expected = pop_var(), *actual = pop_var();
+    double x, y;
+    int eq = float_operands(&actual->val, &expected->val, &x, &y) ? x == y : value_eq(&actual->val, &expected->val);
+    if (!eq) {
+        fflush(stdout);
+        fprintf(stderr, "Expectation \"%s\" failed running %s: expected ", desc->val.str, forwards ? "forwards" : "backwards");
+        print_value(stderr, &expected->val);
+        fprintf(stderr, ", got ");
+        print_value(stderr, &actual->val);
+        fputc('\n', stderr);
+        exit(1);
+    }
+    unref(desc);
+    unref(expected);
+    unref(actual);
+}
+
+static void clock_ms(void) {
+    struct timespec now;
+    clock_gettime(CLOCK_MONOTONIC, &now);
+    push(int_value((now.tv_sec - started.tv_sec) * 1000 + (now.tv_nsec - started.tv_nsec) / 1000000));
+}
+
+
+/* ------------------------------ Iterators ------------------------------ */
+
+static var_t *iter_array(const iter_t *iter) {
+    var_t *var = ref(iter->root);
+    for (size_t i = 0; i < iter->depth; i++) {
+        var_t *item = value_item(&var->val, iter->path[i]);
+        unref(var);
+        var = item;
+    }
+    return var;
+}
+
+/* Iterating backwards, or a reversed iterator forwards, starts from the end */
+static void push_iter(iter_t *iter, size_t length, int forwards) {
+    iter->idx = forwards != iter->reversed ? -1 : (long)length;
+    object_t obj = { NULL, iter };
+    push_object(obj);
+}
+
+static void create_iter(size_t reg, int reversed, long index_reg, size_t depth, int forwards) {
+    iter_t *iter = checked_alloc(sizeof(iter_t));
+    memset(iter, 0, sizeof(iter_t));
+    iter->reg = reg;
+    iter->reversed = reversed;
+    iter->index_reg = index_reg;
+    iter->root = pop_var();
+    iter->depth = depth;
+    iter->path = checked_alloc(depth * sizeof(size_t));
+    for (size_t i = 0; i < depth; i++) {
+        var_t *index = pop_var();
+        iter->path[i] = value_index(&index->val);
+        unref(index);
+    }
+    var_t *array = iter_array(iter);
+    if (array->val.kind != ARRAY) fail("For loop iterator is not an array");
+    push_iter(iter, array->val.len, forwards);
+    unref(array);
+}
+
+static void create_range_iter(size_t reg, int forwards) {
+    var_t *step = pop_var(), *end = pop_var(), *start = pop_var();
+    if (start->val.kind != FRAC || end->val.kind != FRAC || step->val.kind != FRAC)
+        fail("Range bounds and step must be numbers");
+    if (step->val.frac.num == 0) fail("Range step is zero");
+    iter_t *iter = checked_alloc(sizeof(iter_t));
+    memset(iter, 0, sizeof(iter_t));
+    iter->reg = reg;
+    iter->index_reg = -1;
+    iter->is_range = 1;
+    iter->start = start->val.frac;
+    iter->step = step->val.frac;
+    frac_t steps = frac_div(frac_sub(end->val.frac, start->val.frac), step->val.frac);
+    int64_t ceiling = steps.num / steps.den + (steps.num > 0 && steps.num % steps.den != 0);
+    iter->len = ceiling > 0 ? (size_t)ceiling : 0;
+    push_iter(iter, iter->len, forwards);
+    unref(start);
+    unref(end);
+    unref(step);
+}
+
+/* Move the loop variable on, returning 0 when the loop is finished. The array is found afresh each
+   step, in case the body resized it */
+static int step_iter(var_t **regs, int forwards) {
+    if (stack_len == 0 || !stack[stack_len - 1].iter) fail("No IterState on the stack");
+    iter_t *iter = stack[stack_len - 1].iter;
+    int ascending = forwards != iter->reversed;
+    var_t *array = iter->is_range ? NULL : iter_array(iter);
+    size_t length = iter->len;
+    if (array) {
+        if (array->val.kind != ARRAY) fail("For loop iterator is not an array");
+        length = array->val.len;
+    }
+    if ((ascending && iter->idx + 1 >= (long)length) || (!ascending && iter->idx <= 0)) {
+        stack_len--;
+        set_reg(&regs[iter->reg], NULL);
+        if (iter->index_reg >= 0) set_reg(&regs[iter->index_reg], NULL);
+        unref(array);
+        unref(iter->root);
+        free(iter->path);
+        free(iter);
+        return 0;
+    }
+    iter->idx += ascending ? 1 : -1;
+    if (iter->index_reg >= 0) set_reg(&regs[iter->index_reg], var_new(int_value(iter->idx)));
+    if (array) {
+        set_reg(&regs[iter->reg], value_item(&array->val, (size_t)iter->idx));
+        unref(array);
+    } else {
+        set_reg(&regs[iter->reg], var_new(frac_value(frac_add(iter->start, frac_mul(iter->step, frac_int(iter->idx))))));
+    }
+    return 1;
+}
+
+static void quit(void) {
+    fflush(stdout);
+    exit(0);
+}
+
+static void init_runtime(size_t num_globals) {
+    globals = checked_alloc(num_globals * sizeof(var_t *));
+    memset(globals, 0, num_globals * sizeof(var_t *));
+    clock_gettime(CLOCK_MONOTONIC, &started);
+}
+
+static double from_bits(uint64_t bits) {
+    double x;
+    memcpy(&x, &bits, sizeof x);
+    return x;
+}
+
+static value_t array_of(size_t count, ...) {
+    va_list items;
+    value_t array = array_value(count);
+    va_start(items, count);
+    for (size_t i = 0; i < count; i++) array_append(&array, var_new(va_arg(items, value_t)));
+    va_end(items);
+    return array;
+}
@@ -0,0 +1,559 @@
+// The runtime of programs built with "monoxide build --backend rust". This file isn't part of the
+// compiler: rustgen pastes it ahead of the generated functions. Values behave as they do in the
+// interpreter with exact arithmetic //
+
+#![allow(dead_code, unused_mut, unreachable_code, unused_variables, clippy::all)]
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::fmt;
+use std::rc::Rc;
+use std::time::Instant;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
+
+type Fraction = BigRational;
+type Var = Rc<RefCell<Value>>;
+
+#[derive(PartialEq, Clone)]
+enum Value {
+    Frac(Fraction),
+    Float(f64),
+    Array(Vec<Var>),
+    Str(String)
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Frac(val) => write!(f, "{}", val),
+            Value::Float(val) => write!(f, "{:?}", val),
+            Value::Str(string) => write!(f, "{}", string),
+            Value::Array(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.borrow().to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+        }
+    }
+}
+
+impl Value {
+    fn to_bool(&self) -> bool {
+        match self {
+            Value::Frac(value) => !value.is_zero(),
+            Value::Float(value) => *value != 0.0,
+            Value::Array(items) => !items.is_empty(),
+            Value::Str(string) => !string.is_empty()
+        }
+    }
+
+    fn to_usize(&self) -> usize {
+        match self {
+            Value::Frac(value) => value.to_integer().to_usize().expect("Index is not a non-negative integer"),
+            Value::Float(_) => panic!("Floats can't be used as indices"),
+            _ => panic!("Index is not a number")
+        }
+    }
+
+    fn deep_copy(&self) -> Value {
+        match self {
+            Value::Array(items) => Value::Array(items.iter().map(|item| new_var(item.borrow().deep_copy())).collect()),
+            value => value.clone()
+        }
+    }
+
+    fn item(&self, idx: usize) -> Var {
+        match self {
+            Value::Array(items) => Rc::clone(items.get(idx).unwrap_or_else(
+                || panic!("Index {} is out of bounds for an array of length {}", idx, items.len()))),
+            _ => panic!("Indexing is only supported by arrays")
+        }
+    }
+}
+
+fn new_var(value: Value) -> Var {
+    Rc::new(RefCell::new(value))
+}
+
+fn int(value: isize) -> Value {
+    Value::Frac(Fraction::from_integer(BigInt::from(value)))
+}
+
+fn frac(numer: &str, denom: &str) -> Fraction {
+    Fraction::new(numer.parse().unwrap(), denom.parse().unwrap())
+}
+
+fn truth(value: bool) -> Value {
+    int(if value {1} else {0})
+}
+
+fn to_f64(x: &Fraction) -> f64 {
+    match (x.numer().to_f64(), x.denom().to_f64()) {
+        (Some(n), Some(d)) => n / d,
+        _ => panic!("Rationals exceed f64 precision")
+    }
+}
+
+// Mixing a float with a fraction gives a float //
+fn float_operands(lhs: &Value, rhs: &Value) -> Option<(f64, f64)> {
+    match (lhs, rhs) {
+        (Value::Float(left), Value::Float(right)) => Some((*left, *right)),
+        (Value::Float(left), Value::Frac(right)) => Some((*left, to_f64(right))),
+        (Value::Frac(left), Value::Float(right)) => Some((to_f64(left), *right)),
+        _ => None
+    }
+}
+
+enum Source {
+    Array{root: Var, path: Vec<usize>},
+    Range{start: Fraction, step: Fraction, len: usize}
+}
+
+impl Source {
+    fn array(root: &Var, path: &[usize]) -> Var {
+        let mut var = Rc::clone(root);
+        for &index in path {
+            let item = var.borrow().item(index);
+            var = item;
+        }
+        var
+    }
+}
+
+struct IterState {
+    idx: isize,
+    register: usize,
+    source: Source,
+    reversed: bool,
+    index_register: Option<usize>
+}
+
+enum Object {
+    Var(Var),
+    Iter(IterState)
+}
+
+struct Runtime {
+    stack: Vec<Object>,
+    globals: Vec<Option<Var>>,
+    consts: Vec<Value>,
+    strings: Vec<&'static str>,
+    started: Instant
+}
+
+impl Runtime {
+    fn push(&mut self, value: Value) {
+        self.stack.push(Object::Var(new_var(value)));
+    }
+
+    fn push_var(&mut self, var: Var) {
+        self.stack.push(Object::Var(var));
+    }
+
+    fn pop_var(&mut self) -> Var {
+        match self.stack.pop() {
+            Some(Object::Var(var)) => var,
+            Some(Object::Iter(_)) => panic!("Non-variable found on the stack"),
+            None => panic!("Popped off empty stack")
+        }
+    }
+
+    fn truth(&mut self) -> bool {
+        self.pop_var().borrow().to_bool()
+    }
+
+    fn load_const(&mut self, idx: usize) {
+        let value = self.consts[idx].clone();
+        self.push(value);
+    }
+
+    fn load_string(&mut self, idx: usize) {
+        self.push(Value::Str(self.strings[idx].to_string()));
+    }
+
+    fn load(&mut self, register: &Option<Var>) {
+        match register {
+            Some(var) => self.push_var(Rc::clone(var)),
+            None => panic!("Using a register before it has a value")
+        }
+    }
+
+    fn subscript(&mut self, size: usize) {
+        let mut var = self.pop_var();
+        for _ in 0..size {
+            let index = self.pop_var().borrow().to_usize();
+            let item = var.borrow().item(index);
+            var = item;
+        }
+        self.push_var(var);
+    }
+
+    fn slice(&mut self) {
+        let var = self.pop_var();
+        let start = self.pop_var().borrow().to_usize();
+        let end = self.pop_var().borrow().to_usize();
+        let items = match &*var.borrow() {
+            Value::Array(items) if start <= end && end <= items.len() => items[start..end].to_vec(),
+            Value::Array(items) => panic!("Range {}:{} is out of bounds for an array of length {}", start, end, items.len()),
+            _ => panic!("Slicing is only supported by arrays")
+        };
+        self.push(Value::Array(items));
+    }
+
+    fn check_ref(&mut self, register: &Option<Var>) {
+        let var = self.pop_var();
+        let same = match register {
+            Some(held) => Rc::ptr_eq(held, &var) || match (&*held.borrow(), &*var.borrow()) {
+                (Value::Array(left), Value::Array(right)) =>
+                    left.len() == right.len() && left.iter().zip(right.iter()).all(|(l, r)| Rc::ptr_eq(l, r)),
+                _ => false
+            },
+            None => false
+        };
+        if !same {
+            panic!("Removing a reference from something it doesn't reference, so reversing would make it somewhere else");
+        }
+    }
+
+    fn check_unshared(&mut self, register: &Option<Var>) {
+        fn is_shared(var: &Var) -> bool {
+            Rc::strong_count(var) > 1 || match &*var.borrow() {
+                Value::Array(items) => items.iter().any(is_shared),
+                _ => false
+            }
+        }
+        if register.as_ref().is_some_and(is_shared) {
+            panic!("Pulled a variable while a reference still points into it");
+        }
+    }
+
+    fn store(&mut self) {
+        let value = self.pop_var().borrow().clone();
+        *self.pop_var().borrow_mut() = value;
+    }
+
+    fn duplicate_ref(&mut self) {
+        let var = match self.stack.last() {
+            Some(Object::Var(var)) => Rc::clone(var),
+            _ => panic!("Trying to duplicate non-variable")
+        };
+        self.push_var(var);
+    }
+
+    fn unique_var(&mut self) {
+        let var = self.pop_var();
+        if Rc::strong_count(&var) > 1 {
+            let value = var.borrow().deep_copy();
+            self.push(value);
+        } else {
+            self.push_var(var);
+        }
+    }
+
+    fn arith(&mut self, symbol: &str, frac_op: fn(&Fraction, &Fraction) -> Fraction, float_op: fn(f64, f64) -> f64) {
+        let rhs = self.pop_var();
+        let lhs = self.pop_var();
+        let result = match (&*lhs.borrow(), &*rhs.borrow()) {
+            (left, right) if float_operands(left, right).is_some() => {
+                let (left, right) = float_operands(left, right).unwrap();
+                Value::Float(float_op(left, right))
+            },
+            (Value::Frac(_), Value::Frac(right)) if right.is_zero() && (symbol == "/" || symbol == "%") => {
+                panic!("Division by zero")
+            },
+            (Value::Frac(left), Value::Frac(right)) => Value::Frac(frac_op(left, right)),
+            _ => panic!("Applying binop \"{}\" to incompatible types", symbol)
+        };
+        self.push(result);
+    }
+
+    fn idiv(&mut self) {
+        let rhs = self.pop_var();
+        let lhs = self.pop_var();
+        let result = match (&*lhs.borrow(), &*rhs.borrow()) {
+            (left, right) if float_operands(left, right).is_some() => {
+                let (left, right) = float_operands(left, right).unwrap();
+                Value::Float((left / right).trunc())
+            },
+            (Value::Frac(_), Value::Frac(right)) if right.is_zero() => panic!("Division by zero"),
+            (Value::Frac(left), Value::Frac(right)) => Value::Frac((left / right).trunc()),
+            _ => panic!("Applying binop \"//\" to incompatible types")
+        };
+        self.push(result);
+    }
+
+    fn pow(&mut self) {
+        let rhs = self.pop_var();
+        let lhs = self.pop_var();
+        let result = match (&*lhs.borrow(), &*rhs.borrow()) {
+            (left, right) if float_operands(left, right).is_some() => {
+                let (left, right) = float_operands(left, right).unwrap();
+                Value::Float(left.powf(right))
+            },
+            (Value::Frac(left), Value::Frac(right)) => {
+                let value = to_f64(left).powf(to_f64(right));
+                Value::Frac(Fraction::from_float(value).expect("Computing power created an infinite float"))
+            },
+            _ => panic!("Applying binop \"**\" to incompatible types")
+        };
+        self.push(result);
+    }
+
+    // Multiplying or dividing by 2 ** bits, which the compiler put in place of a constant //
+    fn shift(&mut self, bits: isize) {
+        self.push(Value::Frac(Fraction::from_integer(BigInt::from(1) << bits.unsigned_abs())));
+        if bits < 0 {
+            self.arith("/", |a, b| a / b, |a, b| a / b);
+        } else {
+            self.arith("*", |a, b| a * b, |a, b| a * b);
+        }
+    }
+
+    fn compare(&mut self, symbol: &str, test: fn(Ordering) -> bool) {
+        let rhs = self.pop_var();
+        let lhs = self.pop_var();
+        let ordering = match (&*lhs.borrow(), &*rhs.borrow()) {
+            (Value::Frac(left), Value::Frac(right)) => left.cmp(right),
+            (left, right) if float_operands(left, right).is_some() => {
+                let (left, right) = float_operands(left, right).unwrap();
+                left.partial_cmp(&right).unwrap_or(Ordering::Equal)
+            },
+            _ => panic!("Applying binop \"{}\" to incompatible types", symbol)
+        };
+        self.push(truth(test(ordering)));
+    }
+
+    // Floats equal fractions of the same value //
+    fn equal(&mut self, want: bool) {
+        let rhs = self.pop_var();
+        let lhs = self.pop_var();
+        let equal = match float_operands(&lhs.borrow(), &rhs.borrow()) {
+            Some((left, right)) => left == right,
+            None => *lhs.borrow() == *rhs.borrow()
+        };
+        self.push(truth(equal == want));
+    }
+
+    fn xor(&mut self) {
+        let rhs = self.truth();
+        let lhs = self.truth();
+        self.push(truth(lhs ^ rhs));
+    }
+
+    fn neg(&mut self) {
+        let result = match &*self.pop_var().borrow() {
+            Value::Float(x) => Value::Float(-x),
+            Value::Frac(x) => Value::Frac(-x),
+            _ => panic!("The negation operation is only supported by numbers")
+        };
+        self.push(result);
+    }
+
+    fn not(&mut self) {
+        let value = self.truth();
+        self.push(truth(!value));
+    }
+
+    fn len(&mut self) {
+        let len = match &*self.pop_var().borrow() {
+            Value::Array(items) => items.len(),
+            _ => panic!("Length operator (#) used on non-array")
+        };
+        self.push(int(len as isize));
+    }
+
+    fn to_float(&mut self) {
+        let result = match &*self.pop_var().borrow() {
+            Value::Frac(x) => Value::Float(to_f64(x)),
+            Value::Float(x) => Value::Float(*x),
+            _ => panic!("to_float is only supported by numbers")
+        };
+        self.push(result);
+    }
+
+    fn to_frac(&mut self) {
+        let result = match &*self.pop_var().borrow() {
+            Value::Float(x) => Value::Frac(Fraction::from_float(*x).expect("to_frac of an infinite or NaN float")),
+            Value::Frac(x) => Value::Frac(x.clone()),
+            _ => panic!("to_frac is only supported by numbers")
+        };
+        self.push(result);
+    }
+
+    fn float_fn(&mut self, name: &str, func: fn(f64) -> f64) {
+        let result = match &*self.pop_var().borrow() {
+            Value::Frac(x) => Value::Float(func(to_f64(x))),
+            Value::Float(x) => Value::Float(func(*x)),
+            _ => panic!("{} is only supported by numbers", name)
+        };
+        self.push(result);
+    }
+
+    fn array_literal(&mut self, size: usize) {
+        let mut items = Vec::with_capacity(size);
+        for _ in 0..size {
+            let mut item = self.pop_var();
+            if Rc::strong_count(&item) > 1 {
+                let value = item.borrow().deep_copy();
+                item = new_var(value);
+            }
+            items.push(item);
+        }
+        self.push(Value::Array(items));
+    }
+
+    fn array_repeat(&mut self) {
+        let dimensions = self.pop_var();
+        let content = self.pop_var();
+        let dimensions: Vec<usize> = match &*dimensions.borrow() {
+            Value::Array(items) => items.iter().map(|d| d.borrow().to_usize()).collect(),
+            Value::Frac(_) => vec![dimensions.borrow().to_usize()],
+            Value::Float(_) => panic!("Array repetition dimensions can't be floats"),
+            Value::Str(_) => panic!("Array repetition dimensions must be specified in an array")
+        };
+        if dimensions.is_empty() {
+            panic!("Array repetition needs at least one dimension");
+        }
+        fn repeat(content: &Value, dims: &[usize]) -> Vec<Var> {
+            (0..dims[0]).map(|_| new_var(match dims.len() {
+                1 => content.deep_copy(),
+                _ => Value::Array(repeat(content, &dims[1..]))
+            })).collect()
+        }
+        let items = repeat(&content.borrow(), &dimensions);
+        self.push(Value::Array(items));
+    }
+
+    fn pull(&mut self, register: &mut Option<Var>, front: bool) {
+        let item = match &mut *self.pop_var().borrow_mut() {
+            Value::Array(items) if items.is_empty() => panic!("Pulling from empty array"),
+            Value::Array(items) if front => items.remove(0),
+            Value::Array(items) => items.pop().unwrap(),
+            _ => panic!("Pulling is only supported by arrays")
+        };
+        *register = Some(item);
+    }
+
+    fn push_item(&mut self, register: &mut Option<Var>, front: bool) {
+        let item = register.take().expect("Pushing a register before it has a value");
+        match &mut *self.pop_var().borrow_mut() {
+            Value::Array(items) if front => items.insert(0, item),
+            Value::Array(items) => items.push(item),
+            _ => panic!("Pushing is only supported by arrays")
+        }
+    }
+
+    fn print(&mut self, count: isize) {
+        for _ in 0..count.abs() {
+            let text = self.pop_var().borrow().to_string();
+            print!("{}", text);
+        }
+        if count < 0 {
+            println!();
+        }
+    }
+
+    // An expectation holds at the same point in both directions, so failing names the direction //
+    fn expect(&mut self, forwards: bool) {
+        let desc = self.pop_var().borrow().to_string();
+        if !self.truth() {
+            panic!("Expectation \"{}\" failed running {}", desc, if forwards {"forwards"} else {"backwards"});
+        }
+    }
+
+    fn expect_equal(&mut self, forwards: bool) {
+        let desc = self.pop_var().borrow().to_string();
+        let expected = self.pop_var();
+        let actual = self.pop_var();
+        let equal = match float_operands(&actual.borrow(), &expected.borrow()) {
+            Some((left, right)) => left == right,
+            None => *actual.borrow() == *expected.borrow()
+        };
+        if !equal {
+            panic!("Expectation \"{}\" failed running {}: expected {}, got {}",
+                   desc, if forwards {"forwards"} else {"backwards"}, expected.borrow(), actual.borrow());
+        }
+    }
+
+    fn clock_ms(&mut self) {
+        let elapsed = self.started.elapsed().as_millis();
+        self.push(int(elapsed as isize));
+    }
+
+    fn create_iter(&mut self, register: usize, reversed: bool, index_register: Option<usize>, depth: usize, forwards: bool) {
+        let root = self.pop_var();
+        let path: Vec<usize> = (0..depth).map(|_| self.pop_var().borrow().to_usize()).collect();
+        let len = match &*Source::array(&root, &path).borrow() {
+            Value::Array(items) => items.len(),
+            _ => panic!("For loop iterator is not an array")
+        };
+        let state = IterState{idx: 0, register, source: Source::Array{root, path}, reversed, index_register};
+        self.push_iter(state, len, forwards);
+    }
+
+    fn create_range_iter(&mut self, register: usize, forwards: bool) {
+        let step = self.pop_var();
+        let end = self.pop_var();
+        let start = self.pop_var();
+        let (start, end, step) = match (&*start.borrow(), &*end.borrow(), &*step.borrow()) {
+            (Value::Frac(start), Value::Frac(end), Value::Frac(step)) => (start.clone(), end.clone(), step.clone()),
+            _ => panic!("Range bounds and step must be numbers")
+        };
+        if step.is_zero() {
+            panic!("Range step is zero");
+        }
+        let steps = ((end - &start) / &step).ceil();
+        let len = if steps > Fraction::zero() {steps.to_integer().to_usize().expect("Range is too long")} else {0};
+        let state = IterState{idx: 0, register, source: Source::Range{start, step, len}, reversed: false, index_register: None};
+        self.push_iter(state, len, forwards);
+    }
+
+    // Iterating backwards, or a reversed iterator forwards, starts from the end //
+    fn push_iter(&mut self, mut state: IterState, len: usize, forwards: bool) {
+        state.idx = if forwards != state.reversed {-1} else {len as isize};
+        self.stack.push(Object::Iter(state));
+    }
+
+    // Move the loop variable on, returning false when the loop is finished //
+    fn step_iter(&mut self, registers: &mut [Option<Var>], forwards: bool) -> bool {
+        let state = match self.stack.last_mut() {
+            Some(Object::Iter(state)) => state,
+            _ => panic!("No IterState on the stack")
+        };
+        let ascending = forwards != state.reversed;
+        let (len, array) = match &state.source {
+            Source::Array{root, path} => {
+                let array = Source::array(root, path);
+                let len = match &*array.borrow() {
+                    Value::Array(items) => items.len(),
+                    _ => panic!("For loop iterator is not an array")
+                };
+                (len, Some(array))
+            },
+            Source::Range{len, ..} => (*len, None)
+        };
+        if (ascending && state.idx + 1 >= len as isize) || (!ascending && state.idx <= 0) {
+            let (register, index_register) = (state.register, state.index_register);
+            self.stack.pop();
+            registers[register] = None;
+            if let Some(index_register) = index_register {
+                registers[index_register] = None;
+            }
+            return false;
+        }
+        state.idx += if ascending {1} else {-1};
+        if let Some(index_register) = state.index_register {
+            registers[index_register] = Some(new_var(int(state.idx)));
+        }
+        let item = match (array, &state.source) {
+            (Some(array), _) => array.borrow().item(state.idx as usize),
+            (None, Source::Range{start, step, ..}) => new_var(Value::Frac(
+                start + step * Fraction::from_integer(BigInt::from(state.idx)))),
+            (None, Source::Array{..}) => unreachable!()
+        };
+        registers[state.register] = Some(item);
+        true
+    }
+}
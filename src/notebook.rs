@@ -0,0 +1,435 @@
+use std::fs;
+use std::io::{self, BufRead, IsTerminal};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::cfg::Cfg;
+use crate::diff::{self, Change, Snapshot};
+use crate::interpreter::{Interpreter, Module, Region, ReloadError, Session};
+use crate::options::EngineOptions;
+use crate::parser::{self, ParseError};
+use crate::repl::{first_token, function_name};
+use crate::syntaxchecker::{check_syntax_quietly, SyntaxError};
+use crate::tokeniser::Tokeniser;
+
+
+// A notebook interleaves markdown and code cells, each starting at a line beginning "%%", e.g.
+// "%% markdown" or "%% code". As in the repl, the statements of every code cell run so far make up
+// the body of a main function, and the functions they define follow it. A cell's statements are
+// run against the registers left by earlier cells, and undoing a cell runs their backward code,
+// whatever the cells after it have done since. Every result is written as one line of json //
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CellKind {
+    Markdown,
+    Code
+}
+
+#[derive(Debug, Clone)]
+pub struct Cell {
+    pub kind: CellKind,
+    pub lines: Range<usize>,  // Of the notebook file, excluding the marker
+    pub source: String
+}
+
+// A statement or function definition from a code cell, indented so that its columns are those
+// of the notebook //
+struct Piece {
+    text: String,
+    line: usize
+}
+
+pub fn parse_cells(text: &str) -> Result<Vec<Cell>, String> {
+    let mut cells = Vec::new();
+    let mut kind = CellKind::Markdown;
+    let mut start = 1;
+    let mut source = String::new();
+    for (idx, line) in text.lines().enumerate() {
+        let marker = match line.strip_prefix("%%") {
+            Some(marker) => marker.trim(),
+            None => {
+                source.push_str(line);
+                source.push('\n');
+                continue;
+            }
+        };
+        // Text before the first marker is markdown, and dropped if blank //
+        if !(cells.is_empty() && source.trim().is_empty()) {
+            cells.push(Cell{kind, lines: start..idx + 1, source: std::mem::take(&mut source)});
+        }
+        kind = match marker {
+            "" | "code" => CellKind::Code,
+            "md" | "markdown" => CellKind::Markdown,
+            _ => return Err(format!("Unknown cell type \"{}\" at line {}", marker, idx + 1))
+        };
+        start = idx + 2;
+    }
+    if !(cells.is_empty() && source.trim().is_empty()) {
+        let end = start + source.lines().count();
+        cells.push(Cell{kind, lines: start..end, source});
+    }
+    Ok(cells)
+}
+
+// Split a code cell into its top level statements and function definitions //
+fn split_pieces(cell: &Cell) -> (Vec<Piece>, Vec<Piece>) {
+    let src = &cell.source;
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(src.match_indices('\n').map(|(idx, _)| idx + 1))
+        .collect();
+    let (mut stmts, mut functions) = (Vec::new(), Vec::new());
+    let mut open: Option<(usize, usize, usize, bool)> = None;  // Offset, line, column and whether a function
+    let mut depth = 0;
+    let mut seen_body = false;
+    let mut piece = |start: usize, end: usize, line: usize, col: usize, is_function: bool| {
+        let piece = Piece{text: format!("{}{}", " ".repeat(col), src[start..end].trim_end()), line: cell.lines.start + line - 1};
+        if is_function {functions.push(piece)} else {stmts.push(piece)}
+    };
+    for token in Tokeniser::new(src) {
+        if token.type_ == "END_MARKER!" {break}
+        let offset = line_starts[token.line - 1] + token.col;
+        let (start, line, col, is_function) = *open.get_or_insert_with(|| {
+            seen_body = false;
+            (offset, token.line, token.col, first_token(&src[offset..]).as_deref() == Some("fn"))
+        });
+        match token.string_.as_str() {
+            "{" | "(" | "[" => depth += 1,
+            "}" | ")" | "]" => {
+                depth -= 1;
+                if depth == 0 && token.string_ == "}" {seen_body = true}
+            },
+            _ => {}
+        }
+        let ends = depth == 0 && match is_function {
+            true => seen_body && token.string_ == ")",
+            false => token.string_ == ";" && token.type_ == "SYMBOL"
+        };
+        if ends {
+            piece(start, offset + token.string_.len(), line, col, is_function);
+            open = None;
+        }
+    }
+    if let Some((start, line, col, is_function)) = open {
+        piece(start, src.len(), line, col, is_function);
+    }
+    (stmts, functions)
+}
+
+
+#[derive(Default)]
+pub struct Notebook {
+    cells: Vec<Cell>,
+    cfg: Cfg,
+    options: EngineOptions,
+    stmts: Vec<Piece>,
+    functions: Vec<Piece>,
+    lines: Vec<usize>,  // The notebook line of each line of the program, 0 for those added around it
+    compiled: Vec<bool>,
+    applied: Vec<bool>,
+    results: Vec<Value>,
+    module: Option<Module>,
+    session: Session
+}
+
+impl Notebook {
+    pub fn new(cells: Vec<Cell>, cfg: Cfg, options: EngineOptions) -> Notebook {
+        let count = cells.len();
+        Notebook{
+            cells,
+            cfg,
+            options,
+            compiled: vec![false; count],
+            applied: vec![false; count],
+            results: vec![Value::Null; count],
+            ..Default::default()
+        }
+    }
+
+    fn source(stmts: &[Piece], functions: &[Piece]) -> (String, Vec<usize>) {
+        let mut src = String::from("fn main()() {\n");
+        let mut lines = vec![0];
+        for (pieces, footer) in [(stmts, "} ~main()\n\n"), (functions, "")] {
+            for Piece{text, line} in pieces {
+                src.push_str(text);
+                src.push('\n');
+                lines.extend(*line..*line + text.lines().count());
+            }
+            src.push_str(footer);
+            lines.extend(footer.lines().map(|_| 0));
+        }
+        (src, lines)
+    }
+
+    fn position(&self, line: usize, col: usize) -> String {
+        match self.lines.get(line.wrapping_sub(1)) {
+            Some(&line) if line != 0 => format!(" at line {}, column {}", line, col),
+            _ => String::new()
+        }
+    }
+
+    // Compile the program with a cell's code added, keeping it only if it checks. A function
+    // defined again replaces its earlier definition. Returns the warnings about the cell //
+    fn compile_cell(&mut self, idx: usize) -> Result<Vec<String>, String> {
+        let (stmts, functions) = split_pieces(&self.cells[idx]);
+        let new_stmts = stmts.len();
+        let mut new_functions = 0;
+        let mut replaced = Vec::new();
+        self.stmts.extend(stmts);
+        for piece in functions {
+            let name = function_name(&piece.text);
+            match self.functions.iter().position(|function| name.is_some() && function_name(&function.text) == name) {
+                Some(pos) => replaced.push((pos, std::mem::replace(&mut self.functions[pos], piece))),
+                None => {
+                    self.functions.push(piece);
+                    new_functions += 1;
+                }
+            }
+        }
+        let (src, lines) = Notebook::source(&self.stmts, &self.functions);
+        let old_lines = std::mem::replace(&mut self.lines, lines);
+
+        let cfg = &self.cfg;
+        let checked = panic::catch_unwind(AssertUnwindSafe(|| {
+            let parsed = parser::parse(Tokeniser::new(&src)).map_err(|ParseError{line, col, hint}| ("ParseError", line, col, hint))?;
+            check_syntax_quietly(parsed, cfg, false).map_err(|SyntaxError{line, col, desc, ..}| ("SyntaxError", line, col, Some(desc)))
+        }));
+        let result = match checked {
+            Ok(Ok((module, warnings))) => {
+                // A cell only redefining functions swaps them into the running module, if every
+                // other line of the program stays where it was //
+                let (functions, options) = (&self.functions, &self.options);
+                let reloaded = new_stmts == 0 && new_functions == 0 && !replaced.is_empty()
+                    && replaced.iter().all(|(pos, old)| functions[*pos].text.lines().count() == old.text.lines().count())
+                    && match &mut self.module {
+                        Some(compiled) => replaced.iter().all(|(pos, _)| {
+                            let name = function_name(&functions[*pos].text).unwrap_or_default();
+                            module.reload_function(&name, compiled, true, options)
+                                .map_err(|ReloadError{desc}| log::info!("{}, recompiling", desc))
+                                .is_ok()
+                        }),
+                        None => false
+                    };
+                if !reloaded {
+                    self.module = Some(module.compile(true, &self.options));
+                }
+                Ok(warnings.into_iter()
+                    .filter(|warning| self.cell_of(warning.line) == Some(idx))
+                    .map(|SyntaxError{line, col, desc, ..}| format!("Warning{}: {}", self.position(line, col), desc))
+                    .collect())
+            },
+            Ok(Err((kind, line, col, None))) => Err(format!("{}{}", kind, self.position(line, col))),
+            Ok(Err((kind, line, col, Some(desc)))) => Err(format!("{}{}: {}", kind, self.position(line, col), desc)),
+            Err(payload) => Err(match payload.downcast::<String>() {
+                Ok(desc) => *desc,
+                Err(payload) => payload.downcast::<&str>().map_or(String::from("Unknown error"), |desc| desc.to_string())
+            })
+        };
+        if result.is_err() {
+            self.stmts.truncate(self.stmts.len() - new_stmts);
+            self.functions.truncate(self.functions.len() - new_functions);
+            for (pos, old) in replaced.into_iter().rev() {
+                self.functions[pos] = old;
+            }
+            self.lines = old_lines;
+        }
+        result
+    }
+
+    // The cell holding a line of the program //
+    fn cell_of(&self, line: usize) -> Option<usize> {
+        let line = *self.lines.get(line.wrapping_sub(1))?;
+        self.cells.iter().position(|cell| cell.lines.contains(&line))
+    }
+
+    // The top level statements of main that came from a cell //
+    fn regions(&self, idx: usize) -> Vec<Region> {
+        let module = match &self.module {
+            Some(module) => module,
+            None => return Vec::new()
+        };
+        let main_func = &module.functions[module.main_idx.expect("No main function")];
+        main_func.code.regions.iter()
+            .filter(|region| region.depth == 0 && self.cell_of(region.line) == Some(idx))
+            .cloned()
+            .collect()
+    }
+
+    // Run a cell's statements in one direction. If one fails, those already run are run the other
+    // way again, so the cell is either applied or not //
+    fn execute(&mut self, idx: usize, forwards: bool) -> (String, Result<(), String>) {
+        let mut regions = self.regions(idx);
+        if !forwards {regions.reverse()}
+        let module = self.module.as_ref().expect("No program");
+        let mut output = String::new();
+        for (done, region) in regions.iter().enumerate() {
+            // Mono statements have no backward code, so undoing them changes nothing //
+            if !forwards && region.is_mono {continue}
+            let (printed, result) = Interpreter::resume_captured(module, forwards, region.range(forwards).clone(), &mut self.session, &self.options);
+            output.push_str(&printed);
+            if let Err(err) = result {
+                for region in regions[..done].iter().rev().filter(|region| !region.is_mono) {
+                    let _ = Interpreter::resume_captured(module, !forwards, region.range(!forwards).clone(), &mut self.session, &self.options);
+                }
+                let desc = match self.position(err.line, err.col).as_str() {
+                    "" => format!("RuntimeError: {}", err.desc),
+                    position => format!("RuntimeError{}: {}", position, err.desc)
+                };
+                return (output, Err(desc));
+            }
+        }
+        (output, Ok(()))
+    }
+
+    // A change's register named by the variable it holds around the cell, when known //
+    fn name_change(&self, change: Change, regions: &[Region]) -> Value {
+        let span = match (regions.first(), regions.last()) {
+            (Some(first), Some(last)) => first.fwd.start..last.fwd.end,
+            _ => 0..0
+        };
+        let name = diff::name_path(&change.path, self.module.as_ref().expect("No program"), span);
+        json!({"name": name, "old": change.old, "new": change.new})
+    }
+
+    fn result(&self, idx: usize, status: &str, output: &str, changes: Vec<Value>, messages: Vec<String>) -> Value {
+        let cell = &self.cells[idx];
+        let mut result = json!({
+            "cell": idx,
+            "kind": "code",
+            "line": cell.lines.start,
+            "source": cell.source,
+            "status": status,
+            "applied": self.applied[idx],
+            "output": output,
+            "changes": changes
+        });
+        match status {
+            "error" => result["error"] = json!(messages.join("\n")),
+            _ => result["warnings"] = json!(messages)
+        }
+        result
+    }
+
+    // Apply a code cell, compiling it first if it hasn't been //
+    pub fn run_cell(&mut self, idx: usize) -> Value {
+        if self.cells[idx].kind == CellKind::Markdown {
+            return json!({"cell": idx, "kind": "markdown", "error": format!("Cell {} is markdown", idx)});
+        }
+        if self.applied[idx] {
+            return self.result(idx, "error", "", Vec::new(), vec![format!("Cell {} is already applied", idx)]);
+        }
+        let warnings = match self.compiled[idx] {
+            true => Vec::new(),
+            false => match self.compile_cell(idx) {
+                Ok(warnings) => warnings,
+                Err(desc) => {
+                    self.results[idx] = self.result(idx, "error", "", Vec::new(), vec![desc]);
+                    return self.results[idx].clone();
+                }
+            }
+        };
+        self.compiled[idx] = true;
+        self.step(idx, true, warnings)
+    }
+
+    // Run a cell's backward code, undoing it //
+    pub fn undo_cell(&mut self, idx: usize) -> Value {
+        if self.cells[idx].kind == CellKind::Markdown {
+            return json!({"cell": idx, "kind": "markdown", "error": format!("Cell {} is markdown", idx)});
+        }
+        if !self.applied[idx] {
+            return self.result(idx, "error", "", Vec::new(), vec![format!("Cell {} is not applied", idx)]);
+        }
+        self.step(idx, false, Vec::new())
+    }
+
+    fn step(&mut self, idx: usize, forwards: bool, warnings: Vec<String>) -> Value {
+        let before = Snapshot::of(&self.session);
+        let (output, result) = self.execute(idx, forwards);
+        let regions = self.regions(idx);
+        let changes = diff::diff(&before, &Snapshot::of(&self.session)).into_iter()
+            .map(|change| self.name_change(change, &regions))
+            .collect();
+        let result = match result {
+            Ok(()) => {
+                self.applied[idx] = forwards;
+                self.result(idx, "ok", &output, changes, warnings)
+            },
+            Err(desc) => self.result(idx, "error", &output, changes, vec![desc])
+        };
+        self.results[idx] = result.clone();
+        result
+    }
+
+    fn markdown(&self, idx: usize) -> Value {
+        let cell = &self.cells[idx];
+        json!({"cell": idx, "kind": "markdown", "line": cell.lines.start, "source": cell.source})
+    }
+
+    // The last result of every cell //
+    pub fn list(&self) -> Vec<Value> {
+        (0..self.cells.len()).map(|idx| match self.cells[idx].kind {
+            CellKind::Markdown => self.markdown(idx),
+            CellKind::Code if self.results[idx].is_null() => self.result(idx, "pending", "", Vec::new(), Vec::new()),
+            CellKind::Code => self.results[idx].clone()
+        }).collect()
+    }
+
+    pub fn command(&mut self, line: &str) -> Vec<Value> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let cell = words.get(1).and_then(|word| word.parse::<usize>().ok()).filter(|&idx| idx < self.cells.len());
+        match (words.as_slice(), cell) {
+            (["run", _], Some(idx)) => vec![self.run_cell(idx)],
+            (["undo", _], Some(idx)) => vec![self.undo_cell(idx)],
+            (["list"], _) => self.list(),
+            _ => vec![json!({"error": format!("Commands: run <cell>, undo <cell>, list, for cells 0 to {}",
+                                               self.cells.len().saturating_sub(1))})]
+        }
+    }
+}
+
+
+// Run every cell in order, then take commands on stdin until it closes //
+pub fn run(path: &str, cfg: &Cfg, options: &EngineOptions) {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) => {
+            eprintln!("Can't read {}: {}", path, err);
+            return;
+        }
+    };
+    let cells = match parse_cells(&text) {
+        Ok(cells) => cells,
+        Err(desc) => {
+            eprintln!("{}", desc);
+            return;
+        }
+    };
+    let mut cfg = cfg.clone();
+    cfg.add_include_path(Path::new(path).parent().unwrap_or(Path::new(".")));
+    let mut notebook = Notebook::new(cells, cfg, *options);
+
+    panic::set_hook(Box::new(|_| {}));
+    for idx in 0..notebook.cells.len() {
+        let result = match notebook.cells[idx].kind {
+            CellKind::Markdown => notebook.markdown(idx),
+            CellKind::Code => notebook.run_cell(idx)
+        };
+        println!("{}", result);
+    }
+    if io::stdin().is_terminal() {
+        eprintln!("Commands: run <cell>, undo <cell>, list");
+    }
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break
+        };
+        if line.trim().is_empty() {continue}
+        for result in notebook.command(&line) {
+            println!("{}", result);
+        }
+    }
+    let _ = panic::take_hook();
+}
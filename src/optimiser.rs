@@ -1 +1,185 @@
+use std::ops::Range;
 
+use num_bigint::BigInt;
+use num_traits::{One, Signed, ToPrimitive};
+
+use crate::interpreter::{self, Fraction, Instruction, Region, Variable};
+
+
+// Deleted instructions become markers, which strip_markers then removes along with the rest //
+const DELETED: Instruction = Instruction::Marker{stmt: usize::MAX};
+
+// Peephole optimisations of a function's finalised code, run at optimisation level 1 and above
+// before the markers are stripped. Constants made along the way are added to the function's own //
+pub fn peephole(fwd: &mut [Instruction], bkwd: &mut Vec<Instruction>, regions: &mut [Region], consts: &mut Vec<Variable>) {
+    fold_modops(fwd, bkwd, regions, consts);
+    reduce_strength(fwd, bkwd, consts);
+    reduce_strength(bkwd, fwd, consts);
+    outline_backward_blocks(fwd, bkwd, regions);
+}
+
+// Chained updates of a register by constants, e.g. "x += 1; x += 2;", become the one update "x += 3;"
+// in both directions. The statements' regions merge into the first one's, and the others are left
+// empty. Only integer constants are combined, and only where the combined update overflows in
+// checked arithmetic exactly when the chain would have //
+fn fold_modops(fwd: &mut [Instruction], bkwd: &mut [Instruction], regions: &mut [Region], consts: &mut Vec<Variable>) {
+    let (fwd_landings, bkwd_landings) = (landings(fwd, bkwd), landings(bkwd, fwd));
+    let mut first = 0;
+    while first < regions.len() {
+        let Some((register, mut value, op)) = modop(fwd, bkwd, &regions[first], consts) else {
+            first += 1;
+            continue;
+        };
+        let mut last = first;
+        while let Some(next) = regions.get(last + 1) {
+            let prev = &regions[last];
+            let adjacent = next.depth == prev.depth && next.fwd.start == prev.fwd.end && next.bkwd.end == prev.bkwd.start;
+            let combined = match modop(fwd, bkwd, next, consts) {
+                Some((next_register, next_value, next_op)) if adjacent && next_register == register && next_op == op => {
+                    combine(&value, &next_value, op)
+                },
+                _ => None
+            };
+            let Some(combined) = combined else {break};
+            value = combined;
+            last += 1;
+        }
+        let fwd_range = regions[first].fwd.start..regions[last].fwd.end;
+        let bkwd_range = regions[last].bkwd.start..regions[first].bkwd.end;
+        let lands_inside = |landings: &[bool], range: &Range<usize>| landings[range.start + 1..range.end].contains(&true);
+        if last == first || lands_inside(&fwd_landings, &fwd_range) || lands_inside(&bkwd_landings, &bkwd_range) {
+            first += 1;
+            continue;
+        }
+
+        consts.push(Variable::Frac(value));
+        let idx = consts.len() - 1;
+        let inverse = op.inverse().expect("Modop without an inverse");
+        for (stream, range, op) in [(&mut *fwd, &fwd_range, op), (&mut *bkwd, &bkwd_range, inverse)] {
+            let folded = [Instruction::LoadRegister{register}, Instruction::DuplicateRef, Instruction::LoadConst{idx}, op, Instruction::Store];
+            for (ip, instruction) in stream[range.clone()].iter_mut().enumerate() {
+                *instruction = folded.get(ip).copied().unwrap_or(DELETED);
+            }
+        }
+        regions[first].fwd = fwd_range.clone();
+        regions[first].bkwd = bkwd_range.clone();
+        for region in regions[first + 1..=last].iter_mut() {
+            region.fwd = fwd_range.start..fwd_range.start;
+            region.bkwd = bkwd_range.start..bkwd_range.start;
+        }
+        first = last + 1;
+    }
+}
+
+// The register, constant and forward op of a region holding just a modop statement //
+fn modop(fwd: &[Instruction], bkwd: &[Instruction], region: &Region, consts: &[Variable]) -> Option<(usize, Fraction, Instruction)> {
+    if region.is_mono {return None}
+    let code = |stream: &[Instruction], range: &Range<usize>| -> Vec<Instruction> {
+        stream[range.clone()].iter().copied().filter(|i| !matches!(i, Instruction::Marker{..})).collect()
+    };
+    let (fwd_code, bkwd_code) = (code(fwd, &region.fwd), code(bkwd, &region.bkwd));
+    let (register, idx, op) = match fwd_code[..] {
+        [Instruction::LoadRegister{register}, Instruction::DuplicateRef, Instruction::LoadConst{idx}, op, Instruction::Store]
+            if matches!(op, Instruction::BinopAdd | Instruction::BinopSub | Instruction::BinopMul | Instruction::BinopDiv) =>
+            (register, idx, op),
+        _ => return None
+    };
+    let expected = [Instruction::LoadRegister{register}, Instruction::DuplicateRef, Instruction::LoadConst{idx}, op.inverse()?, Instruction::Store];
+    if bkwd_code[..] != expected[..] {return None}
+    match consts.get(idx)? {
+        Variable::Frac(value) if value.is_integer() && value.to_integer().to_i64().is_some() => Some((register, value.clone(), op)),
+        _ => None
+    }
+}
+
+// The constant doing the work of two in a row, if the one update is still exact //
+fn combine(first: &Fraction, second: &Fraction, op: Instruction) -> Option<Fraction> {
+    let combined = match op {
+        Instruction::BinopAdd | Instruction::BinopSub if !(first * second).is_negative() => first + second,
+        Instruction::BinopMul | Instruction::BinopDiv if first.is_positive() && second.is_positive() => first * second,
+        _ => return None
+    };
+    combined.to_integer().to_i64().map(|_| combined)
+}
+
+// Multiplying or dividing by a constant power of two becomes a Shift //
+fn reduce_strength(stream: &mut [Instruction], other: &[Instruction], consts: &[Variable]) {
+    let landings = landings(stream, other);
+    for ip in 1..stream.len() {
+        let (Instruction::LoadConst{idx}, Instruction::BinopMul | Instruction::BinopDiv) = (stream[ip - 1], stream[ip]) else {continue};
+        if landings[ip] {continue}
+        let Some(bits) = consts.get(idx).and_then(power_of_two) else {continue};
+        stream[ip - 1] = DELETED;
+        stream[ip] = Instruction::Shift{bits: if stream[ip] == Instruction::BinopDiv {-bits} else {bits}};
+    }
+}
+
+// k for a constant 2 ** k, keeping to what fits in 64 bits //
+fn power_of_two(value: &Variable) -> Option<isize> {
+    let Variable::Frac(value) = value else {return None};
+    if !value.is_integer() || !value.is_positive() {return None}
+    let value = value.to_integer();
+    let bits = interpreter::trailing_zeros(&value);
+    (bits > 0 && bits < 63 && value == BigInt::one() << bits).then_some(bits as isize)
+}
+
+// Statements whose backward code is the same straight run of instructions, as repeated macro
+// expansions often are, share one copy of it. The copies are outlined after the end of the stream,
+// behind a jump to the end, and each statement runs its copy with a Gosub. Only statements without
+// nested ones are outlined, and only when it makes the stream shorter //
+fn outline_backward_blocks(fwd: &[Instruction], bkwd: &mut Vec<Instruction>, regions: &[Region]) {
+    let landings = landings(bkwd, fwd);
+    let mut blocks: Vec<(Vec<Instruction>, Vec<Range<usize>>)> = Vec::new();
+    for region in regions.iter() {
+        let range = &region.bkwd;
+        let is_leaf = !regions.iter().any(|other| {
+            !other.bkwd.is_empty() && other.bkwd != *range && range.start <= other.bkwd.start && other.bkwd.end <= range.end
+        });
+        if region.is_mono || range.is_empty() || !is_leaf || landings[range.start + 1..range.end].contains(&true) {continue}
+        let code: Vec<Instruction> = bkwd[range.clone()].iter().copied().filter(|i| !matches!(i, Instruction::Marker{..})).collect();
+        if code.is_empty() || !code.iter().all(straight_line) {continue}
+        match blocks.iter_mut().find(|(block, _)| *block == code) {
+            Some((_, ranges)) if ranges.contains(range) => {},
+            Some((_, ranges)) => ranges.push(range.clone()),
+            None => blocks.push((code, vec![range.clone()]))
+        }
+    }
+
+    // n copies of a block of length l shrink to n Gosubs and one copy with its Return //
+    blocks.retain(|(block, ranges)| ranges.len() * block.len() > ranges.len() + block.len() + 1);
+    let saved: usize = blocks.iter().map(|(block, ranges)| ranges.len() * block.len() - ranges.len() - block.len() - 1).sum();
+    if saved <= 1 {return}
+
+    let end = bkwd.len();
+    bkwd.push(Instruction::Jump{ip: 0});
+    for (block, ranges) in blocks {
+        let start = bkwd.len();
+        bkwd.extend(block);
+        bkwd.push(Instruction::Return);
+        for range in ranges {
+            bkwd[range.clone()].fill(DELETED);
+            bkwd[range.start] = Instruction::Gosub{ip: start};
+        }
+    }
+    bkwd[end] = Instruction::Jump{ip: bkwd.len()};
+}
+
+// Whether an instruction always carries on to the next, and makes no calls //
+fn straight_line(instruction: &Instruction) -> bool {
+    instruction.stack_effect().is_some() && instruction.jump_target().is_none() &&
+    !matches!(instruction, Instruction::Reverse{..} | Instruction::Quit | Instruction::Suspend | Instruction::Return)
+}
+
+// Which instructions of a stream are jumped to, from it or by reversing out of the other //
+fn landings(stream: &[Instruction], other: &[Instruction]) -> Vec<bool> {
+    let mut ret = vec![false; stream.len() + 1];
+    for target in stream.iter().filter_map(Instruction::jump_target) {
+        ret[target] = true;
+    }
+    for instruction in other.iter() {
+        if let Instruction::Reverse{idx} = instruction {
+            ret[*idx] = true;
+        }
+    }
+    ret
+}
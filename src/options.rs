@@ -0,0 +1,116 @@
+use std::env;
+
+use log::LevelFilter;
+
+use crate::logging;
+
+
+// How programs are compiled and run. Each option can be given in the project's reaver.toml, as a
+// REAVER_* environment variable, or as a command line flag, each overriding the one before, e.g.
+//
+//     step_budget = 1000000       REAVER_STEP_BUDGET=1000000       --step-budget 1000000
+//
+#[derive(Debug, Clone, Copy)]
+pub struct EngineOptions {
+    pub opt_level: u8,  // 0 keeps statement markers in the bytecode, 1 strips them and runs the peephole optimiser
+    pub step_budget: Option<u64>,  // Instructions a run may execute before it's stopped
+    pub memory_cap: Option<usize>,  // Values a run may hold in one array, or in the registers of its call stack
+    pub log_level: LevelFilter,  // Of internal logging on stderr
+    pub seed: u64  // For host functions that need randomness, so their runs can be repeated
+}
+
+// The names options go by in reaver.toml. Flags swap the underscores for dashes, and environment
+// variables are upper case with a REAVER_ prefix //
+pub const KEYS: [&str; 5] = ["opt_level", "step_budget", "memory_cap", "log", "seed"];
+
+impl Default for EngineOptions {
+    fn default() -> EngineOptions {
+        EngineOptions{
+            opt_level: if cfg!(debug_assertions) {0} else {1},
+            step_budget: None,
+            memory_cap: None,
+            log_level: LevelFilter::Off,
+            seed: 0
+        }
+    }
+}
+
+impl EngineOptions {
+    pub fn with_opt_level(mut self, opt_level: u8) -> EngineOptions {
+        self.opt_level = opt_level;
+        self
+    }
+
+    pub fn with_step_budget(mut self, step_budget: u64) -> EngineOptions {
+        self.step_budget = Some(step_budget);
+        self
+    }
+
+    pub fn with_memory_cap(mut self, memory_cap: usize) -> EngineOptions {
+        self.memory_cap = Some(memory_cap);
+        self
+    }
+
+    pub fn with_log_level(mut self, log_level: LevelFilter) -> EngineOptions {
+        self.log_level = log_level;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> EngineOptions {
+        self.seed = seed;
+        self
+    }
+
+    // Set the option with one of the KEYS from its text //
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "opt_level" => self.opt_level = match value.parse() {
+                Ok(level @ 0..=1) => level,
+                _ => return Err(String::from("takes 0, keeping statement markers, or 1, stripping them"))
+            },
+            "step_budget" => self.step_budget = Some(value.parse().map_err(|_| String::from("takes a number of instructions"))?),
+            "memory_cap" => self.memory_cap = Some(value.parse().map_err(|_| String::from("takes a number of values"))?),
+            "log" => self.log_level = logging::level_from_name(value)
+                .ok_or_else(|| String::from("takes one of off, error, warn, info, debug or trace"))?,
+            "seed" => self.seed = value.parse().map_err(|_| String::from("takes an unsigned 64-bit integer"))?,
+            _ => return Err(format!("Unknown option \"{}\"", key))
+        }
+        Ok(())
+    }
+
+    pub fn apply_env(&mut self) -> Result<(), String> {
+        for key in KEYS {
+            let name = format!("REAVER_{}", key.to_uppercase());
+            if let Ok(value) = env::var(&name) {
+                self.set(key, &value).map_err(|desc| format!("{} {}", name, desc))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn apply_flags(&mut self, flags: &[(&str, &str)]) -> Result<(), String> {
+        for (key, value) in flags {
+            self.set(key, value).map_err(|desc| format!("--{} {}", key.replace('_', "-"), desc))?;
+        }
+        Ok(())
+    }
+}
+
+// Take the flags setting options out of the command line, e.g. "--opt-level 1", in the order
+// given //
+pub fn take_flags<'a>(args: &mut Vec<&'a str>) -> Result<Vec<(&'static str, &'a str)>, String> {
+    let mut flags = Vec::new();
+    let mut idx = 0;
+    while idx < args.len() {
+        let key = args[idx].strip_prefix("--").map(|name| name.replace('-', "_"));
+        match KEYS.iter().find(|&&k| Some(k) == key.as_deref()) {
+            Some(key) => {
+                let value = *args.get(idx + 1).ok_or_else(|| format!("Missing value after {}", args[idx]))?;
+                flags.push((*key, value));
+                args.drain(idx..idx + 2);
+            },
+            None => idx += 1
+        }
+    }
+    Ok(flags)
+}
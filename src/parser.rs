@@ -1,29 +1,200 @@
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::mem;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
-use crate::tokeniser::Token;
+use crate::tokeniser::{Token, Tokeniser};
 use crate::parsetree::{
     StatementNode, ExpressionNode, LookupNode, LetUnletNode,
     FractionNode, BinopNode, IfNode, ModopNode, FunctionNode,
-    CatchNode, ArrayLiteralNode, Module, RefUnrefNode, CallNode,
+    CatchNode, TryNode, ArrayLiteralNode, Module, RefUnrefNode, RelinkNode, MoveNode, CallNode,
     FunctionParam, PushPullNode, UniopNode, WhileNode, ForNode,
-    PrintNode, StringNode, DoYieldNode, ArrayRepeatNode
+    PrintNode, StringNode, DoYieldNode, ArrayRepeatNode, PositionedNode,
+    ForRangeNode, ComprehensionNode, DestructureLetNode, ConstArg,
+    Attribute, CallExprNode, StaticAssertNode, ConstEvalNode, ExpectNode, Import, HoleNode, TodoNode, BacktraceNode, CounterNode,
+    RememberNode, MachineNode, MachineState, Transition, MacroNode, MacroCallNode, ForGenNode, YieldNode
 };
-use crate::interpreter::{Fraction, Instruction};
+use crate::interpreter::{Fraction, Instruction, Variable};
+use crate::syntaxtree::Type;
+
+
+// Functions built into the language, each compiled to a single instruction //
+const BUILTINS: [(&str, Instruction); 7] = [
+    ("to_float", Instruction::UniopToFloat), ("to_frac", Instruction::UniopToFrac),
+    ("sin", Instruction::UniopSin), ("cos", Instruction::UniopCos), ("exp", Instruction::UniopExp),
+    ("log", Instruction::UniopLog), ("sqrt", Instruction::UniopSqrt)
+];
+
+const COUNTERS: [(&str, Instruction); 2] = [
+    ("steps", Instruction::Steps), ("clock_ms", Instruction::ClockMs)
+];
+
+// The grammar the rules below accept, as one EBNF production per rule, printed by "grammar --ebnf".
+// Each memoised rule fails to compile without a production here, so a rule can't be added or
+// renamed without describing it. NAME, NUMBER and STRING are tokens, and END the end of the source //
+pub struct Production {
+    pub rule: &'static str,
+    pub ebnf: &'static str
+}
+
+pub const GRAMMAR: &[Production] = &[
+    Production{rule: "module", ebnf: "{ pragma } , { import } , { global_statement } , function | macro_def , { function | macro_def } , END"},
+    Production{rule: "pragma", ebnf: "\"#\" , \"pragma\" , \"strict\""},
+    Production{rule: "import", ebnf: "\"import\" , name , \";\""},
+    Production{rule: "function", ebnf: "{ attribute } , [ \"pub\" ] , \"fn\" , name , [ links ] , \"(\" , [ borrow_param , { \",\" , borrow_param } ] , \")\" , \"(\" , [ function_param , { \",\" , function_param } ] , \")\" , \"{\" , { statement | function } , \"}\" , \"~\" , name , \"(\" , [ function_param , { \",\" , function_param } ] , \")\""},
+    Production{rule: "macro_def", ebnf: "\"macro\" , name , \"(\" , [ name , { \",\" , name } ] , \")\" , \"{\" , { statement } , \"}\""},
+    Production{rule: "attribute", ebnf: "\"#\" , \"[\" , name , [ \"(\" , [ attribute_arg , { \",\" , attribute_arg } ] , \")\" ] , \"]\""},
+    Production{rule: "attribute_arg", ebnf: "name , [ \"=\" , STRING ]"},
+    Production{rule: "links", ebnf: "\"<\" , [ link_item , { \",\" , link_item } ] , \">\""},
+    Production{rule: "link_item", ebnf: "[ \"const\" ] , name"},
+    Production{rule: "function_param", ebnf: "( \"&\" , NAME , [ name ] | name ) , [ \":\" , type_name ]"},
+    Production{rule: "borrow_param", ebnf: "function_param , [ \"=\" , const_literal ]"},
+    Production{rule: "type_name", ebnf: "\"num\" | \"str\" | \"handle\" | \"array\" , [ \"[\" , type_name , \"]\" ]"},
+    Production{rule: "const_literal", ebnf: "[ \"-\" ] , NUMBER | STRING"},
+    Production{rule: "const_args", ebnf: "\"<\" , [ const_arg , { \",\" , const_arg } ] , \">\""},
+    Production{rule: "const_arg", ebnf: "const_literal | name"},
+    Production{rule: "statement", ebnf: "{ attribute } , ( print_stmt | letunlet_stmt | refunref_stmt | modop_stmt | pull_stmt | move_stmt | if_stmt | while_stmt | for_stmt | for_range_stmt | for_gen_stmt | doyield_stmt | yield_stmt | catch_stmt | try_stmt | remember_stmt | static_assert_stmt | const_eval_stmt | expect_stmt | todo_stmt | backtrace_stmt | machine_stmt | macro_call_stmt | call_stmt | destructure_stmt | destructure_let_stmt )"},
+    Production{rule: "global_statement", ebnf: "{ attribute } , ( letunlet_stmt | refunref_stmt | modop_stmt | pull_stmt | static_assert_stmt | const_eval_stmt )"},
+    Production{rule: "print_stmt", ebnf: "( \"print\" | \"println\" ) , \"(\" , [ expression , { \",\" , expression } ] , \")\" , \";\""},
+    Production{rule: "letunlet_stmt", ebnf: "name , ( \"=\" | \"~=\" ) , expression , \";\""},
+    Production{rule: "refunref_stmt", ebnf: "name , ( \"=\" , \"&\" , lookup , [ slice ] | \"~=\" , \"&\" , lookup , [ slice | \"=\" , \"&\" , lookup ] ) , \";\""},
+    Production{rule: "slice", ebnf: "\"[\" , expression , \":\" , expression , \"]\""},
+    Production{rule: "modop_stmt", ebnf: "lookup , modop , expression , \";\""},
+    Production{rule: "modop", ebnf: "\"+=\" | \"-=\" | \"*=\" | \"/=\""},
+    Production{rule: "pull_stmt", ebnf: "name , ( \"<=\" | \"=>\" ) , lookup , \";\""},
+    Production{rule: "move_stmt", ebnf: "name , \"<\" , \"-\" , name , \";\""},
+    Production{rule: "if_stmt", ebnf: "\"if\" , \"(\" , expression , \")\" , \"{\" , { statement } , \"}\" , [ else_block ] , \"~\" , \"if\" , \"(\" , [ expression ] , \")\" , \";\""},
+    Production{rule: "else_block", ebnf: "\"else\" , \"{\" , { statement } , \"}\""},
+    Production{rule: "while_stmt", ebnf: "\"while\" , \"(\" , expression , \")\" , \"{\" , { statement } , \"}\" , \"~\" , \"while\" , \"(\" , [ expression ] , \")\" , \";\""},
+    Production{rule: "for_stmt", ebnf: "( \"for\" | \"parfor\" ) , \"(\" , ( name , \"in\" , ( lookup | \"reversed\" , \"(\" , lookup , \")\" ) | \"(\" , name , \",\" , name , \")\" , \"in\" , \"enumerate\" , \"(\" , lookup , \")\" ) , \")\" , \"{\" , { statement } , \"}\" , \";\""},
+    Production{rule: "for_range_stmt", ebnf: "\"for\" , \"(\" , name , \"in\" , expression , \"..\" , expression , [ \"step\" , expression ] , \")\" , \"{\" , { statement } , \"}\" , \";\""},
+    Production{rule: "for_gen_stmt", ebnf: "\"for\" , \"(\" , name , \"in\" , function_name - ( \"reversed\" | \"enumerate\" ) , \"(\" , [ lookup , { \",\" , lookup } ] , \")\" , \")\" , \"{\" , { statement } , \"}\" , \";\""},
+    Production{rule: "doyield_stmt", ebnf: "\"do\" , \"{\" , { statement } , \"}\" , yield_block , \"~\" , \"do\" , \";\""},
+    Production{rule: "yield_block", ebnf: "[ \"yield\" , \"{\" , { statement } , \"}\" ]"},
+    Production{rule: "yield_stmt", ebnf: "\"yield\" , expression , \";\""},
+    Production{rule: "catch_stmt", ebnf: "\"catch\" , \"(\" , expression , \")\" , \";\""},
+    Production{rule: "try_stmt", ebnf: "\"try\" , \"{\" , { statement } , \"}\" , \"catch\" , \"(\" , expression , \")\" , \"{\" , { statement } , \"}\" , [ \"~\" , \"catch\" , \"(\" , expression , \")\" , \";\" ]"},
+    Production{rule: "remember_stmt", ebnf: "\"remember\" , \"{\" , { statement } , \"}\""},
+    Production{rule: "static_assert_stmt", ebnf: "\"static_assert\" , \"(\" , expression , \",\" , STRING , \")\" , \";\""},
+    Production{rule: "const_eval_stmt", ebnf: "\"const\" , name , \"=\" , \"eval\" , function_name , \"(\" , [ const_literal , { \",\" , const_literal } ] , \")\" , \";\""},
+    Production{rule: "expect_stmt", ebnf: "\"expect\" , ( expr3 , \"==\" , expr3 | expression ) , \";\""},
+    Production{rule: "todo_stmt", ebnf: "\"todo\" , \";\""},
+    Production{rule: "backtrace_stmt", ebnf: "\"backtrace\" , \"(\" , \")\" , \";\""},
+    Production{rule: "machine_stmt", ebnf: "\"machine\" , name , \"{\" , machine_state , { machine_state } , \"}\""},
+    Production{rule: "machine_state", ebnf: "\"state\" , name , \"{\" , { statement } , { transition } , \"}\""},
+    Production{rule: "transition", ebnf: "\"on\" , expression , \"->\" , name , \";\""},
+    Production{rule: "macro_call_stmt", ebnf: "function_name , \"!\" , \"(\" , [ lookup , { \",\" , lookup } ] , \")\" , \";\""},
+    Production{rule: "call_stmt", ebnf: "[ [ name , { \",\" , name } ] , \"=>\" ] , [ \"~\" ] , function_name , [ const_args ] , \"(\" , [ lookup , { \",\" , lookup } ] , \")\" , [ \"=>\" , [ name , { \",\" , name } ] ] , \";\""},
+    Production{rule: "destructure_stmt", ebnf: "\"(\" , [ function_param , { \",\" , function_param } ] , \")\" , \"=\" , \"call\" , [ [ name , { \",\" , name } ] , \"=>\" ] , [ \"~\" ] , function_name , [ const_args ] , \"(\" , [ lookup , { \",\" , lookup } ] , \")\" , \";\""},
+    Production{rule: "destructure_let_stmt", ebnf: "\"[\" , [ pattern_item , { \",\" , pattern_item } ] , \"]\" , ( \"=\" | \"~=\" ) , name , \";\""},
+    Production{rule: "pattern_item", ebnf: "name , [ \"...\" ]"},
+    Production{rule: "expression", ebnf: "expression , \"|\" , expr0 | expr0"},
+    Production{rule: "expr0", ebnf: "expr0 , \"&\" , expr1 | expr1"},
+    Production{rule: "expr1", ebnf: "expr1 , \"^\" , expr2 | expr2"},
+    Production{rule: "expr2", ebnf: "expr2 , ( \"<\" | \"<=\" | \">\" | \">=\" | \"!=\" | \"==\" ) , expr3 | expr3"},
+    Production{rule: "expr3", ebnf: "expr3 , ( \"+\" | \"-\" ) , expr4 | expr4"},
+    Production{rule: "expr4", ebnf: "expr4 , ( \"*\" | \"/\" | \"//\" | \"%\" ) , expr5 | expr5"},
+    Production{rule: "expr5", ebnf: "expr5 , \"**\" , atom | atom"},
+    Production{rule: "atom", ebnf: "\"(\" , expression , \")\" | array_literal | array_repeat | array_comprehension | builtin , \"(\" , expression , \")\" | counter , \"(\" , \")\" | call_expr | lookup | NUMBER | \"???\" | STRING | ( \"-\" | \"!\" | \"#\" ) , atom"},
+    Production{rule: "array_literal", ebnf: "\"[\" , [ expression , { \",\" , expression } ] , \"]\""},
+    Production{rule: "array_repeat", ebnf: "\"[\" , expression , \"repeat\" , expression , \"]\""},
+    Production{rule: "array_comprehension", ebnf: "\"[\" , expression , \"for\" , name , \"in\" , lookup , [ \"if\" , expression ] , \"]\""},
+    Production{rule: "call_expr", ebnf: "function_name , \"(\" , [ lookup , { \",\" , lookup } ] , \")\""},
+    Production{rule: "lookup", ebnf: "name , { index }"},
+    Production{rule: "index", ebnf: "\"[\" , expression , \"]\""},
+    Production{rule: "function_name", ebnf: "NAME , { \":\" , \":\" , NAME }"},
+    Production{rule: "name", ebnf: "[ \".\" ] , NAME"}
+];
+
+// Whether GRAMMAR describes a rule, for the check each memoised rule makes when compiled //
+const fn has_production(rule: &str) -> bool {
+    let rule = rule.as_bytes();
+    let mut idx = 0;
+    while idx < GRAMMAR.len() {
+        let name = GRAMMAR[idx].rule.as_bytes();
+        let mut pos = 0;
+        while pos < name.len() && pos < rule.len() && name[pos] == rule[pos] {
+            pos += 1;
+        }
+        if pos == name.len() && pos == rule.len() {
+            return true;
+        }
+        idx += 1;
+    }
+    false
+}
 
+// The grammar in EBNF, with the builtins and counters the atom rule accepts listed from their tables //
+pub fn grammar_ebnf() -> String {
+    let alternatives = |names: &mut dyn Iterator<Item=&str>| names.map(|name| format!("\"{}\"", name)).collect::<Vec<_>>().join(" | ");
+    let mut ebnf = String::from("(* NAME, NUMBER and STRING are tokens, and END the end of the source *)\n");
+    for Production{rule, ebnf: production} in GRAMMAR {
+        ebnf.push_str(&format!("{} = {} ;\n", rule, production));
+    }
+    ebnf.push_str(&format!("builtin = {} ;\n", alternatives(&mut BUILTINS.iter().map(|(name, _)| *name))));
+    ebnf.push_str(&format!("counter = {} ;\n", alternatives(&mut COUNTERS.iter().map(|(name, _)| *name))));
+    ebnf
+}
 
-pub struct Parser {
-    tokens: Vec<Token>,
+pub struct Parser<'a> {
+    source: Tokeniser<'a>,
+    tokens: VecDeque<Token>,  // Those pulled from the source and not yet released, from position released on
+    released: usize,
+    releases: bool,  // Whether tokens behind each top level item are dropped, as the parse never goes back to them
     token_pos: usize,
     max_token_pos: usize,
-    memo: HashMap<(usize, String), (usize, Parsed)>
+    memo: HashMap<(usize, String), (usize, Parsed)>,
+    profile: Option<ParseProfile>,
+    failure: Option<ParseError>  // A fault no backtracking gets past, which fails the whole parse
+}
+
+// Counts of how often each memoised rule was tried, how often the memo answered instead, how many
+// times the rule was actually run (for left-recursive rules, once per pass growing the match), and
+// the time spent running it, including the rules it calls //
+#[derive(Debug, Default)]
+pub struct ParseProfile {
+    rules: HashMap<&'static str, RuleStats>
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct RuleStats {
+    calls: usize,
+    hits: usize,
+    passes: usize,
+    time: Duration
+}
+
+impl fmt::Display for ParseProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut rules: Vec<_> = self.rules.iter().collect();
+        rules.sort_by(|a, b| b.1.time.cmp(&a.1.time).then(a.0.cmp(b.0)));
+        writeln!(f, "{:<24} {:>8} {:>8} {:>6} {:>8} {:>10}", "rule", "calls", "hits", "hit%", "passes", "ms")?;
+        for (name, stats) in rules {
+            writeln!(f, "{:<24} {:>8} {:>8} {:>6.1} {:>8} {:>10.3}",
+                name, stats.calls, stats.hits, 100.0 * stats.hits as f64 / stats.calls.max(1) as f64,
+                stats.passes, stats.time.as_secs_f64() * 1000.0)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
 pub struct ParseError {
-    line: usize,
-    col: usize
+    pub line: usize,
+    pub col: usize,
+    pub hint: Option<String>  // e.g. the keyword a misspelt name was probably meant to be //
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ParseError at line {}, column {}", self.line, self.col)?;
+        match &self.hint {
+            Some(hint) => write!(f, ": {}", hint),
+            None => Ok(())
+        }
+    }
 }
 
 type VecStatementNode = Vec<StatementNode>;
@@ -108,10 +279,15 @@ macro_rules! parse_recurse {
 macro_rules! memoise {
     ($raw_func:ident as $out_func:ident -> $ret_type:ident) => {
         fn $out_func(&mut self) -> Option<$ret_type> {
+            const _: () = assert!(has_production(stringify!($out_func)),
+                                  concat!("No production in GRAMMAR for the rule ", stringify!($out_func)));
             let pos = self.mark();
             let key = (pos, String::from(stringify!($raw_func)));
+            let is_hit = self.memo.contains_key(&key);
+            self.profile_call(stringify!($raw_func), is_hit);
             match self.memo.get(&key) {
                 Some((end, result)) => {
+                    log::trace!("Memo hit for {} at token {}", stringify!($raw_func), pos);
                     let end = *end;
                     let result = (*result).clone();
                     if let Parsed::$ret_type(ret) = result {
@@ -120,7 +296,9 @@ macro_rules! memoise {
                     } else {unreachable!()}
                 },
                 None => {
+                    let start = self.profile.as_ref().map(|_| Instant::now());
                     let result = self.$raw_func();
+                    self.profile_time(stringify!($raw_func), start, 1);
                     let new_pos = self.mark();
                     let memo = Parsed::$ret_type(result.clone());
                     self.memo.insert(key, (new_pos, memo));
@@ -134,10 +312,15 @@ macro_rules! memoise {
 macro_rules! memoise_recursive {
     ($raw_func:ident as $out_func:ident -> $ret_type:ident) => {
         fn $out_func(&mut self) -> Option<$ret_type> {
+            const _: () = assert!(has_production(stringify!($out_func)),
+                                  concat!("No production in GRAMMAR for the rule ", stringify!($out_func)));
             let pos = self.mark();
             let key = (pos, String::from(stringify!($raw_func)));
+            let is_hit = self.memo.contains_key(&key);
+            self.profile_call(stringify!($raw_func), is_hit);
             match self.memo.get(&key) {
                 Some((end, result)) => {
+                    log::trace!("Memo hit for {} at token {}", stringify!($raw_func), pos);
                     let end = *end;
                     let result = (*result).clone();
                     if let Parsed::$ret_type(ret) = result {
@@ -146,10 +329,20 @@ macro_rules! memoise_recursive {
                     } else {unreachable!()}
                 },
                 None => {
+                    let start = self.profile.as_ref().map(|_| Instant::now());
+                    let mut passes = 0;
                     let (mut lastres, mut lastpos) = (None, pos);
                     let memo = Parsed::$ret_type(lastres.clone());
                     self.memo.insert(key.clone(), (lastpos, memo));
                     loop {
+                        // Each pass must match more tokens than the last, so can't outnumber them //
+                        passes += 1;
+                        if passes > self.pulled() + 1 {
+                            self.fail_at(pos, format!("Internal parser error: {} kept growing after {} passes",
+                                                      stringify!($raw_func), passes));
+                            self.reset(pos);
+                            return None;
+                        }
                         self.reset(pos);
                         let result = self.$raw_func();
                         let endpos = self.mark();
@@ -159,6 +352,7 @@ macro_rules! memoise_recursive {
                         let memo = Parsed::$ret_type(lastres.clone());
                         self.memo.insert(key.clone(), (lastpos, memo));
                     }
+                    self.profile_time(stringify!($raw_func), start, passes);
                     self.reset(lastpos);
                     return lastres;
                 }
@@ -168,22 +362,155 @@ macro_rules! memoise_recursive {
 }
 
 
-pub fn parse(tokens: Vec<Token>) -> Result<Module, ParseError>{
-    let mut parser = Parser{tokens, token_pos: 0, max_token_pos: 0, memo: HashMap::new()};
-    match parser.module() {
-        Some(module) => Ok(module),
-        None => {
-            let max_token = parser.max_token();
-            Err(ParseError{
-                line: max_token.line,
-                col: max_token.col
-            })
+pub fn parse(source: Tokeniser) -> Result<Module, ParseError>{
+    let mut parser = Parser::new(source);
+    let module = parser.module();
+    match (parser.failure.take(), module) {
+        (Some(failure), _) => Err(failure),
+        (None, Some(module)) => Ok(module),
+        (None, None) => Err(parser.module_error())
+    }
+}
+
+// As parse, also profiling the memoised rules //
+pub fn parse_profiled(source: Tokeniser) -> (Result<Module, ParseError>, ParseProfile) {
+    let mut parser = Parser{profile: Some(ParseProfile::default()), ..Parser::new(source)};
+    let module = parser.module();
+    let result = match parser.failure.take() {
+        Some(failure) => Err(failure),
+        None => module.ok_or_else(|| parser.module_error())
+    };
+    (result, parser.profile.take().unwrap_or_default())
+}
+
+pub fn parse_expression(source: Tokeniser) -> Result<ExpressionNode, ParseError> {
+    let mut parser = Parser::new(source);
+    let expr = parser.expression();
+    if let Some(failure) = parser.failure.take() {
+        return Err(failure);
+    }
+    if let Some(expr) = expr {
+        if parser.expect_type("END_MARKER!").is_some() {
+            return Ok(expr);
+        }
+    }
+    Err(token_error(&parser.max_token()))
+}
+
+// A failure at a token, saying what's wrong with it if it's a character no token starts with //
+fn token_error(token: &Token) -> ParseError {
+    let hint = (token.type_ == "ERROR").then(|| format!("Unexpected character \"{}\"", token.string_));
+    ParseError{line: token.line, col: token.col, hint}
+}
+
+// Keywords a misspelt name in a failing statement is compared against, and words other languages
+// use in their place //
+const KEYWORDS: [&str; 33] = [
+    "fn", "pub", "import", "macro", "pragma", "strict", "if", "else", "while", "for", "parfor", "in", "do", "yield",
+    "catch", "try", "remember", "static_assert", "const", "eval", "expect", "todo", "backtrace", "machine", "state", "on",
+    "print", "println", "call", "reversed", "enumerate", "step", "repeat"
+];
+const KEYWORD_ALIASES: [(&str, &str); 2] = [("function", "fn"), ("func", "fn")];
+
+// The keyword closest to a name, if any is close enough to be a typo of it //
+fn closest_keyword(name: &str) -> Option<&'static str> {
+    if KEYWORDS.contains(&name) {
+        return None;
+    }
+    let limit = (name.chars().count() / 3).max(1);
+    KEYWORDS.iter().map(|&keyword| (keyword, keyword))
+        .chain(KEYWORD_ALIASES.iter().copied())
+        .map(|(spelling, keyword)| (typo_distance(name, spelling), keyword))
+        .filter(|&(distance, _)| distance <= limit)
+        .min()
+        .map(|(_, keyword)| keyword)
+}
+
+// The edit distance between two words, counting swapping adjacent letters as one edit //
+fn typo_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    rows[0] = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitute = rows[i - 1][j - 1] + (a[i - 1] != b[j - 1]) as usize;
+            rows[i][j] = substitute.min(rows[i - 1][j] + 1).min(rows[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                rows[i][j] = rows[i][j].min(rows[i - 2][j - 2] + 1);
+            }
         }
     }
+    rows[a.len()][b.len()]
 }
 
 
-impl Parser {
+impl<'a> Parser<'a> {
+    fn new(source: Tokeniser<'a>) -> Parser<'a> {
+        Parser{
+            source,
+            tokens: VecDeque::new(),
+            released: 0,
+            releases: true,
+            token_pos: 0,
+            max_token_pos: 0,
+            memo: HashMap::new(),
+            profile: None,
+            failure: None
+        }
+    }
+
+
+    fn profile_call(&mut self, rule: &'static str, is_hit: bool) {
+        if let Some(profile) = &mut self.profile {
+            let stats = profile.rules.entry(rule).or_default();
+            stats.calls += 1;
+            stats.hits += is_hit as usize;
+        }
+    }
+
+    fn profile_time(&mut self, rule: &'static str, start: Option<Instant>, passes: usize) {
+        if let (Some(profile), Some(start)) = (&mut self.profile, start) {
+            let stats = profile.rules.entry(rule).or_default();
+            stats.passes += passes;
+            stats.time += start.elapsed();
+        }
+    }
+
+    // Pull tokens from the source only as far as the parser has looked //
+    fn token(&mut self, pos: usize) -> Option<&Token> {
+        let idx = pos.checked_sub(self.released).expect("Internal parser error: going back to a released token");
+        while self.tokens.len() <= idx {
+            match self.source.next() {
+                Some(token) => self.tokens.push_back(token),
+                None => break
+            }
+        }
+        self.tokens.get(idx)
+    }
+
+    // How many tokens have been pulled from the source //
+    fn pulled(&self) -> usize {
+        self.released + self.tokens.len()
+    }
+
+    // Drop the tokens before pos, and what's memoised about them, once the parse can't go back there //
+    fn release(&mut self, pos: usize) {
+        if !self.releases || pos <= self.released {return}
+        self.tokens.drain(..cmp::min(pos - self.released, self.tokens.len()));
+        self.released = pos;
+        self.memo.retain(|(start, _), _| *start >= pos);
+    }
+
+    // Keeps the first fault, at the token at pos, for the parse to report in place of whatever it finds //
+    fn fail_at(&mut self, pos: usize, hint: String) {
+        if self.failure.is_none() {
+            let (line, col) = self.token(pos).map_or((0, 0), |token| (token.line, token.col));
+            self.failure = Some(ParseError{line, col, hint: Some(hint)});
+        }
+    }
 
     fn mark(&self) -> usize {
         self.token_pos
@@ -194,13 +521,56 @@ impl Parser {
         self.max_token_pos = cmp::max(pos, self.max_token_pos);
     }
 
-    fn max_token(&self) -> Token {
-        self.tokens[self.max_token_pos].clone()
+    // Error recovery for a module that failed to parse. A name in the failing statement that's a near
+    // miss for a keyword, e.g. "whlie", is reported as the error if the module parses further with
+    // the keyword in its place //
+    fn module_error(&mut self) -> ParseError {
+        let max_token = self.max_token();
+        if max_token.type_ == "ERROR" {
+            return token_error(&max_token);
+        }
+        let mut start = self.max_token_pos - self.released;
+        while start > 0 && ![";", "{", "}"].contains(&self.tokens[start - 1].string_.as_str()) {
+            start -= 1;
+        }
+        for idx in start..=self.max_token_pos - self.released {
+            let token = self.tokens[idx].clone();
+            if token.type_ != "NAME" {continue}
+            let Some(keyword) = closest_keyword(&token.string_) else {continue};
+
+            // Only the items after those released are parsed again, keeping every token pulled
+            // for the next retry //
+            let mut tokens = self.tokens.clone();
+            tokens[idx].string_ = String::from(keyword);
+            let source = mem::replace(&mut self.source, Tokeniser::new(""));
+            let mut retry = Parser{tokens, releases: false, ..Parser::new(source)};
+            let parsed = match self.released {
+                0 => retry.module().is_some(),
+                _ => {
+                    retry.module_items();
+                    retry.expect_type("END_MARKER!").is_some()
+                }
+            };
+            self.source = mem::replace(&mut retry.source, Tokeniser::new(""));
+            let seen = self.tokens.len();
+            self.tokens.extend(retry.tokens.drain(seen..));
+
+            if parsed || self.released + retry.max_token_pos > self.max_token_pos {
+                return ParseError{line: token.line, col: token.col, hint: Some(format!(
+                    "\"{}\" isn't a keyword, did you mean \"{}\"?", token.string_, keyword))};
+            }
+        }
+        ParseError{line: max_token.line, col: max_token.col, hint: None}
+    }
+
+    fn max_token(&mut self) -> Token {
+        let pos = self.max_token_pos;
+        self.token(pos).expect("Token stream ended early").clone()
     }
 
     fn expect_literal_with_src_position(&mut self, value: &str) -> Option<(usize, usize)> {
         let pos = self.mark();
-        if let Some(tokenref) =  self.tokens.get(pos).as_ref() {
+        if let Some(tokenref) =  self.token(pos) {
             if tokenref.string_ == value {
                 let result = Some((tokenref.line.clone(), tokenref.col.clone()));
                 self.reset(pos + 1);
@@ -216,7 +586,7 @@ impl Parser {
     
     fn expect_type(&mut self, type_: &str) -> Option<Token> {
         let pos = self.mark();
-        if let Some(tokenref) =  self.tokens.get(pos).as_ref() {
+        if let Some(tokenref) =  self.token(pos) {
             if tokenref.type_ == type_ {
                 let result = Some((*tokenref).clone());
                 self.reset(pos + 1);
@@ -227,7 +597,7 @@ impl Parser {
     }
 
     fn repeat<F, R>(&mut self, method: F, allow_empty: bool) -> Option<Vec<R>>
-        where F: Copy + Fn(&mut Parser) -> Option<R>
+        where F: Copy + Fn(&mut Parser<'a>) -> Option<R>
     {
         let pos = self.mark();
         let mut results = Vec::new();
@@ -246,7 +616,7 @@ impl Parser {
     }
 
     fn join<F, R>(&mut self, item_method: F, seperator: &str) -> Vec<R>
-        where F: Copy + Fn(&mut Parser) -> Option<R>
+        where F: Copy + Fn(&mut Parser<'a>) -> Option<R>
     {
         let mut ret = Vec::new();
         match item_method(self) {
@@ -269,19 +639,39 @@ impl Parser {
     
     pub fn module(&mut self) -> Option<Module> {
         parse!(self;
+            pragmas: self.repeat(Parser::pragma, true),
+            imports: self.repeat(Parser::import, true),
             global_stmts: self.repeat(Parser::global_statement, true),
-            functions: self.repeat(Parser::function, false),
+            items: Some(self.module_items()),
             _end: self.expect_type("END_MARKER!"),
             {
+                let (mut functions, macros) = items;
+                if functions.is_empty() && macros.is_empty() {
+                    return None;
+                }
+                let is_strict = pragmas.iter().any(|pragma| pragma == "strict");
+                for function in functions.iter_mut() {
+                    function.is_strict = is_strict;
+                }
                 let global_func = FunctionNode {
+                    line: 0,
+                    col: 0,
                     name: String::from("!global!"),
                     owned_links: Vec::new(),
                     borrow_params: Vec::new(),
                     steal_params: Vec::new(),
                     return_params: Vec::new(),
-                    stmts: global_stmts                 
+                    stmts: global_stmts,
+                    functions: Vec::new(),
+                    const_params: Vec::new(),
+                    const_args: Vec::new(),
+                    specialised: Vec::new(),
+                    attributes: Vec::new(),
+                    is_pub: false,
+                    module: None,
+                    is_strict
                 };
-                return Some(Module{global_func, functions});
+                return Some(Module{imports, global_func, functions, macros});
             }
         );
         None
@@ -291,17 +681,20 @@ impl Parser {
     pub fn function_(&mut self) -> Option<FunctionNode> {
         let pos = self.mark();
 
+        let attributes = self.repeat(Parser::attribute, true).unwrap_or_default();
+        let (line, col) = self.src_position();
+        let is_pub = self.expect_literal("pub");
         if self.expect_literal("fn") {
         if let Some(name) = self.name() {
-        let owned_links = self.links();
+        let (owned_links, const_params) = self.links();
         if self.expect_literal("(") {
-        let borrow_params = self.join(Parser::function_param, ",");
+        let borrow_params = self.join(Parser::borrow_param, ",");
         if self.expect_literal(")") {
         if self.expect_literal("(") {
         let steal_params = self.join(Parser::function_param, ",");
         if self.expect_literal(")") {
         if self.expect_literal("{") {
-        let stmts = self.repeat(Parser::statement, true).unwrap();
+        let (stmts, functions) = self.function_body();
         if self.expect_literal("}") {
         if self.expect_literal("~") {
         if self.name() == Some(name.clone()) {
@@ -309,7 +702,8 @@ impl Parser {
         let return_params = self.join(Parser::function_param, ",");
         if self.expect_literal(")") {
             return Some(FunctionNode{
-                name, owned_links, borrow_params, steal_params, return_params, stmts
+                line, col, name, owned_links, borrow_params, steal_params, return_params, stmts, functions,
+                const_params, const_args: Vec::new(), specialised: Vec::new(), attributes, is_pub, module: None, is_strict: false
             });
         }}}}}}}}}}}};
 
@@ -317,39 +711,226 @@ impl Parser {
         None
     }
 
-    pub fn links(&mut self) -> Vec<String> {
+    // Function and macro definitions, in any order //
+    fn module_items(&mut self) -> (Vec<FunctionNode>, Vec<MacroNode>) {
+        let (mut functions, mut macros) = (Vec::new(), Vec::new());
+        loop {
+            let pos = self.mark();
+            self.release(pos);
+            if let Some(function) = self.function() {
+                functions.push(function);
+            } else if let Some(macro_def) = self.macro_def() {
+                macros.push(macro_def);
+            } else {
+                return (functions, macros);
+            }
+        }
+    }
+
+    pub fn macro_def(&mut self) -> Option<MacroNode> {
+        let (line, col) = self.src_position();
+        parse!(self;
+            "macro",
+            name : self.name(),
+            "(",
+            params : Some(self.join(Parser::name, ",")),
+            ")",
+            "{",
+            stmts : self.repeat(Parser::statement, true),
+            "}",
+            {return Some(MacroNode{line, col, name, params, stmts});}
+        );
+        None
+    }
+
+    // e.g. "#pragma strict", applying to the whole file //
+    pub fn pragma(&mut self) -> Option<String> {
+        parse!(self;
+            "#",
+            "pragma",
+            "strict",
+            {return Some(String::from("strict"));}
+        );
+        None
+    }
+
+    pub fn import(&mut self) -> Option<Import> {
+        let (line, col) = self.src_position();
+        parse!(self;
+            "import",
+            name : self.name(),
+            ";",
+            {return Some(Import{line, col, name});}
+        );
+        None
+    }
+
+    // e.g. "#[inline]" //
+    pub fn attribute(&mut self) -> Option<Attribute> {
+        let (line, col) = self.src_position();
+        parse!(self;
+            "#",
+            "[",
+            name : self.name(),
+            ? args : self.attribute_args(),
+            "]",
+            {
+                let args = args.unwrap_or_default();
+                return Some(Attribute{line, col, name, args});
+            }
+        );
+        None
+    }
+
+    pub fn attribute_args(&mut self) -> Option<Vec<(String, Option<String>)>> {
+        parse!(self;
+            "(",
+            args : Some(self.join(Parser::attribute_arg, ",")),
+            ")",
+            {
+                return Some(args);
+            }
+        );
+        None
+    }
+
+    // e.g. "debug" or "feature = 'x'" //
+    pub fn attribute_arg(&mut self) -> Option<(String, Option<String>)> {
+        let name = self.name()?;
+        let pos = self.mark();
+        if self.expect_literal("=") {
+            if let Some(token) = self.expect_type("STRING") {
+                return Some((name, Some(token.string_)));
+            }
+        }
+        self.reset(pos);
+        Some((name, None))
+    }
+
+    // Statements, interleaved with the definitions of any nested functions //
+    fn function_body(&mut self) -> (Vec<StatementNode>, Vec<FunctionNode>) {
+        let (mut stmts, mut functions) = (Vec::new(), Vec::new());
+        loop {
+            if let Some(stmt) = self.statement() {
+                stmts.push(stmt);
+            } else if let Some(function) = self.function() {
+                functions.push(function);
+            } else {
+                return (stmts, functions);
+            }
+        }
+    }
+
+    // Owned link names, then the names of any const params which make the function generic //
+    pub fn links(&mut self) -> (Vec<String>, Vec<String>) {
         let pos = self.mark();
         if self.expect_literal("<") {
-        let links = self.join(Parser::name, ",");
+        let items = self.join(Parser::link_item, ",");
         if self.expect_literal(">") {
-            return links;
+            let (const_params, links): (Vec<_>, Vec<_>) = items.into_iter().partition(|(is_const, _)| *is_const);
+            return (
+                links.into_iter().map(|(_, name)| name).collect(),
+                const_params.into_iter().map(|(_, name)| name).collect()
+            );
+        }}
+        self.reset(pos);
+        (Vec::new(), Vec::new())
+    }
+
+    // Either a link name, or "const N" //
+    pub fn link_item(&mut self) -> Option<(bool, String)> {
+        let pos = self.mark();
+        let is_const = self.expect_literal("const");
+        if let Some(name) = self.name() {
+            return Some((is_const, name));
+        }
+        self.reset(pos);
+        None
+    }
+
+    // Values for a generic function's const params, e.g. the 4 in "zeros<4>()" //
+    pub fn const_args(&mut self) -> Vec<ConstArg> {
+        let pos = self.mark();
+        if self.expect_literal("<") {
+        let args = self.join(Parser::const_arg, ",");
+        if self.expect_literal(">") {
+            return args;
         }}
         self.reset(pos);
         Vec::new()
     }
 
+    pub fn const_arg(&mut self) -> Option<ConstArg> {
+        if let Some(value) = self.const_literal() {
+            return Some(ConstArg::Value(value));
+        }
+        self.name().map(ConstArg::Name)
+    }
+
+    fn src_position(&mut self) -> (usize, usize) {
+        let pos = self.mark();
+        self.token(pos).map_or((0, 0), |token| (token.line, token.col))
+    }
+
     memoise!(statement_ as statement -> StatementNode);
     pub fn statement_(&mut self) -> Option<StatementNode> {
+        let pos = self.mark();
+        let attributes = self.repeat(Parser::attribute, true).unwrap_or_default();
+        let (line, col) = self.src_position();
+        match self.unpositioned_statement() {
+            Some(stmt) => Some(Box::new(PositionedNode{line, col, stmt, attributes})),
+            None => {self.reset(pos); None}
+        }
+    }
+
+    fn unpositioned_statement(&mut self) -> Option<StatementNode> {
         if let Some(stmt) = self.print_stmt() {return Some(stmt);}
         if let Some(stmt) = self.letunlet_stmt() {return Some(stmt);}
         if let Some(stmt) = self.refunref_stmt() {return Some(stmt);}
         if let Some(stmt) = self.modop_stmt() {return Some(stmt);}
         if let Some(stmt) = self.pull_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.move_stmt() {return Some(stmt);}
         if let Some(stmt) = self.if_stmt() {return Some(stmt);}
         if let Some(stmt) = self.while_stmt() {return Some(stmt);}
         if let Some(stmt) = self.for_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.for_range_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.for_gen_stmt() {return Some(stmt);}
         if let Some(stmt) = self.doyield_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.yield_stmt() {return Some(stmt);}
         if let Some(stmt) = self.catch_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.try_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.remember_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.static_assert_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.const_eval_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.expect_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.todo_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.backtrace_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.machine_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.macro_call_stmt() {return Some(stmt);}
         if let Some(stmt) = self.call_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.destructure_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.destructure_let_stmt() {return Some(stmt);}
         None
     }  
 
     memoise!(global_statement_ as global_statement -> StatementNode);
     pub fn global_statement_(&mut self) -> Option<StatementNode> {
+        let pos = self.mark();
+        let attributes = self.repeat(Parser::attribute, true).unwrap_or_default();
+        let (line, col) = self.src_position();
+        match self.unpositioned_global_statement() {
+            Some(stmt) => Some(Box::new(PositionedNode{line, col, stmt, attributes})),
+            None => {self.reset(pos); None}
+        }
+    }
+
+    fn unpositioned_global_statement(&mut self) -> Option<StatementNode> {
         if let Some(stmt) = self.letunlet_stmt() {return Some(stmt);}
         if let Some(stmt) = self.refunref_stmt() {return Some(stmt);}
         if let Some(stmt) = self.modop_stmt() {return Some(stmt);}
         if let Some(stmt) = self.pull_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.static_assert_stmt() {return Some(stmt);}
+        if let Some(stmt) = self.const_eval_stmt() {return Some(stmt);}
         None
     }  
 
@@ -357,19 +938,83 @@ impl Parser {
     pub fn function_param_(&mut self) -> Option<FunctionParam> {
         let pos = self.mark();
 
-        if self.expect_literal("&") {
-            if let Some(token) = self.expect_type("NAME") {
-                if let Some(name) = self.name() {
-                    let link = Some(token.string_);
-                    return Some(FunctionParam{name, link, is_ref: true});
-                } else {
-                    return Some(FunctionParam{name: token.string_, is_ref: true, link: None});
-                }
+        let (name, link, is_ref) = if self.expect_literal("&") {
+            match (self.expect_type("NAME"), self.name()) {
+                (Some(token), Some(name)) => (name, Some(token.string_), true),
+                (Some(token), None) => (token.string_, None, true),
+                _ => {self.reset(pos); return None}
             }
         } else if let Some(name) = self.name() {
-            return Some(FunctionParam{name, is_ref: false, link: None});
+            (name, None, false)
+        } else {
+            self.reset(pos);
+            return None;
+        };
+
+        let annotation = self.annotation();
+        Some(FunctionParam{name, link, is_ref, annotation, default: None})
+    }
+
+    fn annotation(&mut self) -> Type {
+        let pos = self.mark();
+        if self.expect_literal(":") {
+            if let Some(annotation) = self.type_name() {
+                return annotation;
+            }
+        }
+        self.reset(pos);
+        Type::Unknown
+    }
+
+    pub fn type_name(&mut self) -> Option<Type> {
+        let pos = self.mark();
+        match self.name().as_deref() {
+            Some("num") => return Some(Type::Num),
+            Some("str") => return Some(Type::Str),
+            Some("handle") => return Some(Type::Handle),
+            Some("array") => {
+                let item_pos = self.mark();
+                if self.expect_literal("[") {
+                if let Some(item) = self.type_name() {
+                if self.expect_literal("]") {
+                    return Some(Type::Array(Box::new(item)));
+                }}}
+                self.reset(item_pos);
+                return Some(Type::Array(Box::new(Type::Unknown)));
+            },
+            _ => {}
+        }
+        self.reset(pos);
+        None
+    }
+
+    memoise!(borrow_param_ as borrow_param -> FunctionParam);
+    pub fn borrow_param_(&mut self) -> Option<FunctionParam> {
+        let mut param = self.function_param()?;
+        if !param.is_ref {
+            let pos = self.mark();
+            if self.expect_literal("=") {
+                match self.const_literal() {
+                    Some(value) => param.default = Some(value),
+                    None => self.reset(pos)
+                }
+            }
         }
+        Some(param)
+    }
 
+    pub fn const_literal(&mut self) -> Option<Variable> {
+        let pos = self.mark();
+        let negative = self.expect_literal("-");
+        if let Some(token) = self.expect_type("NUMBER") {
+            let value = Fraction::from_str(&token.string_[..]).unwrap();
+            return Some(Variable::Frac(if negative {-value} else {value}));
+        }
+        if !negative {
+            if let Some(token) = self.expect_type("STRING") {
+                return Some(Variable::Str(token.string_));
+            }
+        }
         self.reset(pos);
         None
     }
@@ -401,7 +1046,8 @@ impl Parser {
 
         let stolen_args = self.stolen_args();
         let is_uncall = self.expect_literal("~");
-        if let Some(name) = self.expect_type("NAME") {
+        if let Some(name) = self.function_name() {
+        let const_args = self.const_args();
         if self.expect_literal("(") {
         let borrow_args = self.join(Parser::lookup, ",");
         if self.expect_literal(")") {
@@ -412,7 +1058,8 @@ impl Parser {
                     name: name.string_,
                     line: name.line,
                     col: name.col,
-                    is_uncall, borrow_args, stolen_args, return_args
+                    is_uncall, borrow_args, stolen_args, return_args, const_args,
+                    bindings: Vec::new()
                 }
             ));
         }}}};
@@ -421,6 +1068,62 @@ impl Parser {
         None
     }
 
+    memoise!(destructure_stmt_ as destructure_stmt -> StatementNode);
+    pub fn destructure_stmt_(&mut self) -> Option<StatementNode> {
+        let pos = self.mark();
+
+        if self.expect_literal("(") {
+        let bindings = self.join(Parser::function_param, ",");
+        if self.expect_literal(")") && self.expect_literal("=") && self.expect_literal("call") {
+        let stolen_args = self.stolen_args();
+        let is_uncall = self.expect_literal("~");
+        if let Some(name) = self.function_name() {
+        let const_args = self.const_args();
+        if self.expect_literal("(") {
+        let borrow_args = self.join(Parser::lookup, ",");
+        if self.expect_literal(")") && self.expect_literal(";") {
+            let return_args = bindings.iter().map(|b| b.name.clone()).collect();
+            return Some(Box::new(
+                CallNode{
+                    name: name.string_,
+                    line: name.line,
+                    col: name.col,
+                    is_uncall, borrow_args, stolen_args, return_args, bindings, const_args
+                }
+            ));
+        }}}}};
+
+        self.reset(pos);
+        None
+    }
+
+
+    memoise!(destructure_let_stmt_ as destructure_let_stmt -> StatementNode);
+    pub fn destructure_let_stmt_(&mut self) -> Option<StatementNode> {
+        let pos = self.mark();
+
+        if let Some((line, col)) = self.expect_literal_with_src_position("[") {
+        let pattern = self.join(Parser::pattern_item, ",");
+        if self.expect_literal("]") {
+        let is_unlet = self.expect_literal("~=");
+        if is_unlet || self.expect_literal("=") {
+        if let Some(name) = self.name() {
+        if self.expect_literal(";") {
+            return Some(Box::new(
+                DestructureLetNode{line, col, is_unlet, pattern, name}
+            ));
+        }}}}};
+
+        self.reset(pos);
+        None
+    }
+
+    // Either "x", or "x..." to collect the rest of the array //
+    pub fn pattern_item(&mut self) -> Option<(String, bool)> {
+        let name = self.name()?;
+        let is_rest = self.expect_literal("...");
+        Some((name, is_rest))
+    }
 
     memoise!(catch_stmt_ as catch_stmt -> StatementNode);
     pub fn catch_stmt_(&mut self) -> Option<StatementNode> {
@@ -440,6 +1143,55 @@ impl Parser {
         None
     }
 
+    memoise!(try_stmt_ as try_stmt -> StatementNode);
+    pub fn try_stmt_(&mut self) -> Option<StatementNode> {
+        parse!(self;
+            "try",
+            "{",
+            body : self.repeat(Parser::statement, true),
+            "}",
+            "catch",
+            "(",
+            cond : self.expression(),
+            ")",
+            "{",
+            handler : self.repeat(Parser::statement, true),
+            "}",
+            {
+                let bkwd_cond = self.catch_bkwd_cond();
+                let bkwd_elided = bkwd_cond.is_none();
+                let bkwd_cond = bkwd_cond.unwrap_or_else(|| cond.clone());
+                return Some(Box::new(
+                    TryNode{body, cond, handler, bkwd_cond, bkwd_elided}
+                ));
+            }
+        );
+        None
+    }
+
+    // The "~catch (cond);" after a try's handler, telling when running backwards whether it ran //
+    fn catch_bkwd_cond(&mut self) -> Option<ExpressionNode> {
+        parse!(self;
+            "~", "catch", "(",
+            cond : self.expression(),
+            ")", ";",
+            {return Some(cond);}
+        );
+        None
+    }
+
+    pub fn remember_stmt(&mut self) -> Option<StatementNode> {
+        let (line, col) = self.src_position();
+        parse!(self;
+            "remember",
+            "{",
+            body : self.repeat(Parser::statement, true),
+            "}",
+            {return Some(Box::new(RememberNode{line, col, body}));}
+        );
+        None
+    }
+
     memoise!(doyield_stmt_ as doyield_stmt -> StatementNode);
     pub fn doyield_stmt_(&mut self) -> Option<StatementNode> {
         parse!(self;
@@ -460,6 +1212,16 @@ impl Parser {
         None
     }
     
+    pub fn yield_stmt(&mut self) -> Option<StatementNode> {
+        parse!(self;
+            "yield",
+            expr : self.expression(),
+            ";",
+            {return Some(Box::new(YieldNode{expr}));}
+        );
+        None
+    }
+
     memoise!(yield_block_ as yield_block -> VecStatementNode);
     pub fn yield_block_(&mut self) -> Option<Vec<StatementNode>> {
         let pos = self.mark();
@@ -477,12 +1239,75 @@ impl Parser {
 
     memoise!(for_stmt_ as for_stmt -> StatementNode);
     pub fn for_stmt_(&mut self) -> Option<StatementNode> {
+        parse!(self;
+            parallel : self.for_keyword(),
+            "(",
+            target : self.for_target(),
+            "in",
+            adaptor : self.for_iterator(),
+            ")",
+            "{",
+            stmts : self.repeat(Parser::statement, true),
+            "}",
+            ";",
+            {
+                let ((index_var, iter_var), (iterator, reversed, enumerated)) = (target, adaptor);
+                if index_var.is_some() == enumerated {
+                    return Some(Box::new(
+                        ForNode{iter_var, index_var, iterator, reversed, parallel, stmts}
+                    ));
+                }
+            }
+        );
+        None
+    }
+
+    // "parfor" marks a loop whose iterations are independent //
+    pub fn for_keyword(&mut self) -> Option<bool> {
+        if self.expect_literal("for") {return Some(false)}
+        if self.expect_literal("parfor") {return Some(true)}
+        None
+    }
+
+    // Either "x", or "(i, x)" to receive indices from enumerate //
+    pub fn for_target(&mut self) -> Option<(Option<String>, String)> {
+        parse!(self;
+            "(",
+            index_var : self.name(),
+            ",",
+            iter_var : self.name(),
+            ")",
+            {return Some((Some(index_var), iter_var));}
+        );
+        self.name().map(|iter_var| (None, iter_var))
+    }
+
+    // An array lookup, optionally wrapped in reversed(...) or enumerate(...) //
+    pub fn for_iterator(&mut self) -> Option<(LookupNode, bool, bool)> {
+        for (adaptor, reversed, enumerated) in [("reversed", true, false), ("enumerate", false, true)] {
+            parse!(self;
+                adaptor,
+                "(",
+                iterator : self.lookup(),
+                ")",
+                {return Some((iterator, reversed, enumerated));}
+            );
+        }
+        self.lookup().map(|iterator| (iterator, false, false))
+    }
+
+    memoise!(for_range_stmt_ as for_range_stmt -> StatementNode);
+    pub fn for_range_stmt_(&mut self) -> Option<StatementNode> {
+        let (line, col) = self.src_position();
         parse!(self;
             "for",
             "(",
             iter_var : self.name(),
             "in",
-            iterator : self.lookup(),
+            start : self.expression(),
+            "..",
+            end : self.expression(),
+            ? step : self.range_step(),
             ")",
             "{",
             stmts : self.repeat(Parser::statement, true),
@@ -490,13 +1315,53 @@ impl Parser {
             ";",
             {
                 return Some(Box::new(
-                    ForNode{iter_var, iterator, stmts}
+                    ForRangeNode{line, col, iter_var, start, end, step, stmts}
                 ));
             }
         );
         None
     }
 
+    // A loop over a generator call, e.g. "for (x in evens(n)) {...};". The iterator adaptors are
+    // left to for_stmt, so misusing them isn't mistaken for a call //
+    memoise!(for_gen_stmt_ as for_gen_stmt -> StatementNode);
+    pub fn for_gen_stmt_(&mut self) -> Option<StatementNode> {
+        parse!(self;
+            "for",
+            "(",
+            iter_var : self.name(),
+            "in",
+            name : self.function_name(),
+            "(",
+            ? borrow_args : self.join(Parser::lookup, ","),
+            ")",
+            ")",
+            "{",
+            stmts : self.repeat(Parser::statement, true),
+            "}",
+            ";",
+            {
+                if name.string_ != "reversed" && name.string_ != "enumerate" {
+                    return Some(Box::new(ForGenNode{
+                        line: name.line, col: name.col, iter_var, name: name.string_, borrow_args, stmts
+                    }));
+                }
+            }
+        );
+        None
+    }
+
+    pub fn range_step(&mut self) -> Option<ExpressionNode> {
+        let pos = self.mark();
+        if self.expect_literal("step") {
+            if let Some(step) = self.expression() {
+                return Some(step);
+            }
+        }
+        self.reset(pos);
+        None
+    }
+
     memoise!(while_stmt_ as while_stmt -> StatementNode);
     pub fn while_stmt_(&mut self) -> Option<StatementNode> {
         parse!(self;
@@ -544,12 +1409,13 @@ impl Parser {
                     Some(stmts) => stmts,
                     None => Vec::new()
                 };
+                let bkwd_elided = bkwd_expr.is_none();
                 let bkwd_expr = match bkwd_expr {
                     Some(expr) => expr,
                     None => fwd_expr.clone()
                 };
                 return Some(Box::new(
-                    IfNode{fwd_expr, if_stmts, else_stmts, bkwd_expr}
+                    IfNode{fwd_expr, if_stmts, else_stmts, bkwd_expr, bkwd_elided}
                 ));
             }
         );
@@ -598,6 +1464,169 @@ impl Parser {
         None
     }
 
+    // "<-" isn't a token, so that "a<-1" still compares a with -1 //
+    memoise!(move_stmt_ as move_stmt -> StatementNode);
+    pub fn move_stmt_(&mut self) -> Option<StatementNode> {
+        parse!(self;
+            named : self.name_with_src_position(),
+            "<",
+            "-",
+            src : self.name(),
+            ";",
+            {
+                let (name, (line, col)) = named;
+                return Some(Box::new(MoveNode{name, src, line, col}));
+            }
+        );
+        None
+    }
+
+    memoise!(static_assert_stmt_ as static_assert_stmt -> StatementNode);
+    pub fn static_assert_stmt_(&mut self) -> Option<StatementNode> {
+        let (line, col) = self.src_position();
+        parse!(self;
+            "static_assert",
+            "(",
+            expr : self.expression(),
+            ",",
+            message : self.expect_type("STRING"),
+            ")",
+            ";",
+            {
+                return Some(Box::new(
+                    StaticAssertNode{line, col, expr, message: message.string_}
+                ));
+            }
+        );
+        None
+    }
+
+    memoise!(const_eval_stmt_ as const_eval_stmt -> StatementNode);
+    pub fn const_eval_stmt_(&mut self) -> Option<StatementNode> {
+        let (line, col) = self.src_position();
+        parse!(self;
+            "const",
+            name : self.name(),
+            "=",
+            "eval",
+            func_name : self.function_name(),
+            "(",
+            ? args : self.join(Parser::const_literal, ","),
+            ")",
+            ";",
+            {
+                return Some(Box::new(
+                    ConstEvalNode{line, col, name, func_name: func_name.string_, args, value: None}
+                ));
+            }
+        );
+        None
+    }
+
+    memoise!(expect_stmt_ as expect_stmt -> StatementNode);
+    pub fn expect_stmt_(&mut self) -> Option<StatementNode> {
+        let (line, col) = self.src_position();
+        parse!(self;
+            "expect",
+            actual : self.expr3(),
+            "==",
+            expected : self.expr3(),
+            ";",
+            {
+                return Some(Box::new(
+                    ExpectNode{line, col, actual, expected: Some(expected)}
+                ));
+            }
+        );
+        parse!(self;
+            "expect",
+            actual : self.expression(),
+            ";",
+            {
+                return Some(Box::new(
+                    ExpectNode{line, col, actual, expected: None}
+                ));
+            }
+        );
+        None
+    }
+
+    pub fn todo_stmt(&mut self) -> Option<StatementNode> {
+        parse!(self;
+            "todo",
+            ";",
+            {return Some(Box::new(TodoNode));}
+        );
+        None
+    }
+
+    pub fn backtrace_stmt(&mut self) -> Option<StatementNode> {
+        parse!(self;
+            "backtrace",
+            "(",
+            ")",
+            ";",
+            {return Some(Box::new(BacktraceNode));}
+        );
+        None
+    }
+
+    pub fn machine_stmt(&mut self) -> Option<StatementNode> {
+        let (line, col) = self.src_position();
+        parse!(self;
+            "machine",
+            name : self.name(),
+            "{",
+            states : self.repeat(Parser::machine_state, false),
+            "}",
+            {return Some(Box::new(MachineNode{line, col, name, states}));}
+        );
+        None
+    }
+
+    pub fn macro_call_stmt(&mut self) -> Option<StatementNode> {
+        parse!(self;
+            name : self.function_name(),
+            "!",
+            "(",
+            args : Some(self.join(Parser::lookup, ",")),
+            ")",
+            ";",
+            {
+                let Token{string_: name, line, col, ..} = name;
+                return Some(Box::new(MacroCallNode{line, col, name, args}));
+            }
+        );
+        None
+    }
+
+    pub fn machine_state(&mut self) -> Option<MachineState> {
+        let (line, col) = self.src_position();
+        parse!(self;
+            "state",
+            name : self.name(),
+            "{",
+            stmts : self.repeat(Parser::statement, true),
+            transitions : self.repeat(Parser::transition, true),
+            "}",
+            {return Some(MachineState{line, col, name, stmts, transitions});}
+        );
+        None
+    }
+
+    pub fn transition(&mut self) -> Option<Transition> {
+        let (line, col) = self.src_position();
+        parse!(self;
+            "on",
+            cond : self.expression(),
+            "->",
+            target : self.name(),
+            ";",
+            {return Some(Transition{line, col, cond, target});}
+        );
+        None
+    }
+
     memoise!(print_stmt_ as print_stmt -> StatementNode);
     pub fn print_stmt_(&mut self) -> Option<StatementNode> {
         let pos = self.mark();
@@ -652,9 +1681,10 @@ impl Parser {
         if self.expect_literal("=") {
         if self.expect_literal("&") {
         if let Some(rhs) = self.lookup() {
+        let range = self.slice();
         if self.expect_literal(";") {
             return Some(Box::new(
-                RefUnrefNode{name, rhs, line, col, is_unref: false}
+                RefUnrefNode{name, rhs, range, line, col, is_unref: false}
             ));
         }}}}};
         self.reset(pos);
@@ -663,13 +1693,42 @@ impl Parser {
         if self.expect_literal("~=") {
         if self.expect_literal("&") {
         if let Some(rhs) = self.lookup() {
+        let range = self.slice();
         if self.expect_literal(";") {
             return Some(Box::new(
-                RefUnrefNode{name, rhs, line, col, is_unref: true}
+                RefUnrefNode{name, rhs, range, line, col, is_unref: true}
             ));
         }}}}};
         self.reset(pos);
 
+        parse!(self;
+            named : self.name_with_src_position(),
+            "~=",
+            "&",
+            old : self.lookup(),
+            "=",
+            "&",
+            new : self.lookup(),
+            ";",
+            {
+                let (name, (line, col)) = named;
+                return Some(Box::new(RelinkNode{name, old, new, line, col}));
+            }
+        );
+
+        None
+    }
+
+    // The elements start to end of an array, e.g. "[2:5]" in "r = &a[2:5];" //
+    pub fn slice(&mut self) -> Option<(ExpressionNode, ExpressionNode)> {
+        parse!(self;
+            "[",
+            start : self.expression(),
+            ":",
+            end : self.expression(),
+            "]",
+            {return Some((start, end));}
+        );
         None
     }
 
@@ -857,6 +1916,33 @@ impl Parser {
             return Some(Box::new(array));
         };
 
+        if let Some(array) = self.array_comprehension() {
+            return Some(array);
+        };
+
+        // Builtins look like calls, but take any expression //
+        for (builtin, op) in BUILTINS {
+            if let Some((line, col)) = self.expect_literal_with_src_position(builtin) {
+            if self.expect_literal("(") {
+            if let Some(expr) = self.expression() {
+            if self.expect_literal(")") {
+                return Some(Box::new(UniopNode{expr, line, col, op}));
+            }}}};
+            self.reset(pos);
+        }
+
+        for (counter, op) in COUNTERS {
+            if let Some((line, col)) = self.expect_literal_with_src_position(counter) {
+            if self.expect_literal("(") && self.expect_literal(")") {
+                return Some(Box::new(CounterNode{line, col, op}));
+            }};
+            self.reset(pos);
+        }
+
+        if let Some(call) = self.call_expr() {
+            return Some(Box::new(call));
+        };
+
         if let Some(lookup) = self.lookup() {
             return Some(Box::new(lookup));
         };
@@ -871,6 +1957,10 @@ impl Parser {
             ));
         };
 
+        if let Some((line, col)) = self.expect_literal_with_src_position("???") {
+            return Some(Box::new(HoleNode{line, col}));
+        };
+
         if let Some(token) = self.expect_type("STRING") {
             return Some(Box::new(
                 StringNode{
@@ -938,6 +2028,51 @@ impl Parser {
         None
     }
 
+    memoise!(array_comprehension_ as array_comprehension -> ExpressionNode);
+    pub fn array_comprehension_(&mut self) -> Option<ExpressionNode> {
+        let (line, col) = self.src_position();
+        parse!(self;
+            "[",
+            item : self.expression(),
+            "for",
+            iter_var : self.name(),
+            "in",
+            source : self.lookup(),
+            ? filter : self.comprehension_filter(),
+            "]",
+            {
+                return Some(Box::new(
+                    ComprehensionNode{line, col, item, iter_var, source, filter}
+                ));
+            }
+        );
+        None
+    }
+
+    // A call to a pure function, e.g. "norm(v)" //
+    pub fn call_expr(&mut self) -> Option<CallExprNode> {
+        let pos = self.mark();
+        if let Some(Token{string_: name, line, col, ..}) = self.function_name() {
+        if self.expect_literal("(") {
+        let borrow_args = self.join(Parser::lookup, ",");
+        if self.expect_literal(")") {
+            return Some(CallExprNode{line, col, name, borrow_args});
+        }}}
+        self.reset(pos);
+        None
+    }
+
+    pub fn comprehension_filter(&mut self) -> Option<ExpressionNode> {
+        let pos = self.mark();
+        if self.expect_literal("if") {
+            if let Some(filter) = self.expression() {
+                return Some(filter);
+            }
+        }
+        self.reset(pos);
+        None
+    }
+
 
     memoise!(modop_ as modop -> Instruction);
     pub fn modop_(&mut self) -> Option<Instruction> {
@@ -976,6 +2111,21 @@ impl Parser {
         None
     }
 
+    // A function name, qualified by the module it was imported from, e.g. "std::rle_encode" //
+    pub fn function_name(&mut self) -> Option<Token> {
+        let mut name = self.expect_type("NAME")?;
+        loop {
+            let pos = self.mark();
+            if self.expect_literal(":") && self.expect_literal(":") {
+            if let Some(part) = self.expect_type("NAME") {
+                name.string_ = format!("{}::{}", name.string_, part.string_);
+                continue;
+            }};
+            self.reset(pos);
+            return Some(name);
+        }
+    }
+
     memoise!(name_ as name -> String);
     pub fn name_(&mut self) -> Option<String> {
         self.name_with_src_position().map(|x| x.0)
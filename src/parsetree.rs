@@ -1,19 +1,32 @@
 
 use std::fmt;
 
+use crate::cfg;
+use crate::formatter;
 use crate::interpreter;
+use crate::macros;
 use crate::syntaxchecker;
 use crate::syntaxtree as ST;
 
 
 
-pub trait Expression: fmt::Debug + ExpressionClone {
+pub trait Expression: fmt::Debug + ExpressionClone + formatter::Source + macros::Substitute {
 
     fn to_syntax_node(self: Box<Self>,  ctx: &mut syntaxchecker::SyntaxContext) 
         -> Result<Box<dyn ST::Expression>, syntaxchecker::SyntaxError>;
 
     fn get_src_pos(&self) 
         -> (usize, usize);
+
+    // The value of a literal index, for telling statically which elements a reference covers //
+    fn constant_index(&self) -> Option<usize> {
+        None
+    }
+
+    // The value of a literal, for telling which variables are bound to constants //
+    fn constant_value(&self) -> Option<interpreter::Variable> {
+        None
+    }
 }
 
 pub type ExpressionNode = Box<dyn Expression>;
@@ -53,6 +66,21 @@ pub struct StringNode {
     pub value: String
 }
 
+// "???", standing in for an expression that hasn't been written yet //
+#[derive(Clone, Debug)]
+pub struct HoleNode {
+    pub line: usize,
+    pub col: usize
+}
+
+// "steps()" or "clock_ms()", reading one of the interpreter's counters //
+#[derive(Clone, Debug)]
+pub struct CounterNode {
+    pub line: usize,
+    pub col: usize,
+    pub op: interpreter::Instruction
+}
+
 #[derive(Clone, Debug)]
 pub struct ArrayLiteralNode {
     pub line: usize,
@@ -68,6 +96,25 @@ pub struct ArrayRepeatNode {
     pub dimensions: ExpressionNode
 }
 
+#[derive(Clone, Debug)]
+pub struct ComprehensionNode {
+    pub line: usize,
+    pub col: usize,
+    pub item: ExpressionNode,
+    pub iter_var: String,
+    pub source: LookupNode,
+    pub filter: Option<ExpressionNode>
+}
+
+// A call to a pure function, in expression position //
+#[derive(Clone, Debug)]
+pub struct CallExprNode {
+    pub line: usize,
+    pub col: usize,
+    pub name: String,
+    pub borrow_args: Vec<LookupNode>
+}
+
 #[derive(Clone, Debug)]
 pub struct LookupNode {
     pub line: usize,
@@ -92,11 +139,44 @@ pub struct UniopNode {
 }
 
 
-pub trait Statement: fmt::Debug + StatementClone {
+pub trait Statement: fmt::Debug + StatementClone + formatter::Source + macros::Substitute {
     fn to_syntax_node(
         self: Box<Self>,
         ctx: &mut syntaxchecker::SyntaxContext
     ) -> Result<Box<dyn ST::Statement>, syntaxchecker::SyntaxError>;
+
+    // Every call made by this statement and its substatements, for monomorphisation //
+    fn calls(&self) -> Vec<&CallNode> {
+        Vec::new()
+    }
+
+    // Drop any substatements disabled by #[cfg] attributes //
+    fn configure(&mut self, _cfg: &cfg::Cfg) -> Result<(), syntaxchecker::SyntaxError> {
+        Ok(())
+    }
+
+    fn take_attributes(&mut self) -> Vec<Attribute> {
+        Vec::new()
+    }
+
+    // The statement lists directly inside this statement, for passes that rewrite them //
+    fn substatements(&mut self) -> Vec<&mut Vec<StatementNode>> {
+        Vec::new()
+    }
+
+    // The plain statements this one is sugar for, if it is sugar //
+    fn desugar(&self) -> Option<Result<Vec<StatementNode>, syntaxchecker::SyntaxError>> {
+        None
+    }
+
+    fn macro_call(&self) -> Option<&MacroCallNode> {
+        None
+    }
+
+    // The const eval this statement is, for filling in the value it evaluates to //
+    fn const_eval(&mut self) -> Option<&mut ConstEvalNode> {
+        None
+    }
 }
 
 pub type StatementNode = Box<dyn Statement>;
@@ -116,6 +196,15 @@ impl Clone for StatementNode {
     }
 }
 
+// Records where a statement starts in the source //
+#[derive(Clone, Debug)]
+pub struct PositionedNode {
+    pub line: usize,
+    pub col: usize,
+    pub stmt: StatementNode,
+    pub attributes: Vec<Attribute>
+}
+
 #[derive(Clone, Debug)]
 pub struct PrintNode {
     pub items: Vec<ExpressionNode>,
@@ -137,7 +226,27 @@ pub struct RefUnrefNode {
     pub col: usize,
     pub is_unref: bool,
     pub name: String,
-    pub rhs: LookupNode
+    pub rhs: LookupNode,
+    pub range: Option<(ExpressionNode, ExpressionNode)>  // For a reference to elements start to end //
+}
+
+// r ~= &a[i] = &a[j]; moves a reference to another part of the same variable //
+#[derive(Clone, Debug)]
+pub struct RelinkNode {
+    pub line: usize,
+    pub col: usize,
+    pub name: String,
+    pub old: LookupNode,
+    pub new: LookupNode
+}
+
+// b <- a; moves the value of a into a new variable b, uninitialising a //
+#[derive(Clone, Debug)]
+pub struct MoveNode {
+    pub line: usize,
+    pub col: usize,
+    pub name: String,
+    pub src: String
 }
 
 #[derive(Clone, Debug)]
@@ -147,6 +256,15 @@ pub struct ModopNode {
     pub rhs: ExpressionNode
 }
 
+#[derive(Clone, Debug)]
+pub struct DestructureLetNode {
+    pub line: usize,
+    pub col: usize,
+    pub is_unlet: bool,
+    pub pattern: Vec<(String, bool)>,  // Names, flagged if they collect the rest //
+    pub name: String
+}
+
 #[derive(Clone, Debug)]
 pub struct PushPullNode {
     pub line: usize,
@@ -161,7 +279,8 @@ pub struct IfNode {
     pub fwd_expr: ExpressionNode,
     pub if_stmts: Vec<StatementNode>,
     pub else_stmts: Vec<StatementNode>,
-    pub bkwd_expr: ExpressionNode
+    pub bkwd_expr: ExpressionNode,
+    pub bkwd_elided: bool  // Written "~if()", so bkwd_expr is a copy of fwd_expr //
 }
 
 #[derive(Clone, Debug)]
@@ -174,10 +293,41 @@ pub struct WhileNode {
 #[derive(Clone, Debug)]
 pub struct ForNode {
     pub iter_var: String,
+    pub index_var: Option<String>,  // Bound by enumerate //
     pub iterator: LookupNode,
+    pub reversed: bool,
+    pub parallel: bool,  // A parfor, whose iterations are independent //
     pub stmts: Vec<StatementNode>
 }
 
+#[derive(Clone, Debug)]
+pub struct ForRangeNode {
+    pub line: usize,
+    pub col: usize,
+    pub iter_var: String,
+    pub start: ExpressionNode,
+    pub end: ExpressionNode,
+    pub step: Option<ExpressionNode>,
+    pub stmts: Vec<StatementNode>
+}
+
+// A for loop over the values a #[generator] yields, e.g. "for (x in evens(n)) {...};" //
+#[derive(Clone, Debug)]
+pub struct ForGenNode {
+    pub line: usize,
+    pub col: usize,
+    pub iter_var: String,
+    pub name: String,
+    pub borrow_args: Vec<LookupNode>,
+    pub stmts: Vec<StatementNode>
+}
+
+// "yield x;", handing a value to the for loop running the #[generator] //
+#[derive(Clone, Debug)]
+pub struct YieldNode {
+    pub expr: ExpressionNode
+}
+
 #[derive(Clone, Debug)]
 pub struct DoYieldNode {
     pub do_stmts: Vec<StatementNode>,
@@ -190,6 +340,99 @@ pub struct CatchNode {
     pub expr: ExpressionNode
 }
 
+#[derive(Clone, Debug)]
+pub struct TryNode {
+    pub body: Vec<StatementNode>,
+    pub cond: ExpressionNode,
+    pub handler: Vec<StatementNode>,
+    pub bkwd_cond: ExpressionNode,  // Whether the handler ran, checked running backwards //
+    pub bkwd_elided: bool  // No "~catch (...);" was written, so bkwd_cond is a copy of cond //
+}
+
+// A block whose pure calls go through the interpreter's memo table. Running it backwards releases
+// the entries it used, so undoing the block leaves the table exactly as before //
+#[derive(Clone, Debug)]
+pub struct RememberNode {
+    pub line: usize,
+    pub col: usize,
+    pub body: Vec<StatementNode>
+}
+
+// A state machine, starting in its first state and running until no transition from the current
+// state fires. Expanded into a loop before checking //
+#[derive(Clone, Debug)]
+pub struct MachineNode {
+    pub line: usize,
+    pub col: usize,
+    pub name: String,
+    pub states: Vec<MachineState>
+}
+
+#[derive(Clone, Debug)]
+pub struct MachineState {
+    pub line: usize,
+    pub col: usize,
+    pub name: String,
+    pub stmts: Vec<StatementNode>,
+    pub transitions: Vec<Transition>
+}
+
+// "on cond -> target;", taken if it's the first transition of its state whose condition holds //
+#[derive(Clone, Debug)]
+pub struct Transition {
+    pub line: usize,
+    pub col: usize,
+    pub cond: ExpressionNode,
+    pub target: String
+}
+
+// e.g. "swapadd!(x, y[i]);", replaced by the body of the macro with its parameters substituted //
+#[derive(Clone, Debug)]
+pub struct MacroCallNode {
+    pub line: usize,
+    pub col: usize,
+    pub name: String,
+    pub args: Vec<LookupNode>
+}
+
+// "todo;", standing in for statements that haven't been written yet //
+#[derive(Clone, Debug)]
+pub struct TodoNode;
+
+// "backtrace();", printing the call stack when run forwards //
+#[derive(Clone, Debug)]
+pub struct BacktraceNode;
+
+#[derive(Clone, Debug)]
+pub struct StaticAssertNode {
+    pub line: usize,
+    pub col: usize,
+    pub expr: ExpressionNode,
+    pub message: String
+}
+
+// "expect x == 5;", checked when run forwards, and again when undone, as the state must then be the
+// same. An equality has its sides kept apart, to report what was expected and what was found //
+#[derive(Clone, Debug)]
+pub struct ExpectNode {
+    pub line: usize,
+    pub col: usize,
+    pub actual: ExpressionNode,
+    pub expected: Option<ExpressionNode>
+}
+
+// "const x = eval f(20);", binding x to what the pure function f returns, as evaluated when the
+// program is compiled. The value is None until then //
+#[derive(Clone, Debug)]
+pub struct ConstEvalNode {
+    pub line: usize,
+    pub col: usize,
+    pub name: String,
+    pub func_name: String,
+    pub args: Vec<interpreter::Variable>,
+    pub value: Option<interpreter::Variable>
+}
+
 #[derive(Clone, Debug)]
 pub struct CallNode {
     pub is_uncall: bool,
@@ -198,28 +441,95 @@ pub struct CallNode {
     pub name: String,
     pub borrow_args: Vec<LookupNode>,
     pub stolen_args: Vec<String>,
-    pub return_args: Vec<String>
+    pub return_args: Vec<String>,
+    pub bindings: Vec<FunctionParam>,  // Empty unless destructured //
+    pub const_args: Vec<ConstArg>  // Empty unless calling a generic function //
+}
+
+#[derive(Clone, Debug)]
+pub enum ConstArg {
+    Value(interpreter::Variable),
+    Name(String)  // A const param of the calling function //
 }
 
 #[derive(Clone, Debug)]
 pub struct FunctionParam {
     pub name: String,
     pub is_ref: bool,
-    pub link: Option<String>
+    pub link: Option<String>,
+    pub annotation: ST::Type,
+    pub default: Option<interpreter::Variable>
 }
 
 #[derive(Clone, Debug)]
 pub struct FunctionNode {
+    pub line: usize,
+    pub col: usize,
     pub name: String,
     pub owned_links: Vec<String>,
     pub borrow_params: Vec<FunctionParam>,
     pub steal_params: Vec<FunctionParam>,
     pub return_params: Vec<FunctionParam>,
+    pub stmts: Vec<StatementNode>,
+    pub functions: Vec<FunctionNode>,  // Nested definitions, hoisted to the top level by the checker //
+    pub const_params: Vec<String>,
+    pub const_args: Vec<interpreter::Variable>,  // Bound when a generic function is monomorphised //
+    pub specialised: Vec<(String, interpreter::Variable)>,  // Borrow params every call passes the same constant //
+    pub attributes: Vec<Attribute>,
+    pub is_pub: bool,  // Callable from other modules //
+    pub module: Option<String>,  // The imported module defining it, or None for the importer's own //
+    pub is_strict: bool  // Checked in strict mode, under --strict or its file's #pragma strict //
+}
+
+// An annotation before "fn" or a statement, e.g. #[pure] or #[cfg(feature = 'x')] //
+#[derive(Clone, Debug)]
+pub struct Attribute {
+    pub line: usize,
+    pub col: usize,
+    pub name: String,
+    pub args: Vec<(String, Option<String>)>  // Each a name, with an optional string value //
+}
+
+// e.g. "macro swapadd(@a, @b) {...}", a statement template. Each parameter stands for a variable
+// or array element of the caller's, and other variables bound in the body are private to each use //
+#[derive(Clone, Debug)]
+pub struct MacroNode {
+    pub line: usize,
+    pub col: usize,
+    pub name: String,
+    pub params: Vec<String>,
     pub stmts: Vec<StatementNode>
 }
 
+// e.g. "import std;", making the module's functions callable as "std::name" //
+#[derive(Clone, Debug)]
+pub struct Import {
+    pub line: usize,
+    pub col: usize,
+    pub name: String
+}
+
 #[derive(Clone, Debug)]
 pub struct Module {
+    pub imports: Vec<Import>,
     pub global_func: FunctionNode,
-    pub functions: Vec<FunctionNode>
-}
\ No newline at end of file
+    pub functions: Vec<FunctionNode>,
+    pub macros: Vec<MacroNode>
+}
+
+impl Module {
+    // The tree one field per line with the source positions left out, so that parses of the same
+    // program laid out differently compare equal //
+    pub fn structure(&self) -> Vec<String> {
+        format!("{:#?}", self).lines()
+            .filter(|field| !field.trim_start().starts_with("line: ") && !field.trim_start().starts_with("col: "))
+            .map(String::from)
+            .collect()
+    }
+}
+
+impl PartialEq for Module {
+    fn eq(&self, other: &Module) -> bool {
+        self.structure() == other.structure()
+    }
+}
@@ -0,0 +1,307 @@
+use std::env;
+use std::io::{self, IsTerminal};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+use crate::cfg::Cfg;
+use crate::diff::{self, Snapshot};
+use crate::interpreter::{Interpreter, Module, Region, ReloadError, RuntimeError, Session};
+use crate::minimise::panic_message;
+use crate::options::EngineOptions;
+use crate::parser;
+use crate::syntaxchecker::{check_syntax, SyntaxError};
+use crate::syntaxtree as ST;
+use crate::tokeniser::Tokeniser;
+
+
+// Entries are accumulated into the source of a program whose main function holds every
+// statement entered so far. Each new statement is checked in the context of all earlier ones,
+// then only its own code is run against the registers left by earlier statements. Moving back
+// runs the backward code of whole statements, and entering a statement while in the past
+// discards the statements after it //
+#[derive(Default)]
+pub struct Repl {
+    functions: Vec<String>,  // One entry each, so a function entered again replaces its definition
+    stmts: Vec<String>,
+    step: usize,
+    module: Option<Module>,
+    session: Session,
+    cfg: Cfg,
+    options: EngineOptions
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Default::default()
+    }
+
+    fn source(stmts: &[String], functions: &[String]) -> String {
+        format!("fn main()() {{\n{}}} ~main()\n\n{}", stmts.concat(), functions.concat())
+    }
+
+    pub fn submit(&mut self, entry: &str) {
+        let is_function = first_token(entry).as_deref() == Some("fn");
+        if is_function {
+            let name = function_name(entry);
+            let redefined = self.functions.iter().position(|function| name.is_some() && function_name(function) == name);
+            let mut functions = self.functions.clone();
+            match redefined {
+                Some(idx) => functions[idx] = format!("{}\n", entry),
+                None => functions.push(format!("{}\n", entry))
+            }
+            let Some(checked) = report(check(&Repl::source(&self.stmts, &functions), &self.cfg)) else {return};
+            self.functions = functions;
+            // A redefinition is swapped into the running module, unless its callers need recompiling too //
+            if let (Some(name), Some(_), Some(module)) = (&name, redefined, &mut self.module) {
+                match checked.reload_function(name, module, true, &self.options) {
+                    Ok(()) => return,
+                    Err(ReloadError{desc}) => log::info!("{}, recompiling", desc)
+                }
+            }
+            self.module = Some(checked.compile(true, &self.options));
+            return;
+        }
+
+        let mut stmts = self.stmts[..self.step].to_vec();
+        stmts.extend(split_statements(entry));
+        if let Some(module) = report(compile(&Repl::source(&stmts, &self.functions), &self.cfg, &self.options)) {
+            self.stmts.truncate(self.step);
+            self.module = Some(module);
+            // Commit one statement at a time, so a runtime error keeps those that completed //
+            for stmt in stmts.drain(self.step..) {
+                if let Err(err) = self.step_forward() {
+                    eprintln!("{}", err);
+                    break;
+                }
+                self.stmts.push(stmt);
+            }
+        }
+    }
+
+    fn main_regions(&self) -> Vec<Region> {
+        let module = self.module.as_ref().expect("No program");
+        let main_func = &module.functions[module.main_idx.expect("No main function")];
+        main_func.code.regions.iter().filter(|r| r.depth == 0).cloned().collect()
+    }
+
+    fn step_forward(&mut self) -> Result<(), RuntimeError> {
+        let region = self.main_regions()[self.step].clone();
+        Interpreter::resume(self.module.as_ref().unwrap(), true, region.fwd, &mut self.session, &self.options)?;
+        self.step += 1;
+        Ok(())
+    }
+
+    // Mono statements have no backward code, so stepping back over them changes nothing //
+    fn step_back(&mut self) -> Result<(), RuntimeError> {
+        let region = self.main_regions()[self.step - 1].clone();
+        if !region.is_mono {
+            Interpreter::resume(self.module.as_ref().unwrap(), false, region.bkwd, &mut self.session, &self.options)?;
+        }
+        self.step -= 1;
+        Ok(())
+    }
+
+    // Move to just after the first `target` statements, or as far as a runtime error allows, then
+    // show what changed //
+    pub fn goto(&mut self, target: usize) {
+        let target = target.min(self.stmts.len());
+        let start = self.step;
+        let before = Snapshot::of(&self.session);
+        while self.step != target {
+            let moved = if self.step > target {self.step_back()} else {self.step_forward()};
+            if let Err(err) = moved {
+                eprintln!("{}", err);
+                break;
+            }
+        }
+        let mut changes = diff::diff(&before, &Snapshot::of(&self.session));
+        if let Some(module) = &self.module {
+            // Named by the variables of the statements moved over //
+            let regions = self.main_regions();
+            let span = match (regions.get(start.min(self.step)), regions.get(start.max(self.step).saturating_sub(1))) {
+                (Some(first), Some(last)) => first.fwd.start..last.fwd.end,
+                _ => 0..0
+            };
+            for change in changes.iter_mut() {
+                change.path = diff::name_path(&change.path, module, span.clone());
+            }
+        }
+        print!("{}", diff::format_changes(&changes, io::stdout().is_terminal()));
+        println!("At step {} of {}", self.step, self.stmts.len());
+    }
+
+    pub fn command(&mut self, line: &str) {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let count = |word: Option<&&str>| word.map_or(Some(1), |w| w.parse::<usize>().ok());
+        match (words.first().copied(), count(words.get(1))) {
+            (Some(":back"), Some(n)) => self.goto(self.step.saturating_sub(n)),
+            (Some(":forward"), Some(n)) => self.goto(self.step + n),
+            (Some(":goto"), Some(n)) if words.len() == 2 => self.goto(n),
+            (Some(":step"), _) => println!("At step {} of {}", self.step, self.stmts.len()),
+            _ => eprintln!("Commands: :back [N], :forward [N], :goto STEP, :step")
+        }
+    }
+}
+
+
+fn report<T>(result: Result<T, String>) -> Option<T> {
+    result.map_err(|desc| eprintln!("{}", desc)).ok()
+}
+
+
+// Split an entry at the semicolons ending each top level statement //
+fn split_statements(entry: &str) -> Vec<String> {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(entry.match_indices('\n').map(|(idx, _)| idx + 1))
+        .collect();
+    let mut ret = Vec::new();
+    let mut start = 0;
+    let mut depth = 0;
+    for token in Tokeniser::new(entry) {
+        match token.string_.as_str() {
+            "{" | "(" | "[" => depth += 1,
+            "}" | ")" | "]" => depth -= 1,
+            ";" if depth == 0 && token.type_ == "SYMBOL" => {
+                let end = line_starts[token.line - 1] + token.col + 1;
+                ret.push(format!("{}\n", entry[start..end].trim()));
+                start = end;
+            },
+            _ => {}
+        }
+    }
+    if !entry[start..].trim().is_empty() {
+        ret.push(format!("{}\n", entry[start..].trim()));
+    }
+    ret
+}
+
+
+pub fn compile(src: &str, cfg: &Cfg, options: &EngineOptions) -> Result<Module, String> {
+    Ok(check(src, cfg)?.compile(true, options))
+}
+
+fn check(src: &str, cfg: &Cfg) -> Result<ST::Module, String> {
+    let parsed = match parser::parse(Tokeniser::new(src)) {
+        Ok(parsed) => parsed,
+        Err(err) => return Err(err.to_string())
+    };
+    check_syntax(parsed, cfg).map_err(|SyntaxError{line, col, desc, ..}| format!(
+        "SyntaxError at line {}, column {}:\n ->  {}", line, col, desc))
+}
+
+
+// Whether more lines are needed before the entry can be parsed: it is inside a string or comment,
+// has unclosed brackets, or has not yet reached the end of a statement or function definition //
+pub fn is_incomplete(entry: &str) -> bool {
+    let mut tokens = Tokeniser::new(entry);
+    let mut depth: isize = 0;
+    let mut is_empty = true;
+    let mut last = None;
+    let mut seen_body = false;
+    for token in tokens.by_ref() {
+        if token.type_ == "END_MARKER!" {break}
+        match token.string_.as_str() {
+            "{" | "(" | "[" => depth += 1,
+            "}" | ")" | "]" => {
+                depth -= 1;
+                if depth == 0 && token.string_ == "}" {seen_body = true}
+            },
+            _ => {}
+        }
+        is_empty = false;
+        last = Some(token.string_);
+    }
+    if tokens.unterminated() || depth > 0 {return true}
+    if depth < 0 {return false}
+
+    if is_empty {return false}
+
+    match (first_token(entry).as_deref(), last.as_deref()) {
+        (Some("fn"), Some(")")) => !seen_body,
+        (Some("fn"), _) => true,
+        (_, Some(";")) => false,
+        _ => true
+    }
+}
+
+// The name of the function an entry defines, e.g. "f" for "#[pure] fn f(x)() {...} ~f()" //
+pub fn function_name(entry: &str) -> Option<String> {
+    let mut tokens = Tokeniser::new(entry).take_while(|t| t.type_ != "END_MARKER!").peekable();
+    while tokens.next_if(|t| t.string_ == "#").is_some() {
+        tokens.by_ref().find(|t| t.string_ == "]");
+    }
+    tokens.next().filter(|t| t.string_ == "fn")?;
+    tokens.next().map(|t| t.string_)
+}
+
+// The first token of an entry after any leading attributes, e.g. "fn" for "#[pure] fn f..." //
+pub fn first_token(entry: &str) -> Option<String> {
+    let mut tokens = Tokeniser::new(entry).take_while(|t| t.type_ != "END_MARKER!").peekable();
+    while tokens.next_if(|t| t.string_ == "#").is_some() {
+        tokens.by_ref().find(|t| t.string_ == "]");
+    }
+    tokens.next().map(|t| t.string_)
+}
+
+
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".monoxide_history"))
+}
+
+pub fn run(cfg: Cfg, options: EngineOptions) {
+    let mut editor = DefaultEditor::new().expect("Failed to initialise line editor");
+    let history = history_path();
+    if let Some(path) = &history {
+        let _ = editor.load_history(path);
+    }
+    let mut repl = Repl::new();
+    repl.cfg = cfg;
+    repl.options = options;
+    let mut entry = String::new();
+
+    // Runtime errors are reported by the steps that hit them, so anything still panicking is
+    // reported once here, without the hook's message too //
+    panic::set_hook(Box::new(|_| {}));
+    loop {
+        let prompt = if entry.is_empty() {">>> "} else {"... "};
+        match editor.readline(prompt) {
+            Ok(line) => {
+                entry.push_str(&line);
+                entry.push('\n');
+                if entry.starts_with(':') {
+                    let _ = editor.add_history_entry(entry.trim_end());
+                    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| repl.command(&entry))) {
+                        eprintln!("Command abandoned: {}", panic_message(payload));
+                    }
+                    entry.clear();
+                    continue;
+                }
+                if panic::catch_unwind(|| is_incomplete(&entry)).unwrap_or(false) {continue}
+                if !entry.trim().is_empty() {
+                    let _ = editor.add_history_entry(entry.trim_end());
+                    // A panic leaves the accumulated program untouched, but variables the entry
+                    // modified before failing keep their changes //
+                    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| repl.submit(&entry))) {
+                        eprintln!("Entry discarded: {}", panic_message(payload));
+                    }
+                }
+                entry.clear();
+            },
+            // Ctrl-C abandons the entry being typed //
+            Err(ReadlineError::Interrupted) => entry.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("{}", err);
+                break;
+            }
+        }
+    }
+    let _ = panic::take_hook();
+
+    if let Some(path) = &history {
+        let _ = editor.save_history(path);
+    }
+}
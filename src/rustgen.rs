@@ -0,0 +1,180 @@
+use std::fmt::Write;
+
+use crate::interpreter::{Constant, Function, Instruction, Module};
+
+
+// Programs built with "--backend rust" become a cargo project, whose main.rs holds the runtime
+// followed by one function per Monoxide function. Each is a state machine matching on the
+// direction and instruction pointer, with one arm per instruction of the compiled streams, so
+// jumps and reversals work as they do in the interpreter //
+const RUNTIME: &str = include_str!("native_runtime.rs");
+
+pub fn cargo_manifest(name: &str) -> String {
+    format!(concat!(
+        "[package]\n",
+        "name = \"{}\"\n",
+        "version = \"0.1.0\"\n",
+        "edition = \"2018\"\n\n",
+        "[dependencies]\n",
+        "num-rational = \"0.2.3\"\n",
+        "num-bigint = \"0.2.6\"\n",
+        "num-traits = \"0.2.11\"\n\n",
+        "[profile.release]\n",
+        "opt-level = 3\n\n",
+        "# Built on its own, even when the project sits inside another cargo workspace\n",
+        "[workspace]\n"), name.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_"))
+}
+
+pub fn module_source(module: &Module, name: &str) -> Result<String, String> {
+    let main_idx = module.main_idx.ok_or_else(|| String::from("The rust backend needs a main function"))?;
+    let mut out = String::from(RUNTIME);
+    writeln!(out, "\n\n// ------------------------- Generated from {} ------------------------- //", name).unwrap();
+
+    writeln!(out, "\nfn consts() -> Vec<Value> {{\n    vec![").unwrap();
+    for value in module.consts.iter() {
+        writeln!(out, "        {},", value_source(value)).unwrap();
+    }
+    writeln!(out, "    ]\n}}").unwrap();
+    writeln!(out, "\nconst STRINGS: &[&str] = &{:?};", module.strings).unwrap();
+
+    for (idx, func) in module.functions.iter().enumerate() {
+        function_source(idx, func, &mut out)?;
+    }
+
+    let global_func = &module.functions[module.global_func_idx];
+    writeln!(out, concat!(
+        "\n// Native frames are larger than the interpreter's, so deep recursion gets a big stack //\n",
+        "fn main() {{\n",
+        "    let run = std::thread::Builder::new().stack_size(1 << 30).spawn(|| {{\n",
+        "        let mut rt = Runtime{{\n",
+        "            stack: Vec::new(),\n",
+        "            globals: vec![None; {}],\n",
+        "            consts: consts(),\n",
+        "            strings: STRINGS.to_vec(),\n",
+        "            started: Instant::now()\n",
+        "        }};\n",
+        "        f{}(&mut rt, true);\n",
+        "        f{}(&mut rt, true);\n",
+        "    }}).expect(\"Failed to start the program\");\n",
+        "    if run.join().is_err() {{\n",
+        "        std::process::exit(1);\n",
+        "    }}\n",
+        "}}"), global_func.num_registers, module.global_func_idx, main_idx).unwrap();
+    Ok(out)
+}
+
+fn value_source(value: &Constant) -> String {
+    match value {
+        Constant::Frac(val) => format!("Value::Frac(frac(\"{}\", \"{}\"))", val.numer(), val.denom()),
+        Constant::Float(val) => format!("Value::Float(f64::from_bits({:#x}))", val.to_bits()),
+        Constant::Str(val) => format!("Value::Str(String::from({:?}))", val),
+        Constant::Handle(_) => unreachable!("Handles only exist in running programs"),
+        Constant::Array(items) => format!("Value::Array(vec![{}])", items.iter()
+            .map(|item| format!("new_var({})", value_source(item)))
+            .collect::<Vec<_>>().join(", "))
+    }
+}
+
+fn function_source(idx: usize, func: &Function, out: &mut String) -> Result<(), String> {
+    writeln!(out, "\n// {} //", func.name).unwrap();
+    writeln!(out, "fn f{}(rt: &mut Runtime, forwards: bool) {{", idx).unwrap();
+    writeln!(out, "    let mut r: Vec<Option<Var>> = vec![None; {}];", func.num_registers).unwrap();
+    writeln!(out, "    let mut forwards = forwards;\n    let mut ip = 0;").unwrap();
+    // Where an outlined block returns to //
+    if func.code.bkwd.iter().any(|instruction| matches!(instruction, Instruction::Gosub{..})) {
+        writeln!(out, "    let mut ret = 0;").unwrap();
+    }
+    writeln!(out, "    loop {{").unwrap();
+    for (branch, forwards) in [("if forwards", true), ("} else", false)] {
+        writeln!(out, "        {} {{\n            match ip {{", branch).unwrap();
+        for (ip, instruction) in func.code.decoded(forwards).iter().enumerate() {
+            let arm = instruction_source(instruction).ok_or_else(|| format!(
+                "The rust backend can't compile {} in \"{}\"", instruction.name(), func.name))?;
+            writeln!(out, "                {} => {},", ip, arm).unwrap();
+        }
+        writeln!(out, "                _ => return\n            }}").unwrap();
+    }
+    writeln!(out, "        }}\n        ip += 1;\n    }}\n}}").unwrap();
+    Ok(())
+}
+
+// The body of an instruction's match arm. Arms that move the instruction pointer themselves
+// continue the loop, and the rest fall through to the next instruction //
+fn instruction_source(instruction: &Instruction) -> Option<String> {
+    use Instruction::*;
+    let jump = |ip: usize| format!("{{ip = {}; continue}}", ip);
+    Some(match *instruction {
+        LoadConst{idx} => format!("rt.load_const({})", idx),
+        LoadString{idx} => format!("rt.load_string({})", idx),
+        LoadRegister{register} => format!("rt.load(&r[{}])", register),
+        LoadGlobalRegister{register} => format!("{{let var = rt.globals[{}].clone(); rt.load(&var)}}", register),
+        StoreRegister{register} => format!("r[{}] = Some(rt.pop_var())", register),
+        StoreGlobalRegister{register} => format!("{{let var = rt.pop_var(); rt.globals[{}] = Some(var)}}", register),
+        FreeRegister{register} => format!("r[{}] = None", register),
+        MoveRegister{from, to} => format!("{{let var = r[{}].take(); r[{}] = var}}", from, to),
+        Subscript{size} => format!("rt.subscript({})", size),
+        Slice => String::from("rt.slice()"),
+        CheckRef{register} => format!("rt.check_ref(&r[{}])", register),
+        CheckUnshared{register} => format!("rt.check_unshared(&r[{}])", register),
+        Store => String::from("rt.store()"),
+        Pull{register} => format!("rt.pull(&mut r[{}], false)", register),
+        PullFront{register} => format!("rt.pull(&mut r[{}], true)", register),
+        Push{register} => format!("rt.push_item(&mut r[{}], false)", register),
+        PushFront{register} => format!("rt.push_item(&mut r[{}], true)", register),
+        CreateInt{val} => format!("rt.push(int({}))", val),
+        BinopAdd => String::from("rt.arith(\"+\", |a, b| a + b, |a, b| a + b)"),
+        BinopSub => String::from("rt.arith(\"-\", |a, b| a - b, |a, b| a - b)"),
+        BinopMul => String::from("rt.arith(\"*\", |a, b| a * b, |a, b| a * b)"),
+        BinopDiv => String::from("rt.arith(\"/\", |a, b| a / b, |a, b| a / b)"),
+        BinopMod => String::from("rt.arith(\"%\", |a, b| a % b, |a, b| a % b)"),
+        BinopIDiv => String::from("rt.idiv()"),
+        BinopPow => String::from("rt.pow()"),
+        Shift{bits} => format!("rt.shift({})", bits),
+        BinopLess => String::from("rt.compare(\"<\", |o| o == Ordering::Less)"),
+        BinopLeq => String::from("rt.compare(\"<=\", |o| o != Ordering::Greater)"),
+        BinopGreat => String::from("rt.compare(\">\", |o| o == Ordering::Greater)"),
+        BinopGeq => String::from("rt.compare(\">=\", |o| o != Ordering::Less)"),
+        BinopEq => String::from("rt.equal(true)"),
+        BinopNeq => String::from("rt.equal(false)"),
+        BinopXor => String::from("rt.xor()"),
+        UniopNeg => String::from("rt.neg()"),
+        UniopNot => String::from("rt.not()"),
+        UniopLen => String::from("rt.len()"),
+        UniopToFloat => String::from("rt.to_float()"),
+        UniopToFrac => String::from("rt.to_frac()"),
+        UniopSin => String::from("rt.float_fn(\"sin\", f64::sin)"),
+        UniopCos => String::from("rt.float_fn(\"cos\", f64::cos)"),
+        UniopExp => String::from("rt.float_fn(\"exp\", f64::exp)"),
+        UniopLog => String::from("rt.float_fn(\"log\", f64::ln)"),
+        UniopSqrt => String::from("rt.float_fn(\"sqrt\", f64::sqrt)"),
+        ArrayLiteral{size} => format!("rt.array_literal({})", size),
+        ArrayRepeat => String::from("rt.array_repeat()"),
+        DuplicateRef => String::from("rt.duplicate_ref()"),
+        UniqueVar => String::from("rt.unique_var()"),
+        Call{idx} => format!("f{}(rt, true)", idx),
+        Uncall{idx} => format!("f{}(rt, false)", idx),
+        // The memo table only saves recomputing pure calls, so they're simply made again //
+        Remember{idx} => format!("f{}(rt, true)", idx),
+        CreateIter{register, reversed, index_register, depth} => format!(
+            "rt.create_iter({}, {}, {:?}, {}, forwards)", register, reversed, index_register, depth),
+        CreateRangeIter{register} => format!("rt.create_range_iter({}, forwards)", register),
+        StepIter{ip} | ParallelStepIter{ip} => format!("if !rt.step_iter(&mut r, forwards) {}", jump(ip)),
+        Print{count} => format!("rt.print({})", count),
+        Expect => String::from("rt.expect(forwards)"),
+        ExpectEqual => String::from("rt.expect_equal(forwards)"),
+        Jump{ip} => jump(ip),
+        JumpIfTrue{ip} => format!("if rt.truth() {}", jump(ip)),
+        JumpIfFalse{ip} => format!("if !rt.truth() {}", jump(ip)),
+        Reverse{idx} => format!("{{forwards = !forwards; ip = {}; continue}}", idx),
+        Gosub{ip: target} => format!("{{ret = ip + 1; ip = {}; continue}}", target),
+        Return => String::from("{ip = ret; continue}"),
+        Quit => String::from("{use std::io::Write; std::io::stdout().flush().ok(); std::process::exit(0)}"),
+        Hole => String::from("panic!(\"Evaluated the unfinished expression \\\"???\\\"\")"),
+        Todo => String::from("panic!(\"Ran the unfinished statement \\\"todo\\\"\")"),
+        ClockMs => String::from("rt.clock_ms()"),
+        Marker{..} => String::from("{}"),
+        Steps | Backtrace | DebugPrint | BinopAnd | BinopOr |
+        RelativeJump{..} | RelativeJumpIfTrue{..} | RelativeJumpIfFalse{..} |
+        CreateGenerator{..} | Resume{..} | Suspend | HostCall => return None
+    })
+}
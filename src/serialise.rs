@@ -0,0 +1,107 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+use num_traits::Zero;
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, Serialize, SerializeMap, SerializeSeq, Serializer};
+
+use crate::interpreter::{Fraction, Variable};
+
+
+// Values cross into other formats as their nearest plain equivalent: strings, floats and nested
+// arrays. Fractions are unbounded, so are a map of their numerator and denominator as decimal
+// strings, e.g. {"num": "-3", "den": "4"}. Handles only mean something to the interpreter that
+// made them, so they're refused //
+impl Serialize for Variable {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Variable::Frac(val) => {
+                let mut map = serializer.serialize_map(Some(2))?;
+                map.serialize_entry("num", &val.numer().to_string())?;
+                map.serialize_entry("den", &val.denom().to_string())?;
+                map.end()
+            },
+            Variable::Float(val) => serializer.serialize_f64(*val),
+            Variable::Str(string) => serializer.serialize_str(string),
+            Variable::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(&*item.borrow())?;
+                }
+                seq.end()
+            },
+            Variable::Handle(_) => Err(ser::Error::custom("Handles name host objects, so can't be serialised"))
+        }
+    }
+}
+
+// The inverse of the above. Integers are also read as fractions, so hand-written inputs can just
+// say 3 //
+impl<'de> Deserialize<'de> for Variable {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Variable, D::Error> {
+        deserializer.deserialize_any(VariableVisitor)
+    }
+}
+
+struct VariableVisitor;
+
+impl<'de> Visitor<'de> for VariableVisitor {
+    type Value = Variable;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a number, string, array, or fraction as {\"num\": ..., \"den\": ...}")
+    }
+
+    fn visit_i64<E: de::Error>(self, val: i64) -> Result<Variable, E> {
+        Ok(Variable::Frac(Fraction::from_integer(BigInt::from(val))))
+    }
+
+    fn visit_u64<E: de::Error>(self, val: u64) -> Result<Variable, E> {
+        Ok(Variable::Frac(Fraction::from_integer(BigInt::from(val))))
+    }
+
+    fn visit_f64<E: de::Error>(self, val: f64) -> Result<Variable, E> {
+        Ok(Variable::Float(val))
+    }
+
+    fn visit_str<E: de::Error>(self, string: &str) -> Result<Variable, E> {
+        Ok(Variable::Str(string.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, string: String) -> Result<Variable, E> {
+        Ok(Variable::Str(string))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Variable, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(item) = seq.next_element::<Variable>()? {
+            items.push(Rc::new(RefCell::new(item)));
+        }
+        Ok(Variable::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Variable, A::Error> {
+        let (mut numer, mut denom) = (None, None);
+        while let Some(key) = map.next_key::<String>()? {
+            let part = match key.as_str() {
+                "num" => &mut numer,
+                "den" => &mut denom,
+                _ => return Err(de::Error::unknown_field(&key, &["num", "den"]))
+            };
+            if part.is_some() {
+                return Err(de::Error::custom(format!("Duplicate field \"{}\"", key)));
+            }
+            let digits: String = map.next_value()?;
+            *part = Some(digits.parse::<BigInt>().map_err(|_| de::Error::custom(format!(
+                "\"{}\" of a fraction should be an integer, not \"{}\"", key, digits)))?);
+        }
+        let numer = numer.ok_or_else(|| de::Error::missing_field("num"))?;
+        let denom = denom.ok_or_else(|| de::Error::missing_field("den"))?;
+        if denom.is_zero() {
+            return Err(de::Error::custom("Fraction with a denominator of zero"));
+        }
+        Ok(Variable::Frac(Fraction::new(numer, denom)))
+    }
+}
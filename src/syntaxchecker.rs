@@ -2,14 +2,24 @@
 use std::collections::{HashSet, HashMap};
 use std::cell::RefCell;
 use std::hash::{Hash, Hasher};
+use std::iter;
 use std::mem;
 use std::rc::Rc;
+use std::sync::Arc;
 
-use num_traits::identities::Zero;
+use num_traits::identities::{Zero, One};
+use num_traits::ToPrimitive;
 
+use crate::cfg::{Cfg, configure, configure_stmts};
+use crate::compiler::compile_expression;
+use crate::desugar::{desugar, expand_machine};
+use crate::formatter;
+use crate::imports::{resolve_imports, Library};
 use crate::interpreter;
+use crate::options::EngineOptions;
 use crate::parsetree as PT;
 use crate::syntaxtree as ST;
+use crate::typechecker::{check_types, operator_symbol};
 
 use PT::Expression as PTExpression;
 
@@ -39,28 +49,84 @@ pub struct Reference {
     is_interior: bool,
     is_borrowed: bool,
     is_global: bool,
+    is_slice: bool,  // A reference to a range of elements, which can't be resized //
+    extent: Option<(usize, usize)>,  // The elements of the variable's outer array it covers, when known //
     register: usize,
     var: Rc<Variable>
 }
 
 #[derive(Debug)]
 pub struct SyntaxError {
+    pub line: usize,
+    pub col: usize,
+    pub desc: String,
+    pub note: Option<Note>  // Another position the error concerns //
+}
+
+// e.g. where a name that is bound twice was first bound //
+#[derive(Debug, Clone)]
+pub struct Note {
     pub line: usize,
     pub col: usize,
     pub desc: String
 }
 
 
+// The innermost parfor around the statement being checked. Its iterations may run in any order, or
+// at once, so each can only modify its own item and the variables it binds //
+#[derive(Debug, Clone)]
+struct Parallel {
+    array_id: isize,
+    aliases: Vec<String>,  // Names of the array being iterated over, besides the iteration variable
+    last_shared_id: isize,  // Variables bound before the loop have ids up to this
+    catch_contained: bool  // Inside a try body within the loop, so a catch only undoes this iteration
+}
+
+
 #[derive(Debug)]
 pub struct SyntaxContext<'a> {
     functions: &'a HashMap<String, ST::FunctionPrototype>,
     consts: Vec<interpreter::Variable>,
-    strings: Vec<String>,
     free_registers: Vec<usize>,
     locals: HashMap<String, Reference>,
     locals_stack: Vec<HashMap<String, Reference>>,
     globals: &'a HashMap<String, Reference>,
     num_registers: usize,
+    position: Option<(usize, usize)>,  // Of the statement being checked
+    symbols: Vec<ST::Symbol>,
+    open_symbols: HashMap<String, Vec<usize>>,
+    sandboxed: bool,  // Reject statements with side effects
+    read_only: Vec<isize>,  // Ids of variables a for loop depends on, while checking its body
+    pieces: Vec<(isize, String, (usize, usize))>,  // Variables split out of an array by a destructuring let
+    func_name: String,  // Mangled, e.g. "outer::inner" for a nested function
+    outer_locals: HashSet<String>,  // Names bound by enclosing functions, which nested ones can't capture
+    const_bindings: HashMap<String, interpreter::Variable>,  // Const params of a monomorphised function
+    specialised: HashMap<String, interpreter::Variable>,  // Borrow params every call passes the same constant
+    let_consts: HashMap<isize, interpreter::Variable>,  // Ids of the variables let bound to literals, with their values
+    borrow_ids: Vec<isize>,  // Ids of the function's borrowed params
+    is_pure: bool,  // Checking a #[pure] function, which mustn't have side effects
+    pure_borrows: Vec<isize>,  // Ids of the variables a #[pure] function borrows, so can't modify
+    remembering: bool,  // Inside a remember block, whose call expressions use the memo table
+    uncomputing: bool,  // Inside the do part of a do/yield, which is undone straight after the yield
+    parallel: Option<Parallel>,
+    is_generator: bool,  // Checking a #[generator], whose yields hand values to the loop over it
+    tries: usize,  // Try bodies open around the statement being checked
+    catch_floor: Option<(usize, &'static str)>,  // Catches need more tries open than this, or the error
+    reads: HashMap<isize, (usize, usize)>,  // The last statement reading each variable
+    call_outputs: Vec<(interpreter::Garbage, Vec<isize>)>,  // Each call made, with the ids of what it returns
+    strict: bool,  // Reject the permissive defaults, under --strict or #pragma strict
+    sketch: bool,  // Uninitialise what top-level lets leave bound at the end, under --sketch
+    check_aliasing: bool,  // Compile runtime checks of what references point to, under --check-aliasing
+    hoist_invariants: bool,  // Compute loop invariants once ahead of their loops, from opt level 1
+    definitions: Vec<(PT::LetUnletNode, isize, HashSet<isize>)>,  // Top-level lets, with the id bound and the ids read
+    written: HashSet<isize>,  // Ids of variables modified, or maybe modified by a call or through a reference
+    modified: HashSet<isize>,  // As written, less those only lent to calls, which specialise() follows through them
+    counters_read: usize,
+    effects: interpreter::Effects,  // What the function does itself, not counting its callees
+    callees: Vec<usize>,
+    call_sites: Vec<(usize, Vec<ST::Passed>)>,  // Each call's callee, and what it passes for each borrowed param
+    parfor_calls: Vec<(usize, usize, usize)>,  // Impure functions called in a parfor, and where
+    const_evals: Vec<ST::ConstEval>,
     last_var_id: isize
 }
 
@@ -73,12 +139,46 @@ impl<'a> SyntaxContext<'a> {
         SyntaxContext {
             functions,
             consts: Vec::new(),
-            strings: Vec::new(),
             free_registers: Vec::new(),
             locals: HashMap::new(),
             locals_stack: Vec::new(),
             globals,
             num_registers: 0,
+            position: None,
+            symbols: Vec::new(),
+            open_symbols: HashMap::new(),
+            sandboxed: false,
+            read_only: Vec::new(),
+            pieces: Vec::new(),
+            func_name: String::new(),
+            outer_locals: HashSet::new(),
+            const_bindings: HashMap::new(),
+            specialised: HashMap::new(),
+            let_consts: HashMap::new(),
+            borrow_ids: Vec::new(),
+            is_pure: false,
+            pure_borrows: Vec::new(),
+            remembering: false,
+            uncomputing: false,
+            parallel: None,
+            is_generator: false,
+            tries: 0,
+            catch_floor: None,
+            reads: HashMap::new(),
+            call_outputs: Vec::new(),
+            strict: false,
+            sketch: false,
+            check_aliasing: false,
+            hoist_invariants: false,
+            definitions: Vec::new(),
+            written: HashSet::new(),
+            modified: HashSet::new(),
+            counters_read: 0,
+            effects: interpreter::Effects::default(),
+            callees: Vec::new(),
+            call_sites: Vec::new(),
+            parfor_calls: Vec::new(),
+            const_evals: Vec::new(),
             last_var_id: 0
         }
     }
@@ -96,6 +196,8 @@ impl<'a> SyntaxContext<'a> {
             register,
             is_borrowed,
             is_global: false,
+            is_slice: false,
+            extent: None,
             var: Rc::new(Variable{
                 id: self.new_variable_id(),
                 exteriors: RefCell::new(exteriors),
@@ -133,6 +235,7 @@ impl<'a> SyntaxContext<'a> {
                 };
                 let register = self.get_free_register();
                 registers.push(register);
+                let name = p.name.clone();
 
                 if !p.is_ref {
                     // Singly owned //
@@ -149,7 +252,7 @@ impl<'a> SyntaxContext<'a> {
                             else           {var.exteriors.borrow_mut().insert(p.name.clone())};
                             self.locals.insert(
                                 p.name,
-                                Reference{is_interior, register, is_borrowed, is_global: false, var: Rc::clone(var)}
+                                Reference{is_interior, register, is_borrowed, is_global: false, is_slice: false, extent: None, var: Rc::clone(var)}
                             );
                         },
                         None => {
@@ -168,7 +271,7 @@ impl<'a> SyntaxContext<'a> {
                             linked.insert(ext_link, Rc::clone(&var));
                             self.locals.insert(
                                 p.name,
-                                Reference{is_interior, register, is_borrowed, is_global: false, var}
+                                Reference{is_interior, register, is_borrowed, is_global: false, is_slice: false, extent: None, var}
                             );
                         }
                     }
@@ -179,6 +282,8 @@ impl<'a> SyntaxContext<'a> {
                     varref.var.interiors.borrow_mut().insert(String::from("calling scope"));
                     self.locals.insert(p.name, varref);
                 }
+                let var_id = self.locals[&name].var.id;
+                self.open_symbol(&name, register, var_id);
             }
         }
 
@@ -222,10 +327,44 @@ impl<'a> SyntaxContext<'a> {
         self.consts.len() - 1
     }
 
+    // Functions nested in this one or its enclosing functions shadow top-level ones //
     fn lookup_function_prototype(&self, name: &str) -> Result<&ST::FunctionPrototype, SyntaxError> {
-        self.functions.get(name).ok_or(
-            SyntaxError{line: 0, col: 0, desc: format!("Undefined function \"{}\"", name)}
-        )
+        let mut scope = Some(self.func_name.as_str());
+        while let Some(prefix) = scope {
+            if let Some(proto) = self.functions.get(&format!("{}::{}", prefix, name)) {
+                return Ok(proto);
+            }
+            scope = prefix.rfind("::").map(|idx| &prefix[..idx]);
+        }
+        let proto = self.functions.get(name).ok_or_else(|| SyntaxError{line: 0, col: 0, desc: format!(
+            "Undefined function \"{}\"{}", name, self.suggest_function(name)), note: None})?;
+        match (&proto.module, self.is_visible(proto)) {
+            (Some(module), false) => Err(SyntaxError{line: 0, col: 0, desc: format!(
+                "Function \"{}\" is private to module \"{}\", as it isn't declared \"pub fn\"", name, module), note: None}),
+            _ => Ok(proto)
+        }
+    }
+
+    // Whether a function can be called from the module being checked //
+    fn is_visible(&self, proto: &ST::FunctionPrototype) -> bool {
+        let module = self.functions.get(&self.func_name).and_then(|own| own.module.as_ref());
+        proto.is_pub || proto.module.as_ref() == module
+    }
+
+    // Names of the functions callable from here, unqualified for those nested in enclosing scopes //
+    fn suggest_function(&self, name: &str) -> String {
+        let scopes: Vec<String> = std::iter::successors(Some(self.func_name.as_str()), |prefix| {
+            prefix.rfind("::").map(|idx| &prefix[..idx])
+        }).map(|prefix| format!("{}::", prefix)).collect();
+        let candidates = self.functions.iter().filter(|(_, proto)| self.is_visible(proto)).map(|(key, _)| {
+            scopes.iter().find_map(|scope| key.strip_prefix(scope.as_str())).unwrap_or(key)
+        });
+        did_you_mean(name, candidates)
+    }
+
+    fn suggest_variable(&self, name: &str) -> String {
+        let scopes = std::iter::once(&self.locals).chain(self.locals_stack.iter()).chain(std::iter::once(self.globals));
+        did_you_mean(name, scopes.flat_map(|scope| scope.keys().map(|key| key.as_str())))
     }
 
     fn check_singly_owned(&self, name: &str) -> Result<bool, SyntaxError> {
@@ -234,6 +373,11 @@ impl<'a> SyntaxContext<'a> {
         Ok(var.interiors.borrow().len() == 0 && var.exteriors.borrow().len() == 1)
     }
 
+    fn not_singly_owned(&self, what: &str, name: &str, line: usize, col: usize) -> SyntaxError {
+        let (held, note) = self.other_reference(name, false).expect("Variable has no other references");
+        SyntaxError{line, col, desc: format!("{} uses non-singly owned variable \"{}\". {}", what, name, held), note}
+    }
+
     fn lookup_variable(&self, name: &str) -> Result<&Reference, SyntaxError> {
         if let Some(var) = self.locals.get(name) { return Ok(var); }
         for locals in self.locals_stack.iter().rev() {
@@ -241,40 +385,119 @@ impl<'a> SyntaxContext<'a> {
         }
         if let Some(var) = self.globals.get(name) { return Ok(var); }
         
+        if self.const_bindings.contains_key(name) {
+            return Err(SyntaxError{line: 0, col: 0, desc: format!(
+                "\"{}\" is a const param, so can only be read", name), note: None});
+        }
+        if self.outer_locals.contains(name) {
+            return Err(SyntaxError{line: 0, col: 0, desc: format!(
+                "\"{}\" belongs to an enclosing function, so must be passed to \"{}\" as a param",
+                name, self.func_name), note: None});
+        }
         Err(SyntaxError{line: 0, col: 0, desc: // TODO: can pass line numbers through to here
-            format!("Looking up non-existant variable \"{}\"", name)})
+            format!("Looking up non-existant variable \"{}\"{}", name, self.suggest_variable(name)), note: None})
     }
 
     fn get_free_register(&mut self) -> usize {
         match self.free_registers.pop() {
-            Some(r) => r,
+            Some(r) => {
+                log::trace!("{}: reusing freed register {}", self.func_name, r);
+                r
+            },
             None => {
                 self.num_registers += 1;
+                log::trace!("{}: allocating new register {}", self.func_name, self.num_registers - 1);
                 (self.num_registers - 1) as usize
             }
         }
     }
 
+    // Record a name being bound from the current statement on, for the debug symbol table //
+    fn open_symbol(&mut self, name: &str, register: usize, var_id: isize) {
+        self.open_symbols.entry(name.to_string()).or_default().push(self.symbols.len());
+        self.symbols.push(ST::Symbol{
+            name: name.to_string(), register, var_id, start: self.position, end: None
+        });
+    }
+
+    // Where the binding of a name in scope was made, for errors about binding it again //
+    fn binding_note(&self, name: &str) -> Option<Note> {
+        let idx = *self.open_symbols.get(name)?.last()?;
+        let (line, col) = self.symbols[idx].start?;
+        Some(Note{line, col, desc: format!("\"{}\" was first bound here", name)})
+    }
+
+    // Another reference to the variable "name" names, preferring interior ones, described along with
+    // where it was made so an error can show both ends of its lifetime //
+    fn other_reference(&self, name: &str, interior_only: bool) -> Option<(String, Option<Note>)> {
+        let var = &self.lookup_variable(name).ok()?.var;
+        let (interiors, exteriors) = (var.interiors.borrow(), var.exteriors.borrow());
+        let mut others: Vec<(&String, bool)> = interiors.iter().filter(|n| !interior_only || self.overlaps(name, n)).map(|n| (n, true))
+            .chain(exteriors.iter().filter(|_| !interior_only).map(|n| (n, false)))
+            .filter(|(other, _)| *other != name).collect();
+        others.sort_by_key(|&(other, is_interior)| (!is_interior, other.clone()));
+        let &(other, is_interior) = others.first()?;
+        let kind = if is_interior {"the interior of "} else {""};
+        if other == "calling scope" || other == "caller anchor" {
+            return Some((format!("The calling function still references {}\"{}\" here", kind, name), None));
+        }
+        let note = self.open_symbols.get(other).and_then(|open| open.last())
+            .and_then(|&idx| self.symbols[idx].start)
+            .map(|(line, col)| Note{line, col, desc: format!("\"{}\" has referenced {}\"{}\" since here", other, kind, name)});
+        Some((format!("\"{}\" still references {}\"{}\" here", other, kind, name), note))
+    }
+
+    // The innermost binding of a name ends with the current statement //
+    fn close_symbol(&mut self, name: &str) {
+        if let Some(idx) = self.open_symbols.get_mut(name).and_then(|open| open.pop()) {
+            self.symbols[idx].end = self.position;
+        }
+    }
+
     fn create_variable(&mut self, name: &str) -> Result<usize, SyntaxError> {
         if self.locals.contains_key(name) {
             return Err(SyntaxError{line: 0, col: 0, desc: // TODO: can pass line numbers through to here
-                format!("A variable named \"{}\" already exists", name)});
+                format!("A variable named \"{}\" already exists", name), note: self.binding_note(name)});
         };
         let register = self.get_free_register();
         let new_var = self.new_variable(name.to_string(), register, false);
+        self.open_symbol(name, register, new_var.var.id);
         self.locals.insert(name.to_string(), new_var);
         Ok(register)
     }
 
-    pub fn create_ref(&mut self, name: &str, lookup: &PT::LookupNode) -> Result<usize, SyntaxError> {
+    pub fn create_ref(
+        &mut self,
+        name: &str,
+        lookup: &PT::LookupNode,
+        range: Option<&(PT::ExpressionNode, PT::ExpressionNode)>
+    ) -> Result<usize, SyntaxError> {
         if self.locals.contains_key(name) {
             return Err(SyntaxError{line: 0, col: 0, desc: // TODO: can pass line numbers through to here
-                format!("A variable named \"{}\" already exists", name)});
+                format!("A variable named \"{}\" already exists", name), note: self.binding_note(name)});
         };
 
         let src = self.lookup_variable(&lookup.name)?;
         
-        let is_interior = src.is_interior || lookup.indices.len() > 0;
+        let is_interior = src.is_interior || !lookup.indices.is_empty() || range.is_some();
+        let is_slice = range.is_some() || (src.is_slice && lookup.indices.is_empty());
+        let extent = match (lookup.indices.first(), range) {
+            // Deeper into an element, or the same elements again //
+            _ if src.is_interior && !src.is_slice => src.extent,
+            (None, None) => src.extent,
+            (index, range) => {
+                let offset = if src.is_slice {src.extent.map(|(start, _)| start)} else {Some(0)};
+                let (start, end) = match (index, range) {
+                    (Some(index), _) => (index.constant_index(), index.constant_index().map(|i| i + 1)),
+                    (None, Some((start, end))) => (start.constant_index(), end.constant_index()),
+                    (None, None) => unreachable!()
+                };
+                match (offset, start, end) {
+                    (Some(offset), Some(start), Some(end)) => Some((offset + start, offset + end)),
+                    _ => None
+                }
+            }
+        };
         let var = Rc::clone(&src.var);
         let is_borrowed = false;
         let is_global = false;
@@ -290,19 +513,34 @@ impl<'a> SyntaxContext<'a> {
         } else {
             var.exteriors.borrow_mut().insert(name.to_string());
         }
+        self.open_symbol(name, register, var.id);
 
         self.locals.insert(
             name.to_string(),
-            Reference{is_interior, register, var, is_borrowed, is_global}
+            Reference{is_interior, register, var, is_borrowed, is_global, is_slice, extent}
         );
         Ok(register)
     }
 
 
-    pub fn remove_ref(&mut self, name: &str, lookup: &PT::LookupNode) -> Result<usize, SyntaxError> {
-        let mut err = SyntaxError{line: 0, col: 0, desc: String::new()};  // TODO: can pass line numbers through to here
+    // A loop's reference to the first element of what it iterates, which it moves through them all //
+    fn create_iterator_ref(&mut self, name: &str, zero_lookup: &PT::LookupNode) -> Result<usize, SyntaxError> {
+        let register = self.create_ref(name, zero_lookup, None)?;
+        self.locals.get_mut(name).expect("Iterator reference missing").extent = None;
+        Ok(register)
+    }
+
+    pub fn remove_ref(&mut self, name: &str, lookup: &PT::LookupNode, is_slice: bool) -> Result<usize, SyntaxError> {
+        let mut err = SyntaxError{line: 0, col: 0, desc: String::new(), note: None};  // TODO: can pass line numbers through to here
 
         match self.locals.remove(name) {
+            None if self.locals_stack.iter().any(|locals| locals.contains_key(name)) => {
+                err.desc = format!(
+                    "Removing reference \"{}\" inside a block, though it was made outside it. A block must leave \
+                    the references around it as it found them, so that e.g. both branches of an if join with the same ones", name);
+                err.note = self.binding_note(name);
+                Err(err)
+            },
             None => {
                 err.desc = format!("Removing non-existant reference \"{}\"", name);
                 Err(err)
@@ -311,12 +549,12 @@ impl<'a> SyntaxContext<'a> {
                 err.desc = format!("Removing borrowed reference \"{}\"", name);
                 Err(err)
             },
-            Some(Reference{is_interior, register, var, ..}) => {
+            Some(Reference{is_interior, register, var, is_slice: was_slice, ..}) => {
                 // let is_interior = is_interior;
 
                 // Check they reference the same variable, and they're not deref'ing an exterior using an interior
                 let Reference{var: other_var, is_interior: mut other_is_interior, ..} = self.lookup_variable(&lookup.name)?;
-                other_is_interior |= lookup.indices.len() > 0;
+                other_is_interior |= !lookup.indices.is_empty() || is_slice;
                 if !Rc::ptr_eq(&var, other_var) {
                     err.desc = format!(
                         "Unreferencing \"{}\" using \"{}\" but they're different variables",
@@ -329,21 +567,65 @@ impl<'a> SyntaxContext<'a> {
                         "Mismatched interior/exterior reference when unreferencing \"{}\"", name);
                     return Err(err)
                 }
+                if is_slice != was_slice {
+                    err.desc = format!(
+                        "Mismatched reference to a range of elements when unreferencing \"{}\"", name);
+                    return Err(err)
+                }
 
                 // Deref
                 var.interiors.borrow_mut().remove(name);
                 var.exteriors.borrow_mut().remove(name);
+                self.close_symbol(name);
                 Ok(register)
             }
         }
     }
 
+    // Move an interior reference to another part of the variable it references, keeping its register //
+    fn relink_ref(&mut self, name: &str, old: &PT::LookupNode, new: &PT::LookupNode) -> Result<usize, SyntaxError> {
+        let err = |desc: String| SyntaxError{line: 0, col: 0, desc, note: None};
+        let Ok(Reference{is_interior, is_borrowed, is_global, is_slice, register, var, ..}) = self.lookup_variable(name) else {
+            return Err(err(format!("Relinking non-existant reference \"{}\"", name)));
+        };
+        if *is_global {
+            return Err(err(format!("Relinking global reference \"{}\"", name)));
+        }
+        if *is_borrowed {
+            return Err(err(format!("Relinking borrowed reference \"{}\"", name)));
+        }
+        if *is_slice {
+            return Err(err(format!("Relinking \"{}\", which references a range of elements", name)));
+        }
+        if !*is_interior {
+            return Err(err(format!(
+                "Relinking exterior reference \"{}\", which can only reference the whole of its variable", name)));
+        }
+        let (register, var) = (*register, Rc::clone(var));
+        for lookup in [old, new] {
+            let target = self.lookup_variable(&lookup.name)?;
+            if !Rc::ptr_eq(&var, &target.var) {
+                return Err(err(format!(
+                    "Relinking \"{}\" using \"{}\", but they're different variables", name, lookup.name)));
+            }
+            if !target.is_interior && lookup.indices.is_empty() {
+                return Err(err(format!(
+                    "Relinking interior reference \"{}\" to the whole of \"{}\"", name, lookup.name)));
+            }
+        }
+        // The reference is made anew here, for the debug symbol table and errors about its lifetime //
+        self.close_symbol(name);
+        self.open_symbol(name, register, var.id);
+        Ok(register)
+    }
+
     fn remove_variable(&mut self, name: &str) -> Result<usize, SyntaxError> {
-        let mut err = SyntaxError{line: 0, col: 0, desc: String::new()};
+        let mut err = SyntaxError{line: 0, col: 0, desc: String::new(), note: None};
+        let held = self.other_reference(name, false);
 
         match self.locals.remove(name) {
             None => {
-                err.desc = format!("Uninitialising non-existant variable \"{}\"", name);
+                err.desc = format!("Uninitialising non-existant variable \"{}\"{}", name, self.suggest_variable(name));
                 Err(err)
             },
             Some(Reference{is_borrowed: true, ..}) => {
@@ -352,13 +634,13 @@ impl<'a> SyntaxContext<'a> {
             },
             Some(Reference{var, register, ..}) => {
                 if !var.interiors.borrow().is_empty() || var.exteriors.borrow().len() > 1 {
-                    err.desc = format!(
-                        "Uninitialising variable \"{}\" which has other other references",
-                        name
-                    );
+                    let (held, note) = held.expect("Variable has no other references");
+                    err.desc = format!("{}, so \"{}\" cannot be uninitialised", held, name);
+                    err.note = note;
                     return Err(err);
                 }
                 self.free_registers.push(register);
+                self.close_symbol(name);
                 Ok(register)
             }
         }
@@ -366,14 +648,225 @@ impl<'a> SyntaxContext<'a> {
 
     fn check_ref_is_resizable(&self, name: &str) -> Result<bool, SyntaxError> {
         let varref = self.lookup_variable(name)?;
-        let num_interiors = varref.var.interiors.borrow().len();
-        Ok(num_interiors == 0 || (num_interiors == 1 && varref.is_interior))
+        let interiors = varref.var.interiors.borrow();
+        Ok(interiors.iter().filter(|other| *other != name).all(|other| !self.overlaps(name, other)))
+    }
+
+    // Whether a reference could see another to the same variable being resized. Only interior
+    // references known to cover different elements of the outer array can't //
+    fn overlaps(&self, name: &str, other: &str) -> bool {
+        let (Ok(reference), Ok(other_ref)) = (self.lookup_variable(name), self.lookup_variable(other)) else {
+            return true;
+        };
+        match (reference.extent, other_ref.extent) {
+            (Some((start, end)), Some((other_start, other_end))) if reference.is_interior && Rc::ptr_eq(&reference.var, &other_ref.var) =>
+                start < other_end && other_start < end,
+            _ => true
+        }
+    }
+
+    fn check_writable(&mut self, name: &str, var_id: isize, line: usize, col: usize) -> Result<(), SyntaxError> {
+        self.written.insert(var_id);
+        self.modified.insert(var_id);
+        if self.read_only.contains(&var_id) {
+            return Err(SyntaxError{line, col, desc: format!(
+                "Modifying \"{}\", which a surrounding for loop depends on", name), note: None});
+        }
+        if self.is_pure && (var_id < 0 || self.pure_borrows.contains(&var_id)) {
+            return Err(SyntaxError{line, col, desc: format!(
+                "Modifying \"{}\", which pure function \"{}\" doesn't own", name, self.func_name), note: None});
+        }
+        if let Some(parallel) = &self.parallel {
+            let shared = var_id < 0 || var_id <= parallel.last_shared_id;
+            if parallel.aliases.iter().any(|alias| alias == name) || (shared && var_id != parallel.array_id) {
+                return Err(SyntaxError{line, col, desc: format!(
+                    "Modifying \"{}\" inside a parfor, whose iterations can only modify their own item", name), note: None});
+            }
+        }
+        Ok(())
+    }
+
+    fn add_callee(&mut self, func_idx: usize, args: &[PT::LookupNode], defaults: &[interpreter::Variable]) {
+        if !self.callees.contains(&func_idx) {
+            self.callees.push(func_idx);
+        }
+        let mut passed = Vec::new();
+        for arg in args {
+            let Ok(var_id) = self.get_var_id(&arg.name) else {
+                passed.push(ST::Passed::Unknown);
+                continue;
+            };
+            // A call given an element may change it, and so the variable holding it //
+            if !arg.indices.is_empty() && self.let_consts.contains_key(&var_id) {
+                self.modified.insert(var_id);
+            }
+            passed.push(match (self.let_consts.get(&var_id), self.borrow_ids.iter().position(|&id| id == var_id)) {
+                (Some(value), _) if arg.indices.is_empty() => ST::Passed::Let(var_id, value.clone()),
+                (_, Some(idx)) => ST::Passed::Param(idx),
+                _ => ST::Passed::Unknown
+            });
+        }
+        passed.extend(defaults.iter().cloned().map(ST::Passed::Const));
+        self.call_sites.push((func_idx, passed));
+    }
+
+    // Iterations of a parfor may run in any order, so side effects are rejected inside one //
+    fn check_parallel(&self, action: &str, line: usize, col: usize) -> Result<(), SyntaxError> {
+        match self.parallel {
+            Some(_) => Err(SyntaxError{line, col, desc: format!(
+                "{} inside a parfor, whose iterations may run in any order", action), note: None}),
+            None => Ok(())
+        }
+    }
+
+    // Pieces of a destructured array must be recombined, unlet or returned by the end of the function //
+    fn check_pieces(&self, returns: &[PT::FunctionParam]) -> Result<(), SyntaxError> {
+        for (var_id, source, (line, col)) in &self.pieces {
+            let live = self.locals.iter().find(|(name, r)| {
+                r.var.id == *var_id && !returns.iter().any(|p| &p.name == *name)
+            });
+            if let Some((name, _)) = live {
+                return Err(SyntaxError{line: *line, col: *col, desc: format!(
+                    "\"{}\", destructured from \"{}\", is never recombined or uninitialised", name, source), note: None});
+            }
+        }
+        Ok(())
+    }
+
+    // Strict mode requires each variable a function owns to be uninitialised or returned by its end //
+    fn check_uninitialised(&self, returns: &[PT::FunctionParam], line: usize, col: usize) -> Result<(), SyntaxError> {
+        let mut live: Vec<&String> = self.locals.iter()
+            .filter(|(name, r)| !r.is_borrowed && !returns.iter().any(|p| &p.name == *name))
+            .map(|(name, _)| name)
+            .collect();
+        live.sort();
+        match live.first() {
+            Some(name) => Err(SyntaxError{line, col, desc: format!(
+                "\"{}\" is never uninitialised or returned by function \"{}\", as strict mode requires",
+                name, self.func_name), note: None}),
+            None => Ok(())
+        }
+    }
+
+    // Sketch mode uninitialises each variable left bound at the end of the function that's provably
+    // still what its top-level let made it, as neither it nor any variable the let read has been
+    // written since. Newer ones go first, as they may have read older ones. Returns a warning for
+    // each unlet added //
+    fn sketch_unlets(
+        &mut self,
+        returns: &[PT::FunctionParam],
+        stmts: &mut Vec<Box<dyn ST::Statement>>
+    ) -> Result<Vec<SyntaxError>, SyntaxError> {
+        let mut warnings = Vec::new();
+        for (definition, var_id, used_vars) in mem::take(&mut self.definitions).into_iter().rev() {
+            let unchanged = |id: &isize| self.locals.values().any(|r| r.var.id == *id) && !self.written.contains(id);
+            let is_bound = self.locals.get(&definition.name).is_some_and(|r| r.var.id == var_id);
+            if !is_bound || returns.iter().any(|p| p.name == definition.name)
+                || !unchanged(&var_id) || !used_vars.iter().all(unchanged) {
+                continue;
+            }
+            let unlet = PT::LetUnletNode{is_unlet: true, ..definition};
+            warnings.push(SyntaxError{line: unlet.line, col: unlet.col, desc: format!(
+                "\"{}\" was still bound at the end of function \"{}\", so --sketch added \"{}\"",
+                unlet.name, self.func_name, formatter::source(&unlet)), note: None});
+            stmts.push(PT::Statement::to_syntax_node(Box::new(unlet), self)?);
+        }
+        Ok(warnings)
+    }
+
+    fn resolve_const_arg(&self, arg: &PT::ConstArg) -> Result<interpreter::Variable, SyntaxError> {
+        match arg {
+            PT::ConstArg::Value(value) => Ok(value.clone()),
+            PT::ConstArg::Name(name) => self.const_bindings.get(name).cloned().ok_or(SyntaxError{
+                line: 0, col: 0, desc: format!("\"{}\" isn't a const param of \"{}\"", name, self.func_name), note: None})
+        }
     }
 
     fn get_var_id(&self, name: &str) -> Result<isize, SyntaxError> {
         Ok(self.lookup_variable(name)?.var.id)
     }
 
+    fn mark_read(&mut self, var_id: isize) {
+        self.reads.insert(var_id, self.position.unwrap_or((0, 0)));
+    }
+
+    // The calls whose caller never reads some of what they return, so carries it as garbage until
+    // the call is undone. Values uncomputed by an unlet or passed to another function count as read //
+    fn find_garbage(&mut self) -> Vec<interpreter::Garbage> {
+        let mut found = Vec::new();
+        for (mut garbage, ids) in mem::take(&mut self.call_outputs) {
+            let unread: Vec<usize> = (0..ids.len()).filter(|i| !self.reads.contains_key(&ids[*i])).collect();
+            if unread.is_empty() {continue}
+            garbage.last_use = ids.iter().filter_map(|id| self.reads.get(id)).max().copied();
+            garbage.names = unread.into_iter().map(|i| mem::take(&mut garbage.names[i])).collect();
+            found.push(garbage);
+        }
+        found
+    }
+
+    // The ids of the variables bound in the function as a loop starts, and those written before it,
+    // with the set of those written taken afresh for the loop //
+    fn start_loop(&mut self) -> (HashSet<isize>, HashSet<isize>) {
+        (self.bound_var_ids(), mem::take(&mut self.written))
+    }
+
+    fn bound_var_ids(&self) -> HashSet<isize> {
+        self.locals_stack.iter().chain(iter::once(&self.locals)).flat_map(|locals| locals.values()).map(|r| r.var.id).collect()
+    }
+
+    // Loop-invariant code motion, from opt level 1. Sub-expressions of the loop reading only variables
+    // bound all through it and written nowhere in it are computed once, into fresh registers, before
+    // it starts. Only those that can't fail whatever they read are hoisted, as the loop may never
+    // have run them //
+    fn hoist_invariants(
+        &mut self,
+        (bound, written): (HashSet<isize>, HashSet<isize>),
+        exprs: Vec<&mut ST::ExpressionNode>
+    ) -> Vec<(usize, Vec<interpreter::Instruction>)> {
+        let loop_written = mem::replace(&mut self.written, written);
+        self.written.extend(loop_written.iter().copied());
+        if !self.hoist_invariants {
+            return Vec::new();
+        }
+        let still_bound = self.bound_var_ids();
+        let invariant: HashSet<isize> = bound.into_iter()
+            .filter(|id| !loop_written.contains(id) && still_bound.contains(id))
+            .collect();
+        let mut hoisted = Vec::new();
+        for expr in exprs {
+            self.hoist_expression(expr, &invariant, &mut hoisted);
+        }
+        hoisted
+    }
+
+    fn hoist_expression(
+        &mut self,
+        expr: &mut ST::ExpressionNode,
+        invariant: &HashSet<isize>,
+        hoisted: &mut Vec<(usize, Vec<interpreter::Instruction>)>
+    ) {
+        let is_invariant = expr.can_hoist() && !expr.is_mono() && expr.used_vars().is_subset(invariant);
+        // Constants and lone variables are no cheaper to read from a register //
+        if !is_invariant || expr.subexpressions().is_empty() {
+            for subexpr in expr.subexpressions() {
+                self.hoist_expression(subexpr, invariant, hoisted);
+            }
+            return;
+        }
+        let instructions = expr.compile();
+        let register = match hoisted.iter().find(|(_, other)| *other == instructions) {
+            Some((register, _)) => *register,
+            // Not a freed register, which a variable local to the loop may be using //
+            None => {
+                self.num_registers += 1;
+                hoisted.push((self.num_registers - 1, instructions));
+                self.num_registers - 1
+            }
+        };
+        let placeholder = Box::new(ST::HoleNode{used_vars: HashSet::new()});
+        *expr = Box::new(ST::HoistedNode{register, expr: mem::replace(expr, placeholder)});
+    }
+
     fn enter_block(&mut self) {
         let locals = HashMap::new();
         self.locals_stack.push(mem::replace(&mut self.locals, locals));
@@ -382,7 +875,31 @@ impl<'a> SyntaxContext<'a> {
     fn exit_block(&mut self) -> Result<(), SyntaxError> {
         if self.locals.len() > 0 {
             return Err(SyntaxError{line: 0, col: 0, desc:
-                String::from("Leaving block with dangling variable references")});
+                String::from("Leaving block with dangling variable references"), note: None});
+        }
+        self.exit_block_nocheck();
+        Ok(())
+    }
+
+    // As exit_block, for a branch of an if. The branches join with the references and variables
+    // bound before the if, so each must remove any it makes //
+    fn exit_branch(&mut self, branch: &str) -> Result<(), SyntaxError> {
+        let mut names: Vec<&String> = self.locals.keys().collect();
+        names.sort();
+        if let Some(&name) = names.first() {
+            let reference = &self.locals[name];
+            let (interiors, exteriors) = (reference.var.interiors.borrow(), reference.var.exteriors.borrow());
+            let mut targets: Vec<&String> = interiors.iter().chain(exteriors.iter()).filter(|n| *n != name).collect();
+            targets.sort();
+            let desc = match targets.first() {
+                Some(target) => format!(
+                    "Reference \"{}\" to \"{}\" is still bound at the end of the {} branch, so the branches would join \
+                    with different references. A reference made in a branch must be removed in it", name, target, branch),
+                None => format!(
+                    "\"{}\" is still bound at the end of the {} branch, so the branches would join with different \
+                    variables. A variable made in a branch must be uninitialised in it", name, branch)
+            };
+            return Err(SyntaxError{line: 0, col: 0, desc, note: self.binding_note(name)});
         }
         self.exit_block_nocheck();
         Ok(())
@@ -399,6 +916,14 @@ impl<'a> SyntaxContext<'a> {
 impl PT::Expression for PT::FractionNode {
     fn get_src_pos(&self) -> (usize, usize) { (self.line, self.col) }
 
+    fn constant_index(&self) -> Option<usize> {
+        if self.value.is_integer() {self.value.to_integer().to_usize()} else {None}
+    }
+
+    fn constant_value(&self) -> Option<interpreter::Variable> {
+        Some(interpreter::Variable::Frac(self.value.clone()))
+    }
+
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Expression>, SyntaxError> {
         let const_idx = ctx.add_const(
             interpreter::Variable::Frac(self.value)
@@ -410,6 +935,10 @@ impl PT::Expression for PT::FractionNode {
 impl PT::Expression for PT::StringNode {
     fn get_src_pos(&self) -> (usize, usize) { (self.line, self.col) }
 
+    fn constant_value(&self) -> Option<interpreter::Variable> {
+        Some(interpreter::Variable::Str(self.value.clone()))
+    }
+
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Expression>, SyntaxError> {
         let const_idx = ctx.add_const(
             interpreter::Variable::Str(self.value)
@@ -418,6 +947,35 @@ impl PT::Expression for PT::StringNode {
     }
 }
 
+impl PT::Expression for PT::HoleNode {
+    fn get_src_pos(&self) -> (usize, usize) { (self.line, self.col) }
+
+    fn to_syntax_node(self: Box<Self>, _ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Expression>, SyntaxError> {
+        Ok(Box::new(ST::HoleNode{used_vars: HashSet::new()}))
+    }
+}
+
+impl PT::Expression for PT::CounterNode {
+    fn get_src_pos(&self) -> (usize, usize) { (self.line, self.col) }
+
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Expression>, SyntaxError> {
+        let name = operator_symbol(&self.op);
+        if ctx.sandboxed {
+            return Err(SyntaxError{line: self.line, col: self.col, desc: format!(
+                "\"{}()\" is not allowed in sandboxed code", name), note: None});
+        }
+        if ctx.is_pure {
+            return Err(SyntaxError{line: self.line, col: self.col, desc: format!(
+                "\"{}()\" in pure function \"{}\", whose results must not vary between calls", name, ctx.func_name),
+                note: None});
+        }
+        ctx.check_parallel(&format!("\"{}()\"", name), self.line, self.col)?;
+        ctx.counters_read += 1;
+        ctx.effects.reads_input = true;
+        Ok(Box::new(ST::CounterNode{op: self.op, used_vars: HashSet::new()}))
+    }
+}
+
 impl PT::Expression for PT::BinopNode {
     fn get_src_pos(&self) -> (usize, usize) { self.lhs.get_src_pos() }
 
@@ -437,7 +995,13 @@ impl PT::Expression for PT::UniopNode {
 
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Expression>, SyntaxError> {
         let expr = self.expr.to_syntax_node(ctx)?;
-        let is_mono = expr.is_mono();
+        // Float arithmetic can't be undone exactly, so floats only flow into mono variables //
+        if ctx.strict && self.op.makes_float() && !expr.is_mono() {
+            return Err(SyntaxError{line: self.line, col: self.col, desc: format!(
+                "\"{}\" makes a mono value from non-mono operands, which strict mode requires to be mono",
+                operator_symbol(&self.op)), note: None});
+        }
+        let is_mono = expr.is_mono() || self.op.makes_float();
         let used_vars = expr.used_vars().clone();
         Ok(Box::new(ST::UniopNode{expr, is_mono, used_vars, op: self.op}))
     }
@@ -472,15 +1036,117 @@ impl PT::Expression for PT::ArrayRepeatNode {
     }
 }
 
+impl PT::Expression for PT::ComprehensionNode {
+    fn get_src_pos(&self) -> (usize, usize) { (self.line, self.col) }
+
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Expression>, SyntaxError> {
+        let mut zero_lookup = self.source.clone();
+        zero_lookup.indices.push(Box::new(PT::FractionNode{
+            value: interpreter::Fraction::zero(),
+            line: 0, col: 0
+        }));
+
+        let source = self.source.to_syntax_node_unboxed(ctx)?;
+        let register = ctx.create_iterator_ref(&self.iter_var, &zero_lookup)?;
+        let item = self.item.to_syntax_node(ctx)?;
+        let filter = match self.filter {
+            Some(filter) => Some(filter.to_syntax_node(ctx)?),
+            None => None
+        };
+        ctx.remove_ref(&self.iter_var, &zero_lookup, false)?;
+
+        // Scratch registers only live for the duration of the expression //
+        let index_register = ctx.get_free_register();
+        let acc_register = ctx.get_free_register();
+        let tmp_register = ctx.get_free_register();
+        ctx.free_registers.extend([tmp_register, acc_register, index_register]);
+
+        let is_mono = source.is_mono || item.is_mono() || filter.as_ref().is_some_and(|f| f.is_mono());
+        let mut used_vars = source.used_vars.clone();
+        used_vars.extend(item.used_vars());
+        if let Some(filter) = &filter {
+            used_vars.extend(filter.used_vars());
+        }
+
+        Ok(Box::new(ST::ComprehensionNode{
+            item, source, filter, register, index_register, acc_register, tmp_register, is_mono, used_vars
+        }))
+    }
+}
+
+impl PT::Expression for PT::CallExprNode {
+    fn get_src_pos(&self) -> (usize, usize) { (self.line, self.col) }
+
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Expression>, SyntaxError> {
+        let err = |desc: String| SyntaxError{line: self.line, col: self.col, desc, note: None};
+        let proto = ctx.lookup_function_prototype(&self.name).map_err(|e| err(e.desc))?;
+        if !proto.is_pure {
+            return Err(err(format!("Function \"{}\" isn't pure, so can't be called in an expression", self.name)));
+        }
+        let num_required = proto.borrow_params.len() - proto.borrow_defaults.len();
+        if self.borrow_args.len() < num_required || self.borrow_args.len() > proto.borrow_params.len() {
+            return Err(err(match num_required == proto.borrow_params.len() {
+                true => format!("Function \"{}\" borrows {} argument(s), but {} are given",
+                                self.name, num_required, self.borrow_args.len()),
+                false => format!("Function \"{}\" borrows {} to {} argument(s), but {} are given",
+                                 self.name, num_required, proto.borrow_params.len(), self.borrow_args.len())
+            }));
+        }
+        let (func_idx, is_mono) = (proto.id, proto.is_mono);
+        let defaults = proto.borrow_defaults[self.borrow_args.len() - num_required..].to_vec();
+        ctx.add_callee(func_idx, &self.borrow_args, &defaults);
+
+        // Pure functions take no references, so every argument is an unlinked borrow //
+        for arg in self.borrow_args.iter() {
+            if !ctx.check_singly_owned(&arg.name)? {
+                return Err(ctx.not_singly_owned("Call", &arg.name, self.line, self.col));
+            }
+        }
+        let default_args = defaults.into_iter().map(|value| ctx.add_const(value)).collect();
+        let borrow_args = self.borrow_args.into_iter()
+                                          .map(|a| a.to_syntax_node_unboxed(ctx))
+                                          .collect::<Result<Vec<_>, _>>()?;
+        if ctx.strict && is_mono && !borrow_args.iter().any(|a| a.is_mono) {
+            return Err(SyntaxError{line: self.line, col: self.col, note: None, desc: format!(
+                "Mono function \"{}\" makes a mono value from non-mono arguments, which strict mode requires to be mono",
+                self.name)});
+        }
+        let is_mono = is_mono || borrow_args.iter().any(|a| a.is_mono);
+        let used_vars = borrow_args.iter().flat_map(|a| a.used_vars.iter().copied()).collect();
+
+        let remember = ctx.remembering;
+        Ok(Box::new(ST::CallExprNode{func_idx, remember, borrow_args, default_args, is_mono, used_vars}))
+    }
+}
+
 impl PT::Expression for PT::LookupNode {
     fn get_src_pos(&self) -> (usize, usize) { (self.line, self.col) }
 
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Expression>, SyntaxError> {
+        // Const params, and borrow params specialised to the constant every call passes, are
+        // substituted with their values //
+        let value = match ctx.specialised.get(&self.name) {
+            Some(value) => Some(value.clone()),
+            None if ctx.lookup_variable(&self.name).is_err() => ctx.const_bindings.get(&self.name).cloned(),
+            None => None
+        };
+        if let (true, Some(value)) = (self.indices.is_empty(), value) {
+            let is_str = matches!(value, interpreter::Variable::Str(_));
+            let const_idx = ctx.add_const(value);
+            return Ok(match is_str {
+                true => Box::new(ST::StringNode{const_idx, used_vars: HashSet::new()}),
+                false => Box::new(ST::FractionNode{const_idx, used_vars: HashSet::new()})
+            });
+        }
         Ok(Box::new(self.to_syntax_node_unboxed(ctx)?))
     }
 }
 impl PT::LookupNode {
     fn to_syntax_node_unboxed(self, ctx: &mut SyntaxContext) -> Result<ST::LookupNode, SyntaxError> {
+        if ctx.parallel.as_ref().is_some_and(|parallel| parallel.aliases.contains(&self.name)) {
+            return Err(SyntaxError{line: self.line, col: self.col, desc: format!(
+                "Reading \"{}\" inside a parfor over it, whose other items are being modified", self.name), note: None});
+        }
         let var = ctx.lookup_variable(&self.name)?;
         let register = var.register;
         let is_global = var.is_global;
@@ -496,6 +1162,8 @@ impl PT::LookupNode {
         let index_used_vars = used_vars.clone();
         let var_id = ctx.get_var_id(&self.name)?;
         used_vars.insert(var_id);
+        ctx.mark_read(var_id);
+        ctx.effects.touches_globals |= is_global;
 
         Ok(ST::LookupNode{register, is_global, indices, used_vars, is_mono, var_is_mono, var_id, index_used_vars})
     }
@@ -507,6 +1175,15 @@ impl PT::LookupNode {
 
 impl PT::Statement for PT::PrintNode {
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        if ctx.sandboxed {
+            return Err(SyntaxError{line: 0, col: 0, desc: String::from("Printing is not allowed in sandboxed code"), note: None});
+        }
+        if ctx.is_pure {
+            return Err(SyntaxError{line: 0, col: 0, desc: format!(
+                "Printing in pure function \"{}\"", ctx.func_name), note: None});
+        }
+        ctx.check_parallel("Printing", 0, 0)?;
+        ctx.effects.prints = true;
         let items: Result<Vec<_>, _> = self.items.into_iter()
                                                  .map(|i| i.to_syntax_node(ctx))
                                                  .collect();
@@ -521,16 +1198,33 @@ impl PT::Statement for PT::PrintNode {
 impl PT::Statement for PT::LetUnletNode {
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
         let is_unlet = self.is_unlet;
+        if is_unlet {
+            if let Ok(var_id) = ctx.get_var_id(&self.name) {
+                ctx.mark_read(var_id);
+            }
+        }
+        // A top-level let reading no counter may be undone by sketch mode //
+        let definition = (ctx.sketch && !is_unlet && ctx.locals_stack.is_empty()).then(|| (*self).clone());
+        let counters_read = ctx.counters_read;
         let register = if self.is_unlet {ctx.remove_variable(&self.name)?}
                        else             {ctx.create_variable(&self.name)?};
+        if let (false, Some(value)) = (is_unlet, self.rhs.constant_value()) {
+            ctx.let_consts.insert(ctx.get_var_id(&self.name)?, value);
+        }
         let rhs = self.rhs.to_syntax_node(ctx)?;
         let is_mono = self.name.starts_with(".");
+        if let Some(definition) = definition {
+            if ctx.counters_read == counters_read {
+                ctx.definitions.push((definition, ctx.get_var_id(&self.name)?, rhs.used_vars().clone()));
+            }
+        }
 
         if !is_mono && rhs.is_mono() {
             let verb = if is_unlet {"Uninitialising"} else {"Initialising"};
             return Err(SyntaxError{
                 line: self.line, col: self.col,
-                desc: format!("{} variable \"{}\" using mono information", verb, self.name)
+                desc: format!("{} variable \"{}\" using mono information", verb, self.name),
+                note: None
             });
         }
 
@@ -541,9 +1235,18 @@ impl PT::Statement for PT::LetUnletNode {
 impl PT::Statement for PT::RefUnrefNode {
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
         let is_unref = self.is_unref;
-        let register = if self.is_unref {ctx.remove_ref(&self.name, &self.rhs)?}
-                       else             {ctx.create_ref(&self.name, &self.rhs)?};
+        // Either name may be used to modify the variable they share //
+        if let Ok(var_id) = ctx.get_var_id(&self.rhs.name) {
+            ctx.written.insert(var_id);
+            ctx.modified.insert(var_id);
+        }
+        let register = if self.is_unref {ctx.remove_ref(&self.name, &self.rhs, self.range.is_some())?}
+                       else             {ctx.create_ref(&self.name, &self.rhs, self.range.as_ref())?};
         let rhs = self.rhs.to_syntax_node_unboxed(ctx)?;
+        let range = match self.range {
+            Some((start, end)) => Some((start.to_syntax_node(ctx)?, end.to_syntax_node(ctx)?)),
+            None => None
+        };
         let is_mono = self.name.starts_with(".");
 
         let problem = if      is_mono != rhs.is_mono     {Some("RHS")}
@@ -553,11 +1256,48 @@ impl PT::Statement for PT::RefUnrefNode {
         if let Some(problem) = problem {
             return Err(SyntaxError{
                 line: self.line, col: self.col,
-                desc: format!("Reference \"{}\" has different mono-ness to {}", self.name, problem)
+                desc: format!("Reference \"{}\" has different mono-ness to {}", self.name, problem),
+                note: None
             });
         }
 
-        Ok(Box::new(ST::RefUnrefNode{is_unref, register, rhs, is_mono}))
+        Ok(Box::new(ST::RefUnrefNode{is_unref, register, rhs, range, is_mono, check_aliasing: ctx.check_aliasing}))
+    }
+}
+
+impl PT::Statement for PT::RelinkNode {
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        let (line, col) = (self.line, self.col);
+        let err = |desc: String| SyntaxError{line, col, desc, note: None};
+        let register = ctx.relink_ref(&self.name, &self.old, &self.new).map_err(|e| err(e.desc))?;
+        let old = self.old.to_syntax_node_unboxed(ctx)?;
+        let new = self.new.to_syntax_node_unboxed(ctx)?;
+        ctx.written.insert(old.var_id);
+        ctx.modified.insert(old.var_id);
+        let is_mono = self.name.starts_with(".");
+        if [&old, &new].iter().any(|lookup| lookup.is_mono != is_mono || lookup.var_is_mono != is_mono) {
+            return Err(err(format!("Reference \"{}\" has different mono-ness to the parts it's moved between", self.name)));
+        }
+
+        Ok(Box::new(ST::RelinkNode{register, old, new, is_mono, check_aliasing: ctx.check_aliasing}))
+    }
+}
+
+impl PT::Statement for PT::MoveNode {
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        let is_mono = self.name.starts_with(".");
+        if self.src.starts_with(".") != is_mono {
+            return Err(SyntaxError{line: self.line, col: self.col, desc: format!(
+                "Moving \"{}\" into \"{}\", which has different mono-ness", self.src, self.name), note: None});
+        }
+        let var_id = ctx.get_var_id(&self.src)?;
+        ctx.check_writable(&self.src, var_id, self.line, self.col)?;
+        ctx.mark_read(var_id);
+
+        // The new variable is created before the old one is removed, so they don't share a register //
+        let to = ctx.create_variable(&self.name)?;
+        let from = ctx.remove_variable(&self.src)?;
+        Ok(Box::new(ST::MoveNode{from, to, is_mono}))
     }
 }
 
@@ -568,21 +1308,22 @@ impl PT::Statement for PT::ModopNode {
         let lookup = self.lookup.to_syntax_node_unboxed(ctx)?;
         let rhs = self.rhs.to_syntax_node(ctx)?;
         let is_mono = lookup.var_is_mono;
+        ctx.check_writable(&varname, lookup.var_id, line, col)?;
 
         if !is_mono && (lookup.is_mono || rhs.is_mono()) {
             return Err(SyntaxError{line, col, desc: format!(
                 "Modifying variable \"{}\" using mono information", varname
-            )});
+            ), note: None});
         }
         if rhs.used_vars().contains(&lookup.var_id) {
             return Err(SyntaxError{line, col, desc: format!(
                 "Self-modification of variable \"{}\"", varname
-            )});
+            ), note: None});
         }
         if lookup.index_used_vars.contains(&lookup.var_id) {
             return Err(SyntaxError{line, col, desc: format!(
                 "Variable \"{}\" is used to index itself, which can lead to self-modification", varname
-            )});
+            ), note: None});
         }
 
         Ok(Box::new(ST::ModopNode{lookup, rhs, is_mono, op: self.op}))
@@ -597,11 +1338,17 @@ impl PT::Statement for PT::PushPullNode {
                        else            {ctx.create_variable(&self.name)?};
         let lookup = self.lookup.to_syntax_node_unboxed(ctx)?;
         let is_mono = self.name.starts_with(".");
+        ctx.check_writable(&lookup_name, lookup.var_id, self.line, self.col)?;
 
 
-        let mut error = SyntaxError{line: self.line, col: self.col, desc: String::new()};
-        if !ctx.check_ref_is_resizable(&lookup_name)? {
-            error.desc = format!("Resizing \"{}\" when other references to its interior exist", lookup_name);
+        let mut error = SyntaxError{line: self.line, col: self.col, desc: String::new(), note: None};
+        if lookup.indices.is_empty() && ctx.lookup_variable(&lookup_name)?.is_slice {
+            error.desc = format!("Resizing \"{}\", which references a range of elements", lookup_name);
+            return Err(error);
+        } else if !ctx.check_ref_is_resizable(&lookup_name)? {
+            let (held, note) = ctx.other_reference(&lookup_name, true).expect("Variable has no other interior references");
+            error.desc = format!("{}, so \"{}\" cannot be {}", held, lookup_name, if self.is_push {"pushed to"} else {"pulled from"});
+            error.note = note;
             return Err(error);
         } else if is_mono != lookup.var_is_mono {
             error.desc = String::from("Can only push to / pull from a variable of matching mono-ness");
@@ -611,37 +1358,100 @@ impl PT::Statement for PT::PushPullNode {
             return Err(error);
         }
 
-        Ok(Box::new(ST::PushPullNode{register, lookup, is_mono, is_push: self.is_push}))
+        Ok(Box::new(ST::PushPullNode{register, lookup, is_mono, is_push: self.is_push, check_aliasing: ctx.check_aliasing}))
+    }
+}
+
+impl PT::Statement for PT::DestructureLetNode {
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        let mut error = SyntaxError{line: self.line, col: self.col, desc: String::new(), note: None};
+
+        let rests: Vec<usize> = (0..self.pattern.len()).filter(|&i| self.pattern[i].1).collect();
+        let rest_idx = match rests[..] {
+            [rest_idx] => rest_idx,
+            _ => {
+                error.desc = String::from("Destructuring pattern needs exactly one rest element, e.g. \"rest...\"");
+                return Err(error);
+            }
+        };
+        let is_mono = self.name.starts_with(".");
+        if let Some((name, _)) = self.pattern.iter().find(|(name, _)| name.starts_with(".") != is_mono) {
+            error.desc = format!("Destructuring \"{}\" into \"{}\", which has different mono-ness", self.name, name);
+            return Err(error);
+        }
+        if !self.is_unlet {
+            ctx.check_writable(&self.name, ctx.get_var_id(&self.name)?, self.line, self.col)?;
+        }
+
+        // New variables are created before the old ones are removed, so no registers are shared //
+        let (register, registers) = if self.is_unlet {
+            let register = ctx.create_variable(&self.name)?;
+            let registers = self.pattern.iter()
+                                        .map(|(name, _)| ctx.remove_variable(name))
+                                        .collect::<Result<Vec<_>, _>>()?;
+            (register, registers)
+        } else {
+            let registers = self.pattern.iter()
+                                        .map(|(name, _)| ctx.create_variable(name))
+                                        .collect::<Result<Vec<_>, _>>()?;
+            for (name, _) in &self.pattern {
+                let var_id = ctx.get_var_id(name)?;
+                ctx.pieces.push((var_id, self.name.clone(), (self.line, self.col)));
+            }
+            (ctx.remove_variable(&self.name)?, registers)
+        };
+
+        let rest_register = registers[rest_idx];
+        let prefix = registers[..rest_idx].to_vec();
+        let suffix = registers[rest_idx + 1..].to_vec();
+        Ok(Box::new(ST::DestructureLetNode{is_unlet: self.is_unlet, register, rest_register, prefix, suffix, is_mono}))
     }
 }
 
 impl PT::Statement for PT::IfNode {
+    fn calls(&self) -> Vec<&PT::CallNode> {
+        self.if_stmts.iter().chain(self.else_stmts.iter()).flat_map(|s| s.calls()).collect()
+    }
+
+    fn configure(&mut self, cfg: &Cfg) -> Result<(), SyntaxError> {
+        configure_stmts(&mut self.if_stmts, cfg)?;
+        configure_stmts(&mut self.else_stmts, cfg)
+    }
+
+    fn substatements(&mut self) -> Vec<&mut Vec<PT::StatementNode>> {
+        vec![&mut self.if_stmts, &mut self.else_stmts]
+    }
+
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
         let (fwd_line, fwd_col) = self.fwd_expr.get_src_pos();
         let (bkwd_line, bkwd_col) = self.bkwd_expr.get_src_pos();
+        if ctx.strict && self.bkwd_elided {
+            return Err(SyntaxError{line: fwd_line, col: fwd_col, desc: String::from(
+                "Strict mode requires the backward condition of an if statement, e.g. \"~if (x == 0);\""), note: None});
+        }
 
         let fwd_expr = self.fwd_expr.to_syntax_node(ctx)?;
         ctx.enter_block();
         let if_stmts = self.if_stmts.into_iter()
                                     .map(|s| s.to_syntax_node(ctx))
                                     .collect::<Result<Vec<_>, _>>()?;
-        ctx.exit_block()?;
+        ctx.exit_branch("if")?;
         ctx.enter_block();
         let else_stmts = self.else_stmts.into_iter()
                                     .map(|s| s.to_syntax_node(ctx))
                                     .collect::<Result<Vec<_>, _>>()?;
-        ctx.exit_block()?;
+        ctx.exit_branch("else")?;
         let bkwd_expr = self.bkwd_expr.to_syntax_node(ctx)?;
         let is_mono = fwd_expr.is_mono();
 
         let all_mono_stmts = if_stmts.iter().chain(else_stmts.iter()).all(|s| s.is_mono());
         if fwd_expr.is_mono() && !all_mono_stmts {
             return Err(SyntaxError{line: fwd_line, col: fwd_col, desc: String::from(
-                "Forward condition in If statement is mono but not all substatements are mono")})
+                "Forward condition in If statement is mono but not all substatements are mono"), note: None})
         }
         if bkwd_expr.is_mono(){
             return Err(SyntaxError{line: bkwd_line, col: bkwd_col, desc: String::from(
-                "Backward condition in If statement is mono")})
+                "Backward condition in If statement is mono"), note: None})
         }
 
         Ok(Box::new(ST::IfNode{fwd_expr, if_stmts, else_stmts, bkwd_expr, is_mono}))
@@ -649,8 +1459,21 @@ impl PT::Statement for PT::IfNode {
 }
 
 impl PT::Statement for PT::WhileNode {
+    fn calls(&self) -> Vec<&PT::CallNode> {
+        self.stmts.iter().flat_map(|s| s.calls()).collect()
+    }
+
+    fn configure(&mut self, cfg: &Cfg) -> Result<(), SyntaxError> {
+        configure_stmts(&mut self.stmts, cfg)
+    }
+
+    fn substatements(&mut self) -> Vec<&mut Vec<PT::StatementNode>> {
+        vec![&mut self.stmts]
+    }
+
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
         let (line, col) = self.fwd_expr.get_src_pos();
+        let loop_start = ctx.start_loop();
         let fwd_expr = self.fwd_expr.to_syntax_node(ctx)?;
         ctx.enter_block();
         let stmts = self.stmts.into_iter()
@@ -667,24 +1490,44 @@ impl PT::Statement for PT::WhileNode {
 
         if is_mono && !all_mono_stmts {
             return Err(SyntaxError{line, col, desc: String::from(
-                "Non-mono statement in mono while loop")});
+                "Non-mono statement in mono while loop"), note: None});
         }
         if is_mono != bkwd_expr.is_none() {
             return Err(SyntaxError{line, col, desc: String::from(
-                "A while loop's reverse condition must be omitted iff the loop is mono")});
+                "A while loop's reverse condition must be omitted iff the loop is mono"), note: None});
         }
         if let Some(expr) = &bkwd_expr {
             if expr.is_mono() {
                 return Err(SyntaxError{line, col, desc: String::from(
-                    "Backward condition in while loop is mono")});
+                    "Backward condition in while loop is mono"), note: None});
             }
         }
 
-        Ok(Box::new(ST::WhileNode{fwd_expr, stmts, bkwd_expr, is_mono}))
+        let mut node = ST::WhileNode{fwd_expr, stmts, bkwd_expr, is_mono, hoisted: Vec::new()};
+        let exprs = match is_mono {
+            true => Vec::new(),
+            false => iter::once(&mut node.fwd_expr).chain(node.bkwd_expr.as_mut())
+                                                  .chain(node.stmts.iter_mut().flat_map(|s| s.expressions()))
+                                                  .collect()
+        };
+        node.hoisted = ctx.hoist_invariants(loop_start, exprs);
+        Ok(Box::new(node))
     }
 }
 
 impl PT::Statement for PT::ForNode {
+    fn calls(&self) -> Vec<&PT::CallNode> {
+        self.stmts.iter().flat_map(|s| s.calls()).collect()
+    }
+
+    fn configure(&mut self, cfg: &Cfg) -> Result<(), SyntaxError> {
+        configure_stmts(&mut self.stmts, cfg)
+    }
+
+    fn substatements(&mut self) -> Vec<&mut Vec<PT::StatementNode>> {
+        vec![&mut self.stmts]
+    }
+
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
 
         let mut zero_lookup = self.iterator.clone();
@@ -693,35 +1536,68 @@ impl PT::Statement for PT::ForNode {
             line: 0, col: 0
         }));
         
-        let register = ctx.create_ref(&self.iter_var, &zero_lookup)?;
+        let loop_start = ctx.start_loop();
+        let register = ctx.create_iterator_ref(&self.iter_var, &zero_lookup)?;
         let (iter_line, iter_col) = (self.iterator.line, self.iterator.col);
         let iterator = self.iterator.to_syntax_node_unboxed(ctx)?;
+        // The iteration variable refers into the array, so may modify it //
+        ctx.written.insert(iterator.var_id);
+        ctx.modified.insert(iterator.var_id);
+
+        // The index is regenerated by the iterator, so the body can't change it //
+        let num_read_only = ctx.read_only.len();
+        let index_register = match &self.index_var {
+            Some(index_var) => {
+                let index_register = ctx.create_variable(index_var)?;
+                ctx.read_only.push(ctx.get_var_id(index_var)?);
+                Some(index_register)
+            },
+            None => None
+        };
+        let outer_parallel = ctx.parallel.clone();
+        if self.parallel {
+            let array_id = iterator.var_id;
+            let mut aliases = outer_parallel.as_ref().map_or(Vec::new(), |parallel| parallel.aliases.clone());
+            for locals in ctx.locals_stack.iter().chain(iter::once(&ctx.locals)).chain(iter::once(ctx.globals)) {
+                for (name, reference) in locals.iter() {
+                    if reference.var.id == array_id && *name != self.iter_var && !aliases.contains(name) {
+                        aliases.push(name.clone());
+                    }
+                }
+            }
+            ctx.parallel = Some(Parallel{array_id, aliases, last_shared_id: ctx.last_var_id, catch_contained: false});
+        }
         ctx.enter_block();
-        let stmts = self.stmts.into_iter()
+        let mut stmts = self.stmts.into_iter()
                               .map(|s| s.to_syntax_node(ctx))
                               .collect::<Result<Vec<_>, _>>()?;
         ctx.exit_block()?;
+        ctx.parallel = outer_parallel;
+        ctx.read_only.truncate(num_read_only);
+        if let Some(index_var) = &self.index_var {
+            ctx.remove_variable(index_var)?;
+        }
         let is_mono = self.iter_var.starts_with(".");
 
-        ctx.remove_ref(&self.iter_var, &zero_lookup)?;
+        ctx.remove_ref(&self.iter_var, &zero_lookup, false)?;
         
         if is_mono {
             if !iterator.var_is_mono {
                 return Err(SyntaxError{
                     line: iter_line, col: iter_col, desc: String::from(
-                        "Creating mono iteration var refernce to non-mono iterator")});
+                        "Creating mono iteration var refernce to non-mono iterator"), note: None});
             }
             if !stmts.iter().all(|s| s.is_mono()) {
                 return Err(SyntaxError{
                     line: iter_line, col: iter_col, desc: String::from(
-                        "Mono for loop contains some non-mono statements")});
+                        "Mono for loop contains some non-mono statements"), note: None});
             }
         } else if iterator.is_mono {
             return Err(SyntaxError{
                 line: iter_line, col: iter_col, desc: format!(
                     "Assigning to non-mono iteration variable \"{}\" using mono information",
                     self.iter_var
-                )});
+                ), note: None});
         }
 
         /* 
@@ -732,17 +1608,106 @@ impl PT::Statement for PT::ForNode {
         is not invertible
         */
 
-        Ok(Box::new(ST::ForNode{register, iterator, stmts, is_mono}))
+        // Iterations of a parfor share no registers //
+        let exprs = match is_mono || self.parallel {
+            true => Vec::new(),
+            false => stmts.iter_mut().flat_map(|s| s.expressions()).collect()
+        };
+        let hoisted = ctx.hoist_invariants(loop_start, exprs);
+        Ok(Box::new(ST::ForNode{
+            register, index_register, reversed: self.reversed, parallel: self.parallel, iterator, stmts, is_mono, hoisted
+        }))
+    }
+}
+
+impl PT::Statement for PT::ForRangeNode {
+    fn calls(&self) -> Vec<&PT::CallNode> {
+        self.stmts.iter().flat_map(|s| s.calls()).collect()
+    }
+
+    fn configure(&mut self, cfg: &Cfg) -> Result<(), SyntaxError> {
+        configure_stmts(&mut self.stmts, cfg)
+    }
+
+    fn substatements(&mut self) -> Vec<&mut Vec<PT::StatementNode>> {
+        vec![&mut self.stmts]
+    }
+
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+
+        let start = self.start.to_syntax_node(ctx)?;
+        let end = self.end.to_syntax_node(ctx)?;
+        let step = match self.step {
+            Some(step) => step.to_syntax_node(ctx)?,
+            None => Box::new(ST::FractionNode{
+                const_idx: ctx.add_const(interpreter::Variable::Frac(interpreter::Fraction::one())),
+                used_vars: HashSet::new()
+            })
+        };
+
+        // The range is recomputed when iterating backwards, so nothing it depends on may change //
+        let loop_start = ctx.start_loop();
+        let register = ctx.create_variable(&self.iter_var)?;
+        let num_read_only = ctx.read_only.len();
+        ctx.read_only.push(ctx.get_var_id(&self.iter_var)?);
+        for expr in [&start, &end, &step] {
+            ctx.read_only.extend(expr.used_vars());
+        }
+        ctx.enter_block();
+        let mut stmts = self.stmts.into_iter()
+                              .map(|s| s.to_syntax_node(ctx))
+                              .collect::<Result<Vec<_>, _>>()?;
+        ctx.exit_block()?;
+        ctx.read_only.truncate(num_read_only);
+        ctx.remove_variable(&self.iter_var)?;
+
+        let is_mono = self.iter_var.starts_with(".");
+        if is_mono {
+            if !stmts.iter().all(|s| s.is_mono()) {
+                return Err(SyntaxError{
+                    line: self.line, col: self.col, desc: String::from(
+                        "Mono for loop contains some non-mono statements"), note: None});
+            }
+        } else if start.is_mono() || end.is_mono() || step.is_mono() {
+            return Err(SyntaxError{
+                line: self.line, col: self.col, desc: format!(
+                    "Assigning to non-mono iteration variable \"{}\" using mono information",
+                    self.iter_var
+                ), note: None});
+        }
+
+        let exprs = match is_mono {
+            true => Vec::new(),
+            false => stmts.iter_mut().flat_map(|s| s.expressions()).collect()
+        };
+        let hoisted = ctx.hoist_invariants(loop_start, exprs);
+        Ok(Box::new(ST::ForRangeNode{register, start, end, step, stmts, is_mono, hoisted}))
     }
 }
 
 impl PT::Statement for PT::DoYieldNode {
+    fn calls(&self) -> Vec<&PT::CallNode> {
+        self.do_stmts.iter().chain(self.yield_stmts.iter()).flat_map(|s| s.calls()).collect()
+    }
+
+    fn configure(&mut self, cfg: &Cfg) -> Result<(), SyntaxError> {
+        configure_stmts(&mut self.do_stmts, cfg)?;
+        configure_stmts(&mut self.yield_stmts, cfg)
+    }
+
+    fn substatements(&mut self) -> Vec<&mut Vec<PT::StatementNode>> {
+        vec![&mut self.do_stmts, &mut self.yield_stmts]
+    }
+
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
 
         ctx.enter_block();
+        let outer = mem::replace(&mut ctx.uncomputing, true);
         let do_stmts = self.do_stmts.into_iter()
                                     .map(|s| s.to_syntax_node(ctx))
-                                    .collect::<Result<Vec<_>, _>>()?;
+                                    .collect::<Result<Vec<_>, _>>();
+        ctx.uncomputing = outer;
+        let do_stmts = do_stmts?;
         ctx.enter_block();
         let yield_stmts = self.yield_stmts.into_iter()
                                           .map(|s| s.to_syntax_node(ctx))
@@ -754,14 +1719,393 @@ impl PT::Statement for PT::DoYieldNode {
     }
 }
 
+impl PT::Statement for PT::ForGenNode {
+    fn calls(&self) -> Vec<&PT::CallNode> {
+        self.stmts.iter().flat_map(|s| s.calls()).collect()
+    }
+
+    fn configure(&mut self, cfg: &Cfg) -> Result<(), SyntaxError> {
+        configure_stmts(&mut self.stmts, cfg)
+    }
+
+    fn substatements(&mut self) -> Vec<&mut Vec<PT::StatementNode>> {
+        vec![&mut self.stmts]
+    }
+
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        let err = |desc: String| SyntaxError{line: self.line, col: self.col, desc, note: None};
+
+        let proto = ctx.lookup_function_prototype(&self.name).map_err(|mut e| {
+            e.line = self.line;
+            e.col = self.col;
+            e
+        })?;
+        if !proto.is_generator {
+            return Err(err(format!("Looping over \"{}\", which isn't marked #[generator]", self.name)));
+        }
+        if self.borrow_args.len() != proto.borrow_params.len() {
+            return Err(err(format!("Generator \"{}\" borrows {} argument(s), but {} are given",
+                                   self.name, proto.borrow_params.len(), self.borrow_args.len())));
+        }
+        let func_idx = proto.id;
+        if ctx.is_pure {
+            return Err(err(format!("Pure function \"{}\" loops over generator \"{}\"", ctx.func_name, self.name)));
+        }
+        ctx.check_parallel(&format!("Looping over generator \"{}\"", self.name), self.line, self.col)?;
+        ctx.add_callee(func_idx, &self.borrow_args, &[]);
+        for arg in self.borrow_args.iter() {
+            if !ctx.check_singly_owned(&arg.name)? {
+                return Err(ctx.not_singly_owned("Generator loop", &arg.name, self.line, self.col));
+            }
+        }
+        let borrow_args = self.borrow_args.into_iter()
+                                          .map(|a| a.to_syntax_node_unboxed(ctx))
+                                          .collect::<Result<Vec<_>, _>>()?;
+        ctx.written.extend(borrow_args.iter().map(|arg| arg.var_id));
+        ctx.modified.extend(borrow_args.iter().map(|arg| arg.var_id));
+
+        // The suspended generator holds its args, and each value is taken back by the next resume //
+        let register = ctx.create_variable(&self.iter_var)?;
+        let num_read_only = ctx.read_only.len();
+        ctx.read_only.push(ctx.get_var_id(&self.iter_var)?);
+        ctx.read_only.extend(borrow_args.iter().map(|arg| arg.var_id));
+        let outer_floor = ctx.catch_floor.replace((ctx.tries,
+            "Catch inside a loop over a generator, which must be within a try in the loop so it only undoes its own iteration"));
+        ctx.enter_block();
+        let stmts = self.stmts.into_iter()
+                              .map(|s| s.to_syntax_node(ctx))
+                              .collect::<Result<Vec<_>, _>>();
+        ctx.catch_floor = outer_floor;
+        let stmts = stmts?;
+        ctx.exit_block()?;
+        ctx.read_only.truncate(num_read_only);
+        ctx.remove_variable(&self.iter_var)?;
+
+        Ok(Box::new(ST::ForGenNode{register, func_idx, borrow_args, stmts}))
+    }
+}
+
+impl PT::Statement for PT::YieldNode {
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        let err = |desc: &str| Err(SyntaxError{line: 0, col: 0, desc: String::from(desc), note: None});
+        if !ctx.is_generator {
+            return err("Yielding outside a function marked #[generator]");
+        }
+        if ctx.tries > 0 {
+            return err("Yielding inside a try, whose catch can't undo handing the value out");
+        }
+        if ctx.uncomputing {
+            return err("Yielding inside a do block, which is undone straight after");
+        }
+        ctx.check_parallel("Yielding", 0, 0)?;
+        let expr = self.expr.to_syntax_node(ctx)?;
+        if expr.is_mono() {
+            return err("Yielding mono information");
+        }
+        Ok(Box::new(ST::YieldNode{expr}))
+    }
+}
+
+impl PT::Statement for PT::PositionedNode {
+    fn calls(&self) -> Vec<&PT::CallNode> {
+        self.stmt.calls()
+    }
+
+    fn configure(&mut self, cfg: &Cfg) -> Result<(), SyntaxError> {
+        self.stmt.configure(cfg)
+    }
+
+    fn substatements(&mut self) -> Vec<&mut Vec<PT::StatementNode>> {
+        self.stmt.substatements()
+    }
+
+    fn desugar(&self) -> Option<Result<Vec<PT::StatementNode>, SyntaxError>> {
+        self.stmt.desugar()
+    }
+
+    fn macro_call(&self) -> Option<&PT::MacroCallNode> {
+        self.stmt.macro_call()
+    }
+
+    fn const_eval(&mut self) -> Option<&mut PT::ConstEvalNode> {
+        self.stmt.const_eval()
+    }
+
+    fn take_attributes(&mut self) -> Vec<PT::Attribute> {
+        mem::take(&mut self.attributes)
+    }
+
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        let (line, col) = (self.line, self.col);
+        let outer_position = ctx.position.replace((line, col));
+        // Errors raised without a position are attributed to the enclosing statement //
+        let stmt = self.stmt.to_syntax_node(ctx);
+        ctx.position = outer_position;
+        let stmt = stmt.map_err(|mut err| {
+            if err.line == 0 {
+                err.line = line;
+                err.col = col;
+            }
+            err
+        })?;
+        Ok(Box::new(ST::PositionedNode{line, col, stmt}))
+    }
+}
+
+impl PT::Statement for PT::StaticAssertNode {
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        let PT::StaticAssertNode{line, col, expr, message} = *self;
+        let err = |desc: String| SyntaxError{line, col, desc, note: None};
+
+        // Any consts added for the condition are only needed while it's evaluated //
+        let num_consts = ctx.consts.len();
+        let expr = expr.to_syntax_node(ctx)?;
+        if !expr.used_vars().is_empty() {
+            return Err(err(String::from("Static assertions can only depend on constants")));
+        }
+        let code = compile_expression(&expr);
+        if code.fwd.iter().any(|i| matches!(i, interpreter::Instruction::Call{..})) {
+            return Err(err(String::from("Static assertions can't call functions")));
+        }
+        if code.fwd.iter().any(|i| matches!(i, interpreter::Instruction::Steps | interpreter::Instruction::ClockMs)) {
+            return Err(err(String::from("Static assertions can't read steps() or clock_ms(), which vary between runs")));
+        }
+        let value = interpreter::Interpreter::evaluate_const(&code, &ctx.consts);
+        ctx.consts.truncate(num_consts);
+
+        match value.map(|value| value.to_bool()) {
+            Ok(Ok(true)) => Ok(Box::new(ST::StaticAssertNode)),
+            Ok(Ok(false)) => Err(err(format!("Static assertion failed: {}", message))),
+            _ => Err(err(String::from("Static assertion couldn't be evaluated")))
+        }
+    }
+}
+
+impl PT::Statement for PT::ConstEvalNode {
+    fn const_eval(&mut self) -> Option<&mut PT::ConstEvalNode> {
+        Some(self)
+    }
+
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        let PT::ConstEvalNode{line, col, name, func_name, mut args, value} = *self;
+        let err = |desc: String| SyntaxError{line, col, desc, note: None};
+
+        // Pure functions are evaluated with every const eval still 0, so mustn't hold one //
+        if ctx.is_pure {
+            return Err(err(format!("Const eval in pure function \"{}\"", ctx.func_name)));
+        }
+        let rhs: PT::ExpressionNode = match value {
+            Some(value) => const_expression(value, line, col).ok_or_else(|| err(format!(
+                "\"{}\" returned a float or a handle, which can't be a constant", func_name)))?,
+            None => {
+                let proto = ctx.lookup_function_prototype(&func_name).map_err(|e| err(e.desc))?;
+                if !proto.is_pure {
+                    return Err(err(format!("Only pure functions can be evaluated at compile time, and \"{}\" isn't #[pure]", func_name)));
+                }
+                let num_required = proto.borrow_params.len() - proto.borrow_defaults.len();
+                if args.len() < num_required || args.len() > proto.borrow_params.len() {
+                    return Err(err(format!("Function \"{}\" borrows {} argument(s), but {} are given",
+                                           func_name, proto.borrow_params.len(), args.len())));
+                }
+                args.extend(proto.borrow_defaults[args.len() - num_required..].iter().cloned());
+                ctx.const_evals.push(ST::ConstEval{line, col, func_idx: proto.id, args});
+                Box::new(PT::FractionNode{line, col, value: interpreter::Fraction::zero()})
+            }
+        };
+        Box::new(PT::LetUnletNode{line, col, is_unlet: false, name, rhs}).to_syntax_node(ctx)
+    }
+}
+
+// A literal for a value found at compile time, if it has one //
+fn const_expression(value: interpreter::Variable, line: usize, col: usize) -> Option<PT::ExpressionNode> {
+    Some(match value {
+        interpreter::Variable::Frac(value) => Box::new(PT::FractionNode{line, col, value}),
+        interpreter::Variable::Str(value) => Box::new(PT::StringNode{line, col, value}),
+        interpreter::Variable::Array(items) => {
+            let items = items.into_iter()
+                             .map(|item| const_expression(item.borrow().clone(), line, col))
+                             .collect::<Option<Vec<_>>>()?;
+            Box::new(PT::ArrayLiteralNode{line, col, items})
+        },
+        interpreter::Variable::Float(_) | interpreter::Variable::Handle(_) => return None
+    })
+}
+
+impl PT::Statement for PT::RememberNode {
+    fn configure(&mut self, cfg: &Cfg) -> Result<(), SyntaxError> {
+        configure_stmts(&mut self.body, cfg)
+    }
+
+    fn substatements(&mut self) -> Vec<&mut Vec<PT::StatementNode>> {
+        vec![&mut self.body]
+    }
+
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        // A pure function runs forwards in both directions of its caller, so would never release
+        // the entries it used //
+        if ctx.is_pure {
+            return Err(SyntaxError{line: self.line, col: self.col, desc: format!(
+                "Remember block in pure function \"{}\"", ctx.func_name), note: None});
+        }
+        ctx.check_parallel("Remember block", self.line, self.col)?;
+        let outer = mem::replace(&mut ctx.remembering, true);
+        let body = self.body.into_iter()
+                            .map(|s| s.to_syntax_node(ctx))
+                            .collect::<Result<Vec<_>, _>>();
+        ctx.remembering = outer;
+        let body = body?;
+        if let Some(stmt) = body.iter().find(|s| s.is_mono() || !s.is_assignment()) {
+            let (line, col) = stmt.position().unwrap_or((self.line, self.col));
+            return Err(SyntaxError{line, col, desc: String::from(
+                "Remember blocks can only hold assignments to non-mono variables, which evaluate their calls once in each direction"),
+                note: None});
+        }
+        Ok(Box::new(ST::RememberNode{body}))
+    }
+}
+
+impl PT::Statement for PT::MachineNode {
+    fn configure(&mut self, cfg: &Cfg) -> Result<(), SyntaxError> {
+        for state in self.states.iter_mut() {
+            configure_stmts(&mut state.stmts, cfg)?;
+        }
+        Ok(())
+    }
+
+    fn desugar(&self) -> Option<Result<Vec<PT::StatementNode>, SyntaxError>> {
+        Some(expand_machine(self))
+    }
+
+    fn to_syntax_node(self: Box<Self>, _ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        unreachable!("Machines are desugared before checking")
+    }
+}
+
+impl PT::Statement for PT::MacroCallNode {
+    fn macro_call(&self) -> Option<&PT::MacroCallNode> {
+        Some(self)
+    }
+
+    fn to_syntax_node(self: Box<Self>, _ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        unreachable!("Macros are expanded before checking")
+    }
+}
+
+impl PT::Statement for PT::TodoNode {
+    fn to_syntax_node(self: Box<Self>, _ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        Ok(Box::new(ST::TodoNode))
+    }
+}
+
+impl PT::Statement for PT::BacktraceNode {
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        if ctx.sandboxed {
+            return Err(SyntaxError{line: 0, col: 0, desc: String::from("Printing a backtrace is not allowed in sandboxed code"), note: None});
+        }
+        if ctx.is_pure {
+            return Err(SyntaxError{line: 0, col: 0, desc: format!(
+                "Printing a backtrace in pure function \"{}\"", ctx.func_name), note: None});
+        }
+        ctx.check_parallel("Printing a backtrace", 0, 0)?;
+        ctx.effects.prints = true;
+        Ok(Box::new(ST::BacktraceNode))
+    }
+}
+
+impl PT::Statement for PT::ExpectNode {
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        let desc = match &self.expected {
+            Some(expected) => format!("{} == {}", formatter::source(&self.actual), formatter::source(expected)),
+            None => formatter::source(&self.actual)
+        };
+        let actual = self.actual.to_syntax_node(ctx)?;
+        let expected = self.expected.map(|expected| expected.to_syntax_node(ctx)).transpose()?;
+        let is_mono = actual.is_mono() || expected.as_ref().is_some_and(|expected| expected.is_mono());
+        let desc_idx = ctx.add_const(interpreter::Variable::Str(desc));
+        Ok(Box::new(ST::ExpectNode{actual, expected, desc_idx, is_mono}))
+    }
+}
+
 impl PT::Statement for PT::CatchNode {
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        // Outside a try body within the loop, a catch would undo earlier iterations too //
+        if ctx.parallel.as_ref().is_some_and(|parallel| !parallel.catch_contained) {
+            return Err(SyntaxError{line: 0, col: 0, desc: String::from(
+                "Catch inside a parfor, which must be within a try in the loop so it only undoes its own iteration"),
+                note: None});
+        }
+        if let Some((_, desc)) = ctx.catch_floor.filter(|&(floor, _)| ctx.tries <= floor) {
+            return Err(SyntaxError{line: 0, col: 0, desc: String::from(desc), note: None});
+        }
         Ok(Box::new(ST::CatchNode{expr: self.expr.to_syntax_node(ctx)?}))
     }
 }
 
 
+impl PT::Statement for PT::TryNode {
+    fn calls(&self) -> Vec<&PT::CallNode> {
+        self.body.iter().chain(self.handler.iter()).flat_map(|s| s.calls()).collect()
+    }
+
+    fn configure(&mut self, cfg: &Cfg) -> Result<(), SyntaxError> {
+        configure_stmts(&mut self.body, cfg)?;
+        configure_stmts(&mut self.handler, cfg)
+    }
+
+    fn substatements(&mut self) -> Vec<&mut Vec<PT::StatementNode>> {
+        vec![&mut self.body, &mut self.handler]
+    }
+
+    fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
+        let (bkwd_line, bkwd_col) = self.bkwd_cond.get_src_pos();
+        if ctx.strict && self.bkwd_elided {
+            return Err(SyntaxError{line: bkwd_line, col: bkwd_col, desc: String::from(
+                "Strict mode requires the backward condition of a try statement, e.g. \"~catch (x == 0);\""), note: None});
+        }
+        // Set while the body is being undone, telling the try to run its handler rather than carry
+        // on backwards past it //
+        let flag_register = ctx.get_free_register();
+
+        let outer_parallel = ctx.parallel.clone();
+        if let Some(parallel) = &mut ctx.parallel {
+            parallel.catch_contained = true;
+        }
+        ctx.tries += 1;
+        ctx.enter_block();
+        let body = self.body.into_iter()
+                            .map(|s| s.to_syntax_node(ctx))
+                            .collect::<Result<Vec<_>, _>>()?;
+        ctx.exit_block()?;
+        ctx.tries -= 1;
+        ctx.parallel = outer_parallel;
+        if let Some(stmt) = body.iter().find(|s| !s.is_undoable()) {
+            let (line, col) = stmt.position().unwrap_or((0, 0));
+            return Err(SyntaxError{line, col, desc: String::from(
+                "Statements in a try block must be reversible, so that a catch can undo them"), note: None});
+        }
+        let cond = self.cond.to_syntax_node(ctx)?;
+
+        ctx.enter_block();
+        let handler = self.handler.into_iter()
+                                  .map(|s| s.to_syntax_node(ctx))
+                                  .collect::<Result<Vec<_>, _>>()?;
+        ctx.exit_block()?;
+        let bkwd_cond = self.bkwd_cond.to_syntax_node(ctx)?;
+        if bkwd_cond.is_mono() {
+            return Err(SyntaxError{line: bkwd_line, col: bkwd_col, desc: String::from(
+                "Backward condition in try statement is mono"), note: None});
+        }
+
+        ctx.free_registers.push(flag_register);
+        Ok(Box::new(ST::TryNode{body, cond, handler, bkwd_cond, flag_register}))
+    }
+}
+
 impl PT::Statement for PT::CallNode {
+    fn calls(&self) -> Vec<&PT::CallNode> {
+        vec![self]
+    }
+
     fn to_syntax_node(self: Box<Self>, ctx: &mut SyntaxContext) -> Result<Box<dyn ST::Statement>, SyntaxError> {
 
         /* 
@@ -775,10 +2119,87 @@ impl PT::Statement for PT::CallNode {
             - Check linked params share a var
         */
 
-        let mut error = SyntaxError{line: self.line, col: self.col, desc: String::new()};
+        let mut error = SyntaxError{line: self.line, col: self.col, desc: String::new(), note: None};
+
+        let const_args = self.const_args.iter()
+                                        .map(|arg| ctx.resolve_const_arg(arg))
+                                        .collect::<Result<Vec<_>, _>>()?;
+        let proto = match const_args.is_empty() {
+            true => ctx.lookup_function_prototype(&self.name)?,
+            false => ctx.lookup_function_prototype(&instance_name(&self.name, &const_args))?
+        };
+        let (func_idx, is_mono, is_pure) = (proto.id, proto.is_mono, proto.is_pure);
+        if proto.is_generator {
+            error.desc = format!("Generator \"{}\" can only be called by a for loop over it", self.name);
+            return Err(error);
+        }
+        if proto.is_mono {
+            if self.is_uncall {
+                error.desc = format!("Function \"{}\" is mono, so can't be uncalled", self.name);
+                return Err(error);
+            }
+            if let Some(arg) = self.stolen_args.iter().chain(self.return_args.iter()).find(|a| !a.starts_with(".")) {
+                error.desc = format!("Function \"{}\" is mono, so \"{}\" must be a mono variable", self.name, arg);
+                return Err(error);
+            }
+        }
+        if ctx.is_pure && !proto.is_pure {
+            error.desc = format!("Pure function \"{}\" calls impure function \"{}\"", ctx.func_name, self.name);
+            return Err(error);
+        }
+
+        // An uncall steals what the call returns, and returns what it steals //
+        let (input_params, output_params) = match self.is_uncall {
+            false => (&proto.steal_params, &proto.return_params),
+            true => (&proto.return_params, &proto.steal_params)
+        };
+        let num_required = proto.borrow_params.len() - proto.borrow_defaults.len();
+        if self.borrow_args.len() < num_required || self.borrow_args.len() > proto.borrow_params.len() {
+            error.desc = match num_required == proto.borrow_params.len() {
+                true => format!("Function \"{}\" borrows {} argument(s), but {} are given",
+                                self.name, num_required, self.borrow_args.len()),
+                false => format!("Function \"{}\" borrows {} to {} argument(s), but {} are given",
+                                 self.name, num_required, proto.borrow_params.len(), self.borrow_args.len())
+            };
+            return Err(error);
+        }
+        let counts = [
+            ("steals", self.stolen_args.len(), input_params.len()),
+            ("returns", self.return_args.len(), output_params.len()),
+        ];
+        for (verb, given, expected) in counts {
+            if given != expected {
+                error.desc = format!(
+                    "Function \"{}\" {} {} argument(s), but {} are given",
+                    self.name, verb, expected, given);
+                return Err(error);
+            }
+        }
+        for (i, arg) in self.return_args.iter().enumerate() {
+            if self.return_args[..i].contains(arg) {
+                error.desc = format!("\"{}\" is bound more than once by the same call", arg);
+                return Err(error);
+            }
+        }
+        for (i, (binding, param)) in self.bindings.iter().zip(output_params.iter()).enumerate() {
+            if binding.is_ref != param.is_some() {
+                error.desc = format!(
+                    "Return value {} of \"{}\" is {}a reference, but \"{}\" is bound as {}",
+                    i + 1, self.name, if param.is_some() {""} else {"not "},
+                    binding.name, if binding.is_ref {"one"} else {"a value"});
+                return Err(error);
+            }
+            if let (Some(link), Some(ST::ParamLink{link: Some(proto_link), is_interior, ..})) = (&binding.link, param) {
+                if exterior_link_name(link) != *proto_link || is_interior_link(link) != *is_interior {
+                    error.desc = format!(
+                        "Return value {} of \"{}\" belongs to link group {}{}, but \"{}\" is bound in group {}",
+                        i + 1, self.name, proto_link, if *is_interior {" (interior)"} else {""},
+                        binding.name, link);
+                    return Err(error);
+                }
+            }
+        }
 
-        let proto = ctx.lookup_function_prototype(&self.name)?;
-        let func_idx = proto.id;
         let mut used_links: HashMap<Rc<Variable>, Option<String>> = HashMap::new();
         let mut used_vars: HashMap<String, Rc<Variable>> = HashMap::new();
 
@@ -812,73 +2233,366 @@ impl PT::Statement for PT::CallNode {
                 },
                 None => {
                     if !ctx.check_singly_owned(&param.name)? {
-                        error.desc = String::from("Call uses non-singly owned variable");
-                        return Err(error);
+                        return Err(ctx.not_singly_owned("Call", &param.name, error.line, error.col));
                     }
 
                 }
             }
         }
 
+        let defaults = proto.borrow_defaults[self.borrow_args.len() - num_required..].to_vec();
+        // Whether a parfor can make an impure call depends on the callee's effects, known once
+        // every function is checked. Until then it may modify whatever it's given //
+        if !is_pure && ctx.parallel.is_some() {
+            for name in self.borrow_args.iter().map(|arg| &arg.name).chain(self.stolen_args.iter()) {
+                let var_id = ctx.get_var_id(name)?;
+                ctx.check_writable(name, var_id, self.line, self.col)?;
+            }
+            ctx.parfor_calls.push((func_idx, self.line, self.col));
+        }
+        ctx.add_callee(func_idx, &self.borrow_args, &defaults);
+        let default_args = defaults.into_iter().map(|value| ctx.add_const(value)).collect();
+
+        // Undoing the call that returned a value uncomputes it, so it was never garbage //
         let mut stolen_args = Vec::with_capacity(self.stolen_args.len());
-        for arg in self.stolen_args.into_iter() {
-            stolen_args.push(ctx.lookup_variable(&arg)?.register);
-            ctx.locals.remove(&arg);
+        let mut stolen_ids = Vec::with_capacity(self.stolen_args.len());
+        for arg in self.stolen_args.iter() {
+            let var = ctx.lookup_variable(arg)?;
+            stolen_args.push(var.register);
+            stolen_ids.push(var.var.id);
+            ctx.locals.remove(arg);
+        }
+        let in_place = self.return_args.iter().any(|arg| self.stolen_args.contains(arg));
+        let (is_uncall, name) = (self.is_uncall, &self.name);
+        let before = ctx.call_outputs.len();
+        ctx.call_outputs.retain(|(garbage, ids)| !(
+            is_uncall && &garbage.callee == name && ids.iter().all(|id| stolen_ids.contains(id))));
+        if ctx.call_outputs.len() == before {
+            for var_id in stolen_ids {
+                ctx.mark_read(var_id);
+            }
         }
         let borrow_args = self.borrow_args.into_iter()
                                           .map(|a| a.to_syntax_node_unboxed(ctx))
                                           .collect::<Result<Vec<_>, _>>()?;
+        // Only pure functions are sure to leave what they borrow unmodified //
+        if !is_pure {
+            ctx.written.extend(borrow_args.iter().map(|arg| arg.var_id));
+        }
+        let return_types = match self.bindings.is_empty() {
+            true => vec![ST::Type::Unknown; self.return_args.len()],
+            false => self.bindings.into_iter().map(|b| b.annotation).collect()
+        };
         let mut return_args = Vec::with_capacity(self.return_args.len());
-        for arg in self.return_args.into_iter() {
-            return_args.push(ctx.create_variable(&arg)?);
+        let mut return_ids = Vec::with_capacity(self.return_args.len());
+        for arg in self.return_args.iter() {
+            return_args.push(ctx.create_variable(arg)?);
+            return_ids.push(ctx.get_var_id(arg)?);
             // TODO: Using create variable is WRONG
         }
-        // TODO: Get is_mono from function prototype
-        let is_mono = false;
+        // Results taking the name of a stolen argument update it in place, rather than being new //
+        if !self.is_uncall && !is_mono && !ctx.uncomputing
+            && !in_place {
+            let (line, col) = ctx.position.unwrap_or((self.line, self.col));
+            let garbage = interpreter::Garbage{
+                line, col, callee: self.name.clone(), names: self.return_args, last_use: None};
+            ctx.call_outputs.push((garbage, return_ids));
+        }
 
         Ok(Box::new(ST::CallNode{
             is_uncall: self.is_uncall,
-            func_idx, borrow_args, stolen_args, return_args, is_mono
+            func_idx, borrow_args, default_args, stolen_args, return_args, return_types, is_mono
         }))
     }
 }
 
+// A function's syntax node, with the variables still bound at its end and the warnings for it //
+type CheckedFunction = (ST::FunctionNode, HashMap<String, Reference>, Vec<SyntaxError>);
+
 impl PT::FunctionNode {
     fn to_syntax_node(
         self,
         func_lookup: &HashMap<String, ST::FunctionPrototype>,
         global_vars: &HashMap<String, Reference>,
-    ) -> Result<ST::FunctionNode, SyntaxError> {
-        let (syntax_node, _) = self.to_syntax_node_and_locals(func_lookup, global_vars)?;
-        Ok(syntax_node)
+        outer_locals: &HashSet<String>,
+        sandboxed: bool,
+        sketch: bool,
+        cfg: &Cfg
+    ) -> Result<(ST::FunctionNode, Vec<SyntaxError>), SyntaxError> {
+        let (syntax_node, _, warnings) = self.to_syntax_node_and_locals(
+            func_lookup, global_vars, outer_locals, sandboxed, sketch, cfg)?;
+        Ok((syntax_node, warnings))
     }
 
     fn to_syntax_node_and_locals(
         self,
         func_lookup: &HashMap<String, ST::FunctionPrototype>,
-        global_vars: &HashMap<String, Reference>
-    ) -> Result<
-        (ST::FunctionNode, HashMap<String, Reference>),
-        SyntaxError
-    > {
+        global_vars: &HashMap<String, Reference>,
+        outer_locals: &HashSet<String>,
+        sandboxed: bool,
+        sketch: bool,
+        cfg: &Cfg
+    ) -> Result<CheckedFunction, SyntaxError> {
+
+        let annotations = |params: &Vec<PT::FunctionParam>| params.iter().map(|p| p.annotation.clone()).collect();
+        let borrow_types = annotations(&self.borrow_params);
+        let steal_types = annotations(&self.steal_params);
+        let return_types = annotations(&self.return_params);
+
+        self.check_attributes()?;
+        let is_host = self.has_attribute("host");
+        let is_mono = self.has_attribute("mono");
+        if is_host && sandboxed {
+            let attribute = self.attributes.iter().find(|a| a.name == "host").expect("Missing host attribute");
+            return Err(SyntaxError{line: attribute.line, col: attribute.col, desc: format!(
+                "Function \"{}\" is marked #[host], so can't run when sandboxed", self.name), note: None});
+        }
+        let borrow_names: Vec<String> = self.borrow_params.iter().map(|p| p.name.clone()).collect();
 
         let mut ctx = SyntaxContext::new(func_lookup, global_vars);
+        ctx.effects.reads_input = is_host;
+        ctx.effects.steals = !self.steal_params.is_empty();
+        ctx.sandboxed = sandboxed;
+        ctx.check_aliasing = cfg.checks_aliasing();
+        ctx.hoist_invariants = cfg.opt_level() > 0;
+        ctx.is_pure = self.has_attribute("pure");
+        // A catch reaching back past a yield can't take back the value handed out //
+        if self.has_attribute("generator") {
+            ctx.is_generator = true;
+            ctx.catch_floor = Some((0,
+                "Catch in a generator, which must be within a try so that it can't undo values already yielded"));
+        }
+        ctx.func_name = self.name.clone();
+        ctx.outer_locals = outer_locals.clone();
+        ctx.const_bindings = self.const_params.iter().cloned().zip(self.const_args.iter().cloned()).collect();
+        ctx.specialised = self.specialised.iter().cloned().collect();
         let (link_set, borrow_registers, steal_registers) = ctx.init_func(
             self.owned_links, self.borrow_params, self.steal_params);
-        let stmts = self.stmts.into_iter()
-                              .map(|s| s.to_syntax_node(&mut ctx))
-                              .collect::<Result<Vec<_>, _>>()?;
-        let return_registers = ctx.end_func(link_set, self.return_params);
+        let borrow_ids = borrow_names.iter().map(|name| ctx.get_var_id(name)).collect::<Result<Vec<_>, _>>()?;
+        ctx.borrow_ids = borrow_ids.clone();
+        if ctx.is_pure {
+            ctx.pure_borrows = borrow_ids.clone();
+        }
+        ctx.strict = self.is_strict;
+        // Globals stay bound after the global scope pseudo function ends //
+        let is_global = self.name == "!global!";
+        ctx.sketch = sketch && !ctx.strict && !is_host && !is_global;
+        let mut stmts = self.stmts.into_iter()
+                                  .map(|s| s.to_syntax_node(&mut ctx))
+                                  .collect::<Result<Vec<_>, _>>()?;
+        let warnings = match ctx.sketch {
+            true => ctx.sketch_unlets(&self.return_params, &mut stmts)?,
+            false => Vec::new()
+        };
+        ctx.effects.is_mono = is_mono || stmts.iter().any(|s| s.is_mono());
+        let read_only_borrows = borrow_ids.iter().map(|id| !ctx.modified.contains(id)).collect();
+        // A let bound variable is only the value it was bound to if nothing in the function writes to it //
+        let call_sites = mem::take(&mut ctx.call_sites).into_iter()
+            .map(|(callee, passed)| (callee, passed.into_iter().map(|passed| match passed {
+                ST::Passed::Let(var_id, _) if ctx.modified.contains(&var_id) => ST::Passed::Unknown,
+                passed => passed
+            }).collect()))
+            .collect();
+        ctx.check_pieces(&self.return_params)?;
+        if ctx.strict && !is_host && !is_global {
+            ctx.check_uninitialised(&self.return_params, self.line, self.col)?;
+        }
+        let garbage = ctx.find_garbage();
+        // The host makes what a #[host] function returns, so its returns are never bound in the body //
+        let return_registers = match is_host {
+            true => self.return_params.iter().map(|_| ctx.get_free_register()).collect(),
+            false => ctx.end_func(link_set, self.return_params)
+        };
 
         let function_node = ST::FunctionNode{
+            name: self.name,
             stmts, borrow_registers, steal_registers, return_registers,
+            borrow_types, steal_types, return_types,
             consts: ctx.consts,
-            num_registers: ctx.num_registers
+            num_registers: ctx.num_registers,
+            symbols: ctx.symbols,
+            is_pure: ctx.is_pure,
+            is_host,
+            garbage,
+            effects: ctx.effects,
+            callees: ctx.callees,
+            call_sites,
+            parfor_calls: ctx.parfor_calls,
+            const_evals: ctx.const_evals,
+            read_only_borrows,
+            specialised: Vec::new(),
+            linked: None
         };
 
-        Ok((function_node, ctx.locals))
+        Ok((function_node, ctx.locals, warnings))
+    }
+
+    fn has_attribute(&self, name: &str) -> bool {
+        self.attributes.iter().any(|a| a.name == name)
+    }
+
+    // Attributes declare properties of a function, which its signature and body must uphold. The
+    // body of a #[pure] function is checked as it's converted //
+    fn check_attributes(&self) -> Result<(), SyntaxError> {
+        for (i, attribute) in self.attributes.iter().enumerate() {
+            let err = |desc: String| Err(SyntaxError{line: attribute.line, col: attribute.col, desc, note: None});
+            if self.attributes[..i].iter().any(|a| a.name == attribute.name) {
+                return err(format!("Function \"{}\" is marked #[{}] more than once", self.name, attribute.name));
+            }
+            if !attribute.args.is_empty() {
+                return err(format!("Attribute #[{}] takes no arguments", attribute.name));
+            }
+            match attribute.name.as_str() {
+                "inline" => {
+                    // Generic instances are named e.g. "f<4>", and nested ones "outer::f" //
+                    let base_name = self.name.split('<').next().unwrap_or_default();
+                    let is_self = |call: &&PT::CallNode| {
+                        base_name == call.name || base_name.ends_with(&format!("::{}", call.name))
+                    };
+                    if let Some(call) = self.stmts.iter().flat_map(|s| s.calls()).find(is_self) {
+                        return Err(SyntaxError{line: call.line, col: call.col, desc: format!(
+                            "Function \"{}\" is marked #[inline], so can't call itself", self.name), note: None});
+                    }
+                },
+                "mono" => {
+                    let params = self.steal_params.iter().chain(self.return_params.iter());
+                    if let Some(param) = params.into_iter().find(|p| !p.name.starts_with(".")) {
+                        return err(format!(
+                            "Function \"{}\" is marked #[mono], so \"{}\" must be a mono variable", self.name, param.name));
+                    }
+                },
+                "pure" => {
+                    if !self.steal_params.is_empty() || self.return_params.len() != 1 {
+                        return err(format!(
+                            "Function \"{}\" is marked #[pure], so must steal nothing and return one value", self.name));
+                    }
+                    let params = self.borrow_params.iter().chain(self.return_params.iter());
+                    if !self.owned_links.is_empty() || params.into_iter().any(|p| p.is_ref || p.link.is_some()) {
+                        return err(format!(
+                            "Function \"{}\" is marked #[pure], so can't take or return references", self.name));
+                    }
+                },
+                "generator" => {
+                    if !self.steal_params.is_empty() || !self.return_params.is_empty() {
+                        return err(format!(
+                            "Function \"{}\" is marked #[generator], so must steal and return nothing", self.name));
+                    }
+                    if !self.owned_links.is_empty() || self.borrow_params.iter().any(|p| p.is_ref || p.link.is_some()) {
+                        return err(format!(
+                            "Function \"{}\" is marked #[generator], so can't take references", self.name));
+                    }
+                    if let Some(other) = ["pure", "inline", "mono"].iter().find(|name| self.has_attribute(name)) {
+                        return err(format!(
+                            "Function \"{}\" is marked #[generator], so can't also be #[{}]", self.name, other));
+                    }
+                    if self.name == "main" {
+                        return err(String::from("The main function can't be a generator"));
+                    }
+                },
+                "no_specialise" => {},
+                "host" => {
+                    if !self.stmts.is_empty() {
+                        return err(format!(
+                            "Function \"{}\" is marked #[host], so its body is given by the host and must be empty", self.name));
+                    }
+                    let params = self.borrow_params.iter().chain(self.steal_params.iter()).chain(self.return_params.iter());
+                    if !self.owned_links.is_empty() || params.into_iter().any(|p| p.is_ref || p.link.is_some()) {
+                        return err(format!(
+                            "Function \"{}\" is marked #[host], so can't take or return references", self.name));
+                    }
+                    if let Some(other) = ["pure", "inline", "mono", "generator"].iter().find(|name| self.has_attribute(name)) {
+                        return err(format!(
+                            "Function \"{}\" is marked #[host], so can't also be #[{}]", self.name, other));
+                    }
+                    if self.name == "main" {
+                        return err(String::from("The main function can't be a host function"));
+                    }
+                },
+                _ => return err(format!("Unknown attribute #[{}]", attribute.name))
+            }
+        }
+        Ok(())
     }
 
+    // Nested functions become top-level functions named "outer::inner", listed after their parent //
+    fn hoist(mut self, parent: Option<String>, hoisted: &mut Vec<(PT::FunctionNode, Option<String>)>) {
+        let nested = mem::take(&mut self.functions);
+        let name = self.name.clone();
+        let module = self.module.clone();
+        let is_strict = self.is_strict;
+        hoisted.push((self, parent));
+        for mut f in nested {
+            f.name = format!("{}::{}", name, f.name);
+            f.module = module.clone();
+            f.is_strict = is_strict;
+            f.hoist(Some(name.clone()), hoisted);
+        }
+    }
+}
+
+// Monomorphised functions are named after their const args, e.g. "zeros<4>" //
+fn instance_name(name: &str, const_args: &[interpreter::Variable]) -> String {
+    let args: Vec<String> = const_args.iter().map(interpreter::Variable::source).collect();
+    format!("{}<{}>", name, args.join(", "))
+}
+
+// Generic functions are templates, copied once for each distinct set of const args they're called
+// with. Instances are scanned in turn, as they may call further instances using their own params //
+fn monomorphise(
+    hoisted: Vec<(PT::FunctionNode, Option<String>)>
+) -> Result<Vec<(PT::FunctionNode, Option<String>)>, SyntaxError> {
+    let (templates, mut functions): (Vec<_>, Vec<_>) = hoisted.into_iter().partition(|(f, _)| !f.const_params.is_empty());
+    let templates: HashMap<String, (PT::FunctionNode, Option<String>)> = templates.into_iter()
+        .map(|(f, parent)| (f.name.clone(), (f, parent)))
+        .collect();
+    let mut instances = HashSet::new();
+
+    let mut idx = 0;
+    while idx < functions.len() {
+        let mut new_instances = Vec::new();
+        let caller = &functions[idx].0;
+        let bindings: HashMap<&String, &interpreter::Variable> = caller.const_params.iter().zip(caller.const_args.iter()).collect();
+        for call in caller.stmts.iter().flat_map(|s| s.calls()) {
+            let err = |desc: String| SyntaxError{line: call.line, col: call.col, desc, note: None};
+
+            // Resolve the name as lookup_function_prototype will //
+            let mut scope = Some(caller.name.as_str());
+            let mut template_name = call.name.clone();
+            while let Some(prefix) = scope {
+                let scoped = format!("{}::{}", prefix, call.name);
+                if templates.contains_key(&scoped) {
+                    template_name = scoped;
+                    break;
+                }
+                scope = prefix.rfind("::").map(|idx| &prefix[..idx]);
+            }
+            let (template, parent) = match templates.get(&template_name) {
+                Some(template) => template,
+                None if call.const_args.is_empty() => continue,
+                None => return Err(err(format!("Function \"{}\" is given const args, but isn't generic", call.name)))
+            };
+            if call.const_args.len() != template.const_params.len() {
+                return Err(err(format!("Generic function \"{}\" takes {} const arg(s), but {} are given",
+                                       call.name, template.const_params.len(), call.const_args.len())));
+            }
+
+            let const_args = call.const_args.iter().map(|arg| match arg {
+                PT::ConstArg::Value(value) => Ok(value.clone()),
+                PT::ConstArg::Name(name) => bindings.get(name).map(|v| (*v).clone()).ok_or(err(format!(
+                    "\"{}\" isn't a const param of \"{}\"", name, caller.name)))
+            }).collect::<Result<Vec<_>, _>>()?;
+            let name = instance_name(&template_name, &const_args);
+            if instances.insert(name.clone()) {
+                let mut instance = template.clone();
+                instance.name = name;
+                instance.const_args = const_args;
+                new_instances.push((instance, parent.clone()));
+            }
+        }
+        functions.extend(new_instances);
+        idx += 1;
+    }
+    Ok(functions)
 }
 
 impl ST::FunctionPrototype {
@@ -949,6 +2663,12 @@ impl ST::FunctionPrototype {
             &mut owned_link_groups,
             true, 2);
 
+        // Defaults must be trailing, so omitted borrows are filled from the right //
+        let borrow_defaults = function.borrow_params.iter()
+            .skip_while(|param| param.default.is_none())
+            .map(|param| param.default.clone().expect("Borrowed parameter without default follows one with a default"))
+            .collect::<Vec<_>>();
+
         let owned_link_groups = owned_link_groups.into_iter().map(|(_, v)| v)
                                                  .collect::<Vec<[Vec<usize>; 3]>>();
 
@@ -963,15 +2683,157 @@ impl ST::FunctionPrototype {
         }
 
         ST::FunctionPrototype{
-            id, borrow_params, steal_params, return_params, owned_link_groups
+            id, borrow_params, borrow_defaults, steal_params, return_params, owned_link_groups,
+            is_inline: function.has_attribute("inline"),
+            is_mono: function.has_attribute("mono"),
+            is_pure: function.has_attribute("pure"),
+            is_generator: function.has_attribute("generator"),
+            is_pub: function.is_pub,
+            module: function.module.clone()
         }
     }
 }
 
-pub fn check_syntax(module: PT::Module) -> Result<ST::Module, SyntaxError> {
+// Check an expression on its own, against registers bound to the given names. Used to evaluate
+// expressions in a paused program //
+pub fn check_expression(
+    expr: PT::ExpressionNode,
+    locals: &[(String, usize)],
+    globals: &[(String, usize)]
+) -> Result<(ST::ExpressionNode, Vec<interpreter::Variable>), SyntaxError> {
+    let functions = HashMap::new();
+    let mut global_refs = HashMap::new();
+    for (i, (name, register)) in globals.iter().enumerate() {
+        global_refs.insert(name.clone(), Reference{
+            is_interior: false,
+            is_borrowed: true,
+            is_global: true,
+            is_slice: false,
+            extent: None,
+            register: *register,
+            var: Rc::new(Variable{
+                id: -(i as isize) - 1,  // Negative id for globals
+                exteriors: RefCell::new(std::iter::once(name.clone()).collect()),
+                interiors: RefCell::new(HashSet::new())
+            })
+        });
+    }
+    let mut ctx = SyntaxContext::new(&functions, &global_refs);
+    for (name, register) in locals.iter() {
+        let reference = ctx.new_variable(name.clone(), *register, true);
+        ctx.locals.insert(name.clone(), reference);
+    }
+    let expr = expr.to_syntax_node(&mut ctx)?;
+    Ok((expr, ctx.consts))
+}
+
+pub fn check_syntax(module: PT::Module, cfg: &Cfg) -> Result<ST::Module, SyntaxError> {
+    check_module(module, cfg, false).map(report_warnings)
+}
+
+// As check_syntax, but rejecting any statement with a side effect, for running untrusted code //
+pub fn check_sandboxed_syntax(module: PT::Module, cfg: &Cfg) -> Result<ST::Module, SyntaxError> {
+    check_module(module, cfg, true).map(report_warnings)
+}
+
+// As check_syntax or check_sandboxed_syntax, but returning the warnings instead of reporting them //
+pub fn check_syntax_quietly(module: PT::Module, cfg: &Cfg, sandboxed: bool) -> Result<(ST::Module, Vec<SyntaxError>), SyntaxError> {
+    check_module(module, cfg, sandboxed)
+}
+
+// As check_syntax, for a module compiled into a library artifact, along with the prototypes of the
+// functions it exports, each numbered by its index in the module. Those are its own "pub fn"s, less
+// generic ones, which have no code until instantiated. As callers outside the library may pass them
+// anything, they aren't specialised to what the library's own calls pass //
+pub fn check_library(mut module: PT::Module, cfg: &Cfg) -> Result<(ST::Module, Vec<(String, ST::FunctionPrototype)>), SyntaxError> {
+    let mut prototypes = Vec::new();
+    for f in module.functions.iter_mut().filter(|f| f.is_pub && f.const_params.is_empty()) {
+        if !f.has_attribute("no_specialise") {
+            f.attributes.push(PT::Attribute{line: f.line, col: f.col, name: String::from("no_specialise"), args: Vec::new()});
+        }
+        prototypes.push((f.name.clone(), ST::FunctionPrototype::from(f, 0)));
+    }
+    let module = check_syntax(module, cfg)?;
+    if let Some(stmt) = module.global_func.stmts.first() {
+        let (line, col) = stmt.position().unwrap_or((0, 0));
+        return Err(SyntaxError{line, col, desc: String::from(
+            "A library has global statements, but may only define functions"), note: None});
+    }
+    // Functions #[cfg] left out aren't exported //
+    let prototypes = prototypes.into_iter().filter_map(|(name, prototype)| {
+        let id = module.functions.iter().position(|f| f.name == name)?;
+        Some((name, ST::FunctionPrototype{id, ..prototype}))
+    }).collect();
+    Ok((module, prototypes))
+}
+
+fn report_warnings((module, warnings): (ST::Module, Vec<SyntaxError>)) -> ST::Module {
+    for SyntaxError{line, col, desc, ..} in warnings {
+        eprintln!("Warning at line {}, column {}:\n ->  {}\n", line, col, desc);
+    }
+    module
+}
+
+fn check_module(mut module: PT::Module, cfg: &Cfg, sandboxed: bool) -> Result<(ST::Module, Vec<SyntaxError>), SyntaxError> {
+    let libraries = resolve_imports(&mut module, cfg)?;
+    // --strict covers every file of the program, though not the standard library //
+    if cfg.is_strict() {
+        for function in module.functions.iter_mut().filter(|f| f.module.as_deref() != Some("std")) {
+            function.is_strict = true;
+        }
+    }
+    configure(&mut module, cfg)?;
+    desugar(&mut module)?;
+    let mut hoisted = Vec::new();
+    for f in module.functions {
+        f.hoist(None, &mut hoisted);
+    }
+    let hoisted = monomorphise(hoisted)?;
+    let (hoisted, pt_global_func) = evaluate_consts(hoisted, module.global_func, &libraries, cfg, sandboxed)?;
+    let unspecialised = (hoisted.clone(), pt_global_func.clone());
+    let (mut global_func, mut functions, mut main_idx, mut sketched)
+        = check_functions(hoisted, pt_global_func, &libraries, cfg, sandboxed)?;
+
+    // Specialised params keep their registers and only fold where read as values, so checking
+    // again can only fail as the first check would have //
+    let (mut hoisted, pt_global_func) = unspecialised;
+    let specialised = specialise(&mut hoisted, &global_func, &functions);
+    if !specialised.is_empty() {
+        (global_func, functions, main_idx, sketched) = check_functions(hoisted, pt_global_func, &libraries, cfg, sandboxed)?;
+        for (idx, params) in specialised {
+            functions[idx].specialised = params;
+        }
+    }
+
+    infer_effects(&mut functions)?;
+
+    let module = ST::Module{functions, main_idx, global_func};
+    let mut warnings = check_types(&module)?;
+    warnings.extend(sketched);
+    let local_functions = module.functions.iter().filter(|function| !function.name.contains("::"));
+    for function in iter::once(&module.global_func).chain(local_functions) {
+        warnings.extend(function.garbage.iter().map(garbage_warning));
+    }
+    Ok((module, warnings))
+}
+
+
+
+// The global scope pseudo function and every function, each checked against the prototypes of all,
+// with the index of main and the warnings left by --sketch. The functions of linked libraries follow
+// the module's own, each library's in the order they were compiled in it //
+type CheckedFunctions = (ST::FunctionNode, Vec<ST::FunctionNode>, Option<usize>, Vec<SyntaxError>);
+
+fn check_functions(
+    hoisted: Vec<(PT::FunctionNode, Option<String>)>,
+    global_func: PT::FunctionNode,
+    libraries: &[Library],
+    cfg: &Cfg,
+    sandboxed: bool
+) -> Result<CheckedFunctions, SyntaxError> {
     // Collect the properties of all the module functions
     let mut func_prototypes = HashMap::new();
-    for f in module.functions.iter() {
+    for (f, _) in hoisted.iter() {
         if func_prototypes.insert(
             f.name.clone(),
             ST::FunctionPrototype::from(&f, func_prototypes.len())
@@ -979,10 +2841,30 @@ pub fn check_syntax(module: PT::Module) -> Result<ST::Module, SyntaxError> {
             panic!("Duplicate function definition");
         }
     }
+    // Only the functions a library exports can be called, though all are linked //
+    let mut linked = Vec::new();
+    for library in libraries {
+        let base = hoisted.len() + linked.len();
+        for (name, prototype) in library.prototypes.iter() {
+            func_prototypes.insert(format!("{}::{}", library.name, name), ST::FunctionPrototype{
+                id: base + prototype.id,
+                module: Some(library.name.clone()),
+                ..prototype.clone()
+            });
+        }
+        for (idx, function) in library.module.functions.iter().enumerate() {
+            let name = format!("{}::{}", library.name, function.name);
+            linked.push(linked_function(name, function, ST::Linked{library: Arc::clone(&library.module), idx, base}));
+        }
+    }
+    for (name, prototype) in func_prototypes.iter() {
+        log::debug!("Prototype of {}: {:?}", name, prototype);
+    }
 
     // Check the syntax of the global scope pseudo function, and convert the variable into globals
-    let (global_func, mut global_refs) 
-        = module.global_func.to_syntax_node_and_locals(&func_prototypes, &HashMap::new())?;
+    let (global_func, mut global_refs, _)
+        = global_func.to_syntax_node_and_locals(
+            &func_prototypes, &HashMap::new(), &HashSet::new(), sandboxed, false, cfg)?;
     let mut global_vars: HashMap<isize, Rc<Variable>> = HashMap::new();
     for (_, reference) in global_refs.iter_mut() {
         reference.is_global = true;
@@ -1006,16 +2888,301 @@ pub fn check_syntax(module: PT::Module) -> Result<ST::Module, SyntaxError> {
 
     // Check the syntax of each function, and find the main function
     let mut main_idx = None;
-    let mut functions = Vec::with_capacity(module.functions.len());
-    for (i, f) in module.functions.into_iter().enumerate() {
-        if f.name == "main" {main_idx = Some(i)}
-        functions.push(f.to_syntax_node(&func_prototypes, &global_refs)?);
+    let mut functions = Vec::with_capacity(hoisted.len());
+    let mut sketched = Vec::new();
+    let mut scope_locals: HashMap<String, HashSet<String>> = HashMap::new();
+    for (i, (f, parent)) in hoisted.into_iter().enumerate() {
+        if f.name == "main" {
+            check_main(&f)?;
+            main_idx = Some(i);
+        }
+        let mut outer_locals = parent.and_then(|p| scope_locals.get(&p).cloned()).unwrap_or_default();
+        let name = f.name.clone();
+        // --sketch, like --strict, leaves the standard library as it is //
+        let sketch = cfg.is_sketch() && f.module.as_deref() != Some("std");
+        let (function, warnings) = f.to_syntax_node(
+            &func_prototypes, &global_refs, &outer_locals, sandboxed, sketch, cfg)?;
+        sketched.extend(warnings);
+        outer_locals.extend(function.symbols.iter().map(|s| s.name.clone()));
+        scope_locals.insert(name, outer_locals);
+        functions.push(function);
     }
+    functions.extend(linked);
+    Ok((global_func, functions, main_idx, sketched))
+}
 
-    Ok(ST::Module{functions, main_idx, global_func})
+// A library function stands in the checked module with no statements, carrying what checking
+// its callers needs from its compiled code. Its params are unannotated, and never specialised //
+fn linked_function(name: String, function: &interpreter::Function, linked: ST::Linked) -> ST::FunctionNode {
+    ST::FunctionNode{
+        name,
+        stmts: Vec::new(),
+        consts: Vec::new(),
+        num_registers: 0,
+        symbols: Vec::new(),
+        is_pure: function.is_pure,
+        is_host: false,
+        garbage: Vec::new(),
+        effects: function.effects,
+        callees: Vec::new(),
+        call_sites: Vec::new(),
+        parfor_calls: Vec::new(),
+        const_evals: Vec::new(),
+        read_only_borrows: vec![false; function.num_borrows],
+        specialised: function.specialised.clone(),
+        linked: Some(linked),
+        borrow_registers: Vec::new(),
+        steal_registers: Vec::new(),
+        return_registers: Vec::new(),
+        borrow_types: vec![ST::Type::Unknown; function.num_borrows],
+        steal_types: vec![ST::Type::Unknown; function.num_steals],
+        return_types: vec![ST::Type::Unknown; function.num_returns]
+    }
 }
 
 
+// A const eval that never ends would stop the program compiling, so each runs with these limits //
+const CONST_EVAL_STEP_BUDGET: u64 = 10_000_000;
+const CONST_EVAL_MEMORY_CAP: usize = 10_000_000;
+
+// Every function hoisted to the top level, with the name of the one it was nested in //
+type HoistedFunctions = Vec<(PT::FunctionNode, Option<String>)>;
+
+// Each "const x = eval f(...);" is evaluated by checking and compiling the module with them all
+// bound to 0, then calling f on its args in a sandboxed interpreter. The global scope isn't run, as
+// pure functions can't read globals. The values found stand in the parse tree as literals //
+fn evaluate_consts(
+    mut hoisted: HoistedFunctions,
+    mut global_func: PT::FunctionNode,
+    libraries: &[Library],
+    cfg: &Cfg,
+    sandboxed: bool
+) -> Result<(HoistedFunctions, PT::FunctionNode), SyntaxError> {
+    let mut values = HashMap::new();
+    let fill = |hoisted: &mut HoistedFunctions, global_func: &mut PT::FunctionNode, values: &HashMap<_, _>| {
+        iter::once(global_func).chain(hoisted.iter_mut().map(|(f, _)| f))
+            .fold(false, |found, f| fill_const_evals(&mut f.stmts, &f.name, values) | found)
+    };
+    if !fill(&mut hoisted, &mut global_func, &values) {
+        return Ok((hoisted, global_func));
+    }
+
+    let (mut checked_global, functions, main_idx, _) = check_functions(hoisted.clone(), global_func.clone(), libraries, cfg, sandboxed)?;
+    checked_global.stmts.clear();
+    checked_global.symbols.clear();
+    let module = ST::Module{functions, main_idx, global_func: checked_global};
+    let options = EngineOptions{
+        step_budget: Some(CONST_EVAL_STEP_BUDGET),
+        memory_cap: Some(CONST_EVAL_MEMORY_CAP),
+        ..EngineOptions::default()
+    };
+    let program = module.compile(true, &options);
+    for function in iter::once(&module.global_func).chain(module.functions.iter()) {
+        for ST::ConstEval{line, col, func_idx, args} in function.const_evals.iter() {
+            let mut interpreter = interpreter::Interpreter::new(&program);
+            interpreter.set_sandboxed(true);
+            interpreter.set_options(&options);
+            interpreter.capture_output();
+            let mut returns = interpreter.try_run_entry(&program, *func_idx, args.clone()).map_err(|e| SyntaxError{
+                line: *line, col: *col, note: None, desc: format!(
+                    "Evaluating \"{}\" at compile time failed: {}", module.functions[*func_idx].name, e)})?;
+            let value = returns.pop().expect("Pure function returned nothing");
+            values.insert((function.name.clone(), *line, *col), value);
+        }
+    }
+    fill(&mut hoisted, &mut global_func, &values);
+    Ok((hoisted, global_func))
+}
+
+// Give the const evals in a function's statements the values found for them, keyed by the function
+// and their position. Returns whether there are any //
+fn fill_const_evals(
+    stmts: &mut [PT::StatementNode],
+    func_name: &str,
+    values: &HashMap<(String, usize, usize), interpreter::Variable>
+) -> bool {
+    let mut found = false;
+    for stmt in stmts.iter_mut() {
+        if let Some(node) = stmt.const_eval() {
+            node.value = values.get(&(func_name.to_string(), node.line, node.col)).cloned();
+            found = true;
+            continue;
+        }
+        for substmts in stmt.substatements() {
+            found |= fill_const_evals(substmts, func_name, values);
+        }
+    }
+    found
+}
+
+// The functions specialised, each with the index, name and value of its specialised params //
+type Specialised = Vec<(usize, Vec<(usize, String, String)>)>;
+
+// A borrow param every call passes the same constant, and the function never modifies, is bound
+// to it: its uses as a value fold to it, as a generic function's const params do. A param or let
+// bound variable handed on to a call that modifies it is modified too, which spreads back through
+// callers over and over until none change //
+fn specialise(
+    hoisted: &mut [(PT::FunctionNode, Option<String>)],
+    global_func: &ST::FunctionNode,
+    functions: &[ST::FunctionNode]
+) -> Specialised {
+    // Host functions may do as they like with what they're given //
+    let mut read_only: Vec<Vec<bool>> = functions.iter()
+        .map(|f| f.read_only_borrows.iter().map(|&read_only| read_only && !f.is_host).collect())
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (idx, f) in functions.iter().enumerate() {
+            for (callee, passed) in f.call_sites.iter() {
+                for (i, passed) in passed.iter().enumerate() {
+                    if let ST::Passed::Param(param) = passed {
+                        if !read_only[*callee][i] && read_only[idx][*param] {
+                            read_only[idx][*param] = false;
+                            changed = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let read_only = &read_only;
+    let mut agreed: HashMap<usize, Vec<Option<interpreter::Variable>>> = HashMap::new();
+    for f in functions.iter().chain(iter::once(global_func)) {
+        let modified: HashSet<isize> = f.call_sites.iter()
+            .flat_map(|(callee, passed)| passed.iter().enumerate().filter_map(move |(i, passed)| match passed {
+                ST::Passed::Let(var_id, _) if !read_only[*callee][i] => Some(*var_id),
+                _ => None
+            }))
+            .collect();
+        for (callee, passed) in f.call_sites.iter() {
+            let values: Vec<_> = passed.iter().map(|passed| match passed {
+                ST::Passed::Const(value) => Some(value.clone()),
+                ST::Passed::Let(var_id, value) if !modified.contains(var_id) => Some(value.clone()),
+                _ => None
+            }).collect();
+            match agreed.get_mut(callee) {
+                None => {agreed.insert(*callee, values);},
+                Some(agreed) => for (agreed, value) in agreed.iter_mut().zip(values) {
+                    if agreed.is_some() && *agreed != value {
+                        *agreed = None;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut specialised = Vec::new();
+    for (idx, (f, _)) in hoisted.iter_mut().enumerate() {
+        let Some(values) = agreed.remove(&idx) else { continue };
+        if ["no_specialise", "host", "generator"].iter().any(|name| f.has_attribute(name)) {
+            continue;
+        }
+        let mut params = Vec::new();
+        for (i, (param, value)) in f.borrow_params.iter().zip(values).enumerate() {
+            let Some(value) = value else { continue };
+            if param.is_ref || param.link.is_some() || !read_only[idx][i] || param.name.starts_with('.') {
+                continue;
+            }
+            params.push((i, param.name.clone(), value.source()));
+            f.specialised.push((param.name.clone(), value));
+        }
+        if !params.is_empty() {
+            specialised.push((idx, params));
+        }
+    }
+    specialised
+}
+
+// Callers take on the effects of the functions they call, over and over until none spread, as calls
+// may be recursive. Then the impure calls made in parfors can be checked //
+fn infer_effects(functions: &mut [ST::FunctionNode]) -> Result<(), SyntaxError> {
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for idx in 0..functions.len() {
+            for i in 0..functions[idx].callees.len() {
+                let callee = functions[functions[idx].callees[i]].effects;
+                changed |= functions[idx].effects.inherit(&callee);
+            }
+        }
+    }
+
+    for function in functions.iter() {
+        for &(callee, line, col) in &function.parfor_calls {
+            let callee = &functions[callee];
+            let effect = match callee.effects {
+                effects if effects.prints => "prints",
+                effects if effects.reads_input => "reads input",
+                effects if effects.touches_globals => "touches globals",
+                _ => continue
+            };
+            return Err(SyntaxError{line, col, desc: format!(
+                "Calling \"{}\", which {}, inside a parfor, whose iterations may run in any order", callee.name, effect), note: None});
+        }
+    }
+    Ok(())
+}
+
+
+// Garbage is best uncomputed as soon as the results it came with have been used, which a do/yield
+// does by undoing the do block straight after the yield block //
+fn garbage_warning(garbage: &interpreter::Garbage) -> SyntaxError {
+    let names = garbage.names.iter().map(|name| format!("\"{}\"", name)).collect::<Vec<_>>().join(", ");
+    let (verb, pronoun) = if garbage.names.len() == 1 {("is", "it")} else {("are", "them")};
+    let desc = format!("{} returned by \"{}\" {} never used, so {} garbage until the call is undone",
+                       names, garbage.callee, verb, verb);
+    let note = garbage.last_use.map(|(line, col)| Note{line, col, desc: format!(
+        "uncompute {} after this, by making the call in a do block and using its results in the yield block", pronoun)});
+    SyntaxError{line: garbage.line, col: garbage.col, desc, note}
+}
+
+// Main is run with nothing to borrow or steal, and nothing to receive what it returns //
+fn check_main(main: &PT::FunctionNode) -> Result<(), SyntaxError> {
+    if main.borrow_params.is_empty() && main.steal_params.is_empty() && main.return_params.is_empty() {
+        return Ok(());
+    }
+    Err(SyntaxError{line: main.line, col: main.col, desc: String::from(
+        "main can't take or return parameters, so must be declared \"fn main()() {...} ~main()\". \
+        Use --entry to run a function with parameters"), note: None})
+}
+
+// A hint naming the candidate closest to a misspelt name, if any is close enough, e.g.
+// ", did you mean \"counter\"?" //
+fn did_you_mean<'b>(name: &str, candidates: impl Iterator<Item = &'b str>) -> String {
+    let bare = name.trim_start_matches('.');
+    let mut best: Option<(usize, &str)> = None;
+    for candidate in candidates {
+        if candidate.trim_start_matches('.') == bare && candidate != name {
+            let kind = if candidate.starts_with('.') {"mono"} else {"non-mono"};
+            return format!(", did you mean the {} variable \"{}\"?", kind, candidate);
+        }
+        let distance = edit_distance(name, candidate);
+        if distance <= (name.chars().count() / 3).max(1) && best.is_none_or(|b| (distance, candidate) < b) {
+            best = Some((distance, candidate));
+        }
+    }
+    best.map_or(String::new(), |(_, candidate)| format!(", did you mean \"{}\"?", candidate))
+}
+
+// The Levenshtein distance between two names //
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = diagonal + (ca != cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
 fn exterior_link_name(link_name: &str) -> String {
     let mut c = link_name.chars();
     match c.next() {
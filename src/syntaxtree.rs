@@ -1,15 +1,33 @@
 
 use std::collections::HashSet;
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use crate::interpreter;
 use crate::compiler;
+use crate::janus::{JanusExpression, JanusStatement};
+use crate::circuit::{CircuitExpression, CircuitStatement};
+use crate::typechecker::{TypedExpression, TypedStatement};
+
+
+// Unknown unifies with every type, so unannotated code is never rejected for lack of types //
+#[derive(Clone, Debug, PartialEq)]
+pub enum Type {
+    Unknown,
+    Num,
+    Str,
+    Handle,
+    Array(Box<Type>)
+}
 
-
-pub trait Expression: Debug {
+pub trait Expression: Debug + TypedExpression + JanusExpression + CircuitExpression {
     fn is_mono(&self) -> bool;
     fn used_vars(&self) -> &HashSet<isize>;
     fn compile(&self) -> Vec<interpreter::Instruction>;
+    // Whether this can be computed once ahead of a loop: it can't fail whatever values it reads,
+    // and reads only variables directly //
+    fn can_hoist(&self) -> bool {false}
+    fn subexpressions(&mut self) -> Vec<&mut ExpressionNode> {Vec::new()}
 }
 
 pub type ExpressionNode = Box<dyn Expression>;
@@ -26,6 +44,27 @@ pub struct StringNode {
     pub used_vars: HashSet<isize>
 }
 
+// A loop-invariant expression, computed into the register before the loop. Backends other than
+// the bytecode compiler evaluate the expression where it stands //
+#[derive(Debug)]
+pub struct HoistedNode {
+    pub register: usize,
+    pub expr: ExpressionNode
+}
+
+// Raises an error if evaluated //
+#[derive(Clone, Debug)]
+pub struct HoleNode {
+    pub used_vars: HashSet<isize>
+}
+
+// Always mono, as the counters differ between runs and directions //
+#[derive(Debug)]
+pub struct CounterNode {
+    pub op: interpreter::Instruction,
+    pub used_vars: HashSet<isize>
+}
+
 #[derive(Debug)]
 pub struct ArrayLiteralNode {
     pub items: Vec<ExpressionNode>,
@@ -41,6 +80,29 @@ pub struct ArrayRepeatNode {
     pub used_vars: HashSet<isize>
 }
 
+#[derive(Debug)]
+pub struct ComprehensionNode {
+    pub item: ExpressionNode,
+    pub source: LookupNode,
+    pub filter: Option<ExpressionNode>,
+    pub register: usize,        // The iteration variable //
+    pub index_register: usize,
+    pub acc_register: usize,
+    pub tmp_register: usize,
+    pub is_mono: bool,
+    pub used_vars: HashSet<isize>
+}
+
+#[derive(Debug)]
+pub struct CallExprNode {
+    pub func_idx: usize,
+    pub remember: bool,  // Inside a remember block, so looked up in the memo table
+    pub borrow_args: Vec<LookupNode>,
+    pub default_args: Vec<usize>,
+    pub is_mono: bool,
+    pub used_vars: HashSet<isize>
+}
+
 #[derive(Debug)]
 pub struct LookupNode {
     pub register: usize,
@@ -72,13 +134,27 @@ pub struct UniopNode {
 
 
 
-pub trait Statement: Debug {
+pub trait Statement: Debug + TypedStatement + JanusStatement + CircuitStatement {
     fn is_mono(&self) -> bool;
     fn compile(&self) -> compiler::Code;
+    fn position(&self) -> Option<(usize, usize)> {None}
+    // Whether a try block can undo this statement when it catches //
+    fn is_undoable(&self) -> bool {!self.is_mono()}
+    // Whether this evaluates its expressions exactly once in each direction //
+    fn is_assignment(&self) -> bool {false}
+    // The expressions a surrounding loop may hoist parts of //
+    fn expressions(&mut self) -> Vec<&mut ExpressionNode> {Vec::new()}
 }
 
 pub type StatementNode = Box<dyn Statement>;
 
+#[derive(Debug)]
+pub struct PositionedNode {
+    pub line: usize,
+    pub col: usize,
+    pub stmt: StatementNode
+}
+
 #[derive(Debug)]
 pub struct PrintNode {
     pub items: Vec<ExpressionNode>,
@@ -99,6 +175,24 @@ pub struct RefUnrefNode {
     pub is_unref: bool,
     pub register: usize,
     pub rhs: LookupNode,
+    pub range: Option<(ExpressionNode, ExpressionNode)>,
+    pub is_mono: bool,
+    pub check_aliasing: bool  // Check at runtime that the reference removed is to what it's removed from
+}
+
+#[derive(Debug)]
+pub struct RelinkNode {
+    pub register: usize,
+    pub old: LookupNode,
+    pub new: LookupNode,
+    pub is_mono: bool,
+    pub check_aliasing: bool
+}
+
+#[derive(Debug)]
+pub struct MoveNode {
+    pub from: usize,
+    pub to: usize,
     pub is_mono: bool
 }
 
@@ -110,12 +204,23 @@ pub struct ModopNode {
     pub is_mono: bool
 }
 
+#[derive(Debug)]
+pub struct DestructureLetNode {
+    pub is_unlet: bool,
+    pub register: usize,
+    pub rest_register: usize,
+    pub prefix: Vec<usize>,
+    pub suffix: Vec<usize>,
+    pub is_mono: bool
+}
+
 #[derive(Debug)]
 pub struct PushPullNode {
     pub is_push: bool,
     pub register: usize,
     pub lookup: LookupNode,
     pub is_mono: bool,
+    pub check_aliasing: bool  // Check at runtime that nothing still references what's pulled
 }
 
 #[derive(Debug)]
@@ -132,15 +237,44 @@ pub struct WhileNode {
     pub fwd_expr: ExpressionNode,
     pub stmts: Vec<StatementNode>,
     pub bkwd_expr: Option<ExpressionNode>,
-    pub is_mono: bool
+    pub is_mono: bool,
+    pub hoisted: Vec<(usize, Vec<interpreter::Instruction>)>  // Registers computed before the loop, and how
 }
 
 #[derive(Debug)]
 pub struct ForNode {
     pub register: usize,
+    pub index_register: Option<usize>,
+    pub reversed: bool,
+    pub parallel: bool,
     pub iterator: LookupNode,
     pub stmts: Vec<StatementNode>,
-    pub is_mono: bool
+    pub is_mono: bool,
+    pub hoisted: Vec<(usize, Vec<interpreter::Instruction>)>
+}
+
+#[derive(Debug)]
+pub struct ForRangeNode {
+    pub register: usize,
+    pub start: ExpressionNode,
+    pub end: ExpressionNode,
+    pub step: ExpressionNode,
+    pub stmts: Vec<StatementNode>,
+    pub is_mono: bool,
+    pub hoisted: Vec<(usize, Vec<interpreter::Instruction>)>
+}
+
+#[derive(Debug)]
+pub struct ForGenNode {
+    pub register: usize,
+    pub func_idx: usize,
+    pub borrow_args: Vec<LookupNode>,
+    pub stmts: Vec<StatementNode>
+}
+
+#[derive(Debug)]
+pub struct YieldNode {
+    pub expr: ExpressionNode
 }
 
 #[derive(Debug)]
@@ -149,18 +283,54 @@ pub struct DoYieldNode {
     pub yield_stmts: Vec<StatementNode>
 }
 
+// The description is a string constant, the expectation's source //
+#[derive(Debug)]
+pub struct ExpectNode {
+    pub actual: ExpressionNode,
+    pub expected: Option<ExpressionNode>,
+    pub desc_idx: usize,
+    pub is_mono: bool
+}
+
 #[derive(Debug)]
 pub struct CatchNode {
     pub expr: ExpressionNode
 }
 
+#[derive(Debug)]
+pub struct TryNode {
+    pub body: Vec<StatementNode>,
+    pub cond: ExpressionNode,
+    pub handler: Vec<StatementNode>,
+    pub bkwd_cond: ExpressionNode,
+    pub flag_register: usize
+}
+
+#[derive(Debug)]
+pub struct RememberNode {
+    pub body: Vec<StatementNode>
+}
+
+// Already checked by the syntax checker, so compiles to nothing //
+#[derive(Debug)]
+pub struct StaticAssertNode;
+
+// Raises an error if run, in either direction //
+#[derive(Debug)]
+pub struct TodoNode;
+
+#[derive(Debug)]
+pub struct BacktraceNode;
+
 #[derive(Debug)]
 pub struct CallNode {
     pub is_uncall: bool,
     pub func_idx: usize,
     pub borrow_args: Vec<LookupNode>,
+    pub default_args: Vec<usize>,  // Const indices filling omitted trailing borrows //
     pub stolen_args: Vec<usize>,
     pub return_args: Vec<usize>,
+    pub return_types: Vec<Type>,
     pub is_mono: bool
 }
 
@@ -178,19 +348,83 @@ pub struct FunctionPrototype {
     pub id: usize,
     pub owned_link_groups: Vec<[Vec<usize>; 3]>,
     pub borrow_params: Vec<Option<ParamLink>>,
+    pub borrow_defaults: Vec<interpreter::Variable>,  // For the trailing borrow params //
     pub steal_params: Vec<Option<ParamLink>>,
-    pub return_params: Vec<Option<ParamLink>>
+    pub return_params: Vec<Option<ParamLink>>,
+    pub is_inline: bool,
+    pub is_mono: bool,  // Only callable as a mono statement //
+    pub is_pure: bool,  // Callable in expressions //
+    pub is_generator: bool,  // Only callable by a for loop over what it yields //
+    pub is_pub: bool,
+    pub module: Option<String>
+}
+
+// A "const x = eval f(...);" whose value is still to be found, by calling the function with the
+// given index on the args //
+#[derive(Debug, Clone)]
+pub struct ConstEval {
+    pub line: usize,
+    pub col: usize,
+    pub func_idx: usize,
+    pub args: Vec<interpreter::Variable>
+}
+
+// What a call passes for one of its callee's borrowed params: a constant, a let bound variable
+// nothing in the caller writes to but which other calls borrowing it might, one of the caller's own
+// borrowed params, or something else //
+#[derive(Debug, Clone)]
+pub enum Passed {
+    Const(interpreter::Variable),
+    Let(isize, interpreter::Variable),
+    Param(usize),
+    Unknown
 }
 
 #[derive(Debug)]
 pub struct FunctionNode {
+    pub name: String,
     pub stmts: Vec<StatementNode>,
     pub consts: Vec<interpreter::Variable>,
     pub num_registers: usize,
+    pub symbols: Vec<Symbol>,
+    pub is_pure: bool,
+    pub is_host: bool,  // Run by the host function registered under its name //
+    pub garbage: Vec<interpreter::Garbage>,
+    pub effects: interpreter::Effects,  // Including those of its callees, once every function is checked
+    pub callees: Vec<usize>,
+    pub call_sites: Vec<(usize, Vec<Passed>)>,  // Each call's callee, and what it passes for each borrow
+    pub parfor_calls: Vec<(usize, usize, usize)>,
+    pub const_evals: Vec<ConstEval>,
+    pub read_only_borrows: Vec<bool>,
+    pub specialised: Vec<(usize, String, String)>,  // Borrow params bound to the constant every call passes, with their values
+    pub linked: Option<Linked>,  // Compiled already, for a function from a library artifact //
 
     pub borrow_registers: Vec<usize>,
     pub steal_registers: Vec<usize>,
     pub return_registers: Vec<usize>,
+    pub borrow_types: Vec<Type>,
+    pub steal_types: Vec<Type>,
+    pub return_types: Vec<Type>,
+}
+
+// A function of a library imported as a compiled artifact, which has no statements to compile. It
+// takes the code at idx in the library, whose functions all follow on from base in the importer //
+#[derive(Debug)]
+pub struct Linked {
+    pub library: Arc<interpreter::Module>,
+    pub idx: usize,
+    pub base: usize
+}
+
+// A variable name bound to a register, from the statement at `start` up to and including the
+// statement at `end`. None means the start or end of the function //
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub register: usize,
+    pub var_id: isize,
+    pub start: Option<(usize, usize)>,
+    pub end: Option<(usize, usize)>
 }
 
 #[derive(Debug)]
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::interpreter::Function;
+
+
+// An execution trace for "run --timeline", recording each statement as it starts along with the
+// variables live in its frame, and each time a catch turns execution around. Markers are stripped
+// from release builds, so statements are found from the regions of each function's code instead //
+const MAX_EVENTS: usize = 20000;
+const MAX_LIFETIME_ROWS: usize = 300;
+
+// The cell drawn for each event, and the width of the labels beside each row //
+const CELL: usize = 6;
+const ROW: usize = 16;
+const LABEL: usize = 360;
+
+#[derive(Debug)]
+pub struct Event {
+    pub func_idx: usize,
+    pub frame: usize,  // Numbered in the order frames were entered, 0 for the global scope
+    pub depth: usize,
+    pub line: usize,
+    pub col: usize,
+    pub forwards: bool,
+    pub unwinding: bool,  // Being undone after a catch fired
+    pub live: Vec<usize>  // Indices into the function's symbols
+}
+
+#[derive(Debug)]
+pub struct Turn {
+    pub event: usize,  // The first event after turning
+    pub caught: bool   // A catch fired, rather than an unwinding finishing
+}
+
+#[derive(Debug, Default)]
+pub struct Timeline {
+    starts: HashMap<(usize, bool), HashMap<usize, (usize, usize)>>,  // Deepest statement starting at each ip
+    frames: Vec<usize>,
+    next_frame: usize,
+    pub events: Vec<Event>,
+    pub turns: Vec<Turn>,
+    pub truncated: bool
+}
+
+impl Timeline {
+    pub fn new() -> Timeline {
+        Timeline{next_frame: 1, ..Timeline::default()}
+    }
+
+    // Record the statement starting at ip, if one does //
+    pub fn step(&mut self, functions: &[Function], func_idx: usize, forwards: bool, ip: usize,
+                depth: usize, unwinding: bool) {
+        if self.truncated {return}
+        let func = &functions[func_idx];
+        let starts = self.starts.entry((func_idx, forwards)).or_insert_with(|| {
+            let mut starts: HashMap<usize, (usize, usize, usize)> = HashMap::new();
+            for region in func.code.regions.iter().filter(|region| region.line != 0) {
+                let range = region.range(forwards);
+                if range.is_empty() {continue}
+                let deepest = starts.get(&range.start).is_none_or(|&(depth, _, _)| region.depth >= depth);
+                if deepest {
+                    starts.insert(range.start, (region.depth, region.line, region.col));
+                }
+            }
+            starts.into_iter().map(|(ip, (_, line, col))| (ip, (line, col))).collect()
+        });
+        let (line, col) = match starts.get(&ip) {
+            Some(&position) => position,
+            None => return
+        };
+        if self.events.len() == MAX_EVENTS {
+            self.truncated = true;
+            return;
+        }
+        let live = func.symbols.iter().enumerate()
+            .filter(|(_, symbol)| symbol.is_live(forwards, ip))
+            .map(|(idx, _)| idx)
+            .collect();
+        self.events.push(Event{
+            func_idx,
+            frame: self.frames.last().copied().unwrap_or(0),
+            depth,
+            line,
+            col,
+            forwards,
+            unwinding,
+            live
+        });
+    }
+
+    pub fn turn(&mut self, caught: bool) {
+        if !self.truncated {
+            self.turns.push(Turn{event: self.events.len(), caught});
+        }
+    }
+
+    pub fn enter_frame(&mut self) {
+        self.frames.push(self.next_frame);
+        self.next_frame += 1;
+    }
+
+    pub fn leave_frame(&mut self) {
+        self.frames.pop();
+    }
+
+    // A self-contained page, with no scripts or external resources, drawing one row per source
+    // statement and one per variable lifetime against the order statements ran in //
+    pub fn html(&self, functions: &[Function], title: &str, src: &str) -> String {
+        let src_lines: Vec<&str> = src.lines().collect();
+
+        // Statement rows, grouped by function in the order they were first run //
+        let mut func_order: Vec<usize> = Vec::new();
+        let mut lines: HashMap<usize, Vec<usize>> = HashMap::new();
+        for event in &self.events {
+            let func_lines = lines.entry(event.func_idx).or_insert_with(|| {
+                func_order.push(event.func_idx);
+                Vec::new()
+            });
+            if !func_lines.contains(&event.line) {
+                func_lines.push(event.line);
+            }
+        }
+        let mut rows: Vec<(String, String)> = Vec::new();
+        let mut statement_rows = HashMap::new();
+        for func_idx in func_order {
+            let name = &functions[func_idx].name;
+            let mut func_lines = lines.remove(&func_idx).unwrap_or_default();
+            func_lines.sort_unstable();
+            // Imported functions come from other files, whose source isn't at hand, while nested
+            // functions are named after the function around them //
+            let local = match name.rsplit_once("::") {
+                Some((outer, _)) => functions.iter().any(|func| func.name == outer),
+                None => true
+            };
+            for line in func_lines {
+                let text = match src_lines.get(line - 1) {
+                    Some(text) if local => text.trim(),
+                    _ => ""
+                };
+                statement_rows.insert((func_idx, line), rows.len());
+                rows.push((format!("{}:{}", name, line), text.to_string()));
+            }
+        }
+        let num_statement_rows = rows.len();
+
+        // Lifetime rows, one per variable per frame, spanning the runs of that frame's statements
+        // at which the variable is live //
+        let mut frame_events: Vec<(usize, Vec<usize>)> = Vec::new();
+        let mut frame_idx = HashMap::new();
+        for (idx, event) in self.events.iter().enumerate() {
+            let pos = *frame_idx.entry(event.frame).or_insert_with(|| {
+                frame_events.push((event.frame, Vec::new()));
+                frame_events.len() - 1
+            });
+            frame_events[pos].1.push(idx);
+        }
+        let mut lifetimes: Vec<(usize, Vec<(usize, usize)>)> = Vec::new();
+        let mut lifetimes_truncated = false;
+        'frames: for (frame, indices) in &frame_events {
+            let func = &functions[self.events[indices[0]].func_idx];
+            // A variable passed through a call is a new symbol afterwards, but the same row //
+            let mut names: Vec<&str> = Vec::new();
+            for symbol in &func.symbols {
+                if !names.contains(&symbol.name.as_str()) {
+                    names.push(&symbol.name);
+                }
+            }
+            for name in names {
+                let mut spans: Vec<(usize, usize)> = Vec::new();
+                let mut open: Option<(usize, usize)> = None;
+                for &idx in indices {
+                    if self.events[idx].live.iter().any(|&symbol| func.symbols[symbol].name == name) {
+                        open = Some(open.map_or((idx, idx), |(start, _)| (start, idx)));
+                    } else if let Some(span) = open.take() {
+                        spans.push(span);
+                    }
+                }
+                spans.extend(open);
+                if spans.is_empty() {continue}
+                if rows.len() - num_statement_rows == MAX_LIFETIME_ROWS {
+                    lifetimes_truncated = true;
+                    break 'frames;
+                }
+                lifetimes.push((rows.len(), spans));
+                rows.push((format!("{} (frame {})", name, frame), func.name.clone()));
+            }
+        }
+
+        let width = (self.events.len().max(1)) * CELL;
+        let height = rows.len().max(1) * ROW;
+        let mut out = String::new();
+        writeln!(out, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">").unwrap();
+        writeln!(out, "<title>Timeline of {}</title>", escape(title)).unwrap();
+        writeln!(out, concat!(
+            "<style>\n",
+            "body {{font-family: sans-serif; margin: 16px; color: #222;}}\n",
+            ".view {{display: flex; border: 1px solid #ccc;}}\n",
+            ".labels {{flex: none; background: #f7f7f7; border-right: 1px solid #ccc;}}\n",
+            ".events {{overflow-x: auto;}}\n",
+            "svg text {{font-family: monospace; font-size: 11px;}}\n",
+            ".key span {{display: inline-block; width: 12px; height: 12px; margin: 0 4px 0 12px; vertical-align: middle;}}\n",
+            "</style>\n</head>\n<body>")).unwrap();
+        writeln!(out, "<h2>{}</h2>", escape(title)).unwrap();
+        writeln!(out, "<p>{} statement(s) run in {} frame(s), with {} reversal(s){}</p>",
+                 self.events.len(), frame_events.len(), self.turns.iter().filter(|turn| turn.caught).count(),
+                 if self.truncated {format!(", stopped recording after {} statements", MAX_EVENTS)} else {String::new()}
+        ).unwrap();
+        writeln!(out, concat!(
+            "<p class=\"key\"><span style=\"background: #3778c2\"></span>forwards",
+            "<span style=\"background: #e0892b\"></span>backwards",
+            "<span style=\"background: #c0392b\"></span>unwinding after a catch",
+            "<span style=\"background: #5a9e5a\"></span>variable live</p>")).unwrap();
+
+        writeln!(out, "<div class=\"view\">\n<div class=\"labels\">").unwrap();
+        writeln!(out, "<svg width=\"{}\" height=\"{}\">", LABEL, height).unwrap();
+        for (row, (label, text)) in rows.iter().enumerate() {
+            let y = row * ROW;
+            if row == num_statement_rows {
+                writeln!(out, "<line x1=\"0\" y1=\"{0}\" x2=\"{1}\" y2=\"{0}\" stroke=\"#999\"/>", y, LABEL).unwrap();
+            }
+            writeln!(out, "<text x=\"4\" y=\"{}\"><title>{} {}</title>{}</text>",
+                     y + ROW - 4, escape(label), escape(text), escape(&clip(&format!("{}  {}", label, text), 56))).unwrap();
+        }
+        writeln!(out, "</svg>\n</div>\n<div class=\"events\">").unwrap();
+        writeln!(out, "<svg width=\"{}\" height=\"{}\">", width, height).unwrap();
+
+        for (idx, event) in self.events.iter().enumerate() {
+            let row = statement_rows[&(event.func_idx, event.line)];
+            let (colour, direction) = match (event.unwinding, event.forwards) {
+                (true, _) => ("#c0392b", "unwinding"),
+                (false, true) => ("#3778c2", "forwards"),
+                (false, false) => ("#e0892b", "backwards")
+            };
+            writeln!(out, concat!(
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\">",
+                "<title>#{} {} line {}, column {} in {} (frame {}, depth {})</title></rect>"),
+                idx * CELL, row * ROW + 2, CELL, ROW - 4, colour,
+                idx, direction, event.line, event.col, escape(&functions[event.func_idx].name), event.frame, event.depth
+            ).unwrap();
+        }
+        for (row, spans) in &lifetimes {
+            for &(start, end) in spans {
+                writeln!(out, concat!(
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"#5a9e5a\">",
+                    "<title>{} from #{} to #{}</title></rect>"),
+                    start * CELL, row * ROW + 4, (end + 1 - start) * CELL, ROW - 8,
+                    escape(&rows[*row].0), start, end
+                ).unwrap();
+            }
+        }
+        if num_statement_rows < rows.len() {
+            writeln!(out, "<line x1=\"0\" y1=\"{0}\" x2=\"{1}\" y2=\"{0}\" stroke=\"#999\"/>",
+                     num_statement_rows * ROW, width).unwrap();
+        }
+        for turn in &self.turns {
+            let (colour, desc) = match turn.caught {
+                true => ("#c0392b", "A catch fired, reversing execution"),
+                false => ("#777", "Finished unwinding")
+            };
+            writeln!(out, concat!(
+                "<line x1=\"{0}\" y1=\"0\" x2=\"{0}\" y2=\"{1}\" stroke=\"{2}\" stroke-width=\"2\" stroke-dasharray=\"4 2\">",
+                "<title>{3} before #{4}</title></line>"),
+                turn.event * CELL, height, colour, desc, turn.event).unwrap();
+        }
+        writeln!(out, "</svg>\n</div>\n</div>").unwrap();
+        if lifetimes_truncated {
+            writeln!(out, "<p>Only the first {} variable lifetimes are shown</p>", MAX_LIFETIME_ROWS).unwrap();
+        }
+        writeln!(out, "</body>\n</html>").unwrap();
+        out
+    }
+}
+
+fn clip(text: &str, len: usize) -> String {
+    match text.char_indices().nth(len) {
+        Some((idx, _)) => format!("{}...", &text[..idx]),
+        None => text.to_string()
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -1,3 +1,6 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::io::BufRead;
 
 use regex;
 
@@ -11,106 +14,203 @@ pub struct Token {
 }
 
 
-pub fn tokenise(data: &String) -> Vec<Token> {
-
-    let name_regex = regex::Regex::new(r"^[a-zA-Z_][a-zA-Z_0-9\.]*").unwrap();
-    let number_regex = regex::Regex::new(r"^\d+(/\d+)?").unwrap();
-    let string_regex = regex::Regex::new(r"^'[^']*'").unwrap();
-    let ignore_regex = regex::Regex::new(r"^(([$][^$]*[$])|([ \t\r\f\v]+))").unwrap();
-    let newline_regex = regex::Regex::new(r"^\n").unwrap();
-    let symbol_regex = regex::Regex::new(&(String::from(r"^(")
-    + r"\+=|\-=|\*=|/="
-    + r"|<=|>=|!=|=="
-    + r"|~=|=>|//|\*\*"
-    + r"|\+|\-|\*|/"
-    + r"|=|<|>"
-    + r"|\[|\]|\(|\)|\{|\}"
-    + r"|;|~|#|,|&|!|%|\||\^|\."
-    + r")")).unwrap();
-
-    let mut ret = Vec::new();
-    let mut pos = 0;
-    let mut line = 1;
-    let mut col = 0;
-    while pos < data.len() {
-        
-        if let Some(m) = name_regex.find(&data[pos..]) {
-            ret.push(Token{
-                type_: String::from("NAME"), 
-                string_: String::from(&data[pos .. pos + m.end()]),
-                line, col
-            });
-            pos += m.end();
-            col += m.end();
-            continue;
-        };
-
-        if let Some(m) = symbol_regex.find(&data[pos..]) {
-            ret.push(Token{
-                type_: String::from("SYMBOL"), 
-                string_: String::from(&data[pos .. pos + m.end()]),
-                line, col
-            });
-            pos += m.end();
-            col += m.end();
-            continue;
-        };
-        
-        if let Some(m) =  number_regex.find(&data[pos..]) {
-            ret.push(Token{
-                type_: String::from("NUMBER"), 
-                string_: String::from(&data[pos .. pos + m.end()]),
-                line, col
-            });
-            pos += m.end();
-            col += m.end();
-            continue;
+struct Patterns {
+    name: regex::Regex,
+    number: regex::Regex,
+    string: regex::Regex,
+    ignore: regex::Regex,
+    newline: regex::Regex,
+    symbol: regex::Regex
+}
+
+impl Patterns {
+    fn new() -> Patterns {
+        Patterns{
+            // Dots may join parts of a name, but not end one, so "n..m" is a range. A leading "@"
+            // marks a macro parameter //
+            name: regex::Regex::new(r"^@?[a-zA-Z_][a-zA-Z_0-9]*(\.[a-zA-Z_0-9]+)*").unwrap(),
+            number: regex::Regex::new(r"^\d+(/\d+)?").unwrap(),
+            string: regex::Regex::new(r"^'[^']*'").unwrap(),
+            ignore: regex::Regex::new(r"^(([$][^$]*[$])|([ \t\r\f\v]+))").unwrap(),
+            newline: regex::Regex::new(r"^\n").unwrap(),
+            symbol: regex::Regex::new(&(String::from(r"^(")
+            + r"\+=|\-=|\*=|/=|\->"
+            + r"|<=|>=|!=|=="
+            + r"|~=|=>|//|\*\*|\.\.\.|\.\.|\?\?\?"
+            + r"|\+|\-|\*|/"
+            + r"|=|<|>"
+            + r"|\[|\]|\(|\)|\{|\}"
+            + r"|;|:|~|#|,|&|!|%|\||\^|\."
+            + r")")).unwrap()
+        }
+    }
+}
+
+
+// Produces tokens lazily, finishing with a single END_MARKER! token. Characters no token starts
+// with become ERROR tokens, one each, for the parser to fail at //
+pub struct Tokeniser<'a> {
+    data: Cow<'a, str>,
+    reader: Option<Box<dyn BufRead + 'a>>,  // Where the rest of data comes from, a line at a time
+    patterns: Patterns,
+    pos: usize,
+    line: usize,
+    col: usize,
+    lookahead: VecDeque<Token>,
+    finished: bool,
+    unterminated: bool
+}
+
+impl<'a> Tokeniser<'a> {
+    pub fn new(data: &'a str) -> Tokeniser<'a> {
+        Tokeniser{
+            data: Cow::Borrowed(data),
+            reader: None,
+            patterns: Patterns::new(),
+            pos: 0,
+            line: 1,
+            col: 0,
+            lookahead: VecDeque::new(),
+            finished: false,
+            unterminated: false
         }
+    }
+
+    // Tokenise text as it's read, holding only what's left of the line being lexed, or of the
+    // string or comment spanning lines. A reader that fails is taken to have ended //
+    pub fn from_reader(reader: impl BufRead + 'a) -> Tokeniser<'a> {
+        Tokeniser{data: Cow::Owned(String::new()), reader: Some(Box::new(reader)), ..Tokeniser::new("")}
+    }
 
-        if let Some(m) = ignore_regex.find(&data[pos..]) {
-            let newlines:Vec<_> = data[pos .. pos + m.end()].match_indices("\n").collect();
-            pos += m.end();
-            line += newlines.len();
-            if let Some(idx) = newlines.last() {
-                col = m.end() - 1 - idx.0;
-            } else {
-                col += m.end();
+    pub fn peek(&mut self) -> Option<&Token> {
+        self.peek_nth(0)
+    }
+
+    // Look n tokens ahead without consuming anything //
+    pub fn peek_nth(&mut self, n: usize) -> Option<&Token> {
+        while self.lookahead.len() <= n {
+            match self.lex() {
+                Some(token) => self.lookahead.push_back(token),
+                None => break
             }
-            continue;
         }
+        self.lookahead.get(n)
+    }
 
-        if let Some(m) = newline_regex.find(&data[pos..]) {
-            pos += m.end();
-            line += 1;
-            col = 0;
-            continue;
+    // Whether the input ended part way through a string or comment. Only meaningful once
+    // the END_MARKER! token has been produced //
+    pub fn unterminated(&self) -> bool {
+        self.unterminated
+    }
+
+    fn advance(&mut self, len: usize) {
+        let newlines: Vec<_> = self.data[self.pos .. self.pos + len].match_indices('\n').collect();
+        self.pos += len;
+        self.line += newlines.len();
+        if let Some(idx) = newlines.last() {
+            self.col = len - 1 - idx.0;
+        } else {
+            self.col += len;
         }
+    }
 
-        if let Some(m) = string_regex.find(&data[pos..]) {
-            ret.push(Token{
-                type_: String::from("STRING"), 
-                string_: String::from(&data[pos + 1 .. pos + m.end() - 1]),
-                line, col
-            });
-            let newlines:Vec<_> = data[pos .. pos + m.end()].match_indices("\n").collect();
-            pos += m.end();
-            line += newlines.len();
-            if let Some(idx) = newlines.last() {
-                col = m.end() - 1 - idx.0;
-            } else {
-                col += m.end();
+    fn make_token(&self, type_: &str, string_: &str) -> Token {
+        Token{
+            type_: String::from(type_),
+            string_: String::from(string_),
+            line: self.line,
+            col: self.col
+        }
+    }
+
+    // Read until the text left holds the end of its line, or the closing quote of a string or
+    // comment it starts with. Tokens never span lines otherwise //
+    fn fill(&mut self) {
+        let Some(reader) = self.reader.as_mut() else {return};
+        loop {
+            let rest = &self.data[self.pos..];
+            let complete = match rest.chars().next() {
+                Some(quote) if quote == '\'' || quote == '$' => rest[1..].contains(quote),
+                _ => rest.contains('\n')
+            };
+            if complete {return}
+
+            let data = self.data.to_mut();
+            data.drain(..self.pos);
+            self.pos = 0;
+            match reader.read_line(data) {
+                Ok(0) | Err(_) => {
+                    self.reader = None;
+                    return;
+                },
+                Ok(_) => {}
             }
-            continue;
         }
-        
-        println!("pos {}", pos);
-        panic!("Unhandled input characters")
     }
 
-    ret.push(Token {
-        string_: String::from(""),
-        type_: String::from("END_MARKER!"),
-        line, col
-    });
-    ret
-}
\ No newline at end of file
+    fn lex(&mut self) -> Option<Token> {
+        if self.finished {return None};
+        self.fill();
+        while self.pos < self.data.len() {
+            let rest = &self.data[self.pos..];
+
+            let matched = [
+                (&self.patterns.name, "NAME"),
+                (&self.patterns.symbol, "SYMBOL"),
+                (&self.patterns.number, "NUMBER")
+            ].iter().find_map(|(pattern, type_)| pattern.find(rest).map(|m| (m.end(), *type_)));
+            if let Some((len, type_)) = matched {
+                let token = self.make_token(type_, &rest[..len]);
+                self.advance(len);
+                return Some(token);
+            }
+
+            if let Some(m) = self.patterns.ignore.find(rest) {
+                self.advance(m.end());
+                self.fill();
+                continue;
+            }
+
+            if let Some(m) = self.patterns.newline.find(rest) {
+                self.advance(m.end());
+                self.fill();
+                continue;
+            }
+
+            if let Some(m) = self.patterns.string.find(rest) {
+                let token = self.make_token("STRING", &rest[1 .. m.end() - 1]);
+                self.advance(m.end());
+                return Some(token);
+            }
+
+            if rest.starts_with('\'') || rest.starts_with('$') {
+                self.unterminated = true;
+                break;
+            }
+
+            let len = rest.chars().next().map_or(1, char::len_utf8);
+            let token = self.make_token("ERROR", &rest[..len]);
+            self.advance(len);
+            return Some(token);
+        }
+
+        self.finished = true;
+        Some(self.make_token("END_MARKER!", ""))
+    }
+}
+
+impl<'a> Iterator for Tokeniser<'a> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        match self.lookahead.pop_front() {
+            Some(token) => Some(token),
+            None => self.lex()
+        }
+    }
+}
+
+
+pub fn tokenise(data: &str) -> Vec<Token> {
+    Tokeniser::new(data).collect()
+}
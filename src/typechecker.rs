@@ -0,0 +1,730 @@
+use std::cell::RefCell;
+use std::fmt;
+
+use crate::interpreter::Instruction;
+use crate::syntaxchecker::SyntaxError;
+use crate::syntaxtree as ST;
+use ST::Type;
+
+
+// Types are inferred per register, in program order. Annotated params seed the registers, and
+// everything else is inferred from literals and operations, falling back to Unknown //
+pub struct TypeContext<'a> {
+    functions: &'a [ST::FunctionNode],
+    globals: &'a [Type],
+    registers: Vec<Type>,
+    position: (usize, usize),
+    warnings: RefCell<Vec<SyntaxError>>
+}
+
+pub trait TypedExpression {
+    fn infer_type(&self, ctx: &TypeContext) -> Result<Type, SyntaxError>;
+
+    // The number of items, if the expression is an array literal //
+    fn literal_length(&self) -> Option<usize> {None}
+}
+
+pub trait TypedStatement {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError>;
+}
+
+
+impl Type {
+    pub fn is_compatible(&self, other: &Type) -> bool {
+        match (self, other) {
+            (Type::Unknown, _) | (_, Type::Unknown) => true,
+            (Type::Array(a), Type::Array(b)) => a.is_compatible(b),
+            (a, b) => a == b
+        }
+    }
+
+    // The more specific of two compatible types //
+    fn refine(&self, other: &Type) -> Type {
+        match (self, other) {
+            (Type::Unknown, t) | (t, Type::Unknown) => t.clone(),
+            (Type::Array(a), Type::Array(b)) => Type::Array(Box::new(a.refine(b))),
+            (t, _) => t.clone()
+        }
+    }
+
+    // The type both could be, if they're not known to be the same //
+    fn join(&self, other: &Type) -> Type {
+        match (self, other) {
+            (Type::Array(a), Type::Array(b)) => Type::Array(Box::new(a.join(b))),
+            (a, b) if a == b => a.clone(),
+            _ => Type::Unknown
+        }
+    }
+
+    fn with_article(&self) -> String {
+        match self {
+            Type::Unknown | Type::Array(_) => format!("an {}", self),
+            _ => format!("a {}", self)
+        }
+    }
+
+    // Handles name host objects, so can be moved but never copied or computed with //
+    fn holds_handle(&self) -> bool {
+        match self {
+            Type::Handle => true,
+            Type::Array(item) => item.holds_handle(),
+            _ => false
+        }
+    }
+
+    fn item(&self) -> Option<Type> {
+        match self {
+            Type::Unknown => Some(Type::Unknown),
+            Type::Array(item) => Some((**item).clone()),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Unknown => write!(f, "unknown"),
+            Type::Num => write!(f, "num"),
+            Type::Str => write!(f, "str"),
+            Type::Handle => write!(f, "handle"),
+            Type::Array(item) => match **item {
+                Type::Unknown => write!(f, "array"),
+                _ => write!(f, "array[{}]", item)
+            }
+        }
+    }
+}
+
+
+impl<'a> TypeContext<'a> {
+    fn error(&self, desc: String) -> SyntaxError {
+        SyntaxError{line: self.position.0, col: self.position.1, desc, note: None}
+    }
+
+    fn warn(&self, desc: String) {
+        let warning = self.error(desc);
+        self.warnings.borrow_mut().push(warning);
+    }
+
+    fn check_stmts(&mut self, stmts: &[ST::StatementNode]) -> Result<(), SyntaxError> {
+        for stmt in stmts {
+            stmt.check_types(self)?;
+        }
+        Ok(())
+    }
+
+    fn function(&self, func_idx: usize) -> &'a ST::FunctionNode {
+        &self.functions[func_idx]
+    }
+}
+
+pub fn operator_symbol(op: &Instruction) -> &'static str {
+    match op {
+        Instruction::BinopAdd => "+",
+        Instruction::BinopSub => "-",
+        Instruction::BinopMul => "*",
+        Instruction::BinopDiv => "/",
+        Instruction::BinopIDiv => "//",
+        Instruction::BinopMod => "%",
+        Instruction::BinopPow => "**",
+        Instruction::BinopLess => "<",
+        Instruction::BinopLeq => "<=",
+        Instruction::BinopGreat => ">",
+        Instruction::BinopGeq => ">=",
+        Instruction::BinopEq => "==",
+        Instruction::BinopNeq => "!=",
+        Instruction::BinopAnd => "&",
+        Instruction::BinopOr => "|",
+        Instruction::BinopXor => "^",
+        Instruction::UniopNeg => "-",
+        Instruction::UniopNot => "!",
+        Instruction::UniopLen => "#",
+        Instruction::UniopToFloat => "to_float",
+        Instruction::UniopToFrac => "to_frac",
+        Instruction::UniopSin => "sin",
+        Instruction::UniopCos => "cos",
+        Instruction::UniopExp => "exp",
+        Instruction::UniopLog => "log",
+        Instruction::UniopSqrt => "sqrt",
+        Instruction::Steps => "steps",
+        Instruction::ClockMs => "clock_ms",
+        _ => "?"
+    }
+}
+
+fn check_binop(ctx: &TypeContext, op: &Instruction, lhs: &Type, rhs: &Type) -> Result<Type, SyntaxError> {
+    if lhs.holds_handle() || rhs.holds_handle() {
+        return Err(ctx.error(format!(
+            "Applying binop \"{}\" to {} and {}, but handles can only be passed to host functions", operator_symbol(op), lhs, rhs)));
+    }
+    match op {
+        Instruction::BinopEq | Instruction::BinopNeq | Instruction::BinopXor |
+        Instruction::BinopAnd | Instruction::BinopOr => Ok(Type::Num),
+        _ => {
+            if !lhs.is_compatible(&Type::Num) || !rhs.is_compatible(&Type::Num) {
+                return Err(ctx.error(format!(
+                    "Applying binop \"{}\" to {} and {}", operator_symbol(op), lhs, rhs)));
+            }
+            Ok(Type::Num)
+        }
+    }
+}
+
+// Evaluating an expression into a new value copies what it reads, which would leave two names for
+// the same host object //
+fn check_not_copying_handle(ctx: &TypeContext, t: Type) -> Result<Type, SyntaxError> {
+    if t.holds_handle() {
+        return Err(ctx.error(format!(
+            "Copying {}, which can only be moved by stealing, returning, pushing or pulling it", t.with_article())));
+    }
+    Ok(t)
+}
+
+
+// ---------------------------- Expression Nodes ---------------------------- //
+
+impl TypedExpression for ST::FractionNode {
+    fn infer_type(&self, _ctx: &TypeContext) -> Result<Type, SyntaxError> {
+        Ok(Type::Num)
+    }
+}
+
+impl TypedExpression for ST::StringNode {
+    fn infer_type(&self, _ctx: &TypeContext) -> Result<Type, SyntaxError> {
+        Ok(Type::Str)
+    }
+}
+
+impl TypedExpression for ST::HoistedNode {
+    fn infer_type(&self, ctx: &TypeContext) -> Result<Type, SyntaxError> {
+        self.expr.infer_type(ctx)
+    }
+}
+
+impl TypedExpression for ST::HoleNode {
+    fn infer_type(&self, ctx: &TypeContext) -> Result<Type, SyntaxError> {
+        ctx.warn(String::from("Unfinished expression \"???\" will raise an error if it is evaluated"));
+        Ok(Type::Unknown)
+    }
+}
+
+impl TypedExpression for ST::CounterNode {
+    fn infer_type(&self, _ctx: &TypeContext) -> Result<Type, SyntaxError> {
+        Ok(Type::Num)
+    }
+}
+
+impl TypedExpression for ST::ArrayLiteralNode {
+    fn infer_type(&self, ctx: &TypeContext) -> Result<Type, SyntaxError> {
+        let mut item_type = None;
+        for item in &self.items {
+            let t = check_not_copying_handle(ctx, item.infer_type(ctx)?)?;
+            item_type = Some(match item_type {
+                None => t,
+                Some(other) => t.join(&other)
+            });
+        }
+        Ok(Type::Array(Box::new(item_type.unwrap_or(Type::Unknown))))
+    }
+
+    fn literal_length(&self) -> Option<usize> {Some(self.items.len())}
+}
+
+impl TypedExpression for ST::ArrayRepeatNode {
+    fn infer_type(&self, ctx: &TypeContext) -> Result<Type, SyntaxError> {
+        let item = check_not_copying_handle(ctx, self.item.infer_type(ctx)?)?;
+        // A literal list of dimensions gives the nesting depth //
+        let depth = match self.dimensions.infer_type(ctx)? {
+            Type::Num => 1,
+            Type::Str => return Err(ctx.error(String::from("Array repetition dimensions must be a num or an array"))),
+            _ => match self.dimensions.literal_length() {
+                Some(0) => return Err(ctx.error(String::from("Array repetition needs at least one dimension"))),
+                Some(depth) => depth,
+                None => return Ok(Type::Array(Box::new(Type::Unknown)))
+            }
+        };
+        Ok((0..depth).fold(item, |t, _| Type::Array(Box::new(t))))
+    }
+}
+
+impl TypedExpression for ST::ComprehensionNode {
+    fn infer_type(&self, ctx: &TypeContext) -> Result<Type, SyntaxError> {
+        let source = self.source.infer_type(ctx)?;
+        let Some(iter_type) = source.item() else {
+            return Err(ctx.error(format!("Comprehension source is {}, not an array", source.with_article())));
+        };
+
+        // The iteration variable is only bound inside the comprehension //
+        let mut registers = ctx.registers.clone();
+        registers[self.register] = iter_type;
+        let inner = TypeContext{
+            functions: ctx.functions,
+            globals: ctx.globals,
+            registers,
+            position: ctx.position,
+            warnings: RefCell::new(Vec::new())
+        };
+        let item = self.item.infer_type(&inner).and_then(|t| check_not_copying_handle(&inner, t));
+        let filter = self.filter.as_ref().map(|f| f.infer_type(&inner));
+        ctx.warnings.borrow_mut().extend(inner.warnings.into_inner());
+        if let Some(filter) = filter {
+            filter?;
+        }
+        Ok(Type::Array(Box::new(item?)))
+    }
+}
+
+impl TypedExpression for ST::LookupNode {
+    fn infer_type(&self, ctx: &TypeContext) -> Result<Type, SyntaxError> {
+        let base = match self.is_global {
+            true => ctx.globals.get(self.register).cloned().unwrap_or(Type::Unknown),
+            false => ctx.registers[self.register].clone()
+        };
+        let mut t = base.clone();
+        for (depth, index) in self.indices.iter().enumerate() {
+            let index_type = index.infer_type(ctx)?;
+            if !index_type.is_compatible(&Type::Num) {
+                return Err(ctx.error(format!("Subscripting with {} instead of a num", index_type.with_article())));
+            }
+            t = match t.item() {
+                Some(item) => item,
+                None if depth == 0 => return Err(ctx.error(format!("Subscripting {}", t.with_article()))),
+                None => return Err(ctx.error(format!(
+                    "Subscripting {} with {} indices, but it only has {} dimension(s)",
+                    base.with_article(), self.indices.len(), depth)))
+            };
+            // Untyped values are never warned about, only partially known shapes //
+            if t == Type::Unknown && base != Type::Unknown && depth + 1 < self.indices.len() {
+                ctx.warn(format!(
+                    "The shape of {} is only known to {} dimension(s), so subscripting it with {} indices can't be checked",
+                    base.with_article(), depth + 1, self.indices.len()));
+                break;
+            }
+        }
+        Ok(t)
+    }
+}
+
+impl TypedExpression for ST::CallExprNode {
+    fn infer_type(&self, ctx: &TypeContext) -> Result<Type, SyntaxError> {
+        let func = ctx.function(self.func_idx);
+        for (i, (arg, expected)) in self.borrow_args.iter().zip(&func.borrow_types).enumerate() {
+            let t = arg.infer_type(ctx)?;
+            if !expected.is_compatible(&t) {
+                return Err(ctx.error(format!(
+                    "Borrowed argument {} of \"{}\" is annotated {}, but is given {}",
+                    i + 1, func.name, expected, t.with_article())));
+            }
+        }
+        Ok(func.return_types[0].clone())
+    }
+}
+
+impl TypedExpression for ST::BinopNode {
+    fn infer_type(&self, ctx: &TypeContext) -> Result<Type, SyntaxError> {
+        let lhs = self.lhs.infer_type(ctx)?;
+        let rhs = self.rhs.infer_type(ctx)?;
+        check_binop(ctx, &self.op, &lhs, &rhs)
+    }
+}
+
+impl TypedExpression for ST::UniopNode {
+    fn infer_type(&self, ctx: &TypeContext) -> Result<Type, SyntaxError> {
+        let t = self.expr.infer_type(ctx)?;
+        match self.op {
+            op if t.holds_handle() => {
+                Err(ctx.error(format!(
+                    "Applying {} to {}, but handles can only be passed to host functions", operator_symbol(&op), t.with_article())))
+            },
+            Instruction::UniopLen if t.item().is_none() => {
+                Err(ctx.error(format!("Length operator (#) used on {}", t.with_article())))
+            },
+            Instruction::UniopNeg if !t.is_compatible(&Type::Num) => {
+                Err(ctx.error(format!("Negating {}", t.with_article())))
+            },
+            Instruction::UniopToFrac if !t.is_compatible(&Type::Num) => {
+                Err(ctx.error(format!("Converting {} to a number", t.with_article())))
+            },
+            op if op.makes_float() && !t.is_compatible(&Type::Num) => {
+                Err(ctx.error(format!("Applying {} to {}", operator_symbol(&op), t.with_article())))
+            },
+            _ => Ok(Type::Num)
+        }
+    }
+}
+
+
+// ---------------------------- Statement Nodes ----------------------------- //
+
+impl TypedStatement for ST::PositionedNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        ctx.position = (self.line, self.col);
+        self.stmt.check_types(ctx)
+    }
+}
+
+impl TypedStatement for ST::PrintNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        for item in &self.items {
+            item.infer_type(ctx)?;
+        }
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::LetUnletNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        let t = check_not_copying_handle(ctx, self.rhs.infer_type(ctx)?)?;
+        if self.is_unlet {
+            let current = &ctx.registers[self.register];
+            if !current.is_compatible(&t) {
+                return Err(ctx.error(format!("Uninitialising {} with {}", current.with_article(), t.with_article())));
+            }
+            ctx.registers[self.register] = Type::Unknown;
+        } else {
+            ctx.registers[self.register] = t;
+        }
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::RefUnrefNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        let t = self.rhs.infer_type(ctx)?;
+        if let Some((start, end)) = &self.range {
+            for bound in [start, end] {
+                let bound_type = bound.infer_type(ctx)?;
+                if !bound_type.is_compatible(&Type::Num) {
+                    return Err(ctx.error(format!("Slicing with {} instead of a num", bound_type.with_article())));
+                }
+            }
+            if t != Type::Unknown && t.item().is_none() {
+                return Err(ctx.error(format!("Slicing {}", t.with_article())));
+            }
+        }
+        ctx.registers[self.register] = if self.is_unref {Type::Unknown} else {t};
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::RelinkNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        self.old.infer_type(ctx)?;
+        ctx.registers[self.register] = self.new.infer_type(ctx)?;
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::MoveNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        ctx.registers[self.to] = std::mem::replace(&mut ctx.registers[self.from], Type::Unknown);
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::ModopNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        let lhs = self.lookup.infer_type(ctx)?;
+        let rhs = self.rhs.infer_type(ctx)?;
+        check_binop(ctx, &self.op, &lhs, &rhs)?;
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::DestructureLetNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        let items = self.prefix.iter().chain(self.suffix.iter());
+        if self.is_unlet {
+            let rest = &ctx.registers[self.rest_register];
+            let Some(item) = rest.item() else {
+                return Err(ctx.error(format!("Recombining {}, which isn't an array", rest.with_article())));
+            };
+            let item = items.fold(item, |t, &register| t.join(&ctx.registers[register]));
+            for &register in self.prefix.iter().chain(self.suffix.iter()).chain([&self.rest_register]) {
+                ctx.registers[register] = Type::Unknown;
+            }
+            ctx.registers[self.register] = Type::Array(Box::new(item));
+        } else {
+            let array = ctx.registers[self.register].clone();
+            let Some(item) = array.item() else {
+                return Err(ctx.error(format!("Destructuring {}, which isn't an array", array.with_article())));
+            };
+            for &register in items {
+                ctx.registers[register] = item.clone();
+            }
+            ctx.registers[self.rest_register] = array;
+            ctx.registers[self.register] = Type::Unknown;
+        }
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::PushPullNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        let array = self.lookup.infer_type(ctx)?;
+        let item = match array.item() {
+            Some(item) => item,
+            None => return Err(ctx.error(format!(
+                "{} {}, which isn't an array", if self.is_push {"Pushing onto"} else {"Pulling from"}, array.with_article())))
+        };
+        if self.is_push {
+            // Arrays may hold mixed items, so pushing a new kind widens rather than errors //
+            if self.lookup.indices.is_empty() && !self.lookup.is_global {
+                let pushed = &ctx.registers[self.register];
+                ctx.registers[self.lookup.register] = Type::Array(Box::new(item.join(pushed)));
+            }
+            ctx.registers[self.register] = Type::Unknown;
+        } else {
+            ctx.registers[self.register] = item;
+        }
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::IfNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        self.fwd_expr.infer_type(ctx)?;
+        ctx.check_stmts(&self.if_stmts)?;
+        ctx.check_stmts(&self.else_stmts)?;
+        self.bkwd_expr.infer_type(ctx)?;
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::WhileNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        self.fwd_expr.infer_type(ctx)?;
+        ctx.check_stmts(&self.stmts)?;
+        if let Some(expr) = &self.bkwd_expr {
+            expr.infer_type(ctx)?;
+        }
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::ForNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        let iterator = self.iterator.infer_type(ctx)?;
+        ctx.registers[self.register] = match iterator.item() {
+            Some(item) => item,
+            None => return Err(ctx.error(format!("For loop iterator is {}, not an array", iterator.with_article())))
+        };
+        if let Some(index_register) = self.index_register {
+            ctx.registers[index_register] = Type::Num;
+        }
+        ctx.check_stmts(&self.stmts)?;
+        ctx.registers[self.register] = Type::Unknown;
+        if let Some(index_register) = self.index_register {
+            ctx.registers[index_register] = Type::Unknown;
+        }
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::ForRangeNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        for (part, expr) in [("start", &self.start), ("end", &self.end), ("step", &self.step)] {
+            let t = expr.infer_type(ctx)?;
+            if !t.is_compatible(&Type::Num) {
+                return Err(ctx.error(format!("Range {} is {}, not a num", part, t.with_article())));
+            }
+        }
+        ctx.registers[self.register] = Type::Num;
+        ctx.check_stmts(&self.stmts)?;
+        ctx.registers[self.register] = Type::Unknown;
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::ForGenNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        let func = ctx.function(self.func_idx);
+        for (i, (arg, expected)) in self.borrow_args.iter().zip(&func.borrow_types).enumerate() {
+            let t = arg.infer_type(ctx)?;
+            if !expected.is_compatible(&t) {
+                return Err(ctx.error(format!(
+                    "Borrowed argument {} of \"{}\" is annotated {}, but is given {}",
+                    i + 1, func.name, expected, t.with_article())));
+            }
+        }
+        ctx.check_stmts(&self.stmts)?;
+        ctx.registers[self.register] = Type::Unknown;
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::YieldNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        self.expr.infer_type(ctx)?;
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::DoYieldNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        ctx.check_stmts(&self.do_stmts)?;
+        ctx.check_stmts(&self.yield_stmts)
+    }
+}
+
+impl TypedStatement for ST::StaticAssertNode {
+    fn check_types(&self, _ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::BacktraceNode {
+    fn check_types(&self, _ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::TodoNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        ctx.warn(String::from("Unfinished statement \"todo\" will raise an error if it is run"));
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::ExpectNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        self.actual.infer_type(ctx)?;
+        if let Some(expected) = &self.expected {
+            expected.infer_type(ctx)?;
+        }
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::CatchNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        self.expr.infer_type(ctx)?;
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::TryNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        ctx.check_stmts(&self.body)?;
+        self.cond.infer_type(ctx)?;
+        ctx.check_stmts(&self.handler)?;
+        self.bkwd_cond.infer_type(ctx)?;
+        Ok(())
+    }
+}
+
+impl TypedStatement for ST::RememberNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        ctx.check_stmts(&self.body)
+    }
+}
+
+impl TypedStatement for ST::CallNode {
+    fn check_types(&self, ctx: &mut TypeContext) -> Result<(), SyntaxError> {
+        let func = ctx.function(self.func_idx);
+        let (input_types, output_types) = match self.is_uncall {
+            false => (&func.steal_types, &func.return_types),
+            true => (&func.return_types, &func.steal_types)
+        };
+
+        for (i, (arg, expected)) in self.borrow_args.iter().zip(&func.borrow_types).enumerate() {
+            let t = arg.infer_type(ctx)?;
+            if !expected.is_compatible(&t) {
+                return Err(ctx.error(format!(
+                    "Borrowed argument {} of \"{}\" is annotated {}, but is given {}",
+                    i + 1, func.name, expected, t.with_article())));
+            }
+        }
+        for (i, (&register, expected)) in self.stolen_args.iter().zip(input_types).enumerate() {
+            let t = &ctx.registers[register];
+            if !expected.is_compatible(t) {
+                return Err(ctx.error(format!(
+                    "Stolen argument {} of \"{}\" is annotated {}, but is given {}",
+                    i + 1, func.name, expected, t.with_article())));
+            }
+            ctx.registers[register] = Type::Unknown;
+        }
+        for (i, ((&register, bound), returned)) in self.return_args.iter()
+                                                     .zip(&self.return_types)
+                                                     .zip(output_types).enumerate() {
+            if !bound.is_compatible(returned) {
+                return Err(ctx.error(format!(
+                    "Return value {} of \"{}\" is annotated {}, but is bound as {}",
+                    i + 1, func.name, returned, bound)));
+            }
+            // Anything a host function gives back could be a handle, unless annotated otherwise //
+            let returned = match returned {
+                Type::Unknown if func.is_host => &Type::Handle,
+                t => t
+            };
+            ctx.registers[register] = bound.refine(returned);
+        }
+        Ok(())
+    }
+}
+
+
+fn check_function(
+    function: &ST::FunctionNode,
+    functions: &[ST::FunctionNode],
+    globals: &[Type],
+    warnings: &mut Vec<SyntaxError>
+) -> Result<Vec<Type>, SyntaxError> {
+    let mut ctx = TypeContext{
+        functions, globals,
+        registers: vec![Type::Unknown; function.num_registers],
+        position: (0, 0),
+        warnings: RefCell::new(Vec::new())
+    };
+    for (&register, t) in function.borrow_registers.iter().zip(&function.borrow_types) {
+        ctx.registers[register] = t.clone();
+    }
+    for (&register, t) in function.steal_registers.iter().zip(&function.steal_types) {
+        ctx.registers[register] = t.clone();
+    }
+
+    ctx.check_stmts(&function.stmts)?;
+
+    for (i, (&register, expected)) in function.return_registers.iter().zip(&function.return_types).enumerate() {
+        let t = &ctx.registers[register];
+        if !expected.is_compatible(t) {
+            return Err(ctx.error(format!(
+                "Return value {} of \"{}\" is annotated {}, but {} is returned",
+                i + 1, function.name, expected, t.with_article())));
+        }
+    }
+    warnings.append(&mut ctx.warnings.borrow_mut());
+    Ok(ctx.registers)
+}
+
+// Check the module for operations on values of the wrong type, which would fail at run time. The
+// types of globals are taken from the end of the global scope. Returns warnings about the checks
+// that couldn't be made //
+pub fn check_types(module: &ST::Module) -> Result<Vec<SyntaxError>, SyntaxError> {
+    let mut warnings = Vec::new();
+    let globals = check_function(&module.global_func, &module.functions, &[], &mut warnings)?;
+    for function in &module.functions {
+        let registers = check_function(function, &module.functions, &globals, &mut warnings)?;
+        check_handles_released(function, &registers)?;
+    }
+    Ok(warnings)
+}
+
+// A handle still held when its function ends would leave the host object it names behind, with
+// nothing left to release it //
+fn check_handles_released(function: &ST::FunctionNode, registers: &[Type]) -> Result<(), SyntaxError> {
+    let given_back = function.borrow_registers.iter().chain(&function.return_registers);
+    let held = function.symbols.iter()
+        .filter(|symbol| symbol.end.is_none() && registers[symbol.register].holds_handle())
+        .find(|symbol| !given_back.clone().any(|&register| register == symbol.register));
+    match held {
+        Some(symbol) => {
+            let (line, col) = symbol.start.unwrap_or((0, 0));
+            Err(SyntaxError{line, col, desc: format!(
+                "\"{}\" holds a handle that is never released, so must be passed to the host function that releases it \
+                before \"{}\" ends", symbol.name, function.name), note: None})
+        },
+        None => Ok(())
+    }
+}
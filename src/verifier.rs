@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use std::ops::Range;
+
+use crate::interpreter::{Instruction, Module, Function, Region};
+use crate::isa::Stream;
+
+
+#[derive(Debug)]
+pub struct VerifyError {
+    pub func_idx: usize,
+    pub forwards: bool,
+    pub ip: usize,
+    pub desc: String
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "function {}, {} ip {}: {}",
+               self.func_idx, if self.forwards {"fwd"} else {"bkwd"}, self.ip, self.desc)
+    }
+}
+
+
+impl Module {
+    pub fn validate(&self) -> Result<(), VerifyError> {
+        let num_globals = self.functions[self.global_func_idx].num_registers;
+        for (func_idx, func) in self.functions.iter().enumerate() {
+            check_operands(func_idx, func, num_globals, self.functions.len(), self.strings.len())?;
+            check_reverse_links(func_idx, func)?;
+            check_stack_effects(func_idx, func, &self.functions)?;
+            check_reverse_consistency(func_idx, func, &self.functions)?;
+        }
+        Ok(())
+    }
+}
+
+
+fn check_operands(
+    func_idx: usize,
+    func: &Function,
+    num_globals: usize,
+    num_functions: usize,
+    num_strings: usize
+) -> Result<(), VerifyError> {
+    let streams = [(true, &func.code.fwd, &func.code.bkwd), (false, &func.code.bkwd, &func.code.fwd)];
+    for (forwards, instructions, other) in streams.iter() {
+        for (ip, instruction) in instructions.iter().enumerate() {
+            let err = |desc: String| Err(VerifyError{func_idx, forwards: *forwards, ip, desc});
+
+            if let Some(target) = instruction.jump_target() {
+                if target > instructions.len() {
+                    return err(format!("{:?} jumps out of bounds (length {})", instruction, instructions.len()));
+                }
+            }
+            match &instruction {
+                Instruction::Reverse{idx} if *idx > other.len() => {
+                    return err(format!("Reverse target {} out of bounds (length {})", idx, other.len()));
+                },
+                Instruction::LoadRegister{register} | Instruction::StoreRegister{register} |
+                Instruction::FreeRegister{register} | Instruction::Pull{register} |
+                Instruction::Push{register} | Instruction::PullFront{register} |
+                Instruction::PushFront{register} | Instruction::CreateIter{register, ..} |
+                Instruction::CreateIter{index_register: Some(register), ..} |
+                Instruction::CreateRangeIter{register} | Instruction::Resume{register, ..} |
+                Instruction::CheckRef{register} | Instruction::CheckUnshared{register} |
+                Instruction::MoveRegister{from: register, ..} | Instruction::MoveRegister{to: register, ..}
+                if *register >= func.num_registers => {
+                    return err(format!("Register {} out of range ({} registers)", register, func.num_registers));
+                },
+                Instruction::LoadGlobalRegister{register} | Instruction::StoreGlobalRegister{register}
+                if *register >= num_globals => {
+                    return err(format!("Global register {} out of range ({} globals)", register, num_globals));
+                },
+                Instruction::LoadConst{idx} if *idx >= func.consts.len() => {
+                    return err(format!("Const {} out of range ({} consts)", idx, func.consts.len()));
+                },
+                Instruction::LoadString{idx} if *idx >= num_strings => {
+                    return err(format!("String {} out of range ({} strings)", idx, num_strings));
+                },
+                Instruction::Call{idx} | Instruction::Uncall{idx} | Instruction::Remember{idx} |
+                Instruction::CreateGenerator{idx}
+                if *idx >= num_functions => {
+                    return err(format!("Call to undefined function {}", idx));
+                },
+                Instruction::Marker{stmt} => {
+                    let region = match func.code.regions.get(*stmt) {
+                        Some(region) => if *forwards {&region.fwd} else {&region.bkwd},
+                        None => return err(format!("Marker for undefined statement {}", stmt))
+                    };
+                    if !region.contains(&ip) {
+                        return err(format!("Marker for statement {} lies outside its region {:?}", stmt, region));
+                    }
+                },
+                Instruction::RelativeJump{..} | Instruction::RelativeJumpIfTrue{..} |
+                Instruction::RelativeJumpIfFalse{..} => {
+                    return err(format!("{:?} survived finalisation", instruction));
+                },
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+
+// A point in the forward stream and the matching point in the backward stream mark the same moment
+// in execution, so ordered by forward position the linked backward positions must never increase //
+fn check_reverse_links(func_idx: usize, func: &Function) -> Result<(), VerifyError> {
+    let mut links = Vec::new();
+    for (f, instruction) in func.code.fwd.iter().enumerate() {
+        if let Instruction::Reverse{idx} = instruction {
+            links.push((f, idx, true, f));
+        }
+    }
+    for (b, instruction) in func.code.bkwd.iter().enumerate() {
+        if let Instruction::Reverse{idx} = instruction {
+            links.push((idx, b, false, b));
+        }
+    }
+    links.sort();
+
+    for pair in links.windows(2) {
+        let ((f1, b1, ..), (f2, b2, forwards, ip)) = (pair[0], pair[1]);
+        if f1 < f2 && b1 < b2 {
+            return Err(VerifyError{func_idx, forwards, ip, desc: format!(
+                "Reverse link ({}, {}) is inconsistent with earlier link ({}, {})", f2, b2, f1, b1
+            )});
+        }
+    }
+    Ok(())
+}
+
+
+// Abstract interpretation of the stack depth along every path through both streams, following
+// Reverse instructions across. A function starts with its inputs on the stack and must finish
+// with exactly its outputs, and every join point must be reached with a single consistent depth //
+fn check_stack_effects(func_idx: usize, func: &Function, functions: &[Function]) -> Result<(), VerifyError> {
+    let mut depths: HashMap<(bool, usize), usize> = HashMap::new();
+    let mut worklist = vec![
+        (true, 0, func.num_borrows + func.num_steals),
+        (false, 0, func.num_borrows + func.num_returns)
+    ];
+
+    while let Some((forwards, ip, depth)) = worklist.pop() {
+        let err = |desc: String| Err(VerifyError{func_idx, forwards, ip, desc});
+
+        match depths.get(&(forwards, ip)) {
+            Some(&existing) if existing == depth => continue,
+            Some(&existing) => return err(format!(
+                "Inconsistent stack depth at join, {} on one path and {} on another", existing, depth)),
+            None => {depths.insert((forwards, ip), depth);}
+        }
+
+        let instructions = if forwards {&func.code.fwd} else {&func.code.bkwd};
+        let instruction = match instructions.get(ip) {
+            Some(instruction) => instruction,
+            None => {
+                let expected = if forwards {func.num_returns} else {func.num_steals};
+                if depth != expected {
+                    return err(format!(
+                        "Function ends with stack depth {} but should leave {}", depth, expected));
+                }
+                continue;
+            }
+        };
+
+        let (pops, pushes) = match &instruction {
+            Instruction::Call{idx} | Instruction::Uncall{idx} | Instruction::Remember{idx} => {
+                let callee = &functions[*idx];
+                let (inputs, outputs) = match instruction {
+                    Instruction::Call{..} | Instruction::Remember{..} => (callee.num_steals, callee.num_returns),
+                    _ => (callee.num_returns, callee.num_steals)
+                };
+                (callee.num_borrows + inputs, outputs)
+            },
+            Instruction::CreateGenerator{idx} => (functions[*idx].num_borrows, 1),
+            Instruction::HostCall => match forwards {
+                true => (func.num_borrows + func.num_steals, func.num_returns),
+                false => (func.num_borrows + func.num_returns, func.num_steals)
+            },
+            Instruction::Gosub{ip: target} => match subroutine_effect(&instructions.subroutine(*target)) {
+                Some(effect) => effect,
+                None => return err(format!("The subroutine at {} isn't a straight run of instructions", target))
+            },
+            _ => {
+                let effect = instruction.stack_effect().expect("Missing stack effect");
+                (effect.pops, effect.pushes)
+            }
+        };
+        if depth < pops {
+            return err(format!("{:?} pops {} items from a stack of depth {}", instruction, pops, depth));
+        }
+        let next_depth = depth - pops + pushes;
+
+        match &instruction {
+            Instruction::Jump{ip: target} => worklist.push((forwards, *target, next_depth)),
+            Instruction::JumpIfTrue{ip: target} | Instruction::JumpIfFalse{ip: target} => {
+                worklist.push((forwards, *target, next_depth));
+                worklist.push((forwards, ip + 1, next_depth));
+            },
+            Instruction::StepIter{ip: target} | Instruction::ParallelStepIter{ip: target} |
+            Instruction::Resume{ip: target, ..} => {
+                // Exhausted iterators and finished generators are popped before jumping //
+                worklist.push((forwards, *target, next_depth - 1));
+                worklist.push((forwards, ip + 1, next_depth));
+            },
+            Instruction::Reverse{idx} => worklist.push((!forwards, *idx, next_depth)),
+            Instruction::Quit => {},
+            _ => worklist.push((forwards, ip + 1, next_depth))
+        }
+    }
+    Ok(())
+}
+
+
+// Each reversible statement's backward code must undo its forward code: the state-changing
+// instructions outside nested statements appear inverted and in reverse order, and the nested
+// reversible statements are undone in the opposite order to the one they were done in //
+fn check_reverse_consistency(func_idx: usize, func: &Function, functions: &[Function]) -> Result<(), VerifyError> {
+    let regions = &func.code.regions;
+    for (idx, region) in regions.iter().enumerate() {
+        if region.is_mono || region.fwd.is_empty() || region.bkwd.is_empty() {continue}
+        let err = |desc: String| Err(VerifyError{func_idx, forwards: true, ip: region.fwd.start, desc});
+
+        let descendants: Vec<&Region> = (0..regions.len())
+            .filter(|&other| is_descendant(regions, other, idx))
+            .map(|other| &regions[other])
+            .collect();
+
+        let fwd_nested: Vec<Range<usize>> = descendants.iter().map(|r| r.fwd.clone()).collect();
+        let bkwd_nested: Vec<Range<usize>> = descendants.iter().map(|r| r.bkwd.clone()).collect();
+        let fwd_effects = effects(&func.code.fwd, &region.fwd, &fwd_nested, functions);
+        let bkwd_effects = effects(&func.code.bkwd, &region.bkwd, &bkwd_nested, functions);
+        let expected: Option<Vec<Instruction>> = fwd_effects.iter().rev().map(|i| i.inverse()).collect();
+        if expected.as_ref() != Some(&bkwd_effects) {
+            return err(format!(
+                "Backward effects {:?} do not undo forward effects {:?}", bkwd_effects, fwd_effects));
+        }
+
+        // Only reversible direct children take part in the ordering //
+        let mut children: Vec<usize> = (0..regions.len())
+            .filter(|&child| {
+                let r = &regions[child];
+                !r.is_mono && !r.fwd.is_empty() && !r.bkwd.is_empty() &&
+                is_descendant(regions, child, idx) &&
+                !(0..regions.len()).any(|mid| is_descendant(regions, child, mid) && is_descendant(regions, mid, idx))
+            })
+            .collect();
+        children.sort_by_key(|&child| regions[child].fwd.start);
+        let fwd_order = children.clone();
+        children.sort_by_key(|&child| std::cmp::Reverse(regions[child].bkwd.start));
+        if fwd_order != children {
+            return err(format!(
+                "Nested statements are undone in the wrong order, fwd {:?} vs bkwd {:?}", fwd_order, children));
+        }
+    }
+    Ok(())
+}
+
+// The items an outlined block needs on the stack and the items it leaves there, if it's a straight
+// run of instructions //
+fn subroutine_effect(body: &[Instruction]) -> Option<(usize, usize)> {
+    let (mut depth, mut lowest) = (0isize, 0isize);
+    for instruction in body {
+        let effect = instruction.stack_effect().filter(|_| instruction.jump_target().is_none())?;
+        depth -= effect.pops as isize;
+        lowest = lowest.min(depth);
+        depth += effect.pushes as isize;
+    }
+    Some((-lowest as usize, (depth - lowest) as usize))
+}
+
+fn contains(outer: &Range<usize>, inner: &Range<usize>) -> bool {
+    outer.start <= inner.start && inner.end <= outer.end
+}
+
+// Whether region `inner` lies within region `outer`. Regions are recorded innermost first,
+// so of two regions spanning identical code the earlier one is the nested one //
+fn is_descendant(regions: &[Region], inner: usize, outer: usize) -> bool {
+    let (a, b) = (&regions[inner], &regions[outer]);
+    inner != outer &&
+    contains(&b.fwd, &a.fwd) && (a.bkwd.is_empty() || contains(&b.bkwd, &a.bkwd)) &&
+    (inner < outer || a.fwd != b.fwd || a.bkwd != b.bkwd)
+}
+
+// The instructions in `range` that change program state, skipping nested statements. A Store is
+// represented by the operator that computed its value, as the two are only inverted together.
+// Calling a pure function only computes a value, like any other operator //
+fn effects(
+    instructions: &Stream,
+    range: &Range<usize>,
+    nested: &[Range<usize>],
+    functions: &[Function]
+) -> Vec<Instruction> {
+    // Outlined blocks count as if their instructions stood in place of the Gosub //
+    let mut code = Vec::new();
+    for ip in range.clone() {
+        if nested.iter().any(|r| r.contains(&ip)) {continue}
+        match instructions.get(ip).expect("Region out of bounds") {
+            Instruction::Gosub{ip: start} => {
+                let body = instructions.subroutine(start);
+                code.extend(body.iter().enumerate().map(|(idx, &instruction)| (instruction, idx.checked_sub(1).map(|prev| body[prev]))));
+            },
+            instruction => code.push((instruction, (ip > range.start).then(|| instructions.get(ip - 1).unwrap())))
+        }
+    }
+
+    let mut ret = Vec::new();
+    for (instruction, prev) in code {
+        match instruction {
+            Instruction::Call{idx} | Instruction::Uncall{idx} if functions[idx].is_pure => {},
+            Instruction::Remember{..} => {},
+            Instruction::StoreRegister{..} | Instruction::FreeRegister{..} | Instruction::MoveRegister{..} |
+            Instruction::Push{..} | Instruction::Pull{..} |
+            Instruction::PushFront{..} | Instruction::PullFront{..} |
+            Instruction::Call{..} | Instruction::Uncall{..} | Instruction::Print{..} => {
+                ret.push(instruction);
+            },
+            Instruction::StoreGlobalRegister{register} => {
+                ret.push(Instruction::StoreRegister{register});
+            },
+            Instruction::Store => ret.push(prev.unwrap_or(Instruction::Store)),
+            _ => {}
+        }
+    }
+
+    // A register stored to and then emptied again within the range is scratch space (e.g. for
+    // array comprehensions), so leaves no effect to undo //
+    let mut scratch = vec![false; ret.len()];
+    let mut stores: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (idx, instruction) in ret.iter().enumerate() {
+        match instruction {
+            Instruction::StoreRegister{register} => stores.entry(*register).or_default().push(idx),
+            Instruction::FreeRegister{register} | Instruction::Push{register} => {
+                if let Some(pending) = stores.remove(register).filter(|p| !p.is_empty()) {
+                    for store in pending {scratch[store] = true}
+                    scratch[idx] = true;
+                }
+            },
+            _ => {}
+        }
+    }
+    let mut scratch = scratch.into_iter();
+    ret.retain(|_| !scratch.next().unwrap());
+    ret
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use monoxide::convert::IntoReaver;
+use monoxide::interpreter::{Arithmetic, Interpreter};
+use monoxide::options::EngineOptions;
+
+
+const SRC: &str = "
+fn scale()(y) {
+    y /= 2;
+    y *= 3;
+}
+~scale(y)
+
+fn main()() {}
+~main()
+";
+
+// With and without the peephole pass, which turns division by two into a shift //
+fn each_program(test: impl Fn(&monoxide::interpreter::Module)) {
+    for opt_level in 0..=1 {
+        test(&common::compile_with(SRC, &EngineOptions::default().with_opt_level(opt_level)));
+    }
+}
+
+#[test]
+fn fixed_width_division_round_trips() {
+    each_program(|program| {
+        let scale = common::func_idx(program, "scale");
+        for arithmetic in [Arithmetic::Checked, Arithmetic::Wrapping] {
+            let mut interpreter = Interpreter::new(program);
+            interpreter.set_arithmetic(arithmetic);
+            let returned = interpreter.run_entry(program, scale, vec![4i64.into_reaver()]);
+            assert_eq!(returned, vec![6i64.into_reaver()]);
+            let stolen = interpreter.uncall_entry(program, scale, Vec::new(), returned);
+            assert_eq!(stolen, vec![4i64.into_reaver()]);
+        }
+    });
+}
+
+#[test]
+fn inexact_fixed_width_division_fails() {
+    each_program(|program| {
+        let scale = common::func_idx(program, "scale");
+        for arithmetic in [Arithmetic::Checked, Arithmetic::Wrapping] {
+            let mut interpreter = Interpreter::new(program);
+            interpreter.set_arithmetic(arithmetic);
+            let err = interpreter.try_run_entry(program, scale, vec![3i64.into_reaver()]).unwrap_err();
+            assert!(err.to_string().contains("Inexact division 3 / 2"), "{}", err);
+        }
+    });
+}
+
+#[test]
+fn exact_division_keeps_fractions() {
+    each_program(|program| {
+        let scale = common::func_idx(program, "scale");
+        let mut interpreter = Interpreter::new(program);
+        let returned = interpreter.run_entry(program, scale, vec![3i64.into_reaver()]);
+        let stolen = interpreter.uncall_entry(program, scale, Vec::new(), returned);
+        assert_eq!(stolen, vec![3i64.into_reaver()]);
+    });
+}
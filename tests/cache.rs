@@ -0,0 +1,91 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+
+const APP: &str = "
+import alpha;
+import beta;
+
+fn main()() {
+    x = 1;
+    x => alpha::bump() => x;
+    x => beta::scale() => x;
+    println(x);
+    x => ~beta::scale() => x;
+    x => ~alpha::bump() => x;
+    x ~= 1;
+}
+~main()
+";
+
+const ALPHA: &str = "
+pub fn bump()(x) {
+    x += 1;
+}
+~bump(x)
+";
+
+const BETA: &str = "
+import alpha;
+
+pub fn scale()(x) {
+    x *= 3;
+    x => alpha::bump() => x;
+}
+~scale(x)
+";
+
+fn project(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("reaver.toml"), "name = \"app\"\n").unwrap();
+    fs::write(dir.join("app.mx"), APP).unwrap();
+    fs::write(dir.join("alpha.mx"), ALPHA).unwrap();
+    fs::write(dir.join("beta.mx"), BETA).unwrap();
+    dir
+}
+
+fn run(dir: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_monoxide")).arg("run").current_dir(dir).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn modified(path: PathBuf) -> SystemTime {
+    fs::metadata(path).unwrap().modified().unwrap()
+}
+
+// Each imported module is cached on its own, so editing one leaves the others' artifacts as they were //
+#[test]
+fn only_changed_modules_are_recompiled() {
+    let dir = project("cache_modules");
+    let modules = dir.join("target/reaver/modules");
+    assert_eq!(run(&dir), "7\n");
+    let (alpha, beta) = (modified(modules.join("alpha.rvc")), modified(modules.join("beta.rvc")));
+
+    assert_eq!(run(&dir), "7\n");
+    assert_eq!((modified(modules.join("alpha.rvc")), modified(modules.join("beta.rvc"))), (alpha, beta));
+
+    let compiled = fs::read_to_string(modules.join("beta.rvc")).unwrap();
+    fs::write(dir.join("beta.mx"), BETA.replace("x *= 3;", "x *= 4;")).unwrap();
+    assert_eq!(run(&dir), "9\n");
+    assert_eq!(modified(modules.join("alpha.rvc")), alpha);
+    assert_ne!(fs::read_to_string(modules.join("beta.rvc")).unwrap(), compiled);
+}
+
+// An edit to a module changes the keys of the modules importing it, so they're compiled again
+// against it //
+#[test]
+fn modules_are_recompiled_when_their_imports_change() {
+    let dir = project("cache_imports");
+    let modules = dir.join("target/reaver/modules");
+    assert_eq!(run(&dir), "7\n");
+    let beta = fs::read_to_string(modules.join("beta.rvc")).unwrap();
+
+    fs::write(dir.join("alpha.mx"), ALPHA.replace("x += 1;", "x += 2;")).unwrap();
+    assert_eq!(run(&dir), "11\n");
+    assert_ne!(fs::read_to_string(modules.join("beta.rvc")).unwrap(), beta);
+}
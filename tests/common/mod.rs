@@ -0,0 +1,45 @@
+// Helpers shared by the integration tests //
+#![allow(dead_code)]
+
+use monoxide::cfg::Cfg;
+use monoxide::interpreter::{Interpreter, Module};
+use monoxide::options::EngineOptions;
+use monoxide::parser;
+use monoxide::syntaxchecker::check_syntax_quietly;
+use monoxide::tokeniser::Tokeniser;
+
+
+pub fn compile(src: &str) -> Module {
+    compile_with(src, &EngineOptions::default())
+}
+
+pub fn compile_with(src: &str, options: &EngineOptions) -> Module {
+    compile_checked(src, options, false)
+}
+
+// Keeping the names of variables, for the debugger //
+pub fn compile_debug(src: &str) -> Module {
+    compile_checked(src, &EngineOptions::default(), true)
+}
+
+fn compile_checked(src: &str, options: &EngineOptions, debug_symbols: bool) -> Module {
+    let parsed = parser::parse(Tokeniser::new(src)).unwrap_or_else(|err| panic!("{}", err));
+    let mut cfg = Cfg::default();
+    cfg.set_opt_level(options.opt_level);
+    let (module, _) = check_syntax_quietly(parsed, &cfg, false)
+        .unwrap_or_else(|err| panic!("SyntaxError at line {}, column {}: {}", err.line, err.col, err.desc));
+    module.compile(debug_symbols, options)
+}
+
+// Everything the program prints running main //
+pub fn output(program: &Module) -> String {
+    let mut interpreter = Interpreter::new(program);
+    interpreter.capture_output();
+    interpreter.run_to_completion(program);
+    interpreter.take_output()
+}
+
+pub fn func_idx(program: &Module, name: &str) -> usize {
+    program.functions.iter().position(|function| function.name == name)
+        .unwrap_or_else(|| panic!("No function named {}", name))
+}
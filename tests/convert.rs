@@ -0,0 +1,66 @@
+use monoxide::convert::{array_of, FromReaver, IntoReaver};
+use monoxide::interpreter::{Fraction, Variable};
+use monoxide::reaver_struct;
+use num_bigint::BigInt;
+
+
+fn round_trip<T: IntoReaver + FromReaver + Clone>(value: T) -> T {
+    T::from_reaver(&value.into_reaver()).unwrap()
+}
+
+#[test]
+fn scalars_round_trip() {
+    assert_eq!(round_trip(-12i64), -12);
+    assert_eq!(round_trip(String::from("text")), "text");
+    let third = Fraction::new(BigInt::from(1), BigInt::from(3));
+    assert_eq!(round_trip(third.clone()), third);
+    assert_eq!(round_trip(0.25f64), 0.25);
+}
+
+#[test]
+fn vecs_round_trip() {
+    assert_eq!(round_trip(vec![vec![1i64, 2], vec![], vec![3]]), vec![vec![1i64, 2], vec![], vec![3]]);
+    assert_eq!(round_trip(vec![String::from("a"), String::from("b")]), vec!["a", "b"]);
+}
+
+#[test]
+fn lossy_and_mismatched_conversions() {
+    let half = Fraction::new(BigInt::from(1), BigInt::from(2)).into_reaver();
+    assert_eq!(f64::from_reaver(&half).unwrap(), 0.5);
+    assert!(i64::from_reaver(&half).is_err());
+    assert!(String::from_reaver(&half).is_err());
+    assert!(Vec::<i64>::from_reaver(&"text".into_reaver()).is_err());
+    let huge = Variable::Frac(Fraction::from_integer(BigInt::from(i64::MAX) * 2));
+    assert!(i64::from_reaver(&huge).is_err());
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Point {
+    x: i64,
+    y: i64
+}
+reaver_struct!(Point {x, y});
+
+#[derive(Debug, Clone, PartialEq)]
+struct Path {
+    name: String,
+    points: Vec<Point>
+}
+reaver_struct!(Path {name, points});
+
+#[test]
+fn structs_round_trip_as_arrays() {
+    let point = Point{x: 1, y: -2};
+    assert_eq!(point.clone().into_reaver(), vec![1i64, -2].into_reaver());
+    assert_eq!(round_trip(point), Point{x: 1, y: -2});
+
+    let path = Path{name: String::from("p"), points: vec![Point{x: 0, y: 0}, Point{x: 3, y: 4}]};
+    assert_eq!(round_trip(path.clone()), path);
+}
+
+#[test]
+fn struct_conversion_errors_name_the_field() {
+    assert!(Point::from_reaver(&vec![1i64].into_reaver()).unwrap_err().desc.contains("array of 2 items"));
+    let bad = array_of(vec![1i64.into_reaver(), "y".into_reaver()]);
+    assert!(Point::from_reaver(&bad).unwrap_err().desc.contains("field \"y\" of Point"));
+}
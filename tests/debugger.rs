@@ -0,0 +1,34 @@
+mod common;
+
+use monoxide::debugger::{Debugger, Stop};
+
+
+// The line of the statement that changed the watched value, at each watchpoint hit //
+fn watch_lines(src: &str, expr: &str) -> Vec<usize> {
+    let program = common::compile_debug(src);
+    let mut debugger = Debugger::new(&program);
+    debugger.interpreter_mut().capture_output();
+    debugger.watch(expr).unwrap();
+    let mut lines = Vec::new();
+    loop {
+        match debugger.resume() {
+            Stop::Watchpoint(_, (func_idx, forwards, ip)) => lines.push(debugger.line_at(func_idx, forwards, ip)),
+            Stop::Finished => return lines,
+            _ => panic!("Stopped for something other than a watchpoint")
+        }
+    }
+}
+
+#[test]
+fn watchpoints_report_the_changing_line() {
+    let src = "fn main()() {
+    x = 0;
+    y = 1;
+    x += 2;
+    println(x);
+    x ~= 2;
+}
+~main()
+";
+    assert_eq!(watch_lines(src, "x"), vec![2, 4, 6]);
+}
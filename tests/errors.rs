@@ -0,0 +1,56 @@
+use std::fs;
+use std::panic;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use monoxide::interpreter::Interpreter;
+
+mod common;
+
+
+static PANICS: AtomicUsize = AtomicUsize::new(0);
+
+// The body of main on each line, with what failing it reports //
+const FAILURES: [(&str, &str); 7] = [
+    ("A = [1];\n    x = A[5];", "Index 5 is out of bounds for an array of length 1"),
+    ("x = 1;\n    y = x / 0;", "Division by zero"),
+    ("A = [];\n    x <= A;", "Pulling from empty array"),
+    ("A = [1]; i = -1;\n    x = A[i];", "Index is not a non-negative integer"),
+    ("x = 2;\n    expect x == 3;", "Expectation \"x == 3\" failed running forwards: expected 3, got 2"),
+    ("a = 10 ** 300; b = a * a;\n    .y = to_float(b);", "Rationals exceed f64 precision"),
+    ("a = 10 ** 300; b = a * a; .f = to_float(2);\n    .y = b * .f;", "Rationals exceed f64 precision")
+];
+
+// Failures are returned as errors positioned at the failing statement, without anything panicking
+// on the way. The other test here runs the binary, so nothing else can panic while the hook is counting //
+#[test]
+fn runtime_errors_are_returned_without_panicking() {
+    let programs: Vec<_> = FAILURES.iter()
+        .map(|(body, _)| common::compile(&format!("fn main()() {{\n    {}\n}}\n~main()\n", body)))
+        .collect();
+    panic::set_hook(Box::new(|_| {PANICS.fetch_add(1, Ordering::SeqCst);}));
+    let results: Vec<_> = programs.iter().map(|program| {
+        let mut interpreter = Interpreter::new(program);
+        interpreter.capture_output();
+        interpreter.try_run_to_completion(program)
+    }).collect();
+    let _ = panic::take_hook();
+    assert_eq!(PANICS.load(Ordering::SeqCst), 0);
+    for (result, (_, desc)) in results.into_iter().zip(FAILURES) {
+        let err = result.unwrap_err();
+        assert_eq!((err.line, err.desc.as_str()), (3, desc), "{}", err);
+    }
+}
+
+// A file that doesn't parse is reported, not panicked over //
+#[test]
+fn parse_errors_exit_with_a_report() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("unparsable.mx");
+    fs::write(&path, "fn main()() {\n    x = (;\n}\n~main()\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_monoxide")).arg(&path).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("ParseError at line 2, column 9"), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use monoxide::interpreter::Interpreter;
+
+
+const SRC: &str = "
+fn f(n)(t) {
+    t += n;
+}
+~f(t)
+
+fn g(n)(t) {
+    x = n * 2;
+    t += x;
+    x ~= n * 2;
+}
+~g(t)
+
+fn main()() {
+    n = 1;
+    t = 0;
+    t => g(n) => t;
+    t => f(n) => t;
+    t => ~g(n) => t;
+    t => ~f(n) => t;
+    println(t);
+    t ~= 0;
+    n ~= 1;
+}
+~main()
+";
+
+// Each function allocates one register window, which its later calls and uncalls take back even
+// when another function's window was returned more recently //
+#[test]
+fn uncalls_reuse_the_window_of_the_same_function() {
+    let program = common::compile(SRC);
+    let mut interpreter = Interpreter::new(&program);
+    interpreter.capture_output();
+    interpreter.run_to_completion(&program);
+    assert_eq!(interpreter.take_output(), "0\n");
+    let frames = interpreter.frame_stats();
+    assert_eq!((frames.allocated, frames.reused), (3, 2));
+}
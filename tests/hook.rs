@@ -0,0 +1,107 @@
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use monoxide::hook::ExecutionHook;
+use monoxide::interpreter::{Instruction, Interpreter};
+
+
+#[derive(Default)]
+struct Recorder {
+    before: usize,
+    after: usize,
+    calls: Vec<(usize, bool)>,
+    reversals: Vec<bool>
+}
+
+impl ExecutionHook for Recorder {
+    fn before_instruction(&mut self, _interpreter: &Interpreter, _instruction: Instruction) {
+        self.before += 1;
+    }
+
+    fn after_instruction(&mut self, _interpreter: &Interpreter, _instruction: Instruction) {
+        self.after += 1;
+    }
+
+    fn on_call(&mut self, _interpreter: &Interpreter, func_idx: usize, forwards: bool) {
+        self.calls.push((func_idx, forwards));
+    }
+
+    fn on_reversal(&mut self, _interpreter: &Interpreter, caught: bool) {
+        self.reversals.push(caught);
+    }
+}
+
+const SRC: &str = "
+fn inc(x)() {
+    x += 1;
+}
+~inc()
+
+fn main()() {
+    x = 0;
+    inc(x);
+    ~inc(x);
+    x ~= 0;
+}
+~main()
+";
+
+#[test]
+fn hook_sees_every_instruction_and_call() {
+    let program = common::compile(SRC);
+    let recorder = Rc::new(RefCell::new(Recorder::default()));
+    let mut interpreter = Interpreter::new(&program);
+    interpreter.set_hook(Some(recorder.clone()));
+    interpreter.run_to_completion(&program);
+
+    let recorder = recorder.borrow();
+    let inc = common::func_idx(&program, "inc");
+    assert!(recorder.before > 0);
+    assert_eq!(recorder.before, recorder.after);
+    assert_eq!(recorder.calls.iter().filter(|call| call.0 == inc).copied().collect::<Vec<_>>(),
+               vec![(inc, true), (inc, false)]);
+    assert!(recorder.reversals.is_empty());
+}
+
+#[test]
+fn hook_sees_reversals() {
+    let program = common::compile("
+fn main()() {
+    x = 0;
+    try {
+        x += 1;
+    } catch (x == 1) {
+        x += 2;
+    }
+    println(x);
+}
+~main()
+");
+    let recorder = Rc::new(RefCell::new(Recorder::default()));
+    let mut interpreter = Interpreter::new(&program);
+    interpreter.capture_output();
+    interpreter.set_hook(Some(recorder.clone()));
+    interpreter.run_to_completion(&program);
+    assert!(!recorder.borrow().reversals.is_empty());
+}
+
+#[test]
+fn unhooked_runs_match_hooked_runs() {
+    let program = common::compile("
+fn main()() {
+    x = 3;
+    while (x < 10) {
+        x += 2;
+    } ~while (x > 3);
+    println(x);
+}
+~main()
+");
+    let mut hooked = Interpreter::new(&program);
+    hooked.capture_output();
+    hooked.set_hook(Some(Rc::new(RefCell::new(Recorder::default()))));
+    hooked.run_to_completion(&program);
+    assert_eq!(hooked.take_output(), common::output(&program));
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use monoxide::convert::{FromReaver, IntoReaver};
+use monoxide::host::{self, Handles, HostFunction};
+use monoxide::cfg::Cfg;
+use monoxide::interpreter::{Interpreter, Module, Variable};
+use monoxide::options::EngineOptions;
+use monoxide::parser;
+use monoxide::syntaxchecker::{check_syntax_quietly, SyntaxError};
+use monoxide::tokeniser::Tokeniser;
+
+
+// Doubles what it's given, keeping a count of its calls in a host object behind a handle //
+struct Double;
+
+impl HostFunction for Double {
+    fn call(&mut self, handles: &mut Handles, borrowed: &[Variable], stolen: Vec<Variable>) -> Result<Vec<Variable>, String> {
+        *handles.get_mut::<usize>(&borrowed[0]).unwrap() += 1;
+        Ok(stolen.iter().map(|value| (i64::from_reaver(value).unwrap() * 2).into_reaver()).collect())
+    }
+
+    fn uncall(&mut self, handles: &mut Handles, borrowed: &[Variable], returned: Vec<Variable>) -> Result<Vec<Variable>, String> {
+        *handles.get_mut::<usize>(&borrowed[0]).unwrap() -= 1;
+        Ok(returned.iter().map(|value| (i64::from_reaver(value).unwrap() / 2).into_reaver()).collect())
+    }
+}
+
+const SRC: &str = "
+#[host]
+fn double(counter)(x: num) {} ~double(y: num)
+
+fn apply(counter)(x) {
+    x => double(counter) => y;
+}
+~apply(y)
+
+fn main()() {}
+~main()
+";
+
+#[test]
+fn host_call_and_uncall() {
+    let program = common::compile(SRC);
+    let apply = common::func_idx(&program, "apply");
+    let mut interpreter = Interpreter::new(&program);
+    interpreter.register_host("double", Box::new(Double));
+    let counter = interpreter.handles().insert(Box::new(0usize));
+
+    let returned = interpreter.run_entry(&program, apply, vec![counter.clone(), 21.into_reaver()]);
+    assert_eq!(returned, vec![42.into_reaver()]);
+    assert_eq!(interpreter.handles().get::<usize>(&counter), Some(&1));
+
+    let stolen = interpreter.uncall_entry(&program, apply, vec![counter.clone()], returned);
+    assert_eq!(stolen, vec![21.into_reaver()]);
+    assert_eq!(interpreter.handles().get::<usize>(&counter), Some(&0));
+}
+
+const STASH: &str = "
+#[host]
+fn stash_open()() {} ~stash_open(stash)
+
+#[host]
+fn stash_put(stash)(value: num) {} ~stash_put()
+
+fn main()() {
+    stash_open() => s;
+    x = 5;
+    x => stash_put(s);
+    y = 7;
+    y => stash_put(s);
+    ~stash_put(s) => a;
+    ~stash_put(s) => b;
+    println(a);
+    println(b);
+    b ~= 5;
+    a ~= 7;
+    s => ~stash_open();
+}
+~main()
+";
+
+#[test]
+fn builtin_stash_gives_values_back_in_reverse() {
+    let program = common::compile(STASH);
+    let mut interpreter = Interpreter::new(&program);
+    host::register_builtins(&mut interpreter);
+    interpreter.capture_output();
+    interpreter.run_to_completion(&program);
+    assert_eq!(interpreter.take_output(), "7\n5\n");
+    assert_eq!(interpreter.handles().live(), 0);
+}
+
+fn check(src: &str) -> Result<Module, SyntaxError> {
+    let parsed = parser::parse(Tokeniser::new(src)).unwrap();
+    let (module, _) = check_syntax_quietly(parsed, &Cfg::default(), false)?;
+    Ok(module.compile(false, &EngineOptions::default()))
+}
+
+// What an unannotated host function gives back is a handle, so can only be moved //
+#[test]
+fn handles_are_moved_and_released() {
+    let copied = STASH.replace("    x = 5;\n", "    t = s;\n    t ~= s;\n    x = 5;\n");
+    let err = check(&copied).err().unwrap();
+    assert_eq!((err.line, err.desc.as_str()),
+               (10, "Copying a handle, which can only be moved by stealing, returning, pushing or pulling it"));
+
+    let unreleased = STASH.replace("    s => ~stash_open();\n", "");
+    let err = check(&unreleased).err().unwrap();
+    assert_eq!((err.line, err.col), (9, 4));
+    assert!(err.desc.starts_with("\"s\" holds a handle that is never released"), "{}", err.desc);
+}
+
+#[test]
+fn host_errors_stop_the_program() {
+    let program = check(&STASH.replace("    ~stash_put(s) => b;\n", "    ~stash_put(s) => b;\n    ~stash_put(s) => c;\n")).unwrap();
+    let mut interpreter = Interpreter::new(&program);
+    host::register_builtins(&mut interpreter);
+    interpreter.capture_output();
+    let err = interpreter.try_run_to_completion(&program).err().unwrap();
+    assert_eq!((err.line, err.desc.as_str()), (16, "In host function \"stash_put\": Took from an empty stash"));
+}
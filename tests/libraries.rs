@@ -0,0 +1,105 @@
+mod common;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use monoxide::cfg::Cfg;
+use monoxide::interpreter::Module;
+use monoxide::options::EngineOptions;
+use monoxide::parser;
+use monoxide::syntaxchecker::{check_syntax_quietly, SyntaxError};
+use monoxide::tokeniser::Tokeniser;
+
+
+const LIBRARY: &str = "
+import std;
+
+fn twice(n)(x) {
+    x += n * 2;
+}
+~twice(x)
+
+pub fn both(&g a, &g b)(x) {
+    x += a + b;
+    n = 1;
+    x => twice(n) => x;
+    n ~= 1;
+}
+~both(x)
+
+pub fn sort()(data) {
+    println('sorting ', data);
+    data => std::sort() => sorted, perm;
+}
+~sort(sorted, perm)
+";
+
+const PROGRAM: &str = "
+import links;
+
+fn main()() {
+    A = [1, 2];
+    B = [3, 4];
+    x = &A[0];
+    y = &A[1];
+    z = &B[0];
+    t = 0;
+    t => links::both(x, y) => t;
+    println(t);
+    t => ~links::both(x, y) => t;
+    t ~= 0;
+    z ~= &B[0];
+    y ~= &A[1];
+    x ~= &A[0];
+    data = [3, 1, 2];
+    data => links::sort() => sorted, perm;
+    println(sorted);
+    sorted, perm => ~links::sort() => data;
+    data ~= [3, 1, 2];
+}
+~main()
+";
+
+// A directory holding the library compiled to an artifact by "--emit rvc", without its source //
+fn linked_library(name: &str) -> PathBuf {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("links.mx");
+    fs::write(&source, LIBRARY).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_monoxide"))
+        .args(["--emit", "rvc"]).arg(&source)
+        .output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    fs::write(dir.join("links.rvc"), output.stdout).unwrap();
+    fs::remove_file(&source).unwrap();
+    dir
+}
+
+fn compile_linked(src: &str, dir: &Path) -> Result<Module, SyntaxError> {
+    let mut cfg = Cfg::default();
+    cfg.add_include_path(dir);
+    let parsed = parser::parse(Tokeniser::new(src)).unwrap();
+    let (module, _) = check_syntax_quietly(parsed, &cfg, false)?;
+    Ok(module.compile(false, &EngineOptions::default()))
+}
+
+#[test]
+fn calls_into_linked_libraries_run() {
+    let dir = linked_library("linked_calls");
+    let program = compile_linked(PROGRAM, &dir).unwrap_or_else(|err| panic!("{}: {}", err.line, err.desc));
+    assert_eq!(common::output(&program), "5\nsorting [3, 1, 2]\n[1, 2, 3]\nsorting [3, 1, 2]\n");
+}
+
+// Link groups are checked against the prototypes stored in the artifact, as they would be from source //
+#[test]
+fn linked_calls_are_held_to_their_link_groups() {
+    let dir = linked_library("linked_link_groups");
+    let err = compile_linked(&PROGRAM.replace("links::both(x, y)", "links::both(x, z)"), &dir).err().unwrap();
+    assert_eq!((err.line, err.desc.as_str()), (11, "Passing incorrectly linked references"));
+
+    // Only the functions a library exports can be called //
+    let private = PROGRAM.replace("t => links::both(x, y) => t;", "n = 1;\n    t => links::twice(n) => t;");
+    let err = compile_linked(&private, &dir).err().unwrap();
+    assert_eq!(err.desc, "Undefined function \"links::twice\"");
+}
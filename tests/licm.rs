@@ -0,0 +1,92 @@
+mod common;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use monoxide::convert::IntoReaver;
+use monoxide::hook::ExecutionHook;
+use monoxide::interpreter::{Arithmetic, Instruction, Interpreter, Module};
+use monoxide::options::EngineOptions;
+
+
+// Counts the comparisons for equality run //
+#[derive(Default)]
+struct EqCounter {
+    count: usize
+}
+
+impl ExecutionHook for EqCounter {
+    fn before_instruction(&mut self, _interpreter: &Interpreter, instruction: Instruction) {
+        if instruction == Instruction::BinopEq {
+            self.count += 1;
+        }
+    }
+}
+
+const SRC: &str = "
+fn count(a, b, n)(t) {
+    i = 0;
+    while (i < n) {
+        t += (a == b);
+        i += 1;
+    } ~while (i > 0);
+    i ~= n;
+}
+~count(t)
+
+fn main()() {}
+~main()
+";
+
+fn compile(opt_level: u8) -> Module {
+    common::compile_with(SRC, &EngineOptions::default().with_opt_level(opt_level))
+}
+
+// The comparisons run calling count on 10 iterations, then uncalling it //
+fn comparisons(program: &Module) -> (usize, usize) {
+    let count = common::func_idx(program, "count");
+    let args = || vec![3.into_reaver(), 3.into_reaver(), 10.into_reaver()];
+    let counter = Rc::new(RefCell::new(EqCounter::default()));
+    let mut interpreter = Interpreter::new(program);
+    interpreter.set_hook(Some(counter.clone()));
+
+    let returned = interpreter.run_entry(program, count, [args(), vec![0.into_reaver()]].concat());
+    assert_eq!(returned, vec![10.into_reaver()]);
+    let forwards = counter.borrow().count;
+    let stolen = interpreter.uncall_entry(program, count, args(), returned);
+    assert_eq!(stolen, vec![0.into_reaver()]);
+    let backwards = counter.borrow().count - forwards;
+    (forwards, backwards)
+}
+
+#[test]
+fn invariants_are_hoisted_in_both_directions() {
+    assert_eq!(comparisons(&compile(1)), (1, 1));
+}
+
+#[test]
+fn invariants_stay_put_at_opt_level_0() {
+    assert_eq!(comparisons(&compile(0)), (10, 10));
+}
+
+#[test]
+fn arithmetic_is_not_hoisted_out_of_loops_that_never_run() {
+    let program = common::compile_with("
+fn main()() {
+    big = 10000000000;
+    i = 0;
+    t = 0;
+    while (i < 0) {
+        t += big * big + i;
+        i += 1;
+    } ~while (i > 0);
+    println(t);
+}
+~main()
+", &EngineOptions::default().with_opt_level(1));
+    let mut interpreter = Interpreter::new(&program);
+    interpreter.set_arithmetic(Arithmetic::Checked);
+    interpreter.capture_output();
+    interpreter.try_run_to_completion(&program).unwrap();
+    assert_eq!(interpreter.take_output(), "0\n");
+}
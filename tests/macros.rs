@@ -0,0 +1,56 @@
+mod common;
+
+use monoxide::parser;
+use monoxide::tokeniser::Tokeniser;
+
+
+#[test]
+fn comments_may_start_with_a_letter() {
+    let program = common::compile("
+$Not reversible$
+fn main()() {
+    x = 1;  $x is consumed below$
+    println(x);
+    x ~= 1;
+}
+~main()
+");
+    assert_eq!(common::output(&program), "1\n");
+}
+
+#[test]
+fn macro_params_are_written_with_an_at() {
+    let program = common::compile("
+macro swapadd(@a, @b) {
+    @a += @b;
+    t = @a;
+    @b += t;
+    t ~= @a;
+}
+
+fn main()() {
+    x = 1;
+    y = 2;
+    swapadd!(x, y);  $Expands in place$
+    println(x);
+    println(y);
+    y ~= 5;
+    x ~= 3;
+}
+~main()
+");
+    assert_eq!(common::output(&program), "3\n5\n");
+}
+
+#[test]
+fn dollar_names_are_comments() {
+    let src = "
+macro bad($a) {
+    $a += 1;
+}
+
+fn main()() {}
+~main()
+";
+    assert!(parser::parse(Tokeniser::new(src)).is_err());
+}
@@ -0,0 +1,25 @@
+use std::path::Path;
+
+use monoxide::manifest::Manifest;
+
+
+#[test]
+fn hashes_in_strings_are_not_comments() {
+    let manifest = Manifest::parse("
+# The project
+name = \"demo#2\"  # trailing comment
+features = [\"colour=#fff\", \"debug\"]  # more
+include_paths = [\"lib#1\"]
+", Path::new("root")).unwrap();
+    assert_eq!(manifest.name, "demo#2");
+    assert_eq!(manifest.features, vec!["colour=#fff", "debug"]);
+    assert_eq!(manifest.include_paths, vec![Path::new("root").join("lib#1")]);
+}
+
+#[test]
+fn comments_still_end_lines() {
+    let manifest = Manifest::parse("name = \"demo\" # \"quoted\" in a comment\nstep_budget = 100 # steps", Path::new(".")).unwrap();
+    assert_eq!(manifest.name, "demo");
+    assert_eq!(manifest.options.step_budget, Some(100));
+    assert!(Manifest::parse("name = \"demo\"\n# only = \"a comment\"\nbogus = 1", Path::new(".")).is_err());
+}
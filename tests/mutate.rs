@@ -0,0 +1,28 @@
+use std::fs;
+use std::process::Command;
+
+
+// Every fixture compiles to backward code the verifier accepts, and rejects whenever any of it is
+// mutated //
+#[test]
+fn verify_catches_every_mutation_of_the_fixtures() {
+    let mut paths: Vec<String> = fs::read_dir("tests/fixtures").unwrap()
+        .map(|entry| entry.unwrap().path().to_string_lossy().into_owned())
+        .filter(|path| path.ends_with(".mx"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_monoxide"))
+        .arg("verify").arg("--mutate").args(&paths)
+        .output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}{}", stdout, String::from_utf8_lossy(&output.stderr));
+    for path in paths.iter() {
+        let line = stdout.lines().find(|line| line.starts_with(&format!("{}: ", path)))
+            .unwrap_or_else(|| panic!("No report for {}", path));
+        let counts: Vec<&str> = line.split(' ').collect();
+        assert_eq!(counts[1], counts[3], "{}", line);
+        assert_ne!(counts[1], "0", "{}", line);
+    }
+}
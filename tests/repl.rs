@@ -0,0 +1,39 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+
+fn repl(input: &str) -> (String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_monoxide"))
+        .arg("repl")
+        .env("HOME", env!("CARGO_TARGET_TMPDIR"))
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+        .spawn().unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    (String::from_utf8_lossy(&output.stdout).into_owned(), String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+// Bad entries are reported once each, without the panic hook's message, and leave the statements
+// before them in place //
+#[test]
+fn bad_entries_give_one_diagnostic_each() {
+    let (stdout, stderr) = repl("A = [1];\nx = A[5];\ny = 1 ` 2;\nprintln(A);\n");
+    assert_eq!(stdout, "[1]\n");
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", stderr);
+    assert!(lines[0].starts_with("RuntimeError at line 3"), "{}", lines[0]);
+    assert!(lines[0].ends_with("Index 5 is out of bounds for an array of length 1"), "{}", lines[0]);
+    assert_eq!(lines[1], "ParseError at line 3, column 6: Unexpected character \"`\"");
+}
+
+// Moving between steps reports the changed registers by the names of the variables they hold //
+#[test]
+fn moves_report_changes_by_variable_name() {
+    let (stdout, stderr) = repl("x = 1;\nA = [1, 2];\nA[1] += 3;\n:back 2\n:forward\n:forward\n");
+    assert_eq!(stderr, "");
+    assert_eq!(stdout, concat!(
+        "  A: [1, 5] -> <empty>\nAt step 1 of 3\n",
+        "  A: <empty> -> [1, 2]\nAt step 2 of 3\n",
+        "  A[1]: 2 -> 5\nAt step 3 of 3\n"
+    ));
+}
@@ -0,0 +1,49 @@
+#![cfg(feature = "serde")]
+
+mod common;
+
+use monoxide::convert::{array_of, IntoReaver};
+use monoxide::interpreter::{Fraction, Interpreter, Variable};
+use num_bigint::BigInt;
+
+
+fn frac(numer: i64, denom: i64) -> Variable {
+    Fraction::new(BigInt::from(numer), BigInt::from(denom)).into_reaver()
+}
+
+#[test]
+fn values_round_trip_through_json() {
+    let value = array_of(vec![frac(-3, 4), "text".into_reaver(), 1.5.into_reaver(), vec![1i64, 2].into_reaver()]);
+    let json = serde_json::to_string(&value).unwrap();
+    assert_eq!(json, r#"[{"num":"-3","den":"4"},"text",1.5,[{"num":"1","den":"1"},{"num":"2","den":"1"}]]"#);
+    assert_eq!(serde_json::from_str::<Variable>(&json).unwrap(), value);
+}
+
+#[test]
+fn integers_read_as_fractions() {
+    assert_eq!(serde_json::from_str::<Variable>("[3, -2]").unwrap(), vec![3i64, -2].into_reaver());
+}
+
+#[test]
+fn malformed_fractions_and_handles_are_refused() {
+    assert!(serde_json::from_str::<Variable>(r#"{"num": "1", "den": "0"}"#).is_err());
+    assert!(serde_json::from_str::<Variable>(r#"{"num": "1"}"#).is_err());
+    assert!(serde_json::from_str::<Variable>(r#"{"num": "x", "den": "2"}"#).is_err());
+    assert!(serde_json::to_string(&Variable::Handle(0)).is_err());
+}
+
+#[test]
+fn run_results_serialise() {
+    let program = common::compile("
+fn halve()(x) {
+    x /= 2;
+}
+~halve(x)
+
+fn main()() {}
+~main()
+");
+    let mut interpreter = Interpreter::new(&program);
+    let returned = interpreter.run_entry(&program, common::func_idx(&program, "halve"), vec![3i64.into_reaver()]);
+    assert_eq!(serde_json::to_string(&returned).unwrap(), r#"[{"num":"3","den":"2"}]"#);
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use monoxide::convert::IntoReaver;
+use monoxide::interpreter::{Interpreter, Module};
+
+
+const SRC: &str = "
+fn scale(k, m)(x) {
+    x += k * m;
+}
+~scale(x)
+
+fn main()() {
+    k = 3;
+    two = 2;
+    five = 5;
+    x = 0;
+    x => scale(k, two) => x;
+    x => scale(k, five) => x;
+    println(x);
+    x => ~scale(k, five) => x;
+    x => ~scale(k, two) => x;
+    x ~= 0;
+    five ~= 5;
+    two ~= 2;
+    k ~= 3;
+}
+~main()
+";
+
+fn specialised(program: &Module, name: &str) -> Vec<(usize, String, String)> {
+    program.functions[common::func_idx(program, name)].specialised.clone()
+}
+
+#[test]
+fn params_every_call_passes_the_same_constant_are_specialised() {
+    let program = common::compile(SRC);
+    assert_eq!(specialised(&program, "scale"), vec![(0, String::from("k"), String::from("3"))]);
+    assert_eq!(common::output(&program), "21\n");
+}
+
+#[test]
+fn no_specialise_opts_out() {
+    let program = common::compile(&SRC.replace("fn scale", "#[no_specialise]\nfn scale"));
+    assert!(specialised(&program, "scale").is_empty());
+    assert_eq!(common::output(&program), "21\n");
+}
+
+#[test]
+fn variables_a_call_may_modify_are_not_constant() {
+    let program = common::compile(&SRC.replace("fn main()() {", "
+fn bump(k)() {
+    k += 1;
+}
+~bump()
+
+fn main()() {").replace("    x => scale(k, five)", "    bump(k);\n    x => scale(k, five)")
+        .replace("    x => ~scale(k, five) => x;\n", "    x => ~scale(k, five) => x;\n    ~bump(k);\n"));
+    assert!(specialised(&program, "scale").is_empty());
+    assert_eq!(common::output(&program), "26\n");
+}
+
+#[test]
+fn params_handed_on_to_a_call_that_modifies_them_are_not_constant() {
+    let program = common::compile("
+fn bump(k)() {
+    k += 1;
+}
+~bump()
+
+fn scale(k)(x) {
+    bump(k);
+    x += k;
+    ~bump(k);
+}
+~scale(x)
+
+fn main()() {
+    k = 3;
+    x = 0;
+    x => scale(k) => x;
+    println(x);
+    x => ~scale(k) => x;
+    x ~= 0;
+    k ~= 3;
+}
+~main()
+");
+    assert!(specialised(&program, "scale").is_empty());
+    assert_eq!(common::output(&program), "4\n");
+}
+
+#[test]
+fn entering_with_other_values_is_rejected() {
+    let program = common::compile(SRC);
+    let scale = common::func_idx(&program, "scale");
+    let mut interpreter = Interpreter::new(&program);
+    let returned = interpreter.run_entry(&program, scale, vec![3.into_reaver(), 4.into_reaver(), 1.into_reaver()]);
+    assert_eq!(returned, vec![13.into_reaver()]);
+
+    let mut interpreter = Interpreter::new(&program);
+    let err = interpreter.try_run_entry(&program, scale, vec![2.into_reaver(), 4.into_reaver(), 1.into_reaver()]).unwrap_err();
+    assert!(err.desc.contains("specialised to k = 3"), "{}", err.desc);
+}
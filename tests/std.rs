@@ -0,0 +1,89 @@
+mod common;
+
+
+// Lets for the given variables, and the unlets undoing them in reverse, which first check they
+// still hold what they were bound to //
+fn bindings(vars: &[(&str, &str)]) -> (String, String) {
+    let lets = vars.iter().map(|(name, value)| format!("    {} = {};\n", name, value)).collect();
+    let unlets = vars.iter().rev()
+        .map(|(name, value)| format!("    expect {name} == {value};\n    {name} ~= {value};\n", name = name, value = value))
+        .collect();
+    (lets, unlets)
+}
+
+// Makes the std call, or uncall if it starts with "~", on the inputs, printing each output. Then
+// undoes it, which must give the inputs back. A call of only borrowed params takes no inputs //
+fn round_trip(call: &str, borrowed: &[(&str, &str)], inputs: &[(&str, &str)], outputs: &[&str]) -> String {
+    let inverse = match call.strip_prefix('~') {
+        Some(call) => call.to_string(),
+        None => format!("~{}", call)
+    };
+    let (borrow_lets, borrow_unlets) = bindings(borrowed);
+    let (input_lets, input_unlets) = bindings(inputs);
+    let input_names: Vec<&str> = inputs.iter().map(|(name, _)| *name).collect();
+    let (inputs, outputs) = (input_names.join(", "), outputs.join(", "));
+    let printed: String = outputs.split(", ").map(|name| format!("    println({});\n", name)).collect();
+    let (stolen, given_back) = match inputs.is_empty() {
+        true => (String::new(), String::new()),
+        false => (format!("{} => ", inputs), format!(" => {}", inputs))
+    };
+    common::output(&common::compile(&format!("
+import std;
+
+fn main()() {{
+{}{}    {stolen}{call} => {outputs};
+{}    {outputs} => {inverse}{given_back};
+{}{}}}
+~main()
+", borrow_lets, input_lets, printed, input_unlets, borrow_unlets,
+        stolen = stolen, given_back = given_back, outputs = outputs, call = call, inverse = inverse)))
+}
+
+#[test]
+fn sort_round_trips() {
+    let unsorted = [("data", "[3, 1, 2, 1]")];
+    let sorted = [("sorted", "[1, 1, 2, 3]"), ("perm", "[1, 3, 2, 0]")];
+    assert_eq!(round_trip("std::sort()", &[], &unsorted, &["sorted", "perm"]), "[1, 1, 2, 3]\n[1, 3, 2, 0]\n");
+    assert_eq!(round_trip("~std::sort()", &[], &sorted, &["data"]), "[3, 1, 2, 1]\n");
+    assert_eq!(round_trip("std::unsort()", &[], &sorted, &["data"]), "[3, 1, 2, 1]\n");
+    assert_eq!(round_trip("~std::unsort()", &[], &unsorted, &["sorted", "perm"]), "[1, 1, 2, 3]\n[1, 3, 2, 0]\n");
+}
+
+#[test]
+fn rle_encode_round_trips() {
+    assert_eq!(round_trip("std::rle_encode()", &[], &[("data", "[7, 7, 7, 2, 9, 9]")], &["encoded"]), "[2, 9, 1, 2, 3, 7]\n");
+    assert_eq!(round_trip("~std::rle_encode()", &[], &[("encoded", "[2, 9, 1, 2, 3, 7]")], &["data"]), "[7, 7, 7, 2, 9, 9]\n");
+}
+
+#[test]
+fn permute_round_trips() {
+    let perm = [("perm", "[2, 0, 1]")];
+    assert_eq!(round_trip("std::permute(perm)", &perm, &[("data", "[10, 20, 30]")], &["gathered"]), "[30, 10, 20]\n");
+    assert_eq!(round_trip("~std::permute(perm)", &perm, &[("gathered", "[30, 10, 20]")], &["data"]), "[10, 20, 30]\n");
+}
+
+#[test]
+fn arith_encode_round_trips() {
+    let model = [("cdf", "[0, 1/2, 3/4]"), ("pdf", "[1/2, 1/4, 1/4]")];
+    let call = "std::arith_encode(cdf, pdf)";
+    assert_eq!(round_trip(call, &model, &[("data", "[0, 2, 1]")], &["code", "count"]), "7/16\n3\n");
+    assert_eq!(round_trip(&format!("~{}", call), &model, &[("code", "7/16"), ("count", "3")], &["data"]), "[0, 2, 1]\n");
+}
+
+#[test]
+fn egcd_and_modexp_compute_and_uncompute() {
+    let operands = [("a", "240"), ("b", "46")];
+    assert_eq!(round_trip("std::egcd(a, b)", &operands, &[], &["g", "x", "y"]), "2\n-9\n47\n");
+    let operands = [("base", "4"), ("exponent", "13"), ("modulus", "497")];
+    assert_eq!(round_trip("std::modexp(base, exponent, modulus)", &operands, &[], &["result"]), "445\n");
+}
+
+#[test]
+fn modular_arithmetic_round_trips() {
+    let operands = [("b", "5"), ("modulus", "7")];
+    assert_eq!(round_trip("std::mod_add(b, modulus)", &operands, &[("a", "4")], &["sum"]), "2\n");
+    assert_eq!(round_trip("~std::mod_add(b, modulus)", &operands, &[("sum", "2")], &["a"]), "4\n");
+    let operands = [("c", "3"), ("modulus", "7")];
+    assert_eq!(round_trip("std::mod_mul(c, modulus)", &operands, &[("a", "4")], &["product"]), "5\n");
+    assert_eq!(round_trip("~std::mod_mul(c, modulus)", &operands, &[("product", "5")], &["a"]), "4\n");
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use monoxide::formatter;
+use monoxide::parser;
+use monoxide::tokeniser::Tokeniser;
+
+
+fn round_trips(src: &str) {
+    let module = parser::parse(Tokeniser::new(src)).unwrap_or_else(|err| panic!("{}", err));
+    formatter::check_round_trip(&module).unwrap();
+}
+
+fn rejects(src: &str) {
+    assert!(parser::parse(Tokeniser::new(src)).is_err(), "Parsed {}", src);
+}
+
+const DESTRUCTURE: &str = "
+fn bump()(A, b) {
+    b += 10;
+    A[0] += 1;
+} ~bump(A, b)
+
+fn main()() {
+    X = [1, 2];
+    y = 4;
+    (A, b) = call X, y => bump();
+    println(A);
+    println(b);
+    (X, y) = call A, b => ~bump();
+    println(X);
+}
+~main()
+";
+
+#[test]
+fn destructuring_calls_bind_with_equals() {
+    round_trips(DESTRUCTURE);
+    assert_eq!(common::output(&common::compile(DESTRUCTURE)), "[2, 2]\n14\n[1, 2]\n");
+    rejects(&DESTRUCTURE.replace(") = call", ") := call"));
+}
+
+const DEFAULTS: &str = "
+fn add(a, b = 10, c = 1/2)(x) {
+    x += a * b + c;
+}
+~add(x)
+
+fn main()() {
+    x = 0;
+    f = 5;
+    t = 2;
+    x => add(f) => x;
+    println(x);
+    x => add(f, t) => x;
+    println(x);
+    x => ~add(f, t) => x;
+    x => ~add(f) => x;
+    x ~= 0;
+}
+~main()
+";
+
+#[test]
+fn defaults_are_given_with_equals() {
+    round_trips(DEFAULTS);
+    assert_eq!(common::output(&common::compile(DEFAULTS)), "101/2\n61\n");
+    rejects(&DEFAULTS.replace("b = 10, c = 1/2", "b := 10, c := 1/2"));
+}
+
+const DESTRUCTURE_LET: &str = "
+fn main()() {
+    A = [1, 2, 3, 4, 5];
+    [a, b, mid..., z] = A;
+    println(a * 100 + b * 10 + z);
+    println(mid);
+    [a, b, mid..., z] ~= A;
+    println(A);
+}
+~main()
+";
+
+#[test]
+fn destructuring_lets_use_equals() {
+    round_trips(DESTRUCTURE_LET);
+    assert_eq!(common::output(&common::compile(DESTRUCTURE_LET)), "125\n[3, 4]\n[1, 2, 3, 4, 5]\n");
+    rejects(&DESTRUCTURE_LET.replace("] = A", "] := A"));
+    rejects(&DESTRUCTURE_LET.replace("] ~= A", "] ~:= A"));
+}
+
+const CONST_EVAL: &str = "
+#[pure]
+fn fib_pair(n)() {
+    a = 0;
+    b = 1;
+    for (i in 0..n) {
+        a += b;
+        b += a;
+    };
+    pair = [a, b];
+}
+~fib_pair(pair)
+
+fn main()() {
+    const x = eval fib_pair(3);
+    println(x);
+}
+~main()
+";
+
+#[test]
+fn const_evals_bind_with_equals() {
+    round_trips(CONST_EVAL);
+    assert_eq!(common::output(&common::compile(CONST_EVAL)), "[8, 13]\n");
+    rejects(&CONST_EVAL.replace("x = eval", "x := eval"));
+}
+
+const TRY: &str = "
+fn f()(a) {
+    try {
+        a += 1;
+    } catch (a > 5) {
+        a *= 10;
+    }
+}
+~f(a)
+
+fn main()() {
+    a = 5;
+    b = 1;
+    a => f() => a;
+    b => f() => b;
+    println(a, ' ', b);
+    a => ~f() => a;
+    b => ~f() => b;
+    println(a, ' ', b);
+}
+~main()
+";
+
+// Uncalling undoes the handler when it ran, and otherwise the body //
+#[test]
+fn try_statements_uncall_either_path() {
+    round_trips(TRY);
+    assert_eq!(common::output(&common::compile(TRY)), "50 2\n5 1\n");
+    let explicit = TRY.replace("        a *= 10;\n    }", "        a *= 10;\n    } ~catch (a >= 50);");
+    round_trips(&explicit);
+    assert_eq!(common::output(&common::compile(&explicit)), "50 2\n5 1\n");
+}
@@ -0,0 +1,116 @@
+use std::cell::Cell;
+use std::io::{self, BufRead, Read};
+use std::rc::Rc;
+
+use monoxide::cfg::Cfg;
+use monoxide::lint;
+use monoxide::parser;
+use monoxide::tokeniser::{Token, Tokeniser};
+
+
+const SRC: &str = "
+$A comment
+  over lines$
+fn main()() {
+    s = 'a string
+over lines';
+    println(s);
+    s ~= 'a string
+over lines';
+}
+~main()
+";
+
+// Serves a source a line at a time, counting the lines read //
+struct Lines<'a> {
+    src: &'a [u8],
+    lines_read: Rc<Cell<usize>>
+}
+
+impl Read for Lines<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.fill_buf()?.len().min(buf.len());
+        buf[..len].copy_from_slice(&self.src[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for Lines<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let end = self.src.iter().position(|&b| b == b'\n').map_or(self.src.len(), |idx| idx + 1);
+        Ok(&self.src[..end])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if amt > 0 && self.src[amt - 1] == b'\n' {
+            self.lines_read.set(self.lines_read.get() + 1);
+        }
+        self.src = &self.src[amt..];
+    }
+}
+
+fn describe(tokens: impl Iterator<Item = Token>) -> Vec<(String, String, usize, usize)> {
+    tokens.map(|Token{type_, string_, line, col}| (type_, string_, line, col)).collect()
+}
+
+#[test]
+fn readers_give_the_same_tokens() {
+    let lines_read = Rc::new(Cell::new(0));
+    let reader = Lines{src: SRC.as_bytes(), lines_read};
+    assert_eq!(describe(Tokeniser::from_reader(reader)), describe(Tokeniser::new(SRC)));
+
+    let program = parser::parse(Tokeniser::from_reader(SRC.as_bytes())).unwrap();
+    assert_eq!(program.functions.len(), 1);
+}
+
+#[test]
+fn readers_are_read_only_as_far_as_needed() {
+    let lines_read = Rc::new(Cell::new(0));
+    let mut tokens = Tokeniser::from_reader(Lines{src: SRC.as_bytes(), lines_read: lines_read.clone()});
+    assert_eq!(tokens.peek().unwrap().string_, "fn");
+    assert_eq!(lines_read.get(), 4);
+    tokens.by_ref().find(|token| token.type_ == "STRING");
+    assert_eq!(lines_read.get(), 6);
+}
+
+#[test]
+fn unterminated_strings_are_noticed_in_readers() {
+    let mut tokens = Tokeniser::from_reader("x = 'never\nclosed".as_bytes());
+    assert_eq!(tokens.by_ref().last().unwrap().type_, "END_MARKER!");
+    assert!(tokens.unterminated());
+}
+
+#[test]
+fn unknown_characters_are_error_tokens() {
+    let tokens = describe(Tokeniser::new("x = 1 ` 2;\ny ¬ 3;"));
+    let errors: Vec<_> = tokens.iter().filter(|(type_, ..)| type_ == "ERROR").collect();
+    assert_eq!(errors, vec![
+        &(String::from("ERROR"), String::from("`"), 1, 6),
+        &(String::from("ERROR"), String::from("¬"), 2, 2)
+    ]);
+}
+
+#[test]
+fn unknown_characters_are_reported_where_they_are() {
+    let src = "fn main()() {\n    x = 1 ` 2;\n}\n~main()\n";
+    let err = parser::parse(Tokeniser::new(src)).unwrap_err();
+    assert_eq!((err.line, err.col), (2, 10));
+    assert_eq!(err.hint.as_deref(), Some("Unexpected character \"`\""));
+
+    let diagnostics = lint::check(src, &Cfg::default());
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!((diagnostics[0].line, diagnostics[0].col), (2, 10));
+}
+
+// Tokens behind each function parsed are let go, but a typo in a later one is still found //
+#[test]
+fn typos_are_found_after_earlier_functions() {
+    let function = |name: &str| format!("fn {}()() {{\n    x = 1;\n    x ~= 1;\n}}\n~{}()\n\n", name, name);
+    let src = format!("{}{}fn main()() {{\n    x = 0;\n    whlie (x < 3) {{\n        x += 1;\n    }} ~while (x > 0);\n}}\n~main()\n",
+                      function("f"), function("g"));
+    let err = parser::parse(Tokeniser::from_reader(src.as_bytes())).unwrap_err();
+    assert_eq!((err.line, err.col), (15, 4));
+    assert_eq!(err.hint.as_deref(), Some("\"whlie\" isn't a keyword, did you mean \"while\"?"));
+    assert!(parser::parse(Tokeniser::from_reader(src.replace("whlie", "while").as_bytes())).is_ok());
+}